@@ -26,7 +26,6 @@ use std::process::Command;
 use std::time::Duration;
 use tokio::time::{sleep, interval};
 use futures::StreamExt;
-use tracing_subscriber;
 
 mod headless;
 #[cfg(unix)]
@@ -145,6 +144,96 @@ enum Commands {
         /// Maximum number of concurrent sessions (None = unlimited)
         #[arg(long)]
         max_sessions: Option<usize>,
+        /// Maximum number of concurrent sessions of one client, by API key or X-Shai-User header (None = unlimited)
+        #[arg(long)]
+        max_sessions_per_owner: Option<usize>,
+        /// Send whitespace keep-alive padding every N seconds on non-streaming responses
+        #[arg(long)]
+        keepalive_padding: Option<u64>,
+        /// Evict sessions idle for more than N seconds (None = never)
+        #[arg(long)]
+        session_ttl: Option<u64>,
+        /// Report the files changed by each request
+        #[arg(long)]
+        track_workspace: bool,
+        /// Stream tool call arguments while the model generates them
+        #[arg(long)]
+        stream_tool_arguments: bool,
+        /// Commit the workspace after every request to a checkpoint store, one history per session (SHAI_CHECKPOINT_FOLDER, built with the `git` feature)
+        #[arg(long)]
+        git_checkpoints: bool,
+        /// Keep the raw output of the tools in a content-addressable store (SHAI_ARTIFACT_FOLDER, default .shai/artifacts)
+        #[arg(long)]
+        artifacts: bool,
+        /// Drop the oldest messages of an agent trace once it exceeds --context-max-tokens
+        #[arg(long)]
+        compaction: bool,
+        /// Tokens of the trace above which compaction drops messages (default: 100000)
+        #[arg(long)]
+        context_max_tokens: Option<u32>,
+        /// Run the tool calls of a model reply one after the other instead of concurrently
+        #[arg(long)]
+        sequential_tools: bool,
+        /// JSON server config file, its `rules` section holds the transformation rules applied to incoming OpenAI requests
+        #[arg(long)]
+        config: Option<std::path::PathBuf>,
+        /// JSON file of the agents and models each API key may use, requests with other keys are refused
+        #[arg(long)]
+        api_keys: Option<std::path::PathBuf>,
+        /// JSON file of the model names routed to a provider and model (aliases, `provider/model`)
+        #[arg(long)]
+        models: Option<std::path::PathBuf>,
+        /// JSON file of the origins browsers may call the API from (default: SHAI_CORS_* variables)
+        #[arg(long)]
+        cors: Option<std::path::PathBuf>,
+        /// Serve the Prometheus metrics on this address instead of the API one (e.g. 127.0.0.1:9090)
+        #[arg(long)]
+        metrics_address: Option<String>,
+        /// Feature flags clients may override with the X-Shai-Features header (comma-separated)
+        #[arg(long, value_delimiter = ',')]
+        overridable_features: Vec<shai_http::Feature>,
+        /// Reject requests naming unknown or non-overridable feature flags instead of ignoring them
+        #[arg(long)]
+        strict_features: bool,
+        /// Keep deleted sessions restorable for N seconds (default: 7 days)
+        #[arg(long)]
+        trash_retention: Option<u64>,
+        /// Delete saved sessions not saved for N seconds, swept hourly (default: kept forever)
+        #[arg(long)]
+        session_max_age: Option<u64>,
+        /// Keep at most N saved sessions, the least recently saved ones are swept (default: unlimited)
+        #[arg(long)]
+        max_saved_sessions: Option<usize>,
+        /// Reject requests once a session trace reaches N bytes (default: unlimited)
+        #[arg(long)]
+        max_trace_bytes: Option<u64>,
+        /// Refuse tool writes and artifacts beyond N bytes per session (default: unlimited)
+        #[arg(long)]
+        max_disk_bytes: Option<u64>,
+        /// Stop saving sessions larger than N bytes (default: unlimited)
+        #[arg(long)]
+        max_persisted_bytes: Option<u64>,
+        /// Save at most the N most recent messages of a session trace, system messages always kept (default: all)
+        #[arg(long)]
+        max_persisted_messages: Option<usize>,
+        /// Drop the oldest messages of a saved session trace beyond N bytes (default: unlimited)
+        #[arg(long)]
+        max_persisted_trace_bytes: Option<u64>,
+        /// Report tool calls slower than N seconds in run summaries (default: 30, 0 = never)
+        #[arg(long)]
+        slow_tool_threshold: Option<u64>,
+        /// Requests that may wait for a busy session, the next ones get 429 (default: unlimited)
+        #[arg(long)]
+        max_queue_depth: Option<usize>,
+        /// Seconds a request waits for a busy session before it gets 429 (default: no limit)
+        #[arg(long)]
+        queue_timeout: Option<u64>,
+        /// System message opening every conversation that does not send its own
+        #[arg(long)]
+        system_prompt: Option<String>,
+        /// LLM tokens a session may consume over all its requests, the next ones are refused (default: unlimited)
+        #[arg(long)]
+        token_budget: Option<u32>,
     }
 }
 
@@ -152,6 +241,9 @@ enum Commands {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
     default_config(cli.default_shai_config_url).await;
+    if let Ok(config) = ShaiConfig::load() {
+        config.install_prompt_adapters();
+    }
 
     match cli.command {
         #[cfg(unix)]
@@ -182,8 +274,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             let command_str = command.join(" ");
             handle_postcmd(exit_code, command_str).await?;
         },
-        Some(Commands::Serve { host, port, agent, ephemeral, max_sessions }) => {
-            handle_serve(host, port, agent, ephemeral, max_sessions).await?;
+        Some(Commands::Serve { host, port, agent, ephemeral, max_sessions, max_sessions_per_owner, keepalive_padding, session_ttl, track_workspace, stream_tool_arguments, git_checkpoints, artifacts, compaction, context_max_tokens, sequential_tools, config, api_keys, models, cors, metrics_address, overridable_features, strict_features, trash_retention, session_max_age, max_saved_sessions, max_trace_bytes, max_disk_bytes, max_persisted_bytes, max_persisted_messages, max_persisted_trace_bytes, slow_tool_threshold, max_queue_depth, queue_timeout, system_prompt, token_budget }) => {
+            let quotas = shai_http::SessionQuotas { max_trace_bytes, max_disk_bytes, max_persisted_bytes, max_persisted_messages, max_persisted_trace_bytes };
+            let retention = shai_http::SessionRetention { max_age: session_max_age.map(std::time::Duration::from_secs), max_sessions: max_saved_sessions };
+            let features = shai_http::FeatureConfig { overridable: overridable_features, strict: strict_features, ..Default::default() };
+            handle_serve(host, port, agent, ephemeral, max_sessions, max_sessions_per_owner, keepalive_padding, session_ttl, track_workspace, stream_tool_arguments, git_checkpoints, artifacts, compaction, context_max_tokens, sequential_tools, config, api_keys, models, cors, metrics_address, features, trash_retention, retention, quotas, slow_tool_threshold, max_queue_depth, queue_timeout, system_prompt, token_budget).await?;
         },
         None => {
             // Check for stdin input or trailing arguments
@@ -470,22 +565,63 @@ pub async fn handle_postcmd(exit_code: i32, command: String) -> Result<(), Box<d
     Ok(())
 }
 
-async fn handle_serve(host: String, port: u16, agent: Option<String>, ephemeral: bool, max_sessions: Option<usize>) -> Result<(), Box<dyn std::error::Error>> {
+async fn handle_serve(host: String, port: u16, agent: Option<String>, ephemeral: bool, max_sessions: Option<usize>, max_sessions_per_owner: Option<usize>, keepalive_padding: Option<u64>, session_ttl: Option<u64>, track_workspace: bool, stream_tool_arguments: bool, git_checkpoints: bool, artifacts: bool, compaction: bool, context_max_tokens: Option<u32>, sequential_tools: bool, config_file: Option<std::path::PathBuf>, api_keys: Option<std::path::PathBuf>, models: Option<std::path::PathBuf>, cors: Option<std::path::PathBuf>, metrics_address: Option<String>, features: shai_http::FeatureConfig, trash_retention: Option<u64>, retention: shai_http::SessionRetention, quotas: shai_http::SessionQuotas, slow_tool_threshold: Option<u64>, max_queue_depth: Option<usize>, queue_timeout: Option<u64>, system_prompt: Option<String>, token_budget: Option<u32>) -> Result<(), Box<dyn std::error::Error>> {
     // Initialize tracing for HTTP server logs
-    tracing_subscriber::fmt()
-        .with_target(false)
-        .with_level(true)
-        .with_env_filter("shai_http=debug")
-        .init();
+    shai_http::init_tracing();
 
     println!("{}", logo_cyan());
 
     let addr = format!("{}:{}", host, port);
-    let config = shai_http::ServerConfig::new(addr)
+    let mut config = shai_http::ServerConfig::new(addr)
         .with_ephemeral(ephemeral)
-        .with_max_sessions(max_sessions);
+        .with_max_sessions(max_sessions)
+        .with_max_sessions_per_owner(max_sessions_per_owner)
+        .with_keepalive_padding(keepalive_padding.map(std::time::Duration::from_secs))
+        .with_session_ttl(session_ttl)
+        .with_track_workspace(track_workspace)
+        .with_stream_tool_arguments(stream_tool_arguments)
+        .with_git_checkpoints(git_checkpoints)
+        .with_artifacts(artifacts)
+        .with_compaction(compaction, context_max_tokens)
+        .with_parallel_tool_calls(!sequential_tools)
+        .with_quotas(quotas)
+        .with_request_queue(max_queue_depth, queue_timeout)
+        .with_system_prompt(system_prompt.filter(|prompt| !prompt.trim().is_empty()))
+        .with_token_budget(token_budget)
+        .with_features(features)
+        .with_admin_token(std::env::var("SHAI_ADMIN_TOKEN").ok().filter(|token| !token.is_empty()))
+        .with_auth(shai_http::AuthConfig::from_env())
+        .with_rate_limit(shai_http::RateLimitConfig::from_env())
+        .with_body_logging(shai_http::BodyLoggingConfig::from_env())
+        .with_cors(shai_http::CorsConfig::from_env())
+        .with_shutdown_timeout(shai_http::shutdown::timeout_from_env())
+        .with_session_store(shai_http::SessionStoreConfig::from_env())
+        .with_sse_heartbeat(shai_http::streaming::heartbeat_from_env())
+        .with_metrics_address(metrics_address)
+        .with_persisted_retention(retention);
+    if trash_retention.is_some() {
+        config = config.with_trash_retention(trash_retention);
+    }
+    if let Some(secs) = slow_tool_threshold {
+        config = config.with_slow_tool_threshold(Some(secs).filter(|secs| *secs > 0).map(std::time::Duration::from_secs));
+    }
+    if let Some(path) = config_file {
+        config = config.with_config_file(path)?;
+    }
+    if let Some(path) = api_keys {
+        config = config.with_api_keys_file(path)?;
+    }
+    if let Some(path) = models {
+        config = config.with_models_file(path)?;
+    }
+    if let Some(path) = cors {
+        config = config.with_cors_file(path)?;
+    }
 
-    shai_http::start_server(config).await?;
+    let served = shai_http::start_server(config).await;
+    #[cfg(feature = "otel")]
+    shai_http::telemetry::shutdown();
+    served?;
 
     Ok(())
 }
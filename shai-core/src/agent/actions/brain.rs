@@ -1,41 +1,84 @@
+use std::sync::Arc;
+use std::time::Duration;
 use chrono::Utc;
-use openai_dive::v1::resources::chat::ChatMessage;
-use tracing::info;
+use shai_llm::ToolCallDelta;
+use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent};
+use tracing::{debug, info, info_span, Instrument};
 use tokio_util::sync::CancellationToken;
-use crate::agent::{AgentCore, AgentError, AgentEvent, InternalAgentEvent, InternalAgentState, ThinkerContext, ThinkerDecision, ThinkerFlowControl};
+use crate::agent::{AgentCore, AgentError, AgentEvent, InternalAgentEvent, InternalAgentState, ThinkerContext, ThinkerDecision, ThinkerFlowControl, ToolCallDeltaSink};
+
+/// Time a cancelled brain step has to return its partial output before it is aborted
+pub const BRAIN_CANCEL_GRACE: Duration = Duration::from_millis(100);
 
 impl AgentCore {
     /// Launch a brain task to decide next step
+    /// Once the token budget is spent no LLM call is made, the agent pauses on a BudgetExceeded error
     pub async fn spawn_next_step(&mut self) {         
+        if let Some(Err(error)) = self.token_budget.as_ref().map(|budget| budget.check()) {
+            info!(target: "agent::think", error = %error, "brain step refused");
+            let _ = self.emit_event(AgentEvent::Error { error: error.to_string() }).await;
+            let _ = self.handle_brain_error::<()>(Err(error)).await;
+            return;
+        }
         let cancellation_token = CancellationToken::new();
         let cancel_token_clone = cancellation_token.clone();
         let trace = self.trace.clone();
         let tx_clone = self.internal_tx.clone();
         let available_tools = self.available_tools.clone();
         let method = self.method.clone();
+
+        // Tool call arguments are forwarded as public events while the model generates them
+        let on_tool_call_delta = match (&self.socket.tx_event, self.stream_tool_arguments) {
+            (Some(tx), true) => {
+                let tx = tx.clone();
+                let sink: ToolCallDeltaSink = Arc::new(move |delta: ToolCallDelta| {
+                    let _ = tx.send(AgentEvent::ToolCallArgumentsDelta {
+                        call_id: delta.call_id,
+                        tool_name: delta.name,
+                        index: delta.index,
+                        delta: delta.arguments,
+                    });
+                });
+                Some(sink)
+            }
+            _ => None,
+        };
+
         let context = ThinkerContext {
             trace,
             available_tools,
-            method
+            method,
+            on_tool_call_delta,
+            cancellation_token: cancellation_token.clone(),
+            sampling: self.sampling.clone(),
         };
         let brain = self.brain.clone();
+        let context_manager = self.context_manager.clone();
+        // the brain records the model it queries
+        let span = info_span!(target: "agent::brain", parent: &self.request_span, "agent.brain_step",
+            session_id = %self.session_id, model = tracing::field::Empty);
         
         //////////////////////// TOKIO SPAWN
-        tokio::spawn(async move {
-            tokio::select! {
-                result = async {
-                    brain.write().await.next_step(context).await
-                } => {
-                    let _ = tx_clone.send(InternalAgentEvent::BrainResult {
-                        result
-                    });
-                }
-                _ = cancel_token_clone.cancelled() => {
-                    // Brain thinking was cancelled, no need to send result
+        // the brain sees the token and stops its LLM call itself, a cancelled step
+        // returns its decision to cancel_brain_task instead of the event loop
+        let task = tokio::spawn(async move {
+            if let Some(manager) = context_manager {
+                manager.fit(&context.trace, &context.cancellation_token).await;
+                if cancel_token_clone.is_cancelled() {
+                    return None;
                 }
             }
-        });
+            let result = brain.write().await.next_step(context).await;
+            if cancel_token_clone.is_cancelled() {
+                return result.ok();
+            }
+            let _ = tx_clone.send(InternalAgentEvent::BrainResult {
+                result
+            });
+            None
+        }.instrument(span));
         //////////////////////// TOKIO SPAWN
+        self.brain_task = Some(task);
         
         self.set_state(InternalAgentState::Processing { 
             task_name: "next_step".to_string(), 
@@ -44,10 +87,65 @@ impl AgentCore {
         }).await;
     }
 
+    /// Wait for a cancelled brain step and record its partial output
+    /// Steps that do not stop within BRAIN_CANCEL_GRACE are aborted, dropping their LLM call
+    pub async fn cancel_brain_task(&mut self) {
+        let Some(mut task) = self.brain_task.take() else {
+            return;
+        };
+        match tokio::time::timeout(BRAIN_CANCEL_GRACE, &mut task).await {
+            Ok(Ok(Some(decision))) => self.record_cancelled_step(decision).await,
+            Ok(_) => {}
+            Err(_) => {
+                debug!(target: "agent::think", "brain step did not stop in time, aborting it");
+                task.abort();
+            }
+        }
+    }
+
+    /// Keep the text generated before a cancellation, incomplete tool calls are dropped
+    async fn record_cancelled_step(&mut self, decision: ThinkerDecision) {
+        let ThinkerDecision { message, token_usage, .. } = decision;
+        if let ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), reasoning_content, .. } = message {
+            if !text.trim().is_empty() {
+                info!(target: "agent::think", partial = ?text, "brain step cancelled");
+                let partial = ChatMessage::Assistant {
+                    content: Some(ChatMessageContent::Text(text)),
+                    reasoning_content,
+                    tool_calls: None,
+                    name: None,
+                    audio: None,
+                    refusal: None,
+                };
+                self.trace.write().await.push(partial.clone());
+                let _ = self.emit_event(AgentEvent::BrainResult {
+                    timestamp: Utc::now(),
+                    thought: Ok(partial)
+                }).await;
+            }
+        }
+
+        if let Some((input_tokens, output_tokens)) = token_usage {
+            self.charge_tokens(input_tokens, output_tokens);
+            let _ = self.emit_event(AgentEvent::TokenUsage {
+                input_tokens,
+                output_tokens
+            }).await;
+        }
+    }
+
+    /// Charge the usage of a brain step to the token budget
+    fn charge_tokens(&self, input_tokens: u32, output_tokens: u32) {
+        if let Some(budget) = &self.token_budget {
+            budget.record(input_tokens, output_tokens);
+        }
+    }
+
 
     /// Process a brain task result
     pub async fn process_next_step(&mut self, result: Result<ThinkerDecision, AgentError>) -> Result<(), AgentError> {
-        let ThinkerDecision{message, flow, token_usage} = self.handle_brain_error(result).await?;
+        self.brain_task = None;
+        let ThinkerDecision{message, flow, token_usage, empty_retries} = self.handle_brain_error(result).await?;
         let ChatMessage::Assistant { content, reasoning_content, tool_calls, .. } = message.clone() else {
             return self.handle_brain_error::<ThinkerDecision>(
                 Err(AgentError::InvalidResponse(format!("ChatMessage::Assistant expected, but got {:?} instead", message)))).await.map(|_| ()
@@ -57,7 +155,14 @@ impl AgentCore {
         // Add the message to trace
         info!(target: "agent::think", reasoning_content = ?reasoning_content, content = ?content);
         let trace = self.trace.clone();
-        trace.write().await.push(message.clone());
+        let trace_len = {
+            let mut trace = trace.write().await;
+            trace.push(message.clone());
+            trace.len()
+        };
+        if let (Some(manager), Some((input_tokens, output_tokens))) = (&self.context_manager, token_usage) {
+            manager.record_usage(input_tokens, output_tokens, trace_len);
+        }
         
         // Emit event to external consumers
         let _ = self.emit_event(AgentEvent::BrainResult {
@@ -65,8 +170,15 @@ impl AgentCore {
             thought: Ok(message.clone())
         }).await;
 
+        if empty_retries > 0 {
+            let _ = self.emit_event(AgentEvent::EmptyCompletionRetried {
+                retries: empty_retries
+            }).await;
+        }
+
         // Emit token usage event if available
         if let Some((input_tokens, output_tokens)) = token_usage {
+            self.charge_tokens(input_tokens, output_tokens);
             let _ = self.emit_event(AgentEvent::TokenUsage {
                 input_tokens,
                 output_tokens
@@ -85,7 +197,7 @@ impl AgentCore {
             ThinkerFlowControl::AgentContinue => {
                 self.set_state(InternalAgentState::Running).await;
             }
-            ThinkerFlowControl::AgentPause => {
+            ThinkerFlowControl::AgentPause | ThinkerFlowControl::Cancelled => {
                 self.set_state(InternalAgentState::Paused).await;
             }
         }
@@ -97,11 +209,12 @@ impl AgentCore {
         match result {
             Ok(value) => Ok(value),
             Err(error) => {
-                self.set_state(InternalAgentState::Paused).await;
+                // the error goes out before the pause so that consumers stopping on pause still see it
                 let _ = self.emit_event(AgentEvent::BrainResult { 
                     timestamp: Utc::now(),
                     thought: Err(error.clone())
                 }).await;
+                self.set_state(InternalAgentState::Paused).await;
                 Err(error)
             }
         }
@@ -5,64 +5,122 @@ use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent, ToolCall
 use tokio::sync::{broadcast, RwLock};
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
-use tracing::info;
+use tracing::{info, info_span, Instrument, Span};
 use serde_json::from_str;
 use uuid::Uuid;
 use crate::agent::{AgentCore, AgentEvent, ClaimManager, InternalAgentEvent, InternalAgentState, PermissionRequest, PermissionResponse};
-use crate::tools::{AnyTool, ToolCall, ToolCapability, ToolResult};
+use crate::tools::{AnyTool, ProgressReporter, ToolCache, ToolCall, ToolCapability, ToolContext, ToolOutputFilters, ToolResult};
+use futures::future::join_all;
 use tracing::debug;
 
+/// What a tool call needs from the agent, cloned into each call
+#[derive(Clone)]
+struct ToolRunner {
+    cancel_token: CancellationToken,
+    public_event_tx: Option<broadcast::Sender<AgentEvent>>,
+    available_tools: Vec<Arc<dyn AnyTool>>,
+    claims: Arc<RwLock<ClaimManager>>,
+    internal_tx: broadcast::Sender<InternalAgentEvent>,
+    tool_context: ToolContext,
+    output_filters: ToolOutputFilters,
+    tool_cache: Option<Arc<ToolCache>>,
+}
+
+/// Trace message of the result of a tool call
+fn tool_message(tool_call_id: &str, result: &ToolResult) -> ChatMessage {
+    ChatMessage::Tool {
+        tool_call_id: tool_call_id.to_string(),
+        content: ChatMessageContent::Text(result.to_string())
+    }
+}
+
+/// Span of a tool call, closed with the status of its result
+fn tool_span(parent: &Span, session_id: &str, tc: &LlmToolCall) -> Span {
+    info_span!(target: "agent::tool", parent: parent, "agent.tool_call",
+        session_id = %session_id, tool_name = %tc.function.name, tool_call_id = %tc.id, status = tracing::field::Empty)
+}
+
+fn cancelled() -> ToolResult {
+    ToolResult::error("tool call was cancelled by the user".to_string())
+}
+
 impl AgentCore {
 
-    /// Spawn a cancellable coroutine that runs all tool call in parrallel and waits for them to finish
+    /// Spawn a cancellable coroutine that runs the tool calls of a brain step and waits for them to finish
+    /// The calls run all at once, or one after the other without `parallel_tool_calls`; either way their
+    /// results enter the trace in the order of the calls
     pub async fn spawn_tools(&mut self, tool_calls: Vec<LlmToolCall>) {
         let cancellation_token = CancellationToken::new();
         let cancel_clone = cancellation_token.clone();
         let internal_tx = self.internal_tx.clone();
+        let parallel = self.parallel_tool_calls;
 
         // Clone all needed data from self before spawning
-        let public_event_tx = self.socket.tx_event.clone();
-        let available_tools = self.available_tools.clone();
-        let claims = self.permissions.clone();
+        let runner = ToolRunner {
+            cancel_token: cancellation_token.clone(),
+            public_event_tx: self.socket.tx_event.clone(),
+            available_tools: self.available_tools.clone(),
+            claims: self.permissions.clone(),
+            internal_tx: self.internal_tx.clone(),
+            tool_context: self.tool_context.clone(),
+            output_filters: self.output_filters.clone(),
+            tool_cache: self.tool_cache.clone(),
+        };
         let trace = self.trace.clone();
+        let parent = self.request_span.clone();
+        let session_id = self.session_id.clone();
 
-        // Spawn a task to wait for all tool executions
-        let mut join_handles = Vec::new();
-        
-        // Spawn all tool executions
-        for tc in tool_calls {
-            let handle = Self::spawn_tool_static(
-                tc,
-                cancel_clone.clone(),
-                public_event_tx.clone(),
-                available_tools.clone(),
-                claims.clone(),
-                internal_tx.clone(),
-                trace.clone(),
-            );
-            join_handles.push(handle);
-        }
-            
-        // Wait for all tools to complete or be cancelled
         tokio::spawn(async move {
-            tokio::select! {
-                _ = cancel_clone.cancelled() => {
-                    // Tools were cancelled, no need to send completion event
+            let outputs = if parallel {
+                // a read running alongside a write may see the files before or after it: such a
+                // batch bypasses the cache, which is cleared once the writes are done
+                let writes = tool_calls.iter().any(|tc| runner.available_tools.iter()
+                    .any(|tool| tool.name() == tc.function.name && ToolCache::invalidates(tool.capabilities())));
+                let batch_runner = ToolRunner { tool_cache: runner.tool_cache.clone().filter(|_| !writes), ..runner.clone() };
+                let (ids, handles): (Vec<_>, Vec<_>) = tool_calls.into_iter()
+                    .map(|tc| {
+                        let span = tool_span(&parent, &session_id, &tc);
+                        (tc.id.clone(), tokio::spawn(Self::run_tool(tc, batch_runner.clone()).instrument(span)))
+                    })
+                    .unzip();
+                let outputs = join_all(handles).await.into_iter().zip(ids).map(|(joined, id)| {
+                    joined.unwrap_or_else(|join_error| {
+                        debug!(target: "agent::tool_completed", "tool execution task failed: {}", join_error);
+                        (Some(tool_message(&id, &ToolResult::error(format!("tool execution task failed: {}", join_error)))), false)
+                    })
+                }).collect::<Vec<_>>();
+                if let Some(cache) = runner.tool_cache.as_ref().filter(|_| writes) {
+                    cache.clear();
                 }
-                any_denied = async {
-                    // wait for all tools completion and collect denial status
-                    let mut result = false;
-                    for handle in join_handles {
-                        if let Ok(was_denied) = handle.await {
-                            result = result || was_denied;
-                        }
+                outputs
+            } else {
+                let mut outputs = Vec::new();
+                for tc in tool_calls {
+                    // calls after a cancellation are not started
+                    if cancel_clone.is_cancelled() {
+                        outputs.push((Some(tool_message(&tc.id, &cancelled())), false));
+                        continue;
                     }
-                    result
-                } => {
-                    // All tools completed, move to Running state
-                    let _ = internal_tx.send(InternalAgentEvent::ToolsCompleted { any_denied });
+                    let span = tool_span(&parent, &session_id, &tc);
+                    outputs.push(Self::run_tool(tc, runner.clone()).instrument(span).await);
+                }
+                outputs
+            };
+
+            // results are added in the order of the calls, whatever order they completed in
+            let mut any_denied = false;
+            {
+                let mut trace = trace.write().await;
+                for (message, denied) in outputs {
+                    any_denied |= denied;
+                    trace.extend(message);
                 }
             }
+
+            // cancelled tools do not complete the step
+            if !cancel_clone.is_cancelled() {
+                let _ = internal_tx.send(InternalAgentEvent::ToolsCompleted { any_denied });
+            }
         });
         
         // Set state to Processing with cancellation token
@@ -73,98 +131,116 @@ impl AgentCore {
         }).await;
     }
 
-    /// Spawn a cancellable coroutine that runs a single tool call
-    /// coordinating the appropriate tool specific event (start/completed)
-    fn spawn_tool_static(
-        tc: LlmToolCall,
-        cancel_token: CancellationToken,
-        public_event_tx: Option<broadcast::Sender<AgentEvent>>,
-        available_tools: Vec<Arc<dyn AnyTool>>,
-        claims: Arc<RwLock<ClaimManager>>,
-        internal_tx: broadcast::Sender<InternalAgentEvent>,
-        trace: Arc<RwLock<Vec<ChatMessage>>>,
-    ) -> tokio::task::JoinHandle<bool> {
-        tokio::spawn(async move {
-            let tc_for_error = tc.clone();
-            match Self::tool_exist(available_tools, tc) {
-                // tool does not exist, we fail immediately
-                Err(tool_result) => {
-                    if let Some(tx) = public_event_tx.clone() {
-                        let _ = tx.send(AgentEvent::ToolCallCompleted { 
-                            duration: TimeDelta::zero(), 
-                            call: ToolCall {
-                                tool_call_id: tc_for_error.id.clone(),
-                                tool_name: tc_for_error.function.name.clone(),
-                                parameters: serde_json::Value::Null
-                            }, 
-                            result: tool_result
-                        });
-                    }
-                    false
+    /// Run a single tool call, coordinating the appropriate tool specific event (start/completed)
+    /// Returns the tool message to add to the trace and whether the call was denied
+    async fn run_tool(tc: LlmToolCall, runner: ToolRunner) -> (Option<ChatMessage>, bool) {
+        let ToolRunner { cancel_token, public_event_tx, available_tools, claims, internal_tx, tool_context, output_filters, tool_cache } = runner;
+        let tc_for_error = tc.clone();
+        match Self::tool_exist(available_tools, tc) {
+            // tool does not exist, we fail immediately
+            Err(tool_result) => {
+                if let Some(tx) = public_event_tx.clone() {
+                    let _ = tx.send(AgentEvent::ToolCallCompleted { 
+                        duration: TimeDelta::zero(), 
+                        call: ToolCall {
+                            tool_call_id: tc_for_error.id.clone(),
+                            tool_name: tc_for_error.function.name.clone(),
+                            parameters: serde_json::Value::Null
+                        }, 
+                        result: tool_result
+                    });
                 }
+                (None, false)
+            }
 
-                // emit tool call
-                // execute tool
-                // emit tool result
-                Ok((tool, call)) => {
-                    let start = Utc::now();
+            // emit tool call
+            // execute tool
+            // emit tool result
+            Ok((tool, call)) => {
+                let start = Utc::now();
 
-                    // Emit tool call started event
-                    if let Some(tx) = public_event_tx.clone() {
-                        let _ = tx.send(AgentEvent::ToolCallStarted { 
-                            timestamp: start.clone(), 
-                            call: call.clone(), 
-                        });
+                // Emit tool call started event
+                if let Some(tx) = public_event_tx.clone() {
+                    let _ = tx.send(AgentEvent::ToolCallStarted { 
+                        timestamp: start.clone(), 
+                        call: call.clone(), 
+                    });
+                }
+                
+                // an identical read-only call already ran: its result is reused
+                let cache = tool_cache.as_ref().filter(|_| ToolCache::is_cacheable(tool.capabilities()));
+                let cached = cache.and_then(|cache| cache.get(&call.tool_name, &call.parameters));
+                let invalidates_cache = ToolCache::invalidates(tool.capabilities());
+
+                let result: ToolResult = match cached {
+                    Some(result) => {
+                        debug!(target: "agent::tool_completed", "{} result served from the tool cache", call.tool_name);
+                        result
                     }
-                    
-                    // execute tool
-                    let tool_handle = Self::spawn_tool_exec(
-                        tool, call.clone(), 
-                        cancel_token.clone(), 
-                        claims, 
-                        public_event_tx.clone(), 
-                        internal_tx.subscribe());
+                    None => {
+                        // execute tool
+                        let tool_handle = Self::spawn_tool_exec(
+                            tool, call.clone(), 
+                            cancel_token.clone(), 
+                            claims, 
+                            public_event_tx.clone(), 
+                            internal_tx.subscribe(),
+                            tool_context);
 
-                    // wait for result (or for cancellation)
-                    let result: ToolResult = tokio::select! {
-                        join_result = tool_handle => {
-                            match join_result {
-                                Ok(tool_result) => tool_result,
-                                Err(join_error) => {
-                                    debug!(target: "agent::tool_completed", "tool execution task failed: {}", join_error);
-                                    ToolResult::error(format!("tool execution task failed: {}", join_error))
+                        // wait for result (or for cancellation)
+                        let result = tokio::select! {
+                            join_result = tool_handle => {
+                                match join_result {
+                                    Ok(tool_result) => tool_result,
+                                    Err(join_error) => {
+                                        debug!(target: "agent::tool_completed", "tool execution task failed: {}", join_error);
+                                        ToolResult::error(format!("tool execution task failed: {}", join_error))
+                                    }
                                 }
+                             },
+                            _ = cancel_token.cancelled() => {
+                                debug!(target: "agent::tool_completed", "cancelled by user");
+                                cancelled()
                             }
-                         },
-                        _ = cancel_token.cancelled() => {
-                            debug!(target: "agent::tool_completed", "cancelled by user");
-                            ToolResult::error("tool call was cancelled by the user".to_string())
+                        };
+                        if let Some(cache) = cache {
+                            cache.put(&call.tool_name, &call.parameters, &result);
                         }
-                    };
-
-                    // let's first add tool result to trace
-                    let _ = {
-                        trace.write().await.push(ChatMessage::Tool {
-                            tool_call_id: call.tool_call_id.clone(),
-                            content: ChatMessageContent::Text(result.to_string())
-                        });
-                    };
+                        result
+                    }
+                };
 
-                    // Emit tool call finish event
-                    let tool_was_denied = result.is_denied();
-                    info!(target: "agent::tool_completed", call = ?tc_for_error.function.name.clone(), result = ?result);
-                    if let Some(tx) = public_event_tx.clone() {
-                        let _ = tx.send(AgentEvent::ToolCallCompleted { 
-                            duration: Utc::now() - start, 
-                            call: call, 
-                            result 
-                        });   
+                // a tool that may have written makes the cached reads stale
+                if invalidates_cache {
+                    if let Some(cache) = &tool_cache {
+                        cache.clear();
                     }
+                }
+
+                // post-process the output, the raw output stays in the result metadata
+                let result = output_filters.apply(&call.tool_name, result);
+                let message = tool_message(&call.tool_call_id, &result);
 
-                    tool_was_denied                    
+                // Emit tool call finish event
+                let tool_was_denied = result.is_denied();
+                let status = match &result {
+                    ToolResult::Success { .. } => "success",
+                    ToolResult::Error { .. } => "error",
+                    ToolResult::Denied => "denied",
+                };
+                Span::current().record("status", status);
+                info!(target: "agent::tool_completed", call = ?tc_for_error.function.name.clone(), result = ?result);
+                if let Some(tx) = public_event_tx.clone() {
+                    let _ = tx.send(AgentEvent::ToolCallCompleted { 
+                        duration: Utc::now() - start, 
+                        call: call, 
+                        result 
+                    });   
                 }
+
+                (Some(message), tool_was_denied)
             }
-        })
+        }
     }
 
     /// execute a single tool call
@@ -175,7 +251,8 @@ impl AgentCore {
         cancel_token: CancellationToken,
         claims: Arc<RwLock<ClaimManager>>, 
         public_event_tx: Option<broadcast::Sender<AgentEvent>>, 
-        mut internal_rx: broadcast::Receiver<InternalAgentEvent>) -> JoinHandle<ToolResult> {
+        mut internal_rx: broadcast::Receiver<InternalAgentEvent>,
+        tool_context: ToolContext) -> JoinHandle<ToolResult> {
         tokio::spawn(async move {
             // check permission, we allow all Read Tool
             let can_run = tool.capabilities().is_empty()  
@@ -191,13 +268,28 @@ impl AgentCore {
             if !can_run {
                 return ToolResult::denied()
             }
+
+            // Progress reported by the tool is forwarded as public events
+            let tool_context = match public_event_tx.clone() {
+                Some(tx) => {
+                    let call_id = call.tool_call_id.clone();
+                    let tool_name = call.tool_name.clone();
+                    tool_context.with_progress(ProgressReporter::new(move |message, percent| {
+                        let _ = tx.send(AgentEvent::ToolProgress {
+                            call_id: call_id.clone(),
+                            tool_name: tool_name.clone(),
+                            message,
+                            percent,
+                        });
+                    }))
+                }
+                None => tool_context,
+            };
             
-            // Execute tool with cancellation support
+            // Execute tool with cancellation support, within the session tool context
             tokio::select! {
-                result = tool.execute_json(call.parameters.clone(), Some(cancel_token.clone())) => result,
-                _ = cancel_token.cancelled() => {
-                    ToolResult::error("tool call was cancelled by the user".to_string())
-                }
+                result = tool_context.scope(tool.execute_json(call.parameters.clone(), Some(cancel_token.clone()))) => result,
+                _ = cancel_token.cancelled() => cancelled()
             }
         })
     }
@@ -3,18 +3,19 @@ use std::boxed::Box;
 use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent};
 use shai_llm::ToolCallMethod;
 use tokio::sync::{mpsc, broadcast, RwLock, oneshot};
+use tokio::task::JoinHandle;
 use serde::{Serialize, Deserialize};
 use async_trait::async_trait;
-use crate::tools::AnyTool;
+use crate::tools::{AnyTool, ToolCache, ToolContext, ToolOutputFilters};
 use crate::agent::ClaimManager;
 
 // Helper functions to make the main loop more readable
 
-use crate::agent::{Brain, InternalAgentEvent};
+use crate::agent::{Brain, ContextManager, InternalAgentEvent, SamplingOverrides, ThinkerDecision, TokenBudget};
 use crate::agent::AgentError;
 use crate::agent::{AgentRequest, AgentEvent};
 use crate::agent::InternalAgentState;
-use tracing::debug;
+use tracing::{debug, Span};
 
 use super::protocol::{AgentController, SentCommand};
 use super::{AgentResponse, AgentEventHandler};
@@ -69,6 +70,23 @@ pub struct AgentCore {
     pub available_tools: Vec<Arc<dyn AnyTool>>,
     pub permissions:     Arc<RwLock<ClaimManager>>,
     pub state:           InternalAgentState,
+    pub tool_context:    ToolContext,
+    pub output_filters:  ToolOutputFilters,
+    /// results of read-only tool calls reused by identical calls (None = every call runs)
+    pub tool_cache:      Option<Arc<ToolCache>>,
+    pub stream_tool_arguments: bool,
+    /// run the tool calls of a brain step concurrently (default) instead of one after the other
+    pub parallel_tool_calls: bool,
+    pub sampling:        SamplingOverrides,
+    /// keeps the trace under a token budget, compacted before brain steps
+    pub context_manager: Option<Arc<ContextManager>>,
+    /// tokens the session may still consume, checked before brain steps (None = no budget)
+    pub token_budget: Option<Arc<TokenBudget>>,
+
+    /// running brain step, hands its partial output over when it is cancelled
+    pub brain_task: Option<JoinHandle<Option<ThinkerDecision>>>,
+    /// span of the request being processed, released when the agent stops or pauses
+    pub request_span: Span,
 
     /// internal event
     pub internal_tx: broadcast::Sender<InternalAgentEvent>,   // event may be produced from many part of the agent
@@ -105,6 +123,16 @@ impl AgentCore {
             available_tools: available_tools.into_iter().map(|t| Arc::from(t) as Arc<dyn AnyTool>).collect(),
             permissions: Arc::new(RwLock::new(permissions)),
             state: InternalAgentState::Starting,
+            tool_context: ToolContext::default(),
+            output_filters: ToolOutputFilters::default(),
+            tool_cache: None,
+            stream_tool_arguments: false,
+            parallel_tool_calls: true,
+            sampling: SamplingOverrides::default(),
+            context_manager: None,
+            token_budget: None,
+            brain_task: None,
+            request_span: Span::none(),
             internal_tx,
             internal_rx,
         }
@@ -418,16 +446,21 @@ impl AgentCore {
                     Ok(AgentResponse::Ack)
                 })
             }
-            AgentRequest::SendTrace{ messages } => {
+            AgentRequest::SendTrace{ messages, span } => {
                 self.handle_event(InternalAgentEvent::CancelTask).await
                 .and({
                     // Add all messages to trace at once
                     self.trace.write().await.extend(messages);
+                    self.request_span = span;
 
                     self.set_state(InternalAgentState::Running).await;
                     Ok(AgentResponse::Ack)
                 })
             }
+            AgentRequest::AppendTrace{ messages } => {
+                self.trace.write().await.extend(messages);
+                Ok(AgentResponse::Ack)
+            }
             AgentRequest::UserQueryResponse{ request_id: query_id, response } => {
                 // This event is managed by the spawn thread directly, thus sending to the broadcast internal event channel
                 let _ = self.internal_tx.send(InternalAgentEvent::UserResponseReceived{
@@ -488,6 +521,12 @@ impl AgentCore {
             "{:?} <<--- {:?}", new_state, old_state
         );
         
+        // the request is over, its span ends once the spans of its steps are closed
+        use super::states::PublicAgentState;
+        if matches!(new_state, PublicAgentState::Paused | PublicAgentState::Completed { .. } | PublicAgentState::Cancelled | PublicAgentState::Failed { .. }) {
+            self.request_span = Span::none();
+        }
+
         // Emit event
         let _ = self.emit_event(AgentEvent::StatusChanged {
             old_status: old_state,
@@ -1,25 +1,148 @@
 use std::sync::Arc;
 use async_trait::async_trait;
-use openai_dive::v1::resources::chat::ChatMessage;
-use shai_llm::ToolCallMethod;
+use openai_dive::v1::resources::chat::{ChatCompletionParameters, ChatCompletionResponseFormat, ChatMessage, ChatMessageContent};
+use openai_dive::v1::resources::shared::StopToken;
+use serde_json::Value;
+use shai_llm::{ToolCallDelta, ToolCallMethod};
 use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
 
 use crate::tools::types::AnyToolBox;
 use super::error::AgentError;
 
 
+/// Receives the tool call arguments streamed by the model
+pub type ToolCallDeltaSink = Arc<dyn Fn(ToolCallDelta) + Send + Sync>;
+
+/// Sampling parameters set by the caller of the agent (e.g. per HTTP request), over the ones of the brain
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SamplingOverrides {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub stop: Option<Vec<String>>,
+    /// Format of the replies (JSON object or schema), see apply_response_format
+    pub response_format: Option<ChatCompletionResponseFormat>,
+}
+
+impl SamplingOverrides {
+    /// Set the overridden parameters on an LLM request, the temperature is left to the brain
+    pub fn apply(&self, request: &mut ChatCompletionParameters) {
+        if let Some(top_p) = self.top_p {
+            request.top_p = Some(top_p);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            request.max_completion_tokens = Some(max_tokens);
+        }
+        if let Some(stop) = &self.stop {
+            request.stop = Some(StopToken::Array(stop.clone()));
+        }
+    }
+
+    /// Ask for the response format on an LLM request: sent as `response_format` when the provider
+    /// supports it (`native`), as a system instruction after the messages otherwise
+    pub fn apply_response_format(&self, request: &mut ChatCompletionParameters, native: bool) {
+        let Some(format) = &self.response_format else {
+            return;
+        };
+        if native {
+            request.response_format = Some(format.clone());
+        } else if let Some(instruction) = json_instruction(format) {
+            request.messages.push(ChatMessage::System {
+                content: ChatMessageContent::Text(instruction),
+                name: None,
+            });
+        }
+    }
+}
+
+/// Instruction standing for a JSON response format, None for plain text
+fn json_instruction(format: &ChatCompletionResponseFormat) -> Option<String> {
+    match format {
+        ChatCompletionResponseFormat::Text => None,
+        ChatCompletionResponseFormat::JsonObject => Some(
+            "Answer with a single valid JSON object and nothing else: no code fence, no text around it.".to_string(),
+        ),
+        ChatCompletionResponseFormat::JsonSchema { json_schema } => {
+            let schema = json_schema.schema.as_ref().map(|schema| schema.to_string()).unwrap_or_else(|| "{}".to_string());
+            Some(format!(
+                "Answer with a single valid JSON value matching the JSON schema below and nothing else: \
+                no code fence, no text around it.\n{}",
+                schema
+            ))
+        }
+    }
+}
+
+/// Whether a response format asks for JSON (object or schema)
+pub fn is_json_format(format: &ChatCompletionResponseFormat) -> bool {
+    !matches!(format, ChatCompletionResponseFormat::Text)
+}
+
+/// JSON of a reply when it matches `format`, taken out of a code fence or the text around it if needed
+pub fn json_reply(text: &str, format: &ChatCompletionResponseFormat) -> Option<String> {
+    let text = text.trim();
+    let fenced = text
+        .strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+        .and_then(|rest| rest.split_once('\n'))
+        .map(|(_, body)| body.trim());
+    let outermost = match (text.find(['{', '[']), text.rfind(['}', ']'])) {
+        (Some(start), Some(end)) if start < end => Some(&text[start..=end]),
+        _ => None,
+    };
+
+    [Some(text), fenced, outermost].into_iter().flatten().find_map(|candidate| {
+        let value: Value = serde_json::from_str(candidate).ok()?;
+        matches_format(&value, format).then(|| candidate.to_string())
+    })
+}
+
+/// Whether a JSON value has the shape of `format`: an object for json_object, the top-level
+/// type and required properties of the schema for json_schema
+pub fn matches_format(value: &Value, format: &ChatCompletionResponseFormat) -> bool {
+    let schema = match format {
+        ChatCompletionResponseFormat::Text => return true,
+        ChatCompletionResponseFormat::JsonObject => return value.is_object(),
+        ChatCompletionResponseFormat::JsonSchema { json_schema } => json_schema.schema.as_ref(),
+    };
+    let Some(schema) = schema else {
+        return true;
+    };
+    let type_matches = match schema.get("type").and_then(Value::as_str) {
+        Some("object") => value.is_object(),
+        Some("array") => value.is_array(),
+        Some("string") => value.is_string(),
+        Some("number") => value.is_number(),
+        Some("integer") => value.is_i64() || value.is_u64(),
+        Some("boolean") => value.is_boolean(),
+        _ => true,
+    };
+    let required = schema.get("required").and_then(Value::as_array).into_iter().flatten();
+    type_matches && required.filter_map(Value::as_str).all(|key| value.get(key).is_some())
+}
+
 /// ThinkerContext is the agent internal state
 pub struct ThinkerContext {
     pub trace:           Arc<RwLock<Vec<ChatMessage>>>,
     pub available_tools: AnyToolBox,
-    pub method:          ToolCallMethod
+    pub method:          ToolCallMethod,
+    /// Set when tool call arguments should be streamed while generated
+    pub on_tool_call_delta: Option<ToolCallDeltaSink>,
+    /// Cancelled when the agent stops the step (stop, terminate), the brain should then
+    /// drop its LLM call and return what was generated so far with ThinkerFlowControl::Cancelled
+    pub cancellation_token: CancellationToken,
+    /// Sampling parameters the brain should use over its own
+    pub sampling: SamplingOverrides,
 }
 
 /// ThinkerFlowControl drives the agentic flow
 #[derive(Debug, Clone)]
 pub enum ThinkerFlowControl {
     AgentContinue,
-    AgentPause
+    AgentPause,
+    /// The step was cancelled, the message holds the partial output
+    Cancelled,
 }
 
 /// This structure pilot the flow of the Agent
@@ -30,6 +153,7 @@ pub struct ThinkerDecision {
     pub message: ChatMessage,
     pub flow:    ThinkerFlowControl,
     pub token_usage: Option<(u32, u32)>, // (input_tokens, output_tokens)
+    pub empty_retries: u32, // calls retried because the model replied with an empty message
 }
 
 impl ThinkerDecision {
@@ -38,6 +162,7 @@ impl ThinkerDecision {
             message,
             flow: ThinkerFlowControl::AgentPause,
             token_usage: None,
+            empty_retries: 0,
         }
     }
 
@@ -46,6 +171,7 @@ impl ThinkerDecision {
             message,
             flow: ThinkerFlowControl::AgentContinue,
             token_usage: None,
+            empty_retries: 0,
         }
     }
 
@@ -54,6 +180,7 @@ impl ThinkerDecision {
             message,
             flow: ThinkerFlowControl::AgentPause,
             token_usage: None,
+            empty_retries: 0,
         }
     }
 
@@ -62,6 +189,7 @@ impl ThinkerDecision {
             message,
             flow: ThinkerFlowControl::AgentContinue,
             token_usage: Some((input_tokens, output_tokens)),
+            empty_retries: 0,
         }
     }
 
@@ -70,9 +198,28 @@ impl ThinkerDecision {
             message,
             flow: ThinkerFlowControl::AgentPause,
             token_usage: Some((input_tokens, output_tokens)),
+            empty_retries: 0,
+        }
+    }
+
+    pub fn cancelled(message: ChatMessage, token_usage: Option<(u32, u32)>) -> Self {
+        ThinkerDecision{
+            message,
+            flow: ThinkerFlowControl::Cancelled,
+            token_usage,
+            empty_retries: 0,
         }
     }
 
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.flow, ThinkerFlowControl::Cancelled)
+    }
+
+    pub fn with_empty_retries(mut self, empty_retries: u32) -> Self {
+        self.empty_retries = empty_retries;
+        self
+    }
+
     pub fn unwrap(self) -> ChatMessage {
         self.message
     }
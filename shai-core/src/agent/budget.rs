@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::AgentError;
+
+/// LLM tokens a session may consume over all its requests, shared by its agent and the server
+///
+/// Usage is charged from the usage the provider reports for each brain step (input and output
+/// tokens), a step is only refused once the budget is spent: the step that crosses it completes.
+#[derive(Debug)]
+pub struct TokenBudget {
+    limit: Option<u64>,
+    used: AtomicU64,
+}
+
+impl TokenBudget {
+    pub fn new(limit: u64) -> Self {
+        Self { limit: Some(limit), used: AtomicU64::new(0) }
+    }
+
+    /// Budget that only tracks usage
+    pub fn unlimited() -> Self {
+        Self { limit: None, used: AtomicU64::new(0) }
+    }
+
+    pub fn limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    /// Tokens left before the budget is spent (None = unlimited)
+    pub fn remaining(&self) -> Option<u64> {
+        self.limit.map(|limit| limit.saturating_sub(self.used()))
+    }
+
+    /// Charge the tokens of a brain step
+    pub fn record(&self, input_tokens: u32, output_tokens: u32) {
+        self.used.fetch_add(input_tokens as u64 + output_tokens as u64, Ordering::SeqCst);
+    }
+
+    /// Check that tokens are left for another LLM call
+    pub fn check(&self) -> Result<(), AgentError> {
+        match self.limit {
+            Some(limit) if self.used() >= limit => Err(AgentError::BudgetExceeded { used: self.used(), limit }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_is_spent_by_the_step_crossing_it() {
+        let budget = TokenBudget::new(1000);
+        budget.record(600, 100);
+        assert_eq!(budget.remaining(), Some(300));
+        assert!(budget.check().is_ok());
+
+        budget.record(500, 20);
+        assert_eq!(budget.used(), 1220);
+        assert_eq!(budget.remaining(), Some(0));
+        assert!(matches!(budget.check(), Err(AgentError::BudgetExceeded { used: 1220, limit: 1000 })));
+
+        let unlimited = TokenBudget::unlimited();
+        unlimited.record(u32::MAX, u32::MAX);
+        assert!(unlimited.check().is_ok());
+        assert_eq!(unlimited.remaining(), None);
+    }
+}
@@ -1,14 +1,16 @@
 use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent};
-use shai_llm::LlmClient;
+use shai_llm::{LlmClient, ToolCallMethod};
 use uuid::Uuid;
+use std::collections::HashMap;
 use std::sync::Arc;
+use tracing::warn;
 
 use crate::tools::mcp::mcp_oauth::signin_oauth;
-use crate::tools::{create_mcp_client, get_mcp_tools, AnyTool, BashTool, EditTool, FetchTool, FindTool, FsOperationLog, LsTool, McpConfig, MultiEditTool, ReadTool, TodoReadTool, TodoStorage, TodoWriteTool, WriteTool};
-use crate::config::agent::AgentConfig;
+use crate::tools::{create_mcp_client, get_mcp_tools, AnyTool, DiskQuota, DynamicToolLoader, ToolCache, ToolContext, ToolOutputFilters, BashTool, EditTool, FetchTool, FindTool, FsOperationLog, LsTool, McpConfig, MultiEditTool, ReadTool, TodoReadTool, TodoStorage, TodoWriteTool, WriteTool};
+use crate::config::agent::{AgentConfig, AgentProviderConfig};
 use crate::config::config::ShaiConfig;
 use crate::runners::coder::CoderBrain;
-use super::Brain;
+use super::{Brain, ContextManager, SamplingOverrides, TokenBudget};
 use super::AgentCore;
 use super::claims::ClaimManager;
 use super::AgentError;
@@ -17,41 +19,134 @@ use super::AgentError;
 pub struct AgentBuilder {
     pub session_id: String,
     pub brain: Box<dyn Brain>,
+    /// Model the brain runs on, its tokenizer counts the trace of the context manager
+    pub model: Option<String>,
     pub goal: Option<String>,
     pub trace: Vec<ChatMessage>,
     pub available_tools: Vec<Box<dyn AnyTool>>,
     pub permissions: ClaimManager,
+    pub tool_env: HashMap<String, String>,
+    pub output_filters: ToolOutputFilters,
+    pub tool_cache: Option<Arc<ToolCache>>,
+    pub stream_tool_arguments: bool,
+    pub parallel_tool_calls: bool,
+    pub disk_quota: Option<Arc<DiskQuota>>,
+    pub sampling: SamplingOverrides,
+    pub context_manager: Option<ContextManager>,
+    pub token_budget: Option<Arc<TokenBudget>>,
 }
 
 impl AgentBuilder {
     /// Create a new AgentBuilder with an optional config name
     /// If None, creates a default agent with LLM from ShaiConfig
     /// If Some(name), loads agent from config file
+    /// If Some(pattern) (e.g. "customer-*"), loads the matching config or falls back to the default agent
     pub async fn create(config_name: Option<String>) -> Result<Self, AgentError> {
-        match config_name {
-            Some(name) => {
-                let config = AgentConfig::load(&name)
-                    .map_err(|e| AgentError::ConfigurationError(format!("Failed to load agent '{}': {}", name, e)))?;
+        Self::create_with_model(config_name, None).await
+    }
+
+    /// Same as `create`, with the model of the agent's LLM provider replaced by `model` when set
+    pub async fn create_with_model(config_name: Option<String>, model: Option<String>) -> Result<Self, AgentError> {
+        match Self::resolve_config(config_name)? {
+            Some(mut config) => {
+                if let Some(model) = model {
+                    config.llm_provider.model = model;
+                }
                 Self::from_config(config).await
             }
-            None => Self::default().await,
+            None => Self::default_with_model(model).await,
+        }
+    }
+
+    /// Same as `create`, with the agent running on `model` of `provider` instead of its own LLM
+    /// Credentials come from the first shai config entry of that provider, else from the environment
+    pub async fn create_with_provider(config_name: Option<String>, provider: &str, model: String) -> Result<Self, AgentError> {
+        let llm_provider = Self::provider_settings(provider, model);
+        match Self::resolve_config(config_name)? {
+            Some(mut config) => {
+                config.llm_provider = llm_provider;
+                Self::from_config(config).await
+            }
+            None => Ok(Self::default_with_llm(Self::llm_client(&llm_provider)?, llm_provider.model)),
+        }
+    }
+
+    /// LLM client `create_with_provider` runs the agents on for `provider`
+    pub fn provider_llm(provider: &str) -> Result<LlmClient, AgentError> {
+        Self::llm_client(&Self::provider_settings(provider, String::new()))
+    }
+
+    /// Settings of `model` on `provider`, borrowed from the configured entry of that provider if any
+    fn provider_settings(provider: &str, model: String) -> AgentProviderConfig {
+        let config = ShaiConfig::load().unwrap_or_default();
+        let entry = config.providers.into_iter().find(|entry| entry.provider == provider);
+        AgentProviderConfig {
+            provider: provider.to_string(),
+            env_vars: entry.as_ref().map(|entry| entry.env_vars.clone()).unwrap_or_default(),
+            model,
+            tool_method: entry.as_ref().map_or(ToolCallMethod::FunctionCall, |entry| entry.tool_method.clone()),
+            key_pool: entry.as_ref().and_then(|entry| entry.key_pool.clone()),
+            circuit_breaker: entry.and_then(|entry| entry.circuit_breaker),
         }
     }
 
+    /// LLM client and model of the agent `create` would build, without creating its tools
+    pub async fn llm(config_name: Option<String>) -> Result<(LlmClient, String), AgentError> {
+        match Self::resolve_config(config_name)? {
+            Some(config) => Ok((Self::llm_client(&config.llm_provider)?, config.llm_provider.model)),
+            None => ShaiConfig::get_llm().await
+                .map_err(|e| AgentError::ConfigurationError(format!("Failed to get LLM from config: {}", e))),
+        }
+    }
+
+    /// Agent config of `config_name`, None for the default agent
+    fn resolve_config(config_name: Option<String>) -> Result<Option<AgentConfig>, AgentError> {
+        let name = match config_name {
+            Some(pattern) if AgentConfig::is_pattern(&pattern) => AgentConfig::resolve_pattern(&pattern)
+                .map_err(|e| AgentError::ConfigurationError(e.to_string()))?,
+            name => name,
+        };
+        name.map(|name| {
+            AgentConfig::load(&name)
+                .map_err(|e| AgentError::ConfigurationError(format!("Failed to load agent '{}': {}", name, e)))
+        })
+        .transpose()
+    }
+
+    /// LLM client of the provider settings of an agent
+    fn llm_client(provider: &AgentProviderConfig) -> Result<LlmClient, AgentError> {
+        LlmClient::create_provider_from_config(
+            &provider.provider,
+            &provider.env_vars,
+            provider.key_pool.as_ref(),
+            provider.circuit_breaker.as_ref(),
+        )
+        .map_err(|e| AgentError::LlmError(e.to_string()))
+    }
+
     /// Create a default AgentBuilder using ShaiConfig LLM and default tools
     pub async fn default() -> Result<Self, AgentError> {
+        Self::default_with_model(None).await
+    }
+
+    async fn default_with_model(model: Option<String>) -> Result<Self, AgentError> {
         // Get LLM from ShaiConfig
-        let (llm_client, model) = ShaiConfig::get_llm().await
+        let (llm_client, configured_model) = ShaiConfig::get_llm().await
             .map_err(|e| AgentError::ConfigurationError(format!("Failed to get LLM from config: {}", e)))?;
+        Ok(Self::default_with_llm(llm_client, model.unwrap_or(configured_model)))
+    }
 
+    fn default_with_llm(llm_client: LlmClient, model: String) -> Self {
         // Create default brain
-        let brain = Box::new(CoderBrain::new(Arc::new(llm_client), model));
+        let brain = Box::new(CoderBrain::new(Arc::new(llm_client), model.clone()));
 
         // Create default toolbox (using ToolConfig from shai-cli)
         // For now, create basic tools - we can expand this later
-        let tools = Self::create_default_tools();
+        let mut tools = Self::create_default_tools();
+        // the folder is only read when SHAI_TOOLS_DIR is set, which opts the default agent in
+        Self::add_dynamic_tools(&mut tools, &["*".to_string()]);
 
-        Ok(Self::with_brain(brain).tools(tools))
+        Self::with_brain(brain).model(model).tools(tools)
     }
 
     /// Create AgentBuilder with a specific brain
@@ -59,10 +154,20 @@ impl AgentBuilder {
         Self {
             session_id: Uuid::new_v4().to_string(),
             brain,
+            model: None,
             goal: None,
             trace: vec![],
             available_tools: vec![],
             permissions: ClaimManager::new(),
+            tool_env: HashMap::new(),
+            output_filters: ToolOutputFilters::default(),
+            tool_cache: None,
+            stream_tool_arguments: false,
+            parallel_tool_calls: true,
+            disk_quota: None,
+            sampling: SamplingOverrides::default(),
+            context_manager: None,
+            token_budget: None,
         }
     }
 
@@ -84,6 +189,29 @@ impl AgentBuilder {
             Box::new(WriteTool::new(fs_log)),
         ]
     }
+
+    /// Add the HTTP tools of the tools folder named in `enabled` ("*" for all), a tool whose name
+    /// is already taken is left out
+    /// Nothing is added unless SHAI_TOOLS_DIR names the folder
+    fn add_dynamic_tools(tools: &mut Vec<Box<dyn AnyTool>>, enabled: &[String]) {
+        if enabled.is_empty() {
+            return;
+        }
+        let Some(loader) = DynamicToolLoader::shared() else {
+            return;
+        };
+        for tool in loader.tools() {
+            let name = tool.name();
+            if !enabled.contains(&"*".to_string()) && !enabled.contains(&name) {
+                continue;
+            }
+            if tools.iter().any(|existing| existing.name() == name) {
+                warn!("Skipping dynamic tool '{}': a tool of that name already exists", name);
+                continue;
+            }
+            tools.push(tool);
+        }
+    }
 }
 
 impl AgentBuilder {
@@ -96,6 +224,12 @@ impl AgentBuilder {
         self.brain = brain;
         self
     }
+
+    /// Model the brain runs on, set by the constructors that create the brain
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
     
     pub fn goal(mut self, goal: &str) -> Self {
         self.goal = Some(goal.to_string());
@@ -117,6 +251,61 @@ impl AgentBuilder {
         self
     }
 
+    /// Environment variables injected into tool execution (e.g. bash processes)
+    pub fn tool_env(mut self, env: HashMap<String, String>) -> Self {
+        self.tool_env = env;
+        self
+    }
+
+    /// Filters applied to successful tool outputs before they enter the trace
+    pub fn output_filters(mut self, filters: ToolOutputFilters) -> Self {
+        self.output_filters = filters;
+        self
+    }
+
+    /// Stream tool call arguments as ToolCallArgumentsDelta events while the model generates them
+    pub fn stream_tool_arguments(mut self, enabled: bool) -> Self {
+        self.stream_tool_arguments = enabled;
+        self
+    }
+
+    /// Run the tool calls of one model reply concurrently (default) or one after the other
+    /// Their results still enter the trace in the order of the calls
+    pub fn parallel_tool_calls(mut self, enabled: bool) -> Self {
+        self.parallel_tool_calls = enabled;
+        self
+    }
+
+    /// Reuse the results of identical read-only tool calls for the TTL of the cache
+    pub fn tool_cache(mut self, cache: Arc<ToolCache>) -> Self {
+        self.tool_cache = Some(cache);
+        self
+    }
+
+    /// Disk quota the file tools charge their writes to
+    pub fn disk_quota(mut self, quota: Arc<DiskQuota>) -> Self {
+        self.disk_quota = Some(quota);
+        self
+    }
+
+    /// Sampling parameters used over the ones of the brain (temperature, top_p, max tokens, stop)
+    pub fn sampling(mut self, sampling: SamplingOverrides) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    /// Keep the trace under a token budget, compacting it with the strategy of the manager
+    pub fn context_manager(mut self, manager: ContextManager) -> Self {
+        self.context_manager = Some(manager);
+        self
+    }
+
+    /// Tokens the agent may consume, brain steps are refused once they are spent
+    pub fn token_budget(mut self, budget: Arc<TokenBudget>) -> Self {
+        self.token_budget = Some(budget);
+        self
+    }
+
     /// Enable sudo mode - bypasses all permission checks
     pub fn sudo(mut self) -> Self {
         self.permissions.sudo();
@@ -130,22 +319,38 @@ impl AgentBuilder {
         }
 
 
-        AgentCore::new(
+        let mut agent = AgentCore::new(
             self.session_id.clone(),
             self.brain,
             self.trace,
             self.available_tools,
             self.permissions
-        )
+        );
+        agent.tool_context = ToolContext::new(self.tool_env).with_disk_quota(self.disk_quota);
+        agent.output_filters = self.output_filters;
+        agent.tool_cache = self.tool_cache;
+        agent.stream_tool_arguments = self.stream_tool_arguments;
+        agent.parallel_tool_calls = self.parallel_tool_calls;
+        agent.sampling = self.sampling;
+        agent.context_manager = self.context_manager.map(|manager| {
+            // count with the tokenizer of the agent's model unless the manager names its own
+            let manager = match self.model {
+                Some(model) if manager.model_name().is_none() => manager.model(model),
+                _ => manager,
+            };
+            Arc::new(manager)
+        });
+        agent.token_budget = self.token_budget;
+        agent
     }
 
     /// Create an AgentBuilder from an AgentConfig
     pub async fn from_config(mut config: AgentConfig) -> Result<Self, AgentError> {
+        let output_filters = ToolOutputFilters::from_config(&config.tools.post_process)
+            .map_err(AgentError::ConfigurationError)?;
+
         // Create LLM client from provider config using the utility method
-        let llm_client = Arc::new(
-            LlmClient::create_provider(&config.llm_provider.provider, &config.llm_provider.env_vars)
-                .map_err(|e| AgentError::LlmError(e.to_string()))?
-        );
+        let llm_client = Arc::new(Self::llm_client(&config.llm_provider)?);
 
         // Create brain with custom system prompt and temperature
         let brain = Box::new(CoderBrain::with_custom_prompt(
@@ -153,7 +358,7 @@ impl AgentBuilder {
             config.llm_provider.model.clone(),
             config.system_prompt.clone(),
             config.temperature,
-        ));
+        ).with_empty_completion(config.empty_completion.clone()));
 
         // Create tools
         let tools = Self::create_tools_from_config(&mut config).await?;
@@ -171,6 +376,11 @@ impl AgentBuilder {
             eprintln!("\x1b[2m░ builtin: {}\x1b[0m", builtin_tools.join(", "));
         }
         
+        // Display HTTP tools of the tools folder
+        if let Some(dynamic_tools) = tool_groups.remove("dynamic") {
+            eprintln!("\x1b[2m░ dynamic: {}\x1b[0m", dynamic_tools.join(", "));
+        }
+
         // Display MCP tools
         for (group_name, group_tools) in tool_groups {
             if group_name != "unknown" {
@@ -179,7 +389,10 @@ impl AgentBuilder {
         }
 
         Ok(Self::with_brain(brain)
+            .model(config.llm_provider.model.clone())
             .tools(tools)
+            .output_filters(output_filters)
+            .stream_tool_arguments(config.stream_tool_arguments)
             .id(&format!("agent-{}", config.name)))
     }
 
@@ -264,6 +477,8 @@ impl AgentBuilder {
             }
         }
 
+        Self::add_dynamic_tools(&mut tools, &config.tools.dynamic);
+
         // Save config if OAuth flow added new tokens
         if config_changed {
             config.save().map_err(|e| AgentError::ConfigurationError(format!("Failed to save agent config: {}", e)))?;
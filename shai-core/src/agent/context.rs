@@ -0,0 +1,377 @@
+use std::sync::{Arc, Mutex};
+
+use openai_dive::v1::resources::chat::{ChatCompletionParametersBuilder, ChatMessage, ChatMessageContent};
+use shai_llm::{count_message_tokens, estimate_message_tokens, LlmClient};
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use super::error::AgentError;
+
+/// Instructions of the summarization call of ContextStrategy::Summarize
+const SUMMARY_PROMPT: &str = "Summarize the conversation below for the assistant that will continue it. \
+Keep the user's goals, the decisions taken, the files and commands involved and the results of the tool calls \
+that still matter. Answer with the summary only.";
+
+/// Prefix of the message replacing the summarized messages
+const SUMMARY_PREFIX: &str = "Summary of the earlier conversation:";
+
+/// How the trace is brought back under the limit
+#[derive(Clone)]
+pub enum ContextStrategy {
+    /// Drop the oldest messages
+    SlidingWindow,
+    /// Replace the oldest messages with a summary written by `llm`
+    Summarize { llm: Arc<LlmClient>, model: String },
+}
+
+/// Keeps the trace of an agent within a token budget
+///
+/// Before each brain step the size of the trace is checked: the usage reported by the provider
+/// for the previous step, plus the tokens of the messages added since (tool results), counted
+/// with the tokenizer of the model when it is known and estimated otherwise. Above
+/// `max_tokens`, the messages between the leading system messages and the last `keep_turns`
+/// turns are dropped or summarized. A turn is a user or assistant message with the tool results
+/// answering it, so tool calls are never separated from their results.
+pub struct ContextManager {
+    max_tokens: u32,
+    keep_turns: usize,
+    strategy: ContextStrategy,
+    /// Model whose tokenizer counts the messages not covered by a reported usage
+    model: Option<String>,
+    /// Tokens of the trace as reported by the provider, and the trace length they cover
+    reported: Mutex<Option<(u32, usize)>>,
+}
+
+impl ContextManager {
+    pub fn new(max_tokens: u32, strategy: ContextStrategy) -> Self {
+        Self { max_tokens, keep_turns: 4, strategy, model: None, reported: Mutex::new(None) }
+    }
+
+    /// Count the messages with the tokenizer of `model` (default: the character-based estimate)
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    /// Model whose tokenizer counts the messages, None for the character-based estimate
+    pub fn model_name(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    /// Turns kept as is when the trace is compacted (default 4)
+    pub fn keep_turns(mut self, keep_turns: usize) -> Self {
+        self.keep_turns = keep_turns.max(1);
+        self
+    }
+
+    pub fn max_tokens(&self) -> u32 {
+        self.max_tokens
+    }
+
+    /// Record the usage of the step that produced the last message of a trace of `trace_len` messages
+    pub fn record_usage(&self, input_tokens: u32, output_tokens: u32, trace_len: usize) {
+        *self.reported.lock().unwrap() = Some((input_tokens + output_tokens, trace_len));
+    }
+
+    /// Tokens of `trace`, as reported up to the last reported usage and counted after it
+    pub fn tokens(&self, trace: &[ChatMessage]) -> u32 {
+        let (reported, covered) = match *self.reported.lock().unwrap() {
+            Some((tokens, len)) if len <= trace.len() => (tokens, len),
+            _ => (0, 0),
+        };
+        let added = &trace[covered..];
+        let counted = self.model.as_deref().and_then(|model| count_message_tokens(added, model).ok());
+        reported + counted.unwrap_or_else(|| added.iter().map(estimate_message_tokens).sum())
+    }
+
+    /// Compact `trace` when it is over the limit, returns whether it changed
+    /// The summary is written on a copy of the messages, without holding the lock, and given up
+    /// when `cancellation_token` is cancelled. A failed summary falls back to dropping the messages
+    pub async fn fit(&self, trace: &RwLock<Vec<ChatMessage>>, cancellation_token: &CancellationToken) -> bool {
+        let (tokens, start, end, compacted, len) = {
+            let trace = trace.read().await;
+            let tokens = self.tokens(&trace);
+            if tokens <= self.max_tokens {
+                return false;
+            }
+            let Some((start, end)) = self.compactable(&trace) else {
+                warn!(target: "agent::context", tokens, max_tokens = self.max_tokens, "context over the limit but nothing left to compact");
+                return false;
+            };
+            (tokens, start, end, trace[start..end].to_vec(), trace.len())
+        };
+
+        let replacement = match &self.strategy {
+            ContextStrategy::SlidingWindow => vec![],
+            ContextStrategy::Summarize { llm, model } => {
+                let summary = tokio::select! {
+                    _ = cancellation_token.cancelled() => {
+                        debug!(target: "agent::context", "step cancelled, summarization dropped");
+                        return false;
+                    }
+                    summary = summarize(llm, model, &compacted) => summary,
+                };
+                match summary {
+                    Ok(summary) => vec![ChatMessage::User {
+                        content: ChatMessageContent::Text(format!("{}\n{}", SUMMARY_PREFIX, summary)),
+                        name: None,
+                    }],
+                    Err(e) => {
+                        warn!(target: "agent::context", error = %e, "summarization failed, dropping the oldest messages");
+                        vec![]
+                    }
+                }
+            }
+        };
+
+        let mut trace = trace.write().await;
+        // messages may have been added meanwhile, only a trace that lost messages is left as is
+        if trace.len() < len {
+            warn!(target: "agent::context", "trace shortened while compacting, left as is");
+            return false;
+        }
+        info!(target: "agent::context", tokens, max_tokens = self.max_tokens, removed = end - start,
+            summarized = !replacement.is_empty(), "context compacted");
+        trace.splice(start..end, replacement);
+        *self.reported.lock().unwrap() = None;
+        true
+    }
+
+    /// Range of messages to compact: after the leading system messages, before the kept turns
+    fn compactable(&self, trace: &[ChatMessage]) -> Option<(usize, usize)> {
+        let start = trace.iter().take_while(|message| is_system(message)).count();
+        let turns: Vec<usize> = (start..trace.len()).filter(|&i| starts_turn(&trace[i])).collect();
+        let end = *turns.iter().rev().nth(self.keep_turns - 1)?;
+        (end > start).then_some((start, end))
+    }
+}
+
+fn is_system(message: &ChatMessage) -> bool {
+    matches!(message, ChatMessage::System { .. } | ChatMessage::Developer { .. })
+}
+
+fn starts_turn(message: &ChatMessage) -> bool {
+    matches!(message, ChatMessage::User { .. } | ChatMessage::Assistant { .. })
+}
+
+/// Ask `llm` for a summary of `messages`
+async fn summarize(llm: &LlmClient, model: &str, messages: &[ChatMessage]) -> Result<String, AgentError> {
+    let transcript = messages.iter().map(render).collect::<Vec<_>>().join("\n\n");
+    let request = ChatCompletionParametersBuilder::default()
+        .model(model)
+        .messages(vec![
+            ChatMessage::System { content: ChatMessageContent::Text(SUMMARY_PROMPT.to_string()), name: None },
+            ChatMessage::User { content: ChatMessageContent::Text(transcript), name: None },
+        ])
+        .build()
+        .map_err(|e| AgentError::LlmError(e.to_string()))?;
+
+    let response = llm.chat(request).await.map_err(|e| AgentError::LlmError(e.to_string()))?;
+    match response.choices.into_iter().next().map(|choice| choice.message) {
+        Some(ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. }) if !text.trim().is_empty() => Ok(text),
+        _ => Err(AgentError::InvalidResponse("empty summary".to_string())),
+    }
+}
+
+/// One message of the transcript sent for summarization
+fn render(message: &ChatMessage) -> String {
+    let text = |content: &ChatMessageContent| match content {
+        ChatMessageContent::Text(text) => text.clone(),
+        _ => "[non-text content]".to_string(),
+    };
+    match message {
+        ChatMessage::System { content, .. } | ChatMessage::Developer { content, .. } => format!("system: {}", text(content)),
+        ChatMessage::User { content, .. } => format!("user: {}", text(content)),
+        ChatMessage::Assistant { content, tool_calls, .. } => {
+            let mut rendered = format!("assistant: {}", content.as_ref().map(text).unwrap_or_default());
+            for call in tool_calls.iter().flatten() {
+                rendered.push_str(&format!("\n[tool call {}({})]", call.function.name, call.function.arguments));
+            }
+            rendered
+        }
+        ChatMessage::Tool { content, .. } => format!("tool result: {}", text(content)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai_dive::v1::resources::chat::{ChatCompletionParameters, ChatCompletionResponse, Function, ToolCall};
+    use openai_dive::v1::resources::model::ListModelResponse;
+    use shai_llm::provider::{LlmError, LlmProvider, LlmStream, ProviderInfo};
+
+    /// Answer of the mock provider to the summarization call
+    #[derive(Clone, Copy)]
+    enum SummaryReply {
+        Summary(&'static str),
+        Failure,
+        Hang,
+    }
+
+    struct SummaryProvider(SummaryReply);
+
+    #[async_trait::async_trait]
+    impl LlmProvider for SummaryProvider {
+        async fn models(&self) -> Result<ListModelResponse, LlmError> {
+            Err("not supported".into())
+        }
+
+        async fn chat(&self, _request: ChatCompletionParameters) -> Result<ChatCompletionResponse, LlmError> {
+            let summary = match self.0 {
+                SummaryReply::Summary(summary) => summary,
+                SummaryReply::Failure => return Err("provider unavailable".into()),
+                SummaryReply::Hang => std::future::pending().await,
+            };
+            Ok(serde_json::from_value(serde_json::json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 0,
+                "model": "mock",
+                "choices": [{ "index": 0, "message": { "role": "assistant", "content": summary }, "finish_reason": "stop" }],
+                "usage": { "prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4 }
+            }))?)
+        }
+
+        async fn chat_stream(&self, _request: ChatCompletionParameters) -> Result<LlmStream, LlmError> {
+            Err("not supported".into())
+        }
+
+        fn supports_functions(&self, _model: String) -> bool {
+            true
+        }
+
+        fn supports_structured_output(&self, _model: String) -> bool {
+            false
+        }
+
+        fn name(&self) -> &'static str {
+            "summary"
+        }
+
+        fn info() -> ProviderInfo {
+            ProviderInfo { name: "summary", display_name: "Summary", env_vars: vec![] }
+        }
+    }
+
+    fn summarizing(reply: SummaryReply) -> ContextManager {
+        let llm = Arc::new(LlmClient::from_provider(Box::new(SummaryProvider(reply))));
+        ContextManager::new(200, ContextStrategy::Summarize { llm, model: "mock".to_string() }).keep_turns(2)
+    }
+
+    fn user(text: &str) -> ChatMessage {
+        ChatMessage::User { content: ChatMessageContent::Text(text.to_string()), name: None }
+    }
+
+    fn call(id: &str) -> ChatMessage {
+        ChatMessage::Assistant {
+            content: None,
+            reasoning_content: None,
+            refusal: None,
+            name: None,
+            audio: None,
+            tool_calls: Some(vec![ToolCall {
+                id: id.to_string(),
+                r#type: "function".to_string(),
+                function: Function { name: "read".to_string(), arguments: "{}".to_string() },
+            }]),
+        }
+    }
+
+    fn result(id: &str) -> ChatMessage {
+        ChatMessage::Tool { content: ChatMessageContent::Text("x".repeat(400)), tool_call_id: id.to_string() }
+    }
+
+    fn long_trace() -> RwLock<Vec<ChatMessage>> {
+        RwLock::new(vec![
+            ChatMessage::System { content: ChatMessageContent::Text("rules".to_string()), name: None },
+            user("read the files"),
+            call("1"), result("1"),
+            call("2"), result("2"),
+            call("3"), result("3"),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_sliding_window_keeps_system_and_last_turns() {
+        let manager = ContextManager::new(200, ContextStrategy::SlidingWindow).keep_turns(2);
+        let shared = long_trace();
+        let token = CancellationToken::new();
+        assert!(manager.tokens(&shared.read().await) > 200);
+
+        assert!(manager.fit(&shared, &token).await);
+        let trace = shared.read().await;
+        assert_eq!(trace.len(), 5);
+        assert!(matches!(trace[0], ChatMessage::System { .. }));
+        // the kept turns start with a tool call, its result follows
+        assert!(matches!(&trace[1], ChatMessage::Assistant { tool_calls: Some(calls), .. } if calls[0].id == "2"));
+        assert!(matches!(&trace[4], ChatMessage::Tool { tool_call_id, .. } if tool_call_id == "3"));
+
+        // nothing older than the kept turns is left
+        drop(trace);
+        assert!(!manager.fit(&shared, &token).await);
+    }
+
+    #[tokio::test]
+    async fn test_summarize_replaces_the_oldest_messages() {
+        let manager = summarizing(SummaryReply::Summary("read file 1"));
+        let shared = long_trace();
+        assert!(manager.fit(&shared, &CancellationToken::new()).await);
+
+        let trace = shared.read().await;
+        assert_eq!(trace.len(), 6);
+        assert!(matches!(trace[0], ChatMessage::System { .. }));
+        assert!(matches!(&trace[1], ChatMessage::User { content: ChatMessageContent::Text(text), .. }
+            if *text == format!("{}\nread file 1", SUMMARY_PREFIX)));
+        assert!(matches!(&trace[2], ChatMessage::Assistant { tool_calls: Some(calls), .. } if calls[0].id == "2"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_summary_falls_back_to_sliding_window() {
+        for reply in [SummaryReply::Failure, SummaryReply::Summary("  ")] {
+            let manager = summarizing(reply);
+            let shared = long_trace();
+            assert!(manager.fit(&shared, &CancellationToken::new()).await);
+
+            let trace = shared.read().await;
+            assert_eq!(trace.len(), 5);
+            assert!(matches!(&trace[1], ChatMessage::Assistant { tool_calls: Some(calls), .. } if calls[0].id == "2"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_summary_leaves_the_trace() {
+        let manager = summarizing(SummaryReply::Hang);
+        let shared = long_trace();
+        let token = CancellationToken::new();
+
+        let fit = manager.fit(&shared, &token);
+        tokio::pin!(fit);
+        // the summary is written without holding the trace
+        tokio::select! {
+            _ = &mut fit => panic!("the summary never answers"),
+            _ = tokio::time::sleep(std::time::Duration::from_millis(20)) => {}
+        }
+        assert!(shared.try_write().is_ok());
+
+        token.cancel();
+        assert!(!fit.await);
+        assert_eq!(shared.read().await.len(), 8);
+    }
+
+    #[test]
+    fn test_reported_usage_counts() {
+        let manager = ContextManager::new(1_000, ContextStrategy::SlidingWindow);
+        let trace = vec![user("hi"), call("1"), result("1")];
+        manager.record_usage(900, 50, 2);
+        assert_eq!(manager.tokens(&trace), 950 + estimate_message_tokens(&trace[2]));
+        // a trace shorter than the reported one is estimated
+        assert_eq!(manager.tokens(&trace[..1]), estimate_message_tokens(&trace[0]));
+
+        // with a known model the added messages are counted by its tokenizer
+        let manager = ContextManager::new(1_000, ContextStrategy::SlidingWindow).model("gpt-4o");
+        assert_eq!(manager.tokens(&trace), count_message_tokens(&trace, "gpt-4o").unwrap());
+        let manager = ContextManager::new(1_000, ContextStrategy::SlidingWindow).model("my-custom-model");
+        assert_eq!(manager.tokens(&trace[..1]), estimate_message_tokens(&trace[0]));
+    }
+}
@@ -13,6 +13,16 @@ pub enum AgentError {
     SessionClosed,
     #[error("Invalid response: {0}")]
     InvalidResponse(String),
+    #[error("Empty completion from the model after {0} attempts")]
+    EmptyCompletion(u32),
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+    #[error("Token budget exceeded: {used} of {limit} tokens used")]
+    BudgetExceeded { used: u64, limit: u64 },
+    #[error("Session busy: {message}")]
+    SessionBusy { message: String, retry_after_secs: u64 },
+    #[error("Maximum number of sessions per client reached: {limit}")]
+    SessionLimitReached { limit: usize },
     #[error("User interaction timeout")]
     UserTimeout,
     #[error("Permission denied")]
@@ -78,6 +78,21 @@ pub enum AgentEvent {
         call: ToolCall,
         result: ToolResult
     },
+    /// Fragment of tool call arguments while the model generates them
+    /// Deltas of a call share its call_id, their concatenation is the final arguments
+    ToolCallArgumentsDelta {
+        call_id: String,
+        tool_name: String,
+        index: u32,
+        delta: String
+    },
+    /// Intermediate progress reported by a running tool
+    ToolProgress {
+        call_id: String,
+        tool_name: String,
+        message: String,
+        percent: Option<u8>
+    },
     /// User provided input to the agent
     UserInput { 
         input: String,
@@ -101,6 +116,10 @@ pub enum AgentEvent {
         input_tokens: u32,
         output_tokens: u32
     },
+    /// The model returned empty replies that were retried before a usable one
+    EmptyCompletionRetried {
+        retries: u32
+    },
 }
 
 /// Types of user input that an agent can request
@@ -238,6 +257,22 @@ impl std::fmt::Debug for AgentEvent {
                     .field("result", result)
                     .finish()
             }
+            AgentEvent::ToolCallArgumentsDelta { call_id, tool_name, index, delta } => {
+                f.debug_struct("ToolCallArgumentsDelta")
+                    .field("call_id", call_id)
+                    .field("tool_name", tool_name)
+                    .field("index", index)
+                    .field("delta_len", &delta.len())
+                    .finish()
+            }
+            AgentEvent::ToolProgress { call_id, tool_name, message, percent } => {
+                f.debug_struct("ToolProgress")
+                    .field("call_id", call_id)
+                    .field("tool_name", tool_name)
+                    .field("message", message)
+                    .field("percent", percent)
+                    .finish()
+            }
             AgentEvent::UserInput { input } => {
                 f.debug_struct("UserInput")
                     .field("input", input)
@@ -274,6 +309,11 @@ impl std::fmt::Debug for AgentEvent {
                     .field("output_tokens", output_tokens)
                     .finish()
             }
+            AgentEvent::EmptyCompletionRetried { retries } => {
+                f.debug_struct("EmptyCompletionRetried")
+                    .field("retries", retries)
+                    .finish()
+            }
         }
     }
 }
@@ -2,6 +2,8 @@ pub mod builder;
 pub mod claims;
 pub mod error;
 pub mod brain;
+pub mod context;
+pub mod budget;
 pub mod agent;
 pub mod protocol;
 pub mod events;
@@ -30,5 +32,7 @@ pub use output::StdoutEventManager;
 pub use builder::AgentBuilder;
 pub use claims::{ClaimManager, PermissionError};
 pub use error::{AgentError, AgentExecutionError};
-pub use brain::{Brain, ThinkerContext, ThinkerDecision, ThinkerFlowControl};
+pub use context::{ContextManager, ContextStrategy};
+pub use budget::TokenBudget;
+pub use brain::{is_json_format, json_reply, matches_format, Brain, SamplingOverrides, ThinkerContext, ThinkerDecision, ThinkerFlowControl, ToolCallDeltaSink};
 pub use crate::logging::LoggingConfig;
\ No newline at end of file
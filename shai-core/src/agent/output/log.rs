@@ -38,6 +38,12 @@ impl FileEventLogger {
             AgentEvent::ToolCallCompleted { duration, call, result } => {
                 format!("ToolCallCompleted: {} in {:?} - {:?}", call.tool_name, duration, result)
             }
+            AgentEvent::ToolCallArgumentsDelta { call_id, tool_name, delta, .. } => {
+                format!("ToolCallArgumentsDelta: {} ({}) +{} bytes", tool_name, call_id, delta.len())
+            }
+            AgentEvent::ToolProgress { call_id, tool_name, message, percent } => {
+                format!("ToolProgress: {} ({}) {:?}% - {}", tool_name, call_id, percent, message)
+            }
             AgentEvent::UserInput { input } => {
                 format!("UserInput: {}", input)
             }
@@ -56,6 +62,9 @@ impl FileEventLogger {
             AgentEvent::TokenUsage { input_tokens, output_tokens } => {
                 format!("Token Usage: input={} output={} total={}", input_tokens, output_tokens, input_tokens + output_tokens)
             }
+            AgentEvent::EmptyCompletionRetried { retries } => {
+                format!("EmptyCompletionRetried: {} retries", retries)
+            }
         };
 
         let log_line = format!("[{}] {}\n", timestamp.format("%Y-%m-%d %H:%M:%S%.3f"), event_str);
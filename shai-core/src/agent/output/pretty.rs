@@ -110,10 +110,15 @@ impl PrettyFormatter {
                 
                 Some(completion_skin.term_text(&markdown).to_string())
             },
+            AgentEvent::ToolProgress { .. } | AgentEvent::ToolCallArgumentsDelta { .. } => {
+                // Progress is transient, only the final tool result is printed
+                None
+            },
             AgentEvent::TokenUsage { .. } => {
                 // Don't display token usage in the main output - it's handled by /tokens command
                 None
             },
+            AgentEvent::EmptyCompletionRetried { .. } => None,
         }.map(|s| format!("\n{}", s))
     }
 
@@ -2,6 +2,7 @@ use openai_dive::v1::resources::chat::ChatMessage;
 use shai_llm::ToolCallMethod;
 use tokio::sync::{mpsc, oneshot};
 use tokio::time::{timeout, Duration};
+use tracing::Span;
 use crate::agent::AgentError;
 
 use super::{PermissionResponse, PublicAgentState, UserResponse};
@@ -23,6 +24,12 @@ pub enum AgentRequest {
     },
     /// Send multiple messages as a trace (cancels current task, adds all to trace, resumes agent)
     SendTrace{
+        messages: Vec<ChatMessage>,
+        /// span of the request the messages belong to, parent of the brain step and tool call spans
+        span: Span
+    },
+    /// Add messages to the trace without resuming the agent (notes for its next turn)
+    AppendTrace{
         messages: Vec<ChatMessage>
     },
     /// Switch method for tool call
@@ -126,7 +133,11 @@ impl AgentController {
     }
 
     pub async fn send_trace(&self, messages: Vec<ChatMessage>) -> Result<(), AgentError> {
-        self.send(AgentRequest::SendTrace { messages }).await.map(|_| Ok(()))?
+        self.send(AgentRequest::SendTrace { messages, span: Span::current() }).await.map(|_| Ok(()))?
+    }
+
+    pub async fn append_trace(&self, messages: Vec<ChatMessage>) -> Result<(), AgentError> {
+        self.send(AgentRequest::AppendTrace { messages }).await.map(|_| Ok(()))?
     }
 
     pub async fn response_user_query(&self,  request_id: String, response: UserResponse) -> Result<(), AgentError> {
@@ -33,6 +33,7 @@ impl AgentCore {
         };
 
         cancellation_token.cancel();
+        self.cancel_brain_task().await;
         Ok(())
     }
 }
\ No newline at end of file
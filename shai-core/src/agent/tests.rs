@@ -1,5 +1,5 @@
 use crate::agent::Agent;
-use crate::tools::{AnyTool, ToolResult, ReadTool, LsTool};
+use crate::tools::{AnyTool, ToolCache, ToolResult, ReadTool, LsTool};
 use crate::tools::tool;
 use super::brain::{ThinkerContext, Brain};
 use super::error::AgentError;
@@ -12,6 +12,7 @@ use serde::{Serialize, Deserialize};
 use schemars::JsonSchema;
 use std::time::Duration;
 use std::sync::{Arc, Once};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use tokio::sync::Mutex;
 
 static INIT_LOGGING: Once = Once::new();
@@ -461,3 +462,341 @@ async fn test_agent_with_real_tools() {
         }
     }
 }
+
+// Test thinker streaming a generation that never ends after its first chunk
+struct SlowStreamThinker;
+
+#[async_trait]
+impl Brain for SlowStreamThinker {
+    async fn next_step(&mut self, context: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        use openai_dive::v1::resources::chat::{ChatCompletionChunkChoice, ChatCompletionChunkResponse, DeltaChatMessage};
+        use futures::StreamExt;
+
+        let chunk = ChatCompletionChunkResponse {
+            id: Some("chatcmpl-1".to_string()),
+            object: "chat.completion.chunk".to_string(),
+            created: 1,
+            model: "slow".to_string(),
+            choices: vec![ChatCompletionChunkChoice {
+                index: Some(0),
+                delta: DeltaChatMessage::Assistant {
+                    content: Some(ChatMessageContent::Text("Partial answer".to_string())),
+                    reasoning_content: None,
+                    refusal: None,
+                    name: None,
+                    tool_calls: None,
+                },
+                finish_reason: None,
+                logprobs: None,
+            }],
+            usage: None,
+            system_fingerprint: None,
+        };
+        let stream: shai_llm::provider::LlmStream = Box::new(
+            futures::stream::iter(vec![Ok::<_, shai_llm::provider::LlmError>(chunk)]).chain(futures::stream::pending())
+        );
+
+        let mut cancelled: shai_llm::CancelSignal = Box::pin(context.cancellation_token.cancelled());
+        let outcome = shai_llm::read_stream_until(stream, &|_: shai_llm::ToolCallDelta| {}, &mut cancelled).await
+            .map_err(|e| AgentError::LlmError(e.to_string()))?;
+        let message = outcome.into_response().choices.into_iter().next().unwrap().message;
+        Ok(ThinkerDecision::cancelled(message, Some((10, 2))))
+    }
+}
+
+#[tokio::test]
+async fn test_stop_cancels_streaming_brain() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::with_brain(Box::new(SlowStreamThinker))
+        .id("test-cancel-stream-agent")
+        .goal("Start streaming")
+        .sudo()
+        .build();
+    let controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+
+    // wait for the first chunk to be read
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(matches!(controller.get_state().await.unwrap(), PublicAgentState::Processing { .. }));
+
+    let started = std::time::Instant::now();
+    controller.stop_current_task().await.expect("Failed to stop current task");
+    assert!(started.elapsed() < Duration::from_millis(50), "stream took {:?} to stop", started.elapsed());
+    assert!(matches!(controller.get_state().await.unwrap(), PublicAgentState::Paused));
+
+    // the partial output is kept in the trace
+    let trace = controller.get_trace().await.unwrap();
+    assert!(matches!(trace.last(), Some(ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), tool_calls: None, .. }) if text == "Partial answer"));
+
+    controller.terminate().await.expect("Failed to terminate");
+    let _ = handle.await.unwrap();
+}
+
+// Test thinker ignoring cancellation
+struct StuckThinker;
+
+#[async_trait]
+impl Brain for StuckThinker {
+    async fn next_step(&mut self, _: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Err(AgentError::LlmError("should have been aborted".to_string()))
+    }
+}
+
+#[tokio::test]
+async fn test_terminate_aborts_brain_ignoring_cancellation() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::with_brain(Box::new(StuckThinker))
+        .id("test-cancel-stuck-agent")
+        .goal("Start thinking")
+        .sudo()
+        .build();
+    let controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let started = std::time::Instant::now();
+    controller.terminate().await.expect("Failed to terminate");
+    let result = tokio::time::timeout(Duration::from_secs(1), handle).await.expect("agent kept running").unwrap();
+    assert!(started.elapsed() < super::actions::brain::BRAIN_CANCEL_GRACE * 3, "terminated after {:?}", started.elapsed());
+    assert!(matches!(result, Ok(result) if !result.success));
+}
+
+// Test thinker that calls the sleeping tool three times in one reply then completes
+struct FanOutThinker {
+    called_tools: bool,
+}
+
+#[async_trait]
+impl Brain for FanOutThinker {
+    async fn next_step(&mut self, _: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        if self.called_tools {
+            return Ok(ThinkerDecision::agent_pause(ChatMessage::Assistant {
+                content: Some(ChatMessageContent::Text("all done".to_string())),
+                reasoning_content: None,
+                tool_calls: None,
+                name: None,
+                audio: None,
+                refusal: None,
+            }));
+        }
+        self.called_tools = true;
+        let tool_calls = (1..=3).map(|i| ToolCall {
+            id: format!("call_{}", i),
+            r#type: "function".to_string(),
+            function: Function {
+                name: "sleeping_tool".to_string(),
+                arguments: "{}".to_string(),
+            },
+        }).collect();
+        Ok(ThinkerDecision::agent_continue(ChatMessage::Assistant {
+            content: None,
+            reasoning_content: None,
+            tool_calls: Some(tool_calls),
+            name: None,
+            audio: None,
+            refusal: None,
+        }))
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct VersionParams {}
+
+// Test tool reading a version slowly: the version is taken when the call starts
+struct SlowReadTool {
+    version: Arc<AtomicUsize>,
+}
+
+#[tool(name = "slow_read", description = "Read the version of a file", capabilities = [Read])]
+impl SlowReadTool {
+    async fn execute(&self, _params: VersionParams) -> ToolResult {
+        let version = self.version.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        ToolResult::success(format!("version {}", version))
+    }
+}
+
+// Test tool writing a new version of a file
+struct BumpTool {
+    version: Arc<AtomicUsize>,
+}
+
+#[tool(name = "bump", description = "Write a new version of a file", capabilities = [Write])]
+impl BumpTool {
+    async fn execute(&self, _params: VersionParams) -> ToolResult {
+        self.version.fetch_add(1, Ordering::SeqCst);
+        ToolResult::success("bumped".to_string())
+    }
+}
+
+// Test thinker calling the given tools, one list per step, then completing
+struct ScriptedCallsThinker {
+    steps: Vec<Vec<&'static str>>,
+}
+
+#[async_trait]
+impl Brain for ScriptedCallsThinker {
+    async fn next_step(&mut self, _: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        if self.steps.is_empty() {
+            return Ok(ThinkerDecision::agent_pause(ChatMessage::Assistant {
+                content: Some(ChatMessageContent::Text("all done".to_string())),
+                reasoning_content: None,
+                tool_calls: None,
+                name: None,
+                audio: None,
+                refusal: None,
+            }));
+        }
+        let step = self.steps.len();
+        let tool_calls = self.steps.remove(0).into_iter().enumerate().map(|(i, name)| ToolCall {
+            id: format!("call_{}_{}", step, i),
+            r#type: "function".to_string(),
+            function: Function {
+                name: name.to_string(),
+                arguments: "{}".to_string(),
+            },
+        }).collect();
+        Ok(ThinkerDecision::agent_continue(ChatMessage::Assistant {
+            content: None,
+            reasoning_content: None,
+            tool_calls: Some(tool_calls),
+            name: None,
+            audio: None,
+            refusal: None,
+        }))
+    }
+}
+
+#[tokio::test]
+async fn test_parallel_write_leaves_no_stale_read_cached() {
+    init_test_logging();
+    let version = Arc::new(AtomicUsize::new(0));
+    let tools: Vec<Box<dyn AnyTool>> = vec![
+        Box::new(SlowReadTool { version: version.clone() }),
+        Box::new(BumpTool { version: version.clone() }),
+    ];
+    // the write ends while the read of the first step still runs
+    let mut agent = AgentBuilder::with_brain(Box::new(ScriptedCallsThinker { steps: vec![vec!["slow_read", "bump"], vec!["slow_read"]] }))
+        .id("test-parallel-write-cache-agent")
+        .goal("Read, write, read again")
+        .tools(tools)
+        .tool_cache(Arc::new(ToolCache::new(Duration::from_secs(60))))
+        .parallel_tool_calls(true)
+        .sudo()
+        .build();
+
+    let result = agent.run().await.expect("agent should complete");
+    let reads: Vec<String> = result.trace.iter()
+        .filter_map(|msg| match msg {
+            ChatMessage::Tool { content: ChatMessageContent::Text(text), .. } if text.contains("version") => Some(text.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(reads.len(), 2);
+    assert!(reads[0].contains("version 0"), "{:?}", reads);
+    assert!(reads[1].contains("version 1"), "the second read was served a stale result: {:?}", reads);
+}
+
+#[tokio::test]
+async fn test_parallel_tool_calls() {
+    init_test_logging();
+
+    // calls run concurrently unless turned off
+    assert!(AgentBuilder::with_brain(Box::new(FanOutThinker { called_tools: false })).parallel_tool_calls);
+
+    for parallel in [false, true] {
+        let sleeping_tool: Box<dyn AnyTool> = Box::new(SleepingTool::new(300));
+        let mut agent = AgentBuilder::with_brain(Box::new(FanOutThinker { called_tools: false }))
+            .id("test-parallel-tool-calls-agent")
+            .goal("Run the three calls")
+            .tools(vec![sleeping_tool])
+            .parallel_tool_calls(parallel)
+            .sudo()
+            .build();
+
+        let start_time = std::time::Instant::now();
+        let result = agent.run().await.expect("agent should complete");
+        let elapsed = start_time.elapsed();
+        if parallel {
+            assert!(elapsed < Duration::from_millis(850), "parallel calls took {:?}", elapsed);
+        } else {
+            assert!(elapsed >= Duration::from_millis(900), "sequential calls took {:?}", elapsed);
+        }
+
+        // results follow the order of the calls
+        let ids: Vec<&str> = result.trace.iter()
+            .filter_map(|msg| match msg {
+                ChatMessage::Tool { tool_call_id, .. } => Some(tool_call_id.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ids, vec!["call_1", "call_2", "call_3"]);
+    }
+}
+
+// Test thinker that keeps going, each step reporting 600 tokens
+struct HungryThinker {
+    steps: Arc<std::sync::atomic::AtomicUsize>,
+}
+
+#[async_trait]
+impl Brain for HungryThinker {
+    async fn next_step(&mut self, _: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        let step = self.steps.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+        Ok(ThinkerDecision::agent_continue_with_tokens(ChatMessage::Assistant {
+            content: Some(ChatMessageContent::Text(format!("step {}", step))),
+            reasoning_content: None,
+            tool_calls: None,
+            name: None,
+            audio: None,
+            refusal: None,
+        }, 500, 100))
+    }
+}
+
+#[tokio::test]
+async fn test_token_budget_stops_brain_steps() {
+    init_test_logging();
+
+    let steps = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let budget = Arc::new(super::TokenBudget::new(1000));
+    let mut agent = AgentBuilder::with_brain(Box::new(HungryThinker { steps: steps.clone() }))
+        .id("test-token-budget-agent")
+        .goal("Keep thinking")
+        .token_budget(budget.clone())
+        .sudo()
+        .build();
+
+    // the second step crosses the budget, the third one is never asked for
+    let result = tokio::time::timeout(Duration::from_secs(5), agent.run()).await.expect("agent kept running").unwrap();
+    assert_eq!(steps.load(std::sync::atomic::Ordering::SeqCst), 2);
+    assert_eq!(budget.used(), 1200);
+    assert_eq!(budget.remaining(), Some(0));
+    let replies = result.trace.iter().filter(|msg| matches!(msg, ChatMessage::Assistant { .. })).count();
+    assert_eq!(replies, 2);
+}
+
+#[test]
+fn test_context_manager_counts_with_agent_model() {
+    use super::{ContextManager, ContextStrategy};
+
+    let agent = AgentBuilder::with_brain(Box::new(HungryThinker { steps: Arc::default() }))
+        .model("gpt-4o")
+        .context_manager(ContextManager::new(1_000, ContextStrategy::SlidingWindow))
+        .build();
+    assert_eq!(agent.context_manager.unwrap().model_name(), Some("gpt-4o"));
+
+    // a model named by the manager is kept
+    let agent = AgentBuilder::with_brain(Box::new(HungryThinker { steps: Arc::default() }))
+        .model("gpt-4o")
+        .context_manager(ContextManager::new(1_000, ContextStrategy::SlidingWindow).model("o1"))
+        .build();
+    assert_eq!(agent.context_manager.unwrap().model_name(), Some("o1"));
+}
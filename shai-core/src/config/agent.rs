@@ -1,8 +1,9 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 use serde::{Serialize, Deserialize};
-use shai_llm::ToolCallMethod;
+use shai_llm::{BreakerConfig, KeyPoolConfig, ToolCallMethod};
 use crate::tools::mcp::McpConfig;
+use crate::tools::ToolOutputFilters;
 use super::config::ShaiConfig;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,6 +12,12 @@ pub struct AgentProviderConfig {
     pub env_vars: HashMap<String, String>,
     pub model: String,
     pub tool_method: ToolCallMethod,
+    /// Several API keys with rotation and failover (instead of the key in env_vars)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_pool: Option<KeyPoolConfig>,
+    /// Circuit breaker thresholds (enabled with the defaults when absent)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<BreakerConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +37,26 @@ pub struct AgentTools {
     pub builtin_excluded: Vec<String>,
     #[serde(default)]
     pub mcp: HashMap<String, McpToolConfig>,
+    /// HTTP tools of the tools folder (SHAI_TOOLS_DIR) the agent gets, "*" for all of them (default none)
+    #[serde(default)]
+    pub dynamic: Vec<String>,
+    /// jq-like filter applied to the successful output of a tool before it enters the trace (tool name -> expression)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub post_process: HashMap<String, String>,
+}
+
+/// What to do when the model returns an empty assistant message (no text, no tool call)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmptyCompletionConfig {
+    /// Number of retries before failing with an empty_completion error
+    #[serde(default = "default_empty_completion_retries")]
+    pub max_retries: u32,
+    /// Temperature added at each retry
+    #[serde(default = "default_empty_completion_temperature_step")]
+    pub temperature_step: f32,
+    /// Accept empty replies as a normal answer (no retry, no error)
+    #[serde(default)]
+    pub allow_empty: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -46,6 +73,14 @@ pub struct AgentConfig {
     pub max_tokens: u32,
     #[serde(default = "default_temperature")]
     pub temperature: f32,
+    #[serde(default)]
+    pub empty_completion: EmptyCompletionConfig,
+    /// Path components skipped by workspace snapshots
+    #[serde(default = "default_workspace_ignore")]
+    pub workspace_ignore: Vec<String>,
+    /// Stream tool call arguments while the model generates them (function calling only)
+    #[serde(default)]
+    pub stream_tool_arguments: bool,
 }
 
 fn default_llm_provider() -> AgentProviderConfig {
@@ -62,6 +97,8 @@ fn default_llm_provider() -> AgentProviderConfig {
         env_vars: provider_config.env_vars.clone(),
         model: provider_config.model.clone(),
         tool_method: provider_config.tool_method.clone(),
+        key_pool: provider_config.key_pool.clone(),
+        circuit_breaker: provider_config.circuit_breaker.clone(),
     }
 }
 
@@ -77,16 +114,40 @@ fn default_temperature() -> f32 {
     0.3
 }
 
+fn default_empty_completion_retries() -> u32 {
+    2
+}
+
+fn default_empty_completion_temperature_step() -> f32 {
+    0.1
+}
+
+pub fn default_workspace_ignore() -> Vec<String> {
+    vec![".git".to_string(), "node_modules".to_string()]
+}
+
 fn default_enabled_tools() -> Vec<String> {
     vec!["*".to_string()]
 }
 
+impl Default for EmptyCompletionConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: default_empty_completion_retries(),
+            temperature_step: default_empty_completion_temperature_step(),
+            allow_empty: false,
+        }
+    }
+}
+
 impl Default for AgentTools {
     fn default() -> Self {
         Self {
             builtin: vec!["*".to_string()],
             builtin_excluded: Vec::new(),
             mcp: HashMap::new(),
+            dynamic: Vec::new(),
+            post_process: HashMap::new(),
         }
     }
 }
@@ -123,9 +184,16 @@ impl AgentConfig {
 
         let content = std::fs::read_to_string(config_path)?;
         let config: AgentConfig = serde_json::from_str(&content)?;
+        config.validate()?;
         Ok(config)
     }
 
+    /// Check the settings that serde cannot validate
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        ToolOutputFilters::from_config(&self.tools.post_process)?;
+        Ok(())
+    }
+
     /// Save the agent config to file
     pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
         let config_path = Self::agent_config_path(&self.name)?;
@@ -160,6 +228,36 @@ impl AgentConfig {
         Ok(agents)
     }
 
+    /// Resolve a glob pattern (e.g. "customer-*") against the available agent configs
+    /// Returns None when nothing matches and the first match in name order for a wildcard pattern,
+    /// a name without wildcard matching several configs is an error listing them
+    pub fn resolve_pattern(pattern: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        Self::match_pattern(pattern, &Self::list_agents()?)
+    }
+
+    fn match_pattern(pattern: &str, agents: &[String]) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let glob = glob::Pattern::new(pattern)
+            .map_err(|e| format!("Invalid agent pattern '{}': {}", pattern, e))?;
+
+        let mut candidates: Vec<&String> = agents.iter().filter(|name| glob.matches(name)).collect();
+        candidates.sort();
+        match candidates.as_slice() {
+            [] => Ok(None),
+            [first, ..] if Self::is_pattern(pattern) => Ok(Some(first.to_string())),
+            [name] => Ok(Some(name.to_string())),
+            _ => Err(format!(
+                "Agent pattern '{}' is ambiguous, candidates: {}",
+                pattern,
+                candidates.iter().map(|n| n.as_str()).collect::<Vec<_>>().join(", ")
+            ).into()),
+        }
+    }
+
+    /// True if the name contains glob metacharacters
+    pub fn is_pattern(name: &str) -> bool {
+        name.contains(['*', '?', '['])
+    }
+
     /// Check if an agent config exists
     pub fn exists(agent_name: &str) -> bool {
         Self::agent_config_path(agent_name)
@@ -200,4 +298,47 @@ impl AgentConfig {
             .cloned()
             .collect()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agents() -> Vec<String> {
+        vec!["customer-globex".to_string(), "customer-acme".to_string(), "internal".to_string()]
+    }
+
+    #[test]
+    fn test_pattern_single_match() {
+        let name = AgentConfig::match_pattern("customer-a*", &agents()).unwrap();
+        assert_eq!(name, Some("customer-acme".to_string()));
+    }
+
+    #[test]
+    fn test_pattern_no_match() {
+        assert_eq!(AgentConfig::match_pattern("tenant-*", &agents()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_pattern_first_sorted_match() {
+        assert_eq!(AgentConfig::match_pattern("customer-*", &agents()).unwrap(), Some("customer-acme".to_string()));
+        assert_eq!(AgentConfig::match_pattern("customer-[gx]*", &agents()).unwrap(), Some("customer-globex".to_string()));
+
+        // only a name without wildcard matching several configs is ambiguous
+        let duplicated = vec!["internal".to_string(), "internal".to_string()];
+        let err = AgentConfig::match_pattern("internal", &duplicated).unwrap_err().to_string();
+        assert!(err.contains("ambiguous"));
+    }
+
+    #[test]
+    fn test_is_pattern() {
+        assert!(AgentConfig::is_pattern("customer-*"));
+        assert!(!AgentConfig::is_pattern("customer-acme"));
+    }
+
+    #[test]
+    fn test_dynamic_tools_opt_in() {
+        assert!(AgentTools::default().dynamic.is_empty());
+        let tools: AgentTools = serde_json::from_value(serde_json::json!({ "builtin": ["*"] })).unwrap();
+        assert!(tools.dynamic.is_empty());
+    }
+}
@@ -4,7 +4,7 @@ use std::fs;
 use std::os::unix::fs::PermissionsExt;
 use reqwest::Url;
 use serde::{Serialize, Deserialize};
-use shai_llm::{LlmClient, ToolCallMethod};
+use shai_llm::{BreakerConfig, KeyPoolConfig, LlmClient, PromptAdapter, ToolCallMethod};
 use crate::tools::mcp::McpConfig;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -12,7 +12,13 @@ pub struct ProviderConfig {
     pub provider: String,
     pub env_vars: std::collections::HashMap<String, String>,
     pub model: String,
-    pub tool_method: ToolCallMethod
+    pub tool_method: ToolCallMethod,
+    /// Several API keys with rotation and failover (instead of the key in env_vars)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_pool: Option<KeyPoolConfig>,
+    /// Circuit breaker thresholds (enabled with the defaults when absent)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<BreakerConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,6 +27,9 @@ pub struct ShaiConfig {
     pub selected_provider: usize,
     #[serde(default)]
     pub mcp_configs: HashMap<String, McpConfig>,
+    /// Message rewrites per model name pattern, looked up before the built-in table
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prompt_adapters: Vec<PromptAdapter>,
 }
 
 impl ShaiConfig {
@@ -36,7 +45,9 @@ impl ShaiConfig {
             provider,
             env_vars,
             model,
-            tool_method: ToolCallMethod::FunctionCall
+            tool_method: ToolCallMethod::FunctionCall,
+            key_pool: None,
+            circuit_breaker: None,
         };
         
         self.providers.push(provider_config);
@@ -99,7 +110,7 @@ impl ShaiConfig {
         } else if config.selected_provider >= config.providers.len() {
             config.selected_provider = 0; // Reset to first provider if index is invalid
         }
-        
+
         Ok(config)
     }
 
@@ -125,6 +136,11 @@ impl ShaiConfig {
             .unwrap_or(false)
     }
 
+    /// Make the prompt adapters of this config the ones applied by the LLM clients
+    pub fn install_prompt_adapters(&self) {
+        shai_llm::adapter::set_prompt_adapters(self.prompt_adapters.clone());
+    }
+
     /// Set environment variables from the currently selected provider
     pub fn set_env_vars(&self) {
         if let Some(provider_config) = self.get_selected_provider() {
@@ -228,10 +244,13 @@ impl Default for ShaiConfig {
                     (String::from("OVH_BASE_URL"), String::from("https://qwen-3-32b.endpoints.kepler.ai.cloud.ovh.net/api/openai_compat/v1"))
                 ]),
                 model: "Qwen3-32B".to_string(),
-                tool_method: ToolCallMethod::FunctionCall
+                tool_method: ToolCallMethod::FunctionCall,
+                key_pool: None,
+                circuit_breaker: None,
             }],
             selected_provider: 0,
             mcp_configs: HashMap::new(),
+            prompt_adapters: Vec::new(),
         }
     }
 }
@@ -244,9 +263,11 @@ impl ShaiConfig {
         config.set_env_vars();
         
         let llm = if let Some(provider_config) = config.get_selected_provider() {
-            LlmClient::create_provider(
+            LlmClient::create_provider_from_config(
                 &provider_config.provider, 
-                &provider_config.env_vars)
+                &provider_config.env_vars,
+                provider_config.key_pool.as_ref(),
+                provider_config.circuit_breaker.as_ref())
                 .map_err(|e| format!("Failed to create {} client: {}", provider_config.provider, e))?
         } else {
             return Err("No provider configured".into());
@@ -1,12 +1,16 @@
 use std::sync::Arc;
 
-use openai_dive::v1::resources::chat::{ChatCompletionParametersBuilder, ChatMessage, ChatMessageContent};
+use openai_dive::v1::resources::chat::{ChatCompletionParameters, ChatCompletionParametersBuilder, ChatCompletionResponse, ChatMessage, ChatMessageContent};
 use shai_llm::client::LlmClient;
+use shai_llm::provider::LlmError;
+use shai_llm::{estimate_usage, StreamAccumulator, StreamOutcome, ToolBox, ToolCallMethod, ToolCallStreaming};
 use async_trait::async_trait;
-use tracing::debug;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
 
 use crate::agent::brain::ThinkerDecision;
-use crate::agent::{Agent, AgentBuilder, AgentError, Brain, ThinkerContext};
+use crate::config::agent::EmptyCompletionConfig;
+use crate::agent::{is_json_format, json_reply, Agent, AgentBuilder, AgentError, Brain, ThinkerContext, ToolCallDeltaSink};
 use crate::tools::types::{ContainsAnyTool, IntoToolBox};
 use shai_llm::tool::LlmToolCall;
 use crate::tools::{AnyTool, BashTool, EditTool, FetchTool, FindTool, LsTool, MultiEditTool, ReadTool, TodoReadTool, TodoWriteTool, WriteTool, TodoStorage, FsOperationLog};
@@ -19,6 +23,7 @@ pub struct CoderBrain {
     pub model: String,
     pub system_prompt_template: String,
     pub temperature: f32,
+    pub empty_completion: EmptyCompletionConfig,
 }
 
 impl CoderBrain {
@@ -29,6 +34,7 @@ impl CoderBrain {
             model,
             system_prompt_template: "{{CODER_BASE_PROMPT}}".to_string(),
             temperature: 0.3,
+            empty_completion: EmptyCompletionConfig::default(),
         }
     }
 
@@ -39,6 +45,107 @@ impl CoderBrain {
             model,
             system_prompt_template,
             temperature,
+            empty_completion: EmptyCompletionConfig::default(),
+        }
+    }
+
+    /// Set how empty replies from the model are handled
+    pub fn with_empty_completion(mut self, empty_completion: EmptyCompletionConfig) -> Self {
+        self.empty_completion = empty_completion;
+        self
+    }
+
+    /// Query the model, streaming tool call arguments when the context asks for it
+    /// Only function calling can be streamed, Auto falls back to the other methods on failure
+    /// Once `cancel` is cancelled the call is dropped: a streamed completion returns what the model
+    /// generated so far, a non-streamed one returns an empty message
+    async fn complete(
+        &self,
+        request: ChatCompletionParameters,
+        toolbox: &ToolBox,
+        method: &ToolCallMethod,
+        on_tool_call_delta: Option<&ToolCallDeltaSink>,
+        cancel: &CancellationToken,
+    ) -> Result<StreamOutcome, LlmError> {
+        let Some(on_delta) = on_tool_call_delta else {
+            return self.complete_unstreamed(request, toolbox, method.clone(), cancel).await;
+        };
+
+        match method {
+            ToolCallMethod::FunctionCall => {
+                self.llm.chat_with_tools_fc_stream_until(request, toolbox, on_delta.as_ref(), Box::pin(cancel.cancelled())).await
+            }
+            ToolCallMethod::Auto => {
+                match self.llm.chat_with_tools_fc_stream_until(request.clone(), toolbox, on_delta.as_ref(), Box::pin(cancel.cancelled())).await {
+                    Ok(outcome) => Ok(outcome),
+                    Err(e) => {
+                        debug!(target: "brain::coder", error = %e, "streamed function calling failed, trying other methods");
+                        self.complete_unstreamed(request, toolbox, ToolCallMethod::Auto, cancel).await
+                    }
+                }
+            }
+            _ => self.complete_unstreamed(request, toolbox, method.clone(), cancel).await,
+        }
+    }
+
+    /// Request of one step on `messages`, with the sampling and response format of the context
+    fn request(&self, messages: Vec<ChatMessage>, temperature: f32, context: &ThinkerContext) -> Result<ChatCompletionParameters, AgentError> {
+        let mut request = ChatCompletionParametersBuilder::default()
+            .model(&self.model)
+            .messages(messages)
+            .temperature(temperature)
+            .build()
+            .map_err(|e| AgentError::LlmError(e.to_string()))?;
+        context.sampling.apply(&mut request);
+        // the structured output and parsing methods use the reply format for the tool calls
+        let native_format = self.llm.provider().supports_structured_output(self.model.clone())
+            && !matches!(context.method, ToolCallMethod::StructuredOutput | ToolCallMethod::Parsing);
+        context.sampling.apply_response_format(&mut request, native_format);
+        Ok(request)
+    }
+
+    /// Run one completion, returns its message and whether it was cancelled
+    /// Its token usage is added to `token_usage`, estimated when the provider does not report it
+    async fn complete_step(
+        &self,
+        request: ChatCompletionParameters,
+        toolbox: &ToolBox,
+        context: &ThinkerContext,
+        trace: &[ChatMessage],
+        token_usage: &mut Option<(u32, u32)>,
+    ) -> Result<(ChatMessage, bool), AgentError> {
+        let outcome = self.complete(request, toolbox, &context.method, context.on_tool_call_delta.as_ref(), &context.cancellation_token)
+                .await
+                .map_err(|e| AgentError::LlmError(e.to_string()))?;
+        let cancelled = outcome.is_cancelled();
+        let brain_decision = outcome.into_response();
+
+        let reported = brain_decision.usage.as_ref()
+            .map(|usage| (usage.prompt_tokens.unwrap_or(0), usage.completion_tokens.unwrap_or(0)));
+        let message = brain_decision.choices.into_iter().next()
+            .ok_or_else(|| AgentError::InvalidResponse("no choice in completion".to_string()))?
+            .message;
+
+        let (input, output) = reported.unwrap_or_else(|| {
+            debug!(target: "brain::coder", "no usage reported by the provider, estimating it");
+            estimate_usage(trace, &message)
+        });
+        let (total_input, total_output) = token_usage.unwrap_or((0, 0));
+        *token_usage = Some((total_input + input, total_output + output));
+        Ok((message, cancelled))
+    }
+
+    async fn complete_unstreamed(
+        &self,
+        request: ChatCompletionParameters,
+        toolbox: &ToolBox,
+        method: ToolCallMethod,
+        cancel: &CancellationToken,
+    ) -> Result<StreamOutcome, LlmError> {
+        tokio::select! {
+            biased;
+            _ = cancel.cancelled() => Ok(StreamOutcome::Cancelled(StreamAccumulator::new().finish())),
+            response = self.llm.chat_with_tools(request, toolbox, method) => response.map(StreamOutcome::Completed),
         }
     }
 }
@@ -47,6 +154,7 @@ impl CoderBrain {
 #[async_trait]
 impl Brain for CoderBrain {
     async fn next_step(&mut self, context: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        tracing::Span::current().record("model", self.model.as_str());
         let mut trace = context.trace.read().await.clone();
 
         // Render the user's system prompt template
@@ -63,42 +171,101 @@ impl Brain for CoderBrain {
             name: None,
         });
 
-        // get next step with custom temperature
-        let request = ChatCompletionParametersBuilder::default()
-            .model(&self.model)
-            .messages(trace)
-            .temperature(self.temperature)
-            .build()
-            .map_err(|e| AgentError::LlmError(e.to_string()))?;
-        
-        let brain_decision = self.llm.chat_with_tools(
-                request,
-                &context.available_tools.into_toolbox(),
-                context.method)
-                .await
-                .map_err(|e| AgentError::LlmError(e.to_string()))?;
+        // get next step with custom temperature, retrying empty replies with a nudge
+        let policy = &self.empty_completion;
+        let temperature = context.sampling.temperature.unwrap_or(self.temperature);
+        let toolbox = context.available_tools.clone().into_toolbox();
+        let mut attempt = 0;
+        let mut token_usage: Option<(u32, u32)> = None;
+        let message = loop {
+            let mut messages = trace.clone();
+            if attempt > 0 {
+                messages.push(ChatMessage::User {
+                    content: ChatMessageContent::Text(EMPTY_COMPLETION_NUDGE.to_string()),
+                    name: None,
+                });
+            }
 
-        // Extract token usage information
-        let token_usage = brain_decision.usage.as_ref().map(|usage| {
-            let input = usage.prompt_tokens.unwrap_or(0);
-            let output = usage.completion_tokens.unwrap_or(0);
-            (input, output)
-        });
+            let request = self.request(messages, temperature + policy.temperature_step * attempt as f32, &context)?;
+            let (message, cancelled) = self.complete_step(request, &toolbox, &context, &trace, &mut token_usage).await?;
+            if cancelled {
+                debug!(target: "brain::coder", "step cancelled, returning the partial output");
+                return Ok(ThinkerDecision::cancelled(message, token_usage).with_empty_retries(attempt));
+            }
+            if policy.allow_empty || !is_empty_completion(&message) {
+                break message;
+            }
 
-        // stop here if there's no other tool calls
-        let message = brain_decision.choices.into_iter().next().unwrap().message;
-        if let ChatMessage::Assistant { reasoning_content, content, tool_calls, .. } = &message {
-            if tool_calls.as_ref().map_or(true, |calls| calls.is_empty()) {
-                return Ok(match token_usage {
-                    Some((input_tokens, output_tokens)) => ThinkerDecision::agent_pause_with_tokens(message, input_tokens, output_tokens),
-                    None => ThinkerDecision::agent_pause(message),
+            if attempt >= policy.max_retries {
+                warn!(target: "brain::coder", attempts = attempt + 1, "model kept returning empty completions");
+                return Err(AgentError::EmptyCompletion(attempt + 1));
+            }
+            attempt += 1;
+            warn!(target: "brain::coder", attempt, "empty completion, retrying");
+        };
+
+        // a final reply that is not the JSON asked for is asked for again once, only this completion is run again
+        let has_tool_calls = matches!(&message, ChatMessage::Assistant { tool_calls: Some(calls), .. } if !calls.is_empty());
+        let json_format = context.sampling.response_format.as_ref().filter(|format| is_json_format(format));
+        let message = match json_format {
+            Some(format) if !has_tool_calls && json_reply(assistant_text(&message), format).is_none() => {
+                warn!(target: "brain::coder", "the model did not reply with valid JSON, asking again");
+                let mut messages = trace.clone();
+                messages.push(message.clone());
+                messages.push(ChatMessage::User {
+                    content: ChatMessageContent::Text(JSON_RETRY_NUDGE.to_string()),
+                    name: None,
                 });
+                let request = self.request(messages, temperature, &context)?;
+                let (retried, cancelled) = self.complete_step(request, &toolbox, &context, &trace, &mut token_usage).await?;
+                if cancelled {
+                    return Ok(ThinkerDecision::cancelled(message, token_usage).with_empty_retries(attempt));
+                }
+                retried
             }
+            _ => message,
+        };
+
+        // stop here if there's no other tool calls
+        let has_tool_calls = matches!(&message, ChatMessage::Assistant { tool_calls: Some(calls), .. } if !calls.is_empty());
+        let decision = match (has_tool_calls, token_usage) {
+            (false, Some((input_tokens, output_tokens))) => ThinkerDecision::agent_pause_with_tokens(message, input_tokens, output_tokens),
+            (false, None) => ThinkerDecision::agent_pause(message),
+            (true, Some((input_tokens, output_tokens))) => ThinkerDecision::agent_continue_with_tokens(message, input_tokens, output_tokens),
+            (true, None) => ThinkerDecision::agent_continue(message),
+        };
+        Ok(decision.with_empty_retries(attempt))
+    }
+}
+
+/// Message appended to the request when retrying an empty completion
+const EMPTY_COMPLETION_NUDGE: &str = "Your previous reply was empty. Please answer the request or call a tool.";
+
+/// Message appended to the request when asking again for a reply that was not valid JSON
+const JSON_RETRY_NUDGE: &str = "Your previous reply was not valid JSON in the requested format. \
+Answer again with the JSON only: no code fence, no text around it.";
+
+/// Text of an assistant message, empty when it has none
+fn assistant_text(message: &ChatMessage) -> &str {
+    match message {
+        ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. } => text,
+        _ => "",
+    }
+}
+
+/// True for an assistant message with no tool call and only whitespace (or no) text
+fn is_empty_completion(message: &ChatMessage) -> bool {
+    match message {
+        ChatMessage::Assistant { content, tool_calls, .. } => {
+            let no_tool_call = tool_calls.as_ref().map_or(true, |calls| calls.is_empty());
+            let no_text = match content {
+                None => true,
+                Some(ChatMessageContent::Text(text)) => text.trim().is_empty(),
+                Some(_) => false,
+            };
+            no_tool_call && no_text
         }
-        Ok(match token_usage {
-            Some((input_tokens, output_tokens)) => ThinkerDecision::agent_continue_with_tokens(message, input_tokens, output_tokens),
-            None => ThinkerDecision::agent_continue(message),
-        })
+        _ => false,
     }
 }
 
@@ -1,10 +1,13 @@
 use super::coder::CoderBrain;
-use crate::agent::{Agent, Brain, StdoutEventManager, ThinkerContext};
+use crate::agent::{Agent, AgentError, Brain, SamplingOverrides, StdoutEventManager, ThinkerContext};
+use crate::config::agent::EmptyCompletionConfig;
 use crate::logging::LoggingConfig;
 use crate::tools::AnyTool;
 use shai_llm::ToolCallMethod;
-use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent};
+use openai_dive::v1::resources::chat::{ChatCompletionParameters, ChatCompletionResponse, ChatCompletionResponseFormat, ChatMessage, ChatMessageContent};
+use openai_dive::v1::resources::model::ListModelResponse;
 use shai_llm::client::LlmClient;
+use shai_llm::provider::{LlmError, LlmProvider, LlmStream, ProviderInfo};
 use tokio::sync::RwLock;
 use std::sync::Arc;
 use tempfile::TempDir;
@@ -72,7 +75,10 @@ async fn test_coder_brain_think_simple() {
             name: None,
         }])),
         available_tools: vec![],
-        method: ToolCallMethod::FunctionCall
+        method: ToolCallMethod::FunctionCall,
+        on_tool_call_delta: None,
+        cancellation_token: tokio_util::sync::CancellationToken::new(),
+        sampling: SamplingOverrides::default(),
     };
     
     let result = brain.next_step(context).await;
@@ -86,6 +92,208 @@ async fn test_coder_brain_think_simple() {
     }
 }
 
+/// Keeps the requests it receives, replies with the scripted replies in order then "hello"
+struct RecordingProvider(Arc<std::sync::Mutex<Vec<ChatCompletionParameters>>>, Vec<&'static str>);
+
+#[async_trait::async_trait]
+impl LlmProvider for RecordingProvider {
+    async fn models(&self) -> Result<ListModelResponse, LlmError> {
+        Err("not supported".into())
+    }
+
+    async fn chat(&self, request: ChatCompletionParameters) -> Result<ChatCompletionResponse, LlmError> {
+        let mut requests = self.0.lock().unwrap();
+        let reply = self.1.get(requests.len()).copied().unwrap_or("hello");
+        requests.push(request);
+        Ok(serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "mock",
+            "choices": [{ "index": 0, "message": { "role": "assistant", "content": reply }, "finish_reason": "stop" }],
+            "usage": { "prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4 }
+        }))?)
+    }
+
+    async fn chat_stream(&self, _request: ChatCompletionParameters) -> Result<LlmStream, LlmError> {
+        Err("not supported".into())
+    }
+
+    fn supports_functions(&self, _model: String) -> bool {
+        true
+    }
+
+    fn supports_structured_output(&self, _model: String) -> bool {
+        false
+    }
+
+    fn name(&self) -> &'static str {
+        "recording"
+    }
+
+    fn info() -> ProviderInfo {
+        ProviderInfo { name: "recording", display_name: "Recording", env_vars: vec![] }
+    }
+}
+
+#[tokio::test]
+async fn test_sampling_overrides_reach_the_provider() {
+    let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let llm_client = Arc::new(LlmClient::from_provider(Box::new(RecordingProvider(requests.clone(), vec![]))));
+    let mut brain = CoderBrain::new(llm_client, "mock".to_string());
+
+    let sampling = SamplingOverrides {
+        temperature: Some(1.5),
+        top_p: Some(0.5),
+        max_tokens: Some(256),
+        stop: Some(vec!["END".to_string()]),
+        ..Default::default()
+    };
+    let context = ThinkerContext {
+        trace: Arc::new(RwLock::new(vec![ChatMessage::User {
+            content: ChatMessageContent::Text("Say hello".to_string()),
+            name: None,
+        }])),
+        available_tools: vec![],
+        method: ToolCallMethod::FunctionCall,
+        on_tool_call_delta: None,
+        cancellation_token: tokio_util::sync::CancellationToken::new(),
+        sampling,
+    };
+    brain.next_step(context).await.expect("brain step");
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    let request = serde_json::to_value(&requests[0]).unwrap();
+    assert_eq!(request["temperature"], 1.5);
+    assert_eq!(request["top_p"], 0.5);
+    assert_eq!(request["max_completion_tokens"], 256);
+    assert_eq!(request["stop"], serde_json::json!(["END"]));
+}
+
+#[tokio::test]
+async fn test_response_format_as_instruction() {
+    let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let replies = vec![r#"Sure! {"colors": ["red", "#, "```json\n{\"colors\": [\"red\", \"green\", \"blue\"]}\n```"];
+    let llm_client = Arc::new(LlmClient::from_provider(Box::new(RecordingProvider(requests.clone(), replies))));
+    let mut brain = CoderBrain::new(llm_client, "mock".to_string());
+
+    let sampling = SamplingOverrides {
+        response_format: Some(ChatCompletionResponseFormat::JsonObject),
+        ..Default::default()
+    };
+    let context = ThinkerContext {
+        trace: Arc::new(RwLock::new(vec![ChatMessage::User {
+            content: ChatMessageContent::Text("List three colors".to_string()),
+            name: None,
+        }])),
+        available_tools: vec![],
+        method: ToolCallMethod::FunctionCall,
+        on_tool_call_delta: None,
+        cancellation_token: tokio_util::sync::CancellationToken::new(),
+        sampling,
+    };
+    let decision = brain.next_step(context).await.expect("brain step");
+
+    // the provider does not support structured output, the format is asked for in a last system message
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    let request = serde_json::to_value(&requests[0]).unwrap();
+    assert!(request.get("response_format").map_or(true, |format| format.is_null()));
+    let last = request["messages"].as_array().unwrap().last().unwrap().clone();
+    assert_eq!(last["role"], "system");
+    assert!(last["content"].as_str().unwrap().contains("JSON object"));
+
+    // the malformed reply is asked for again once: the same trace, the reply and a nudge, then the instruction
+    let retry = serde_json::to_value(&requests[1]).unwrap();
+    let messages = retry["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), request["messages"].as_array().unwrap().len() + 2);
+    let [reply, nudge, instruction] = &messages[messages.len() - 3..] else { unreachable!() };
+    assert_eq!(reply["content"], r#"Sure! {"colors": ["red", "#);
+    assert_eq!(nudge["role"], "user");
+    assert!(nudge["content"].as_str().unwrap().contains("not valid JSON"));
+    assert_eq!(instruction, &last);
+
+    assert_eq!(decision.token_usage, Some((6, 2)));
+    match decision.unwrap() {
+        ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. } => assert!(text.contains(r#""blue"]"#)),
+        other => panic!("Expected Assistant message, got {:?}", other),
+    }
+}
+
+/// Context of a brain step answering `text`, with no tool and default sampling
+fn user_context(text: &str) -> ThinkerContext {
+    ThinkerContext {
+        trace: Arc::new(RwLock::new(vec![ChatMessage::User {
+            content: ChatMessageContent::Text(text.to_string()),
+            name: None,
+        }])),
+        available_tools: vec![],
+        method: ToolCallMethod::FunctionCall,
+        on_tool_call_delta: None,
+        cancellation_token: tokio_util::sync::CancellationToken::new(),
+        sampling: SamplingOverrides::default(),
+    }
+}
+
+/// Coder brain on a recording provider replying `replies`, with `empty_completion` as its empty reply policy
+fn scripted_brain(replies: Vec<&'static str>, empty_completion: EmptyCompletionConfig) -> (CoderBrain, Arc<std::sync::Mutex<Vec<ChatCompletionParameters>>>) {
+    let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let llm_client = Arc::new(LlmClient::from_provider(Box::new(RecordingProvider(requests.clone(), replies))));
+    let brain = CoderBrain::new(llm_client, "mock".to_string()).with_empty_completion(empty_completion);
+    (brain, requests)
+}
+
+#[tokio::test]
+async fn test_empty_completion_retried() {
+    let policy = EmptyCompletionConfig { max_retries: 2, temperature_step: 0.5, allow_empty: false };
+    let (mut brain, requests) = scripted_brain(vec!["", " \n"], policy);
+    let decision = brain.next_step(user_context("Say hello")).await.expect("brain step");
+
+    assert_eq!(decision.empty_retries, 2);
+    assert_eq!(decision.token_usage, Some((9, 3)));
+    match &decision.message {
+        ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. } => assert_eq!(text, "hello"),
+        other => panic!("Expected Assistant message, got {:?}", other),
+    }
+
+    // each retry nudges the model and raises the temperature
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 3);
+    for (attempt, request) in requests.iter().enumerate() {
+        let request = serde_json::to_value(request).unwrap();
+        let temperature = request["temperature"].as_f64().unwrap();
+        assert!((temperature - (0.3 + 0.5 * attempt as f64)).abs() < 1e-6, "attempt {}: {}", attempt, temperature);
+        let last = request["messages"].as_array().unwrap().last().unwrap().clone();
+        assert_eq!(last["role"], "user");
+        assert_eq!(last["content"].as_str().unwrap().contains("previous reply was empty"), attempt > 0);
+    }
+}
+
+#[tokio::test]
+async fn test_empty_completion_allowed() {
+    let policy = EmptyCompletionConfig { allow_empty: true, ..Default::default() };
+    let (mut brain, requests) = scripted_brain(vec![""], policy);
+    let decision = brain.next_step(user_context("Say nothing")).await.expect("brain step");
+
+    assert_eq!(requests.lock().unwrap().len(), 1);
+    assert_eq!(decision.empty_retries, 0);
+    match &decision.message {
+        ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. } => assert!(text.is_empty()),
+        other => panic!("Expected Assistant message, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_empty_completion_retries_exhausted() {
+    let policy = EmptyCompletionConfig { max_retries: 1, ..Default::default() };
+    let (mut brain, requests) = scripted_brain(vec!["", "", "hello"], policy);
+    let result = brain.next_step(user_context("Say hello")).await;
+
+    assert!(matches!(result, Err(AgentError::EmptyCompletion(2))), "{:?}", result.err());
+    assert_eq!(requests.lock().unwrap().len(), 2);
+}
+
 // Integration tests with real coding tasks and temporary files
 
 #[tokio::test]
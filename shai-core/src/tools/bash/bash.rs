@@ -1,5 +1,5 @@
 use super::structs::BashToolParams;
-use crate::tools::{tool, ToolResult};
+use crate::tools::{tool, ToolContext, ToolResult};
 use serde_json::json;
 use tokio_util::sync::CancellationToken;
 use std::collections::HashMap;
@@ -53,6 +53,11 @@ impl BashTool {
             cmd.current_dir(working_dir);
         }
 
+        // Session environment first, so per-call variables can refine it
+        for (key, value) in ToolContext::current().env() {
+            cmd.env(key, value);
+        }
+
         // Set environment variables
         for (key, value) in &params.env {
             cmd.env(key, value);
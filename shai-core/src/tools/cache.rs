@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use super::{ToolCapability, ToolResult};
+
+/// Results of tool calls, reused by identical calls (same tool, same parameters) until the TTL elapses
+/// Only the successful results of read-only tools are cached, and a call of a tool that may write
+/// drops them all so that reads never return content from before the write
+#[derive(Debug)]
+pub struct ToolCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<(String, String), (ToolResult, Instant)>>,
+}
+
+impl ToolCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, entries: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Tools only reading (read, ls, find) may have their results reused
+    pub fn is_cacheable(capabilities: &[ToolCapability]) -> bool {
+        capabilities == [ToolCapability::Read]
+    }
+
+    /// Tools that may write invalidate the cached results
+    pub fn invalidates(capabilities: &[ToolCapability]) -> bool {
+        capabilities.contains(&ToolCapability::Write)
+    }
+
+    /// Result of an identical call made less than the TTL ago
+    pub fn get(&self, tool_name: &str, parameters: &Value) -> Option<ToolResult> {
+        let key = (tool_name.to_string(), normalize(parameters));
+        let mut entries = self.entries.lock().unwrap();
+        let cached = match entries.get(&key) {
+            Some((result, stored)) if stored.elapsed() < self.ttl => Some(result.clone()),
+            Some(_) => {
+                entries.remove(&key);
+                None
+            }
+            None => None,
+        };
+
+        #[cfg(feature = "prometheus")]
+        match cached {
+            Some(_) => metrics::counter!("shai_tool_cache_hits_total", "tool" => tool_name.to_string()).increment(1),
+            None => metrics::counter!("shai_tool_cache_misses_total", "tool" => tool_name.to_string()).increment(1),
+        }
+        cached
+    }
+
+    /// Keep a result for the next identical calls, failures are not cached
+    pub fn put(&self, tool_name: &str, parameters: &Value, result: &ToolResult) {
+        if !result.is_success() {
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        // expired entries go when a new one comes in, the cache does not outgrow the calls of a TTL
+        entries.retain(|_, (_, stored)| now.duration_since(*stored) < self.ttl);
+        entries.insert((tool_name.to_string(), normalize(parameters)), (result.clone(), now));
+    }
+
+    /// Drop every cached result, e.g. after the workspace was changed outside of the tools
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// JSON of the parameters with the object keys sorted, so that key order does not change the cache key
+fn normalize(parameters: &Value) -> String {
+    fn sorted(value: &Value) -> Value {
+        match value {
+            Value::Object(map) => {
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                Value::Object(keys.into_iter().map(|key| (key.clone(), sorted(&map[key]))).collect())
+            }
+            Value::Array(items) => Value::Array(items.iter().map(sorted).collect()),
+            other => other.clone(),
+        }
+    }
+    sorted(parameters).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_cache_key_ignores_key_order() {
+        let cache = ToolCache::new(Duration::from_secs(60));
+        let result = ToolResult::success("file content".to_string());
+        cache.put("read", &json!({"path": "a.txt", "options": {"lines": 10, "from": 1}}), &result);
+
+        let hit = cache.get("read", &json!({"options": {"from": 1, "lines": 10}, "path": "a.txt"}));
+        assert_eq!(hit, Some(result));
+        assert!(cache.get("read", &json!({"path": "b.txt"})).is_none());
+        assert!(cache.get("ls", &json!({"path": "a.txt", "options": {"lines": 10, "from": 1}})).is_none());
+    }
+
+    #[test]
+    fn test_only_read_only_tools_are_cached() {
+        assert!(ToolCache::is_cacheable(&[ToolCapability::Read]));
+        assert!(!ToolCache::is_cacheable(&[ToolCapability::Read, ToolCapability::Write]));
+        assert!(!ToolCache::is_cacheable(&[ToolCapability::Network]));
+        assert!(!ToolCache::is_cacheable(&[]));
+        assert!(ToolCache::invalidates(&[ToolCapability::Read, ToolCapability::Write, ToolCapability::Network]));
+    }
+
+    #[test]
+    fn test_cache_ttl_and_failures() {
+        let cache = ToolCache::new(Duration::ZERO);
+        cache.put("read", &json!({"path": "a.txt"}), &ToolResult::success("content".to_string()));
+        assert!(cache.get("read", &json!({"path": "a.txt"})).is_none());
+
+        let cache = ToolCache::new(Duration::from_secs(60));
+        cache.put("read", &json!({"path": "missing.txt"}), &ToolResult::error("not found".to_string()));
+        assert!(cache.is_empty());
+    }
+}
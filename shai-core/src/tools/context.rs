@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::quota::{DiskQuota, QuotaExceeded};
+
+tokio::task_local! {
+    static TOOL_CONTEXT: ToolContext;
+}
+
+/// Callback receiving the progress of a tool call (message, percent)
+/// Installed by the agent for each call, it emits AgentEvent::ToolProgress
+#[derive(Clone)]
+pub struct ProgressReporter {
+    callback: Arc<dyn Fn(String, Option<u8>) + Send + Sync>,
+}
+
+impl ProgressReporter {
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: Fn(String, Option<u8>) + Send + Sync + 'static,
+    {
+        Self { callback: Arc::new(callback) }
+    }
+
+    /// Report progress, percent is capped at 100
+    pub fn report(&self, message: impl Into<String>, percent: Option<u8>) {
+        (self.callback)(message.into(), percent.map(|p| p.min(100)));
+    }
+}
+
+/// Per-session context available to a tool while it executes
+/// Set by the agent around each tool call, read by tools with `ToolContext::current()`
+#[derive(Clone, Default)]
+pub struct ToolContext {
+    env: Arc<HashMap<String, String>>,
+    progress: Option<ProgressReporter>,
+    disk_quota: Option<Arc<DiskQuota>>,
+}
+
+impl ToolContext {
+    pub fn new(env: HashMap<String, String>) -> Self {
+        Self { env: Arc::new(env), progress: None, disk_quota: None }
+    }
+
+    /// Charge the writes of file tools to a session disk quota (None = not accounted)
+    pub fn with_disk_quota(mut self, quota: Option<Arc<DiskQuota>>) -> Self {
+        self.disk_quota = quota;
+        self
+    }
+
+    pub fn disk_quota(&self) -> Option<&Arc<DiskQuota>> {
+        self.disk_quota.as_ref()
+    }
+
+    /// Charge the disk quota before writing `new_len` bytes to `path`, replacing its content
+    /// Returns the previous size of the file, to undo the charge if the write fails
+    pub fn charge_file_write(&self, path: &Path, new_len: u64) -> Result<u64, QuotaExceeded> {
+        let old_len = std::fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+        if let Some(quota) = &self.disk_quota {
+            quota.try_resize(old_len, new_len)?;
+        }
+        Ok(old_len)
+    }
+
+    /// Undo `charge_file_write` after a failed write
+    pub fn refund_file_write(&self, old_len: u64, new_len: u64) {
+        if let Some(quota) = &self.disk_quota {
+            let _ = quota.try_resize(new_len, old_len);
+        }
+    }
+
+    /// Same context with a progress reporter for a single tool call
+    pub fn with_progress(mut self, reporter: ProgressReporter) -> Self {
+        self.progress = Some(reporter);
+        self
+    }
+
+    /// Report the progress of the running tool call (no-op when nobody listens)
+    pub fn report_progress(&self, message: impl Into<String>, percent: Option<u8>) {
+        if let Some(reporter) = &self.progress {
+            reporter.report(message, percent);
+        }
+    }
+
+    /// Context of the tool call being executed (empty outside of a tool call)
+    pub fn current() -> Self {
+        TOOL_CONTEXT.try_with(|ctx| ctx.clone()).unwrap_or_default()
+    }
+
+    /// Session environment variables, to inject in spawned processes
+    pub fn env(&self) -> &HashMap<String, String> {
+        &self.env
+    }
+
+    /// Run `f` with this context as the current one
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        TOOL_CONTEXT.scope(self, f).await
+    }
+}
+
+impl std::fmt::Debug for ToolContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // values may hold credentials, only show the names
+        f.debug_struct("ToolContext")
+            .field("env", &self.env.keys().collect::<Vec<_>>())
+            .field("progress", &self.progress.is_some())
+            .field("disk_quota", &self.disk_quota)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_context_scope() {
+        assert!(ToolContext::current().env().is_empty());
+
+        let ctx = ToolContext::new(HashMap::from([("API_URL".to_string(), "https://acme".to_string())]));
+        let value = ctx.scope(async {
+            ToolContext::current().env().get("API_URL").cloned()
+        }).await;
+        assert_eq!(value.as_deref(), Some("https://acme"));
+
+        assert!(ToolContext::current().env().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_report_progress() {
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = reports.clone();
+        let ctx = ToolContext::default().with_progress(ProgressReporter::new(move |message, percent| {
+            sink.lock().unwrap().push((message, percent));
+        }));
+
+        ctx.scope(async {
+            ToolContext::current().report_progress("cloning", Some(40));
+            ToolContext::current().report_progress("done", Some(250));
+        }).await;
+
+        // outside of a tool call reporting is a no-op
+        ToolContext::current().report_progress("ignored", None);
+
+        assert_eq!(*reports.lock().unwrap(), vec![
+            ("cloning".to_string(), Some(40)),
+            ("done".to_string(), Some(100)),
+        ]);
+    }
+
+    #[test]
+    fn test_charge_file_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "0123456789").unwrap();
+
+        let quota = Arc::new(DiskQuota::new(15));
+        let ctx = ToolContext::default().with_disk_quota(Some(quota.clone()));
+
+        // only the growth of an existing file is charged
+        assert_eq!(ctx.charge_file_write(&path, 20).unwrap(), 10);
+        assert_eq!(quota.used(), 10);
+        assert!(ctx.charge_file_write(&dir.path().join("big.txt"), 6).is_err());
+
+        ctx.refund_file_write(10, 20);
+        assert_eq!(quota.used(), 0);
+
+        // without quota writes are never refused
+        assert!(ToolContext::default().charge_file_write(&path, u64::MAX).is_ok());
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use shai_llm::ToolDescription;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use super::{AnyTool, ToolCall, ToolCapability, ToolResult};
+
+/// Time an endpoint has to answer a tool call when its definition sets none
+const DEFAULT_TOOL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Tool described by a JSON file of the tools folder, its calls are POSTed to `http_endpoint`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HttpToolDefinition {
+    pub name: String,
+    pub description: String,
+    pub parameters_schema: Value,
+    pub http_endpoint: String,
+    /// Seconds the endpoint has to answer (default 60)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// Client of the HTTP tools, shared so that their calls reuse the connections to the endpoints
+fn http_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(reqwest::Client::new)
+}
+
+/// Tool calling an HTTP endpoint: the ToolCall is POSTed as JSON, the answer is the ToolResult
+#[derive(Clone)]
+pub struct HttpTool {
+    definition: HttpToolDefinition,
+    timeout: Duration,
+}
+
+impl HttpTool {
+    pub fn new(definition: HttpToolDefinition) -> Self {
+        let timeout = definition.timeout_secs.map_or(DEFAULT_TOOL_TIMEOUT, Duration::from_secs);
+        Self { definition, timeout }
+    }
+
+    pub fn definition(&self) -> &HttpToolDefinition {
+        &self.definition
+    }
+
+    async fn call(&self, params: Value) -> Result<ToolResult, String> {
+        let tool_call = ToolCall {
+            tool_call_id: format!("http-{}", uuid::Uuid::new_v4()),
+            tool_name: self.definition.name.clone(),
+            parameters: params,
+        };
+        let response = http_client().post(&self.definition.http_endpoint)
+            .timeout(self.timeout)
+            .json(&tool_call)
+            .send()
+            .await
+            .map_err(|e| format!("request to {} failed: {}", self.definition.http_endpoint, e))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("{} answered {}: {}", self.definition.http_endpoint, status, body));
+        }
+        response.json::<ToolResult>().await
+            .map_err(|e| format!("invalid tool result from {}: {}", self.definition.http_endpoint, e))
+    }
+}
+
+impl ToolDescription for HttpTool {
+    fn name(&self) -> String {
+        self.definition.name.clone()
+    }
+
+    fn description(&self) -> String {
+        self.definition.description.clone()
+    }
+
+    fn parameters_schema(&self) -> Value {
+        self.definition.parameters_schema.clone()
+    }
+
+    fn group(&self) -> Option<&str> {
+        Some("dynamic")
+    }
+}
+
+#[async_trait]
+impl AnyTool for HttpTool {
+    fn capabilities(&self) -> &[ToolCapability] {
+        &[ToolCapability::Network]
+    }
+
+    async fn execute_json(&self, params: Value, cancel_token: Option<CancellationToken>) -> ToolResult {
+        let cancel_token = cancel_token.unwrap_or_default();
+        tokio::select! {
+            result = self.call(params) => result.unwrap_or_else(|e| ToolResult::error(format!("HTTP tool execution failed: {}", e))),
+            _ = cancel_token.cancelled() => ToolResult::error("HTTP tool call cancelled".to_string()),
+        }
+    }
+
+    async fn execute_preview_json(&self, _params: Value) -> Option<ToolResult> {
+        None // the endpoint has no preview mode
+    }
+}
+
+/// HTTP tools defined by the `*.json` files of a folder, read at startup and reloaded
+/// Agents take a snapshot of the tools when they are built: a reload only changes the tools of
+/// the agents built after it, the calls in flight keep the tool they started with
+pub struct DynamicToolLoader {
+    dir: PathBuf,
+    reload_interval: Option<Duration>,
+    tools: RwLock<Arc<Vec<HttpTool>>>,
+}
+
+impl DynamicToolLoader {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir, reload_interval: None, tools: RwLock::new(Arc::new(Vec::new())) }
+    }
+
+    /// Read the folder again every `interval` once `spawn_reload` runs (None = read at startup only)
+    pub fn with_reload_interval(mut self, interval: Option<Duration>) -> Self {
+        self.reload_interval = interval;
+        self
+    }
+
+    /// Loader of SHAI_TOOLS_DIR, reloaded every SHAI_TOOLS_RELOAD_SECS seconds when set (0 = never)
+    /// None when SHAI_TOOLS_DIR is not set: no folder is read unless it is named explicitly, a
+    /// checked out repository cannot bring its own tools
+    pub fn from_env() -> Option<Self> {
+        let dir = std::env::var("SHAI_TOOLS_DIR").ok().filter(|dir| !dir.trim().is_empty()).map(PathBuf::from)?;
+        let interval = std::env::var("SHAI_TOOLS_RELOAD_SECS")
+            .ok()
+            .and_then(|secs| secs.trim().parse().ok())
+            .map(Duration::from_secs)
+            .filter(|interval| !interval.is_zero());
+        Some(Self::new(dir).with_reload_interval(interval))
+    }
+
+    /// Loader of the environment, loaded on first use and reloaded in the background when an
+    /// interval is set and a tokio runtime runs (None = SHAI_TOOLS_DIR not set)
+    /// The first load reads the folder on the calling thread, once per process
+    pub fn shared() -> Option<Arc<Self>> {
+        static LOADER: OnceLock<Option<Arc<DynamicToolLoader>>> = OnceLock::new();
+        LOADER.get_or_init(|| {
+            let loader = Arc::new(Self::from_env()?);
+            loader.reload();
+            if tokio::runtime::Handle::try_current().is_ok() {
+                loader.clone().spawn_reload();
+            }
+            Some(loader)
+        }).clone()
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Read the definitions of the folder and replace the tools with them, returns their number
+    /// Files that do not parse are skipped, as are the later files of an already defined name
+    pub fn reload(&self) -> usize {
+        let tools: Vec<HttpTool> = load_definitions(&self.dir).into_iter().map(HttpTool::new).collect();
+        let count = tools.len();
+        *self.tools.write().unwrap() = Arc::new(tools);
+        count
+    }
+
+    /// Reload the folder every reload interval, nothing is spawned without one
+    /// The folder is read on the blocking pool, not on the runtime threads
+    pub fn spawn_reload(self: Arc<Self>) -> Option<tokio::task::JoinHandle<()>> {
+        let interval = self.reload_interval?;
+        Some(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let loader = self.clone();
+                match tokio::task::spawn_blocking(move || loader.reload()).await {
+                    Ok(count) => debug!("Reloaded {} tools from {}", count, self.dir.display()),
+                    Err(e) => warn!("Failed to reload the tools of {}: {}", self.dir.display(), e),
+                }
+            }
+        }))
+    }
+
+    /// Tools of the last load
+    pub fn tools(&self) -> Vec<Box<dyn AnyTool>> {
+        let tools = self.tools.read().unwrap().clone();
+        tools.iter().map(|tool| Box::new(tool.clone()) as Box<dyn AnyTool>).collect()
+    }
+}
+
+/// Definitions of the `*.json` files of a folder in file name order, none when it does not exist
+fn load_definitions(dir: &Path) -> Vec<HttpToolDefinition> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+        .collect();
+    paths.sort();
+
+    let mut names = HashSet::new();
+    let mut definitions = Vec::new();
+    for path in paths {
+        let definition = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| serde_json::from_str::<HttpToolDefinition>(&content).map_err(|e| e.to_string()));
+        match definition {
+            Ok(definition) if !names.insert(definition.name.clone()) => {
+                warn!("Skipping tool {}: '{}' is already defined", path.display(), definition.name);
+            }
+            Ok(definition) => definitions.push(definition),
+            Err(e) => warn!("Skipping tool {}: {}", path.display(), e),
+        }
+    }
+    definitions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn write_definition(dir: &Path, file: &str, name: &str, endpoint: &str) {
+        let definition = json!({
+            "name": name,
+            "description": format!("{} tool", name),
+            "parameters_schema": { "type": "object", "properties": { "city": { "type": "string" } } },
+            "http_endpoint": endpoint,
+        });
+        std::fs::write(dir.join(file), definition.to_string()).unwrap();
+    }
+
+    /// Answers one HTTP request with `body`, returns the endpoint URL and the request received
+    async fn serve_once(body: String) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/tools/weather", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            loop {
+                let read = socket.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length = text.lines()
+                        .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|len| len.trim().parse::<usize>().unwrap()))
+                        .unwrap_or(0);
+                    if request.len() >= end + 4 + length {
+                        break;
+                    }
+                }
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn test_load_and_reload_definitions() {
+        let dir = tempfile::tempdir().unwrap();
+        write_definition(dir.path(), "a_weather.json", "weather", "http://localhost:9000/weather");
+        write_definition(dir.path(), "b_weather.json", "weather", "http://localhost:9001/weather");
+        std::fs::write(dir.path().join("broken.json"), "{ not json").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "ignored").unwrap();
+
+        let loader = DynamicToolLoader::new(dir.path().to_path_buf());
+        assert!(loader.tools().is_empty());
+        assert_eq!(loader.reload(), 1);
+        let tools = loader.tools();
+        assert_eq!(tools[0].name(), "weather");
+        assert_eq!(tools[0].group(), Some("dynamic"));
+        assert_eq!(tools[0].parameters_schema()["properties"]["city"]["type"], "string");
+
+        // tools taken before a reload are left as they were
+        write_definition(dir.path(), "c_search.json", "search", "http://localhost:9002/search");
+        assert_eq!(loader.reload(), 2);
+        assert_eq!(tools.len(), 1);
+        let names: Vec<String> = loader.tools().iter().map(|tool| tool.name()).collect();
+        assert_eq!(names, vec!["weather", "search"]);
+
+        assert_eq!(DynamicToolLoader::new(dir.path().join("missing")).reload(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let loader = Arc::new(DynamicToolLoader::new(dir.path().to_path_buf()).with_reload_interval(Some(Duration::from_millis(10))));
+        let task = loader.clone().spawn_reload().unwrap();
+
+        write_definition(dir.path(), "weather.json", "weather", "http://localhost:9000/weather");
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while loader.tools().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.expect("the reload picks up the new definition");
+        task.abort();
+
+        assert!(DynamicToolLoader::new(dir.path().to_path_buf()).spawn_reload().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_http_tool_call() {
+        let result = ToolResult::success("18°C in Paris".to_string());
+        let (url, server) = serve_once(serde_json::to_string(&result).unwrap()).await;
+        let tool = HttpTool::new(serde_json::from_value(json!({
+            "name": "weather",
+            "description": "Weather of a city",
+            "parameters_schema": { "type": "object" },
+            "http_endpoint": url,
+        })).unwrap());
+
+        assert_eq!(tool.execute_json(json!({ "city": "Paris" }), None).await, result);
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /tools/weather"));
+        let body: ToolCall = serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body.tool_name, "weather");
+        assert_eq!(body.parameters, json!({ "city": "Paris" }));
+
+        // an answer that is not a ToolResult is an error result, not a panic
+        let (url, _) = serve_once("{\"temperature\": 18}".to_string()).await;
+        let mut definition = tool.definition().clone();
+        definition.http_endpoint = url;
+        assert!(HttpTool::new(definition).execute_json(json!({}), None).await.is_error());
+    }
+}
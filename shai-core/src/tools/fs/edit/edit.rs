@@ -1,6 +1,6 @@
 use super::structs::EditToolParams;
 use super::super::{FsOperationLog, FsOperationType};
-use crate::tools::{tool, ToolResult};
+use crate::tools::{tool, ToolContext, ToolResult};
 use similar::{ChangeTag, TextDiff};
 use serde_json::json;
 use std::collections::HashMap;
@@ -177,19 +177,35 @@ impl EditTool {
         fs::write(path, new_content).map_err(|e| e.to_string())
     }
 
-    fn perform_edit(&self, params: &EditToolParams, preview: bool) -> Result<(String, usize), String> {
+    /// Same as commit_edit, charging the session disk quota first
+    /// A write refused by the quota is returned as its error result, with the quota details
+    pub fn commit_edit_charged(&self, path: &str, new_content: &str, operation: &str) -> Result<(), ToolResult> {
+        let context = ToolContext::current();
+        let new_len = new_content.len() as u64;
+        let old_len = context.charge_file_write(Path::new(path), new_len)
+            .map_err(|e| e.to_tool_result(operation))?;
+
+        self.commit_edit(path, new_content).map_err(|e| {
+            context.refund_file_write(old_len, new_len);
+            ToolResult::error(format!("{} failed: {}", operation, e))
+        })
+    }
+
+    fn perform_edit(&self, params: &EditToolParams, preview: bool) -> Result<(String, usize), ToolResult> {
+        let failed = |e: String| ToolResult::error(format!("Edit {} failed: {}", if preview { "preview" } else { "" }, e));
         let path = Path::new(&params.path);
 
         // Check if file exists
         if !path.exists() {
-            return Err(format!("File does not exist: {}", params.path));
+            return Err(failed(format!("File does not exist: {}", params.path)));
         }
 
         // Read the file content
-        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let content = fs::read_to_string(path).map_err(|e| failed(e.to_string()))?;
 
         // Perform edit on content
-        let (new_content, replacements) = self.perform_edit_on_content(&content, &params.old_string, &params.new_string, params.replace_all)?;
+        let (new_content, replacements) = self.perform_edit_on_content(&content, &params.old_string, &params.new_string, params.replace_all)
+            .map_err(failed)?;
 
         // Generate proper diff using Myers' algorithm
         let diff = self.myers_diff(&content, &new_content);
@@ -200,7 +216,7 @@ impl EditTool {
 
         // Only write to file if not preview mode
         if !preview {
-            self.commit_edit(&params.path, &new_content)?;
+            self.commit_edit_charged(&params.path, &new_content, "Edit")?;
         }
 
         Ok((diff_output.join("\n"), replacements))
@@ -263,9 +279,7 @@ impl EditTool {
                     metadata: Some(meta),
                 }
             },
-            Err(e) => {
-                ToolResult::error(format!("Edit {} failed: {}", if preview { "preview" } else { "" }, e))
-            }
+            Err(result) => result,
         }
     }
 }
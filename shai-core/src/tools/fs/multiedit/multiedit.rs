@@ -23,16 +23,17 @@ impl MultiEditTool {
         Self { operation_log, edit_tool }
     }
     
-    async fn perform_multi_edit(&self, params: &MultiEditToolParams, preview: bool) -> Result<(String, Vec<usize>), String> {
+    async fn perform_multi_edit(&self, params: &MultiEditToolParams, preview: bool) -> Result<(String, Vec<usize>), ToolResult> {
+        let failed = |e: String| ToolResult::error(format!("MultiEdit {} failed: {}", if preview { "preview" } else { "" }, e));
         let path = Path::new(&params.file_path);
 
         // Check if file exists
         if !path.exists() {
-            return Err(format!("File does not exist: {}", params.file_path));
+            return Err(failed(format!("File does not exist: {}", params.file_path)));
         }
 
         // Read initial content
-        let mut current_content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut current_content = fs::read_to_string(path).map_err(|e| failed(e.to_string()))?;
         let original_content = current_content.clone();
         let mut replacements_per_edit = Vec::new();
 
@@ -44,7 +45,7 @@ impl MultiEditTool {
                     replacements_per_edit.push(replacements);
                 },
                 Err(error) => {
-                    return Err(format!("Edit #{}: {}", index + 1, error));
+                    return Err(failed(format!("Edit #{}: {}", index + 1, error)));
                 }
             }
         }
@@ -54,7 +55,7 @@ impl MultiEditTool {
         
         // Only write to file if not preview mode
         if !preview {
-            self.edit_tool.commit_edit(&params.file_path, &current_content)?;
+            self.edit_tool.commit_edit_charged(&params.file_path, &current_content, "MultiEdit")?;
         }
 
         Ok((diff, replacements_per_edit))
@@ -126,9 +127,7 @@ impl MultiEditTool {
                     metadata: Some(meta),
                 }
             },
-            Err(e) => {
-                ToolResult::error(format!("MultiEdit {} failed: {}", if preview { "preview" } else { "" }, e))
-            }
+            Err(result) => result,
         }
     }
 }
\ No newline at end of file
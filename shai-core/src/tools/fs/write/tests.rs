@@ -1,6 +1,6 @@
 use super::structs::WriteToolParams;
 use super::write::WriteTool;
-use crate::tools::{Tool, ToolCapability, FsOperationLog};
+use crate::tools::{Tool, ToolCapability, ToolContext, ToolResult, DiskQuota, FsOperationLog, QUOTA_EXCEEDED_METADATA};
 use shai_llm::ToolDescription;
 use std::fs;
 use std::sync::Arc;
@@ -46,4 +46,30 @@ async fn test_write_new_file() {
     
     let content = fs::read_to_string(&file_path).unwrap();
     assert_eq!(content, "Hello, World!");
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn test_write_refused_by_disk_quota() {
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("big.txt");
+
+    let quota = Arc::new(DiskQuota::new(8));
+    let context = ToolContext::default().with_disk_quota(Some(quota.clone()));
+    let tool = WriteTool::new(Arc::new(FsOperationLog::new()));
+    let params = |content: &str| WriteToolParams {
+        path: file_path.to_string_lossy().to_string(),
+        content: content.to_string(),
+    };
+
+    let result = context.clone().scope(tool.execute(params("Hello, World!"), None)).await;
+    match result {
+        ToolResult::Error { metadata: Some(metadata), .. } => assert!(metadata.contains_key(QUOTA_EXCEEDED_METADATA)),
+        other => panic!("Expected a quota error, got {:?}", other),
+    }
+    assert!(!file_path.exists());
+    assert_eq!(quota.used(), 0);
+
+    let result = context.scope(tool.execute(params("Hello"), None)).await;
+    assert!(result.is_success());
+    assert_eq!(quota.used(), 5);
+}
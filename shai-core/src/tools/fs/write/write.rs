@@ -1,6 +1,6 @@
 use super::structs::WriteToolParams;
 use super::super::{FsOperationLog, FsOperationType};
-use crate::tools::{ToolResult, tool};
+use crate::tools::{ToolContext, ToolResult, tool};
 //use crate::tools::highlight::highlight_content;
 use serde_json::json;
 use std::collections::HashMap;
@@ -65,6 +65,14 @@ impl WriteTool {
     }
 
     async fn execute(&self, params: WriteToolParams) -> ToolResult {
+        // The session disk quota is charged before anything is written
+        let context = ToolContext::current();
+        let new_len = params.content.len() as u64;
+        let old_len = match context.charge_file_write(Path::new(&params.path), new_len) {
+            Ok(old_len) => old_len,
+            Err(e) => return e.to_tool_result("Write"),
+        };
+
         match self.perform_write(&params) {
             Ok(message) => {
                 // Log the write operation
@@ -91,6 +99,7 @@ impl WriteTool {
                 }
             },
             Err(e) => {
+                context.refund_file_write(old_len, new_len);
                 ToolResult::error(format!("Write failed: {}", e))
             }
         }
@@ -1,20 +1,30 @@
 pub mod types;
+pub mod cache;
+pub mod context;
+pub mod postprocess;
+pub mod quota;
 pub mod highlight;
 pub mod todo;
 pub mod fs;
 pub mod fetch;
 pub mod bash;
 pub mod mcp;
+pub mod dynamic;
 
 #[cfg(test)]
 mod tests_llm;
 
 pub use shai_macros::tool;
 pub use types::{Tool, ToolCall, ToolResult, ToolError, ToolCapability, AnyTool, AnyToolBox, ToolEmptyParams};
+pub use cache::ToolCache;
+pub use context::{ProgressReporter, ToolContext};
+pub use postprocess::{JsonFilter, ToolOutputFilters, RAW_OUTPUT_METADATA};
+pub use quota::{DiskQuota, QuotaExceeded, QUOTA_EXCEEDED_METADATA};
 
 // Re-export all tools
 pub use bash::BashTool;
 pub use fetch::FetchTool;
 pub use fs::{EditTool, FindTool, LsTool, MultiEditTool, ReadTool, WriteTool, FsOperationLog, FsOperationType, FsOperation, FsOperationSummary};
 pub use todo::{TodoReadTool, TodoWriteTool, TodoStorage, TodoItem, TodoStatus, TodoWriteParams, TodoItemInput};
+pub use dynamic::{DynamicToolLoader, HttpTool, HttpToolDefinition};
 pub use mcp::{McpClient, McpToolDescription, McpConfig, create_mcp_client, get_mcp_tools, StdioClient, HttpClient, SseClient};
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use serde_json::Value;
+use tracing::warn;
+use super::ToolResult;
+
+/// Metadata key holding the unfiltered output of a post-processed tool call
+pub const RAW_OUTPUT_METADATA: &str = "raw_output";
+
+/// One step of a filter path
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    /// `.name` or `."name"` or `["name"]`
+    Field(String),
+    /// `[2]`, negative indexes count from the end
+    Index(i64),
+    /// `[1:3]`, `[:2]`, `[-2:]`
+    Slice(Option<i64>, Option<i64>),
+    /// `[]`, iterate over array elements or object values
+    Iterate,
+}
+
+/// A jq-like path filter applied to JSON tool outputs
+///
+/// Supported syntax: `.`, `.a.b`, `."a b"`, `.a[0]`, `.a[-1]`, `.a[1:3]`, `.a[]`,
+/// `.a[].b` and `|` between paths. When a filter yields several values
+/// (through `[]`) they are collected in an array.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonFilter {
+    expression: String,
+    steps: Vec<Step>,
+}
+
+impl JsonFilter {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let chars: Vec<char> = expression.chars().collect();
+        let mut steps = Vec::new();
+        let mut pos = 0;
+        let mut expect_path = true;
+
+        while pos < chars.len() {
+            match chars[pos] {
+                c if c.is_whitespace() => pos += 1,
+                '|' if !expect_path => {
+                    pos += 1;
+                    expect_path = true;
+                }
+                '.' => {
+                    pos += 1;
+                    expect_path = false;
+                    match chars.get(pos) {
+                        Some(c) if c.is_alphabetic() || *c == '_' => {
+                            let start = pos;
+                            while pos < chars.len() && (chars[pos].is_alphanumeric() || chars[pos] == '_') {
+                                pos += 1;
+                            }
+                            steps.push(Step::Field(chars[start..pos].iter().collect()));
+                        }
+                        Some('"') => {
+                            let (name, next) = parse_string(&chars, pos)?;
+                            steps.push(Step::Field(name));
+                            pos = next;
+                        }
+                        _ => {} // identity, or followed by a bracket
+                    }
+                }
+                '[' if !expect_path => {
+                    let end = chars[pos..].iter().position(|c| *c == ']')
+                        .map(|offset| pos + offset)
+                        .ok_or_else(|| format!("unclosed '[' at position {} in '{}'", pos, expression))?;
+                    let inner: String = chars[pos + 1..end].iter().collect();
+                    steps.push(parse_bracket(inner.trim())
+                        .map_err(|e| format!("{} at position {} in '{}'", e, pos, expression))?);
+                    pos = end + 1;
+                }
+                c => return Err(format!("unexpected '{}' at position {} in '{}'", c, pos, expression)),
+            }
+        }
+
+        if expect_path {
+            return Err(format!("expected a path starting with '.' in '{}'", expression));
+        }
+
+        Ok(Self { expression: expression.to_string(), steps })
+    }
+
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    /// Apply the filter, a single result is returned as is, several results as an array
+    pub fn apply(&self, input: &Value) -> Result<Value, String> {
+        let mut values = vec![input.clone()];
+        let mut iterated = false;
+
+        for step in &self.steps {
+            let mut next = Vec::with_capacity(values.len());
+            for value in values {
+                match (step, value) {
+                    (_, Value::Null) if !matches!(step, Step::Iterate) => next.push(Value::Null),
+                    (Step::Field(name), Value::Object(mut map)) => {
+                        next.push(map.remove(name).unwrap_or(Value::Null));
+                    }
+                    (Step::Index(index), Value::Array(items)) => {
+                        next.push(resolve_index(*index, items.len())
+                            .and_then(|i| items.get(i).cloned())
+                            .unwrap_or(Value::Null));
+                    }
+                    (Step::Slice(start, end), Value::Array(items)) => {
+                        let len = items.len();
+                        let start = start.map_or(0, |s| clamp_index(s, len));
+                        let end = end.map_or(len, |e| clamp_index(e, len));
+                        next.push(Value::Array(items.into_iter().take(end).skip(start).collect()));
+                    }
+                    (Step::Iterate, Value::Array(items)) => {
+                        iterated = true;
+                        next.extend(items);
+                    }
+                    (Step::Iterate, Value::Object(map)) => {
+                        iterated = true;
+                        next.extend(map.into_iter().map(|(_, v)| v));
+                    }
+                    (step, value) => {
+                        return Err(format!("cannot apply {} to {}", describe_step(step), type_name(&value)));
+                    }
+                }
+            }
+            values = next;
+        }
+
+        if iterated {
+            Ok(Value::Array(values))
+        } else {
+            Ok(values.into_iter().next().unwrap_or(Value::Null))
+        }
+    }
+}
+
+fn parse_string(chars: &[char], start: usize) -> Result<(String, usize), String> {
+    let end = chars[start + 1..].iter().position(|c| *c == '"')
+        .map(|offset| start + 1 + offset)
+        .ok_or_else(|| format!("unclosed string at position {}", start))?;
+    Ok((chars[start + 1..end].iter().collect(), end + 1))
+}
+
+fn parse_bracket(inner: &str) -> Result<Step, String> {
+    if inner.is_empty() {
+        return Ok(Step::Iterate);
+    }
+    if let Some(name) = inner.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        return Ok(Step::Field(name.to_string()));
+    }
+    let parse_int = |s: &str| s.trim().parse::<i64>().map_err(|_| format!("invalid index '{}'", s.trim()));
+    match inner.split_once(':') {
+        Some((start, end)) => {
+            let start = if start.trim().is_empty() { None } else { Some(parse_int(start)?) };
+            let end = if end.trim().is_empty() { None } else { Some(parse_int(end)?) };
+            Ok(Step::Slice(start, end))
+        }
+        None => Ok(Step::Index(parse_int(inner)?)),
+    }
+}
+
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    if index >= 0 {
+        Some(index as usize)
+    } else {
+        (len as i64 + index).try_into().ok()
+    }
+}
+
+fn clamp_index(index: i64, len: usize) -> usize {
+    let index = if index < 0 { len as i64 + index } else { index };
+    index.clamp(0, len as i64) as usize
+}
+
+fn describe_step(step: &Step) -> String {
+    match step {
+        Step::Field(name) => format!("field \"{}\"", name),
+        Step::Index(index) => format!("index [{}]", index),
+        Step::Slice(..) => "slice".to_string(),
+        Step::Iterate => "iteration []".to_string(),
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Filters applied to successful tool outputs before they enter the trace, keyed by tool name
+#[derive(Debug, Clone, Default)]
+pub struct ToolOutputFilters {
+    filters: Arc<HashMap<String, JsonFilter>>,
+}
+
+impl ToolOutputFilters {
+    /// Parse the expressions of an agent config, fails on the first invalid one
+    pub fn from_config(expressions: &HashMap<String, String>) -> Result<Self, String> {
+        let filters = expressions.iter()
+            .map(|(tool, expression)| {
+                JsonFilter::parse(expression)
+                    .map(|filter| (tool.clone(), filter))
+                    .map_err(|e| format!("invalid post_process filter for tool '{}': {}", tool, e))
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        Ok(Self { filters: Arc::new(filters) })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Filter the output of a successful call, the raw output is kept in the result metadata.
+    /// If the output is not JSON or the filter fails, the result is returned unchanged.
+    pub fn apply(&self, tool_name: &str, result: ToolResult) -> ToolResult {
+        let Some(filter) = self.filters.get(tool_name) else {
+            return result;
+        };
+        let ToolResult::Success { output, metadata } = result else {
+            return result;
+        };
+
+        let filtered = serde_json::from_str::<Value>(&output)
+            .map_err(|e| format!("output is not JSON: {}", e))
+            .and_then(|value| filter.apply(&value));
+
+        match filtered {
+            Ok(value) => {
+                let filtered_output = match value {
+                    Value::String(s) => s,
+                    other => other.to_string(),
+                };
+                let mut metadata = metadata.unwrap_or_default();
+                metadata.insert(RAW_OUTPUT_METADATA.to_string(), Value::String(output));
+                ToolResult::Success { output: filtered_output, metadata: Some(metadata) }
+            }
+            Err(e) => {
+                warn!(target: "agent::tool_completed", "post_process '{}' failed for tool {}, keeping raw output: {}", filter.expression(), tool_name, e);
+                ToolResult::Success { output, metadata }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn filters(tool: &str, expression: &str) -> ToolOutputFilters {
+        ToolOutputFilters::from_config(&HashMap::from([(tool.to_string(), expression.to_string())])).unwrap()
+    }
+
+    #[test]
+    fn test_nested_extraction() {
+        let input = json!({"data": {"user": {"name": "ada", "tags": ["a", "b"]}, "items": [{"id": 1}, {"id": 2}]}});
+
+        assert_eq!(JsonFilter::parse(".data.user.name").unwrap().apply(&input).unwrap(), json!("ada"));
+        assert_eq!(JsonFilter::parse(".data.items[].id").unwrap().apply(&input).unwrap(), json!([1, 2]));
+        assert_eq!(JsonFilter::parse(".data | .user | .tags[-1]").unwrap().apply(&input).unwrap(), json!("b"));
+        assert_eq!(JsonFilter::parse(".data.missing.deeper").unwrap().apply(&input).unwrap(), Value::Null);
+        assert_eq!(JsonFilter::parse(".").unwrap().apply(&input).unwrap(), input);
+    }
+
+    #[test]
+    fn test_array_slicing() {
+        let input = json!({"rows": [0, 1, 2, 3, 4]});
+
+        assert_eq!(JsonFilter::parse(".rows[1:3]").unwrap().apply(&input).unwrap(), json!([1, 2]));
+        assert_eq!(JsonFilter::parse(".rows[:2]").unwrap().apply(&input).unwrap(), json!([0, 1]));
+        assert_eq!(JsonFilter::parse(".rows[-2:]").unwrap().apply(&input).unwrap(), json!([3, 4]));
+        assert_eq!(JsonFilter::parse(".rows[3:100]").unwrap().apply(&input).unwrap(), json!([3, 4]));
+        assert_eq!(JsonFilter::parse(".rows[4:1]").unwrap().apply(&input).unwrap(), json!([]));
+    }
+
+    #[test]
+    fn test_invalid_expressions_fail_validation() {
+        for expression in ["", "data", ".a[", ".a[x]", ".a |", ".a ! .b"] {
+            assert!(JsonFilter::parse(expression).is_err(), "'{}' should be rejected", expression);
+        }
+        let err = ToolOutputFilters::from_config(&HashMap::from([("fetch".to_string(), ".a[".to_string())])).unwrap_err();
+        assert!(err.contains("fetch"));
+    }
+
+    #[test]
+    fn test_filtered_output_keeps_raw_output() {
+        let raw = r#"{"results": [{"title": "first"}, {"title": "second"}], "debug": "noise"}"#;
+        let result = filters("search", ".results[].title").apply("search", ToolResult::success(raw.to_string()));
+
+        let ToolResult::Success { output, metadata } = result else { panic!("expected success") };
+        assert_eq!(output, r#"["first","second"]"#);
+        assert_eq!(metadata.unwrap()[RAW_OUTPUT_METADATA], json!(raw));
+    }
+
+    #[test]
+    fn test_extraction_error_falls_back_to_raw_output() {
+        let filters = filters("search", ".results[0].title");
+
+        // the filter does not match the shape of the output
+        let raw = r#"{"results": "rate limited"}"#;
+        let result = filters.apply("search", ToolResult::success(raw.to_string()));
+        let ToolResult::Success { output, metadata } = result else { panic!("expected success") };
+        assert_eq!(output, raw);
+        assert!(metadata.is_none());
+
+        // non JSON outputs are kept as is
+        let result = filters.apply("search", ToolResult::success("plain text".to_string()));
+        assert!(matches!(result, ToolResult::Success { ref output, .. } if output == "plain text"));
+
+        // other tools and errors are untouched
+        let error = filters.apply("search", ToolResult::error("boom".to_string()));
+        assert!(error.is_error());
+        let other = filters.apply("bash", ToolResult::success(raw.to_string()));
+        assert!(matches!(other, ToolResult::Success { metadata: None, .. }));
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde_json::json;
+use thiserror::Error;
+
+use super::ToolResult;
+
+/// Metadata key of the ToolResult::Error returned when a write is refused by the disk quota
+/// Its value holds the quota details: `{"quota": "disk", "limit", "used", "requested"}`
+pub const QUOTA_EXCEEDED_METADATA: &str = "quota_exceeded";
+
+/// A write that would take a session over its disk quota
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("disk quota exceeded: {requested} more bytes requested, {used} of {limit} bytes already used")]
+pub struct QuotaExceeded {
+    pub limit: u64,
+    pub used: u64,
+    pub requested: u64,
+}
+
+impl QuotaExceeded {
+    /// Error result of a tool whose write was refused
+    pub fn to_tool_result(&self, operation: &str) -> ToolResult {
+        let details = json!({
+            "quota": "disk",
+            "limit": self.limit,
+            "used": self.used,
+            "requested": self.requested,
+        });
+        let metadata = HashMap::from([(QUOTA_EXCEEDED_METADATA.to_string(), details)]);
+        ToolResult::error_with_metadata(format!("{} failed: {}", operation, self), metadata)
+    }
+}
+
+/// Disk space a session may use, shared by its tools and by the server storing its artifacts
+///
+/// Usage is tracked incrementally from the writes the quota is charged with, the disk is never
+/// walked. Only writes going through the file tools are accounted (not files created by bash).
+#[derive(Debug)]
+pub struct DiskQuota {
+    limit: u64,
+    used: AtomicU64,
+}
+
+impl DiskQuota {
+    pub fn new(limit: u64) -> Self {
+        Self { limit, used: AtomicU64::new(0) }
+    }
+
+    /// Quota that only tracks usage
+    pub fn unlimited() -> Self {
+        Self::new(u64::MAX)
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    /// Charge `bytes` more, nothing is charged if it would go over the limit
+    pub fn try_charge(&self, bytes: u64) -> Result<(), QuotaExceeded> {
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| used.checked_add(bytes).filter(|total| *total <= self.limit))
+            .map(|_| ())
+            .map_err(|used| QuotaExceeded { limit: self.limit, used, requested: bytes })
+    }
+
+    /// Give back `bytes` (usage never goes below zero)
+    pub fn release(&self, bytes: u64) {
+        let _ = self.used.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| Some(used.saturating_sub(bytes)));
+    }
+
+    /// Charge a file going from `old_len` to `new_len` bytes, shrinking a file frees quota
+    pub fn try_resize(&self, old_len: u64, new_len: u64) -> Result<(), QuotaExceeded> {
+        if new_len >= old_len {
+            self.try_charge(new_len - old_len)
+        } else {
+            self.release(old_len - new_len);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_charge_and_release() {
+        let quota = DiskQuota::new(100);
+        quota.try_charge(60).unwrap();
+        assert_eq!(quota.try_charge(50), Err(QuotaExceeded { limit: 100, used: 60, requested: 50 }));
+        // a refused charge leaves the usage untouched
+        assert_eq!(quota.used(), 60);
+
+        quota.try_resize(60, 20).unwrap();
+        assert_eq!(quota.used(), 20);
+        quota.try_resize(0, 80).unwrap();
+        assert_eq!(quota.used(), 100);
+
+        quota.release(500);
+        assert_eq!(quota.used(), 0);
+    }
+
+    #[test]
+    fn test_tool_result_carries_details() {
+        let exceeded = QuotaExceeded { limit: 10, used: 8, requested: 5 };
+        let ToolResult::Error { error, metadata: Some(metadata) } = exceeded.to_tool_result("Write") else {
+            panic!("expected an error with metadata");
+        };
+        assert!(error.starts_with("Write failed: disk quota exceeded"));
+        assert_eq!(metadata[QUOTA_EXCEEDED_METADATA]["requested"], 5);
+    }
+}
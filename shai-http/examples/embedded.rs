@@ -0,0 +1,26 @@
+//! Mount the shai API inside an existing axum application under /ai
+//!
+//! cargo run -p shai-http --example embedded
+//! curl -N http://127.0.0.1:8080/ai/v1/chat/completions -d '{"model":"default","messages":[{"role":"user","content":"hello"}]}'
+
+use axum::{routing::get, Router};
+use shai_http::{build_router, ServerConfig, ServerState};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The host application owns global setup, shai_http::init_tracing() is opt-in
+    tracing_subscriber::fmt().init();
+
+    let config = ServerConfig::new("127.0.0.1:8080".to_string())
+        .with_ephemeral(true)
+        .with_max_sessions(Some(10));
+    let shai = build_router(ServerState::new(config));
+
+    let app = Router::new()
+        .route("/health", get(|| async { "ok" }))
+        .nest("/ai", shai);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
@@ -0,0 +1,11 @@
+-- Persisted sessions, soft-deleted ones keep their row with a deletion date
+CREATE TABLE IF NOT EXISTS sessions (
+    session_id TEXT PRIMARY KEY NOT NULL,
+    created_at DATETIME NOT NULL,
+    updated_at DATETIME NOT NULL,
+    trace BLOB NOT NULL,
+    metadata TEXT NOT NULL,
+    deleted_at DATETIME
+);
+
+CREATE INDEX IF NOT EXISTS sessions_deleted_at ON sessions (deleted_at);
@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use shai_core::config::agent::AgentConfig;
+use shai_core::config::config::ShaiConfig;
+use thiserror::Error;
+use tracing::warn;
+
+use crate::model::{ModelRegistry, MODEL_HEADER};
+use crate::rules::{bearer_token, wildcard_match};
+use crate::ErrorResponse;
+
+/// Error code of the requests naming an agent or model outside the key's allowance
+pub const NOT_ALLOWED_CODE: &str = "model_not_allowed";
+
+/// Agent name of requests that do not pick one
+pub const DEFAULT_AGENT: &str = "default";
+
+/// What the requests of one API key may run
+/// Agents are what requests name in their `model` field, models are the LLM models those agents run on
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyAllowance {
+    /// Agents the key may run, `*` globs (empty = any)
+    #[serde(default)]
+    pub allowed_agents: Vec<String>,
+    /// LLM models the agents of the key may run on, `*` globs (empty = any)
+    #[serde(default)]
+    pub allowed_models: Vec<String>,
+    /// Agent run by the requests naming `default`
+    #[serde(default)]
+    pub default_agent: Option<String>,
+}
+
+impl KeyAllowance {
+    pub fn allows_agent(&self, agent: &str) -> bool {
+        self.allowed_agents.is_empty() || self.allowed_agents.iter().any(|pattern| wildcard_match(pattern, agent))
+    }
+
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.allowed_models.is_empty() || self.allowed_models.iter().any(|pattern| wildcard_match(pattern, model))
+    }
+
+    /// Check an agent against the allowance, `model_of` gives the LLM model it runs on
+    /// A model override (X-Shai-Model) is the model checked in place of the agent's own
+    fn check(&self, agent: &str, model_override: Option<&str>, model_of: &impl Fn(&str) -> Option<String>) -> Result<(), ErrorResponse> {
+        if !self.allows_agent(agent) {
+            return Err(not_allowed(format!(
+                "This API key may not use '{}', allowed: {}",
+                agent, self.allowed_agents.join(", ")
+            )));
+        }
+        if self.allowed_models.is_empty() {
+            return Ok(());
+        }
+        if let Some(model) = model_override {
+            return match self.allows_model(model) {
+                true => Ok(()),
+                false => Err(not_allowed(format!(
+                    "The {} model '{}' is not allowed, this API key may only use: {}",
+                    MODEL_HEADER, model, self.allowed_models.join(", ")
+                ))),
+            };
+        }
+        match model_of(agent) {
+            Some(model) if self.allows_model(&model) => Ok(()),
+            Some(model) => Err(not_allowed(format!(
+                "'{}' runs on model '{}', this API key may only use: {}",
+                agent, model, self.allowed_models.join(", ")
+            ))),
+            None => Err(not_allowed(format!(
+                "The model of '{}' is unknown, this API key may only use: {}",
+                agent, self.allowed_models.join(", ")
+            ))),
+        }
+    }
+}
+
+fn not_allowed(message: String) -> ErrorResponse {
+    ErrorResponse::new(message, "forbidden".to_string(), Some(NOT_ALLOWED_CODE.to_string()))
+}
+
+/// Error loading the API key allowances
+#[derive(Debug, Error)]
+pub enum ApiKeysError {
+    #[error("failed to read {path}: {source}")]
+    Io { path: String, source: std::io::Error },
+    #[error("invalid API keys file {path}: {source}")]
+    Parse { path: String, source: serde_json::Error },
+}
+
+/// LLM model of each agent, read from its config on first use
+#[derive(Debug, Clone, Default)]
+pub struct AgentModels(Arc<RwLock<HashMap<String, Option<String>>>>);
+
+impl AgentModels {
+    fn get(&self, agent: &str) -> Option<String> {
+        if let Some(model) = self.0.read().unwrap().get(agent) {
+            return model.clone();
+        }
+        let model = agent_model(agent);
+        self.0.write().unwrap().insert(agent.to_string(), model.clone());
+        model
+    }
+
+    /// Forget the models read so far, the next requests read the agent configs again
+    pub fn clear(&self) {
+        self.0.write().unwrap().clear();
+    }
+}
+
+/// Allowances by API key (bearer token)
+/// Once any is configured, requests without a key or with a key that has no entry are refused
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ApiKeys {
+    #[serde(default)]
+    pub keys: HashMap<String, KeyAllowance>,
+    /// Models of the agents the allowances are checked against
+    #[serde(skip)]
+    pub agent_models: AgentModels,
+}
+
+impl ApiKeys {
+    /// Load the allowances from a JSON file: `{ "keys": { "sk-...": { "allowed_agents": [...] } } }`
+    pub fn from_file(path: &Path) -> Result<Self, ApiKeysError> {
+        let display = path.display().to_string();
+        let content = std::fs::read_to_string(path).map_err(|source| ApiKeysError::Io { path: display.clone(), source })?;
+        serde_json::from_str(&content).map_err(|source| ApiKeysError::Parse { path: display, source })
+    }
+
+    /// Allowance of the key a request was sent with (None = no allowance configured, unrestricted)
+    /// A missing key, or one without an entry, is refused once allowances are configured
+    pub fn allowance(&self, headers: &HeaderMap) -> Result<Option<&KeyAllowance>, ErrorResponse> {
+        if self.keys.is_empty() {
+            return Ok(None);
+        }
+        match bearer_token(headers).and_then(|key| self.keys.get(key)) {
+            Some(allowance) => Ok(Some(allowance)),
+            None => {
+                warn!("audit: request without an API key of the allowances refused");
+                Err(ErrorResponse::unauthorized("A valid API key is required".to_string()))
+            }
+        }
+    }
+
+    /// Resolve the agent a request runs (pinned default) and check it against the key's allowance
+    /// Runs after the transformation rules, so that rewritten models are the ones checked
+    /// Names of the model registry are checked against the model of their route, and the
+    /// X-Shai-Model header, when sent, against the models of the key in place of the agent's model
+    pub fn authorize(&self, headers: &HeaderMap, agent: &mut String, models: &ModelRegistry) -> Result<(), ErrorResponse> {
+        self.authorize_with(headers, agent, |name| self.routed_model(models, name))
+    }
+
+    fn authorize_with(
+        &self,
+        headers: &HeaderMap,
+        agent: &mut String,
+        model_of: impl Fn(&str) -> Option<String>,
+    ) -> Result<(), ErrorResponse> {
+        let Some(allowance) = self.allowance(headers)? else {
+            return Ok(());
+        };
+        if agent.is_empty() || agent == DEFAULT_AGENT {
+            if let Some(default_agent) = &allowance.default_agent {
+                *agent = default_agent.clone();
+            }
+        }
+        allowance.check(agent, model_override(headers), &model_of).inspect_err(|e| warn!("audit: {}", e.error.message))
+    }
+
+    /// Agents a request with these headers may run, out of `agents`
+    pub fn allowed_agents(&self, headers: &HeaderMap, agents: Vec<String>, models: &ModelRegistry) -> Vec<String> {
+        self.allowed_agents_with(headers, agents, |name| self.routed_model(models, name))
+    }
+
+    fn allowed_agents_with(
+        &self,
+        headers: &HeaderMap,
+        agents: Vec<String>,
+        model_of: impl Fn(&str) -> Option<String>,
+    ) -> Vec<String> {
+        let allowance = match self.allowance(headers) {
+            Ok(Some(allowance)) => allowance,
+            Ok(None) => return agents,
+            Err(_) => return Vec::new(),
+        };
+        agents
+            .into_iter()
+            .filter(|agent| {
+                // `default` stands for the pinned agent
+                let resolved = match (agent.as_str(), &allowance.default_agent) {
+                    (DEFAULT_AGENT, Some(default_agent)) => default_agent.as_str(),
+                    _ => agent.as_str(),
+                };
+                allowance.check(resolved, None, &model_of).is_ok()
+            })
+            .collect()
+    }
+
+    /// LLM model a registry name or agent runs on
+    fn routed_model(&self, models: &ModelRegistry, name: &str) -> Option<String> {
+        models.resolve(name).map(|route| route.model).or_else(|| self.agent_models.get(name))
+    }
+}
+
+/// Model of the X-Shai-Model header, as the request runs with it
+fn model_override(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(MODEL_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|model| !model.is_empty())
+}
+
+/// LLM model an agent runs on: its config, or the selected provider for the default agent
+pub fn agent_model(agent: &str) -> Option<String> {
+    if agent == DEFAULT_AGENT {
+        let config = ShaiConfig::load().unwrap_or_default();
+        return config.get_selected_provider().map(|provider| provider.model.clone());
+    }
+    AgentConfig::load(agent).ok().map(|config| config.llm_provider.model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{header::AUTHORIZATION, HeaderValue};
+
+    fn headers(api_key: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", api_key)).unwrap());
+        headers
+    }
+
+    fn model_of(agent: &str) -> Option<String> {
+        match agent {
+            "default" | "coder" => Some("gpt-4o-mini".to_string()),
+            "coder-pro" => Some("gpt-4o".to_string()),
+            "ops" => Some("claude-opus".to_string()),
+            _ => None,
+        }
+    }
+
+    fn api_keys() -> ApiKeys {
+        serde_json::from_value(serde_json::json!({ "keys": {
+            "sk-ci": { "allowed_agents": ["coder*"], "allowed_models": ["*-mini"], "default_agent": "coder" },
+            "sk-ops": { "allowed_agents": ["ops"] }
+        }})).unwrap()
+    }
+
+    #[test]
+    fn test_wildcard_allowances() {
+        let keys = api_keys();
+
+        let mut agent = "coder".to_string();
+        assert!(keys.authorize_with(&headers("sk-ci"), &mut agent, model_of).is_ok());
+
+        // the agent matches coder* but runs on a model outside *-mini
+        let mut agent = "coder-pro".to_string();
+        let error = keys.authorize_with(&headers("sk-ci"), &mut agent, model_of).unwrap_err();
+        assert_eq!(error.error.r#type, "forbidden");
+        assert_eq!(error.error.code.as_deref(), Some(NOT_ALLOWED_CODE));
+        assert!(error.error.message.contains("*-mini"));
+
+        // the error lists the permitted agents
+        let mut agent = "ops".to_string();
+        let error = keys.authorize_with(&headers("sk-ci"), &mut agent, model_of).unwrap_err();
+        assert!(error.error.message.contains("coder*"));
+
+        // once allowances are configured, keys without one are refused
+        let mut agent = "ops".to_string();
+        let error = keys.authorize_with(&headers("sk-other"), &mut agent, model_of).unwrap_err();
+        assert_eq!(error.error.r#type, "unauthorized");
+        assert!(keys.authorize_with(&HeaderMap::new(), &mut agent, model_of).is_err());
+
+        // without allowances, any request passes
+        assert!(ApiKeys::default().authorize_with(&HeaderMap::new(), &mut agent, model_of).is_ok());
+    }
+
+    #[test]
+    fn test_model_override_checked() {
+        let keys = api_keys();
+        let with_model = |model: &str| {
+            let mut headers = headers("sk-ci");
+            headers.insert(MODEL_HEADER, HeaderValue::from_str(model).unwrap());
+            headers
+        };
+
+        // coder runs on gpt-4o-mini, the header would switch it to gpt-4o
+        let mut agent = "coder".to_string();
+        let error = keys.authorize_with(&with_model("gpt-4o"), &mut agent, model_of).unwrap_err();
+        assert_eq!(error.error.code.as_deref(), Some(NOT_ALLOWED_CODE));
+        assert!(error.error.message.contains("gpt-4o"));
+
+        // the override is what runs, coder-pro on a mini model is allowed
+        let mut agent = "coder-pro".to_string();
+        assert!(keys.authorize_with(&with_model("o3-mini"), &mut agent, model_of).is_ok());
+    }
+
+    #[test]
+    fn test_pinned_default_agent() {
+        let keys = api_keys();
+
+        let mut agent = "default".to_string();
+        keys.authorize_with(&headers("sk-ci"), &mut agent, model_of).unwrap();
+        assert_eq!(agent, "coder");
+
+        // without a pinned agent, default must itself be allowed
+        let mut agent = "default".to_string();
+        assert!(keys.authorize_with(&headers("sk-ops"), &mut agent, model_of).is_err());
+        assert_eq!(agent, "default");
+    }
+
+    #[test]
+    fn test_listed_agents() {
+        let keys = api_keys();
+        let agents = || vec!["default".to_string(), "coder".to_string(), "coder-pro".to_string(), "ops".to_string()];
+
+        assert_eq!(keys.allowed_agents_with(&headers("sk-ci"), agents(), model_of), vec!["default", "coder"]);
+        assert_eq!(keys.allowed_agents_with(&headers("sk-ops"), agents(), model_of), vec!["ops"]);
+        assert!(keys.allowed_agents_with(&headers("sk-other"), agents(), model_of).is_empty());
+        assert_eq!(ApiKeys::default().allowed_agents_with(&headers("sk-other"), agents(), model_of).len(), 4);
+    }
+}
@@ -0,0 +1,196 @@
+use axum::{extract::{Path, Query, State}, http::HeaderMap, Json};
+use chrono::{DateTime, TimeDelta, Utc};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use shai_llm::breaker::{breaker_statuses, BreakerStatus};
+use tracing::{info, warn};
+
+use crate::apis::sessions::require_admin;
+use crate::replay::ReplayDescriptor;
+use crate::session::{IntegrityReport, PruneReport, SessionRetention, ToolAggregate};
+use crate::{ErrorResponse, ServerState};
+
+pub mod sessions;
+
+/// GET /v1/admin/artifacts/verify - Check the artifact store against its index
+/// Re-hashes every blob, reports corrupted, missing and orphaned artifacts (admin token required)
+pub async fn handle_verify_artifacts(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+) -> Result<Json<IntegrityReport>, ErrorResponse> {
+    require_admin(&state, &headers)?;
+    let Some(artifacts) = state.session_manager.artifacts().cloned() else {
+        return Err(ErrorResponse::invalid_request("Artifact store is not enabled or could not be opened".to_string()));
+    };
+
+    let report = tokio::task::spawn_blocking(move || artifacts.verify())
+        .await
+        .map_err(|e| ErrorResponse::internal_error(format!("Integrity check failed: {}", e)))?
+        .map_err(|e| ErrorResponse::internal_error(format!("Integrity check failed: {}", e)))?;
+
+    if report.is_healthy() {
+        info!("GET /v1/admin/artifacts/verify - {} artifacts checked", report.checked);
+    } else {
+        warn!(
+            "GET /v1/admin/artifacts/verify - {} corrupted, {} missing, {} orphaned",
+            report.corrupted.len(), report.missing.len(), report.orphaned.len()
+        );
+    }
+    Ok(Json(report))
+}
+
+/// GET /v1/admin/replays/{replay_id} - Replay descriptor of a request
+/// Includes the path of the recorded cassette when recording was on for that request (admin token required)
+pub async fn handle_get_replay(
+    State(state): State<ServerState>,
+    Path(replay_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<ReplayDescriptor>, ErrorResponse> {
+    info!("GET /v1/admin/replays/{}", replay_id);
+    require_admin(&state, &headers)?;
+    let Some(replays) = state.session_manager.replays().cloned() else {
+        return Err(ErrorResponse::internal_error("Replay store is not available".to_string()));
+    };
+
+    let id = replay_id.clone();
+    let descriptor = tokio::task::spawn_blocking(move || replays.get(&id))
+        .await
+        .map_err(|e| ErrorResponse::internal_error(format!("Failed to load replay: {}", e)))?
+        .map_err(|e| ErrorResponse::invalid_request(format!("Failed to load replay: {}", e)))?;
+
+    descriptor
+        .map(Json)
+        .ok_or_else(|| ErrorResponse::not_found(format!("Replay not found: {}", replay_id)))
+}
+
+/// Circuit breakers of the provider instances used by the server
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProviderList {
+    pub object: String,
+    pub data: Vec<BreakerStatus>,
+}
+
+/// GET /v1/admin/providers - Circuit breaker state of every provider instance
+/// Instances show up once a session used them (admin token required)
+pub async fn handle_list_providers(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+) -> Result<Json<ProviderList>, ErrorResponse> {
+    require_admin(&state, &headers)?;
+    let data = breaker_statuses();
+    info!("GET /v1/admin/providers - {} provider instances", data.len());
+    Ok(Json(ProviderList { object: "list".to_string(), data }))
+}
+
+/// Query of GET /v1/admin/tools/stats
+#[derive(Debug, Default, Deserialize)]
+pub struct ToolStatsQuery {
+    /// Start of the period: an RFC 3339 timestamp or a number of seconds ago (default: the whole window)
+    pub since: Option<String>,
+}
+
+/// Per-tool figures over a period of the rolling window
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ToolStatsList {
+    pub object: String,
+    pub since: Option<DateTime<Utc>>,
+    pub data: Vec<ToolAggregate>,
+}
+
+fn parse_since(since: &str) -> Option<DateTime<Utc>> {
+    if let Ok(secs) = since.parse::<i64>() {
+        return Some(Utc::now() - TimeDelta::seconds(secs.max(0)));
+    }
+    DateTime::parse_from_rfc3339(since).ok().map(|at| at.with_timezone(&Utc))
+}
+
+/// GET /v1/admin/tools/stats - Calls, outcomes, durations and output sizes by tool
+/// Computed from the tool calls of the last hour, without a metrics stack (admin token required)
+pub async fn handle_tool_stats(
+    State(state): State<ServerState>,
+    Query(query): Query<ToolStatsQuery>,
+    headers: HeaderMap,
+) -> Result<Json<ToolStatsList>, ErrorResponse> {
+    require_admin(&state, &headers)?;
+    let since = match query.since.as_deref() {
+        Some(since) => Some(parse_since(since).ok_or_else(|| {
+            ErrorResponse::invalid_request(format!("Invalid since '{}': expected an RFC 3339 timestamp or a number of seconds", since))
+        })?),
+        None => None,
+    };
+
+    let data = state.session_manager.tool_stats().aggregate(since);
+    info!("GET /v1/admin/tools/stats - {} tools", data.len());
+    Ok(Json(ToolStatsList { object: "list".to_string(), since, data }))
+}
+
+/// Request rules in effect after a reload
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RulesStatus {
+    pub object: String,
+    pub path: String,
+    pub rules: usize,
+}
+
+/// POST /v1/admin/rules/reload - Re-read the rules section of the server config file (admin token required)
+/// The models of the agents checked by the API key allowances are read again on their next use
+/// An invalid section is refused and the rules in effect are kept
+pub async fn handle_reload_rules(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+) -> Result<Json<RulesStatus>, ErrorResponse> {
+    info!("POST /v1/admin/rules/reload");
+    require_admin(&state, &headers)?;
+
+    let rule_set = state.rules.clone();
+    let rules = tokio::task::spawn_blocking(move || rule_set.reload())
+        .await
+        .map_err(|e| ErrorResponse::internal_error(format!("Failed to reload rules: {}", e)))?
+        .inspect_err(|e| warn!("POST /v1/admin/rules/reload - {}", e))?;
+    // the agent configs the key allowances are checked against are read again too
+    state.config.api_keys.agent_models.clear();
+
+    Ok(Json(RulesStatus {
+        object: "rules".to_string(),
+        path: state.rules.path().map(|path| path.display().to_string()).unwrap_or_default(),
+        rules,
+    }))
+}
+
+/// Query of POST /v1/admin/sessions/prune, the retention of the server is used when both are unset
+#[derive(Debug, Default, Deserialize)]
+pub struct PruneQuery {
+    /// Delete the sessions not saved for this many seconds
+    pub max_age_secs: Option<u64>,
+    /// Keep at most this many saved sessions, the most recently saved ones
+    pub max_sessions: Option<usize>,
+}
+
+/// POST /v1/admin/sessions/prune - Delete the saved sessions past a retention now (admin token required)
+/// The sessions loaded in memory are kept
+pub async fn handle_prune_sessions(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+    Query(query): Query<PruneQuery>,
+) -> Result<Json<PruneReport>, ErrorResponse> {
+    info!("POST /v1/admin/sessions/prune");
+    require_admin(&state, &headers)?;
+
+    let retention = match (query.max_age_secs, query.max_sessions) {
+        (None, None) => *state.session_manager.persisted_retention(),
+        (max_age_secs, max_sessions) => SessionRetention { max_age: max_age_secs.map(Duration::from_secs), max_sessions },
+    };
+    if retention.is_unlimited() {
+        return Err(ErrorResponse::invalid_request(
+            "No retention configured: set max_age_secs or max_sessions".to_string(),
+        ));
+    }
+
+    let report = state.session_manager.prune_persisted(&retention).await
+        .map_err(|e| ErrorResponse::internal_error(format!("Failed to prune sessions: {}", e)))?;
+    info!(
+        "POST /v1/admin/sessions/prune - {} of {} sessions deleted, {} active kept",
+        report.deleted.len(), report.scanned, report.skipped_active
+    );
+    Ok(Json(report))
+}
@@ -0,0 +1,134 @@
+use axum::{extract::{Path, Query, State}, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use shai_core::agent::PublicAgentState;
+use std::io;
+use std::time::Duration;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::session::{AgentSession, SessionData, SessionMetadata, SessionPersist};
+use crate::{ErrorResponse, ServerState};
+
+/// Sessions returned when `?limit` is not set
+const DEFAULT_SESSIONS_LIMIT: usize = 100;
+
+/// Query of GET /v1/sessions
+#[derive(Debug, Default, Deserialize)]
+pub struct ListSessionsQuery {
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub offset: usize,
+    /// Only the sessions of this user
+    pub user: Option<String>,
+    /// Only the sessions carrying this tag
+    pub tag: Option<String>,
+}
+
+/// A session loaded in memory, as listed by GET /v1/sessions
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub session_id: String,
+    pub created_at: DateTime<Utc>,
+    pub last_activity: DateTime<Utc>,
+    pub ephemeral: bool,
+    pub agent_name: String,
+    /// Messages in the agent trace, None while a request is running on the session
+    pub message_count: Option<usize>,
+    /// State of the agent: starting, running, processing, paused, completed, cancelled, failed
+    /// (None when the agent no longer answers)
+    pub state: Option<String>,
+    /// Requests run on the session since it was created
+    #[serde(default)]
+    pub requests_served: u64,
+    /// Seconds of inactivity left before the session is evicted (None = no idle TTL)
+    /// A session processing a request keeps its whole TTL
+    #[serde(default)]
+    pub ttl_remaining_secs: Option<u64>,
+    /// Requests waiting for the running one to end
+    #[serde(default)]
+    pub queued_requests: usize,
+    /// LLM tokens the session may still consume (None = no token budget)
+    #[serde(default)]
+    pub remaining_token_budget: Option<u64>,
+    /// User, tags and custom fields given by the clients
+    #[serde(default, skip_serializing_if = "SessionMetadata::is_empty")]
+    pub metadata: SessionMetadata,
+}
+
+impl SessionSummary {
+    async fn of(session: &AgentSession, ttl: Option<Duration>) -> Self {
+        let ttl_remaining = ttl.map(|ttl| match session.is_busy() {
+            true => ttl,
+            false => ttl.saturating_sub(session.idle_for()),
+        });
+        Self {
+            session_id: session.session_id.clone(),
+            created_at: session.created_at(),
+            last_activity: Utc::now() - session.idle_for(),
+            ephemeral: session.is_ephemeral(),
+            agent_name: session.agent_name.clone(),
+            message_count: session.message_count().await,
+            state: session.state().await.map(|state| state_name(&state).to_string()),
+            requests_served: session.requests_served(),
+            ttl_remaining_secs: ttl_remaining.map(|remaining| remaining.as_secs()),
+            queued_requests: session.queued_requests(),
+            remaining_token_budget: session.remaining_budget(),
+            metadata: session.metadata(),
+        }
+    }
+}
+
+fn state_name(state: &PublicAgentState) -> &'static str {
+    match state {
+        PublicAgentState::Starting => "starting",
+        PublicAgentState::Running => "running",
+        PublicAgentState::Processing { .. } => "processing",
+        PublicAgentState::Paused => "paused",
+        PublicAgentState::Completed { .. } => "completed",
+        PublicAgentState::Cancelled => "cancelled",
+        PublicAgentState::Failed { .. } => "failed",
+    }
+}
+
+/// GET /v1/sessions - Sessions loaded in memory, oldest first (`?limit=N&offset=M`)
+/// `?user=` and `?tag=` keep the sessions of a user or carrying a tag, before paging
+pub async fn handle_list_sessions(
+    State(state): State<ServerState>,
+    Query(query): Query<ListSessionsQuery>,
+) -> Json<Vec<SessionSummary>> {
+    let http_request_id = Uuid::new_v4();
+    let sessions = state.session_manager.list_sessions().await;
+    info!("[{}] GET /v1/sessions - {} sessions", http_request_id, sessions.len());
+
+    // the agents are asked for their state at the same time, each one may take up to a second to answer
+    let page = sessions
+        .iter()
+        .filter(|session| session.metadata().matches(query.user.as_deref(), query.tag.as_deref()))
+        .skip(query.offset).take(query.limit.unwrap_or(DEFAULT_SESSIONS_LIMIT));
+    let summaries = page.map(|session| SessionSummary::of(session, state.session_manager.effective_ttl(session)));
+    Json(futures::future::join_all(summaries).await)
+}
+
+/// GET /v1/sessions/{session_id}/data - Session as persisted in storage (trace, input items, run summaries)
+/// Environment values are hidden, like in the other session endpoints
+pub async fn handle_get_session_data(
+    State(state): State<ServerState>,
+    Path(session_id): Path<String>,
+) -> Result<Json<SessionData>, ErrorResponse> {
+    let http_request_id = Uuid::new_v4();
+    info!("[{}] GET /v1/sessions/{}/data", http_request_id, session_id);
+
+    if !SessionPersist::is_enabled() {
+        return Err(ErrorResponse::invalid_request("Session persistence is not enabled on this server".to_string()));
+    }
+
+    let mut data = SessionPersist::load_session_from(&*state.session_store, &session_id).await.map_err(|e| {
+        match e.downcast_ref::<io::Error>().map(io::Error::kind) {
+            Some(io::ErrorKind::NotFound) => ErrorResponse::not_found(format!("Session not found: {}", session_id)),
+            _ => ErrorResponse::internal_error(format!("Failed to load session: {}", e)),
+        }
+    })?;
+    data.attributes.env.values_mut().for_each(|value| *value = "***".to_string());
+    Ok(Json(data))
+}
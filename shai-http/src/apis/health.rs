@@ -0,0 +1,146 @@
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use shai_core::config::config::ShaiConfig;
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::ServerState;
+
+/// Default fraction of max_sessions above which the server reports not ready
+const DEFAULT_CAPACITY_THRESHOLD: f64 = 0.95;
+
+/// Upper bound on the provider check so a slow provider cannot hang the probe
+const PROVIDER_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Capacity threshold from SHAI_READY_CAPACITY_THRESHOLD (0.0-1.0)
+fn capacity_threshold() -> f64 {
+    std::env::var("SHAI_READY_CAPACITY_THRESHOLD")
+        .ok()
+        .and_then(|v| v.parse::<f64>().ok())
+        .filter(|v| (0.0..=1.0).contains(v))
+        .unwrap_or(DEFAULT_CAPACITY_THRESHOLD)
+}
+
+fn not_ready(reason: String) -> Response {
+    warn!("GET /v1/ready - not ready: {}", reason);
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({ "ready": false, "reason": reason })),
+    )
+        .into_response()
+}
+
+/// Outcome of the LLM provider check of the readiness probe, reused for a TTL so that frequent
+/// probes do not each call the provider
+#[derive(Debug, Default)]
+pub struct ProviderCheck {
+    cached: Mutex<Option<(Instant, Result<(), String>)>>,
+}
+
+impl ProviderCheck {
+    /// Cached outcome when younger than `ttl` (None = always checked), failures included
+    /// Concurrent probes wait for the same check
+    pub async fn get(&self, ttl: Option<Duration>) -> Result<(), String> {
+        let mut cached = self.cached.lock().await;
+        if let (Some((checked_at, outcome)), Some(ttl)) = (cached.as_ref(), ttl) {
+            if checked_at.elapsed() < ttl {
+                return outcome.clone();
+            }
+        }
+
+        let outcome = Self::check().await;
+        *cached = Some((Instant::now(), outcome.clone()));
+        outcome
+    }
+
+    /// The provider of the shai config lists its models, the error is the reason it is not ready
+    async fn check() -> Result<(), String> {
+        let llm = match ShaiConfig::get_llm().await {
+            Ok((llm, _model)) => llm,
+            Err(e) => return Err(format!("no LLM provider configured: {}", e)),
+        };
+
+        match tokio::time::timeout(PROVIDER_CHECK_TIMEOUT, llm.models()).await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(e)) => Err(format!("LLM provider unavailable: {}", e)),
+            Err(_) => Err("LLM provider check timed out".to_string()),
+        }
+    }
+}
+
+/// GET /v1/ready - Readiness probe
+/// Not ready when the session manager is near capacity or the LLM provider cannot list its models,
+/// the provider check is cached for `ready_cache_ttl`
+pub async fn handle_ready(State(state): State<ServerState>) -> Response {
+    if let Some(max) = state.session_manager.max_sessions() {
+        let count = state.session_manager.session_count().await;
+        let threshold = capacity_threshold();
+        if count as f64 >= max as f64 * threshold {
+            return not_ready(format!("session capacity reached ({}/{})", count, max));
+        }
+    }
+
+    if let Err(reason) = state.provider_check.get(state.config.ready_cache_ttl).await {
+        return not_ready(reason);
+    }
+
+    debug!("GET /v1/ready - ready");
+    Json(json!({ "ready": true })).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::SessionStoreConfig;
+    use crate::http::DEFAULT_READY_CACHE_TTL;
+    use crate::ServerConfig;
+
+    fn state(folder: &std::path::Path) -> ServerState {
+        let store = SessionStoreConfig::File { folder: folder.to_path_buf(), compression: None, encryption: None };
+        ServerState::new(ServerConfig::new("127.0.0.1:0".to_string()).with_session_store(store))
+    }
+
+    async fn ready(state: &ServerState) -> (StatusCode, serde_json::Value) {
+        let response = handle_ready(State(state.clone())).await;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_provider_check_cached_for_ttl() {
+        let check = ProviderCheck::default();
+        *check.cached.lock().await = Some((Instant::now(), Err("LLM provider unavailable: down".to_string())));
+
+        // the failure is reused, the provider is not called again
+        let outcome = check.get(Some(Duration::from_secs(60))).await;
+        assert_eq!(outcome, Err("LLM provider unavailable: down".to_string()));
+
+        *check.cached.lock().await = Some((Instant::now(), Ok(())));
+        assert_eq!(check.get(Some(Duration::from_secs(60))).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_ready_reports_the_provider_check() {
+        let folder = tempfile::tempdir().unwrap();
+        let state = state(folder.path());
+        assert_eq!(state.config.ready_cache_ttl, Some(DEFAULT_READY_CACHE_TTL));
+
+        *state.provider_check.cached.lock().await = Some((Instant::now(), Ok(())));
+        let (status, body) = ready(&state).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["ready"], true);
+
+        *state.provider_check.cached.lock().await = Some((Instant::now(), Err("LLM provider check timed out".to_string())));
+        let (status, body) = ready(&state).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["reason"], "LLM provider check timed out");
+    }
+}
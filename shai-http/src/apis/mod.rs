@@ -1,2 +1,6 @@
+pub mod admin;
+pub mod health;
+pub mod sessions;
 pub mod simple;
-pub mod openai;
\ No newline at end of file
+pub mod openai;
+pub mod websocket;
\ No newline at end of file
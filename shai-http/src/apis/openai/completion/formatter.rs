@@ -1,20 +1,37 @@
+use std::collections::{HashMap, HashSet};
+
 use async_trait::async_trait;
+use axum::response::sse::Event;
 use openai_dive::v1::resources::chat::{
     ChatCompletionChunkResponse, ChatCompletionChunkChoice, DeltaChatMessage,
-    ChatMessageContent, ChatMessage,
+    ChatMessageContent, ChatMessage, DeltaFunction, DeltaToolCall,
 };
 use openai_dive::v1::resources::shared::FinishReason;
 use shai_core::agent::AgentEvent;
 use uuid::Uuid;
 
-use crate::streaming::EventFormatter;
+use crate::run::RunUsage;
+use crate::streaming::{EventFormatter, DONE_SENTINEL};
 
 /// Formatter for OpenAI Chat Completion API (streaming)
-/// Tool calls are converted to "thinking" reasoning_content deltas
+/// The first chunk carries the assistant role, tool calls are streamed both as `tool_calls`
+/// deltas and as "thinking" reasoning_content deltas, and a successful stream ends on `[DONE]`
+/// The final chunk carries the token usage of the whole run
 pub struct ChatCompletionFormatter {
     pub model: String,
     pub created: u32,
+    /// Shared by every chunk of the stream
+    id: String,
     accumulated_text: String,
+    role_sent: bool,
+    /// Position of each tool call in the stream, by call id
+    tool_call_indexes: HashMap<String, u32>,
+    /// Calls whose arguments were already streamed as `tool_calls` deltas
+    streamed_calls: HashSet<String>,
+    /// Token usage summed over the brain iterations
+    usage: RunUsage,
+    /// End the stream on `finish_reason: tool_calls` when the agent ran tools
+    expose_tools: bool,
 }
 
 impl ChatCompletionFormatter {
@@ -27,13 +44,44 @@ impl ChatCompletionFormatter {
         Self {
             model,
             created,
+            id: format!("chatcmpl-{}", Uuid::new_v4()),
             accumulated_text: String::new(),
+            role_sent: false,
+            tool_call_indexes: HashMap::new(),
+            streamed_calls: HashSet::new(),
+            usage: RunUsage::default(),
+            expose_tools: false,
+        }
+    }
+
+    pub fn with_expose_tools(mut self, expose_tools: bool) -> Self {
+        self.expose_tools = expose_tools;
+        self
+    }
+
+    /// Index of a tool call in the stream, assigned on first sight
+    fn tool_call_index(&mut self, call_id: &str) -> u32 {
+        let next = self.tool_call_indexes.len() as u32;
+        *self.tool_call_indexes.entry(call_id.to_string()).or_insert(next)
+    }
+
+    /// `tool_calls` delta of one call: id and name come with its first fragment only
+    fn tool_call_delta(&mut self, call_id: &str, name: &str, arguments: &str) -> DeltaToolCall {
+        let first = !self.tool_call_indexes.contains_key(call_id);
+        DeltaToolCall {
+            index: Some(self.tool_call_index(call_id)),
+            id: first.then(|| call_id.to_string()),
+            r#type: first.then(|| "function".to_string()),
+            function: DeltaFunction {
+                name: first.then(|| name.to_string()),
+                arguments: Some(arguments.to_string()),
+            },
         }
     }
 
     fn create_chunk(&self, delta: DeltaChatMessage, finish_reason: Option<FinishReason>) -> ChatCompletionChunkResponse {
         ChatCompletionChunkResponse {
-            id: Some(format!("chatcmpl-{}", Uuid::new_v4())),
+            id: Some(self.id.clone()),
             object: "chat.completion.chunk".to_string(),
             created: self.created,
             model: self.model.clone(),
@@ -53,6 +101,34 @@ impl ChatCompletionFormatter {
 impl EventFormatter for ChatCompletionFormatter {
     type Output = ChatCompletionChunkResponse;
 
+    /// The first chunk of the stream only announces the assistant role
+    async fn format_events(
+        &mut self,
+        event: AgentEvent,
+        session_id: &str,
+    ) -> Vec<Self::Output> {
+        let Some(chunk) = self.format_event(event, session_id).await else {
+            return Vec::new();
+        };
+        if self.role_sent {
+            return vec![chunk];
+        }
+
+        self.role_sent = true;
+        let role = DeltaChatMessage::Assistant {
+            content: Some(ChatMessageContent::Text(String::new())),
+            reasoning_content: None,
+            refusal: None,
+            name: None,
+            tool_calls: None,
+        };
+        vec![self.create_chunk(role, None), chunk]
+    }
+
+    fn format_done(&mut self) -> Option<Event> {
+        Some(Event::default().data(DONE_SENTINEL))
+    }
+
     async fn format_event(
         &mut self,
         event: AgentEvent,
@@ -73,29 +149,40 @@ impl EventFormatter for ChatCompletionFormatter {
                         }
                         None
                     }
-                    Err(err) => {
-                        // Stream error as assistant message
-                        let delta = DeltaChatMessage::Assistant {
-                            content: Some(ChatMessageContent::Text(format!("Error: {}", err))),
-                            reasoning_content: None,
-                            refusal: None,
-                            name: None,
-                            tool_calls: None,
-                        };
-                        Some(self.create_chunk(delta, Some(FinishReason::StopSequenceReached)))
-                    }
+                    // Errors end the run, they are sent as the stream's error frame
+                    Err(_) => None,
                 }
             }
 
-            // Tool call started - stream as thinking delta
+            // Arguments generated by the model - stream as tool_calls delta
+            AgentEvent::ToolCallArgumentsDelta { call_id, tool_name, delta, .. } => {
+                self.streamed_calls.insert(call_id.clone());
+                let tool_call = self.tool_call_delta(&call_id, &tool_name, &delta);
+                let delta = DeltaChatMessage::Assistant {
+                    content: None,
+                    reasoning_content: None,
+                    refusal: None,
+                    name: None,
+                    tool_calls: Some(vec![tool_call]),
+                };
+
+                Some(self.create_chunk(delta, None))
+            }
+
+            // Tool call started - stream as tool_calls and thinking delta
             AgentEvent::ToolCallStarted { call, .. } => {
                 let thinking_text = format!("[toolcall: {}]", call.tool_name);
+                // streamed arguments are not repeated
+                let tool_calls = match self.streamed_calls.contains(&call.tool_call_id) {
+                    true => None,
+                    false => Some(vec![self.tool_call_delta(&call.tool_call_id, &call.tool_name, &call.parameters.to_string())]),
+                };
                 let delta = DeltaChatMessage::Assistant {
                     content: None,
                     reasoning_content: Some(thinking_text),
                     refusal: None,
                     name: None,
-                    tool_calls: None,
+                    tool_calls,
                 };
 
                 Some(self.create_chunk(delta, None))
@@ -144,24 +231,20 @@ impl EventFormatter for ChatCompletionFormatter {
                     tool_calls: None,
                 };
 
-                // Always use StopSequenceReached for completion
-                // Success/failure is indicated in the content
-                let finish_reason = Some(FinishReason::StopSequenceReached);
+                // Success/failure is indicated in the content, tool_calls only when they are exposed
+                let finish_reason = match self.expose_tools && !self.tool_call_indexes.is_empty() {
+                    true => Some(FinishReason::ToolCalls),
+                    false => Some(FinishReason::StopSequenceReached),
+                };
 
-                Some(self.create_chunk(content_delta, finish_reason))
+                let mut chunk = self.create_chunk(content_delta, finish_reason);
+                chunk.usage = Some(self.usage.into());
+                Some(chunk)
             }
 
-            AgentEvent::Error { error } => {
-                // Stream error as content delta
-                let delta = DeltaChatMessage::Assistant {
-                    content: Some(ChatMessageContent::Text(format!("Error: {}", error))),
-                    reasoning_content: None,
-                    refusal: None,
-                    name: None,
-                    tool_calls: None,
-                };
-
-                Some(self.create_chunk(delta, Some(FinishReason::StopSequenceReached)))
+            AgentEvent::TokenUsage { input_tokens, output_tokens } => {
+                self.usage.add(input_tokens, output_tokens);
+                None
             }
 
             _ => None,
@@ -1,39 +1,117 @@
 use axum::{
-    extract::State,
+    extract::{Extension, Query, State},
+    http::{HeaderMap, HeaderValue},
     response::{IntoResponse, Response, Sse, Json},
 };
-use futures::StreamExt;
 use openai_dive::v1::resources::chat::{
-    ChatCompletionParameters, ChatCompletionResponse, ChatCompletionChoice,
-    ChatMessage, ChatMessageContent,
+    ChatCompletionParameters, ChatCompletionResponse, ChatCompletionChoice, ChatCompletionResponseFormat,
+    ChatCompletionToolChoice, ChatMessage, ChatMessageContent, Function, ToolCall,
 };
-use openai_dive::v1::resources::shared::{Usage, FinishReason};
-use shai_core::agent::AgentEvent;
-use tokio_stream::wrappers::BroadcastStream;
-use tracing::info;
+use openai_dive::v1::resources::shared::{FinishReason, StopToken};
+use futures::{stream, StreamExt, TryStreamExt};
+use serde::Deserialize;
+use shai_core::agent::{is_json_format, json_reply, SamplingOverrides};
+use shai_core::tools::ToolResult;
+use tracing::{info, warn};
 use uuid::Uuid;
 
 use super::formatter::ChatCompletionFormatter;
-use crate::{ApiJson, ServerState, ErrorResponse, session_to_sse_stream};
+use crate::{ApiJson, ModelOverride, ModelRoute, ServerState, ErrorResponse, OpenAiError, run_to_sse_stream};
+use crate::run::{run_agent_collect, AgentRun, RunOutcome, RunUsage};
+use crate::keepalive::padded_json_response;
+use crate::replay::{ReplayDescriptor, SamplingParams, REPLAY_ID_HEADER};
+use crate::apis::openai::user::validate_user;
+use crate::features::{parse_state, Features};
+use crate::rules::RuleRoute;
+use crate::session::{RequestOverrides, SessionAttributes};
+
+/// Request header returning the tool calls run by the agent in the assistant message
+pub const EXPOSE_TOOLS_HEADER: &str = "x-shai-expose-tools";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ChatCompletionQuery {
+    /// Same as the x-shai-expose-tools header
+    #[serde(default)]
+    pub expose_tools: bool,
+}
+
+/// Whether the tool calls run by the agent are returned as `tool_calls` (header or query parameter)
+fn expose_tools(headers: &HeaderMap, query: &ChatCompletionQuery) -> bool {
+    query.expose_tools
+        || headers
+            .get(EXPOSE_TOOLS_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_state)
+            .unwrap_or(false)
+}
 
 /// Handle OpenAI chat completion - supports both streaming and non-streaming
+/// `tool_choice: "none"` runs the agent without its tools
+/// A JSON `response_format` is sent to the providers supporting it and asked for in the prompt otherwise,
+/// the agent asks for a reply that is not valid JSON again once, non-streamed replies still not valid fail
 pub async fn handle_chat_completion(
     State(state): State<ServerState>,
-    ApiJson(payload): ApiJson<ChatCompletionParameters>,
-) -> Result<Response, ErrorResponse> {
+    Extension(features): Extension<Features>,
+    Query(query): Query<ChatCompletionQuery>,
+    ModelOverride(model_override): ModelOverride,
+    headers: HeaderMap,
+    payload: Result<ApiJson<ChatCompletionParameters>, ErrorResponse>,
+) -> Result<Response, OpenAiError> {
+    let ApiJson(mut payload) = payload?;
     let request_id = Uuid::new_v4();
     let session_id = Uuid::new_v4().to_string();
 
+    // Compatibility rules run before anything reads the parameters
+    state.rules.apply(RuleRoute::ChatCompletions, &request_id.to_string(), &headers, &mut payload)?;
+    // Checked after the rules so that rewritten models are the ones allowed
+    state.config.api_keys.authorize(&headers, &mut payload.model, &state.config.models)?;
+    let (agent_name, route) = state.config.models.route(&payload.model)?;
+
     let is_streaming = payload.stream.unwrap_or(false);
-    info!("[{}] POST /v1/chat/completions model={} stream={} (ephemeral)",
-        request_id, payload.model, is_streaming);
+    let n = payload.n.unwrap_or(1);
+    if n > state.config.max_choices {
+        return Err(ErrorResponse::invalid_request(format!("n must be at most {}", state.config.max_choices))
+            .with_param("n")
+            .into());
+    }
+    if n > 1 && is_streaming {
+        return Err(ErrorResponse::not_implemented("Streamed chat completions have a single choice (n=1)".to_string())
+            .with_param("n")
+            .into());
+    }
+    let user = validate_user(payload.user.as_deref())?;
+    let expose_tools = expose_tools(&headers, &query);
+    info!("[{}] POST /v1/chat/completions model={} llm_model={} stream={} user={} expose_tools={} (ephemeral)",
+        request_id, payload.model, model_override.as_deref().unwrap_or("-"), is_streaming, user.as_deref().unwrap_or("-"), expose_tools);
+
+    // Replay descriptor, its id is returned in the x-shai-replay-id header
+    let replay = ReplayDescriptor::capture(
+        "chat.completions",
+        &request_id.to_string(),
+        &session_id,
+        &payload.model,
+        &build_message_trace(&payload),
+        SamplingParams {
+            temperature: payload.temperature,
+            top_p: payload.top_p,
+            max_tokens: payload.max_completion_tokens,
+        },
+    ).await;
+    state.session_manager.record_replay(&replay);
+
+    let mut attributes = session_attributes(&payload, features, agent_name, route, model_override);
+    attributes.overrides.owner = state.config.auth.session_owner(&headers);
 
     // Check if streaming is requested
-    if is_streaming {
-        handle_chat_completion_stream(state, payload, request_id, session_id).await
+    let mut response = if is_streaming {
+        handle_chat_completion_stream(state, payload, request_id, session_id, features, attributes, expose_tools).await?
     } else {
-        handle_chat_completion_non_stream(state, payload, request_id, session_id).await
+        handle_chat_completion_non_stream(state, payload, request_id, session_id, features, attributes, expose_tools).await?
+    };
+    if let Ok(value) = HeaderValue::from_str(&replay.id) {
+        response.headers_mut().insert(REPLAY_ID_HEADER, value);
     }
+    Ok(response)
 }
 
 /// Handle streaming chat completion
@@ -42,113 +120,99 @@ async fn handle_chat_completion_stream(
     payload: ChatCompletionParameters,
     request_id: Uuid,
     session_id: String,
+    features: Features,
+    attributes: SessionAttributes,
+    expose_tools: bool,
 ) -> Result<Response, ErrorResponse> {
     let trace = build_message_trace(&payload);
     let model = payload.model.clone();
 
     // Create ephemeral session
     let agent_session = state.session_manager
-        .create_new_session(&request_id.to_string(), &session_id, Some(model.clone()), true)
+        .create_new_session_with(&request_id.to_string(), &session_id, attributes.agent_name.clone(), true, attributes)
         .await
-        .map_err(|e| ErrorResponse::internal_error(format!("Failed to create session: {}", e)))?;
+        .map_err(ErrorResponse::session_not_created)?;
+    agent_session.set_user(payload.user.clone());
 
     // Create request session
     let request_session = agent_session
-        .handle_request(&request_id.to_string(), trace)
+        .handle_request(&request_id.to_string(), trace, features)
         .await
-        .map_err(|e| ErrorResponse::internal_error(format!("Failed to handle request: {}", e)))?;
+        .map_err(ErrorResponse::request_failed)?;
 
     // Create the formatter for OpenAI Chat Completion API
-    let formatter = ChatCompletionFormatter::new(model);
+    let formatter = ChatCompletionFormatter::new(model).with_expose_tools(expose_tools);
 
-    // Create SSE stream
-    let stream = session_to_sse_stream(request_session, formatter, session_id, true);
+    // Create SSE stream, stopping the agent if the client disconnects
+    let options = state.config.run_options().with_cancel_on_disconnect(true);
+    let run = AgentRun::new(request_session, session_id.clone(), options);
+    let stream = run_to_sse_stream(run, formatter, session_id);
 
     Ok(Sse::new(stream).into_response())
 }
 
 /// Handle non-streaming chat completion
-/// Directly processes events and returns a single complete response
+/// Optionally pads the body with whitespace while the agent runs (see ServerConfig::keepalive_padding)
 async fn handle_chat_completion_non_stream(
     state: ServerState,
     payload: ChatCompletionParameters,
     request_id: Uuid,
     session_id: String,
+    features: Features,
+    attributes: SessionAttributes,
+    expose_tools: bool,
 ) -> Result<Response, ErrorResponse> {
-    let trace = build_message_trace(&payload);
-
-    // Create ephemeral session
-    let agent_session = state.session_manager
-        .create_new_session(&request_id.to_string(), &session_id, Some(payload.model.clone()), true)
-        .await
-        .map_err(|e| ErrorResponse::internal_error(format!("Failed to create session: {}", e)))?;
+    match state.config.keepalive_padding {
+        Some(interval) => {
+            let work = collect_chat_completion(state, payload, request_id, session_id, features, attributes, expose_tools);
+            Ok(padded_json_response(interval, work))
+        }
+        None => {
+            let response = collect_chat_completion(state, payload, request_id, session_id, features, attributes, expose_tools).await?;
+            Ok(Json(response).into_response())
+        }
+    }
+}
 
-    // Send messages and get event stream
-    let request_session = agent_session
-        .handle_request(&request_id.to_string(), trace)
-        .await
-        .map_err(|e| ErrorResponse::internal_error(format!("Failed to handle request: {}", e)))?;
-
-    // Collect events - accumulate both content and reasoning (tool calls)
-    let mut event_stream = BroadcastStream::new(request_session.event_rx);
-    let mut final_message = String::new();
-    let mut reasoning_steps = Vec::new();
-
-    while let Some(result) = event_stream.next().await {
-        match result {
-            Ok(event) => {
-                // Check if this is a terminal event
-                let is_terminal = matches!(
-                    event,
-                    AgentEvent::Completed { .. }
-                        | AgentEvent::StatusChanged {
-                            new_status: shai_core::agent::PublicAgentState::Paused,
-                            ..
-                        }
-                );
-
-                match event {
-                    AgentEvent::Completed { message, .. } => {
-                        final_message = message;
-                    }
-                    AgentEvent::BrainResult { thought, .. } => {
-                        if let Ok(msg) = thought {
-                            if let ChatMessage::Assistant {
-                                content: Some(ChatMessageContent::Text(text)),
-                                ..
-                            } = msg
-                            {
-                                final_message = text;
-                            }
-                        }
-                    }
-                    AgentEvent::ToolCallStarted { call, .. } => {
-                        reasoning_steps.push(format!("[toolcall: {}]", call.tool_name));
-                    }
-                    AgentEvent::ToolCallCompleted { call, result: tool_result, .. } => {
-                        use shai_core::tools::ToolResult;
-                        let step = match &tool_result {
-                            ToolResult::Success { .. } => format!("[tool succeeded: {}]", call.tool_name),
-                            ToolResult::Error { error, .. } => {
-                                let error_oneline = error.lines().next().unwrap_or(error);
-                                format!("[tool failed: {} - {}]", call.tool_name, error_oneline)
-                            }
-                            ToolResult::Denied => format!("[tool denied: {}]", call.tool_name),
-                        };
-                        reasoning_steps.push(step);
-                    }
-                    _ => {}
-                }
+/// Directly processes events and returns a single complete response
+/// With `n` > 1, one agent per choice runs on the same trace, at most `choices_concurrency` at a time
+async fn collect_chat_completion(
+    state: ServerState,
+    payload: ChatCompletionParameters,
+    request_id: Uuid,
+    session_id: String,
+    features: Features,
+    attributes: SessionAttributes,
+    expose_tools: bool,
+) -> Result<ChatCompletionResponse, ErrorResponse> {
+    let n = payload.n.unwrap_or(1).max(1);
+    let choices: Vec<_> = stream::iter(0..n)
+        .map(|index| {
+            // the first choice keeps the session id of the request
+            let session_id = match index {
+                0 => session_id.clone(),
+                _ => Uuid::new_v4().to_string(),
+            };
+            collect_choice(&state, &payload, request_id, session_id, features, attributes.clone(), expose_tools)
+        })
+        .buffered(state.config.choices_concurrency.max(1))
+        .try_collect()
+        .await?;
 
-                if is_terminal {
-                    break;
-                }
+    let mut usage = RunUsage::default();
+    let choices = choices
+        .into_iter()
+        .enumerate()
+        .map(|(index, (message, finish_reason, choice_usage))| {
+            usage.add(choice_usage.input_tokens, choice_usage.output_tokens);
+            ChatCompletionChoice {
+                index: index as u32,
+                message,
+                finish_reason: Some(finish_reason),
+                logprobs: None,
             }
-            Err(e) => {
-                return Err(ErrorResponse::internal_error(format!("Event stream error: {}", e)));
-            }
-        }
-    }
+        })
+        .collect();
 
     // Build OpenAI-compatible response
     let response = ChatCompletionResponse {
@@ -159,39 +223,186 @@ async fn handle_chat_completion_non_stream(
             .unwrap()
             .as_secs() as u32,
         model: payload.model.clone(),
-        choices: vec![ChatCompletionChoice {
-            index: 0,
-            message: ChatMessage::Assistant {
-                content: Some(ChatMessageContent::Text(final_message)),
-                name: None,
-                tool_calls: None,
-                audio: None,
-                reasoning_content: if reasoning_steps.is_empty() {
-                    None
-                } else {
-                    Some(reasoning_steps.join("\n"))
-                },
-                refusal: None,
-            },
-            finish_reason: Some(FinishReason::StopSequenceReached),
-            logprobs: None,
-        }],
-        usage: Some(Usage {
-            input_tokens: None,
-            input_tokens_details: None,
-            output_tokens: None,
-            output_tokens_details: None,
-            prompt_tokens: Some(0),
-            completion_tokens: Some(0),
-            total_tokens: 0,
-            completion_tokens_details: None,
-            prompt_tokens_details: None,
-        }),
+        choices,
+        usage: Some(usage.into()),
         system_fingerprint: None,
         service_tier: None,
     };
 
-    Ok(Json(response).into_response())
+    Ok(response)
+}
+
+/// Run one agent on the messages of the request, returns its final message, finish reason and usage
+/// With a JSON `response_format`, a reply that is still not valid JSON once the agent asked for it again fails
+async fn collect_choice(
+    state: &ServerState,
+    payload: &ChatCompletionParameters,
+    request_id: Uuid,
+    session_id: String,
+    features: Features,
+    attributes: SessionAttributes,
+    expose_tools: bool,
+) -> Result<(ChatMessage, FinishReason, RunUsage), ErrorResponse> {
+    let trace = build_message_trace(payload);
+    let outcome = run_choice(state, request_id, session_id, payload.user.clone(), features, attributes, trace).await?;
+    let mut outcome = match json_format(payload) {
+        Some(format) => ensure_json(format, outcome)?,
+        None => outcome,
+    };
+
+    let usage = outcome.usage;
+    // the provider may not enforce the stop sequences it was sent (e.g. some Ollama models)
+    let stopped = truncate_at_stop(&mut outcome.final_message, &stop_sequences(payload));
+    let (message, finish_reason) = assistant_message(outcome, expose_tools);
+    let finish_reason = if stopped { FinishReason::StopSequenceReached } else { finish_reason };
+    Ok((message, finish_reason, usage))
+}
+
+/// Run the agent of an ephemeral session on `trace` until it is done
+async fn run_choice(
+    state: &ServerState,
+    request_id: Uuid,
+    session_id: String,
+    user: Option<String>,
+    features: Features,
+    attributes: SessionAttributes,
+    trace: Vec<ChatMessage>,
+) -> Result<RunOutcome, ErrorResponse> {
+    // Create ephemeral session
+    let agent_session = state.session_manager
+        .create_new_session_with(&request_id.to_string(), &session_id, attributes.agent_name.clone(), true, attributes)
+        .await
+        .map_err(ErrorResponse::session_not_created)?;
+    agent_session.set_user(user);
+
+    // Send messages and get event stream
+    let request_session = agent_session
+        .handle_request(&request_id.to_string(), trace, features)
+        .await
+        .map_err(ErrorResponse::request_failed)?;
+
+    let options = state.config.run_options().with_cancel_on_disconnect(true);
+    let outcome = run_agent_collect(request_session, session_id, options).await;
+    match outcome.to_error() {
+        Some(error) => Err(error),
+        None => Ok(outcome),
+    }
+}
+
+/// JSON format the request asks the replies in, None for plain text
+fn json_format(payload: &ChatCompletionParameters) -> Option<&ChatCompletionResponseFormat> {
+    payload.response_format.as_ref().filter(|format| is_json_format(format))
+}
+
+/// Check the final message of a run against a JSON format, the JSON is taken out of a code fence
+/// or the text around it, the agent already asked for it again once when it was not valid
+fn ensure_json(format: &ChatCompletionResponseFormat, mut outcome: RunOutcome) -> Result<RunOutcome, ErrorResponse> {
+    match json_reply(&outcome.final_message, format) {
+        Some(json) => {
+            outcome.final_message = json;
+            Ok(outcome)
+        }
+        None => Err(ErrorResponse::invalid_json_output(
+            "The model did not reply with valid JSON in the requested response_format, even when asked again".to_string(),
+        )),
+    }
+}
+
+/// Attributes of the session of a request, its sampling parameters are used over the ones of the agent
+/// `agent_name` and `route` are what the model of the request resolved to
+fn session_attributes(
+    payload: &ChatCompletionParameters,
+    features: Features,
+    agent_name: String,
+    route: Option<ModelRoute>,
+    model: Option<String>,
+) -> SessionAttributes {
+    let sampling = SamplingOverrides {
+        temperature: payload.temperature,
+        top_p: payload.top_p,
+        max_tokens: payload.max_completion_tokens.or(payload.max_tokens),
+        stop: Some(stop_sequences(payload)).filter(|stop| !stop.is_empty()),
+        response_format: json_format(payload).cloned(),
+    };
+    SessionAttributes {
+        features: Some(features),
+        tools_disabled: matches!(payload.tool_choice, Some(ChatCompletionToolChoice::None)),
+        agent_name: Some(agent_name),
+        overrides: RequestOverrides { route, model, sampling, ..Default::default() },
+        ..Default::default()
+    }
+}
+
+/// Stop sequences of a request, `stop` being a string or an array of strings
+fn stop_sequences(payload: &ChatCompletionParameters) -> Vec<String> {
+    let stop = match &payload.stop {
+        Some(StopToken::String(stop)) => vec![stop.clone()],
+        Some(StopToken::Array(stop)) => stop.clone(),
+        None => vec![],
+    };
+    stop.into_iter().filter(|stop| !stop.is_empty()).collect()
+}
+
+/// Cut `text` before the first stop sequence it contains, returns whether one was found
+fn truncate_at_stop(text: &mut String, stop: &[String]) -> bool {
+    match stop.iter().filter_map(|stop| text.find(stop.as_str())).min() {
+        Some(position) => {
+            text.truncate(position);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Assistant message of a run and its finish reason
+/// With `expose_tools`, the calls the agent ran are returned as `tool_calls` (finish reason `tool_calls`)
+fn assistant_message(outcome: RunOutcome, expose_tools: bool) -> (ChatMessage, FinishReason) {
+    let reasoning_steps = reasoning_steps(&outcome);
+    let exposed = if expose_tools { outcome.tool_calls.as_slice() } else { &[] };
+    let tool_calls: Vec<ToolCall> = exposed.iter()
+        .map(|record| ToolCall {
+            id: record.call.tool_call_id.clone(),
+            r#type: "function".to_string(),
+            function: Function {
+                name: record.call.tool_name.clone(),
+                arguments: record.call.parameters.to_string(),
+            },
+        })
+        .collect();
+    let finish_reason = match tool_calls.is_empty() {
+        true => FinishReason::StopSequenceReached,
+        false => FinishReason::ToolCalls,
+    };
+
+    let message = ChatMessage::Assistant {
+        content: Some(ChatMessageContent::Text(outcome.final_message)),
+        name: None,
+        tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+        audio: None,
+        reasoning_content: if reasoning_steps.is_empty() {
+            None
+        } else {
+            Some(reasoning_steps.join("\n"))
+        },
+        refusal: None,
+    };
+    (message, finish_reason)
+}
+
+/// Tool calls of a run rendered as reasoning lines, same wording as the streaming formatter
+fn reasoning_steps(outcome: &RunOutcome) -> Vec<String> {
+    outcome.tool_calls.iter().flat_map(|record| {
+        let name = &record.call.tool_name;
+        let result = match &record.result {
+            ToolResult::Success { .. } => format!("[tool succeeded: {}]", name),
+            ToolResult::Error { error, .. } => {
+                let error_oneline = error.lines().next().unwrap_or(error);
+                format!("[tool failed: {} - {}]", name, error_oneline)
+            }
+            ToolResult::Denied => format!("[tool denied: {}]", name),
+        };
+        [format!("[toolcall: {}]", name), result]
+    }).collect()
 }
 
 /// Build message trace from OpenAI chat completion parameters
@@ -248,3 +459,133 @@ fn build_message_trace(params: &ChatCompletionParameters) -> Vec<ChatMessage> {
 
     trace
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run::ToolCallRecord;
+    use shai_core::tools::ToolCall as AgentToolCall;
+
+    fn outcome() -> RunOutcome {
+        RunOutcome {
+            final_message: "done".to_string(),
+            tool_calls: vec![ToolCallRecord {
+                call: AgentToolCall {
+                    tool_call_id: "call_1".to_string(),
+                    tool_name: "read".to_string(),
+                    parameters: serde_json::json!({ "path": "a.txt" }),
+                },
+                result: ToolResult::success("content".to_string()),
+                duration: chrono::TimeDelta::milliseconds(5),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_exposed_tool_calls() {
+        let (message, finish_reason) = assistant_message(outcome(), true);
+        assert!(matches!(finish_reason, FinishReason::ToolCalls));
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(json["content"], "done");
+        assert_eq!(json["tool_calls"][0]["id"], "call_1");
+        assert_eq!(json["tool_calls"][0]["type"], "function");
+        assert_eq!(json["tool_calls"][0]["function"]["name"], "read");
+        assert_eq!(json["tool_calls"][0]["function"]["arguments"], r#"{"path":"a.txt"}"#);
+
+        // hidden by default, the calls only show in the reasoning
+        let (message, finish_reason) = assistant_message(outcome(), false);
+        assert!(matches!(finish_reason, FinishReason::StopSequenceReached));
+        let ChatMessage::Assistant { tool_calls, reasoning_content, .. } = message else {
+            panic!("expected an assistant message");
+        };
+        assert!(tool_calls.is_none());
+        assert!(reasoning_content.unwrap().contains("[toolcall: read]"));
+    }
+
+    #[test]
+    fn test_expose_tools_flag() {
+        let mut headers = HeaderMap::new();
+        assert!(!expose_tools(&headers, &ChatCompletionQuery::default()));
+        assert!(expose_tools(&headers, &ChatCompletionQuery { expose_tools: true }));
+        headers.insert(EXPOSE_TOOLS_HEADER, HeaderValue::from_static("true"));
+        assert!(expose_tools(&headers, &ChatCompletionQuery::default()));
+        headers.insert(EXPOSE_TOOLS_HEADER, HeaderValue::from_static("off"));
+        assert!(!expose_tools(&headers, &ChatCompletionQuery::default()));
+    }
+
+    #[test]
+    fn test_stop_sequences() {
+        let payload = |stop: serde_json::Value| -> ChatCompletionParameters {
+            serde_json::from_value(serde_json::json!({ "model": "default", "messages": [], "stop": stop })).unwrap()
+        };
+
+        let single = payload(serde_json::json!("END"));
+        assert_eq!(stop_sequences(&single), vec!["END"]);
+        let mut text = "answer END trailing".to_string();
+        assert!(truncate_at_stop(&mut text, &stop_sequences(&single)));
+        assert_eq!(text, "answer ");
+
+        // the earliest of the sequences wins
+        let array = payload(serde_json::json!(["\n\n", "###", ""]));
+        assert_eq!(stop_sequences(&array), vec!["\n\n", "###"]);
+        let mut text = "a ### b\n\nc".to_string();
+        assert!(truncate_at_stop(&mut text, &stop_sequences(&array)));
+        assert_eq!(text, "a ");
+
+        let mut text = "no stop here".to_string();
+        assert!(!truncate_at_stop(&mut text, &stop_sequences(&array)));
+        assert_eq!(text, "no stop here");
+        assert_eq!(session_attributes(&array, Features::default(), "default".to_string(), None, None).overrides.sampling.stop,
+            Some(vec!["\n\n".to_string(), "###".to_string()]));
+    }
+
+    #[test]
+    fn test_invalid_json_reply_fails() {
+        let format = ChatCompletionResponseFormat::JsonObject;
+        let reply = |text: &str| RunOutcome {
+            final_message: text.to_string(),
+            usage: RunUsage { input_tokens: 10, output_tokens: 5 },
+            ..Default::default()
+        };
+
+        // JSON in a code fence is taken out of it
+        let outcome = ensure_json(&format, reply("```json\n{\"colors\": [\"red\"]}\n```")).unwrap();
+        assert_eq!(outcome.final_message, r#"{"colors": ["red"]}"#);
+        assert_eq!(outcome.usage.total_tokens(), 15);
+
+        // truncated JSON, an array is not an object either
+        for invalid in [r#"Sure! {"colors": ["red""#, "[1, 2]"] {
+            let error = ensure_json(&format, reply(invalid)).unwrap_err();
+            assert_eq!(error.error.code.as_deref(), Some("invalid_json_output"));
+        }
+    }
+
+    #[test]
+    fn test_json_reply_matches_schema() {
+        let payload: ChatCompletionParameters = serde_json::from_value(serde_json::json!({
+            "model": "default",
+            "messages": [],
+            "response_format": {
+                "type": "json_schema",
+                "json_schema": {
+                    "name": "answer",
+                    "schema": { "type": "object", "required": ["answer"], "properties": { "answer": { "type": "string" } } }
+                }
+            }
+        }))
+        .unwrap();
+        let format = json_format(&payload).unwrap();
+
+        assert_eq!(json_reply(r#"The answer: {"answer": "42"}."#, format).as_deref(), Some(r#"{"answer": "42"}"#));
+        assert_eq!(json_reply(r#"{"result": "42"}"#, format), None);
+        assert!(session_attributes(&payload, Features::default(), "default".to_string(), None, None)
+            .overrides.sampling.response_format.is_some());
+
+        let text: ChatCompletionParameters = serde_json::from_value(serde_json::json!({
+            "model": "default", "messages": [], "response_format": { "type": "text" }
+        }))
+        .unwrap();
+        assert!(json_format(&text).is_none());
+    }
+}
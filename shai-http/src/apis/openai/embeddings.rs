@@ -0,0 +1,101 @@
+use axum::{extract::State, http::HeaderMap, Json};
+use openai_dive::v1::resources::embedding::{EmbeddingParameters, EmbeddingResponse};
+use serde_json::Value;
+use shai_core::agent::AgentBuilder;
+use shai_core::config::config::ShaiConfig;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{ApiJson, ErrorResponse, OpenAiError, ServerState};
+
+/// POST /v1/embeddings - Embeddings of the input, forwarded to the LLM provider without any agent
+/// `model` may name an entry of the model registry (alias or `provider/model`), other names are sent
+/// as is to the provider selected in the shai config. Restricted API keys must allow the model,
+/// providers without embeddings answer 501
+pub async fn handle_embeddings(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+    payload: Result<ApiJson<EmbeddingParameters>, ErrorResponse>,
+) -> Result<Json<EmbeddingResponse>, OpenAiError> {
+    let ApiJson(mut payload) = payload?;
+    let request_id = Uuid::new_v4();
+    info!("[{}] POST /v1/embeddings model={}", request_id, payload.model);
+
+    validate_embedding_request(&payload)?;
+    if let Some(allowance) = state.config.api_keys.allowance(&headers)? {
+        if !allowance.allows_model(&payload.model) {
+            warn!("audit: embeddings with model '{}' refused", payload.model);
+            return Err(ErrorResponse::forbidden(format!("This API key may not use the model '{}'", payload.model)).into());
+        }
+    }
+
+    let llm = match state.config.models.resolve(&payload.model) {
+        Some(route) => {
+            payload.model = route.model;
+            AgentBuilder::provider_llm(&route.provider)
+                .map_err(|e| ErrorResponse::internal_error(format!("Failed to create the provider: {}", e)))?
+        }
+        None => {
+            ShaiConfig::get_llm()
+                .await
+                .map_err(|e| ErrorResponse::internal_error(format!("Failed to get LLM from config: {}", e)))?
+                .0
+        }
+    };
+
+    if !llm.supports_embeddings() {
+        return Err(ErrorResponse::not_implemented(format!("{} does not support embeddings", llm.provider_name())).into());
+    }
+
+    let response = llm.embeddings(payload).await.map_err(|e| {
+        warn!("[{}] POST /v1/embeddings - {} failed: {}", request_id, llm.provider_name(), e);
+        ErrorResponse::upstream_error(format!("Embeddings request failed: {}", e))
+    })?;
+    Ok(Json(response))
+}
+
+/// `input` must be a non-empty string, or a non-empty array of strings or token arrays
+fn validate_embedding_request(payload: &EmbeddingParameters) -> Result<(), ErrorResponse> {
+    if payload.model.trim().is_empty() {
+        return Err(ErrorResponse::invalid_request("model is required".to_string()).with_param("model"));
+    }
+    let input = serde_json::to_value(&payload.input).unwrap_or(Value::Null);
+    let valid = match &input {
+        Value::String(text) => !text.is_empty(),
+        Value::Array(items) => !items.is_empty() && items.iter().all(|item| match item {
+            Value::String(text) => !text.is_empty(),
+            Value::Number(_) => true,
+            Value::Array(tokens) => !tokens.is_empty(),
+            _ => false,
+        }),
+        _ => false,
+    };
+    match valid {
+        true => Ok(()),
+        false => Err(ErrorResponse::invalid_request(
+            "input must be a non-empty string or a non-empty array of strings or token arrays".to_string(),
+        )
+        .with_param("input")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameters(input: Value) -> EmbeddingParameters {
+        serde_json::from_value(serde_json::json!({ "model": "nomic-embed-text", "input": input })).unwrap()
+    }
+
+    #[test]
+    fn test_embedding_input_validation() {
+        assert!(validate_embedding_request(&parameters(serde_json::json!("hello"))).is_ok());
+        assert!(validate_embedding_request(&parameters(serde_json::json!(["a", "b"]))).is_ok());
+        assert!(validate_embedding_request(&parameters(serde_json::json!([[1, 2], [3]]))).is_ok());
+
+        for input in [serde_json::json!(""), serde_json::json!([]), serde_json::json!(["a", ""])] {
+            let error = validate_embedding_request(&parameters(input)).unwrap_err();
+            assert_eq!(error.error.param.as_deref(), Some("input"));
+        }
+    }
+}
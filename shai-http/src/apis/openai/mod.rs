@@ -1,5 +1,10 @@
 pub mod completion;
+pub mod embeddings;
+pub mod models;
 pub mod response;
+pub mod user;
 
 pub use completion::handle_chat_completion;
-pub use response::{handle_response, handle_get_response, handle_cancel_response};
+pub use embeddings::handle_embeddings;
+pub use models::handle_list_models;
+pub use response::{handle_response, handle_get_response, handle_delete_response, handle_cancel_response, handle_list_input_items};
@@ -0,0 +1,177 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use axum::{extract::State, http::HeaderMap, Json};
+use futures::future::join_all;
+use openai_dive::v1::resources::model::ListModelResponse;
+use serde::{Deserialize, Serialize};
+use shai_core::config::agent::AgentConfig;
+use shai_core::config::config::ShaiConfig;
+use shai_llm::LlmClient;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::access::DEFAULT_AGENT;
+use crate::ServerState;
+
+/// A model of the OpenAI models list: an agent, a registry model or a model of a configured provider
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Model {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub owned_by: String,
+}
+
+/// Response of GET /v1/models
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelList {
+    pub object: String,
+    pub data: Vec<Model>,
+}
+
+/// Models of every provider configured in the shai config, named `provider/model`, fetched at most once per TTL
+#[derive(Debug, Default)]
+pub struct ProviderModels {
+    cached: Mutex<Option<(Instant, Vec<Model>)>>,
+}
+
+impl ProviderModels {
+    /// Cached list when younger than `ttl` (None = always fetched)
+    /// Providers that cannot be reached are left out, concurrent calls wait for the same fetch
+    pub async fn get(&self, ttl: Option<Duration>) -> Vec<Model> {
+        let mut cached = self.cached.lock().await;
+        if let (Some((fetched_at, models)), Some(ttl)) = (cached.as_ref(), ttl) {
+            if fetched_at.elapsed() < ttl {
+                return models.clone();
+            }
+        }
+
+        let models = Self::fetch().await;
+        *cached = Some((Instant::now(), models.clone()));
+        models
+    }
+
+    async fn fetch() -> Vec<Model> {
+        let config = ShaiConfig::load().unwrap_or_default();
+        let lists = config.providers.iter().map(|provider| async move {
+            let client = LlmClient::create_provider_from_config(
+                &provider.provider,
+                &provider.env_vars,
+                provider.key_pool.as_ref(),
+                provider.circuit_breaker.as_ref(),
+            )
+            .map_err(|e| format!("{}: {}", provider.provider, e))?;
+            let models = client.models().await.map_err(|e| format!("{}: {}", provider.provider, e))?;
+            Ok::<_, String>((provider.provider.clone(), models))
+        });
+
+        let mut fetched = Vec::new();
+        for result in join_all(lists).await {
+            match result {
+                Ok(list) => fetched.push(list),
+                Err(e) => warn!("GET /v1/models - failed to list the models of {}", e),
+            }
+        }
+        merge_provider_models(fetched)
+    }
+}
+
+/// Models of several providers as `provider/model`, without duplicates (entries of the same provider)
+fn merge_provider_models(lists: Vec<(String, ListModelResponse)>) -> Vec<Model> {
+    let mut models: BTreeMap<String, Model> = BTreeMap::new();
+    for (provider, list) in lists {
+        for model in list.data {
+            let id = format!("{}/{}", provider, model.id);
+            models.entry(id.clone()).or_insert_with(|| Model {
+                id,
+                object: "model".to_string(),
+                created: model.created.unwrap_or(0) as u64,
+                owned_by: provider.clone(),
+            });
+        }
+    }
+    models.into_values().collect()
+}
+
+/// GET /v1/models - Agents, registry models and the models of every configured provider
+/// Agents and registry models are what the requests name in their `model` field, provider models are
+/// listed as `provider/model`, which requests can name for the providers of the registry.
+/// Filtered by the allowance of the API key the request was sent with
+/// Agents are owned by "shai", the other entries by their provider
+pub async fn handle_list_models(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+) -> Json<ModelList> {
+    let mut agents = vec![DEFAULT_AGENT.to_string()];
+    match AgentConfig::list_agents() {
+        Ok(names) => agents.extend(names),
+        Err(e) => warn!("GET /v1/models - failed to list agents: {}", e),
+    }
+
+    let registry = &state.config.models;
+    agents.retain(|agent| !registry.models.contains_key(agent));
+    agents.extend(registry.models.keys().cloned());
+
+    let names = state.config.api_keys.allowed_agents(&headers, agents, registry);
+    let mut data: Vec<Model> = names
+        .into_iter()
+        .map(|id| {
+            let owned_by = registry.models.get(&id).map_or_else(|| "shai".to_string(), |route| route.provider.clone());
+            Model { id, object: "model".to_string(), created: 0, owned_by }
+        })
+        .collect();
+
+    // Restricted keys only see the provider models their allowed models name
+    let allowance = state.config.api_keys.allowance(&headers);
+    let provider_models = state.provider_models.get(state.config.models_cache_ttl).await;
+    let provider_models: Vec<Model> = provider_models
+        .into_iter()
+        .filter(|model| match &allowance {
+            Ok(None) => true,
+            Ok(Some(allowance)) => !allowance.allowed_models.is_empty() && allowance.allows_model(&model.id),
+            Err(_) => false,
+        })
+        .filter(|model| !data.iter().any(|listed| listed.id == model.id))
+        .collect();
+    data.extend(provider_models);
+
+    info!("GET /v1/models - {} models", data.len());
+    Json(ModelList { object: "list".to_string(), data })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_provider_models_cached_for_ttl() {
+        let models = ProviderModels::default();
+        let qwen = Model { id: "qwen2.5".to_string(), object: "model".to_string(), created: 0, owned_by: "ollama".to_string() };
+        *models.cached.lock().await = Some((Instant::now(), vec![qwen]));
+
+        let listed = models.get(Some(Duration::from_secs(60))).await;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].owned_by, "ollama");
+    }
+
+    #[test]
+    fn test_provider_models_merged() {
+        let list = |ids: &[&str]| -> ListModelResponse {
+            let data: Vec<serde_json::Value> = ids.iter()
+                .map(|id| serde_json::json!({ "id": id, "object": "model", "owned_by": "x" }))
+                .collect();
+            serde_json::from_value(serde_json::json!({ "object": "list", "data": data })).unwrap()
+        };
+
+        let models = merge_provider_models(vec![
+            ("openai".to_string(), list(&["gpt-4o", "gpt-4o-mini"])),
+            ("ollama".to_string(), list(&["llama3"])),
+            // a second entry of the same provider
+            ("openai".to_string(), list(&["gpt-4o"])),
+        ]);
+        let ids: Vec<&str> = models.iter().map(|model| model.id.as_str()).collect();
+        assert_eq!(ids, vec!["ollama/llama3", "openai/gpt-4o", "openai/gpt-4o-mini"]);
+        assert_eq!(models[0].owned_by, "ollama");
+    }
+}
@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use axum::response::sse::Event;
 use openai_dive::v1::resources::response::{
     items::{FunctionToolCall, InputItemStatus},
     request::ResponseParameters,
@@ -7,13 +8,15 @@ use openai_dive::v1::resources::response::{
         ResponseOutput, Role,
     },
 };
-use openai_dive::v1::resources::shared::Usage;
 use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent};
 use shai_core::agent::AgentEvent;
+use std::collections::HashSet;
 use uuid::Uuid;
 
 use super::types::ResponseStreamEvent;
+use crate::run::RunUsage;
 use crate::streaming::EventFormatter;
+use crate::ErrorResponse;
 
 /// Formatter for OpenAI Response API
 pub struct ResponseFormatter {
@@ -25,7 +28,13 @@ pub struct ResponseFormatter {
     sequence: u32,
     output: Vec<ResponseOutput>,
     accumulated_text: String,
+    /// Text of the last message streamed as an output item
+    streamed_text: Option<String>,
     initial_event_sent: bool,
+    /// Calls whose arguments were streamed as function_call_arguments.delta
+    streamed_calls: HashSet<String>,
+    /// Token usage summed over the brain iterations
+    pub(super) usage: RunUsage,
 }
 
 impl ResponseFormatter {
@@ -42,11 +51,123 @@ impl ResponseFormatter {
             sequence: 0,
             output: Vec::new(),
             accumulated_text: String::new(),
+            streamed_text: None,
             initial_event_sent: false,
+            streamed_calls: HashSet::new(),
+            usage: RunUsage::default(),
         }
     }
 
-    fn build_response_object(
+    /// The response.created event, only returned on the first call
+    fn initial_event(&mut self, session_id: &str) -> Option<ResponseStreamEvent> {
+        if self.initial_event_sent {
+            return None;
+        }
+        self.initial_event_sent = true;
+        let initial_response = self.build_response_object(
+            session_id,
+            ReasoningStatus::InProgress,
+            vec![],
+        );
+        let evt = ResponseStreamEvent::created(self.sequence, initial_response);
+        self.sequence += 1;
+        Some(evt)
+    }
+
+    /// Position of a function call in the output
+    fn function_call_index(&self, call_id: &str) -> Option<usize> {
+        self.output.iter().position(|o| {
+            if let ResponseOutput::FunctionToolCall(tc) = o {
+                tc.id == call_id
+            } else {
+                false
+            }
+        })
+    }
+
+    /// Events for a fragment of tool call arguments
+    /// The first fragment of a call adds its output item, arguments are accumulated in the item
+    fn arguments_delta_events(&mut self, call_id: String, tool_name: String, delta: String) -> Vec<ResponseStreamEvent> {
+        let mut events = Vec::new();
+        let output_index = match self.function_call_index(&call_id) {
+            Some(idx) => idx,
+            None => {
+                let tool_output = ResponseOutput::FunctionToolCall(FunctionToolCall {
+                    id: call_id.clone(),
+                    call_id: call_id.clone(),
+                    name: tool_name,
+                    arguments: String::new(),
+                    status: InputItemStatus::InProgress,
+                });
+                self.output.push(tool_output.clone());
+                self.streamed_calls.insert(call_id.clone());
+
+                let output_index = self.output.len() - 1;
+                events.push(ResponseStreamEvent::output_item_added(self.sequence, output_index, tool_output));
+                self.sequence += 1;
+                output_index
+            }
+        };
+
+        if let ResponseOutput::FunctionToolCall(tc) = &mut self.output[output_index] {
+            tc.arguments.push_str(&delta);
+        }
+        events.push(ResponseStreamEvent::function_call_arguments_delta(self.sequence, call_id, output_index, delta));
+        self.sequence += 1;
+        events
+    }
+
+    fn next_sequence(&mut self) -> u32 {
+        let sequence = self.sequence;
+        self.sequence += 1;
+        sequence
+    }
+
+    /// Events of an assistant message produced by the model: the item, its text part and its text
+    fn message_events(&mut self, text: String) -> Vec<ResponseStreamEvent> {
+        let item_id = Uuid::new_v4().to_string();
+        let output_index = self.output.len();
+        let message = |status, content| ResponseOutput::Message(OutputMessage {
+            id: item_id.clone(),
+            role: Role::Assistant,
+            status,
+            content,
+        });
+        let part = |text: &str| OutputContent::Text { text: text.to_string(), annotations: vec![] };
+
+        let mut events = vec![
+            ResponseStreamEvent::output_item_added(self.next_sequence(), output_index, message(MessageStatus::InProgress, vec![])),
+            ResponseStreamEvent::content_part_added(self.next_sequence(), item_id.clone(), output_index, 0, part("")),
+            ResponseStreamEvent::output_text_delta(self.next_sequence(), item_id.clone(), output_index, 0, text.clone()),
+            ResponseStreamEvent::output_text_done(self.next_sequence(), item_id.clone(), output_index, 0, text.clone()),
+            ResponseStreamEvent::content_part_done(self.next_sequence(), item_id.clone(), output_index, 0, part(&text)),
+        ];
+        let done = message(MessageStatus::Completed, vec![part(&text)]);
+        self.output.push(done.clone());
+        events.push(ResponseStreamEvent::output_item_done(self.next_sequence(), output_index, done));
+
+        self.accumulated_text = text.clone();
+        self.streamed_text = Some(text);
+        events
+    }
+
+    /// Add the final assistant message to the output, unless it was already streamed as an item
+    fn push_final_message(&mut self) {
+        if self.streamed_text.as_deref() == Some(self.accumulated_text.as_str()) {
+            return;
+        }
+        self.output.push(ResponseOutput::Message(OutputMessage {
+            id: Uuid::new_v4().to_string(),
+            role: Role::Assistant,
+            status: MessageStatus::Completed,
+            content: vec![OutputContent::Text {
+                text: self.accumulated_text.clone(),
+                annotations: vec![],
+            }],
+        }));
+    }
+
+    pub(super) fn build_response_object(
         &self,
         session_id: &str,
         status: ReasoningStatus,
@@ -64,7 +185,7 @@ impl ResponseFormatter {
             temperature: self.payload.temperature,
             max_output_tokens: self.payload.max_output_tokens,
             parallel_tool_calls: self.payload.parallel_tool_calls,
-            previous_response_id: None,
+            previous_response_id: self.payload.previous_response_id.clone(),
             reasoning: self.payload.reasoning.clone(),
             text: self.payload.text.clone(),
             tool_choice: self.payload.tool_choice.clone(),
@@ -72,17 +193,7 @@ impl ResponseFormatter {
             top_p: self.payload.top_p,
             truncation: self.payload.truncation.clone(),
             user: self.payload.user.clone(),
-            usage: Usage {
-                input_tokens: None,
-                input_tokens_details: None,
-                output_tokens: None,
-                output_tokens_details: None,
-                completion_tokens: Some(0),
-                prompt_tokens: Some(0),
-                total_tokens: 0,
-                completion_tokens_details: None,
-                prompt_tokens_details: None,
-            },
+            usage: self.usage.into(),
             incomplete_details: None,
             error: None,
         }
@@ -98,42 +209,48 @@ impl EventFormatter for ResponseFormatter {
         event: AgentEvent,
         session_id: &str,
     ) -> Option<Self::Output> {
+        // Usage is recorded even when the event only triggers response.created
+        if let AgentEvent::TokenUsage { input_tokens, output_tokens } = &event {
+            self.usage.add(*input_tokens, *output_tokens);
+        }
+
         // Send initial event on first call
-        if !self.initial_event_sent {
-            self.initial_event_sent = true;
-            let initial_response = self.build_response_object(
-                session_id,
-                ReasoningStatus::InProgress,
-                vec![],
-            );
-            let evt = ResponseStreamEvent::created(self.sequence, initial_response);
-            self.sequence += 1;
+        if let Some(evt) = self.initial_event(session_id) {
             return Some(evt);
         }
 
         match event {
             // Capture assistant messages from brain results
             AgentEvent::BrainResult { thought, .. } => {
-                match thought {
-                    Ok(msg) => {
-                        if let ChatMessage::Assistant {
-                            content: Some(ChatMessageContent::Text(text)),
-                            ..
-                        } = msg
-                        {
-                            self.accumulated_text = text;
-                        }
-                    }
-                    Err(err) => {
-                        // Accumulate error message as text
-                        self.accumulated_text = format!("Error: {}", err);
-                    }
+                // Errors end the run, they are sent as response.failed
+                if let Ok(ChatMessage::Assistant {
+                    content: Some(ChatMessageContent::Text(text)),
+                    ..
+                }) = thought
+                {
+                    self.accumulated_text = text;
                 }
                 None
             }
 
             // Tool calls
             AgentEvent::ToolCallStarted { call, .. } => {
+                // Arguments were streamed, the item already exists
+                if self.streamed_calls.contains(&call.tool_call_id) {
+                    let idx = self.function_call_index(&call.tool_call_id)?;
+                    let ResponseOutput::FunctionToolCall(tc) = &self.output[idx] else {
+                        return None;
+                    };
+                    let event = ResponseStreamEvent::function_call_arguments_done(
+                        self.sequence,
+                        call.tool_call_id.clone(),
+                        idx,
+                        tc.arguments.clone(),
+                    );
+                    self.sequence += 1;
+                    return Some(event);
+                }
+
                 let tool_output = ResponseOutput::FunctionToolCall(FunctionToolCall {
                     id: call.tool_call_id.clone(),
                     call_id: call.tool_call_id.clone(),
@@ -163,18 +280,17 @@ impl EventFormatter for ResponseFormatter {
                     }
                 };
 
-                if let Some(idx) = self.output.iter().position(|o| {
-                    if let ResponseOutput::FunctionToolCall(tc) = o {
-                        tc.id == call.tool_call_id
-                    } else {
-                        false
-                    }
-                }) {
+                if let Some(idx) = self.function_call_index(&call.tool_call_id) {
+                    // streamed arguments are kept as generated, byte for byte
+                    let arguments = match &self.output[idx] {
+                        ResponseOutput::FunctionToolCall(tc) if self.streamed_calls.contains(&call.tool_call_id) => tc.arguments.clone(),
+                        _ => call.parameters.to_string(),
+                    };
                     self.output[idx] = ResponseOutput::FunctionToolCall(FunctionToolCall {
                         id: call.tool_call_id.clone(),
                         call_id: call.tool_call_id.clone(),
                         name: call.tool_name.clone(),
-                        arguments: call.parameters.to_string(),
+                        arguments,
                         status: tool_status,
                     });
 
@@ -191,17 +307,7 @@ impl EventFormatter for ResponseFormatter {
                 if !message.is_empty() {
                     self.accumulated_text = message;
                 }
-
-                let msg_output = ResponseOutput::Message(OutputMessage {
-                    id: Uuid::new_v4().to_string(),
-                    role: Role::Assistant,
-                    status: MessageStatus::Completed,
-                    content: vec![OutputContent::Text {
-                        text: self.accumulated_text.clone(),
-                        annotations: vec![],
-                    }],
-                });
-                self.output.push(msg_output);
+                self.push_final_message();
 
                 let final_status = if success {
                     ReasoningStatus::Completed
@@ -223,16 +329,7 @@ impl EventFormatter for ResponseFormatter {
             AgentEvent::StatusChanged { new_status, .. } => {
                 use shai_core::agent::PublicAgentState;
                 if matches!(new_status, PublicAgentState::Paused { .. }) {
-                    let msg_output = ResponseOutput::Message(OutputMessage {
-                        id: Uuid::new_v4().to_string(),
-                        role: Role::Assistant,
-                        status: MessageStatus::Completed,
-                        content: vec![OutputContent::Text {
-                            text: self.accumulated_text.clone(),
-                            annotations: vec![],
-                        }],
-                    });
-                    self.output.push(msg_output);
+                    self.push_final_message();
 
                     let final_response = self.build_response_object(
                         session_id,
@@ -250,7 +347,174 @@ impl EventFormatter for ResponseFormatter {
         }
     }
 
+    async fn format_events(
+        &mut self,
+        event: AgentEvent,
+        session_id: &str,
+    ) -> Vec<Self::Output> {
+        match event {
+            AgentEvent::ToolCallArgumentsDelta { call_id, tool_name, delta, .. } => {
+                let mut events: Vec<_> = self.initial_event(session_id).into_iter().collect();
+                events.extend(self.arguments_delta_events(call_id, tool_name, delta));
+                events
+            }
+            // Assistant text is streamed as a message item as soon as the model produced it
+            AgentEvent::BrainResult {
+                thought: Ok(ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. }),
+                ..
+            } if !text.trim().is_empty() => {
+                let mut events: Vec<_> = self.initial_event(session_id).into_iter().collect();
+                events.extend(self.message_events(text));
+                events
+            }
+            event => self.format_event(event, session_id).await.into_iter().collect(),
+        }
+    }
+
     fn event_name(&self, output: &Self::Output) -> &str {
         output.event_name()
     }
+
+    /// response.failed carrying the output produced so far and the error object
+    fn format_error(&mut self, error: &ErrorResponse, session_id: &str) -> Result<Event, serde_json::Error> {
+        let failed = self.build_response_object(session_id, ReasoningStatus::Failed, self.output.clone());
+        let event = ResponseStreamEvent::failed(self.sequence, failed);
+        self.sequence += 1;
+
+        // the Responses API error object is {code, message}, set on the JSON so the type is kept as well
+        let mut json = serde_json::to_value(&event)?;
+        json["response"]["error"] = serde_json::json!({
+            "code": error.error.code.as_deref().unwrap_or(&error.error.r#type),
+            "message": error.error.message,
+            "type": error.error.r#type,
+        });
+        serde_json::to_string(&json).map(|json| Event::default().data(json))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::types::ResponseEventData;
+    use shai_core::tools::ToolCall;
+
+    fn delta(call_id: &str, delta: &str) -> AgentEvent {
+        AgentEvent::ToolCallArgumentsDelta {
+            call_id: call_id.to_string(),
+            tool_name: "write".to_string(),
+            index: 0,
+            delta: delta.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_function_call_arguments_deltas() {
+        let payload: ResponseParameters = serde_json::from_value(serde_json::json!({ "model": "test", "input": "hi" })).unwrap();
+        let mut formatter = ResponseFormatter::new("test".to_string(), payload);
+        let pieces = ["{\"path\":", "\"a.txt\",", "\"content\":\"é\"}"];
+
+        let mut events = Vec::new();
+        for piece in pieces {
+            events.extend(formatter.format_events(delta("call_1", piece), "resp_1").await);
+        }
+        let names: Vec<_> = events.iter().map(|e| e.event_name()).collect();
+        assert_eq!(names, vec![
+            "response.created",
+            "response.output_item.added",
+            "response.function_call_arguments.delta",
+            "response.function_call_arguments.delta",
+            "response.function_call_arguments.delta",
+        ]);
+
+        let started = AgentEvent::ToolCallStarted {
+            timestamp: chrono::Utc::now(),
+            call: ToolCall {
+                tool_call_id: "call_1".to_string(),
+                tool_name: "write".to_string(),
+                parameters: serde_json::json!({ "path": "a.txt", "content": "é" }),
+            },
+        };
+        let done = formatter.format_events(started, "resp_1").await;
+        assert_eq!(done.len(), 1);
+        let ResponseEventData::ArgumentsDone { item_id, output_index, arguments, .. } = &done[0].data else {
+            panic!("expected function_call_arguments.done, got {:?}", done[0]);
+        };
+        assert_eq!(item_id, "call_1");
+        assert_eq!(*output_index, 0);
+        assert_eq!(arguments.as_bytes(), pieces.concat().as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_assistant_text_is_streamed_as_a_message_item() {
+        let payload: ResponseParameters = serde_json::from_value(serde_json::json!({ "model": "test", "input": "hi" })).unwrap();
+        let mut formatter = ResponseFormatter::new("test".to_string(), payload);
+        let thought = AgentEvent::BrainResult {
+            timestamp: chrono::Utc::now(),
+            thought: Ok(ChatMessage::Assistant {
+                content: Some(ChatMessageContent::Text("All done".to_string())),
+                reasoning_content: None,
+                tool_calls: None,
+                name: None,
+                audio: None,
+                refusal: None,
+            }),
+        };
+
+        let events = formatter.format_events(thought, "resp_1").await;
+        let names: Vec<_> = events.iter().map(|e| e.event_name()).collect();
+        assert_eq!(names, vec![
+            "response.created",
+            "response.output_item.added",
+            "response.content_part.added",
+            "response.output_text.delta",
+            "response.output_text.done",
+            "response.content_part.done",
+            "response.output_item.done",
+        ]);
+        let ResponseEventData::TextDelta { delta, output_index, .. } = &events[3].data else {
+            panic!("expected output_text.delta, got {:?}", events[3]);
+        };
+        assert_eq!(delta, "All done");
+        assert_eq!(*output_index, 0);
+
+        // the streamed message is not repeated in the completed response
+        let completed = formatter
+            .format_events(AgentEvent::Completed { success: true, message: String::new() }, "resp_1")
+            .await;
+        let ResponseEventData::Response { response, sequence_number } = &completed[0].data else {
+            panic!("expected response.completed, got {:?}", completed[0]);
+        };
+        assert_eq!(*sequence_number, 7);
+        assert_eq!(response.output.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_usage_is_summed_over_iterations() {
+        let payload: ResponseParameters = serde_json::from_value(serde_json::json!({ "model": "test", "input": "hi" })).unwrap();
+        let mut formatter = ResponseFormatter::new("test".to_string(), payload);
+        for (input_tokens, output_tokens) in [(120, 30), (180, 12), (210, 8)] {
+            formatter.format_events(AgentEvent::TokenUsage { input_tokens, output_tokens }, "resp_1").await;
+        }
+
+        let completed = formatter
+            .format_events(AgentEvent::Completed { success: true, message: "done".to_string() }, "resp_1")
+            .await;
+        let ResponseEventData::Response { response, .. } = &completed[0].data else {
+            panic!("expected response.completed, got {:?}", completed[0]);
+        };
+        assert_eq!(response.usage.prompt_tokens, Some(510));
+        assert_eq!(response.usage.completion_tokens, Some(50));
+        assert_eq!(response.usage.total_tokens, 560);
+    }
+
+    #[test]
+    fn test_previous_response_id_is_echoed() {
+        let payload: ResponseParameters = serde_json::from_value(serde_json::json!({
+            "model": "test", "input": "and then?", "previous_response_id": "resp_1"
+        })).unwrap();
+        let formatter = ResponseFormatter::new("test".to_string(), payload);
+        let response = formatter.build_response_object("resp_2", ReasoningStatus::Completed, vec![]);
+        assert_eq!(response.id, "resp_2");
+        assert_eq!(response.previous_response_id.as_deref(), Some("resp_1"));
+    }
 }
@@ -1,36 +1,149 @@
+use std::sync::Arc;
 use axum::{
-    extract::{Path, State},
+    extract::{Extension, Path, Query, State},
+    http::{HeaderMap, HeaderValue},
     response::{IntoResponse, Response, Sse},
     Json,
 };
 use openai_dive::v1::resources::response::request::ResponseParameters;
+use openai_dive::v1::resources::response::response::ResponseObject;
+use serde::Deserialize;
+use shai_core::agent::{AgentBuilder, AgentError, AgentEvent, SamplingOverrides};
 use tracing::info;
 use uuid::Uuid;
 
-use crate::{event_to_sse_stream, session_to_sse_stream, ApiJson, ErrorResponse, ServerState};
-use super::types::build_message_trace;
+use crate::{event_to_sse_stream, run_to_sse_stream, AgentRun, ApiJson, ErrorResponse, ModelOverride, OpenAiError, ServerState, AgentSession, EventFormatter};
+use crate::keepalive::padded_json_response;
+use crate::replay::{ReplayDescriptor, SamplingParams, REPLAY_ID_HEADER};
+use crate::apis::openai::user::validate_user;
+use crate::features::Features;
+use crate::rules::RuleRoute;
+use crate::session::{RequestOverrides, SessionAttributes, SessionPersist};
+use super::types::{build_input_items, build_message_trace, has_image_input, ResponseEventData, ResponseEventType, ResponseStreamEvent};
 use super::formatter::ResponseFormatter;
+use super::stored::{ResponseDeleted, StoredResponse};
 
 /// POST /v1/responses - Create a model response
 /// Supports both stateful (store=true, previous_response_id) and stateless (store=false) modes
+/// Every response gets a new id, a response stored with store=true is persisted like a session
+/// (same folder, deleted with DELETE /v1/responses/{id} or /v1/sessions/{id}) and can be continued with previous_response_id
 pub async fn handle_response(
     State(state): State<ServerState>,
-    ApiJson(payload): ApiJson<ResponseParameters>,
-) -> Result<Response, ErrorResponse> {
+    Extension(features): Extension<Features>,
+    ModelOverride(model): ModelOverride,
+    headers: HeaderMap,
+    payload: Result<ApiJson<ResponseParameters>, ErrorResponse>,
+) -> Result<Response, OpenAiError> {
+    let ApiJson(mut payload) = payload?;
     let request_id = Uuid::new_v4();
+
+    // Compatibility rules run before anything reads the parameters
+    state.rules.apply(RuleRoute::Responses, &request_id.to_string(), &headers, &mut payload)?;
+    // Checked after the rules so that rewritten models are the ones allowed
+    state.config.api_keys.authorize(&headers, &mut payload.model, &state.config.models)?;
+    let (agent_name, route) = state.config.models.route(&payload.model)?;
     let store = payload.store.unwrap_or(true);
-    let session_id = payload.previous_response_id.clone()
-        .unwrap_or_else(|| format!("resp_{}", Uuid::new_v4()));
+    let session_id = format!("resp_{}", Uuid::new_v4());
+
+    let user = validate_user(payload.user.as_deref())?;
+    info!("[{}] POST /v1/responses session={} previous={} store={} stream={} user={}",
+        request_id, session_id, payload.previous_response_id.as_deref().unwrap_or("-"), store,
+        payload.stream.unwrap_or(false), user.as_deref().unwrap_or("-"));
 
-    info!("[{}] POST /v1/responses session={} store={} stream={}",
-        request_id, session_id, store, payload.stream.unwrap_or(false));
+    // Sampling parameters of the request are used over the ones of the agent
+    let sampling = SamplingOverrides {
+        temperature: payload.temperature,
+        top_p: payload.top_p,
+        max_tokens: payload.max_output_tokens,
+        ..Default::default()
+    };
+    let overrides = RequestOverrides { route, model, sampling, owner: state.config.auth.session_owner(&headers) };
+
+    if has_image_input(&payload) {
+        check_image_support(&agent_name, &overrides).await?;
+    }
+
+    // Replay descriptor, its id is returned in the response metadata and header
+    let replay = ReplayDescriptor::capture(
+        "responses",
+        &request_id.to_string(),
+        &session_id,
+        &payload.model,
+        &build_message_trace(&payload),
+        SamplingParams {
+            temperature: payload.temperature,
+            top_p: payload.top_p,
+            max_tokens: payload.max_output_tokens,
+        },
+    ).await;
+    state.session_manager.record_replay(&replay);
+    payload.metadata.get_or_insert_with(Default::default).insert("replay_id".to_string(), replay.id.clone());
 
     // Check if streaming is requested
-    if payload.stream.unwrap_or(false) {
-        handle_response_stream(state, payload, request_id, session_id, !store).await
+    let mut response = if payload.stream.unwrap_or(false) {
+        handle_response_stream(state, payload, request_id, session_id, !store, features, overrides).await?
     } else {
-        handle_response_non_stream(state, payload, request_id, session_id, !store).await
+        handle_response_non_stream(state, payload, request_id, session_id, !store, features, overrides).await?
+    };
+    if let Ok(value) = HeaderValue::from_str(&replay.id) {
+        response.headers_mut().insert(REPLAY_ID_HEADER, value);
     }
+    Ok(response)
+}
+
+/// Images are rejected upfront when the model of the agent cannot read them, rather than dropped from the input
+async fn check_image_support(agent_name: &str, overrides: &RequestOverrides) -> Result<(), ErrorResponse> {
+    let resolve_error = |e: AgentError| ErrorResponse::internal_error(format!("Failed to resolve the model of '{}': {}", agent_name, e));
+    let (llm_client, configured_model) = match &overrides.route {
+        Some(route) => (AgentBuilder::provider_llm(&route.provider).map_err(resolve_error)?, route.model.clone()),
+        None => {
+            let config_name = Some(agent_name.to_string()).filter(|name| name != "default");
+            AgentBuilder::llm(config_name).await.map_err(resolve_error)?
+        }
+    };
+    let model = overrides.model.clone().unwrap_or(configured_model);
+
+    if !llm_client.supports_images(&model) {
+        return Err(ErrorResponse::images_not_supported(format!(
+            "Model '{}' ({}) of '{}' does not support image input", model, llm_client.provider_name(), agent_name
+        )));
+    }
+    Ok(())
+}
+
+/// Create the session backing a response
+/// With previous_response_id it starts from the trace of that response, which must exist (in memory or disk)
+async fn resolve_session(
+    state: &ServerState,
+    payload: &ResponseParameters,
+    request_id: &Uuid,
+    session_id: &str,
+    is_ephemeral: bool,
+    features: Features,
+    overrides: RequestOverrides,
+) -> Result<Arc<AgentSession>, ErrorResponse> {
+    let (agent_name, _) = state.config.models.route(&payload.model)?;
+    let session = if let Some(previous_response_id) = &payload.previous_response_id {
+        state.session_manager
+            .continue_session(&request_id.to_string(), previous_response_id, session_id, Some(agent_name), overrides, is_ephemeral)
+            .await
+            .map_err(|e| match e {
+                AgentError::SessionLimitReached { limit } => ErrorResponse::session_limit(limit),
+                e => ErrorResponse::invalid_request(format!("Cannot continue previous response: {}", e)),
+            })?
+    } else {
+        let attributes = SessionAttributes { features: Some(features), overrides, ..Default::default() };
+        state.session_manager
+            .create_new_session_with(&request_id.to_string(), session_id, Some(agent_name), is_ephemeral, attributes)
+            .await
+            .map_err(ErrorResponse::session_not_created)?
+    };
+
+    // Follow-up requests without `user` keep the one already attached to the session
+    if payload.user.is_some() {
+        session.set_user(payload.user.clone());
+    }
+    Ok(session)
 }
 
 /// Handle streaming response
@@ -40,68 +153,148 @@ async fn handle_response_stream(
     request_id: Uuid,
     session_id: String,
     is_ephemeral: bool,
+    features: Features,
+    overrides: RequestOverrides,
 ) -> Result<Response, ErrorResponse> {
     let trace = build_message_trace(&payload);
     let model = payload.model.clone();
 
-    // Get or create session agent based on whether previous_response_id was provided
-    let agent_session = if payload.previous_response_id.is_some() {
-        // previous_response_id provided -> must exist (in memory or disk), error if not
-        state.session_manager
-            .get_session(&request_id.to_string(), &session_id, model.clone())
-            .await
-            .map_err(|e| ErrorResponse::invalid_request(format!("Previous response not found: {}", e)))?
-    } else {
-        // No previous_response_id -> create new session
-        state.session_manager
-            .create_new_session(&request_id.to_string(), &session_id, Some(model.clone()), is_ephemeral)
-            .await
-            .map_err(|e| ErrorResponse::internal_error(format!("Failed to create session: {}", e)))?
-    };
-
+    let agent_session = resolve_session(&state, &payload, &request_id, &session_id, is_ephemeral, features, overrides).await?;
     // Create request session
     let request_session = agent_session
-        .handle_request(&request_id.to_string(), trace)
+        .handle_request(&request_id.to_string(), trace, features)
         .await
-        .map_err(|e| ErrorResponse::internal_error(format!("Failed to handle request: {}", e)))?;
+        .map_err(ErrorResponse::request_failed)?;
+    // a refused request (busy session, quota) leaves no input items
+    agent_session.record_input_items(build_input_items(&payload));
 
     // Create the formatter for OpenAI Response API
     let formatter = ResponseFormatter::new(model, payload);
 
-    // Create SSE stream
-    let stream = session_to_sse_stream(request_session, formatter, session_id, true);
+    // Create SSE stream, the agent keeps running if the client disconnects (see GET/cancel)
+    let run = AgentRun::new(request_session, session_id.clone(), state.config.run_options());
+    let stream = run_to_sse_stream(run, formatter, session_id);
 
     Ok(Sse::new(stream).into_response())
 }
 
 /// Handle non-streaming response
+/// Optionally pads the body with whitespace while the agent runs (see ServerConfig::keepalive_padding)
 async fn handle_response_non_stream(
-    _state: ServerState,
-    _payload: ResponseParameters,
-    _request_id: Uuid,
-    _session_id: String,
-    _is_ephemeral: bool,
+    state: ServerState,
+    payload: ResponseParameters,
+    request_id: Uuid,
+    session_id: String,
+    is_ephemeral: bool,
+    features: Features,
+    overrides: RequestOverrides,
 ) -> Result<Response, ErrorResponse> {
-    return Err(ErrorResponse::internal_error("Response API (non-stream) not yet implemented".to_string()));
+    match state.config.keepalive_padding {
+        Some(interval) => {
+            let work = collect_response(state, payload, request_id, session_id, is_ephemeral, features, overrides);
+            Ok(padded_json_response(interval, work))
+        }
+        None => {
+            let response = collect_response(state, payload, request_id, session_id, is_ephemeral, features, overrides).await?;
+            Ok(Json(response).into_response())
+        }
+    }
 }
 
+/// Run the agent until the formatter produces the final ResponseObject
+async fn collect_response(
+    state: ServerState,
+    payload: ResponseParameters,
+    request_id: Uuid,
+    session_id: String,
+    is_ephemeral: bool,
+    features: Features,
+    overrides: RequestOverrides,
+) -> Result<ResponseObject, ErrorResponse> {
+    let trace = build_message_trace(&payload);
+    let model = payload.model.clone();
+
+    let agent_session = resolve_session(&state, &payload, &request_id, &session_id, is_ephemeral, features, overrides).await?;
+    let request_session = agent_session
+        .handle_request(&request_id.to_string(), trace, features)
+        .await
+        .map_err(ErrorResponse::request_failed)?;
+    // a refused request (busy session, quota) leaves no input items
+    agent_session.record_input_items(build_input_items(&payload));
+
+    let mut formatter = ResponseFormatter::new(model, payload);
+    let mut run = AgentRun::new(request_session, session_id.clone(), state.config.run_options());
+
+    while let Some(event) = run.next_event().await {
+        if let AgentEvent::BrainResult { thought: Err(AgentError::EmptyCompletion(attempts)), .. } = &event {
+            return Err(ErrorResponse::empty_completion(format!(
+                "The model returned an empty completion after {} attempts", attempts
+            )));
+        }
+        if let Some(ResponseStreamEvent { event_type: ResponseEventType::ResponseCompleted, data }) =
+            formatter.format_event(event, &session_id).await
+        {
+            if let ResponseEventData::Response { response, .. } = data {
+                // record the files changed by the run before releasing it
+                let _ = run.workspace_changes().await;
+                return Ok(response);
+            }
+        }
+    }
+
+    Err(run.stop_reason()
+        .and_then(|reason| reason.to_error())
+        .unwrap_or_else(|| ErrorResponse::internal_error("Agent stopped before completing the response".to_string())))
+}
+
+
+/// Query parameters of GET /v1/responses/{response_id}
+#[derive(Debug, Default, Deserialize)]
+pub struct GetResponseQuery {
+    /// Follow the events of the response (true) or return the stored one (false)
+    /// Without it a response still in progress is followed, as this route always did, any other is returned
+    #[serde(default)]
+    pub stream: Option<bool>,
+}
 
 /// GET /v1/responses/{response_id} - Retrieve a model response
-/// Read-only access to an ongoing or completed session
+/// The response is rebuilt from the session stored under its id (in memory, else on disk),
+/// a response still in progress streams its events unless `?stream=false`
 pub async fn handle_get_response(
     State(state): State<ServerState>,
     Path(response_id): Path<String>,
+    Query(query): Query<GetResponseQuery>,
 ) -> Result<Response, ErrorResponse> {
     let request_id = Uuid::new_v4();
-    info!("[{}] GET /v1/responses/{}", request_id, response_id);
+    let session = state.session_manager.find_session(&response_id).await;
+    let stream = query.stream.unwrap_or_else(|| session.as_ref().is_some_and(|session| session.is_busy()));
+    info!("[{}] GET /v1/responses/{} (stream: {})", request_id, response_id, stream);
+
+    if stream {
+        return watch_response(state, request_id, response_id).await;
+    }
 
+    let stored = match session {
+        Some(session) => StoredResponse::of_session(&session)
+            .await
+            .map_err(|e| ErrorResponse::internal_error(format!("Failed to read response: {}", e)))?,
+        None => SessionPersist::load_session_from(&*state.session_store, &response_id)
+            .await
+            .map(StoredResponse::from)
+            .map_err(|_| ErrorResponse::not_found(format!("Response not found: {}", response_id)))?,
+    };
+    Ok(Json(stored.into_response_object(&response_id)).into_response())
+}
+
+/// Events of an ongoing response
+async fn watch_response(state: ServerState, request_id: Uuid, response_id: String) -> Result<Response, ErrorResponse> {
     // Get the existing session (note: without agent_name, will only check memory, not disk)
     // For GET we don't have the model from request, so we use the session's agent_name
     // This means GET can only access in-memory sessions
     let agent_session = state.session_manager
         .get_session(&request_id.to_string(), &response_id, "default".to_string())
         .await
-        .map_err(|e| ErrorResponse::invalid_request(format!("Response not found: {}", e)))?;
+        .map_err(|e| ErrorResponse::not_found(format!("Response not found: {}", e)))?;
 
     // Subscribe to events (non-blocking, read-only)
     let event_rx = agent_session.watch();
@@ -123,6 +316,25 @@ pub async fn handle_get_response(
     Ok(Sse::new(stream).into_response())
 }
 
+/// DELETE /v1/responses/{response_id} - Delete a stored response
+/// Its session is terminated and its persisted data removed for good, there is no trash
+pub async fn handle_delete_response(
+    State(state): State<ServerState>,
+    Path(response_id): Path<String>,
+) -> Result<Json<ResponseDeleted>, ErrorResponse> {
+    let request_id = Uuid::new_v4().to_string();
+    info!("[{}] DELETE /v1/responses/{}", request_id, response_id);
+
+    let found = state.session_manager
+        .purge_session(&request_id, &response_id)
+        .await
+        .map_err(|e| ErrorResponse::internal_error(format!("Failed to delete response: {}", e)))?;
+
+    match found {
+        true => Ok(Json(ResponseDeleted::new(response_id))),
+        false => Err(ErrorResponse::not_found(format!("Response not found: {}", response_id))),
+    }
+}
 
 /// POST /v1/responses/{response_id}/cancel - Cancel a model response
 pub async fn handle_cancel_response(
@@ -0,0 +1,183 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::session::SessionPersist;
+use crate::{ErrorResponse, ServerState};
+
+const DEFAULT_LIMIT: usize = 20;
+const MAX_LIMIT: usize = 100;
+
+/// `include[]` value expanding image payloads of input_image parts
+const INCLUDE_IMAGE_URL: &str = "message.input_image.image_url";
+
+/// Page of input items (OpenAI list object)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InputItemList {
+    pub object: String,
+    pub data: Vec<Value>,
+    pub first_id: Option<String>,
+    pub last_id: Option<String>,
+    pub has_more: bool,
+}
+
+/// Query parameters: limit, after, include[] (repeatable)
+struct InputItemsQuery {
+    limit: usize,
+    after: Option<String>,
+    include_image_url: bool,
+}
+
+impl InputItemsQuery {
+    fn parse(pairs: Vec<(String, String)>) -> Result<Self, ErrorResponse> {
+        let mut query = Self { limit: DEFAULT_LIMIT, after: None, include_image_url: false };
+        for (key, value) in pairs {
+            match key.as_str() {
+                "limit" => {
+                    query.limit = value.parse::<usize>()
+                        .ok()
+                        .filter(|limit| (1..=MAX_LIMIT).contains(limit))
+                        .ok_or_else(|| ErrorResponse::invalid_request(format!("limit must be between 1 and {}", MAX_LIMIT)))?;
+                }
+                "after" => query.after = Some(value),
+                "include" | "include[]" => {
+                    if value == INCLUDE_IMAGE_URL {
+                        query.include_image_url = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(query)
+    }
+}
+
+fn item_id(item: &Value) -> Option<String> {
+    item.get("id").and_then(Value::as_str).map(str::to_string)
+}
+
+/// Reference images instead of returning their URL or base64 payload
+fn strip_image_payloads(item: &mut Value) {
+    let Some(parts) = item.get_mut("content").and_then(Value::as_array_mut) else {
+        return;
+    };
+    for part in parts {
+        if part.get("type").and_then(Value::as_str) == Some("input_image") {
+            if let Some(part) = part.as_object_mut() {
+                part.remove("image_url");
+            }
+        }
+    }
+}
+
+/// Cursor pagination over items in their original order
+fn paginate(items: Vec<Value>, query: &InputItemsQuery) -> Result<InputItemList, ErrorResponse> {
+    let start = match &query.after {
+        Some(after) => items
+            .iter()
+            .position(|item| item_id(item).as_deref() == Some(after.as_str()))
+            .map(|index| index + 1)
+            .ok_or_else(|| ErrorResponse::invalid_request(format!("Unknown cursor: {}", after)))?,
+        None => 0,
+    };
+
+    let has_more = items.len() > start + query.limit;
+    let mut data: Vec<Value> = items.into_iter().skip(start).take(query.limit).collect();
+    if !query.include_image_url {
+        data.iter_mut().for_each(strip_image_payloads);
+    }
+
+    Ok(InputItemList {
+        object: "list".to_string(),
+        first_id: data.first().and_then(item_id),
+        last_id: data.last().and_then(item_id),
+        data,
+        has_more,
+    })
+}
+
+/// GET /v1/responses/{response_id}/input_items - List the input items of a stored response
+pub async fn handle_list_input_items(
+    State(state): State<ServerState>,
+    Path(response_id): Path<String>,
+    Query(pairs): Query<Vec<(String, String)>>,
+) -> Result<Json<InputItemList>, ErrorResponse> {
+    let request_id = Uuid::new_v4();
+    info!("[{}] GET /v1/responses/{}/input_items", request_id, response_id);
+
+    let query = InputItemsQuery::parse(pairs)?;
+
+    // In-memory session first, then the persisted one (without restoring its agent)
+    let items = match state.session_manager.find_session(&response_id).await {
+        Some(session) => session.input_items(),
+        None => SessionPersist::load_session_from(&*state.session_store, &response_id)
+            .await
+            .map_err(|_| ErrorResponse::not_found(format!("Response not found: {}", response_id)))?
+            .input_items,
+    };
+
+    paginate(items, &query).map(Json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn items(count: usize) -> Vec<Value> {
+        (0..count).map(|i| json!({ "id": format!("msg_{}", i), "type": "message", "role": "user" })).collect()
+    }
+
+    fn query(pairs: &[(&str, &str)]) -> InputItemsQuery {
+        InputItemsQuery::parse(pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_cursor_pagination() {
+        let first = paginate(items(5), &query(&[("limit", "2")])).unwrap();
+        assert_eq!(first.first_id.as_deref(), Some("msg_0"));
+        assert_eq!(first.last_id.as_deref(), Some("msg_1"));
+        assert!(first.has_more);
+
+        let last = paginate(items(5), &query(&[("limit", "2"), ("after", "msg_3")])).unwrap();
+        assert_eq!(last.data.len(), 1);
+        assert_eq!(last.first_id.as_deref(), Some("msg_4"));
+        assert!(!last.has_more);
+
+        assert!(paginate(items(5), &query(&[("after", "msg_missing")])).is_err());
+        assert!(InputItemsQuery::parse(vec![("limit".to_string(), "0".to_string())]).is_err());
+    }
+
+    #[test]
+    fn test_empty_for_older_responses() {
+        let page = paginate(Vec::new(), &query(&[])).unwrap();
+        assert!(page.data.is_empty());
+        assert!(page.first_id.is_none());
+        assert!(!page.has_more);
+    }
+
+    #[test]
+    fn test_image_payloads_need_include() {
+        let item = json!({
+            "id": "msg_0",
+            "type": "message",
+            "role": "user",
+            "content": [
+                { "type": "input_text", "text": "what is this?" },
+                { "type": "input_image", "image_url": "data:image/png;base64,AAAA", "detail": "auto" },
+            ],
+        });
+
+        let referenced = paginate(vec![item.clone()], &query(&[])).unwrap();
+        assert!(referenced.data[0]["content"][1].get("image_url").is_none());
+        assert_eq!(referenced.data[0]["content"][1]["detail"], "auto");
+
+        let expanded = paginate(vec![item], &query(&[("include[]", INCLUDE_IMAGE_URL)])).unwrap();
+        assert_eq!(expanded.data[0]["content"][1]["image_url"], "data:image/png;base64,AAAA");
+    }
+}
@@ -1,5 +1,8 @@
 pub mod handler;
 pub mod types;
 pub mod formatter;
+pub mod input_items;
+pub mod stored;
 
-pub use handler::{handle_response, handle_get_response, handle_cancel_response};
\ No newline at end of file
+pub use handler::{handle_response, handle_get_response, handle_delete_response, handle_cancel_response};
+pub use input_items::handle_list_input_items;
\ No newline at end of file
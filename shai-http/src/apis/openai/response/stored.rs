@@ -0,0 +1,193 @@
+use chrono::{DateTime, Utc};
+use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent};
+use openai_dive::v1::resources::response::{
+    items::{FunctionToolCall, InputItemStatus},
+    request::ResponseParameters,
+    response::{MessageStatus, OutputContent, OutputMessage, ReasoningStatus, ResponseObject, ResponseOutput, Role},
+};
+use serde::{Deserialize, Serialize};
+use shai_core::agent::AgentError;
+
+use crate::access::DEFAULT_AGENT;
+use crate::run::{RunSummary, RunTerminalReason, RunUsage};
+use crate::session::SessionData;
+use crate::AgentSession;
+use super::formatter::ResponseFormatter;
+
+/// Response of DELETE /v1/responses/{response_id}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseDeleted {
+    pub id: String,
+    pub object: String,
+    pub deleted: bool,
+}
+
+impl ResponseDeleted {
+    pub fn new(id: String) -> Self {
+        Self { id, object: "response".to_string(), deleted: true }
+    }
+}
+
+/// A response read back from the session stored under its id
+pub struct StoredResponse {
+    pub model: String,
+    pub created_at: DateTime<Utc>,
+    pub trace: Vec<ChatMessage>,
+    pub run_summaries: Vec<RunSummary>,
+    /// A request is still running on the session
+    pub in_progress: bool,
+}
+
+impl From<SessionData> for StoredResponse {
+    fn from(data: SessionData) -> Self {
+        Self {
+            model: data.attributes.agent_name.unwrap_or_else(|| DEFAULT_AGENT.to_string()),
+            created_at: data.created_at,
+            trace: data.trace,
+            run_summaries: data.run_summaries,
+            in_progress: false,
+        }
+    }
+}
+
+impl StoredResponse {
+    /// Snapshot of a session still in memory, the output of a running request is not read
+    pub async fn of_session(session: &AgentSession) -> Result<Self, AgentError> {
+        let in_progress = session.is_busy();
+        let trace = match in_progress {
+            true => Vec::new(),
+            false => session.trace().await?,
+        };
+        let run_summaries = session.run_summaries();
+        Ok(Self {
+            model: session.agent_name.clone(),
+            created_at: run_summaries.first().map_or_else(Utc::now, |summary| summary.started_at),
+            trace,
+            run_summaries,
+            in_progress,
+        })
+    }
+
+    /// The response object as it was returned when the last request of the session ended
+    pub fn into_response_object(self, response_id: &str) -> ResponseObject {
+        let last_run = self.run_summaries.last();
+        let status = match (self.in_progress, last_run.map(|summary| summary.terminal_reason)) {
+            (true, _) => ReasoningStatus::InProgress,
+            (false, None | Some(RunTerminalReason::Completed)) => ReasoningStatus::Completed,
+            (false, Some(RunTerminalReason::Failed | RunTerminalReason::Closed)) => ReasoningStatus::Failed,
+            (false, Some(_)) => ReasoningStatus::Incomplete,
+        };
+        let usage = last_run.map_or_else(RunUsage::default, |summary| RunUsage {
+            input_tokens: summary.input_tokens,
+            output_tokens: summary.output_tokens,
+        });
+
+        let payload = ResponseParameters { model: self.model.clone(), ..Default::default() };
+        let mut formatter = ResponseFormatter::new(self.model, payload);
+        formatter.created_at = self.created_at.timestamp() as u32;
+        formatter.usage = usage;
+        formatter.build_response_object(response_id, status, output_items(&self.trace))
+    }
+}
+
+/// Output items of the last request of a trace: what the agent produced after the last user message
+/// The text of an assistant message comes before its tool calls, as when it was streamed
+fn output_items(trace: &[ChatMessage]) -> Vec<ResponseOutput> {
+    let start = trace
+        .iter()
+        .rposition(|message| matches!(message, ChatMessage::User { .. }))
+        .map_or(0, |index| index + 1);
+
+    let mut output = Vec::new();
+    for message in &trace[start..] {
+        let ChatMessage::Assistant { content, tool_calls, .. } = message else {
+            continue;
+        };
+        if let Some(ChatMessageContent::Text(text)) = content {
+            if !text.trim().is_empty() {
+                output.push(ResponseOutput::Message(OutputMessage {
+                    id: format!("msg_{}", output.len()),
+                    role: Role::Assistant,
+                    status: MessageStatus::Completed,
+                    content: vec![OutputContent::Text { text: text.clone(), annotations: vec![] }],
+                }));
+            }
+        }
+        for call in tool_calls.iter().flatten() {
+            output.push(ResponseOutput::FunctionToolCall(FunctionToolCall {
+                id: call.id.clone(),
+                call_id: call.id.clone(),
+                name: call.function.name.clone(),
+                arguments: call.function.arguments.clone(),
+                status: InputItemStatus::Completed,
+            }));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai_dive::v1::resources::chat::{Function, ToolCall};
+
+    fn user(text: &str) -> ChatMessage {
+        ChatMessage::User { content: ChatMessageContent::Text(text.to_string()), name: None }
+    }
+
+    fn assistant(text: &str, tool_calls: Option<Vec<ToolCall>>) -> ChatMessage {
+        ChatMessage::Assistant {
+            content: Some(ChatMessageContent::Text(text.to_string())),
+            reasoning_content: None,
+            refusal: None,
+            name: None,
+            audio: None,
+            tool_calls,
+        }
+    }
+
+    #[test]
+    fn test_output_of_the_last_request() {
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            r#type: "function".to_string(),
+            function: Function { name: "read".to_string(), arguments: "{\"path\":\"a.txt\"}".to_string() },
+        };
+        let trace = vec![
+            user("hi"),
+            assistant("hello", None),
+            user("read a.txt"),
+            assistant("Let me read it.", Some(vec![call])),
+            ChatMessage::Tool { content: ChatMessageContent::Text("content".to_string()), tool_call_id: "call_1".to_string() },
+            assistant("It says content.", None),
+        ];
+
+        let output = output_items(&trace);
+        assert_eq!(output.len(), 3);
+        assert!(matches!(&output[0], ResponseOutput::Message(message) if message.id == "msg_0"));
+        let ResponseOutput::FunctionToolCall(call) = &output[1] else {
+            panic!("expected a function call, got {:?}", output[1]);
+        };
+        assert_eq!((call.call_id.as_str(), call.name.as_str()), ("call_1", "read"));
+        let ResponseOutput::Message(message) = &output[2] else {
+            panic!("expected a message, got {:?}", output[2]);
+        };
+        assert!(matches!(&message.content[0], OutputContent::Text { text, .. } if text == "It says content."));
+    }
+
+    #[test]
+    fn test_status_of_the_last_run() {
+        let stored = StoredResponse {
+            model: "coder".to_string(),
+            created_at: Utc::now(),
+            trace: vec![user("hi"), assistant("hello", None)],
+            run_summaries: Vec::new(),
+            in_progress: false,
+        };
+        let response = stored.into_response_object("resp_1");
+        assert_eq!(response.id, "resp_1");
+        assert_eq!(response.model, "coder");
+        assert!(matches!(response.status, ReasoningStatus::Completed));
+        assert_eq!(response.output.len(), 1);
+    }
+}
@@ -6,9 +6,11 @@
 use serde::{Deserialize, Serialize};
 use openai_dive::v1::resources::response::{
     request::{ContentInput, ContentItem, ResponseInput, ResponseInputItem, ResponseParameters},
-    response::{ResponseObject, ResponseOutput, Role},
+    response::{OutputContent, ResponseObject, ResponseOutput, Role},
 };
 use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent};
+use serde_json::{json, Value};
+use uuid::Uuid;
 
 /// Base streaming event structure
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -31,17 +33,29 @@ pub enum ResponseEventType {
     ResponseOutputItemAdded,
     #[serde(rename = "response.output_item.done")]
     ResponseOutputItemDone,
+    #[serde(rename = "response.content_part.added")]
+    ResponseContentPartAdded,
+    #[serde(rename = "response.content_part.done")]
+    ResponseContentPartDone,
     #[serde(rename = "response.output_text.delta")]
     ResponseOutputTextDelta,
+    #[serde(rename = "response.output_text.done")]
+    ResponseOutputTextDone,
+    #[serde(rename = "response.function_call_arguments.delta")]
+    ResponseFunctionCallArgumentsDelta,
+    #[serde(rename = "response.function_call_arguments.done")]
+    ResponseFunctionCallArgumentsDone,
     #[serde(rename = "response.completed")]
     ResponseCompleted,
+    #[serde(rename = "response.failed")]
+    ResponseFailed,
 }
 
 /// Event data for streaming events
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum ResponseEventData {
-    /// response.created, response.in_progress, response.completed
+    /// response.created, response.in_progress, response.completed, response.failed
     Response {
         sequence_number: u32,
         response: ResponseObject,
@@ -60,6 +74,36 @@ pub enum ResponseEventData {
         content_index: usize,
         delta: String,
     },
+    /// response.output_text.done
+    TextDone {
+        sequence_number: u32,
+        item_id: String,
+        output_index: usize,
+        content_index: usize,
+        text: String,
+    },
+    /// response.content_part.added, response.content_part.done
+    ContentPart {
+        sequence_number: u32,
+        item_id: String,
+        output_index: usize,
+        content_index: usize,
+        part: OutputContent,
+    },
+    /// response.function_call_arguments.delta
+    ArgumentsDelta {
+        sequence_number: u32,
+        item_id: String,
+        output_index: usize,
+        delta: String,
+    },
+    /// response.function_call_arguments.done
+    ArgumentsDone {
+        sequence_number: u32,
+        item_id: String,
+        output_index: usize,
+        arguments: String,
+    },
 }
 
 impl ResponseStreamEvent {
@@ -129,6 +173,102 @@ impl ResponseStreamEvent {
         }
     }
 
+    /// Create a response.output_text.done event
+    pub fn output_text_done(
+        sequence_number: u32,
+        item_id: String,
+        output_index: usize,
+        content_index: usize,
+        text: String,
+    ) -> Self {
+        Self {
+            event_type: ResponseEventType::ResponseOutputTextDone,
+            data: ResponseEventData::TextDone {
+                sequence_number,
+                item_id,
+                output_index,
+                content_index,
+                text,
+            },
+        }
+    }
+
+    /// Create a response.content_part.added event
+    pub fn content_part_added(
+        sequence_number: u32,
+        item_id: String,
+        output_index: usize,
+        content_index: usize,
+        part: OutputContent,
+    ) -> Self {
+        Self {
+            event_type: ResponseEventType::ResponseContentPartAdded,
+            data: ResponseEventData::ContentPart {
+                sequence_number,
+                item_id,
+                output_index,
+                content_index,
+                part,
+            },
+        }
+    }
+
+    /// Create a response.content_part.done event
+    pub fn content_part_done(
+        sequence_number: u32,
+        item_id: String,
+        output_index: usize,
+        content_index: usize,
+        part: OutputContent,
+    ) -> Self {
+        Self {
+            event_type: ResponseEventType::ResponseContentPartDone,
+            data: ResponseEventData::ContentPart {
+                sequence_number,
+                item_id,
+                output_index,
+                content_index,
+                part,
+            },
+        }
+    }
+
+    /// Create a response.function_call_arguments.delta event
+    pub fn function_call_arguments_delta(
+        sequence_number: u32,
+        item_id: String,
+        output_index: usize,
+        delta: String,
+    ) -> Self {
+        Self {
+            event_type: ResponseEventType::ResponseFunctionCallArgumentsDelta,
+            data: ResponseEventData::ArgumentsDelta {
+                sequence_number,
+                item_id,
+                output_index,
+                delta,
+            },
+        }
+    }
+
+    /// Create a response.function_call_arguments.done event
+    pub fn function_call_arguments_done(
+        sequence_number: u32,
+        item_id: String,
+        output_index: usize,
+        arguments: String,
+    ) -> Self {
+        Self {
+            event_type: ResponseEventType::ResponseFunctionCallArgumentsDone,
+            data: ResponseEventData::ArgumentsDone {
+                sequence_number,
+                item_id,
+                output_index,
+                arguments,
+            },
+        }
+    }
+
     /// Create a response.completed event
     pub fn completed(sequence_number: u32, response: ResponseObject) -> Self {
         Self {
@@ -140,6 +280,17 @@ impl ResponseStreamEvent {
         }
     }
 
+    /// Create a response.failed event
+    pub fn failed(sequence_number: u32, response: ResponseObject) -> Self {
+        Self {
+            event_type: ResponseEventType::ResponseFailed,
+            data: ResponseEventData::Response {
+                sequence_number,
+                response,
+            },
+        }
+    }
+
     /// Get the SSE event name for this event
     pub fn event_name(&self) -> &'static str {
         match self.event_type {
@@ -147,12 +298,46 @@ impl ResponseStreamEvent {
             ResponseEventType::ResponseInProgress => "response.in_progress",
             ResponseEventType::ResponseOutputItemAdded => "response.output_item.added",
             ResponseEventType::ResponseOutputItemDone => "response.output_item.done",
+            ResponseEventType::ResponseContentPartAdded => "response.content_part.added",
+            ResponseEventType::ResponseContentPartDone => "response.content_part.done",
             ResponseEventType::ResponseOutputTextDelta => "response.output_text.delta",
+            ResponseEventType::ResponseOutputTextDone => "response.output_text.done",
+            ResponseEventType::ResponseFunctionCallArgumentsDelta => "response.function_call_arguments.delta",
+            ResponseEventType::ResponseFunctionCallArgumentsDone => "response.function_call_arguments.done",
             ResponseEventType::ResponseCompleted => "response.completed",
+            ResponseEventType::ResponseFailed => "response.failed",
         }
     }
 }
 
+/// Input items of a request as stored for GET /v1/responses/{id}/input_items
+/// Plain text input becomes a user message, every item gets a stable id
+pub fn build_input_items(params: &ResponseParameters) -> Vec<Value> {
+    let items = match &params.input {
+        ResponseInput::Text(text) => vec![json!({
+            "type": "message",
+            "role": "user",
+            "content": [{ "type": "input_text", "text": text }],
+        })],
+        ResponseInput::List(items) => items
+            .iter()
+            .filter_map(|item| serde_json::to_value(item).ok())
+            .collect(),
+    };
+
+    items
+        .into_iter()
+        .map(|mut item| {
+            if let Some(object) = item.as_object_mut() {
+                object
+                    .entry("id")
+                    .or_insert_with(|| json!(format!("msg_{}", Uuid::new_v4().simple())));
+            }
+            item
+        })
+        .collect()
+}
+
 /// Convert OpenAI Response API input to ChatMessage trace
 pub fn build_message_trace(params: &ResponseParameters) -> Vec<ChatMessage> {
     let mut trace = Vec::new();
@@ -178,48 +363,14 @@ pub fn build_message_trace(params: &ResponseParameters) -> Vec<ChatMessage> {
                 if let ResponseInputItem::Message(msg) = item {
                     match &msg.role {
                         Role::User => {
-                            // Convert content to text (simplified for now)
-                            let text = match &msg.content {
-                                ContentInput::Text(t) => t.clone(),
-                                ContentInput::List(items) => {
-                                    // For now, just extract text items
-                                    items
-                                        .iter()
-                                        .filter_map(|item| {
-                                            if let ContentItem::Text { text } = item {
-                                                Some(text.clone())
-                                            } else {
-                                                None
-                                            }
-                                        })
-                                        .collect::<Vec<_>>()
-                                        .join("\n")
-                                }
-                            };
                             trace.push(ChatMessage::User {
-                                content: ChatMessageContent::Text(text),
+                                content: user_content(&msg.content),
                                 name: None,
                             });
                         }
                         Role::Assistant => {
-                            let text = match &msg.content {
-                                ContentInput::Text(t) => t.clone(),
-                                ContentInput::List(items) => {
-                                    items
-                                        .iter()
-                                        .filter_map(|item| {
-                                            if let ContentItem::Text { text } = item {
-                                                Some(text.clone())
-                                            } else {
-                                                None
-                                            }
-                                        })
-                                        .collect::<Vec<_>>()
-                                        .join("\n")
-                                }
-                            };
                             trace.push(ChatMessage::Assistant {
-                                content: Some(ChatMessageContent::Text(text)),
+                                content: Some(ChatMessageContent::Text(content_text(&msg.content))),
                                 tool_calls: None,
                                 name: None,
                                 audio: None,
@@ -227,7 +378,13 @@ pub fn build_message_trace(params: &ResponseParameters) -> Vec<ChatMessage> {
                                 refusal: None,
                             });
                         }
-                        _ => {}
+                        // Developer messages are instructions too, as OpenAI treats them
+                        Role::System | Role::Developer => {
+                            trace.push(ChatMessage::System {
+                                content: ChatMessageContent::Text(content_text(&msg.content)),
+                                name: None,
+                            });
+                        }
                     }
                 }
             }
@@ -235,4 +392,146 @@ pub fn build_message_trace(params: &ResponseParameters) -> Vec<ChatMessage> {
     }
 
     trace
-}
\ No newline at end of file
+}
+
+/// Whether a user message of the input holds an image
+pub fn has_image_input(params: &ResponseParameters) -> bool {
+    let ResponseInput::List(items) = &params.input else {
+        return false;
+    };
+    items.iter().any(|item| match item {
+        ResponseInputItem::Message(msg) => matches!(msg.role, Role::User)
+            && matches!(&msg.content, ContentInput::List(content) if content.iter().any(|item| content_type(item) == Some(INPUT_IMAGE))),
+        _ => false,
+    })
+}
+
+const INPUT_TEXT: &str = "input_text";
+const INPUT_IMAGE: &str = "input_image";
+
+/// `type` tag of a content item as sent by the client
+fn content_type(item: &ContentItem) -> Option<&'static str> {
+    match serde_json::to_value(item).ok()?["type"].as_str()? {
+        INPUT_TEXT => Some(INPUT_TEXT),
+        INPUT_IMAGE => Some(INPUT_IMAGE),
+        _ => None,
+    }
+}
+
+/// Text items of a content joined by new lines, other items are left out
+fn content_text(content: &ContentInput) -> String {
+    match content {
+        ContentInput::Text(text) => text.clone(),
+        ContentInput::List(items) => items
+            .iter()
+            .filter_map(|item| match item {
+                ContentItem::Text { text } => Some(text.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// Content of a user message: plain text, or chat content parts when it holds images
+/// Images are passed by URL (data URLs included), images referenced by file_id are left out
+fn user_content(content: &ContentInput) -> ChatMessageContent {
+    let ContentInput::List(items) = content else {
+        return ChatMessageContent::Text(content_text(content));
+    };
+    if !items.iter().any(|item| content_type(item) == Some(INPUT_IMAGE)) {
+        return ChatMessageContent::Text(content_text(content));
+    }
+
+    let parts: Vec<Value> = items
+        .iter()
+        .filter_map(|item| {
+            let item = serde_json::to_value(item).ok()?;
+            match item["type"].as_str()? {
+                INPUT_TEXT => Some(json!({ "type": "text", "text": item["text"] })),
+                INPUT_IMAGE => {
+                    let mut image_url = json!({ "url": item["image_url"].as_str()? });
+                    if let Some(detail) = item["detail"].as_str() {
+                        image_url["detail"] = json!(detail);
+                    }
+                    Some(json!({ "type": "image_url", "image_url": image_url }))
+                }
+                _ => None,
+            }
+        })
+        .collect();
+
+    match serde_json::from_value(Value::Array(parts)) {
+        Ok(parts) => ChatMessageContent::ContentPart(parts),
+        Err(_) => ChatMessageContent::Text(content_text(content)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(input: Value) -> ResponseParameters {
+        serde_json::from_value(json!({ "model": "default", "input": input })).unwrap()
+    }
+
+    #[test]
+    fn test_text_and_image_items_become_content_parts() {
+        let params = params(json!([{
+            "type": "message",
+            "role": "user",
+            "content": [
+                { "type": "input_text", "text": "What is in this picture?" },
+                { "type": "input_image", "image_url": "data:image/png;base64,AAAA", "detail": "low" },
+            ],
+        }]));
+        assert!(has_image_input(&params));
+
+        let trace = build_message_trace(&params);
+        assert_eq!(trace.len(), 1);
+        let ChatMessage::User { content: ChatMessageContent::ContentPart(parts), .. } = &trace[0] else {
+            panic!("expected content parts, got {:?}", trace[0]);
+        };
+        let parts = serde_json::to_value(parts).unwrap();
+        assert_eq!(parts[0]["type"], "text");
+        assert_eq!(parts[0]["text"], "What is in this picture?");
+        assert_eq!(parts[1]["type"], "image_url");
+        assert_eq!(parts[1]["image_url"]["url"], "data:image/png;base64,AAAA");
+        assert_eq!(parts[1]["image_url"]["detail"], "low");
+    }
+
+    #[test]
+    fn test_instruction_roles_keep_their_place() {
+        let params = params(json!([
+            { "type": "message", "role": "system", "content": "Answer in French." },
+            { "type": "message", "role": "user", "content": "hello" },
+            { "type": "message", "role": "assistant", "content": "bonjour" },
+            { "type": "message", "role": "developer", "content": [{ "type": "input_text", "text": "Be brief." }] },
+            { "type": "message", "role": "user", "content": "how are you?" },
+        ]));
+
+        let trace = build_message_trace(&params);
+        assert_eq!(trace.len(), 5);
+        assert!(matches!(&trace[0], ChatMessage::System { content: ChatMessageContent::Text(text), .. } if text == "Answer in French."));
+        assert!(matches!(&trace[1], ChatMessage::User { content: ChatMessageContent::Text(text), .. } if text == "hello"));
+        assert!(matches!(&trace[2], ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. } if text == "bonjour"));
+        assert!(matches!(&trace[3], ChatMessage::System { content: ChatMessageContent::Text(text), .. } if text == "Be brief."));
+        assert!(matches!(&trace[4], ChatMessage::User { content: ChatMessageContent::Text(text), .. } if text == "how are you?"));
+    }
+
+    #[test]
+    fn test_text_only_items_stay_plain_text() {
+        let params = params(json!([{
+            "type": "message",
+            "role": "user",
+            "content": [
+                { "type": "input_text", "text": "hello" },
+                { "type": "input_text", "text": "world" },
+            ],
+        }]));
+        assert!(!has_image_input(&params));
+
+        let trace = build_message_trace(&params);
+        assert!(matches!(&trace[0], ChatMessage::User { content: ChatMessageContent::Text(text), .. } if text == "hello\nworld"));
+    }
+}
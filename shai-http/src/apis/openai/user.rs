@@ -0,0 +1,44 @@
+use crate::ErrorResponse;
+
+/// Longest accepted `user` value
+const MAX_USER_LEN: usize = 256;
+
+/// Validate the OpenAI `user` field (end-user identifier supplied by the caller)
+/// Only a conservative charset is accepted so the value is safe in logs and identifiers
+pub fn validate_user(user: Option<&str>) -> Result<Option<String>, ErrorResponse> {
+    let Some(user) = user else {
+        return Ok(None);
+    };
+
+    if user.is_empty() || user.len() > MAX_USER_LEN {
+        return Err(ErrorResponse::invalid_request(format!(
+            "Invalid 'user': must be between 1 and {} characters",
+            MAX_USER_LEN
+        )));
+    }
+
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@' | ':' | '+' | '=');
+    if !user.chars().all(valid_char) {
+        return Err(ErrorResponse::invalid_request(
+            "Invalid 'user': only ASCII letters, digits and '-_.@:+=' are allowed".to_string(),
+        ));
+    }
+
+    Ok(Some(user.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_user() {
+        assert_eq!(validate_user(None).unwrap(), None);
+        assert_eq!(validate_user(Some("user-42@acme.io")).unwrap(), Some("user-42@acme.io".to_string()));
+
+        assert!(validate_user(Some("")).is_err());
+        assert!(validate_user(Some(&"a".repeat(MAX_USER_LEN + 1))).is_err());
+        assert!(validate_user(Some("../../etc/passwd")).is_err());
+        assert!(validate_user(Some("john doe")).is_err());
+    }
+}
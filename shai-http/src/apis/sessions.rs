@@ -0,0 +1,553 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::StreamExt;
+use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::auth::constant_time_eq;
+use crate::quota::QuotaUsage;
+use crate::session::{SessionPersist, TranscriptRenderer};
+use crate::workspace::WorkspaceChanges;
+use crate::{ErrorResponse, OpenAiError, ServerState};
+#[cfg(feature = "git")]
+use crate::git::{CheckpointCommit, CheckpointError};
+
+/// Header carrying the admin token of admin-scoped operations
+pub const ADMIN_TOKEN_HEADER: &str = "x-shai-admin-token";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DeleteSessionQuery {
+    /// Skip the trash and remove the session for good (admin only), also accepted as `?purge=true`
+    #[serde(default, alias = "purge")]
+    pub permanent: bool,
+    /// Terminate the session even while it processes a request, the request ends with it
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Result of a session deletion or restoration
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionStatus {
+    pub id: String,
+    pub object: String,
+    /// "trashed", "deleted" (permanently) or "restored"
+    pub status: String,
+}
+
+impl SessionStatus {
+    fn new(id: String, status: &str) -> Self {
+        Self { id, object: "session".to_string(), status: status.to_string() }
+    }
+}
+
+/// State of a session loaded in memory
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionDetail {
+    pub id: String,
+    pub object: String,
+    pub agent: String,
+    pub ephemeral: bool,
+    /// A request is running on the session
+    pub busy: bool,
+    /// Requests waiting for the running one to end
+    #[serde(default)]
+    pub queued_requests: usize,
+    pub idle_secs: u64,
+    /// Idle time after which the session is evicted (None = never)
+    pub ttl_secs: Option<u64>,
+    /// Session environment, values hidden
+    pub env: HashMap<String, String>,
+    pub quota: QuotaUsage,
+}
+
+/// Check the admin token sent with the request against the server's
+pub(crate) fn require_admin(state: &ServerState, headers: &HeaderMap) -> Result<(), ErrorResponse> {
+    let Some(expected) = state.config.admin_token.as_deref() else {
+        return Err(ErrorResponse::forbidden("Admin operations are disabled on this server (no admin token configured)".to_string()));
+    };
+    match headers.get(ADMIN_TOKEN_HEADER).and_then(|value| value.to_str().ok()) {
+        Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => Ok(()),
+        _ => Err(ErrorResponse::forbidden(format!("Missing or invalid {} header", ADMIN_TOKEN_HEADER))),
+    }
+}
+
+/// GET /v1/sessions/{session_id} - State and quota consumption of a session loaded in memory
+pub async fn handle_get_session(
+    State(state): State<ServerState>,
+    Path(session_id): Path<String>,
+) -> Result<Json<SessionDetail>, ErrorResponse> {
+    let http_request_id = Uuid::new_v4();
+    info!("[{}] GET /v1/sessions/{}", http_request_id, session_id);
+
+    let session = state.session_manager
+        .find_session(&session_id)
+        .await
+        .ok_or_else(|| ErrorResponse::not_found(format!("Session not found: {}", session_id)))?;
+
+    Ok(Json(SessionDetail {
+        id: session.session_id.clone(),
+        object: "session".to_string(),
+        agent: session.agent_name.clone(),
+        ephemeral: session.is_ephemeral(),
+        busy: session.is_busy(),
+        queued_requests: session.queued_requests(),
+        idle_secs: session.idle_for().as_secs(),
+        ttl_secs: state.session_manager.effective_ttl(&session).map(|ttl| ttl.as_secs()),
+        env: session.masked_env(),
+        quota: session.quota_usage(),
+    }))
+}
+
+/// DELETE /v1/sessions/{session_id} - Terminate a session and move it to the trash
+/// The session can be restored until the trash retention expires, `?permanent=true` (admin) skips the trash
+/// Refused with 409 while the session is processing a request, unless `?force=true` terminates it anyway
+/// Answers once the agent stopped and the session is unloaded, with 200 and the session status rather
+/// than 204: the body tells whether the session went to the trash or was deleted for good
+pub async fn handle_delete_session(
+    State(state): State<ServerState>,
+    Path(session_id): Path<String>,
+    Query(query): Query<DeleteSessionQuery>,
+    headers: HeaderMap,
+) -> Result<Json<SessionStatus>, ErrorResponse> {
+    let http_request_id = Uuid::new_v4().to_string();
+    info!(
+        "[{}] DELETE /v1/sessions/{} (permanent: {}, force: {})",
+        http_request_id, session_id, query.permanent, query.force
+    );
+
+    if query.permanent {
+        require_admin(&state, &headers)?;
+    }
+    let busy = state.session_manager.find_session(&session_id).await.is_some_and(|session| session.is_busy());
+    if busy && !query.force {
+        return Err(ErrorResponse::conflict(format!(
+            "Session {} is processing a request, cancel it first or pass ?force=true",
+            session_id
+        )));
+    }
+
+    let found = state.session_manager
+        .delete_session(&http_request_id, &session_id, query.permanent, query.force)
+        .await
+        .map_err(|e| ErrorResponse::internal_error(format!("Failed to delete session: {}", e)))?;
+    let status = if query.permanent { "deleted" } else { "trashed" };
+    match found {
+        true => Ok(Json(SessionStatus::new(session_id, status))),
+        false => Err(ErrorResponse::not_found(format!("Session not found: {}", session_id))),
+    }
+}
+
+/// POST /v1/sessions/{session_id}/restore - Bring back a session from the trash
+pub async fn handle_restore_session(
+    State(state): State<ServerState>,
+    Path(session_id): Path<String>,
+) -> Result<Json<SessionStatus>, ErrorResponse> {
+    let http_request_id = Uuid::new_v4().to_string();
+    info!("[{}] POST /v1/sessions/{}/restore", http_request_id, session_id);
+
+    let restored = state.session_manager
+        .restore_session(&http_request_id, &session_id)
+        .await
+        .map_err(|e| ErrorResponse::internal_error(format!("Failed to restore session: {}", e)))?;
+
+    match restored {
+        true => Ok(Json(SessionStatus::new(session_id, "restored"))),
+        false => Err(ErrorResponse::not_found(format!("Session not in trash (or retention expired): {}", session_id))),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ForkSessionQuery {
+    /// Messages of the source trace copied to the fork (default: the whole trace)
+    pub at_message: Option<usize>,
+}
+
+/// Session created by a fork
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionFork {
+    pub id: String,
+    pub object: String,
+    /// Session the fork was copied from
+    pub forked_from: String,
+    /// Messages of the trace the fork starts with, fewer than `at_message` when the cut fell inside a tool call
+    pub messages: usize,
+}
+
+/// POST /v1/sessions/{session_id}/fork - Branch a conversation into a new session
+/// The trace of the session, cut after `?at_message=N` messages, is copied to a session with a new id,
+/// the cut moving back before a tool call whose results it would split,
+/// loaded and ready for requests. Both sessions are then saved independently
+/// A session loaded in memory is copied as it is now, unless it is processing a request: its last
+/// persisted version is used then, the fork does not wait for the request
+pub async fn handle_fork_session(
+    State(state): State<ServerState>,
+    Path(session_id): Path<String>,
+    Query(query): Query<ForkSessionQuery>,
+) -> Result<Json<SessionFork>, ErrorResponse> {
+    let http_request_id = Uuid::new_v4().to_string();
+    info!("[{}] POST /v1/sessions/{}/fork (at_message: {:?})", http_request_id, session_id, query.at_message);
+
+    let loaded = state.session_manager.find_session(&session_id).await;
+    let snapshot = match &loaded {
+        Some(session) => session.snapshot().await,
+        None => None,
+    };
+    let source = match snapshot {
+        Some(source) => source,
+        None if SessionPersist::is_enabled() => SessionPersist::find_session_from(&*state.session_store, &session_id)
+            .await
+            .map_err(|e| ErrorResponse::internal_error(format!("Failed to load session: {}", e)))?
+            .ok_or_else(|| match loaded.is_some() {
+                true => ErrorResponse::conflict(format!("Session {} is processing its first request and has not been saved yet", session_id)),
+                false => ErrorResponse::not_found(format!("Session not found: {}", session_id)),
+            })?,
+        None => return Err(match loaded.is_some() {
+            true => ErrorResponse::conflict(format!("Session {} is processing a request, fork it once the request ends", session_id)),
+            false => ErrorResponse::not_found(format!("Session not found: {}", session_id)),
+        }),
+    };
+
+    let messages = query.at_message.unwrap_or(source.trace.len());
+    if messages > source.trace.len() {
+        return Err(ErrorResponse::invalid_request(format!(
+            "at_message is {} but session {} has {} messages",
+            messages,
+            session_id,
+            source.trace.len()
+        ))
+        .with_param("at_message"));
+    }
+
+    // a cut inside a tool call and its results is moved back before the call
+    let fork = source.fork(Uuid::new_v4().to_string(), messages);
+    let messages = fork.trace.len();
+    let session = state.session_manager
+        .fork_session(&http_request_id, fork)
+        .await
+        .map_err(|e| ErrorResponse::internal_error(format!("Failed to fork session: {}", e)))?;
+
+    Ok(Json(SessionFork {
+        id: session.session_id.clone(),
+        object: "session".to_string(),
+        forked_from: session_id,
+        messages,
+    }))
+}
+
+/// Messages returned by the history endpoint when `?limit` is not set
+const DEFAULT_HISTORY_LIMIT: usize = 100;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct HistoryQuery {
+    /// Most recent messages returned (default 100)
+    pub limit: Option<usize>,
+    /// Only the messages before this index of the trace, to page back through long traces
+    pub before: Option<usize>,
+    /// Comma-separated roles kept (system, developer, user, assistant, tool), all by default
+    /// Without "tool" the tool calls are stripped from the assistant messages too
+    pub roles: Option<String>,
+}
+
+/// A page of the trace of a session, oldest message first
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionHistory {
+    pub object: String,
+    pub session_id: String,
+    /// Messages in the shape of the persisted session trace
+    pub data: Vec<ChatMessage>,
+    /// Index in the trace of the first message returned, the `before` of the previous page
+    pub first_index: Option<usize>,
+    /// Older messages are left
+    pub has_more: bool,
+    /// Messages in the trace
+    pub total: usize,
+}
+
+fn role_name(message: &ChatMessage) -> &'static str {
+    match message {
+        ChatMessage::System { .. } => "system",
+        ChatMessage::Developer { .. } => "developer",
+        ChatMessage::User { .. } => "user",
+        ChatMessage::Assistant { .. } => "assistant",
+        ChatMessage::Tool { .. } => "tool",
+    }
+}
+
+/// Page of `trace` selected by the query: roles first, then the last `limit` messages before `before`
+fn history_page(session_id: String, trace: Vec<ChatMessage>, query: &HistoryQuery) -> SessionHistory {
+    let total = trace.len();
+    let roles: Option<Vec<String>> = query.roles.as_ref().map(|roles| {
+        roles.split(',').map(|role| role.trim().to_ascii_lowercase()).filter(|role| !role.is_empty()).collect()
+    });
+    let strip_tool_calls = roles.as_ref().is_some_and(|roles| !roles.iter().any(|role| role == "tool"));
+    let before = query.before.unwrap_or(total).min(total);
+
+    let mut messages: Vec<(usize, ChatMessage)> = trace
+        .into_iter()
+        .enumerate()
+        .take(before)
+        .filter(|(_, message)| roles.as_ref().is_none_or(|roles| roles.iter().any(|role| role == role_name(message))))
+        .filter_map(|(index, mut message)| {
+            if let (true, ChatMessage::Assistant { content, tool_calls, .. }) = (strip_tool_calls, &mut message) {
+                *tool_calls = None;
+                // a message that only called tools has nothing left to show
+                if matches!(content, None | Some(ChatMessageContent::None)) {
+                    return None;
+                }
+            }
+            Some((index, message))
+        })
+        .collect();
+
+    let limit = query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+    let skipped = messages.len().saturating_sub(limit);
+    let page = messages.split_off(skipped);
+    SessionHistory {
+        object: "list".to_string(),
+        session_id,
+        first_index: page.first().map(|(index, _)| *index),
+        has_more: skipped > 0,
+        data: page.into_iter().map(|(_, message)| message).collect(),
+        total,
+    }
+}
+
+/// GET /v1/sessions/{session_id}/history - Messages of a session, to rebuild a conversation view
+/// The trace of the agent when the session is loaded, else the persisted one
+/// `?limit=N&before=M` pages back through long traces, `?roles=user,assistant` keeps these roles only
+pub async fn handle_get_session_history(
+    State(state): State<ServerState>,
+    Path(session_id): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<SessionHistory>, OpenAiError> {
+    let http_request_id = Uuid::new_v4();
+    info!("[{}] GET /v1/sessions/{}/history", http_request_id, session_id);
+
+    let live = match state.session_manager.find_session(&session_id).await {
+        Some(session) => session
+            .live_trace()
+            .await
+            .inspect_err(|e| warn!("[{}] live trace of {} unavailable, reading the persisted one: {}", http_request_id, session_id, e))
+            .ok(),
+        None => None,
+    };
+    let trace = match live {
+        Some(trace) => trace,
+        None => SessionPersist::find_session_from(&*state.session_store, &session_id)
+            .await
+            .map_err(|e| ErrorResponse::internal_error(format!("Failed to load session: {}", e)))?
+            .map(|data| data.trace)
+            .ok_or_else(|| {
+                ErrorResponse::new(format!("Session not found: {}", session_id), "not_found".to_string(), Some("session_not_found".to_string()))
+            })?,
+    };
+
+    Ok(Json(history_page(session_id, trace, &query)))
+}
+
+/// GET /v1/sessions/{session_id}/requests/{request_id}/changes - Files changed by a request
+/// Includes unified diffs for text files under the size cap, binary files only report sizes
+pub async fn handle_get_request_changes(
+    State(state): State<ServerState>,
+    Path((session_id, request_id)): Path<(String, String)>,
+) -> Result<Json<WorkspaceChanges>, ErrorResponse> {
+    let http_request_id = Uuid::new_v4();
+    info!("[{}] GET /v1/sessions/{}/requests/{}/changes", http_request_id, session_id, request_id);
+
+    let session = state.session_manager
+        .find_session(&session_id)
+        .await
+        .ok_or_else(|| ErrorResponse::not_found(format!("Session not found: {}", session_id)))?;
+
+    session.workspace_changes(&request_id)
+        .map(Json)
+        .ok_or_else(|| ErrorResponse::not_found(format!("No workspace changes recorded for request {}", request_id)))
+}
+
+/// Transcript lines sent before the live ones when `?lines` is not set
+const DEFAULT_TAIL_LINES: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct TailQuery {
+    /// Keep the response open and stream the new lines (false = history only)
+    #[serde(default = "default_follow")]
+    pub follow: bool,
+    /// Lines of history sent first
+    pub lines: Option<usize>,
+    /// Keep the ANSI colors of tool outputs
+    #[serde(default)]
+    pub color: bool,
+}
+
+fn default_follow() -> bool {
+    true
+}
+
+fn text_chunk(lines: Vec<String>) -> Option<Result<String, Infallible>> {
+    (!lines.is_empty()).then(|| Ok(lines.join("\n") + "\n"))
+}
+
+/// GET /v1/sessions/{session_id}/tail - Human readable transcript of a session, for `curl -N`
+/// Plain text lines (timestamp, role, tool calls with durations, truncated outputs) for the recent
+/// events, then the live ones until the agent stops. Read-only, like GET /v1/sessions/{session_id}
+pub async fn handle_tail_session(
+    State(state): State<ServerState>,
+    Path(session_id): Path<String>,
+    Query(query): Query<TailQuery>,
+) -> Result<Response, ErrorResponse> {
+    let http_request_id = Uuid::new_v4();
+    info!("[{}] GET /v1/sessions/{}/tail (follow: {})", http_request_id, session_id, query.follow);
+
+    let session = state.session_manager
+        .find_session(&session_id)
+        .await
+        .ok_or_else(|| ErrorResponse::not_found(format!("Session not found: {}", session_id)))?;
+
+    let renderer = TranscriptRenderer::new(query.color);
+    let (history, live) = session.transcript().subscribe();
+    let mut backlog: Vec<String> = history.iter().flat_map(|entry| renderer.render(entry)).collect();
+    let backlog = backlog.split_off(backlog.len().saturating_sub(query.lines.unwrap_or(DEFAULT_TAIL_LINES)));
+
+    let live = live.filter(|_| query.follow).map(|live| {
+        BroadcastStream::new(live).filter_map(move |entry| async move {
+            match entry {
+                Ok(entry) => text_chunk(renderer.render(&entry)),
+                Err(BroadcastStreamRecvError::Lagged(skipped)) => text_chunk(vec![format!("... {} events skipped", skipped)]),
+            }
+        })
+    });
+    let body = futures::stream::iter(text_chunk(backlog)).chain(futures::stream::iter(live).flatten());
+
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8"), (header::CACHE_CONTROL, "no-cache")],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+#[cfg(feature = "git")]
+const DEFAULT_COMMITS_LIMIT: usize = 20;
+
+#[cfg(feature = "git")]
+#[derive(Debug, Default, Deserialize)]
+pub struct CommitsQuery {
+    pub limit: Option<usize>,
+}
+
+/// Commits of a session workspace (OpenAI list object)
+#[cfg(feature = "git")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommitList {
+    pub object: String,
+    pub data: Vec<CheckpointCommit>,
+}
+
+#[cfg(feature = "git")]
+impl From<CheckpointError> for ErrorResponse {
+    fn from(error: CheckpointError) -> Self {
+        match error {
+            CheckpointError::CommitNotFound(_) => ErrorResponse::not_found(error.to_string()),
+            CheckpointError::Disabled => ErrorResponse::invalid_request(error.to_string()),
+            CheckpointError::DirtyWorkspace(_) => ErrorResponse::conflict(error.to_string()),
+            e => ErrorResponse::internal_error(format!("Workspace checkpoint failed: {}", e)),
+        }
+    }
+}
+
+/// GET /v1/sessions/{session_id}/commits - Workspace commits of a session, newest first (one per request)
+#[cfg(feature = "git")]
+pub async fn handle_list_commits(
+    State(state): State<ServerState>,
+    Path(session_id): Path<String>,
+    Query(query): Query<CommitsQuery>,
+) -> Result<Json<CommitList>, ErrorResponse> {
+    let http_request_id = Uuid::new_v4();
+    info!("[{}] GET /v1/sessions/{}/commits", http_request_id, session_id);
+
+    let session = state.session_manager
+        .find_session(&session_id)
+        .await
+        .ok_or_else(|| ErrorResponse::not_found(format!("Session not found: {}", session_id)))?;
+    let git = session.git().ok_or(CheckpointError::Disabled)?;
+
+    let data = git.commits(query.limit.unwrap_or(DEFAULT_COMMITS_LIMIT)).await?;
+    Ok(Json(CommitList { object: "list".to_string(), data }))
+}
+
+/// POST /v1/sessions/{session_id}/revert/{commit} - Reset the session workspace to a commit
+/// Waits for the running request, the agent is told about the revert by a system note in its trace
+#[cfg(feature = "git")]
+pub async fn handle_revert_session(
+    State(state): State<ServerState>,
+    Path((session_id, commit)): Path<(String, String)>,
+) -> Result<Json<CheckpointCommit>, ErrorResponse> {
+    let http_request_id = Uuid::new_v4().to_string();
+    info!("[{}] POST /v1/sessions/{}/revert/{}", http_request_id, session_id, commit);
+
+    let session = state.session_manager
+        .find_session(&session_id)
+        .await
+        .ok_or_else(|| ErrorResponse::not_found(format!("Session not found: {}", session_id)))?;
+
+    Ok(Json(session.revert_workspace(&http_request_id, &commit).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai_dive::v1::resources::chat::{Function, ToolCall};
+
+    fn text(role: &str, text: &str) -> ChatMessage {
+        let content = ChatMessageContent::Text(text.to_string());
+        match role {
+            "user" => ChatMessage::User { content, name: None },
+            "tool" => ChatMessage::Tool { content, tool_call_id: "call_1".to_string() },
+            _ => ChatMessage::Assistant { content: Some(content), reasoning_content: None, refusal: None, name: None, audio: None, tool_calls: None },
+        }
+    }
+
+    fn tool_call() -> ChatMessage {
+        ChatMessage::Assistant {
+            content: None,
+            reasoning_content: None,
+            refusal: None,
+            name: None,
+            audio: None,
+            tool_calls: Some(vec![ToolCall {
+                id: "call_1".to_string(),
+                r#type: "function".to_string(),
+                function: Function { name: "read".to_string(), arguments: "{}".to_string() },
+            }]),
+        }
+    }
+
+    #[test]
+    fn test_history_pages_and_roles() {
+        let trace = vec![text("user", "q1"), tool_call(), text("tool", "file"), text("assistant", "a1"), text("user", "q2"), text("assistant", "a2")];
+
+        let query = HistoryQuery { limit: Some(2), ..Default::default() };
+        let page = history_page("s".to_string(), trace.clone(), &query);
+        assert_eq!((page.data.len(), page.first_index, page.has_more, page.total), (2, Some(4), true, 6));
+
+        let query = HistoryQuery { limit: Some(2), before: Some(4), ..Default::default() };
+        let page = history_page("s".to_string(), trace.clone(), &query);
+        assert_eq!((page.first_index, page.has_more), (Some(2), true));
+
+        // the tool call and its result are left out of the conversation view
+        let query = HistoryQuery { roles: Some("user, assistant".to_string()), ..Default::default() };
+        let page = history_page("s".to_string(), trace, &query);
+        let roles: Vec<_> = page.data.iter().map(role_name).collect();
+        assert_eq!(roles, ["user", "assistant", "user", "assistant"]);
+        assert_eq!((page.first_index, page.has_more), (Some(0), false));
+    }
+}
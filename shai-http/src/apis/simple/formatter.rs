@@ -1,19 +1,30 @@
 use async_trait::async_trait;
-use shai_core::agent::AgentEvent;
+use axum::response::sse::Event;
+use shai_core::agent::{AgentEvent, PublicAgentState};
 use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent};
 use std::collections::HashMap;
 
 use super::types::{MultiModalStreamingResponse, ToolCall, ToolCallResult};
-use crate::streaming::EventFormatter;
+use crate::run::RunSummary;
+use crate::streaming::{EventFormatter, ERROR_EVENT};
+use crate::ErrorResponse;
 
 /// Formatter for Simple API multimodal responses
 pub struct SimpleFormatter {
     pub model: String,
+    pub replay_id: Option<String>,
+    summary: Option<RunSummary>,
 }
 
 impl SimpleFormatter {
     pub fn new(model: String) -> Self {
-        Self { model }
+        Self { model, replay_id: None, summary: None }
+    }
+
+    /// Replay id sent on the final event of the stream
+    pub fn with_replay_id(mut self, replay_id: String) -> Self {
+        self.replay_id = Some(replay_id);
+        self
     }
 }
 
@@ -46,20 +57,14 @@ impl EventFormatter for SimpleFormatter {
                                 assistant: Some(text),
                                 call: None,
                                 result: None,
+                                replay_id: None,
+                summary: None,
                             });
                         }
                         None
                     }
-                    Err(err) => {
-                        // Format error as assistant message
-                        Some(MultiModalStreamingResponse {
-                            id: session_id.to_string(),
-                            model: self.model.clone(),
-                            assistant: Some(format!("Error: {}", err)),
-                            call: None,
-                            result: None,
-                        })
-                    }
+                    // Errors end the run, they are sent as the stream's `error` event
+                    Err(_) => None,
                 }
             }
             AgentEvent::ToolCallStarted { call, .. } => Some(MultiModalStreamingResponse {
@@ -72,6 +77,8 @@ impl EventFormatter for SimpleFormatter {
                     output: None,
                 }),
                 result: None,
+                replay_id: None,
+                summary: None,
             }),
             AgentEvent::ToolCallCompleted { call, result, .. } => {
                 use shai_core::tools::ToolResult;
@@ -125,6 +132,8 @@ impl EventFormatter for SimpleFormatter {
                         output: Some(output_str),
                     }),
                     result: Some(tool_result),
+                    replay_id: None,
+                summary: None,
                 })
             }
             AgentEvent::Completed { message, .. } => Some(MultiModalStreamingResponse {
@@ -133,25 +142,35 @@ impl EventFormatter for SimpleFormatter {
                 assistant: Some(message),
                 call: None,
                 result: None,
+                replay_id: self.replay_id.clone(),
+                summary: self.summary.clone(),
             }),
-            AgentEvent::Error { error } => Some(MultiModalStreamingResponse {
-                id: session_id.to_string(),
-                model: self.model.clone(),
-                assistant: None,
-                call: None,
-                result: Some(ToolCallResult {
-                    text: None,
-                    text_stream: None,
-                    image: None,
-                    speech: None,
-                    other: None,
-                    error: Some(error),
-                    extra: None,
-                }),
-            }),
+            // End of turn: a last frame carrying the replay id and the run summary
+            AgentEvent::StatusChanged { new_status: PublicAgentState::Paused, .. } => {
+                if self.replay_id.is_none() && self.summary.is_none() {
+                    return None;
+                }
+                Some(MultiModalStreamingResponse {
+                    id: session_id.to_string(),
+                    model: self.model.clone(),
+                    assistant: None,
+                    call: None,
+                    result: None,
+                    replay_id: self.replay_id.clone(),
+                    summary: self.summary.clone(),
+                })
+            }
             _ => None,
         }
     }
+
+    fn set_run_summary(&mut self, summary: &RunSummary) {
+        self.summary = Some(summary.clone());
+    }
+
+    fn format_error(&mut self, error: &ErrorResponse, _session_id: &str) -> Result<Event, serde_json::Error> {
+        serde_json::to_string(error).map(|json| Event::default().event(ERROR_EVENT).data(json))
+    }
 }
 
 /// Convert serde_json::Value parameters to HashMap<String, String>
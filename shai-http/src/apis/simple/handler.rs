@@ -1,5 +1,8 @@
+use std::time::Duration;
+
 use axum::{
-    extract::{Path, State},
+    extract::{Extension, Path, State},
+    http::{HeaderMap, HeaderValue},
     response::{IntoResponse, Response, Sse},
 };
 use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent, ToolCall as LlmToolCall, Function};
@@ -8,32 +11,82 @@ use uuid::Uuid;
 
 use super::types::{MultiModalQuery, Message};
 use super::formatter::SimpleFormatter;
-use crate::{session_to_sse_stream, ApiJson, ErrorResponse, ServerState};
+use crate::{run_to_sse_stream, AgentRun, ApiJson, ErrorResponse, ServerState};
+use crate::auth::USER_HEADER;
+use crate::features::Features;
+use crate::replay::{ReplayDescriptor, SamplingParams, REPLAY_ID_HEADER};
+use crate::session::{RequestOverrides, SessionAttributes, SessionMetadata};
+
+/// Per-session TTL override in seconds (request) and the TTL applied (response)
+const SESSION_TTL_HEADER: &str = "x-shai-session-ttl";
+const SESSION_TTL_EFFECTIVE_HEADER: &str = "x-shai-session-ttl-effective";
+
+/// Tags of the session (comma separated), its user is the one of X-Shai-User
+const SESSION_TAGS_HEADER: &str = "x-shai-tags";
 
 /// Handle multimodal query without explicit session id (ephemeral session)
 pub async fn handle_multimodal_query_stream(
     State(state): State<ServerState>,
+    Extension(features): Extension<Features>,
+    headers: HeaderMap,
     ApiJson(payload): ApiJson<MultiModalQuery>,
 ) -> Result<Response, ErrorResponse> {
-    handle_multimodal_query_stream_internal(state, None, payload).await
+    handle_multimodal_query_stream_internal(state, None, None, features, &headers, payload).await
 }
 
 /// Handle multimodal query with provided session id (persistent session)
 pub async fn handle_multimodal_query_stream_with_session(
     State(state): State<ServerState>,
     Path(session_id): Path<String>,
+    Extension(features): Extension<Features>,
+    headers: HeaderMap,
     ApiJson(payload): ApiJson<MultiModalQuery>,
 ) -> Result<Response, ErrorResponse> {
-    handle_multimodal_query_stream_internal(state, Some(session_id), payload).await
+    let session_ttl = parse_session_ttl(&headers)?;
+    handle_multimodal_query_stream_internal(state, Some(session_id), session_ttl, features, &headers, payload).await
+}
+
+/// Parse the optional X-Shai-Session-TTL header (seconds)
+fn parse_session_ttl(headers: &HeaderMap) -> Result<Option<Duration>, ErrorResponse> {
+    let Some(value) = headers.get(SESSION_TTL_HEADER) else {
+        return Ok(None);
+    };
+
+    value
+        .to_str()
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(|secs| Some(Duration::from_secs(secs)))
+        .ok_or_else(|| ErrorResponse::invalid_request(format!(
+            "Invalid {} header: expected a number of seconds",
+            SESSION_TTL_HEADER
+        )))
+}
+
+/// Session metadata of the X-Shai-User and X-Shai-Tags headers
+fn header_metadata(headers: &HeaderMap) -> SessionMetadata {
+    let header = |name: &'static str| headers.get(name).and_then(|value| value.to_str().ok()).map(str::trim).filter(|value| !value.is_empty());
+    SessionMetadata {
+        user: header(USER_HEADER).map(str::to_string),
+        tags: header(SESSION_TAGS_HEADER)
+            .map(|tags| tags.split(',').map(str::trim).filter(|tag| !tag.is_empty()).map(str::to_string).collect())
+            .unwrap_or_default(),
+        ..Default::default()
+    }
 }
 
 /// Shared implementation for multimodal query handlers
 async fn handle_multimodal_query_stream_internal(
     state: ServerState,
     session_id_param: Option<String>,
-    payload: MultiModalQuery,
+    session_ttl: Option<Duration>,
+    features: Features,
+    headers: &HeaderMap,
+    mut payload: MultiModalQuery,
 ) -> Result<Response, ErrorResponse> {
     let request_id = Uuid::new_v4();
+    state.config.api_keys.authorize(headers, &mut payload.model, &state.config.models)?;
+    let (agent_name, route) = state.config.models.route(&payload.model)?;
 
     // Determine session_id: use provided, or generate ephemeral
     let is_ephemeral = session_id_param.is_none();
@@ -48,40 +101,69 @@ async fn handle_multimodal_query_stream_internal(
     // Build trace from query
     let trace = build_message_trace(&payload);
 
-    // Get or create session agent
-    let agent_session = if is_ephemeral {
-        // Ephemeral -> create new session
-        state.session_manager
-            .create_new_session(&request_id.to_string(), &session_id, Some(payload.model.clone()), is_ephemeral)
-            .await
-            .map_err(|e| ErrorResponse::internal_error(format!("Failed to create session: {}", e)))?
-    } else {
-        // Persistent -> get existing (from memory or disk) or create new
-        match state.session_manager.get_session(&request_id.to_string(), &session_id, payload.model.clone()).await {
-            Ok(session) => session,
-            Err(_) => {
-                // Doesn't exist in memory or disk, create it
-                state.session_manager
-                    .create_new_session(&request_id.to_string(), &session_id, Some(payload.model.clone()), is_ephemeral)
-                    .await
-                    .map_err(|e| ErrorResponse::internal_error(format!("Failed to create session: {}", e)))?
-            }
-        }
+    // Replay descriptor, its id is sent in the header and the final event
+    let replay = ReplayDescriptor::capture(
+        "multimodal",
+        &request_id.to_string(),
+        &session_id,
+        &payload.model,
+        &trace,
+        SamplingParams::default(),
+    ).await;
+    state.session_manager.record_replay(&replay);
+
+    // Session environment for tools, checked against the server allowlist
+    let attributes = SessionAttributes {
+        env: payload.env.clone().unwrap_or_default(),
+        features: Some(features),
+        overrides: RequestOverrides { route, owner: state.config.auth.session_owner(headers), ..Default::default() },
+        ..Default::default()
     };
+    state.session_manager
+        .validate_env(&attributes.env)
+        .map_err(ErrorResponse::invalid_request)?;
+
+    // Get or create session agent: ephemeral sessions are always new, persistent ones are
+    // resumed from memory or disk when they exist
+    let agent_session = state.session_manager
+        .get_or_create_session(&request_id.to_string(), &session_id, agent_name.clone(), is_ephemeral, attributes)
+        .await
+        .map_err(ErrorResponse::session_not_created)?;
+
+    // Per-session TTL override, kept for the following requests on this session
+    if session_ttl.is_some() {
+        agent_session.set_ttl(session_ttl);
+    }
+    // Metadata of the headers then of the body, merged into the one of earlier requests
+    agent_session.merge_metadata(header_metadata(headers));
+    if let Some(metadata) = payload.metadata.clone() {
+        agent_session.merge_metadata(metadata);
+    }
+    let effective_ttl = state.session_manager.effective_ttl(&agent_session);
 
     // Create request session
     let request_session = agent_session
-        .handle_request(&request_id.to_string(), trace)
+        .handle_request(&request_id.to_string(), trace, features)
         .await
-        .map_err(|e| ErrorResponse::internal_error(format!("Failed to handle request: {}", e)))?;
+        .map_err(ErrorResponse::request_failed)?;
 
     // Create the formatter for Simple Multimodal API
-    let formatter = SimpleFormatter::new(payload.model.clone());
+    let formatter = SimpleFormatter::new(payload.model.clone()).with_replay_id(replay.id.clone());
+
+    // Create SSE stream, the client cannot reattach so its disconnect stops the agent
+    let options = state.config.run_options().with_cancel_on_disconnect(true);
+    let run = AgentRun::new(request_session, session_id.clone(), options);
+    let stream = run_to_sse_stream(run, formatter, session_id);
 
-    // Create SSE stream
-    let stream = session_to_sse_stream(request_session, formatter, session_id, true);
+    let mut response = Sse::new(stream).into_response();
+    if let Ok(value) = HeaderValue::from_str(&replay.id) {
+        response.headers_mut().insert(REPLAY_ID_HEADER, value);
+    }
+    if let Some(ttl) = effective_ttl {
+        response.headers_mut().insert(SESSION_TTL_EFFECTIVE_HEADER, HeaderValue::from(ttl.as_secs()));
+    }
 
-    Ok(Sse::new(stream).into_response())
+    Ok(response)
 }
 
 
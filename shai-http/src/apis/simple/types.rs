@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::run::RunSummary;
+use crate::session::SessionMetadata;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolCall {
     pub tool: String,
@@ -123,6 +126,12 @@ pub struct MultiModalQuery {
     pub messages: Option<Vec<Message>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<Vec<AgentTool>>,
+    /// Environment variables injected into tool execution, only applied when the session is created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env: Option<HashMap<String, String>>,
+    /// User, tags and fields merged into the metadata of the session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<SessionMetadata>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -135,6 +144,12 @@ pub struct MultiModalStreamingResponse {
     pub call: Option<ToolCall>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub result: Option<ToolCallResult>,
+    /// Replay id of the request, set on the final event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replay_id: Option<String>,
+    /// Summary of the run, set on the final event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<RunSummary>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -0,0 +1,337 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Extension, Path, Query, State,
+    },
+    response::Response,
+};
+use futures::{SinkExt, StreamExt};
+use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent};
+use serde::{Deserialize, Serialize};
+use shai_core::agent::{AgentController, AgentEvent, PermissionResponse};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use super::simple::SimpleFormatter;
+use crate::features::Features;
+use crate::run::{AgentRun, RunOptions};
+use crate::session::{colored_session_id, AgentSession};
+use crate::streaming::{
+    EventFormatter, ToolProgressPayload, ERROR_EVENT, RUN_SUMMARY_EVENT, TOOL_PROGRESS_EVENT, WORKSPACE_CHANGES_EVENT,
+};
+use crate::{ErrorResponse, ServerState};
+
+/// Event name of the frames asking the client to approve a tool call
+pub const PERMISSION_REQUIRED_EVENT: &str = "permission.required";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SocketQuery {
+    /// Ask the client before running tools (default: tools run without asking, as on the HTTP APIs)
+    #[serde(default)]
+    pub approvals: bool,
+}
+
+/// Frames sent by the client, JSON text tagged by `type`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientFrame {
+    /// User message: starts a request, or interrupts the running one and is added to it
+    Message { content: String },
+    /// Stop the running request
+    Cancel,
+    /// Decision on a tool call announced by a `permission.required` frame
+    Approval { request_id: String, decision: ApprovalDecision },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalDecision {
+    Allow,
+    /// Allow this call and the next ones of the same tool
+    AllowAlways,
+    Deny,
+}
+
+impl From<ApprovalDecision> for PermissionResponse {
+    fn from(decision: ApprovalDecision) -> Self {
+        match decision {
+            ApprovalDecision::Allow => PermissionResponse::Allow,
+            ApprovalDecision::AllowAlways => PermissionResponse::AllowAlways,
+            ApprovalDecision::Deny => PermissionResponse::Forbidden,
+        }
+    }
+}
+
+/// Frame sent to the client: the SSE event name and its JSON data
+#[derive(Debug, Serialize)]
+struct ServerFrame<'a, T: Serialize> {
+    event: &'a str,
+    data: &'a T,
+}
+
+fn frame<T: Serialize>(event: &str, data: &T) -> Option<String> {
+    match serde_json::to_string(&ServerFrame { event, data }) {
+        Ok(json) => Some(json),
+        Err(e) => {
+            error!("Failed to serialize {} frame: {}", event, e);
+            None
+        }
+    }
+}
+
+/// Client frame of a text message: a JSON frame, or a user message when the text is not a JSON object
+pub fn parse_frame(text: &str) -> Result<ClientFrame, serde_json::Error> {
+    match serde_json::from_str::<ClientFrame>(text) {
+        Ok(frame) => Ok(frame),
+        Err(e) => match serde_json::from_str::<serde_json::Value>(text) {
+            Ok(value) if value.is_object() => Err(e),
+            _ => Ok(ClientFrame::Message { content: text.to_string() }),
+        },
+    }
+}
+
+/// GET /v1/sessions/{session_id}/ws - Bidirectional connection to a session, resumed from its
+/// persisted data when it is not loaded in memory
+/// The client sends user messages (JSON frames or plain text), cancellations and tool approvals,
+/// the server sends the events of the Simple API stream as `{"event": ..., "data": ...}` frames.
+/// Each request holds the session lock like an HTTP request, closing the socket stops the running
+/// request, and the socket of an ephemeral session is closed once its request ended
+pub async fn handle_session_ws(
+    State(state): State<ServerState>,
+    Path(session_id): Path<String>,
+    Query(query): Query<SocketQuery>,
+    Extension(features): Extension<Features>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ErrorResponse> {
+    let http_request_id = Uuid::new_v4();
+    info!("[{}] GET /v1/sessions/{}/ws (approvals: {})", http_request_id, session_id, query.approvals);
+
+    let session = state.session_manager
+        .find_or_resume_session(&http_request_id.to_string(), &session_id)
+        .await
+        .map_err(ErrorResponse::request_failed)?
+        .ok_or_else(|| ErrorResponse::not_found(format!("Session not found: {}", session_id)))?;
+
+    // the client is attached for the whole run, it does not come back for a request it left
+    let options = state.config.run_options().with_cancel_on_disconnect(true);
+    let socket = SessionSocket::new(session, features, options, query.approvals);
+    Ok(ws.on_upgrade(move |ws| socket.serve(ws)))
+}
+
+/// Request started by the socket, its run keeps the controller lock until it is dropped
+struct ActiveRequest {
+    request_id: String,
+    run: AgentRun,
+    controller: AgentController,
+    summary_sent: bool,
+}
+
+struct SessionSocket {
+    session: Arc<AgentSession>,
+    features: Features,
+    options: RunOptions,
+    approvals: bool,
+    formatter: SimpleFormatter,
+    active: Option<ActiveRequest>,
+}
+
+impl SessionSocket {
+    fn new(session: Arc<AgentSession>, features: Features, options: RunOptions, approvals: bool) -> Self {
+        let formatter = SimpleFormatter::new(session.agent_name.clone());
+        Self { session, features, options, approvals, formatter, active: None }
+    }
+
+    async fn serve(mut self, socket: WebSocket) {
+        let session_id = self.session.session_id.clone();
+        info!("{} websocket attached", colored_session_id(&session_id));
+        if self.approvals {
+            if let Err(e) = self.session.set_sudo(false).await {
+                warn!("{} failed to enable tool approvals: {}", colored_session_id(&session_id), e);
+            }
+        }
+
+        let (mut sender, mut receiver) = socket.split();
+        'socket: loop {
+            let (frames, finished) = tokio::select! {
+                message = receiver.next() => match message {
+                    Some(Ok(Message::Text(text))) => (self.handle_frame(text.as_str()).await, false),
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    // pings are answered by axum, binary frames are not part of the protocol
+                    Some(Ok(_)) => continue,
+                },
+                event = next_event(&mut self.active), if self.active.is_some() => match event {
+                    Some(event) => (self.format_event(event).await, false),
+                    None => (self.finish_request().await, true),
+                },
+            };
+            for frame in frames {
+                if sender.send(Message::Text(frame.into())).await.is_err() {
+                    break 'socket;
+                }
+            }
+
+            // An ephemeral session ends with its request (RequestLifecycle), as on the HTTP APIs
+            if finished && self.session.ephemeral {
+                info!("{} ephemeral session ended, closing the websocket", colored_session_id(&session_id));
+                let _ = sender.send(Message::Close(None)).await;
+                break;
+            }
+        }
+
+        // Dropping the run stops the agent and releases the controller lock (RequestLifecycle)
+        if let Some(active) = self.active.take() {
+            info!("[{}] - {} websocket closed during the request", active.request_id, colored_session_id(&session_id));
+        }
+        if self.approvals {
+            if let Err(e) = self.session.set_sudo(true).await {
+                warn!("{} failed to disable tool approvals: {}", colored_session_id(&session_id), e);
+            }
+        }
+        info!("{} websocket detached", colored_session_id(&session_id));
+    }
+
+    /// Apply a client frame, returns the error frame of a refused one
+    async fn handle_frame(&mut self, text: &str) -> Vec<String> {
+        let result = match parse_frame(text) {
+            Ok(ClientFrame::Message { content }) => self.send_message(content).await,
+            Ok(ClientFrame::Cancel) => match &self.active {
+                Some(active) => active.controller.stop_current_task().await.map_err(ErrorResponse::request_failed),
+                None => Err(ErrorResponse::invalid_request("No request is running".to_string())),
+            },
+            Ok(ClientFrame::Approval { request_id, decision }) => match &self.active {
+                Some(active) => active.controller
+                    .response_permission_request(request_id, decision.into())
+                    .await
+                    .map_err(ErrorResponse::request_failed),
+                None => Err(ErrorResponse::invalid_request("No request is running".to_string())),
+            },
+            Err(e) => Err(ErrorResponse::invalid_request(format!("Invalid frame: {}", e))),
+        };
+        match result {
+            Ok(()) => vec![],
+            Err(error) => frame(ERROR_EVENT, &error).into_iter().collect(),
+        }
+    }
+
+    /// Start a request with the message, or hand it to the running one
+    async fn send_message(&mut self, content: String) -> Result<(), ErrorResponse> {
+        let message = ChatMessage::User { content: ChatMessageContent::Text(content), name: None };
+        if let Some(active) = &self.active {
+            self.session.transcript().record_user_input(std::slice::from_ref(&message));
+            return active.controller.send_trace(vec![message]).await.map_err(ErrorResponse::request_failed);
+        }
+
+        // Waits for the request another client may be running on the session
+        let request_id = Uuid::new_v4().to_string();
+        let request_session = self.session
+            .handle_request(&request_id, vec![message], self.features)
+            .await
+            .map_err(ErrorResponse::request_failed)?;
+        let controller = request_session.controller.clone();
+        let run = AgentRun::new(request_session, self.session.session_id.clone(), self.options.clone());
+        self.active = Some(ActiveRequest { request_id, run, controller, summary_sent: false });
+        Ok(())
+    }
+
+    /// Frames of an event of the running request, as the Simple API stream would send them
+    async fn format_event(&mut self, event: AgentEvent) -> Vec<String> {
+        let session_id = self.session.session_id.clone();
+        let mut frames = Vec::new();
+        match &event {
+            AgentEvent::ToolProgress { call_id, tool_name, message, percent } => {
+                let payload = ToolProgressPayload {
+                    call_id: call_id.clone(),
+                    tool_name: tool_name.clone(),
+                    message: message.clone(),
+                    percent: *percent,
+                };
+                return frame(TOOL_PROGRESS_EVENT, &payload).into_iter().collect();
+            }
+            AgentEvent::PermissionRequired { request_id, request, .. } => {
+                let payload = serde_json::json!({ "request_id": request_id, "request": request });
+                frames.extend(frame(PERMISSION_REQUIRED_EVENT, &payload));
+            }
+            _ => {}
+        }
+
+        // The summary goes right before the terminal event, a failed run ends on its error instead
+        if let Some(active) = &mut self.active {
+            if let Some(summary) = active.run.summary() {
+                active.summary_sent = true;
+                self.formatter.set_run_summary(&summary);
+                frames.extend(frame(RUN_SUMMARY_EVENT, &summary));
+                if let Some(error) = active.run.error() {
+                    warn!("[{}] Run failed: {}", session_id, error.error.message);
+                    frames.extend(frame(ERROR_EVENT, &error));
+                    return frames;
+                }
+            }
+        }
+
+        for output in self.formatter.format_events(event, &session_id).await {
+            let name = self.formatter.event_name(&output).to_string();
+            frames.extend(frame(&name, &output));
+        }
+        frames
+    }
+
+    /// Last frames of the request, the run is dropped with the lock it holds
+    async fn finish_request(&mut self) -> Vec<String> {
+        let Some(mut active) = self.active.take() else {
+            return vec![];
+        };
+        let mut frames = Vec::new();
+
+        // The run ended without terminal event (timeout, agent died)
+        if !active.summary_sent {
+            frames.extend(active.run.summary().and_then(|summary| frame(RUN_SUMMARY_EVENT, &summary)));
+            if let Some(error) = active.run.error() {
+                warn!("[{}] Run failed: {}", self.session.session_id, error.error.message);
+                frames.extend(frame(ERROR_EVENT, &error));
+            }
+        }
+        if let Some(changes) = active.run.workspace_changes().await {
+            frames.extend(frame(WORKSPACE_CHANGES_EVENT, &changes.summary()));
+        }
+        frames
+    }
+}
+
+/// Next event of the running request (only polled while there is one)
+async fn next_event(active: &mut Option<ActiveRequest>) -> Option<AgentEvent> {
+    match active {
+        Some(active) => active.run.next_event().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_frames() {
+        let frame: ClientFrame = serde_json::from_str(r#"{"type": "message", "content": "hello"}"#).unwrap();
+        assert_eq!(frame, ClientFrame::Message { content: "hello".to_string() });
+
+        let frame: ClientFrame = serde_json::from_str(r#"{"type": "approval", "request_id": "perm_1", "decision": "deny"}"#).unwrap();
+        let ClientFrame::Approval { request_id, decision } = frame else {
+            panic!("expected an approval frame");
+        };
+        assert_eq!(request_id, "perm_1");
+        assert_eq!(PermissionResponse::from(decision), PermissionResponse::Forbidden);
+
+        assert!(serde_json::from_str::<ClientFrame>(r#"{"type": "sudo"}"#).is_err());
+    }
+
+    #[test]
+    fn test_plain_text_frames_are_messages() {
+        assert_eq!(parse_frame("list the files").unwrap(), ClientFrame::Message { content: "list the files".to_string() });
+        assert_eq!(parse_frame("[1, 2]").unwrap(), ClientFrame::Message { content: "[1, 2]".to_string() });
+        assert_eq!(parse_frame(r#"{"type": "cancel"}"#).unwrap(), ClientFrame::Cancel);
+        // a JSON object is a frame, a malformed one is refused rather than sent to the agent
+        assert!(parse_frame(r#"{"type": "sudo"}"#).is_err());
+    }
+}
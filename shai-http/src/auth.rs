@@ -0,0 +1,207 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use tower::{Layer, Service};
+use tracing::warn;
+
+use crate::rules::{bearer_token, wildcard_match};
+use crate::ErrorResponse;
+
+/// User a request acts for, the owner of its sessions when it has no accepted API key
+pub const USER_HEADER: &str = "x-shai-user";
+
+/// Which bearer tokens the server accepts
+/// Authentication is disabled when no token is configured
+#[derive(Debug, Clone, Default)]
+pub struct AuthConfig {
+    /// Accepted `Authorization: Bearer <token>` values
+    pub tokens: Vec<String>,
+    /// Paths served without a token, `*` globs (e.g. "/v1/models", "/v1/ready")
+    /// Relative to the router, whatever prefix it is nested under
+    pub exempt_paths: Vec<String>,
+}
+
+impl AuthConfig {
+    pub fn new(tokens: Vec<String>) -> Self {
+        Self { tokens, exempt_paths: Vec::new() }
+    }
+
+    /// Tokens from SHAI_API_KEYS and exempt paths from SHAI_AUTH_EXEMPT_PATHS (both comma-separated)
+    pub fn from_env() -> Self {
+        Self {
+            tokens: env_list("SHAI_API_KEYS"),
+            exempt_paths: env_list("SHAI_AUTH_EXEMPT_PATHS"),
+        }
+    }
+
+    /// Serve these paths without a token
+    pub fn with_exempt_paths(mut self, exempt_paths: Vec<String>) -> Self {
+        self.exempt_paths = exempt_paths;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    pub fn is_exempt(&self, path: &str) -> bool {
+        self.exempt_paths.iter().any(|pattern| wildcard_match(pattern, path))
+    }
+
+    /// Compared in constant time, so that response times do not leak how much of a token matched
+    pub fn accepts(&self, token: &str) -> bool {
+        self.tokens
+            .iter()
+            .fold(false, |found, valid| constant_time_eq(valid.as_bytes(), token.as_bytes()) | found)
+    }
+
+    /// Client the sessions of a request are counted against: its API key when accepted, else
+    /// the user of the X-Shai-User header (None = counted against nobody)
+    /// Keys are kept as a fingerprint, the owners never hold a usable token
+    pub fn session_owner(&self, headers: &HeaderMap) -> Option<String> {
+        if let Some(token) = bearer_token(headers).filter(|token| self.is_enabled() && self.accepts(token)) {
+            return Some(format!("key:{}", &blake3::hash(token.as_bytes()).to_hex()[..16]));
+        }
+        headers
+            .get(USER_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|user| !user.is_empty())
+            .map(|user| format!("user:{}", user))
+    }
+}
+
+pub(crate) fn env_list(name: &str) -> Vec<String> {
+    std::env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Rejects the requests without a valid bearer token with 401, outside of the exempt paths
+#[derive(Debug, Clone)]
+pub struct AuthLayer {
+    config: Arc<AuthConfig>,
+}
+
+impl AuthLayer {
+    pub fn new(config: AuthConfig) -> Self {
+        Self { config: Arc::new(config) }
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = AuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthService { inner, config: self.config.clone() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthService<S> {
+    inner: S,
+    config: Arc<AuthConfig>,
+}
+
+impl<S> AuthService<S> {
+    fn check(&self, request: &Request) -> Result<(), ErrorResponse> {
+        let path = request.uri().path();
+        if self.config.is_exempt(path) {
+            return Ok(());
+        }
+
+        let reason = match bearer_token(request.headers()) {
+            Some(token) if self.config.accepts(token) => return Ok(()),
+            Some(_) => "Invalid API key",
+            None => "Missing API key",
+        };
+        let client = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map_or_else(|| "-".to_string(), |ConnectInfo(address)| address.ip().to_string());
+        warn!("audit: {} {} from {} rejected: {}", request.method(), path, client, reason);
+
+        Err(ErrorResponse::unauthorized(format!(
+            "{}: send a valid key in the Authorization header (Bearer <key>)",
+            reason
+        )))
+    }
+}
+
+impl<S> Service<Request> for AuthService<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        match self.check(&request) {
+            Ok(()) => Box::pin(self.inner.call(request)),
+            Err(error) => Box::pin(async move { Ok(error.into_response()) }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_and_exempt_paths() {
+        let config = AuthConfig::new(vec!["sk-one".to_string(), "sk-two".to_string()])
+            .with_exempt_paths(vec!["/v1/models".to_string(), "/v1/admin/*".to_string()]);
+        assert!(config.is_enabled());
+        assert!(config.accepts("sk-two"));
+        assert!(!config.accepts("sk-tw"));
+        assert!(!config.accepts(""));
+
+        assert!(config.is_exempt("/v1/models"));
+        assert!(config.is_exempt("/v1/admin/providers"));
+        assert!(!config.is_exempt("/v1/models/extra"));
+        assert!(!config.is_exempt("/v1/responses"));
+
+        assert!(!AuthConfig::default().is_enabled());
+    }
+
+    #[test]
+    fn test_session_owner() {
+        let headers = |pairs: &[(&'static str, &str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(*name, value.parse().unwrap());
+            }
+            headers
+        };
+        let config = AuthConfig::new(vec!["sk-one".to_string()]);
+
+        let owner = config.session_owner(&headers(&[("authorization", "Bearer sk-one"), (USER_HEADER, "alice")])).unwrap();
+        assert!(owner.starts_with("key:") && !owner.contains("sk-one"));
+        // a key the server does not accept does not name the owner, the user header does
+        assert_eq!(config.session_owner(&headers(&[("authorization", "Bearer sk-two"), (USER_HEADER, " alice ")])).as_deref(), Some("user:alice"));
+        assert_eq!(AuthConfig::default().session_owner(&headers(&[("authorization", "Bearer sk-one")])), None);
+        assert_eq!(config.session_owner(&headers(&[(USER_HEADER, "")])), None);
+    }
+}
@@ -0,0 +1,388 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::Request,
+    http::{header, HeaderMap, Method, Uri},
+    response::Response,
+};
+use futures::StreamExt;
+use serde_json::Value;
+use tower::{Layer, Service};
+use tracing::{debug, Level};
+
+use crate::rules::wildcard_match;
+
+/// Fields redacted when SHAI_LOG_REDACT_FIELDS is not set
+/// `*token` spares the usage counts (`max_tokens`, `total_tokens`), `env` is the environment of the tools
+pub const DEFAULT_REDACT_FIELDS: &[&str] = &[
+    "*password*", "*secret*", "*token", "*api_key*", "*apikey*", "*credential*", "authorization", "cookie", "env",
+];
+
+/// Value the redacted fields are logged with
+const REDACTED: &str = "[REDACTED]";
+
+/// Data of the event streams that ends them, logged as is
+const DONE_MARKER: &str = "[DONE]";
+
+/// Bytes of a body kept for the log, the rest is only counted
+const MAX_LOGGED_BYTES: usize = 64 * 1024;
+
+/// Debug logging of the request and response bodies, off by default
+#[derive(Debug, Clone)]
+pub struct BodyLoggingConfig {
+    pub enabled: bool,
+    /// JSON fields whose values are replaced before logging, matched case-insensitively at any depth,
+    /// `*` matches any part of a name (e.g. `*secret*`)
+    pub redact_fields: Vec<String>,
+}
+
+impl Default for BodyLoggingConfig {
+    fn default() -> Self {
+        Self { enabled: false, redact_fields: DEFAULT_REDACT_FIELDS.iter().map(|field| field.to_string()).collect() }
+    }
+}
+
+impl BodyLoggingConfig {
+    /// Enabled by SHAI_BODY_LOGGING=true, fields of SHAI_LOG_REDACT_FIELDS (comma separated)
+    pub fn from_env() -> Self {
+        let enabled = std::env::var("SHAI_BODY_LOGGING").is_ok_and(|value| matches!(value.trim(), "true" | "1"));
+        let config = Self { enabled, ..Default::default() };
+        match std::env::var("SHAI_LOG_REDACT_FIELDS") {
+            Ok(fields) => config.with_redact_fields(fields.split(',').map(|field| field.trim().to_string()).collect()),
+            Err(_) => config,
+        }
+    }
+
+    pub fn with_redact_fields(mut self, fields: Vec<String>) -> Self {
+        self.redact_fields = fields.into_iter().filter(|field| !field.is_empty()).collect();
+        self
+    }
+}
+
+/// Replaces the values of the redacted fields in the logged bodies
+#[derive(Debug)]
+struct Redactor {
+    fields: Vec<String>,
+}
+
+impl Redactor {
+    fn new(fields: &[String]) -> Self {
+        Self { fields: fields.iter().map(|field| field.to_lowercase()).collect() }
+    }
+
+    fn is_redacted(&self, key: &str) -> bool {
+        let key = key.to_lowercase();
+        self.fields.iter().any(|field| wildcard_match(field, &key))
+    }
+
+    fn redact_value(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    match self.is_redacted(key) {
+                        true => redact_all(value),
+                        false => self.redact_value(value),
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact_value(item)),
+            _ => {}
+        }
+    }
+
+    /// Body as logged: JSON with its fields redacted, the `data:` lines of an event stream
+    /// redacted one by one, and only the size of anything else (a `data:` line cut by
+    /// MAX_LOGGED_BYTES included)
+    fn redact(&self, content_type: &str, body: &[u8], total: usize) -> String {
+        if total == 0 {
+            return "<empty>".to_string();
+        }
+        let text = String::from_utf8_lossy(body);
+        let logged = if content_type.starts_with("text/event-stream") {
+            text.lines()
+                .map(|line| match line.strip_prefix("data:") {
+                    Some(data) => match data.trim() {
+                        DONE_MARKER => format!("data: {}", DONE_MARKER),
+                        data => match self.redact_json(data) {
+                            Some(json) => format!("data: {}", json),
+                            None => format!("data: <{} bytes, not JSON>", data.len()),
+                        },
+                    },
+                    None => line.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        } else {
+            match self.redact_json(&text) {
+                Some(json) => json,
+                None if total > body.len() => return format!("<{} bytes, truncated JSON not logged>", total),
+                None => return format!("<{} bytes, not JSON>", total),
+            }
+        };
+        match total > body.len() {
+            true => format!("{}... ({} bytes)", logged, total),
+            false => logged,
+        }
+    }
+
+    fn redact_json(&self, text: &str) -> Option<String> {
+        let mut value: Value = serde_json::from_str(text).ok()?;
+        self.redact_value(&mut value);
+        Some(value.to_string())
+    }
+}
+
+/// Value of a redacted field: the keys of an object are kept (e.g. the variable names of `env`),
+/// every other value is replaced
+fn redact_all(value: &mut Value) {
+    match value {
+        Value::Object(map) => map.values_mut().for_each(redact_all),
+        _ => *value = Value::String(REDACTED.to_string()),
+    }
+}
+
+fn content_type(headers: &HeaderMap) -> String {
+    headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or_default().to_string()
+}
+
+/// Logs the request and response bodies at DEBUG level with their sensitive fields redacted
+/// Both are passed on chunk by chunk and logged once they end: nothing is held back and no more
+/// than MAX_LOGGED_BYTES of a body is kept, the body limits of the router still apply
+#[derive(Debug, Clone)]
+pub struct BodyLoggingLayer {
+    redactor: Arc<Redactor>,
+}
+
+impl BodyLoggingLayer {
+    pub fn new(config: &BodyLoggingConfig) -> Self {
+        Self { redactor: Arc::new(Redactor::new(&config.redact_fields)) }
+    }
+}
+
+impl<S> Layer<S> for BodyLoggingLayer {
+    type Service = BodyLoggingService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BodyLoggingService { inner, redactor: self.redactor.clone() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BodyLoggingService<S> {
+    inner: S,
+    redactor: Arc<Redactor>,
+}
+
+impl<S> Service<Request> for BodyLoggingService<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        if !tracing::enabled!(Level::DEBUG) {
+            return Box::pin(self.inner.call(request));
+        }
+        // the service that was polled ready serves this request, the clone the next one
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let redactor = self.redactor.clone();
+
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let (method, uri) = (parts.method.clone(), parts.uri.clone());
+            let request_capture = CapturedBody::new(&method, &uri, "request".to_string(), content_type(&parts.headers), redactor.clone());
+            let request = Request::from_parts(parts, capture(body, request_capture));
+
+            let response = inner.call(request).await?;
+            let (parts, body) = response.into_parts();
+            let label = format!("response {}", parts.status.as_u16());
+            let response_capture = CapturedBody::new(&method, &uri, label, content_type(&parts.headers), redactor);
+            Ok(Response::from_parts(parts, capture(body, response_capture)))
+        })
+    }
+}
+
+/// Body passing the chunks of `body` on, logged once it ends
+fn capture(body: Body, captured: CapturedBody) -> Body {
+    let mut capture = BodyCapture(Some(captured));
+    let body = body.into_data_stream().map(move |chunk| {
+        if let Ok(bytes) = &chunk {
+            capture.push(bytes);
+        }
+        chunk
+    });
+    Body::from_stream(body)
+}
+
+/// Request or response body seen so far
+struct CapturedBody {
+    method: Method,
+    uri: Uri,
+    /// `request`, or `response` and its status
+    label: String,
+    content_type: String,
+    redactor: Arc<Redactor>,
+    body: Vec<u8>,
+    total: usize,
+}
+
+impl CapturedBody {
+    fn new(method: &Method, uri: &Uri, label: String, content_type: String, redactor: Arc<Redactor>) -> Self {
+        Self { method: method.clone(), uri: uri.clone(), label, content_type, redactor, body: Vec::new(), total: 0 }
+    }
+
+    fn push(&mut self, chunk: &Bytes) {
+        let kept = MAX_LOGGED_BYTES.saturating_sub(self.body.len()).min(chunk.len());
+        self.body.extend_from_slice(&chunk[..kept]);
+        self.total += chunk.len();
+    }
+
+    fn log(&self) {
+        debug!(
+            "body: {} {} {} {}",
+            self.method,
+            self.uri.path(),
+            self.label,
+            self.redactor.redact(&self.content_type, &self.body, self.total)
+        );
+    }
+}
+
+/// Logs the captured body once it ends or is dropped (client gone, body not read)
+struct BodyCapture(Option<CapturedBody>);
+
+impl BodyCapture {
+    fn push(&mut self, chunk: &Bytes) {
+        if let Some(captured) = &mut self.0 {
+            captured.push(chunk);
+        }
+    }
+}
+
+impl Drop for BodyCapture {
+    fn drop(&mut self) {
+        let Some(captured) = self.0.take() else {
+            return;
+        };
+        // redacted off the task that served the response
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn_blocking(move || captured.log());
+            }
+            Err(_) => captured.log(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn redactor() -> Redactor {
+        Redactor::new(&BodyLoggingConfig::default().with_redact_fields(vec!["password".to_string(), "API_KEY".to_string()]).redact_fields)
+    }
+
+    #[test]
+    fn test_json_fields_redacted_at_any_depth() {
+        let body = json!({
+            "model": "gpt-4o",
+            "api_key": "sk-secret",
+            "metadata": { "Password": "hunter2", "user": "ada" },
+            "input": [{ "role": "user", "content": "hello", "password": ["a", "b"] }],
+        });
+        let logged: Value = serde_json::from_str(&redactor().redact("application/json", body.to_string().as_bytes(), body.to_string().len())).unwrap();
+        assert_eq!(logged["api_key"], REDACTED);
+        assert_eq!(logged["metadata"]["Password"], REDACTED);
+        assert_eq!(logged["metadata"]["user"], "ada");
+        assert_eq!(logged["input"][0]["password"], REDACTED);
+        assert_eq!(logged["input"][0]["content"], "hello");
+    }
+
+    #[test]
+    fn test_default_fields_match_by_pattern() {
+        let redactor = Redactor::new(&BodyLoggingConfig::default().redact_fields);
+        let body = json!({
+            "max_tokens": 256,
+            "env": { "GITHUB_TOKEN": "ghp_secret", "PATH": "/usr/bin", "nested": { "URL": "postgres://user:pw@db" } },
+            "metadata": { "AWS_SECRET_ACCESS_KEY": "aws-secret", "x_api_key": "sk-1", "session_token": "t" },
+            "usage": { "total_tokens": 12 },
+        });
+        let logged: Value = serde_json::from_str(&redactor.redact("application/json", body.to_string().as_bytes(), body.to_string().len())).unwrap();
+        assert!(!logged.to_string().contains("secret") && !logged.to_string().contains("pw@db"));
+        assert_eq!(logged["env"]["GITHUB_TOKEN"], REDACTED);
+        assert_eq!(logged["env"]["PATH"], REDACTED);
+        assert_eq!(logged["env"]["nested"]["URL"], REDACTED);
+        assert_eq!(logged["metadata"]["x_api_key"], REDACTED);
+        assert_eq!(logged["metadata"]["session_token"], REDACTED);
+        assert_eq!(logged["max_tokens"], 256);
+        assert_eq!(logged["usage"]["total_tokens"], 12);
+    }
+
+    #[test]
+    fn test_event_streams_and_other_bodies() {
+        let stream = "event: response.created\ndata: {\"api_key\": \"sk-secret\", \"id\": \"resp_1\"}\n\ndata: [DONE]\n";
+        let logged = redactor().redact("text/event-stream", stream.as_bytes(), stream.len());
+        assert!(!logged.contains("sk-secret"));
+        assert!(logged.contains("resp_1") && logged.contains("data: [DONE]") && logged.contains("event: response.created"));
+
+        // a data line that is not JSON, or cut at the end of the kept bytes, is only counted
+        let stream = "data: {\"id\": \"resp_1\"}\n\ndata: token=sk-secret\n\ndata: {\"api_key\": \"sk-sec";
+        let logged = redactor().redact("text/event-stream", stream.as_bytes(), MAX_LOGGED_BYTES * 2);
+        assert!(!logged.contains("sk-sec"));
+        assert!(logged.contains("data: {\"id\":\"resp_1\"}"));
+        assert!(logged.contains("data: <15 bytes, not JSON>"));
+        assert!(logged.contains("data: <19 bytes, not JSON>"));
+        assert!(logged.ends_with(&format!("... ({} bytes)", MAX_LOGGED_BYTES * 2)));
+
+        assert_eq!(redactor().redact("text/plain", b"password=hunter2", 16), "<16 bytes, not JSON>");
+        assert_eq!(redactor().redact("application/json", b"{\"password\": \"hun", 100), "<100 bytes, truncated JSON not logged>");
+        assert_eq!(redactor().redact("application/json", b"", 0), "<empty>");
+    }
+
+    #[tokio::test]
+    async fn test_large_request_streamed_to_the_handler() {
+        use tower::ServiceExt;
+
+        let subscriber = tracing_subscriber::fmt().with_max_level(Level::DEBUG).with_test_writer().finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let handler = tower::service_fn(|request: Request| async move {
+            let bytes = axum::body::to_bytes(request.into_body(), usize::MAX).await.unwrap();
+            Ok::<_, std::convert::Infallible>(Response::new(Body::from(bytes.len().to_string())))
+        });
+        let service = BodyLoggingLayer::new(&BodyLoggingConfig::default()).layer(handler);
+
+        // the body goes to the handler whole, the layer only keeps its start
+        let request = Request::builder().method(Method::POST).uri("/v1/responses").body(Body::from(vec![b'a'; MAX_LOGGED_BYTES * 3])).unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        let answer = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(answer, (MAX_LOGGED_BYTES * 3).to_string().as_bytes());
+    }
+
+    #[test]
+    fn test_capture_keeps_the_start_of_large_bodies() {
+        let mut capture = CapturedBody::new(
+            &Method::POST,
+            &Uri::from_static("/v1/responses"),
+            "response 200".to_string(),
+            "application/json".to_string(),
+            Arc::new(redactor()),
+        );
+        capture.push(&Bytes::from(vec![b'a'; MAX_LOGGED_BYTES - 10]));
+        capture.push(&Bytes::from(vec![b'b'; 100]));
+        assert_eq!(capture.body.len(), MAX_LOGGED_BYTES);
+        assert_eq!(capture.total, MAX_LOGGED_BYTES + 90);
+    }
+}
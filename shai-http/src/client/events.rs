@@ -0,0 +1,247 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::stream::{Stream, StreamExt};
+use openai_dive::v1::resources::chat::ChatCompletionChunkResponse;
+
+use crate::apis::openai::response::types::ResponseStreamEvent;
+use crate::apis::simple::types::MultiModalStreamingResponse;
+use crate::run::RunSummary;
+use crate::streaming::{
+    QuotaExceededPayload, ToolCallArgumentsDeltaPayload, ToolProgressPayload, DONE_SENTINEL, ERROR_EVENT,
+    QUOTA_EXCEEDED_EVENT, RUN_SUMMARY_EVENT, TOOL_CALL_ARGUMENTS_DELTA_EVENT, TOOL_PROGRESS_EVENT, WORKSPACE_CHANGES_EVENT,
+};
+use crate::workspace::WorkspaceChanges;
+use crate::ErrorResponse;
+
+use super::ClientError;
+
+/// API a stream comes from, decides how unnamed `message` events are decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamKind {
+    ChatCompletions,
+    Responses,
+    Query,
+}
+
+/// A raw server-sent event
+#[derive(Debug, Clone, PartialEq)]
+pub struct SseMessage {
+    /// Event name, "message" when the server did not name it
+    pub event: String,
+    pub data: String,
+}
+
+/// Incremental parser of a `text/event-stream` body
+/// Chunks may split lines (and UTF-8 sequences) anywhere, comments (keep-alives) are skipped
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a chunk of the body, returns the events it completed
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseMessage> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut messages = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            // A blank line dispatches the event
+            if line.is_empty() {
+                let event = self.event.take().unwrap_or_else(|| "message".to_string());
+                if !self.data.is_empty() {
+                    messages.push(SseMessage { event, data: self.data.join("\n") });
+                    self.data.clear();
+                }
+                continue;
+            }
+            if line.starts_with(':') {
+                continue;
+            }
+
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (line, ""),
+            };
+            match field {
+                "event" => self.event = Some(value.to_string()),
+                "data" => self.data.push(value.to_string()),
+                _ => {}
+            }
+        }
+        messages
+    }
+}
+
+/// Event of a shai-http stream, decoded into the types the server serializes
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    /// Chat completions API chunk
+    ChatCompletionChunk(ChatCompletionChunkResponse),
+    /// Responses API event (response.created, response.output_text.delta, ...)
+    Response(ResponseStreamEvent),
+    /// Simple query API frame
+    Query(MultiModalStreamingResponse),
+    /// `tool_progress`
+    ToolProgress(ToolProgressPayload),
+    /// `tool_call.arguments.delta`
+    ToolCallArgumentsDelta(ToolCallArgumentsDeltaPayload),
+    /// `quota.exceeded`, a tool write was refused by the session disk quota
+    QuotaExceeded(QuotaExceededPayload),
+    /// `run.summary`, sent right before the terminal event
+    RunSummary(RunSummary),
+    /// `workspace.changes`, sent after the terminal event
+    WorkspaceChanges(WorkspaceChanges),
+    /// The run failed after the stream started: `data: {"error": ...}` chunk of the chat completions
+    /// stream or `error` event of the query stream (Responses streams end on response.failed instead)
+    Error(ErrorResponse),
+    /// `data: [DONE]`, last frame of a successful chat completions stream
+    Done,
+    /// Event this client does not know about
+    Other(SseMessage),
+}
+
+impl ClientEvent {
+    /// Decode an event of a stream of the given kind
+    pub fn decode(kind: StreamKind, message: SseMessage) -> Result<Self, serde_json::Error> {
+        let event = match message.event.as_str() {
+            TOOL_PROGRESS_EVENT => Self::ToolProgress(serde_json::from_str(&message.data)?),
+            TOOL_CALL_ARGUMENTS_DELTA_EVENT => Self::ToolCallArgumentsDelta(serde_json::from_str(&message.data)?),
+            QUOTA_EXCEEDED_EVENT => Self::QuotaExceeded(serde_json::from_str(&message.data)?),
+            RUN_SUMMARY_EVENT => Self::RunSummary(serde_json::from_str(&message.data)?),
+            WORKSPACE_CHANGES_EVENT => Self::WorkspaceChanges(serde_json::from_str(&message.data)?),
+            ERROR_EVENT => Self::Error(serde_json::from_str(&message.data)?),
+            name if name.starts_with("response.") => Self::Response(serde_json::from_str(&message.data)?),
+            "message" if message.data == DONE_SENTINEL => Self::Done,
+            "message" => match kind {
+                StreamKind::ChatCompletions => match serde_json::from_str::<ErrorResponse>(&message.data) {
+                    Ok(error) => Self::Error(error),
+                    Err(_) => Self::ChatCompletionChunk(serde_json::from_str(&message.data)?),
+                },
+                StreamKind::Responses => Self::Response(serde_json::from_str(&message.data)?),
+                StreamKind::Query => Self::Query(serde_json::from_str(&message.data)?),
+            },
+            _ => Self::Other(message),
+        };
+        Ok(event)
+    }
+}
+
+/// Stream of decoded events, ends when the server closes the response
+pub struct EventStream {
+    inner: Pin<Box<dyn Stream<Item = Result<ClientEvent, ClientError>> + Send>>,
+}
+
+impl EventStream {
+    pub(crate) fn new(response: reqwest::Response, kind: StreamKind) -> Self {
+        let state = (response.bytes_stream(), SseDecoder::new(), VecDeque::new());
+        let inner = futures::stream::unfold(state, move |(mut bytes, mut decoder, mut pending)| async move {
+            loop {
+                if let Some(message) = pending.pop_front() {
+                    let event = ClientEvent::decode(kind, message).map_err(ClientError::from);
+                    return Some((event, (bytes, decoder, pending)));
+                }
+                match bytes.next().await? {
+                    // Heartbeats are comments, the decoder drops them
+                    Ok(chunk) => pending.extend(decoder.push(&chunk)),
+                    Err(e) => return Some((Err(ClientError::from(e)), (bytes, decoder, pending))),
+                }
+            }
+        });
+        Self { inner: Box::pin(inner) }
+    }
+
+    /// Read the stream to the end
+    pub async fn collect_events(mut self) -> Result<Vec<ClientEvent>, ClientError> {
+        let mut events = Vec::new();
+        while let Some(event) = self.next().await {
+            events.push(event?);
+        }
+        Ok(events)
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Result<ClientEvent, ClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apis::openai::response::types::ResponseEventType;
+
+    fn sse(event: Option<&str>, data: &str) -> String {
+        match event {
+            Some(event) => format!("event: {}\ndata: {}\n\n", event, data),
+            None => format!("data: {}\n\n", data),
+        }
+    }
+
+    #[test]
+    fn test_decoder_handles_split_chunks_and_comments() {
+        let body = format!(
+            ":keep-alive\n\n{}{}",
+            sse(Some("tool_progress"), r#"{"call_id":"c1","tool_name":"bash","message":"héllo","percent":50}"#),
+            "data: line one\r\ndata: line two\r\n\r\n",
+        );
+
+        // one byte at a time splits every line and the UTF-8 sequence
+        let mut decoder = SseDecoder::new();
+        let messages: Vec<SseMessage> = body.as_bytes().chunks(1).flat_map(|chunk| decoder.push(chunk)).collect();
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].event, "tool_progress");
+        assert!(messages[0].data.contains("héllo"));
+        assert_eq!(messages[1], SseMessage { event: "message".to_string(), data: "line one\nline two".to_string() });
+    }
+
+    #[test]
+    fn test_decode_server_payloads() {
+        let delta = ResponseStreamEvent::output_text_delta(3, "msg_1".to_string(), 0, 0, "hi".to_string());
+        let summary: RunSummary = serde_json::from_value(serde_json::json!({
+            "session_id": "s1",
+            "started_at": "2025-01-01T00:00:00Z",
+            "elapsed_ms": 12,
+            "brain_iterations": 1,
+            "tool_calls": {},
+            "input_tokens": 10,
+            "output_tokens": 2,
+            "budgets": [],
+            "warnings": [],
+            "terminal_reason": "completed"
+        })).unwrap();
+        let body = format!(
+            "{}{}{}{}",
+            sse(None, &serde_json::to_string(&delta).unwrap()),
+            sse(Some(RUN_SUMMARY_EVENT), &serde_json::to_string(&summary).unwrap()),
+            sse(Some(WORKSPACE_CHANGES_EVENT), &serde_json::to_string(&WorkspaceChanges::default()).unwrap()),
+            sse(Some("custom"), "{}"),
+        );
+
+        let events: Vec<ClientEvent> = SseDecoder::new()
+            .push(body.as_bytes())
+            .into_iter()
+            .map(|message| ClientEvent::decode(StreamKind::Responses, message).unwrap())
+            .collect();
+
+        assert!(matches!(&events[0], ClientEvent::Response(event) if event.event_type == ResponseEventType::ResponseOutputTextDelta));
+        assert!(matches!(&events[1], ClientEvent::RunSummary(summary) if summary.session_id == "s1"));
+        assert!(matches!(&events[2], ClientEvent::WorkspaceChanges(_)));
+        assert!(matches!(&events[3], ClientEvent::Other(message) if message.event == "custom"));
+    }
+}
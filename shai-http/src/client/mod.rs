@@ -0,0 +1,314 @@
+//! Typed async client for the shai-http API
+//!
+//! Requests and events use the same serde types as the server, so a client built
+//! from this crate cannot drift from the server it talks to.
+
+mod events;
+
+pub use events::{ClientEvent, EventStream, SseDecoder, SseMessage, StreamKind};
+
+use std::time::Duration;
+
+use openai_dive::v1::resources::chat::{ChatCompletionParameters, ChatCompletionResponse};
+use openai_dive::v1::resources::response::{request::ResponseParameters, response::ResponseObject};
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+use tracing::debug;
+
+use crate::apis::openai::models::ModelList;
+use crate::apis::openai::response::input_items::InputItemList;
+use crate::apis::openai::response::stored::ResponseDeleted;
+use crate::apis::sessions::{SessionDetail, SessionStatus, ADMIN_TOKEN_HEADER};
+use crate::apis::simple::types::MultiModalQuery;
+#[cfg(feature = "git")]
+use crate::apis::sessions::CommitList;
+#[cfg(feature = "git")]
+use crate::git::CheckpointCommit;
+use crate::error::ErrorDetail;
+use crate::workspace::WorkspaceChanges;
+use crate::ErrorResponse;
+
+/// Delay before the first retry, doubled on every attempt
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+
+/// Error returned by the client
+#[derive(Debug, Error)]
+pub enum ClientError {
+    /// The request could not be sent or its body could not be read
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    /// The server answered with an error status
+    #[error("server returned {status}: {}", .error.message)]
+    Api { status: u16, error: ErrorDetail },
+    /// A response or event did not match the expected type
+    #[error("invalid payload: {0}")]
+    Decode(#[from] serde_json::Error),
+}
+
+impl ClientError {
+    /// HTTP status of an API error
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Self::Api { status, .. } => Some(*status),
+            Self::Http(e) => e.status().map(|status| status.as_u16()),
+            Self::Decode(_) => None,
+        }
+    }
+}
+
+/// Configuration of a ShaiClient
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    /// Server URL, including the prefix the router is nested under (e.g. "http://host/ai")
+    pub base_url: String,
+    /// Sent as a bearer token on every request
+    pub api_key: Option<String>,
+    /// Sent in X-Shai-Admin-Token, required by admin-scoped operations
+    pub admin_token: Option<String>,
+    /// Retries of idempotent calls on connection errors and 429/502/503/504
+    pub max_retries: u32,
+    pub connect_timeout: Duration,
+}
+
+impl ClientConfig {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: None,
+            admin_token: None,
+            max_retries: 2,
+            connect_timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Authenticate requests with a bearer token
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Set the token of admin-scoped operations
+    pub fn with_admin_token(mut self, admin_token: impl Into<String>) -> Self {
+        self.admin_token = Some(admin_token.into());
+        self
+    }
+
+    /// Set how many times idempotent calls are retried
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Set the connection timeout (agent runs have no overall timeout)
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+}
+
+/// Async client of the shai-http API
+#[derive(Clone, Debug)]
+pub struct ShaiClient {
+    http: reqwest::Client,
+    config: ClientConfig,
+}
+
+impl ShaiClient {
+    pub fn new(config: ClientConfig) -> Result<Self, ClientError> {
+        let http = reqwest::Client::builder()
+            .connect_timeout(config.connect_timeout)
+            .build()?;
+        Ok(Self { http, config })
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self.http.request(method, format!("{}{}", self.config.base_url, path));
+        match &self.config.api_key {
+            Some(api_key) => request.bearer_auth(api_key),
+            None => request,
+        }
+    }
+
+    /// Send a request, retrying idempotent ones on transient failures
+    /// Error statuses are turned into ClientError::Api
+    async fn send(&self, build: impl Fn() -> RequestBuilder, idempotent: bool) -> Result<reqwest::Response, ClientError> {
+        let max_retries = if idempotent { self.config.max_retries } else { 0 };
+        let mut attempt = 0;
+        loop {
+            match build().send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) if attempt < max_retries && is_transient(response.status()) => {
+                    debug!("{} returned {}, retrying", response.url(), response.status());
+                }
+                Ok(response) => return Err(Self::api_error(response).await),
+                Err(e) if attempt < max_retries && (e.is_connect() || e.is_timeout()) => {
+                    debug!("request failed, retrying: {}", e);
+                }
+                Err(e) => return Err(e.into()),
+            }
+
+            tokio::time::sleep(RETRY_BASE_DELAY * 2u32.pow(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    async fn api_error(response: reqwest::Response) -> ClientError {
+        let status = response.status().as_u16();
+        let body = match response.text().await {
+            Ok(body) => body,
+            Err(e) => return e.into(),
+        };
+        let error = match serde_json::from_str::<ErrorResponse>(&body) {
+            Ok(response) => response.error,
+            Err(_) => ErrorDetail { message: body, r#type: "http_error".to_string(), code: None, param: None },
+        };
+        ClientError::Api { status, error }
+    }
+
+    async fn json<T: DeserializeOwned>(&self, build: impl Fn() -> RequestBuilder, idempotent: bool) -> Result<T, ClientError> {
+        let body = self.send(build, idempotent).await?.bytes().await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn stream(&self, build: impl Fn() -> RequestBuilder, idempotent: bool, kind: StreamKind) -> Result<EventStream, ClientError> {
+        let response = self.send(build, idempotent).await?;
+        Ok(EventStream::new(response, kind))
+    }
+
+    /// POST /v1/chat/completions
+    pub async fn chat_completion(&self, mut params: ChatCompletionParameters) -> Result<ChatCompletionResponse, ClientError> {
+        params.stream = Some(false);
+        self.json(|| self.request(Method::POST, "/v1/chat/completions").json(&params), false).await
+    }
+
+    /// POST /v1/chat/completions with `stream: true`
+    pub async fn chat_completion_stream(&self, mut params: ChatCompletionParameters) -> Result<EventStream, ClientError> {
+        params.stream = Some(true);
+        self.stream(|| self.request(Method::POST, "/v1/chat/completions").json(&params), false, StreamKind::ChatCompletions).await
+    }
+
+    /// GET /v1/models - Agents the client's API key may use
+    pub async fn list_models(&self) -> Result<ModelList, ClientError> {
+        self.json(|| self.request(Method::GET, "/v1/models"), true).await
+    }
+
+    /// POST /v1/responses
+    pub async fn create_response(&self, mut params: ResponseParameters) -> Result<ResponseObject, ClientError> {
+        params.stream = Some(false);
+        self.json(|| self.request(Method::POST, "/v1/responses").json(&params), false).await
+    }
+
+    /// POST /v1/responses with `stream: true`
+    pub async fn create_response_stream(&self, mut params: ResponseParameters) -> Result<EventStream, ClientError> {
+        params.stream = Some(true);
+        self.stream(|| self.request(Method::POST, "/v1/responses").json(&params), false, StreamKind::Responses).await
+    }
+
+    /// GET /v1/responses/{id}?stream=false - A stored response, as it is so far when still in progress
+    pub async fn get_response(&self, response_id: &str) -> Result<ResponseObject, ClientError> {
+        let path = format!("/v1/responses/{}", response_id);
+        self.json(|| self.request(Method::GET, &path).query(&[("stream", "false")]), true).await
+    }
+
+    /// GET /v1/responses/{id}?stream=true - Follow the events of an ongoing response
+    pub async fn watch_response(&self, response_id: &str) -> Result<EventStream, ClientError> {
+        let path = format!("/v1/responses/{}", response_id);
+        self.stream(|| self.request(Method::GET, &path).query(&[("stream", "true")]), true, StreamKind::Responses).await
+    }
+
+    /// DELETE /v1/responses/{id} - Delete a stored response for good
+    pub async fn delete_response(&self, response_id: &str) -> Result<ResponseDeleted, ClientError> {
+        let path = format!("/v1/responses/{}", response_id);
+        self.json(|| self.request(Method::DELETE, &path), true).await
+    }
+
+    /// POST /v1/responses/{id}/cancel
+    pub async fn cancel_response(&self, response_id: &str) -> Result<SessionStatus, ClientError> {
+        let path = format!("/v1/responses/{}/cancel", response_id);
+        self.json(|| self.request(Method::POST, &path), true).await
+    }
+
+    /// GET /v1/responses/{id}/input_items
+    pub async fn list_input_items(&self, response_id: &str, limit: Option<usize>, after: Option<&str>) -> Result<InputItemList, ClientError> {
+        let path = format!("/v1/responses/{}/input_items", response_id);
+        let mut query = Vec::new();
+        if let Some(limit) = limit {
+            query.push(("limit", limit.to_string()));
+        }
+        if let Some(after) = after {
+            query.push(("after", after.to_string()));
+        }
+        self.json(|| self.request(Method::GET, &path).query(&query), true).await
+    }
+
+    /// POST /v1/multimodal[/{session_id}] - Simple query API (always streamed)
+    pub async fn query(&self, session_id: Option<&str>, query: &MultiModalQuery) -> Result<EventStream, ClientError> {
+        let path = match session_id {
+            Some(session_id) => format!("/v1/multimodal/{}", session_id),
+            None => "/v1/multimodal".to_string(),
+        };
+        self.stream(|| self.request(Method::POST, &path).json(query), false, StreamKind::Query).await
+    }
+
+    /// GET /v1/sessions/{id} - State and quota consumption of a session
+    pub async fn get_session(&self, session_id: &str) -> Result<SessionDetail, ClientError> {
+        let path = format!("/v1/sessions/{}", session_id);
+        self.json(|| self.request(Method::GET, &path), true).await
+    }
+
+    /// DELETE /v1/sessions/{id} - Move a session to the trash
+    pub async fn delete_session(&self, session_id: &str) -> Result<SessionStatus, ClientError> {
+        let path = format!("/v1/sessions/{}", session_id);
+        self.json(|| self.request(Method::DELETE, &path), true).await
+    }
+
+    /// DELETE /v1/sessions/{id}?permanent=true - Delete a session for good (admin token required)
+    pub async fn purge_session(&self, session_id: &str) -> Result<SessionStatus, ClientError> {
+        let path = format!("/v1/sessions/{}", session_id);
+        let admin_token = self.config.admin_token.clone().unwrap_or_default();
+        self.json(
+            || self.request(Method::DELETE, &path).query(&[("permanent", "true")]).header(ADMIN_TOKEN_HEADER, &admin_token),
+            true,
+        ).await
+    }
+
+    /// POST /v1/sessions/{id}/restore - Bring back a session from the trash
+    pub async fn restore_session(&self, session_id: &str) -> Result<SessionStatus, ClientError> {
+        let path = format!("/v1/sessions/{}/restore", session_id);
+        self.json(|| self.request(Method::POST, &path), true).await
+    }
+
+    /// GET /v1/sessions/{id}/requests/{request_id}/changes
+    pub async fn request_changes(&self, session_id: &str, request_id: &str) -> Result<WorkspaceChanges, ClientError> {
+        let path = format!("/v1/sessions/{}/requests/{}/changes", session_id, request_id);
+        self.json(|| self.request(Method::GET, &path), true).await
+    }
+
+    /// GET /v1/sessions/{id}/commits
+    #[cfg(feature = "git")]
+    pub async fn list_commits(&self, session_id: &str, limit: Option<usize>) -> Result<CommitList, ClientError> {
+        let path = format!("/v1/sessions/{}/commits", session_id);
+        let query: Vec<(&str, String)> = limit.map(|limit| ("limit", limit.to_string())).into_iter().collect();
+        self.json(|| self.request(Method::GET, &path).query(&query), true).await
+    }
+
+    /// POST /v1/sessions/{id}/revert/{commit}
+    /// Not retried: a second reset would add a second note to the agent trace
+    #[cfg(feature = "git")]
+    pub async fn revert_session(&self, session_id: &str, commit: &str) -> Result<CheckpointCommit, ClientError> {
+        let path = format!("/v1/sessions/{}/revert/{}", session_id, commit);
+        self.json(|| self.request(Method::POST, &path), false).await
+    }
+}
+
+fn is_transient(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
@@ -0,0 +1,159 @@
+use std::path::Path;
+use std::time::Duration;
+
+use axum::http::{HeaderValue, Method};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tracing::warn;
+
+use crate::auth::env_list;
+
+/// Methods allowed when none are configured
+const DEFAULT_METHODS: &[Method] = &[Method::GET, Method::POST, Method::DELETE, Method::OPTIONS];
+
+/// Error loading the CORS settings
+#[derive(Debug, Error)]
+pub enum CorsError {
+    #[error("failed to read {path}: {source}")]
+    Io { path: String, source: std::io::Error },
+    #[error("invalid CORS file {path}: {source}")]
+    Parse { path: String, source: serde_json::Error },
+}
+
+/// Origins browsers may call the API from
+/// CORS headers are not sent when no origin is allowed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorsConfig {
+    /// Any origin (`Access-Control-Allow-Origin: *`), the allowed origins are ignored
+    #[serde(default)]
+    pub allow_all: bool,
+    /// Exact origins, e.g. "https://app.example.com"
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// Methods of the cross-origin requests (empty = GET, POST, DELETE and OPTIONS)
+    #[serde(default)]
+    pub allowed_methods: Vec<String>,
+    /// Seconds browsers may cache a preflight response (None = browser default)
+    #[serde(default)]
+    pub max_age: Option<u64>,
+}
+
+/// Any origin may call the API unless told otherwise
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self { allow_all: true, allowed_origins: Vec::new(), allowed_methods: Vec::new(), max_age: None }
+    }
+}
+
+impl CorsConfig {
+    /// Only these origins
+    pub fn new(allowed_origins: Vec<String>) -> Self {
+        Self { allow_all: false, allowed_origins, ..Default::default() }
+    }
+
+    /// Origins from SHAI_CORS_ALLOWED_ORIGINS and methods from SHAI_CORS_ALLOWED_METHODS (both comma-separated),
+    /// preflight cache from SHAI_CORS_MAX_AGE (seconds). SHAI_CORS_ALLOW_ALL=true allows any origin,
+    /// it defaults to true when no origin is listed (false with no origin = CORS disabled)
+    pub fn from_env() -> Self {
+        let allowed_origins = env_list("SHAI_CORS_ALLOWED_ORIGINS");
+        let allow_all = match std::env::var("SHAI_CORS_ALLOW_ALL") {
+            Ok(value) => matches!(value.trim().to_ascii_lowercase().as_str(), "true" | "1" | "yes"),
+            Err(_) => allowed_origins.is_empty(),
+        };
+        Self {
+            allow_all,
+            allowed_origins,
+            allowed_methods: env_list("SHAI_CORS_ALLOWED_METHODS"),
+            max_age: std::env::var("SHAI_CORS_MAX_AGE").ok().and_then(|value| value.trim().parse().ok()),
+        }
+    }
+
+    /// Load the settings from a JSON file: `{ "allowed_origins": ["https://app.example.com"], "max_age": 600 }`
+    pub fn from_file(path: &Path) -> Result<Self, CorsError> {
+        let display = path.display().to_string();
+        let content = std::fs::read_to_string(path).map_err(|source| CorsError::Io { path: display.clone(), source })?;
+        serde_json::from_str(&content).map_err(|source| CorsError::Parse { path: display, source })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.allow_all || !self.allowed_origins.is_empty()
+    }
+
+    /// Layer answering the preflight requests and adding the CORS headers, None when disabled
+    /// Invalid origins and methods are skipped with a warning
+    pub fn layer(&self) -> Option<CorsLayer> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let origin = match self.allow_all {
+            true => AllowOrigin::from(Any),
+            false => AllowOrigin::list(self.allowed_origins.iter().filter_map(|origin| {
+                HeaderValue::from_str(origin).inspect_err(|_| warn!("CORS: invalid origin '{}' ignored", origin)).ok()
+            })),
+        };
+        let methods: Vec<Method> = match self.allowed_methods.is_empty() {
+            true => DEFAULT_METHODS.to_vec(),
+            false => self.allowed_methods
+                .iter()
+                .filter_map(|method| {
+                    Method::from_bytes(method.trim().to_ascii_uppercase().as_bytes())
+                        .inspect_err(|_| warn!("CORS: invalid method '{}' ignored", method))
+                        .ok()
+                })
+                .collect(),
+        };
+
+        let layer = CorsLayer::new().allow_origin(origin).allow_methods(methods).allow_headers(Any).expose_headers(Any);
+        Some(match self.max_age {
+            Some(secs) => layer.max_age(Duration::from_secs(secs)),
+            None => layer,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, extract::Request, http::header, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn preflight(origin: &str) -> Request {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/v1/models")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn app(config: &CorsConfig) -> Router {
+        Router::new().route("/v1/models", get(|| async { "models" })).layer(config.layer().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_preflight_of_allowed_origin() {
+        let config = CorsConfig { max_age: Some(600), ..CorsConfig::new(vec!["https://app.example.com".to_string()]) };
+
+        let response = app(&config).oneshot(preflight("https://app.example.com")).await.unwrap();
+        assert!(response.status().is_success());
+        let headers = response.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://app.example.com");
+        assert!(headers[header::ACCESS_CONTROL_ALLOW_METHODS].to_str().unwrap().contains("POST"));
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+
+        let response = app(&config).oneshot(preflight("https://evil.example.com")).await.unwrap();
+        assert!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_allow_all_sends_wildcard() {
+        let response = app(&CorsConfig::default()).oneshot(preflight("https://anywhere.example.com")).await.unwrap();
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+
+        let disabled = CorsConfig { allow_all: false, ..Default::default() };
+        assert!(disabled.layer().is_none());
+    }
+}
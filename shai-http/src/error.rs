@@ -1,23 +1,30 @@
 use axum::{
     extract::{rejection::JsonRejection, FromRequest},
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response, Json},
 };
 use serde::{Deserialize, Serialize};
+use shai_core::agent::AgentError;
 use tracing::error;
 
 /// Error response structure for API errors
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ErrorResponse {
     pub error: ErrorDetail,
+    /// Seconds the client should wait before retrying, sent in the Retry-After header
+    #[serde(skip)]
+    pub retry_after: Option<u64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ErrorDetail {
     pub message: String,
     pub r#type: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub code: Option<String>,
+    /// Request parameter the error is about
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub param: Option<String>,
 }
 
 impl ErrorResponse {
@@ -27,10 +34,24 @@ impl ErrorResponse {
                 message,
                 r#type: error_type,
                 code,
+                param: None,
             },
+            retry_after: None,
         }
     }
 
+    /// Tell the client when to retry with a Retry-After header
+    pub fn with_retry_after(mut self, secs: u64) -> Self {
+        self.retry_after = Some(secs);
+        self
+    }
+
+    /// Name the request parameter the error is about
+    pub fn with_param(mut self, param: &str) -> Self {
+        self.error.param = Some(param.to_string());
+        self
+    }
+
     pub fn not_found(message: String) -> Self {
         Self::new(message, "not_found".to_string(), Some("model_not_found".to_string()))
     }
@@ -39,19 +60,202 @@ impl ErrorResponse {
         Self::new(message, "invalid_request".to_string(), None)
     }
 
+    /// The model of the agent cannot read images sent in the input
+    pub fn images_not_supported(message: String) -> Self {
+        Self::new(message, "invalid_request".to_string(), Some("image_input_not_supported".to_string()))
+    }
+
+    /// The request carries no valid API key
+    pub fn unauthorized(message: String) -> Self {
+        Self::new(message, "unauthorized".to_string(), Some("invalid_api_key".to_string()))
+    }
+
+    /// The caller is not allowed to perform the operation
+    pub fn forbidden(message: String) -> Self {
+        Self::new(message, "forbidden".to_string(), None)
+    }
+
+    /// The API key sent more requests than its rate limit allows
+    pub fn rate_limited(message: String) -> Self {
+        Self::new(message, "rate_limited".to_string(), Some("rate_limit_exceeded".to_string()))
+    }
+
+    /// The session has too many requests waiting for it, or one waited too long
+    pub fn session_busy(message: String, retry_after_secs: u64) -> Self {
+        Self::new(message, "session_busy".to_string(), Some("session_busy".to_string())).with_retry_after(retry_after_secs)
+    }
+
+    /// The client already holds as many sessions as `max_sessions_per_owner` allows
+    pub fn session_limit(limit: usize) -> Self {
+        Self::new(
+            format!("Maximum number of sessions per client reached: {}, end one of them before opening another", limit),
+            "rate_limited".to_string(),
+            Some("max_sessions_per_owner".to_string()),
+        )
+    }
+
+    /// A session could not be created for the request
+    pub fn session_not_created(error: AgentError) -> Self {
+        match error {
+            AgentError::SessionLimitReached { limit } => Self::session_limit(limit),
+            e => Self::internal_error(format!("Failed to create session: {}", e)),
+        }
+    }
+
+    /// The resource is busy, e.g. a session processing a request
+    pub fn conflict(message: String) -> Self {
+        Self::new(message, "conflict".to_string(), None)
+    }
+
+    /// The request uses a feature the server does not implement
+    pub fn not_implemented(message: String) -> Self {
+        Self::new(message, "not_implemented".to_string(), Some("unsupported_feature".to_string()))
+    }
+
     pub fn internal_error(message: String) -> Self {
         Self::new(message, "internal_error".to_string(), None)
     }
+
+    /// The session went over one of its hard quotas
+    pub fn quota_exceeded(message: String) -> Self {
+        Self::new(message, "quota_exceeded".to_string(), Some("session_quota_exceeded".to_string()))
+    }
+
+    /// The session consumed its token budget
+    pub fn budget_exceeded(message: String) -> Self {
+        Self::new(message, "quota_exceeded".to_string(), Some("token_budget_exceeded".to_string()))
+    }
+
+    /// A session could not take a request
+    pub fn request_failed(error: AgentError) -> Self {
+        match error {
+            AgentError::QuotaExceeded(message) => Self::quota_exceeded(message),
+            e @ AgentError::BudgetExceeded { .. } => Self::budget_exceeded(e.to_string()),
+            AgentError::SessionBusy { message, retry_after_secs } => Self::session_busy(message, retry_after_secs),
+            e => Self::internal_error(format!("Failed to handle request: {}", e)),
+        }
+    }
+
+    /// The upstream model kept returning empty replies
+    pub fn empty_completion(message: String) -> Self {
+        Self::new(message, "empty_completion".to_string(), Some("empty_completion".to_string()))
+    }
+
+    /// The model provider failed (unreachable, rejected the call, invalid reply)
+    pub fn upstream_error(message: String) -> Self {
+        Self::new(message, "upstream_error".to_string(), Some("provider_error".to_string()))
+    }
+
+    /// The model did not reply in the JSON format the request asked for
+    pub fn invalid_json_output(message: String) -> Self {
+        Self::new(message, "upstream_error".to_string(), Some("invalid_json_output".to_string()))
+    }
+
+    /// The server is not taking requests, e.g. while it shuts down
+    pub fn service_unavailable(message: String) -> Self {
+        Self::new(message, "service_unavailable".to_string(), Some("server_shutting_down".to_string()))
+    }
+
+    /// The agent ran out of time
+    pub fn timeout(message: String) -> Self {
+        Self::new(message, "timeout".to_string(), Some("agent_timeout".to_string()))
+    }
+
+    /// Error an agent run failed on, as reported to the client
+    pub fn agent_error(error: &AgentError) -> Self {
+        let message = error.to_string();
+        match error {
+            AgentError::LlmError(_) | AgentError::InvalidResponse(_) => Self::upstream_error(message),
+            AgentError::EmptyCompletion(_) => Self::empty_completion(message),
+            AgentError::QuotaExceeded(_) => Self::quota_exceeded(message),
+            AgentError::BudgetExceeded { .. } => Self::budget_exceeded(message),
+            AgentError::TimeoutError | AgentError::UserTimeout => Self::timeout(message),
+            AgentError::MaxIterationsReached => {
+                Self::new(message, "internal_error".to_string(), Some("max_iterations".to_string()))
+            }
+            AgentError::SessionClosed => {
+                Self::new(message, "internal_error".to_string(), Some("session_closed".to_string()))
+            }
+            _ => Self::internal_error(message),
+        }
+    }
 }
 
-impl IntoResponse for ErrorResponse {
-    fn into_response(self) -> Response {
-        let status = match self.error.r#type.as_str() {
+impl ErrorResponse {
+    pub fn status(&self) -> StatusCode {
+        match self.error.r#type.as_str() {
             "not_found" => StatusCode::NOT_FOUND,
             "invalid_request" => StatusCode::BAD_REQUEST,
+            "unauthorized" => StatusCode::UNAUTHORIZED,
+            "forbidden" => StatusCode::FORBIDDEN,
+            "conflict" => StatusCode::CONFLICT,
+            "rate_limited" | "session_busy" => StatusCode::TOO_MANY_REQUESTS,
+            "quota_exceeded" => StatusCode::PAYLOAD_TOO_LARGE,
+            "empty_completion" | "upstream_error" => StatusCode::BAD_GATEWAY,
+            "timeout" => StatusCode::GATEWAY_TIMEOUT,
+            "service_unavailable" => StatusCode::SERVICE_UNAVAILABLE,
+            "not_implemented" => StatusCode::NOT_IMPLEMENTED,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Error with the types of the OpenAI API, for the OpenAI compatible endpoints
+    /// Client errors become invalid_request_error and server errors server_error, the status is kept
+    /// and the shai type moves to `code` when the error has none, so that SDKs can still tell them apart
+    pub fn into_openai_error(mut self) -> OpenAiError {
+        let status = self.status();
+        let openai_type = match self.error.r#type.as_str() {
+            "not_implemented" => "not_implemented",
+            _ if status.is_client_error() => "invalid_request_error",
+            _ => "server_error",
         };
-        (status, Json(self)).into_response()
+        let shai_type = std::mem::replace(&mut self.error.r#type, openai_type.to_string());
+        self.error.code.get_or_insert(shai_type);
+        OpenAiError { status, body: self }
+    }
+}
+
+impl IntoResponse for ErrorResponse {
+    fn into_response(self) -> Response {
+        let retry_after = self.retry_after;
+        with_retry_after((self.status(), Json(self)).into_response(), retry_after)
+    }
+}
+
+fn with_retry_after(mut response: Response, retry_after: Option<u64>) -> Response {
+    if let Some(secs) = retry_after {
+        response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(secs));
+    }
+    response
+}
+
+/// Error of the OpenAI compatible endpoints: `{"error": {"message", "type", "code", "param"}}`
+/// with the OpenAI error types, see ErrorResponse::into_openai_error
+#[derive(Debug, Clone)]
+pub struct OpenAiError {
+    status: StatusCode,
+    body: ErrorResponse,
+}
+
+impl From<ErrorResponse> for OpenAiError {
+    fn from(error: ErrorResponse) -> Self {
+        error.into_openai_error()
+    }
+}
+
+impl IntoResponse for OpenAiError {
+    fn into_response(self) -> Response {
+        // OpenAI sends every field of the error object, null when unset
+        let error = &self.body.error;
+        let body = serde_json::json!({
+            "error": {
+                "message": error.message,
+                "type": error.r#type,
+                "code": error.code,
+                "param": error.param,
+            }
+        });
+        with_retry_after((self.status, Json(body)).into_response(), self.body.retry_after)
     }
 }
 
@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use axum::{
+    extract::{Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::rules::bearer_token;
+use crate::session::SessionManagerConfig;
+use crate::{ErrorResponse, ServerState};
+
+/// Request header overriding feature flags: `X-Shai-Features: workspace-tracking=on,git-checkpoints=off`
+pub const FEATURES_HEADER: &str = "x-shai-features";
+
+/// Behaviors that can be switched per request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Feature {
+    /// Report the files changed by the request
+    WorkspaceTracking,
+    /// Stream tool call arguments while the model generates them (sessions created by the request)
+    ToolArgumentStreaming,
+    /// Commit the workspace after the request (requires the `git` feature)
+    GitCheckpoints,
+    /// Drop the oldest messages of the trace once it exceeds the context limit (sessions created by the request)
+    Compaction,
+    /// Run the tool calls of a model reply concurrently (sessions created by the request)
+    ParallelTools,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 5] = [
+        Feature::WorkspaceTracking,
+        Feature::ToolArgumentStreaming,
+        Feature::GitCheckpoints,
+        Feature::Compaction,
+        Feature::ParallelTools,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::WorkspaceTracking => "workspace-tracking",
+            Self::ToolArgumentStreaming => "tool-argument-streaming",
+            Self::GitCheckpoints => "git-checkpoints",
+            Self::Compaction => "compaction",
+            Self::ParallelTools => "parallel-tools",
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Feature {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|feature| feature.name() == name)
+            .ok_or_else(|| format!("unknown feature '{}'", name))
+    }
+}
+
+/// Feature flags resolved for a request, carried in the request extensions
+/// Tool calls run in parallel unless turned off, the other flags are off by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Features {
+    #[serde(default)]
+    pub workspace_tracking: bool,
+    #[serde(default)]
+    pub tool_argument_streaming: bool,
+    #[serde(default)]
+    pub git_checkpoints: bool,
+    #[serde(default)]
+    pub compaction: bool,
+    #[serde(default = "default_parallel_tools")]
+    pub parallel_tools: bool,
+}
+
+fn default_parallel_tools() -> bool {
+    true
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            workspace_tracking: false,
+            tool_argument_streaming: false,
+            git_checkpoints: false,
+            compaction: false,
+            parallel_tools: default_parallel_tools(),
+        }
+    }
+}
+
+impl Features {
+    /// Server defaults, from the session manager settings
+    pub fn from_config(config: &SessionManagerConfig) -> Self {
+        Self {
+            workspace_tracking: config.track_workspace,
+            tool_argument_streaming: config.stream_tool_arguments,
+            git_checkpoints: config.git_checkpoints,
+            compaction: config.compaction,
+            parallel_tools: config.parallel_tool_calls,
+        }
+    }
+
+    pub fn get(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::WorkspaceTracking => self.workspace_tracking,
+            Feature::ToolArgumentStreaming => self.tool_argument_streaming,
+            Feature::GitCheckpoints => self.git_checkpoints,
+            Feature::Compaction => self.compaction,
+            Feature::ParallelTools => self.parallel_tools,
+        }
+    }
+
+    pub fn set(&mut self, feature: Feature, enabled: bool) {
+        match feature {
+            Feature::WorkspaceTracking => self.workspace_tracking = enabled,
+            Feature::ToolArgumentStreaming => self.tool_argument_streaming = enabled,
+            Feature::GitCheckpoints => self.git_checkpoints = enabled,
+            Feature::Compaction => self.compaction = enabled,
+            Feature::ParallelTools => self.parallel_tools = enabled,
+        }
+    }
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags: Vec<String> = Feature::ALL
+            .iter()
+            .map(|feature| format!("{}={}", feature, if self.get(*feature) { "on" } else { "off" }))
+            .collect();
+        write!(f, "{}", flags.join(","))
+    }
+}
+
+/// How clients may change the feature flags of their requests
+#[derive(Debug, Clone, Default)]
+pub struct FeatureConfig {
+    /// Flags the X-Shai-Features header may override (empty = header ignored)
+    pub overridable: Vec<Feature>,
+    /// Flags forced for the requests of an API key (bearer token), applied over the server defaults
+    pub keys: HashMap<String, HashMap<Feature, bool>>,
+    /// Refuse requests naming unknown or non-overridable flags (400) instead of ignoring them
+    pub strict: bool,
+}
+
+pub(crate) fn parse_state(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "on" | "true" | "1" => Some(true),
+        "off" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+impl FeatureConfig {
+    /// Flags of a request: server defaults, then the API key's flags, then the header overrides
+    /// Returns the flags and the header entries that were ignored
+    pub fn resolve(&self, defaults: Features, headers: &HeaderMap) -> Result<(Features, Vec<String>), ErrorResponse> {
+        let mut features = defaults;
+        if let Some(flags) = bearer_token(headers).and_then(|key| self.keys.get(key)) {
+            for (feature, enabled) in flags {
+                features.set(*feature, *enabled);
+            }
+        }
+
+        let Some(header) = headers.get(FEATURES_HEADER) else {
+            return Ok((features, Vec::new()));
+        };
+        let header = header
+            .to_str()
+            .map_err(|_| ErrorResponse::invalid_request(format!("Invalid {} header", FEATURES_HEADER)))?;
+
+        let mut ignored = Vec::new();
+        for entry in header.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let parsed = entry
+                .split_once('=')
+                .ok_or_else(|| format!("'{}' is not name=on|off", entry))
+                .and_then(|(name, value)| {
+                    let feature = name.trim().parse::<Feature>()?;
+                    let enabled = parse_state(value).ok_or_else(|| format!("'{}' is not name=on|off", entry))?;
+                    match self.overridable.contains(&feature) {
+                        true => Ok((feature, enabled)),
+                        false => Err(format!("feature '{}' cannot be overridden", feature)),
+                    }
+                });
+            match parsed {
+                Ok((feature, enabled)) => features.set(feature, enabled),
+                Err(reason) if self.strict => {
+                    return Err(ErrorResponse::invalid_request(format!("Invalid {} header: {}", FEATURES_HEADER, reason)));
+                }
+                Err(reason) => ignored.push(reason),
+            }
+        }
+        Ok((features, ignored))
+    }
+}
+
+/// Resolve the feature flags of every request into its extensions
+pub async fn resolve_features(
+    State(state): State<ServerState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ErrorResponse> {
+    let defaults = Features::from_config(&state.config.session_manager);
+    let (features, ignored) = state.config.features.resolve(defaults, request.headers())?;
+    for reason in &ignored {
+        warn!("{} {}: {}, ignored", request.method(), request.uri().path(), reason);
+    }
+    if request.headers().contains_key(FEATURES_HEADER) {
+        info!("audit: {} {} features {}", request.method(), request.uri().path(), features);
+    }
+    request.extensions_mut().insert(features);
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{header::AUTHORIZATION, HeaderValue};
+
+    fn headers(api_key: Option<&str>, features: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(key) = api_key {
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", key)).unwrap());
+        }
+        if let Some(features) = features {
+            headers.insert(FEATURES_HEADER, HeaderValue::from_str(features).unwrap());
+        }
+        headers
+    }
+
+    fn config(strict: bool) -> FeatureConfig {
+        FeatureConfig {
+            overridable: vec![Feature::WorkspaceTracking, Feature::GitCheckpoints],
+            keys: HashMap::from([(
+                "sk-beta".to_string(),
+                HashMap::from([(Feature::WorkspaceTracking, true), (Feature::ToolArgumentStreaming, true)]),
+            )]),
+            strict,
+        }
+    }
+
+    #[test]
+    fn test_precedence() {
+        let defaults = Features { git_checkpoints: true, ..Default::default() };
+        let config = config(false);
+
+        // server defaults
+        let (features, _) = config.resolve(defaults, &headers(None, None)).unwrap();
+        assert_eq!(features, defaults);
+
+        // key-level flags over the defaults
+        let (features, _) = config.resolve(defaults, &headers(Some("sk-beta"), None)).unwrap();
+        assert!(features.workspace_tracking && features.tool_argument_streaming && features.git_checkpoints);
+
+        // the header over both
+        let (features, ignored) = config
+            .resolve(defaults, &headers(Some("sk-beta"), Some("workspace-tracking=off, git-checkpoints=off")))
+            .unwrap();
+        assert!(!features.workspace_tracking && !features.git_checkpoints);
+        assert!(features.tool_argument_streaming);
+        assert!(ignored.is_empty());
+    }
+
+    #[test]
+    fn test_non_overridable_and_unknown_flags() {
+        let header = Some("tool-argument-streaming=on,auto-merge=off,workspace-tracking=maybe,git-checkpoints=on");
+
+        let (features, ignored) = config(false).resolve(Features::default(), &headers(None, header)).unwrap();
+        assert!(!features.tool_argument_streaming);
+        assert!(features.git_checkpoints);
+        assert_eq!(ignored.len(), 3);
+        assert!(ignored[0].contains("cannot be overridden"));
+        assert!(ignored[1].contains("unknown feature"));
+
+        let error = config(true).resolve(Features::default(), &headers(None, header)).unwrap_err();
+        assert_eq!(error.error.r#type, "invalid_request");
+        assert!(error.error.message.contains("tool-argument-streaming"));
+    }
+
+    #[test]
+    fn test_display_and_serde() {
+        let features = Features { workspace_tracking: true, ..Default::default() };
+        assert_eq!(
+            features.to_string(),
+            "workspace-tracking=on,tool-argument-streaming=off,git-checkpoints=off,compaction=off,parallel-tools=on"
+        );
+        let json = serde_json::to_value(features).unwrap();
+        assert_eq!(json["workspace-tracking"], true);
+        assert_eq!(json["parallel-tools"], true);
+        // flags saved before parallel-tools existed keep the parallel tool calls
+        assert_eq!(serde_json::from_value::<Features>(serde_json::json!({})).unwrap(), Features::default());
+    }
+
+    #[test]
+    fn test_compaction_and_parallel_tools() {
+        let config = FeatureConfig { overridable: vec![Feature::Compaction, Feature::ParallelTools], strict: true, ..Default::default() };
+        let defaults = Features { compaction: true, ..Default::default() };
+        let (features, ignored) = config.resolve(defaults, &headers(None, Some("compaction=off,parallel-tools=off"))).unwrap();
+        assert!(!features.compaction && !features.parallel_tools);
+        assert!(ignored.is_empty());
+        assert_eq!("parallel-tools".parse::<Feature>(), Ok(Feature::ParallelTools));
+    }
+}
@@ -0,0 +1,626 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex as StdMutex};
+
+use chrono::{DateTime, Utc};
+use git2::{Commit, Delta, Index, ObjectType, Oid, Repository, Signature, StatusOptions, StatusShow, Tree};
+use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent};
+use serde::{Deserialize, Serialize};
+use shai_core::agent::{AgentError, AgentEvent};
+use thiserror::Error;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tracing::{debug, info, warn};
+
+use crate::quota::SessionQuota;
+use crate::workspace::WorkspaceConfig;
+
+/// Message of the commit capturing the workspace as it was before the first request
+const BASELINE_MESSAGE: &str = "Workspace baseline";
+
+/// Longest summary kept in a commit subject
+const SUMMARY_MAX_CHARS: usize = 72;
+
+/// Commit body line listing a file left out of the commit
+const SKIPPED_PREFIX: &str = "Skipped (disk quota): ";
+
+#[derive(Debug, Error)]
+pub enum CheckpointError {
+    #[error("git error: {0}")]
+    Git(#[from] git2::Error),
+    #[error("commit not found: {0}")]
+    CommitNotFound(String),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("the workspace has uncommitted changes, reverting would lose them: {}", .0.join(", "))]
+    DirtyWorkspace(Vec<String>),
+    #[error("git checkpoints are not enabled for this session")]
+    Disabled,
+    #[error("agent error: {0}")]
+    Agent(#[from] AgentError),
+    #[error("checkpoint task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
+}
+
+/// A commit of the session workspace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointCommit {
+    pub id: String,
+    /// Request whose changes the commit holds (None for the baseline)
+    pub request_id: Option<String>,
+    pub summary: String,
+    pub created_at: DateTime<Utc>,
+    /// Files left out because they would have gone over the session disk quota
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<String>,
+}
+
+impl CheckpointCommit {
+    fn from_commit(commit: &Commit) -> Self {
+        let message = commit.message().unwrap_or_default();
+        let mut lines = message.lines();
+        let subject = lines.next().unwrap_or_default();
+        let (request_id, summary) = match subject.strip_prefix('[').and_then(|rest| rest.split_once("] ")) {
+            Some((request_id, summary)) => (Some(request_id.to_string()), summary.to_string()),
+            None => (None, subject.to_string()),
+        };
+
+        Self {
+            id: commit.id().to_string(),
+            request_id,
+            summary,
+            created_at: DateTime::from_timestamp(commit.time().seconds(), 0).unwrap_or_default(),
+            skipped: lines.filter_map(|line| line.strip_prefix(SKIPPED_PREFIX)).map(str::to_string).collect(),
+        }
+    }
+}
+
+/// Name of a session in the checkpoint store: its id when it is safe in a ref and a file name, a hash otherwise
+fn session_key(session_id: &str) -> String {
+    let safe = !session_id.is_empty()
+        && !session_id.starts_with('-')
+        && session_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    match safe {
+        true => session_id.to_string(),
+        false => blake3::hash(session_id.as_bytes()).to_hex()[..32].to_string(),
+    }
+}
+
+/// First non-empty line of the request's answer, shortened to fit a commit subject
+fn summary_line(text: &str) -> String {
+    let line = text.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or("No summary");
+    match line.char_indices().nth(SUMMARY_MAX_CHARS) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+/// Checkpoint store opened on the workspace of a session: the working tree is the workspace root,
+/// the index is the session's own
+struct SessionRepository {
+    repo: Repository,
+    index: Index,
+    /// Ref of the last checkpoint of the session
+    head: String,
+}
+
+impl SessionRepository {
+    fn open(store: &Path, key: &str, config: &WorkspaceConfig) -> Result<Self, CheckpointError> {
+        let repo = Repository::open_bare(store)?;
+        repo.set_workdir(&config.root, false)?;
+        let mut index = Index::open(&store.join("indexes").join(key))?;
+        repo.set_index(&mut index)?;
+        Ok(Self { repo, index, head: format!("refs/sessions/{}", key) })
+    }
+
+    fn head_commit(&self) -> Option<Commit<'_>> {
+        self.repo.find_reference(&self.head).ok()?.peel_to_commit().ok()
+    }
+
+    /// Stage every change of the working tree and commit it on the session ref (None if nothing changed)
+    ///
+    /// Staged content the store does not have yet becomes a new blob and is charged to `quota`,
+    /// files that do not fit are left out of the commit and listed in its message.
+    fn commit_all(&mut self, config: &WorkspaceConfig, quota: Option<&SessionQuota>, subject: &str) -> Result<Option<CheckpointCommit>, CheckpointError> {
+        let Self { repo, index, head } = self;
+        let odb = repo.odb()?;
+        let mut skipped = Vec::new();
+
+        let mut options = StatusOptions::new();
+        options.show(StatusShow::Workdir).include_untracked(true).recurse_untracked_dirs(true);
+        for entry in repo.statuses(Some(&mut options))?.iter() {
+            let Some(path) = entry.path().map(PathBuf::from) else {
+                continue;
+            };
+            if config.is_ignored(&path) {
+                continue;
+            }
+            if entry.status().is_wt_deleted() {
+                index.remove_path(&path)?;
+                continue;
+            }
+
+            // content already in the store (unchanged, reverted, committed by another session) is free
+            let file = config.root.join(&path);
+            let size = match Oid::hash_file(ObjectType::Blob, &file) {
+                Ok(id) if odb.exists(id) => 0,
+                _ => fs::metadata(&file).map_or(0, |metadata| metadata.len()),
+            };
+            if size > 0 && quota.is_some_and(|quota| quota.charge_disk(size).is_err()) {
+                skipped.push(path.to_string_lossy().to_string());
+                continue;
+            }
+            index.add_path(&path)?;
+        }
+        index.write()?;
+
+        let tree = repo.find_tree(index.write_tree_to(repo)?)?;
+        let parent = repo.find_reference(head).ok().and_then(|head| head.peel_to_commit().ok());
+        if parent.as_ref().is_some_and(|parent| parent.tree_id() == tree.id()) {
+            return Ok(None);
+        }
+
+        let mut message = subject.to_string();
+        if !skipped.is_empty() {
+            message.push('\n');
+            for path in &skipped {
+                message.push_str(&format!("\n{}{}", SKIPPED_PREFIX, path));
+            }
+        }
+
+        let signature = Signature::now("shai", "shai@localhost")?;
+        let parents: Vec<&Commit> = parent.iter().collect();
+        let id = repo.commit(Some(head.as_str()), &signature, &signature, &message, &tree, &parents)?;
+        Ok(Some(CheckpointCommit::from_commit(&repo.find_commit(id)?)))
+    }
+
+    /// Tracked files changed since the last commit, the ones a revert would lose
+    /// Untracked files are left alone by a revert and not counted
+    fn uncommitted_changes(&self, config: &WorkspaceConfig) -> Result<Vec<String>, CheckpointError> {
+        let mut options = StatusOptions::new();
+        options.show(StatusShow::Workdir).include_untracked(false).include_ignored(false);
+        Ok(self
+            .repo
+            .statuses(Some(&mut options))?
+            .iter()
+            .filter_map(|entry| entry.path().map(str::to_string))
+            .filter(|path| !config.is_ignored(Path::new(path)))
+            .collect())
+    }
+
+    /// Bring the working tree, the index and the session ref from the last checkpoint to `target`
+    /// Only the files that differ between both are written or removed
+    fn reset(&mut self, target: Oid) -> Result<CheckpointCommit, CheckpointError> {
+        let Self { repo, index, head } = self;
+        let target = repo.find_commit(target).map_err(|_| CheckpointError::CommitNotFound(target.to_string()))?;
+        let target_tree = target.tree()?;
+        let current_tree = repo.find_reference(head).ok().and_then(|reference| reference.peel_to_tree().ok());
+        restore_tree(repo, current_tree.as_ref(), &target_tree)?;
+        index.read_tree(&target_tree)?;
+        index.write()?;
+        repo.reference(head, target.id(), true, "revert")?;
+        Ok(CheckpointCommit::from_commit(&target))
+    }
+}
+
+/// Write the files of `to` that differ from `from` in the working tree, remove the ones `to` does not have
+fn restore_tree(repo: &Repository, from: Option<&Tree>, to: &Tree) -> Result<(), CheckpointError> {
+    let root = repo.workdir().map(Path::to_path_buf).unwrap_or_default();
+    let diff = repo.diff_tree_to_tree(from, Some(to), None)?;
+    for delta in diff.deltas() {
+        if delta.status() == Delta::Deleted {
+            if let Some(path) = delta.old_file().path() {
+                match fs::remove_file(root.join(path)) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+            }
+            continue;
+        }
+        let Some(path) = delta.new_file().path() else {
+            continue;
+        };
+        let path = root.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, repo.find_blob(delta.new_file().id())?.content())?;
+    }
+    Ok(())
+}
+
+/// Git checkpoints of a session workspace, one commit per request
+///
+/// Commits go to a checkpoint store of the server (`SHAI_CHECKPOINT_FOLDER`), not to a repository of
+/// the workspace: each session commits on its own ref with its own index, the store shares only the
+/// objects. The repository of the operator, its branches and the history of the other sessions are
+/// never touched, and sessions do not wait for each other.
+///
+/// Only the working tree is reverted, files never committed (ignored or over quota) are kept.
+/// A session only reverts to its own checkpoints.
+pub struct GitWorkspace {
+    config: WorkspaceConfig,
+    quota: Arc<SessionQuota>,
+    /// Folder of the checkpoint store
+    store: PathBuf,
+    /// Name of the session in the store, see session_key
+    key: String,
+    lock: Arc<Mutex<()>>,
+    /// Commits the session may revert to: the baseline, then one per request
+    checkpoints: StdMutex<Vec<String>>,
+}
+
+impl GitWorkspace {
+    /// Get the folder path of the checkpoint store
+    pub fn folder() -> PathBuf {
+        std::env::var("SHAI_CHECKPOINT_FOLDER")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(".shai/checkpoints"))
+    }
+
+    /// Open the checkpoints of a session in the store at the configured folder
+    pub async fn open_default(session_id: &str, config: WorkspaceConfig, quota: Arc<SessionQuota>) -> Result<Self, CheckpointError> {
+        Self::open(Self::folder(), session_id, config, quota).await
+    }
+
+    /// Open (or create) the store at `store` and commit the workspace as the baseline of the session,
+    /// so that its first request can be reverted too
+    /// The baseline is not charged to the session disk quota, the files were there before it
+    pub async fn open(store: impl Into<PathBuf>, session_id: &str, mut config: WorkspaceConfig, quota: Arc<SessionQuota>) -> Result<Self, CheckpointError> {
+        let store = store.into();
+        let store = if store.is_absolute() { store } else { config.root.join(store) };
+        // a store inside the workspace is not part of it
+        if let Ok(relative) = store.strip_prefix(&config.root) {
+            if let Some(Component::Normal(name)) = relative.components().next() {
+                let name = name.to_string_lossy().to_string();
+                if !config.ignore.contains(&name) {
+                    config.ignore.push(name);
+                }
+            }
+        }
+
+        let key = session_key(session_id);
+        let (baseline_store, baseline_key, baseline_config) = (store.clone(), key.clone(), config.clone());
+        let baseline = tokio::task::spawn_blocking(move || -> Result<Option<String>, CheckpointError> {
+            if Repository::open_bare(&baseline_store).is_err() {
+                Repository::init_bare(&baseline_store)?;
+            }
+            fs::create_dir_all(baseline_store.join("indexes"))?;
+            let mut repo = SessionRepository::open(&baseline_store, &baseline_key, &baseline_config)?;
+            if let Some(commit) = repo.head_commit() {
+                return Ok(Some(commit.id().to_string()));
+            }
+            Ok(repo.commit_all(&baseline_config, None, BASELINE_MESSAGE)?.map(|commit| commit.id))
+        })
+        .await??;
+
+        Ok(Self { config, quota, store, key, lock: Arc::new(Mutex::new(())), checkpoints: StdMutex::new(baseline.into_iter().collect()) })
+    }
+
+    fn open_repository(&self) -> Result<SessionRepository, CheckpointError> {
+        SessionRepository::open(&self.store, &self.key, &self.config)
+    }
+
+    fn record_checkpoint(&self, id: &str) {
+        self.checkpoints.lock().unwrap().push(id.to_string());
+    }
+
+    /// Checkpoint of the session a full or abbreviated id names
+    fn find_checkpoint(&self, commit: &str) -> Option<String> {
+        let checkpoints = self.checkpoints.lock().unwrap();
+        let mut matching = checkpoints.iter().filter(|id| id.starts_with(&commit.to_ascii_lowercase()));
+        match (matching.next(), matching.next()) {
+            (Some(id), None) => Some(id.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.config.root
+    }
+
+    /// Start a request: wait for the commit of the previous request of the session and hold the
+    /// checkpoints until its own commit
+    pub async fn begin(self: &Arc<Self>, request_id: String) -> Checkpoint {
+        let guard = self.lock.clone().lock_owned().await;
+        Checkpoint { workspace: self.clone(), request_id, last_message: String::new(), _guard: guard }
+    }
+
+    /// Checkpoints of the session, newest first
+    pub async fn commits(self: &Arc<Self>, limit: usize) -> Result<Vec<CheckpointCommit>, CheckpointError> {
+        let _guard = self.lock.lock().await;
+        let ids: Vec<String> = self.checkpoints.lock().unwrap().iter().rev().take(limit).cloned().collect();
+        let workspace = self.clone();
+        tokio::task::spawn_blocking(move || -> Result<Vec<CheckpointCommit>, CheckpointError> {
+            let repo = Repository::open_bare(&workspace.store)?;
+            ids.iter()
+                .map(|id| -> Result<CheckpointCommit, CheckpointError> {
+                    Ok(CheckpointCommit::from_commit(&repo.find_commit(Oid::from_str(id)?)?))
+                })
+                .collect()
+        })
+        .await?
+    }
+
+    /// Reset the working tree to a checkpoint of the session (full or abbreviated id), the session
+    /// ref moves back to it, the other sessions keep their history
+    /// Refused while tracked files have uncommitted changes, a revert would discard them
+    pub async fn revert(self: &Arc<Self>, commit: &str) -> Result<CheckpointCommit, CheckpointError> {
+        if commit.len() < 4 || !commit.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(CheckpointError::CommitNotFound(commit.to_string()));
+        }
+
+        let _guard = self.lock.lock().await;
+        let id = self.find_checkpoint(commit).ok_or_else(|| CheckpointError::CommitNotFound(commit.to_string()))?;
+        let workspace = self.clone();
+        let target = tokio::task::spawn_blocking(move || -> Result<CheckpointCommit, CheckpointError> {
+            let mut repo = workspace.open_repository()?;
+            let changes = repo.uncommitted_changes(&workspace.config)?;
+            if !changes.is_empty() {
+                return Err(CheckpointError::DirtyWorkspace(changes));
+            }
+            repo.reset(Oid::from_str(&id)?)
+        })
+        .await??;
+
+        // the checkpoints after the target are no longer in the history of the session
+        let mut checkpoints = self.checkpoints.lock().unwrap();
+        if let Some(position) = checkpoints.iter().position(|id| *id == target.id) {
+            checkpoints.truncate(position + 1);
+        }
+        Ok(target)
+    }
+}
+
+/// Note added to the trace after a revert, so the agent knows its earlier changes are gone
+pub fn revert_note(commit: &CheckpointCommit) -> ChatMessage {
+    let label = match &commit.request_id {
+        Some(request_id) => format!("the end of request {}", request_id),
+        None => "the workspace baseline".to_string(),
+    };
+    ChatMessage::System {
+        content: ChatMessageContent::Text(format!(
+            "The workspace was reverted to commit {} ({}). Files changed after that point were restored to their previous state.",
+            &commit.id[..commit.id.len().min(12)],
+            label,
+        )),
+        name: None,
+    }
+}
+
+/// Repository held by a running request, its changes are committed when it ends
+pub struct Checkpoint {
+    workspace: Arc<GitWorkspace>,
+    request_id: String,
+    /// Last answer of the agent, summarized in the commit subject
+    last_message: String,
+    _guard: OwnedMutexGuard<()>,
+}
+
+impl Checkpoint {
+    pub fn record(&mut self, event: &AgentEvent) {
+        match event {
+            AgentEvent::BrainResult { thought: Ok(ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. }), .. } => {
+                self.last_message = text.clone();
+            }
+            AgentEvent::Completed { message, .. } if !message.is_empty() => self.last_message = message.clone(),
+            _ => {}
+        }
+    }
+
+    /// Commit the changes of the request, the repository is released afterwards
+    pub async fn commit(self) -> Result<Option<CheckpointCommit>, CheckpointError> {
+        let subject = format!("[{}] {}", self.request_id, summary_line(&self.last_message));
+        let workspace = self.workspace.clone();
+        let result = tokio::task::spawn_blocking(move || -> Result<Option<CheckpointCommit>, CheckpointError> {
+            workspace.open_repository()?.commit_all(&workspace.config, Some(&workspace.quota), &subject)
+        })
+        .await?;
+        if let Ok(Some(commit)) = &result {
+            self.workspace.record_checkpoint(&commit.id);
+        }
+        drop(self);
+        result
+    }
+
+    /// Commit without waiting, the next request on the repository waits for it
+    pub fn commit_in_background(self) {
+        let request_id = self.request_id.clone();
+        tokio::spawn(async move {
+            match self.commit().await {
+                Ok(Some(commit)) => info!("[{}] - workspace committed as {}", request_id, commit.id),
+                Ok(None) => debug!("[{}] - no workspace change to commit", request_id),
+                Err(e) => warn!("[{}] - failed to commit workspace: {}", request_id, e),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quota::SessionQuotas;
+
+    /// Store of the tests, inside the workspace like the default one
+    const STORE: &str = ".shai/checkpoints";
+
+    fn temp_workspace() -> WorkspaceConfig {
+        let root = std::env::temp_dir().join(format!("shai-git-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        WorkspaceConfig::new(root, vec![".git".to_string(), "node_modules".to_string()])
+    }
+
+    fn assistant(text: &str) -> AgentEvent {
+        AgentEvent::BrainResult {
+            timestamp: Utc::now(),
+            thought: Ok(ChatMessage::Assistant {
+                content: Some(ChatMessageContent::Text(text.to_string())),
+                tool_calls: None,
+                name: None,
+                audio: None,
+                reasoning_content: None,
+                refusal: None,
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_commit_per_request_and_revert() {
+        let config = temp_workspace();
+        let root = config.root.clone();
+        fs::write(root.join("main.rs"), "fn main() {}\n").unwrap();
+        let quota = Arc::new(SessionQuota::new("s1", SessionQuotas::default()));
+        let git = Arc::new(GitWorkspace::open(STORE, "s1", config, quota).await.unwrap());
+
+        let mut checkpoint = git.begin("req-1".to_string()).await;
+        fs::write(root.join("main.rs"), "fn main() { println!(\"hi\"); }\n").unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/notes.txt"), "todo\n").unwrap();
+        checkpoint.record(&assistant("Added a greeting\nand some notes"));
+        let first = checkpoint.commit().await.unwrap().expect("the request changed files");
+        assert_eq!(first.request_id.as_deref(), Some("req-1"));
+        assert_eq!(first.summary, "Added a greeting");
+
+        // nothing changed: no empty commit
+        let checkpoint = git.begin("req-2".to_string()).await;
+        assert!(checkpoint.commit().await.unwrap().is_none());
+
+        let commits = git.commits(10).await.unwrap();
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].id, first.id);
+        assert_eq!(commits[1].summary, BASELINE_MESSAGE);
+        assert!(commits[1].request_id.is_none());
+
+        let baseline = git.revert(&commits[1].id[..8]).await.unwrap();
+        assert_eq!(baseline.id, commits[1].id);
+        assert_eq!(fs::read_to_string(root.join("main.rs")).unwrap(), "fn main() {}\n");
+        assert!(!root.join("src/notes.txt").exists());
+        assert!(root.join(STORE).exists());
+        assert!(matches!(git.revert("deadbeef").await, Err(CheckpointError::CommitNotFound(_))));
+        assert!(matches!(git.revert("HEAD~1").await, Err(CheckpointError::CommitNotFound(_))));
+
+        // the reverted request can be redone and committed again
+        let checkpoint = git.begin("req-3".to_string()).await;
+        fs::write(root.join("main.rs"), "fn main() { again(); }\n").unwrap();
+        assert!(checkpoint.commit().await.unwrap().is_some());
+        assert_eq!(git.commits(10).await.unwrap().len(), 2);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sessions_keep_their_own_history() {
+        let config = temp_workspace();
+        let root = config.root.clone();
+        fs::write(root.join("main.rs"), "fn main() {}\n").unwrap();
+
+        // the repository of the operator, its branch must not move
+        let operator = Repository::init(&root).unwrap();
+        let mut index = operator.index().unwrap();
+        index.add_path(Path::new("main.rs")).unwrap();
+        index.write().unwrap();
+        let tree = operator.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("operator", "operator@localhost").unwrap();
+        let operator_head = operator.commit(Some("HEAD"), &signature, &signature, "Initial commit", &tree, &[]).unwrap();
+
+        let quota = || Arc::new(SessionQuota::new("s", SessionQuotas::default()));
+        let git = Arc::new(GitWorkspace::open(STORE, "s1", config.clone(), quota()).await.unwrap());
+        let other = Arc::new(GitWorkspace::open(STORE, "s2", config, quota()).await.unwrap());
+
+        // both sessions run requests at the same time
+        let checkpoint = git.begin("req-1".to_string()).await;
+        let other_checkpoint = other.begin("req-2".to_string()).await;
+        fs::write(root.join("one.rs"), "fn one() {}\n").unwrap();
+        let first = checkpoint.commit().await.unwrap().unwrap();
+        fs::write(root.join("two.rs"), "fn two() {}\n").unwrap();
+        let foreign = other_checkpoint.commit().await.unwrap().unwrap();
+
+        // a commit of another session is out of reach
+        assert!(matches!(git.revert(&foreign.id).await, Err(CheckpointError::CommitNotFound(_))));
+        assert_eq!(git.commits(10).await.unwrap()[0].id, first.id);
+
+        // reverting a session leaves the history of the other one and of the operator alone
+        let baseline = git.commits(10).await.unwrap()[1].id.clone();
+        git.revert(&baseline).await.unwrap();
+        assert!(!root.join("one.rs").exists());
+        assert!(root.join("two.rs").exists());
+        assert_eq!(other.commits(10).await.unwrap()[0].id, foreign.id);
+        assert_eq!(operator.head().unwrap().target(), Some(operator_head));
+
+        // uncommitted changes are not discarded
+        fs::write(root.join("main.rs"), "fn main() { unsaved(); }\n").unwrap();
+        match other.revert(&foreign.id[..12]).await {
+            Err(CheckpointError::DirtyWorkspace(files)) => assert!(files.contains(&"main.rs".to_string())),
+            result => panic!("expected a dirty workspace, got {:?}", result.map(|commit| commit.id)),
+        }
+        assert_eq!(fs::read_to_string(root.join("main.rs")).unwrap(), "fn main() { unsaved(); }\n");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_open_creates_the_store() {
+        let config = temp_workspace();
+        let root = config.root.clone();
+        let store = std::env::temp_dir().join(format!("shai-checkpoints-{}", uuid::Uuid::new_v4()));
+        let quota = Arc::new(SessionQuota::new("s1", SessionQuotas::default()));
+        let git = Arc::new(GitWorkspace::open(&store, "not a/ref name", config, quota).await.unwrap());
+
+        // no repository is created in the workspace
+        assert!(!root.join(".git").exists());
+        assert!(Repository::open_bare(&store).is_ok());
+        assert_eq!(git.commits(10).await.unwrap()[0].summary, BASELINE_MESSAGE);
+
+        let checkpoint = git.begin("req-1".to_string()).await;
+        fs::write(root.join("main.rs"), "fn main() {}\n").unwrap();
+        assert!(checkpoint.commit().await.unwrap().is_some());
+        assert_eq!(git.commits(10).await.unwrap().len(), 2);
+
+        fs::remove_dir_all(root).unwrap();
+        fs::remove_dir_all(store).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_files_over_the_disk_quota_are_skipped() {
+        let config = temp_workspace();
+        let root = config.root.clone();
+        let quota = Arc::new(SessionQuota::new("s1", SessionQuotas { max_disk_bytes: Some(100), ..Default::default() }));
+        let git = Arc::new(GitWorkspace::open(STORE, "s1", config, quota.clone()).await.unwrap());
+
+        let checkpoint = git.begin("req-1".to_string()).await;
+        fs::write(root.join("small.txt"), "small\n").unwrap();
+        fs::write(root.join("large.bin"), vec![0u8; 1024]).unwrap();
+        let commit = checkpoint.commit().await.unwrap().unwrap();
+        assert_eq!(commit.skipped, vec!["large.bin".to_string()]);
+        assert_eq!(git.commits(1).await.unwrap()[0].skipped, commit.skipped);
+        assert_eq!(quota.usage().disk_bytes, 6);
+        assert_eq!(quota.usage().exceeded_count, 1);
+
+        // only new content is charged: a copy and a change undone cost nothing
+        let checkpoint = git.begin("req-2".to_string()).await;
+        fs::write(root.join("copy.txt"), "small\n").unwrap();
+        fs::write(root.join("small.txt"), "changed\n").unwrap();
+        checkpoint.commit().await.unwrap().unwrap();
+        assert_eq!(quota.usage().disk_bytes, 6 + 8);
+
+        let checkpoint = git.begin("req-3".to_string()).await;
+        fs::write(root.join("small.txt"), "small\n").unwrap();
+        checkpoint.commit().await.unwrap().unwrap();
+        assert_eq!(quota.usage().disk_bytes, 6 + 8);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_summary_line() {
+        assert_eq!(summary_line("\n  Done.  \nDetails"), "Done.");
+        assert_eq!(summary_line(""), "No summary");
+        assert_eq!(summary_line(&"é".repeat(100)), format!("{}...", "é".repeat(SUMMARY_MAX_CHARS)));
+    }
+
+    #[test]
+    fn test_session_key() {
+        assert_eq!(session_key("2f1c-session_1"), "2f1c-session_1");
+        assert_eq!(session_key("../escape").len(), 32);
+        assert_ne!(session_key("a/b"), session_key("a:b"));
+    }
+}
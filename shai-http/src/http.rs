@@ -1,13 +1,48 @@
 use axum::{
+    middleware,
     routing::{get, post},
     Router,
 };
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tower_http::cors::CorsLayer;
-use tracing::info;
+use std::time::Duration;
+use tracing::{info, warn};
+use shai_core::config::config::ShaiConfig;
 
-use crate::session::{SessionManager, SessionManagerConfig};
+use crate::access::{ApiKeys, ApiKeysError};
+use crate::auth::{AuthConfig, AuthLayer};
+use crate::body_log::{BodyLoggingConfig, BodyLoggingLayer};
+use crate::cors::{CorsConfig, CorsError};
+use crate::features::{self, FeatureConfig};
+use crate::model::{ModelRegistry, ModelRegistryError};
+use crate::quota::SessionQuotas;
+use crate::ratelimit::{RateLimitConfig, RateLimitLayer, RateLimiter};
+use crate::rules::{RuleSet, RulesError, TransformRules};
+use crate::run::RunOptions;
+use crate::session::{PersistBackend, SessionManager, SessionManagerConfig, SessionRetention, SessionStoreConfig};
+use crate::shutdown::{self, Draining, DEFAULT_SHUTDOWN_TIMEOUT};
+use crate::streaming::DEFAULT_SSE_HEARTBEAT;
+#[cfg(feature = "prometheus")]
+use crate::session::{CompositeEventSink, LoggingEventSink, PrometheusEventSink};
 use crate::apis;
+use crate::apis::health::ProviderCheck;
+use crate::apis::openai::models::ProviderModels;
+
+/// Default duration over which tool calls are reported as slow
+pub const DEFAULT_SLOW_TOOL_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// Default duration the model list of the provider is cached for by GET /v1/models
+pub const DEFAULT_MODELS_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Default duration GET /v1/ready reuses the outcome of its LLM provider check
+pub const DEFAULT_READY_CACHE_TTL: Duration = Duration::from_secs(10);
+
+/// Default maximum `n` of a chat completion request
+pub const DEFAULT_MAX_CHOICES: u32 = 8;
+
+/// Default number of choices of a chat completion generated at the same time
+pub const DEFAULT_CHOICES_CONCURRENCY: usize = 4;
 
 /// Configuration for the HTTP server
 #[derive(Clone, Debug)]
@@ -16,6 +51,50 @@ pub struct ServerConfig {
     pub address: String,
     /// Session manager configuration
     pub session_manager: SessionManagerConfig,
+    /// Emit whitespace padding at this interval while a non-streaming response is computed
+    /// (None = disabled). Keeps idle-timeout proxies from closing long requests.
+    pub keepalive_padding: Option<Duration>,
+    /// Maximum duration of a single agent run (None = unlimited)
+    pub request_timeout: Option<Duration>,
+    /// Tool calls lasting longer are listed in the run summary (None = not reported)
+    pub slow_tool_threshold: Option<Duration>,
+    /// Token required by admin-scoped operations, sent in X-Shai-Admin-Token (None = admin operations disabled)
+    pub admin_token: Option<String>,
+    /// Transformation rules applied to incoming OpenAI requests (validated)
+    pub rules: TransformRules,
+    /// Server config file the rules were loaded from, re-read by POST /v1/admin/rules/reload
+    pub config_file: Option<PathBuf>,
+    /// Feature flags clients may override per request (defaults come from the session manager settings)
+    pub features: FeatureConfig,
+    /// Agents and models each API key may use (once any is set, requests need one of its keys)
+    pub api_keys: ApiKeys,
+    /// Bearer tokens required on every request (no token = authentication disabled)
+    pub auth: AuthConfig,
+    /// Requests per minute of each API key (no limit = rate limiting disabled)
+    pub rate_limit: RateLimitConfig,
+    /// Origins browsers may call the API from, applied by `start_server` (any origin by default)
+    pub cors: CorsConfig,
+    /// Request and response bodies logged at DEBUG level, redacted (disabled by default)
+    pub body_logging: BodyLoggingConfig,
+    /// Model names routed to a provider and model, next to the agent names
+    pub models: ModelRegistry,
+    /// How long GET /v1/models reuses the model list of the provider (None = fetched on every call)
+    pub models_cache_ttl: Option<Duration>,
+    /// How long GET /v1/ready reuses the outcome of its LLM provider check (None = checked on every probe)
+    pub ready_cache_ttl: Option<Duration>,
+    /// Separate address serving GET /metrics, e.g. "127.0.0.1:9090" (None = served with the API)
+    /// Requires the `prometheus` feature
+    pub metrics_address: Option<String>,
+    /// Largest `n` a chat completion may ask for, larger values are refused with 400
+    pub max_choices: u32,
+    /// Agents running at the same time for the choices of one chat completion
+    pub choices_concurrency: usize,
+    /// Time the running requests are given to end on Ctrl-C or SIGTERM before the sessions are terminated
+    pub shutdown_timeout: Duration,
+    /// Store the sessions are persisted to (None = the one of the SHAI_SESSION_PERSIST_* variables)
+    pub session_store: Option<SessionStoreConfig>,
+    /// A streaming response idle for this long gets a ping event, so that proxies do not close it (None = never)
+    pub sse_heartbeat: Option<Duration>,
 }
 
 impl ServerConfig {
@@ -24,6 +103,27 @@ impl ServerConfig {
         Self {
             address,
             session_manager: SessionManagerConfig::default(),
+            keepalive_padding: None,
+            request_timeout: None,
+            slow_tool_threshold: Some(DEFAULT_SLOW_TOOL_THRESHOLD),
+            admin_token: None,
+            rules: TransformRules::default(),
+            config_file: None,
+            features: FeatureConfig::default(),
+            api_keys: ApiKeys::default(),
+            auth: AuthConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            cors: CorsConfig::default(),
+            body_logging: BodyLoggingConfig::default(),
+            models: ModelRegistry::default(),
+            models_cache_ttl: Some(DEFAULT_MODELS_CACHE_TTL),
+            ready_cache_ttl: Some(DEFAULT_READY_CACHE_TTL),
+            metrics_address: None,
+            max_choices: DEFAULT_MAX_CHOICES,
+            choices_concurrency: DEFAULT_CHOICES_CONCURRENCY,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            session_store: None,
+            sse_heartbeat: Some(DEFAULT_SSE_HEARTBEAT),
         }
     }
 
@@ -38,12 +138,405 @@ impl ServerConfig {
         self.session_manager.max_sessions = max_sessions;
         self
     }
+
+    /// Set the maximum number of concurrent sessions of one client, its API key or X-Shai-User
+    pub fn with_max_sessions_per_owner(mut self, max_sessions_per_owner: Option<usize>) -> Self {
+        self.session_manager.max_sessions_per_owner = max_sessions_per_owner;
+        self
+    }
+
+    /// Set the idle time after which sessions are evicted (None = never)
+    pub fn with_session_ttl(mut self, session_ttl_secs: Option<u64>) -> Self {
+        self.session_manager.session_ttl_secs = session_ttl_secs;
+        self
+    }
+
+    /// Restrict the environment variable names clients may set on sessions
+    pub fn with_env_allowlist(mut self, allowlist: Option<Vec<String>>) -> Self {
+        self.session_manager.env_allowlist = allowlist;
+        self
+    }
+
+    /// Report the files changed by each request (workspace snapshots around every run)
+    pub fn with_track_workspace(mut self, track_workspace: bool) -> Self {
+        self.session_manager.track_workspace = track_workspace;
+        self
+    }
+
+    /// Commit the session workspace to the checkpoint store after every request (requires the `git` feature)
+    pub fn with_git_checkpoints(mut self, git_checkpoints: bool) -> Self {
+        self.session_manager.git_checkpoints = git_checkpoints;
+        self
+    }
+
+    /// Keep the raw output of the tools in the artifact store, created in SHAI_ARTIFACT_FOLDER
+    pub fn with_artifacts(mut self, artifacts: bool) -> Self {
+        self.session_manager.artifacts = artifacts;
+        self
+    }
+
+    /// Stream tool call arguments to clients while the model generates them
+    pub fn with_stream_tool_arguments(mut self, stream_tool_arguments: bool) -> Self {
+        self.session_manager.stream_tool_arguments = stream_tool_arguments;
+        self
+    }
+
+    /// Drop the oldest messages of the agent traces over `max_tokens` (None = DEFAULT_CONTEXT_MAX_TOKENS)
+    pub fn with_compaction(mut self, compaction: bool, max_tokens: Option<u32>) -> Self {
+        self.session_manager.compaction = compaction;
+        if let Some(max_tokens) = max_tokens {
+            self.session_manager.context_max_tokens = max_tokens;
+        }
+        self
+    }
+
+    /// Run the tool calls of a model reply concurrently (default) or one after the other
+    pub fn with_parallel_tool_calls(mut self, parallel_tool_calls: bool) -> Self {
+        self.session_manager.parallel_tool_calls = parallel_tool_calls;
+        self
+    }
+
+    /// Set how long replay descriptors are kept (None = never pruned)
+    pub fn with_replay_retention(mut self, retention_secs: Option<u64>) -> Self {
+        self.session_manager.replay_retention_secs = retention_secs;
+        self
+    }
+
+    /// Set how long deleted sessions stay restorable (None = until deleted permanently)
+    pub fn with_trash_retention(mut self, retention_secs: Option<u64>) -> Self {
+        self.session_manager.trash_retention_secs = retention_secs;
+        self
+    }
+
+    /// Delete saved sessions not saved for `max_age`, or beyond the `max_sessions` most recently saved
+    /// (both None = kept forever), the sessions loaded in memory are kept
+    pub fn with_persisted_retention(mut self, retention: SessionRetention) -> Self {
+        self.session_manager.persisted_retention = retention;
+        self
+    }
+
+    /// Bound the requests waiting for a busy session and how long they wait, the others get 429
+    pub fn with_request_queue(mut self, max_depth: Option<usize>, timeout_secs: Option<u64>) -> Self {
+        self.session_manager.max_queue_depth = max_depth;
+        self.session_manager.queue_timeout_secs = timeout_secs;
+        self
+    }
+
+    /// Open every conversation without a system message with this one
+    pub fn with_system_prompt(mut self, system_prompt: Option<String>) -> Self {
+        self.session_manager.system_prompt = system_prompt;
+        self
+    }
+
+    /// Set the hard memory and disk caps of each session
+    pub fn with_quotas(mut self, quotas: SessionQuotas) -> Self {
+        self.session_manager.quotas = quotas;
+        self
+    }
+
+    /// Cap the LLM tokens each session may consume over all its requests
+    pub fn with_token_budget(mut self, token_budget: Option<u32>) -> Self {
+        self.session_manager.token_budget = token_budget;
+        self
+    }
+
+    /// Set the token enabling admin-scoped operations
+    pub fn with_admin_token(mut self, admin_token: Option<String>) -> Self {
+        self.admin_token = admin_token;
+        self
+    }
+
+    /// Set the request transformation rules, rejected when invalid
+    pub fn with_rules(mut self, rules: TransformRules) -> Result<Self, RulesError> {
+        rules.validate()?;
+        self.rules = rules;
+        Ok(self)
+    }
+
+    /// Load the server config file (JSON), rejected when invalid
+    /// Its `rules` section holds the request transformation rules, reloadable without restarting the server
+    pub fn with_config_file(mut self, path: PathBuf) -> Result<Self, RulesError> {
+        self.rules = TransformRules::from_config_file(&path)?;
+        self.config_file = Some(path);
+        Ok(self)
+    }
+
+    /// Set which feature flags clients may override, per API key and through X-Shai-Features
+    pub fn with_features(mut self, features: FeatureConfig) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// Restrict the agents and models of API keys
+    pub fn with_api_keys(mut self, api_keys: ApiKeys) -> Self {
+        self.api_keys = api_keys;
+        self
+    }
+
+    /// Load the agent and model allowances of API keys from a JSON file
+    pub fn with_api_keys_file(mut self, path: PathBuf) -> Result<Self, ApiKeysError> {
+        self.api_keys = ApiKeys::from_file(&path)?;
+        Ok(self)
+    }
+
+    /// Route model names to providers and models
+    pub fn with_models(mut self, models: ModelRegistry) -> Self {
+        self.models = models;
+        self
+    }
+
+    /// Set how long the model list of the provider is cached for
+    pub fn with_models_cache_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.models_cache_ttl = ttl;
+        self
+    }
+
+    /// Set how long the readiness probe reuses the outcome of its LLM provider check
+    pub fn with_ready_cache_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.ready_cache_ttl = ttl;
+        self
+    }
+
+    /// Load the model registry from a JSON file
+    pub fn with_models_file(mut self, path: PathBuf) -> Result<Self, ModelRegistryError> {
+        self.models = ModelRegistry::from_file(&path)?;
+        Ok(self)
+    }
+
+    /// Serve GET /metrics on its own address instead of the API one (`prometheus` feature)
+    pub fn with_metrics_address(mut self, metrics_address: Option<String>) -> Self {
+        self.metrics_address = metrics_address;
+        self
+    }
+
+    /// Set the largest `n` of a chat completion and how many of its choices run at the same time
+    pub fn with_max_choices(mut self, max_choices: u32, concurrency: usize) -> Self {
+        self.max_choices = max_choices;
+        self.choices_concurrency = concurrency;
+        self
+    }
+
+    /// Require one of the configured bearer tokens on every request outside the exempt paths
+    pub fn with_auth(mut self, auth: AuthConfig) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Limit the requests per minute of each API key, the requests without a valid key share one limit
+    pub fn with_rate_limit(mut self, rate_limit: RateLimitConfig) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    /// Set the origins browsers may call the API from
+    pub fn with_cors(mut self, cors: CorsConfig) -> Self {
+        self.cors = cors;
+        self
+    }
+
+    /// Log the request and response bodies at DEBUG level, with the fields of the config redacted
+    pub fn with_body_logging(mut self, body_logging: BodyLoggingConfig) -> Self {
+        self.body_logging = body_logging;
+        self
+    }
+
+    /// Load the CORS settings from a JSON file
+    pub fn with_cors_file(mut self, path: PathBuf) -> Result<Self, CorsError> {
+        self.cors = CorsConfig::from_file(&path)?;
+        Ok(self)
+    }
+
+    /// Enable keep-alive padding for non-streaming responses
+    pub fn with_keepalive_padding(mut self, interval: Option<Duration>) -> Self {
+        self.keepalive_padding = interval;
+        self
+    }
+
+    /// Set the maximum duration of a single agent run
+    pub fn with_request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Set the duration over which tool calls are reported as slow in run summaries
+    pub fn with_slow_tool_threshold(mut self, threshold: Option<Duration>) -> Self {
+        self.slow_tool_threshold = threshold;
+        self
+    }
+
+    /// Set the time the running requests are given to end when the server stops
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Set the idle time after which a streaming response gets a ping (None = never)
+    pub fn with_sse_heartbeat(mut self, heartbeat: Option<Duration>) -> Self {
+        self.sse_heartbeat = heartbeat;
+        self
+    }
+
+    /// Set the store the sessions are persisted to
+    pub fn with_session_store(mut self, store: SessionStoreConfig) -> Self {
+        self.session_store = Some(store);
+        self
+    }
+
+    /// Open the session store of the config, the one of the SHAI_SESSION_PERSIST_* variables when not set
+    /// The session manager and the handlers load and save the sessions through it
+    pub fn open_session_store(&self) -> Arc<dyn PersistBackend> {
+        self.session_store.clone().unwrap_or_else(SessionStoreConfig::from_env).open()
+    }
+
+    /// Run options shared by all API handlers
+    pub fn run_options(&self) -> RunOptions {
+        RunOptions::default()
+            .with_timeout(self.request_timeout)
+            .with_slow_tool_threshold(self.slow_tool_threshold)
+            .with_heartbeat(self.sse_heartbeat)
+    }
 }
 
 /// Server state holding the session manager
 #[derive(Clone)]
 pub struct ServerState {
     pub session_manager: Arc<SessionManager>,
+    pub config: Arc<ServerConfig>,
+    /// Request transformation rules in effect (reloadable)
+    pub rules: Arc<RuleSet>,
+    /// Model list of the provider, cached for GET /v1/models
+    pub provider_models: Arc<ProviderModels>,
+    /// Outcome of the LLM provider check, cached for GET /v1/ready
+    pub provider_check: Arc<ProviderCheck>,
+    /// Store the sessions are persisted to, shared with the session manager
+    pub session_store: Arc<dyn PersistBackend>,
+}
+
+impl ServerState {
+    /// Build the state from a config, creating its session manager
+    pub fn new(config: ServerConfig) -> Self {
+        let session_store = config.open_session_store();
+        let session_manager = SessionManager::new(config.session_manager.clone()).with_store(session_store);
+        Self::with_session_manager(config, Arc::new(session_manager))
+    }
+
+    /// Build the state around an externally constructed session manager
+    /// (config.session_manager is then only informative)
+    pub fn with_session_manager(config: ServerConfig, session_manager: Arc<SessionManager>) -> Self {
+        let rules = RuleSet::new(config.config_file.clone(), config.rules.clone());
+        Self {
+            session_store: session_manager.store(),
+            session_manager,
+            config: Arc::new(config),
+            rules: Arc::new(rules),
+            provider_models: Arc::new(ProviderModels::default()),
+            provider_check: Arc::new(ProviderCheck::default()),
+        }
+    }
+}
+
+/// Build the API router without binding it
+/// Routes are absolute (/v1/...) so the router can be nested under any prefix of a host application.
+/// Only the feature flags and authentication (when configured) layers are installed,
+/// the host application brings its own middleware (CORS, tracing, ...)
+pub fn build_router(state: ServerState) -> Router {
+    let router = Router::new()
+        // Simple API
+        .route("/v1/multimodal", post(apis::simple::handle_multimodal_query_stream))
+        .route("/v1/multimodal/{session_id}", post(apis::simple::handle_multimodal_query_stream_with_session))
+        // OpenAI-compatible Response API
+        .route("/v1/responses", post(apis::openai::handle_response))
+        .route("/v1/responses/{response_id}", get(apis::openai::handle_get_response).delete(apis::openai::handle_delete_response))
+        .route("/v1/responses/{response_id}/cancel", post(apis::openai::handle_cancel_response))
+        .route("/v1/responses/{response_id}/input_items", get(apis::openai::handle_list_input_items))
+        // OpenAI-compatible Chat Completion API
+        .route("/v1/chat/completions", post(apis::openai::handle_chat_completion))
+        .route("/v1/models", get(apis::openai::handle_list_models))
+        .route("/v1/embeddings", post(apis::openai::handle_embeddings))
+        // Sessions
+        .route("/v1/sessions", get(apis::admin::sessions::handle_list_sessions))
+        .route("/v1/sessions/{session_id}", get(apis::sessions::handle_get_session).delete(apis::sessions::handle_delete_session))
+        .route("/v1/sessions/{session_id}/data", get(apis::admin::sessions::handle_get_session_data))
+        .route("/v1/sessions/{session_id}/restore", post(apis::sessions::handle_restore_session))
+        .route("/v1/sessions/{session_id}/fork", post(apis::sessions::handle_fork_session))
+        .route("/v1/sessions/{session_id}/history", get(apis::sessions::handle_get_session_history))
+        .route("/v1/sessions/{session_id}/tail", get(apis::sessions::handle_tail_session))
+        .route("/v1/sessions/{session_id}/ws", get(apis::websocket::handle_session_ws))
+        .route("/v1/sessions/{session_id}/requests/{request_id}/changes", get(apis::sessions::handle_get_request_changes))
+        // Probes
+        .route("/v1/ready", get(apis::health::handle_ready))
+        // Admin
+        .route("/v1/admin/artifacts/verify", get(apis::admin::handle_verify_artifacts))
+        .route("/v1/admin/replays/{replay_id}", get(apis::admin::handle_get_replay))
+        .route("/v1/admin/providers", get(apis::admin::handle_list_providers))
+        .route("/v1/admin/tools/stats", get(apis::admin::handle_tool_stats))
+        .route("/v1/admin/rules/reload", post(apis::admin::handle_reload_rules))
+        .route("/v1/admin/sessions/prune", post(apis::admin::handle_prune_sessions));
+
+    // Git checkpoints
+    #[cfg(feature = "git")]
+    let router = router
+        .route("/v1/sessions/{session_id}/commits", get(apis::sessions::handle_list_commits))
+        .route("/v1/sessions/{session_id}/revert/{commit}", post(apis::sessions::handle_revert_session));
+
+    // Prometheus metrics, on the API address unless a separate one is configured
+    #[cfg(feature = "prometheus")]
+    let router = {
+        let router = match state.config.metrics_address {
+            Some(_) => router,
+            None => router.route("/metrics", get(crate::metrics::handle_metrics)),
+        };
+        router.route_layer(middleware::from_fn(crate::metrics::track_requests))
+    };
+
+    let router = router.layer(middleware::from_fn_with_state(state.clone(), features::resolve_features));
+
+    // Inside the rejections, only the bodies of authenticated requests are logged
+    let router = match state.config.body_logging.enabled {
+        true => router.layer(BodyLoggingLayer::new(&state.config.body_logging)),
+        false => router,
+    };
+
+    // Counted once authenticated, so that invalid keys fall in the anonymous bucket
+    let router = match state.config.rate_limit.is_enabled() {
+        true => router.layer(RateLimitLayer::new(RateLimiter::new(state.config.rate_limit.clone(), state.config.auth.clone()))),
+        false => router,
+    };
+
+    // Outermost: unauthenticated requests are rejected before any other work
+    let router = match state.config.auth.is_enabled() {
+        true => router.layer(AuthLayer::new(state.config.auth.clone())),
+        false => router,
+    };
+
+    // Root span of each request, around the rejections too
+    #[cfg(feature = "otel")]
+    let router = router.layer(
+        tower_http::trace::TraceLayer::new_for_http().make_span_with(|request: &axum::extract::Request| crate::telemetry::request_span(request)),
+    );
+    router.with_state(state)
+}
+
+/// Install the default tracing subscriber used by the standalone server
+/// Opt-in: applications embedding the router keep their own subscriber
+/// With the otel feature and OTEL_EXPORTER_OTLP_ENDPOINT set, spans are also exported over OTLP
+pub fn init_tracing() {
+    #[cfg(feature = "otel")]
+    if let Some(otel) = crate::telemetry::layer() {
+        use tracing_subscriber::prelude::*;
+        let fmt = tracing_subscriber::fmt::layer()
+            .with_target(false)
+            .with_level(true)
+            .with_filter(tracing_subscriber::EnvFilter::new("shai_http=debug"));
+        tracing_subscriber::registry().with(fmt).with(otel).init();
+        return;
+    }
+
+    tracing_subscriber::fmt()
+        .with_target(false)
+        .with_level(true)
+        .with_env_filter("shai_http=debug")
+        .init();
 }
 
 
@@ -51,8 +544,22 @@ pub struct ServerState {
 pub async fn start_server(
     config: ServerConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Create session manager
-    let session_manager = SessionManager::new(config.session_manager.clone());
+    if let Ok(shai_config) = ShaiConfig::load() {
+        shai_config.install_prompt_adapters();
+    }
+
+    #[cfg(feature = "prometheus")]
+    let state = {
+        crate::metrics::install_recorder();
+        let sink = CompositeEventSink(vec![Arc::new(LoggingEventSink), Arc::new(PrometheusEventSink)]);
+        let session_store = config.open_session_store();
+        let session_manager = SessionManager::new(config.session_manager.clone())
+            .with_event_sink(Arc::new(sink))
+            .with_store(session_store);
+        ServerState::with_session_manager(config.clone(), Arc::new(session_manager))
+    };
+    #[cfg(not(feature = "prometheus"))]
+    let state = ServerState::new(config.clone());
 
     println!("✓ Session manager initialized");
     if let Some(max) = config.session_manager.max_sessions {
@@ -60,25 +567,140 @@ pub async fn start_server(
     } else {
         println!("  Max sessions: \x1b[1munlimited\x1b[0m");
     }
+    if let Some(max) = config.session_manager.max_sessions_per_owner {
+        println!("  Max sessions per client: \x1b[1m{}\x1b[0m", max);
+    }
     println!("  Default mode: \x1b[1m{}\x1b[0m", if config.session_manager.ephemeral { "ephemeral" } else { "persistent" });
+    if let Some(ttl) = config.session_manager.session_ttl_secs {
+        println!("  Session TTL: \x1b[1m{}s\x1b[0m", ttl);
+    }
+    if config.session_manager.track_workspace {
+        println!("  Workspace tracking: \x1b[1menabled\x1b[0m");
+    }
+    if config.session_manager.stream_tool_arguments {
+        println!("  Tool argument streaming: \x1b[1menabled\x1b[0m");
+    }
+    if config.session_manager.compaction {
+        println!("  Compaction: \x1b[1mover {} tokens\x1b[0m", config.session_manager.context_max_tokens);
+    }
+    if !config.session_manager.parallel_tool_calls {
+        println!("  Tool calls: \x1b[1msequential\x1b[0m");
+    }
+    if config.session_manager.artifacts {
+        println!("  Artifacts: \x1b[1m{}\x1b[0m", crate::session::ArtifactStore::folder().display());
+    }
+    #[cfg(feature = "git")]
+    if config.session_manager.git_checkpoints {
+        println!("  Git checkpoints: \x1b[1m{}\x1b[0m", crate::git::GitWorkspace::folder().display());
+    }
+    if config.session_manager.max_queue_depth.is_some() || config.session_manager.queue_timeout_secs.is_some() {
+        println!(
+            "  Request queue: \x1b[1mdepth {}, timeout {}\x1b[0m",
+            config.session_manager.max_queue_depth.map_or("unlimited".to_string(), |depth| depth.to_string()),
+            config.session_manager.queue_timeout_secs.map_or("none".to_string(), |secs| format!("{}s", secs)),
+        );
+    }
+    if let Some(prompt) = &config.session_manager.system_prompt {
+        println!("  System prompt: \x1b[1m{} chars\x1b[0m", prompt.chars().count());
+    }
+    if let Some(store) = &config.session_store {
+        println!("  Session store: \x1b[1m{}\x1b[0m", store.kind());
+        if let SessionStoreConfig::File { encryption: Some(_), .. } = store {
+            println!("  Session files: \x1b[1mencrypted (AES-256-GCM)\x1b[0m");
+        }
+    }
+    match config.session_manager.trash_retention_secs {
+        Some(retention) => println!("  Trash retention: \x1b[1m{}s\x1b[0m", retention),
+        None => println!("  Trash retention: \x1b[1munlimited\x1b[0m"),
+    }
+    let retention = &config.session_manager.persisted_retention;
+    if !retention.is_unlimited() {
+        println!(
+            "  Saved sessions kept: \x1b[1m{}, {}\x1b[0m",
+            retention.max_age.map_or("any age".to_string(), |age| format!("{}s since their last save", age.as_secs())),
+            retention.max_sessions.map_or("any count".to_string(), |max| format!("{} at most", max)),
+        );
+    }
+    let quotas = &config.session_manager.quotas;
+    let quota_bytes = |limit: Option<u64>| limit.map_or("unlimited".to_string(), |bytes| format!("{} bytes", bytes));
+    if quotas.max_trace_bytes.is_some() || quotas.max_disk_bytes.is_some() || quotas.max_persisted_bytes.is_some() {
+        println!(
+            "  Session quotas: \x1b[1mtrace {}, disk {}, persisted {}\x1b[0m",
+            quota_bytes(quotas.max_trace_bytes),
+            quota_bytes(quotas.max_disk_bytes),
+            quota_bytes(quotas.max_persisted_bytes),
+        );
+    }
+    if quotas.max_persisted_messages.is_some() || quotas.max_persisted_trace_bytes.is_some() {
+        println!(
+            "  Persisted trace: \x1b[1m{} messages, {}\x1b[0m (oldest dropped beyond)",
+            quotas.max_persisted_messages.map_or("unlimited".to_string(), |max| max.to_string()),
+            quota_bytes(quotas.max_persisted_trace_bytes),
+        );
+    }
+    if let Some(budget) = config.session_manager.token_budget {
+        println!("  Session token budget: \x1b[1m{} tokens\x1b[0m", budget);
+    }
+    if !config.features.overridable.is_empty() {
+        let overridable: Vec<String> = config.features.overridable.iter().map(|f| f.to_string()).collect();
+        println!(
+            "  Overridable features: \x1b[1m{}\x1b[0m{}",
+            overridable.join(", "),
+            if config.features.strict { " (strict)" } else { "" },
+        );
+    }
+    if config.auth.is_enabled() {
+        let exempt = match config.auth.exempt_paths.is_empty() {
+            true => String::new(),
+            false => format!(" (exempt: {})", config.auth.exempt_paths.join(", ")),
+        };
+        println!("  API key authentication: \x1b[1m{} keys\x1b[0m{}", config.auth.tokens.len(), exempt);
+    }
+    #[cfg(feature = "otel")]
+    if let Some(endpoint) = crate::telemetry::endpoint() {
+        println!("  OpenTelemetry traces: \x1b[1m{}\x1b[0m", endpoint);
+    }
+    if config.body_logging.enabled {
+        println!("  Body logging: \x1b[1mDEBUG level\x1b[0m (redacted: {})", config.body_logging.redact_fields.join(", "));
+    }
+    if let Some(rpm) = config.rate_limit.requests_per_minute {
+        println!("  Rate limit: \x1b[1m{} requests/min per key\x1b[0m (burst {})", rpm, config.rate_limit.burst);
+    }
+    match (config.cors.allow_all, config.cors.allowed_origins.len()) {
+        (true, _) => println!("  CORS: \x1b[1many origin\x1b[0m"),
+        (false, 0) => println!("  CORS: \x1b[1mdisabled\x1b[0m"),
+        (false, origins) => println!("  CORS: \x1b[1m{} origins\x1b[0m", origins),
+    }
+    if !config.models.is_empty() {
+        println!("  Model registry: \x1b[1m{} models, {} providers\x1b[0m", config.models.models.len(), config.models.providers.len());
+    }
+    if !config.api_keys.keys.is_empty() {
+        println!("  API key allowances: \x1b[1m{} keys, other keys refused\x1b[0m", config.api_keys.keys.len());
+    }
+    if !config.rules.rules.is_empty() {
+        println!("  Request rules: \x1b[1m{}\x1b[0m", config.rules.rules.len());
+    }
+    if let Some(interval) = config.keepalive_padding {
+        println!("  Keep-alive padding: \x1b[1m{}s\x1b[0m", interval.as_secs());
+    }
+    match config.sse_heartbeat {
+        Some(interval) => println!("  SSE heartbeat: \x1b[1m{}s\x1b[0m", interval.as_secs()),
+        None => println!("  SSE heartbeat: \x1b[1mdisabled\x1b[0m"),
+    }
+    println!("  Shutdown timeout: \x1b[1m{}s\x1b[0m", config.shutdown_timeout.as_secs());
     println!();
 
-    let state = ServerState {
-        session_manager: Arc::new(session_manager),
-    };
+    let session_manager = state.session_manager.clone();
+    let draining = Draining::default();
 
-    let app = Router::new()
-        // Simple API
-        .route("/v1/multimodal", post(apis::simple::handle_multimodal_query_stream))
-        .route("/v1/multimodal/{session_id}", post(apis::simple::handle_multimodal_query_stream_with_session))
-        // OpenAI-compatible Response API
-        .route("/v1/responses", post(apis::openai::handle_response))
-        .route("/v1/responses/{response_id}", get(apis::openai::handle_get_response))
-        .route("/v1/responses/{response_id}/cancel", post(apis::openai::handle_cancel_response))
-        // OpenAI-compatible Chat Completion API
-        .route("/v1/chat/completions", post(apis::openai::handle_chat_completion))
-        .layer(CorsLayer::permissive())
-        .with_state(state);
+    // Outside the authentication, preflight requests carry no token
+    let app = build_router(state);
+    let app = match config.cors.layer() {
+        Some(cors) => app.layer(cors),
+        None => app,
+    };
+    // Outermost, nothing new starts once the server drains
+    let app = app.layer(middleware::from_fn_with_state(draining.clone(), shutdown::refuse_while_draining));
 
     let listener = tokio::net::TcpListener::bind(&config.address).await?;
 
@@ -88,9 +710,47 @@ pub async fn start_server(
     println!("  \x1b[1mPOST /v1/chat/completions\x1b[0m            - OpenAI Chat Completions API (ephemeral)");
     println!("  \x1b[1mPOST /v1/responses\x1b[0m                    - OpenAI Responses API (stateful/stateless)");
     println!("  \x1b[1mGET  /v1/responses/:id\x1b[0m                - Get response by ID");
+    println!("  \x1b[1mDELETE /v1/responses/:id\x1b[0m             - Delete a stored response");
     println!("  \x1b[1mPOST /v1/responses/:id/cancel\x1b[0m        - Cancel a response");
+    println!("  \x1b[1mGET  /v1/responses/:id/input_items\x1b[0m   - List the input items of a response");
+    println!("  \x1b[1mGET  /v1/models\x1b[0m                     - Agents available to the API key");
+    println!("  \x1b[1mPOST /v1/embeddings\x1b[0m                 - Embeddings from the LLM provider");
     println!("  \x1b[1mPOST /v1/multimodal\x1b[0m                   - Simple multimodal API (streaming)");
     println!("  \x1b[1mPOST /v1/multimodal/:session_id\x1b[0m      - Simple multimodal API (with session)");
+    println!("  \x1b[1mGET  /v1/sessions\x1b[0m                     - Sessions in memory (?limit, ?offset)");
+    println!("  \x1b[1mGET  /v1/sessions/:id\x1b[0m                 - Session state and quota usage");
+    println!("  \x1b[1mGET  /v1/sessions/:id/data\x1b[0m            - Persisted session data");
+    println!("  \x1b[1mDELETE /v1/sessions/:id\x1b[0m              - Move a session to the trash (?permanent=true: admin)");
+    println!("  \x1b[1mPOST /v1/sessions/:id/restore\x1b[0m        - Restore a deleted session");
+    println!("  \x1b[1mPOST /v1/sessions/:id/fork\x1b[0m           - Branch a session (?at_message)");
+    println!("  \x1b[1mGET  /v1/sessions/:id/history\x1b[0m        - Messages of a session (?limit, ?before, ?roles)");
+    println!("  \x1b[1mGET  /v1/sessions/:id/tail\x1b[0m           - Live plain text transcript (?follow, ?lines, ?color)");
+    println!("  \x1b[1mGET  /v1/sessions/:id/ws\x1b[0m             - WebSocket: messages, cancel and tool approvals (?approvals)");
+    println!("  \x1b[1mGET  /v1/sessions/:id/requests/:rid/changes\x1b[0m - Files changed by a request");
+    #[cfg(feature = "git")]
+    {
+        println!("  \x1b[1mGET  /v1/sessions/:id/commits\x1b[0m         - Workspace checkpoints of a session");
+        println!("  \x1b[1mPOST /v1/sessions/:id/revert/:commit\x1b[0m - Reset the workspace to a checkpoint");
+    }
+    println!("  \x1b[1mGET  /v1/ready\x1b[0m                      - Readiness probe");
+    println!("  \x1b[1mGET  /v1/admin/artifacts/verify\x1b[0m     - Artifact store integrity check");
+    println!("  \x1b[1mGET  /v1/admin/replays/:id\x1b[0m          - Replay descriptor of a request");
+    println!("  \x1b[1mGET  /v1/admin/providers\x1b[0m           - Provider circuit breakers");
+    println!("  \x1b[1mGET  /v1/admin/tools/stats\x1b[0m         - Tool call figures (?since=)");
+    println!("  \x1b[1mPOST /v1/admin/rules/reload\x1b[0m        - Reload the rules of the config file");
+    #[cfg(feature = "prometheus")]
+    match &config.metrics_address {
+        Some(address) => {
+            let listener = tokio::net::TcpListener::bind(address).await?;
+            println!("  \x1b[1mGET  /metrics\x1b[0m                      - Prometheus metrics (on http://{})", address);
+            tokio::spawn(async move {
+                if let Err(e) = axum::serve(listener, crate::metrics::metrics_router()).await {
+                    tracing::error!("Metrics server stopped: {}", e);
+                }
+            });
+        }
+        None => println!("  \x1b[1mGET  /metrics\x1b[0m                      - Prometheus metrics"),
+    }
 
     // List available agents
     use shai_core::config::agent::AgentConfig;
@@ -105,6 +765,23 @@ pub async fn start_server(
 
     info!("HTTP server listening on {}", config.address);
 
-    axum::serve(listener, app).await?;
+    // On Ctrl-C or SIGTERM new requests get 503 while the running ones end, then the sessions
+    // are saved and terminated and the server stops once their connections are closed
+    let shutdown_timeout = config.shutdown_timeout;
+    let drained = async move {
+        shutdown::signal().await;
+        draining.start();
+        println!("\nShutting down, waiting up to {}s for the running requests...", shutdown_timeout.as_secs());
+        let still_running = session_manager.shutdown(shutdown_timeout).await;
+        if !still_running.is_empty() {
+            warn!("{} sessions were still running after {}s: {}", still_running.len(), shutdown_timeout.as_secs(), still_running.join(", "));
+        }
+    };
+
+    // Connection info gives the client address to the authentication logs
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(drained)
+        .await?;
+    info!("HTTP server stopped");
     Ok(())
 }
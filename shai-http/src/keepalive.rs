@@ -0,0 +1,120 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::time::Duration;
+
+use axum::{
+    body::{Body, Bytes},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use tokio::time::{interval_at, Instant, Interval};
+use tracing::error;
+
+use crate::ErrorResponse;
+
+/// Aborts the wrapped task when dropped (e.g. client disconnected mid-response)
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Build a non-streaming JSON response that keeps the connection alive while `work` runs
+///
+/// Headers are sent immediately with chunked transfer encoding, then a single space is
+/// emitted every `interval` until the body is ready. Leading whitespace is legal JSON so
+/// standard clients still parse the body. Because the status line is already sent, an
+/// error produced by `work` is returned as an OpenAI error body with a 200 status.
+pub fn padded_json_response<T, F>(interval: Duration, work: F) -> Response
+where
+    T: Serialize + Send + 'static,
+    F: Future<Output = Result<T, ErrorResponse>> + Send + 'static,
+{
+    let task = AbortOnDrop(tokio::spawn(work));
+    let ticker = interval_at(Instant::now() + interval, interval);
+
+    let stream = futures::stream::unfold(Some((task, ticker)), |state| async move {
+        let (mut task, mut ticker): (AbortOnDrop<Result<T, ErrorResponse>>, Interval) = state?;
+        tokio::select! {
+            result = &mut task.0 => {
+                let body = match result {
+                    Ok(Ok(value)) => serde_json::to_vec(&value),
+                    Ok(Err(err)) => serde_json::to_vec(&err),
+                    Err(join_error) => {
+                        error!("Keep-alive response task failed: {}", join_error);
+                        serde_json::to_vec(&ErrorResponse::internal_error(format!("Request failed: {}", join_error)))
+                    }
+                };
+                let body = body.unwrap_or_else(|e| {
+                    error!("Failed to serialize keep-alive response: {}", e);
+                    br#"{"error":{"message":"Failed to serialize response","type":"internal_error"}}"#.to_vec()
+                });
+                Some((Ok::<_, Infallible>(Bytes::from(body)), None))
+            }
+            _ = ticker.tick() => {
+                Some((Ok(Bytes::from_static(b" ")), Some((task, ticker))))
+            }
+        }
+    });
+
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        Body::from_stream(stream),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, serde::Deserialize, Debug, PartialEq)]
+    struct Payload {
+        id: String,
+        value: u32,
+    }
+
+    async fn collect_body(response: Response) -> Vec<u8> {
+        axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_padded_body_is_valid_json() {
+        let response = padded_json_response(Duration::from_millis(10), async {
+            tokio::time::sleep(Duration::from_millis(55)).await;
+            Ok::<_, ErrorResponse>(Payload { id: "resp_1".to_string(), value: 42 })
+        });
+
+        let body = collect_body(response).await;
+        assert!(body.starts_with(b" "), "expected keep-alive padding before the body");
+
+        let parsed: Payload = serde_json::from_slice(&body).expect("strict parser must accept padded body");
+        assert_eq!(parsed, Payload { id: "resp_1".to_string(), value: 42 });
+    }
+
+    #[tokio::test]
+    async fn test_error_body_is_valid_json() {
+        let response = padded_json_response(Duration::from_millis(10), async {
+            tokio::time::sleep(Duration::from_millis(25)).await;
+            Err::<Payload, _>(ErrorResponse::internal_error("boom".to_string()))
+        });
+
+        let body = collect_body(response).await;
+        let parsed: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(parsed["error"]["message"], "boom");
+    }
+
+    #[tokio::test]
+    async fn test_fast_response_has_no_padding() {
+        let response = padded_json_response(Duration::from_secs(10), async {
+            Ok::<_, ErrorResponse>(Payload { id: "resp_2".to_string(), value: 1 })
+        });
+
+        let body = collect_body(response).await;
+        assert_eq!(body.first(), Some(&b'{'));
+    }
+}
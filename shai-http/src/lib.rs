@@ -1,10 +1,42 @@
 pub mod http;
+pub mod access;
+pub mod auth;
+pub mod body_log;
 pub mod apis;
+pub mod client;
+pub mod cors;
 pub mod error;
+pub mod features;
+#[cfg(feature = "git")]
+pub mod git;
+pub mod keepalive;
+#[cfg(feature = "prometheus")]
+pub mod metrics;
+pub mod model;
+pub mod quota;
+pub mod ratelimit;
+pub mod replay;
+pub mod rules;
+pub mod run;
 pub mod session;
+pub mod shutdown;
 pub mod streaming;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+pub mod workspace;
 
-pub use error::{ApiJson, ErrorResponse};
-pub use session::{SessionManager, SessionManagerConfig, AgentSession};
-pub use streaming::{EventFormatter, event_to_sse_stream, session_to_sse_stream};
-pub use http::{ServerConfig, ServerState, start_server};
\ No newline at end of file
+pub use error::{ApiJson, ErrorResponse, OpenAiError};
+pub use access::{ApiKeys, ApiKeysError, KeyAllowance};
+pub use auth::{AuthConfig, AuthLayer};
+pub use body_log::{BodyLoggingConfig, BodyLoggingLayer};
+pub use cors::{CorsConfig, CorsError};
+pub use client::{ClientConfig, ClientError, ClientEvent, EventStream, ShaiClient};
+pub use session::{SessionManager, SessionManagerConfig, AgentSession, PersistBackend, SessionStoreConfig, SessionRetention, SessionKeys};
+pub use features::{Feature, FeatureConfig, Features};
+pub use model::{ModelOverride, ModelRegistry, ModelRegistryError, ModelRoute};
+pub use quota::{QuotaUsage, SessionQuotas};
+pub use ratelimit::{RateLimitConfig, RateLimitLayer, RateLimiter};
+pub use rules::{RuleSet, RulesError, TransformRules};
+pub use run::{AgentRun, RunOptions, RunOutcome, RunStopReason, RunSummary, RunTerminalReason, SlowToolCall, ToolCallStats, run_agent_collect, run_agent_stream};
+pub use streaming::{EventFormatter, event_to_sse_stream, run_to_sse_stream, session_to_sse_stream};
+pub use http::{ServerConfig, ServerState, build_router, init_tracing, start_server};
\ No newline at end of file
@@ -0,0 +1,60 @@
+use std::sync::OnceLock;
+
+use axum::{
+    extract::{MatchedPath, Request},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use tracing::warn;
+
+/// Buckets of the LLM request duration histogram, in seconds
+const LLM_DURATION_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Install the Prometheus recorder of the `metrics` facade, once per process
+/// When the application installed its own recorder first, /metrics only renders what this one recorded
+pub fn install_recorder() -> &'static PrometheusHandle {
+    HANDLE.get_or_init(|| {
+        let builder = PrometheusBuilder::new()
+            .set_buckets_for_metric(Matcher::Full("shai_llm_request_duration_seconds".to_string()), LLM_DURATION_BUCKETS)
+            .expect("histogram buckets are not empty");
+        let recorder = builder.build_recorder();
+        let handle = recorder.handle();
+        if let Err(e) = metrics::set_global_recorder(recorder) {
+            warn!("Prometheus recorder not installed: {}", e);
+        }
+        handle
+    })
+}
+
+/// GET /metrics - Server metrics in the Prometheus text format
+pub async fn handle_metrics() -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        install_recorder().render(),
+    )
+        .into_response()
+}
+
+/// Count the requests by route and status (`shai_requests_total`)
+/// Installed as a route layer: the path label is the route pattern, not the raw path
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| "unmatched".to_string(), |path| path.as_str().to_string());
+    let response = next.run(request).await;
+    let status = response.status().as_u16().to_string();
+    metrics::counter!("shai_requests_total", "path" => path, "status" => status).increment(1);
+    response
+}
+
+/// Router serving /metrics alone, for a listener kept away from the load balancers
+pub fn metrics_router() -> Router {
+    Router::new().route("/metrics", get(handle_metrics))
+}
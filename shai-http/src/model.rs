@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use axum::{extract::FromRequestParts, http::request::Parts};
+use serde::{Deserialize, Serialize};
+use shai_core::config::agent::AgentConfig;
+use thiserror::Error;
+
+use crate::access::DEFAULT_AGENT;
+use crate::ErrorResponse;
+
+/// Request header replacing the model of the agent for that request: `X-Shai-Model: gpt-4o`
+pub const MODEL_HEADER: &str = "x-shai-model";
+
+/// Model override of a request (None = the model configured for the agent)
+/// It applies to the session created by the request only, sessions it continues from keep their model
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModelOverride(pub Option<String>);
+
+impl<S: Send + Sync> FromRequestParts<S> for ModelOverride {
+    type Rejection = ErrorResponse;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get(MODEL_HEADER) else {
+            return Ok(Self(None));
+        };
+        match value.to_str().map(str::trim) {
+            Ok(model) if !model.is_empty() => Ok(Self(Some(model.to_string()))),
+            _ => Err(ErrorResponse::invalid_request(format!("Invalid {} header: expected a model name", MODEL_HEADER))),
+        }
+    }
+}
+
+/// LLM a model name of the registry runs on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelRoute {
+    /// Provider type, as in the shai config (openai, anthropic, ollama...)
+    pub provider: String,
+    pub model: String,
+}
+
+/// Model names requests may send besides agent names, each running the default agent on its own LLM
+/// One server can front several providers: `"model": "fast"` (alias) or `"model": "ollama/qwen2.5"`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelRegistry {
+    /// Alias -> provider and model
+    #[serde(default)]
+    pub models: BTreeMap<String, ModelRoute>,
+    /// Providers any model of which can be named as `provider/model`
+    #[serde(default)]
+    pub providers: Vec<String>,
+}
+
+/// Error loading the model registry
+#[derive(Debug, Error)]
+pub enum ModelRegistryError {
+    #[error("failed to read {path}: {source}")]
+    Io { path: String, source: std::io::Error },
+    #[error("invalid models file {path}: {source}")]
+    Parse { path: String, source: serde_json::Error },
+}
+
+impl ModelRegistry {
+    /// Load the registry from a JSON file:
+    /// `{ "models": { "fast": { "provider": "groq", "model": "llama-3.1-8b-instant" } }, "providers": ["ollama"] }`
+    pub fn from_file(path: &Path) -> Result<Self, ModelRegistryError> {
+        let display = path.display().to_string();
+        let content = std::fs::read_to_string(path).map_err(|source| ModelRegistryError::Io { path: display.clone(), source })?;
+        serde_json::from_str(&content).map_err(|source| ModelRegistryError::Parse { path: display, source })
+    }
+
+    /// Register an alias of `model` on `provider`
+    pub fn with_model(mut self, name: &str, provider: &str, model: &str) -> Self {
+        self.models.insert(name.to_string(), ModelRoute { provider: provider.to_string(), model: model.to_string() });
+        self
+    }
+
+    /// Accept `provider/<any model>` names
+    pub fn with_provider(mut self, provider: &str) -> Self {
+        self.providers.push(provider.to_string());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty() && self.providers.is_empty()
+    }
+
+    /// LLM of a registry model name, None for the names outside the registry (agent names)
+    pub fn resolve(&self, name: &str) -> Option<ModelRoute> {
+        if let Some(route) = self.models.get(name) {
+            return Some(route.clone());
+        }
+        let (provider, model) = name.split_once('/')?;
+        (!model.is_empty() && self.providers.iter().any(|known| known == provider))
+            .then(|| ModelRoute { provider: provider.to_string(), model: model.to_string() })
+    }
+
+    /// Agent a request naming `model` runs, with the LLM replacing its own when `model` is in the registry
+    /// Names that are neither in the registry nor an agent are refused with 404 model_not_found
+    pub fn route(&self, model: &str) -> Result<(String, Option<ModelRoute>), ErrorResponse> {
+        if let Some(route) = self.resolve(model) {
+            return Ok((DEFAULT_AGENT.to_string(), Some(route)));
+        }
+        if model.is_empty() || model == DEFAULT_AGENT {
+            return Ok((DEFAULT_AGENT.to_string(), None));
+        }
+        if AgentConfig::is_pattern(model) || AgentConfig::exists(model) {
+            return Ok((model.to_string(), None));
+        }
+        Err(ErrorResponse::not_found(format!("The model '{}' does not exist", model)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    async fn extract(header: Option<&str>) -> Result<ModelOverride, ErrorResponse> {
+        let mut request = Request::builder();
+        if let Some(value) = header {
+            request = request.header(MODEL_HEADER, value);
+        }
+        let (mut parts, _) = request.body(()).unwrap().into_parts();
+        ModelOverride::from_request_parts(&mut parts, &()).await
+    }
+
+    #[tokio::test]
+    async fn test_model_header() {
+        assert_eq!(extract(None).await.unwrap(), ModelOverride(None));
+        assert_eq!(extract(Some(" gpt-4o ")).await.unwrap(), ModelOverride(Some("gpt-4o".to_string())));
+        assert!(extract(Some("  ")).await.is_err());
+    }
+
+    #[test]
+    fn test_registry_routes() {
+        let registry = ModelRegistry::default()
+            .with_model("fast", "groq", "llama-3.1-8b-instant")
+            .with_provider("ollama");
+
+        assert_eq!(registry.resolve("fast").unwrap().model, "llama-3.1-8b-instant");
+        assert_eq!(
+            registry.resolve("ollama/qwen2.5"),
+            Some(ModelRoute { provider: "ollama".to_string(), model: "qwen2.5".to_string() })
+        );
+        assert_eq!(registry.resolve("ollama/"), None);
+        assert_eq!(registry.resolve("openai/gpt-4o"), None);
+
+        let (agent, route) = registry.route("ollama/qwen2.5").unwrap();
+        assert_eq!((agent.as_str(), route.unwrap().provider.as_str()), (DEFAULT_AGENT, "ollama"));
+        assert_eq!(registry.route("default").unwrap(), (DEFAULT_AGENT.to_string(), None));
+
+        let error = registry.route("no-such-model-xyz").unwrap_err();
+        assert_eq!(error.error.code.as_deref(), Some("model_not_found"));
+    }
+}
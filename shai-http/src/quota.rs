@@ -0,0 +1,276 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use openai_dive::v1::resources::chat::ChatMessage;
+use serde::{Deserialize, Serialize};
+use shai_core::agent::{AgentError, TokenBudget};
+use shai_core::tools::{DiskQuota, QuotaExceeded};
+use tracing::warn;
+
+use crate::session::{colored_session_id, PersistLimits, SessionEventSink};
+
+/// Hard per-session resource caps (None = unlimited)
+#[derive(Clone, Debug, Default)]
+pub struct SessionQuotas {
+    /// Serialized size of the conversation trace held in memory, requests that would go over it are rejected
+    pub max_trace_bytes: Option<u64>,
+    /// Bytes written by the file tools plus the artifacts stored for the session,
+    /// writes beyond it fail with a quota error result
+    pub max_disk_bytes: Option<u64>,
+    /// Serialized size of the persisted session, a larger session is no longer saved
+    pub max_persisted_bytes: Option<u64>,
+    /// Messages of the persisted trace, the oldest ones are dropped from the saved copy beyond it
+    /// (the agent keeps its whole trace in memory)
+    pub max_persisted_messages: Option<usize>,
+    /// Serialized size of the persisted trace, the oldest messages are dropped from the saved copy beyond it
+    pub max_persisted_trace_bytes: Option<u64>,
+}
+
+impl SessionQuotas {
+    /// Limits applied when the session is saved
+    pub fn persist_limits(&self) -> PersistLimits {
+        PersistLimits {
+            max_bytes: self.max_persisted_bytes,
+            max_trace_messages: self.max_persisted_messages,
+            max_trace_bytes: self.max_persisted_trace_bytes,
+        }
+    }
+}
+
+/// The quota an operation went over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaKind {
+    Trace,
+    Disk,
+    Persisted,
+    Tokens,
+}
+
+impl fmt::Display for QuotaKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Trace => write!(f, "trace"),
+            Self::Disk => write!(f, "disk"),
+            Self::Persisted => write!(f, "persisted"),
+            Self::Tokens => write!(f, "tokens"),
+        }
+    }
+}
+
+/// Quota consumption of a session, reported by GET /v1/sessions/{id}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    pub trace_bytes: u64,
+    pub max_trace_bytes: Option<u64>,
+    pub disk_bytes: u64,
+    pub max_disk_bytes: Option<u64>,
+    /// Size of the last saved version of the session
+    pub persisted_bytes: u64,
+    pub max_persisted_bytes: Option<u64>,
+    /// Operations refused because they went over a cap
+    pub exceeded_count: u64,
+    /// LLM tokens consumed by the session, the ones of its requests before a restore included
+    #[serde(default)]
+    pub tokens_used: u64,
+    #[serde(default)]
+    pub token_budget: Option<u64>,
+}
+
+/// Serialized size of a trace, the unit of the trace quota
+pub fn trace_bytes(trace: &[ChatMessage]) -> u64 {
+    serde_json::to_vec(trace).map_or(0, |json| json.len() as u64)
+}
+
+/// Quota consumption of one session
+///
+/// Every counter is updated when the consumption changes (end of a request, tool write,
+/// artifact stored, session saved), nothing is recomputed from the disk.
+pub struct SessionQuota {
+    session_id: String,
+    quotas: SessionQuotas,
+    disk: Arc<DiskQuota>,
+    tokens: Arc<TokenBudget>,
+    trace_bytes: AtomicU64,
+    persisted_bytes: AtomicU64,
+    dropped_messages: AtomicUsize,
+    exceeded: AtomicU64,
+    event_sink: Option<Arc<dyn SessionEventSink>>,
+}
+
+impl SessionQuota {
+    pub fn new(session_id: &str, quotas: SessionQuotas) -> Self {
+        let disk = quotas.max_disk_bytes.map_or_else(DiskQuota::unlimited, DiskQuota::new);
+        Self {
+            session_id: session_id.to_string(),
+            quotas,
+            disk: Arc::new(disk),
+            tokens: Arc::new(TokenBudget::unlimited()),
+            trace_bytes: AtomicU64::new(0),
+            persisted_bytes: AtomicU64::new(0),
+            dropped_messages: AtomicUsize::new(0),
+            exceeded: AtomicU64::new(0),
+            event_sink: None,
+        }
+    }
+
+    /// Report refused operations to this sink (in addition to the warning log)
+    pub fn with_event_sink(mut self, event_sink: Arc<dyn SessionEventSink>) -> Self {
+        self.event_sink = Some(event_sink);
+        self
+    }
+
+    /// Tokens the session may consume over all its requests (None = only tracked)
+    pub fn with_token_budget(mut self, limit: Option<u32>) -> Self {
+        self.tokens = Arc::new(limit.map_or_else(TokenBudget::unlimited, |limit| TokenBudget::new(limit as u64)));
+        self
+    }
+
+    /// Token budget shared with the session's agent
+    pub fn tokens(&self) -> &Arc<TokenBudget> {
+        &self.tokens
+    }
+
+    /// Disk quota shared with the session's file tools
+    pub fn disk(&self) -> &Arc<DiskQuota> {
+        &self.disk
+    }
+
+    pub fn max_persisted_bytes(&self) -> Option<u64> {
+        self.quotas.max_persisted_bytes
+    }
+
+    pub fn persist_limits(&self) -> PersistLimits {
+        self.quotas.persist_limits()
+    }
+
+    /// Messages dropped from the persisted trace before the one the agent started from
+    pub fn dropped_messages(&self) -> usize {
+        self.dropped_messages.load(Ordering::SeqCst)
+    }
+
+    pub fn usage(&self) -> QuotaUsage {
+        QuotaUsage {
+            trace_bytes: self.trace_bytes.load(Ordering::SeqCst),
+            max_trace_bytes: self.quotas.max_trace_bytes,
+            disk_bytes: self.disk.used(),
+            max_disk_bytes: self.quotas.max_disk_bytes,
+            persisted_bytes: self.persisted_bytes.load(Ordering::SeqCst),
+            max_persisted_bytes: self.quotas.max_persisted_bytes,
+            exceeded_count: self.exceeded.load(Ordering::SeqCst),
+            tokens_used: self.tokens.used(),
+            token_budget: self.tokens.limit(),
+        }
+    }
+
+    /// Check that the messages of a new request fit in the trace quota
+    pub fn check_trace(&self, incoming: &[ChatMessage]) -> Result<(), AgentError> {
+        let Some(limit) = self.quotas.max_trace_bytes else {
+            return Ok(());
+        };
+        let total = self.trace_bytes.load(Ordering::SeqCst) + trace_bytes(incoming);
+        if total <= limit {
+            return Ok(());
+        }
+
+        let message = format!("session trace would grow to {} bytes, over its {} bytes quota", total, limit);
+        self.exceeded(QuotaKind::Trace, &message);
+        Err(AgentError::QuotaExceeded(message))
+    }
+
+    /// Check that the session has tokens left for a new request
+    pub fn check_tokens(&self) -> Result<(), AgentError> {
+        self.tokens.check().inspect_err(|e| self.exceeded(QuotaKind::Tokens, &e.to_string()))
+    }
+
+    /// Record the trace held by the agent (at the end of each request)
+    pub fn record_trace(&self, trace: &[ChatMessage]) {
+        self.trace_bytes.store(trace_bytes(trace), Ordering::SeqCst);
+    }
+
+    /// Record the size of the saved session
+    pub fn record_persisted(&self, bytes: u64) {
+        self.persisted_bytes.store(bytes, Ordering::SeqCst);
+    }
+
+    /// Record the messages dropped from the saved version a session is resumed from
+    pub fn record_dropped_messages(&self, count: usize) {
+        self.dropped_messages.store(count, Ordering::SeqCst);
+    }
+
+    /// Charge data the server stores for the session (artifacts, git checkpoints) to the disk quota
+    pub fn charge_disk(&self, bytes: u64) -> Result<(), QuotaExceeded> {
+        self.disk.try_charge(bytes).inspect_err(|e| self.exceeded(QuotaKind::Disk, &e.to_string()))
+    }
+
+    /// Report an operation refused by a cap: warning log and event sink
+    pub fn exceeded(&self, kind: QuotaKind, message: &str) {
+        self.exceeded.fetch_add(1, Ordering::SeqCst);
+        warn!("{} - {} quota exceeded: {}", colored_session_id(&self.session_id), kind, message);
+        if let Some(sink) = &self.event_sink {
+            sink.on_quota_exceeded(&self.session_id, kind);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai_dive::v1::resources::chat::ChatMessageContent;
+
+    fn user(text: &str) -> ChatMessage {
+        ChatMessage::User { content: ChatMessageContent::Text(text.to_string()), name: None }
+    }
+
+    #[test]
+    fn test_trace_quota() {
+        let history = vec![user(&"a".repeat(100))];
+        let limit = trace_bytes(&history) + 40;
+        let quota = SessionQuota::new("s1", SessionQuotas { max_trace_bytes: Some(limit), ..Default::default() });
+        quota.record_trace(&history);
+
+        assert!(quota.check_trace(&[user("short")]).is_ok());
+        let refused = quota.check_trace(&[user(&"b".repeat(100))]);
+        assert!(matches!(refused, Err(AgentError::QuotaExceeded(_))));
+
+        let usage = quota.usage();
+        assert_eq!(usage.trace_bytes, trace_bytes(&history));
+        assert_eq!(usage.max_trace_bytes, Some(limit));
+        assert_eq!(usage.exceeded_count, 1);
+    }
+
+    #[test]
+    fn test_tools_and_artifacts_share_the_disk_quota() {
+        let quota = SessionQuota::new("s1", SessionQuotas { max_disk_bytes: Some(100), ..Default::default() });
+        quota.disk().try_charge(70).unwrap();
+
+        assert!(quota.charge_disk(20).is_ok());
+        assert!(quota.charge_disk(20).is_err());
+        assert_eq!(quota.usage().disk_bytes, 90);
+        assert_eq!(quota.usage().exceeded_count, 1);
+
+        // without caps usage is still tracked
+        let unlimited = SessionQuota::new("s2", SessionQuotas::default());
+        unlimited.charge_disk(1 << 40).unwrap();
+        assert_eq!(unlimited.usage().disk_bytes, 1 << 40);
+        assert!(unlimited.check_trace(&[user("hi")]).is_ok());
+    }
+
+    #[test]
+    fn test_token_budget() {
+        let quota = SessionQuota::new("s1", SessionQuotas::default()).with_token_budget(Some(100));
+        quota.tokens().record(80, 10);
+        assert!(quota.check_tokens().is_ok());
+
+        quota.tokens().record(20, 5);
+        assert!(matches!(quota.check_tokens(), Err(AgentError::BudgetExceeded { used: 115, limit: 100 })));
+        let usage = quota.usage();
+        assert_eq!((usage.tokens_used, usage.token_budget, usage.exceeded_count), (115, Some(100), 1));
+
+        let unlimited = SessionQuota::new("s2", SessionQuotas::default());
+        unlimited.tokens().record(1000, 1000);
+        assert!(unlimited.check_tokens().is_ok());
+        assert_eq!(unlimited.usage().tokens_used, 2000);
+    }
+}
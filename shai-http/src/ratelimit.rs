@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::Request,
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use shai_llm::rotation::key_fingerprint;
+use tower::{Layer, Service};
+use tracing::warn;
+
+use crate::auth::AuthConfig;
+use crate::rules::bearer_token;
+use crate::ErrorResponse;
+
+/// Length of the rate limit window
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Bucket of the requests without a valid API key
+const ANONYMOUS: &str = "anonymous";
+
+/// Requests each API key may send per minute
+/// Rate limiting is disabled when no limit is configured
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitConfig {
+    /// Requests per minute of each key (None = unlimited)
+    pub requests_per_minute: Option<u32>,
+    /// Requests a key may send over its per minute limit before being refused
+    pub burst: u32,
+}
+
+impl RateLimitConfig {
+    pub fn new(requests_per_minute: u32) -> Self {
+        Self { requests_per_minute: Some(requests_per_minute), burst: 0 }
+    }
+
+    /// Limit from SHAI_RATE_LIMIT_RPM and burst from SHAI_RATE_LIMIT_BURST
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().and_then(|value| value.trim().parse::<u32>().ok());
+        Self {
+            requests_per_minute: var("SHAI_RATE_LIMIT_RPM").filter(|rpm| *rpm > 0),
+            burst: var("SHAI_RATE_LIMIT_BURST").unwrap_or(0),
+        }
+    }
+
+    /// Allow this many requests over the per minute limit
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.requests_per_minute.is_some()
+    }
+}
+
+/// Sliding window counter of one key: the requests of the current window, plus the ones of the
+/// previous window weighted by how much of it the sliding window still covers
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    current: u32,
+    previous: u32,
+}
+
+impl Window {
+    fn new(now: Instant) -> Self {
+        Self { started: now, current: 0, previous: 0 }
+    }
+
+    /// Move the window forward to `now`
+    fn advance(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.started);
+        if elapsed < WINDOW {
+            return;
+        }
+        // the previous window only counts when it is the one right before
+        self.previous = if elapsed < WINDOW * 2 { self.current } else { 0 };
+        self.current = 0;
+        self.started = now - Duration::from_nanos((elapsed.as_nanos() % WINDOW.as_nanos()) as u64);
+    }
+
+    /// Requests sent over the last minute
+    fn estimate(&self, now: Instant) -> f64 {
+        let covered = 1.0 - now.duration_since(self.started).as_secs_f64() / WINDOW.as_secs_f64();
+        self.previous as f64 * covered.max(0.0) + self.current as f64
+    }
+
+    /// Time until the current window ends, when the oldest requests stop counting
+    fn retry_after(&self, now: Instant) -> Duration {
+        WINDOW.saturating_sub(now.duration_since(self.started))
+    }
+}
+
+/// Windows of the buckets, with the time idle buckets were last dropped
+#[derive(Debug)]
+struct Windows {
+    buckets: HashMap<String, Window>,
+    pruned: Instant,
+}
+
+impl Windows {
+    /// Drop the buckets idle for two windows, they no longer weigh on the estimate
+    /// Runs at most once per window, not on every request
+    fn prune(&mut self, now: Instant) {
+        if now.saturating_duration_since(self.pruned) < WINDOW {
+            return;
+        }
+        self.buckets.retain(|_, window| now.saturating_duration_since(window.started) < WINDOW * 2);
+        self.pruned = now;
+    }
+}
+
+/// Request counts of the API keys
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    auth: AuthConfig,
+    windows: Mutex<Windows>,
+}
+
+impl RateLimiter {
+    /// Keys are only told apart when `auth` accepts them, other requests share the anonymous bucket
+    pub fn new(config: RateLimitConfig, auth: AuthConfig) -> Self {
+        let windows = Windows { buckets: HashMap::new(), pruned: Instant::now() };
+        Self { config, auth, windows: Mutex::new(windows) }
+    }
+
+    /// Bucket of a request: its API key when valid, the anonymous one otherwise
+    fn bucket(&self, request: &Request) -> String {
+        match bearer_token(request.headers()) {
+            Some(token) if self.auth.is_enabled() && self.auth.accepts(token) => token.to_string(),
+            _ => ANONYMOUS.to_string(),
+        }
+    }
+
+    /// Count a request of `bucket`, Err(retry after) when it is over the limit
+    fn check(&self, bucket: &str, now: Instant) -> Result<(), Duration> {
+        let Some(rpm) = self.config.requests_per_minute else {
+            return Ok(());
+        };
+        let limit = (rpm + self.config.burst) as f64;
+
+        let mut windows = self.windows.lock().unwrap();
+        windows.prune(now);
+        let window = match windows.buckets.get_mut(bucket) {
+            Some(window) => window,
+            None => windows.buckets.entry(bucket.to_string()).or_insert_with(|| Window::new(now)),
+        };
+        window.advance(now);
+
+        let estimate = window.estimate(now);
+        #[cfg(feature = "prometheus")]
+        metrics::gauge!("shai_rate_limit_current_rpm", "key" => label(bucket)).set(estimate);
+        if estimate + 1.0 > limit {
+            return Err(window.retry_after(now).max(Duration::from_secs(1)));
+        }
+        window.current += 1;
+        Ok(())
+    }
+}
+
+/// Name of a bucket in logs and metrics, keys are not exposed
+fn label(bucket: &str) -> String {
+    match bucket {
+        ANONYMOUS => ANONYMOUS.to_string(),
+        key => key_fingerprint(key),
+    }
+}
+
+/// Rejects the requests of the keys over their rate limit with 429 and a Retry-After header
+#[derive(Debug, Clone)]
+pub struct RateLimitLayer {
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitLayer {
+    pub fn new(limiter: RateLimiter) -> Self {
+        Self { limiter: Arc::new(limiter) }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService { inner, limiter: self.limiter.clone() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<S> Service<Request> for RateLimitService<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let bucket = self.limiter.bucket(&request);
+        let Err(retry_after) = self.limiter.check(&bucket, Instant::now()) else {
+            return Box::pin(self.inner.call(request));
+        };
+
+        let label = label(&bucket);
+        warn!("rate limit: {} {} from {} refused, retry in {}s", request.method(), request.uri().path(), label, retry_after.as_secs());
+        #[cfg(feature = "prometheus")]
+        metrics::counter!("shai_rate_limit_rejected_total", "key" => label).increment(1);
+
+        let mut response = ErrorResponse::rate_limited(format!(
+            "Rate limit exceeded ({} requests per minute), retry in {}s",
+            self.limiter.config.requests_per_minute.unwrap_or_default(),
+            retry_after.as_secs()
+        ))
+        .into_response();
+        response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry_after.as_secs()));
+        Box::pin(async move { Ok(response) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sliding_window() {
+        let limiter = RateLimiter::new(RateLimitConfig::new(2).with_burst(1), AuthConfig::default());
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check("key", start).is_ok());
+        }
+        let retry_after = limiter.check("key", start + Duration::from_secs(10)).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(50));
+        // other keys have their own window
+        assert!(limiter.check("other", start).is_ok());
+
+        // halfway through the next window, half of the previous requests still count
+        assert!(limiter.check("key", start + Duration::from_secs(90)).is_ok());
+        assert!(limiter.check("key", start + Duration::from_secs(90)).is_err());
+        // two windows later they are forgotten
+        assert!(limiter.check("key", start + Duration::from_secs(200)).is_ok());
+    }
+
+    #[test]
+    fn test_idle_buckets_pruned_once_per_window() {
+        let limiter = RateLimiter::new(RateLimitConfig::new(10), AuthConfig::default());
+        let start = Instant::now();
+        let buckets = || {
+            let mut names: Vec<String> = limiter.windows.lock().unwrap().buckets.keys().cloned().collect();
+            names.sort();
+            names
+        };
+        assert!(limiter.check("idle", start).is_ok());
+        assert!(limiter.check("late", start + Duration::from_secs(20)).is_ok());
+
+        // buckets idle for two windows are dropped
+        assert!(limiter.check("busy", start + Duration::from_secs(130)).is_ok());
+        assert_eq!(buckets(), vec!["busy", "late"]);
+
+        // until a window has passed since the last prune, they are left for the next one
+        assert!(limiter.check("busy", start + Duration::from_secs(150)).is_ok());
+        assert_eq!(buckets(), vec!["busy", "late"]);
+        assert!(limiter.check("busy", start + Duration::from_secs(190)).is_ok());
+        assert_eq!(buckets(), vec!["busy"]);
+    }
+
+    #[test]
+    fn test_unauthenticated_requests_share_a_bucket() {
+        let auth = AuthConfig::new(vec!["sk-one".to_string()]);
+        let limiter = RateLimiter::new(RateLimitConfig::new(10), auth);
+        let request = |token: Option<&str>| {
+            let mut builder = Request::builder().uri("/v1/models");
+            if let Some(token) = token {
+                builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            builder.body(axum::body::Body::empty()).unwrap()
+        };
+
+        assert_eq!(limiter.bucket(&request(Some("sk-one"))), "sk-one");
+        assert_eq!(limiter.bucket(&request(Some("sk-invalid"))), ANONYMOUS);
+        assert_eq!(limiter.bucket(&request(None)), ANONYMOUS);
+        assert_eq!(label("sk-one"), key_fingerprint("sk-one"));
+    }
+}
@@ -0,0 +1,302 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{DateTime, Utc};
+use openai_dive::v1::resources::chat::ChatMessage;
+use serde::{Deserialize, Serialize};
+use shai_core::config::agent::AgentConfig;
+use shai_core::config::config::ShaiConfig;
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+type ReplayError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Response header carrying the replay id of a request
+pub const REPLAY_ID_HEADER: &str = "x-shai-replay-id";
+
+/// Default time descriptors are kept before being pruned
+pub const DEFAULT_REPLAY_RETENTION: Duration = Duration::from_secs(30 * 24 * 3600);
+
+/// Minimum delay between two pruning passes
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Sampling parameters the request ran with (request overrides, else agent config)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SamplingParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+}
+
+/// Everything needed to reproduce a request, referenced by users in bug reports
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayDescriptor {
+    pub id: String,
+    pub request_id: String,
+    pub session_id: String,
+    /// API the request came from (responses, chat.completions, multimodal)
+    pub api: String,
+    pub created_at: DateTime<Utc>,
+    pub agent: String,
+    /// Hash of the agent (or provider) config the request ran with
+    pub config_version: Option<String>,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub sampling: SamplingParams,
+    /// Hash of the trace sent to the agent
+    pub prompt_hash: String,
+    /// Recorded cassette for this request, filled when the descriptor is looked up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cassette: Option<String>,
+}
+
+impl ReplayDescriptor {
+    /// Describe a request before it runs
+    /// The id is derived from the request id and its inputs, the same inputs give the same id
+    /// The agent and shai configs are read on the blocking thread pool
+    pub async fn capture(
+        api: &str,
+        request_id: &str,
+        session_id: &str,
+        agent: &str,
+        trace: &[ChatMessage],
+        overrides: SamplingParams,
+    ) -> Self {
+        let name = agent.to_string();
+        let (config_version, provider, model, defaults) = tokio::task::spawn_blocking(move || resolve_config(&name))
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Failed to read the config of agent {} for its replay descriptor: {}", agent, e);
+                (None, None, None, SamplingParams::default())
+            });
+        let sampling = SamplingParams {
+            temperature: overrides.temperature.or(defaults.temperature),
+            top_p: overrides.top_p.or(defaults.top_p),
+            max_tokens: overrides.max_tokens.or(defaults.max_tokens),
+        };
+        let prompt_hash = short_hash(&serde_json::to_vec(trace).unwrap_or_default());
+
+        let mut hasher = blake3::Hasher::new();
+        for part in [request_id, &prompt_hash, config_version.as_deref().unwrap_or(""), model.as_deref().unwrap_or("")] {
+            hasher.update(part.as_bytes());
+            hasher.update(&[0]);
+        }
+        hasher.update(&serde_json::to_vec(&sampling).unwrap_or_default());
+        let id = format!("rpl_{}", &hasher.finalize().to_hex()[..24]);
+
+        Self {
+            id,
+            request_id: request_id.to_string(),
+            session_id: session_id.to_string(),
+            api: api.to_string(),
+            created_at: Utc::now(),
+            agent: agent.to_string(),
+            config_version,
+            provider,
+            model,
+            sampling,
+            prompt_hash,
+            cassette: None,
+        }
+    }
+}
+
+fn short_hash(content: &[u8]) -> String {
+    blake3::hash(content).to_hex()[..16].to_string()
+}
+
+/// Config version, provider, model and default sampling of the agent serving a request
+fn resolve_config(agent: &str) -> (Option<String>, Option<String>, Option<String>, SamplingParams) {
+    let name = match agent {
+        "default" => None,
+        pattern if AgentConfig::is_pattern(pattern) => AgentConfig::resolve_pattern(pattern).ok().flatten(),
+        name => Some(name.to_string()),
+    };
+
+    if let Some(config) = name.and_then(|name| AgentConfig::load(&name).ok()) {
+        let version = serde_json::to_vec(&config).ok().map(|json| short_hash(&json));
+        let sampling = SamplingParams {
+            temperature: Some(config.temperature),
+            top_p: None,
+            max_tokens: Some(config.max_tokens),
+        };
+        return (version, Some(config.llm_provider.provider), Some(config.llm_provider.model), sampling);
+    }
+
+    let shai_config = ShaiConfig::load().unwrap_or_default();
+    match shai_config.get_selected_provider() {
+        // provider env vars hold credentials, they stay out of the version hash
+        Some(provider) => {
+            let version = short_hash(format!("{}\0{}\0{:?}", provider.provider, provider.model, provider.tool_method).as_bytes());
+            (Some(version), Some(provider.provider.clone()), Some(provider.model.clone()), SamplingParams::default())
+        }
+        None => (None, None, None, SamplingParams::default()),
+    }
+}
+
+/// File store of replay descriptors, one JSON file per descriptor
+pub struct ReplayStore {
+    root: PathBuf,
+    retention: Option<Duration>,
+    last_prune: Mutex<Option<Instant>>,
+}
+
+impl ReplayStore {
+    /// Get the folder path for replay descriptors
+    pub fn folder() -> PathBuf {
+        std::env::var("SHAI_REPLAY_FOLDER")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(".shai/replays"))
+    }
+
+    /// Folder where the record-and-replay tooling writes cassettes, named after the replay id
+    pub fn cassette_folder() -> PathBuf {
+        std::env::var("SHAI_CASSETTE_FOLDER")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(".shai/cassettes"))
+    }
+
+    /// Open the store at the configured folder
+    pub fn open_default(retention: Option<Duration>) -> Result<Self, ReplayError> {
+        Self::open(Self::folder(), retention)
+    }
+
+    /// Open (or create) a store rooted at `root`, descriptors older than `retention` are pruned
+    pub fn open(root: impl Into<PathBuf>, retention: Option<Duration>) -> Result<Self, ReplayError> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        Ok(Self { root, retention, last_prune: Mutex::new(None) })
+    }
+
+    fn path(&self, id: &str) -> Result<PathBuf, ReplayError> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid replay id: {}", id)).into());
+        }
+        Ok(self.root.join(format!("{}.json", id)))
+    }
+
+    /// Atomic write of a descriptor: write to temp file, then rename
+    pub fn save(&self, descriptor: &ReplayDescriptor) -> Result<(), ReplayError> {
+        let path = self.path(&descriptor.id)?;
+        let temp_path = self.root.join(format!("{}.tmp", Uuid::new_v4()));
+        fs::write(&temp_path, serde_json::to_string_pretty(descriptor)?)?;
+        fs::rename(&temp_path, path)?;
+        debug!("Replay descriptor saved: {}", descriptor.id);
+        Ok(())
+    }
+
+    /// Load a descriptor, with the pointer to its cassette when one was recorded
+    pub fn get(&self, id: &str) -> Result<Option<ReplayDescriptor>, ReplayError> {
+        let path = self.path(id)?;
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut descriptor: ReplayDescriptor = serde_json::from_str(&content)?;
+        let cassette = Self::cassette_folder().join(format!("{}.json", id));
+        if cassette.exists() {
+            descriptor.cassette = Some(cassette.to_string_lossy().to_string());
+        }
+        Ok(Some(descriptor))
+    }
+
+    /// Save a descriptor in the background, failures are only logged
+    pub fn record(self: &std::sync::Arc<Self>, descriptor: ReplayDescriptor) {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = store.save(&descriptor) {
+                error!("Failed to save replay descriptor {}: {}", descriptor.id, e);
+            }
+        });
+    }
+
+    /// Remove descriptors older than the retention, returns the number removed
+    pub fn prune(&self, now: SystemTime) -> Result<usize, ReplayError> {
+        let Some(retention) = self.retention else {
+            return Ok(0);
+        };
+
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let age = entry.metadata()?.modified().ok().and_then(|modified| now.duration_since(modified).ok());
+            if age.is_some_and(|age| age > retention) {
+                match fs::remove_file(entry.path()) {
+                    Ok(_) => removed += 1,
+                    Err(e) => warn!("Failed to remove replay descriptor {}: {}", entry.path().display(), e),
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Prune at most once per PRUNE_INTERVAL (called from the session sweep)
+    pub fn prune_if_due(&self) -> Result<usize, ReplayError> {
+        {
+            let mut last_prune = self.last_prune.lock().unwrap();
+            if last_prune.is_some_and(|at| at.elapsed() < PRUNE_INTERVAL) {
+                return Ok(0);
+            }
+            *last_prune = Some(Instant::now());
+        }
+        self.prune(SystemTime::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai_dive::v1::resources::chat::ChatMessageContent;
+
+    fn user(text: &str) -> ChatMessage {
+        ChatMessage::User { content: ChatMessageContent::Text(text.to_string()), name: None }
+    }
+
+    fn temp_store(retention: Option<Duration>) -> (ReplayStore, PathBuf) {
+        let root = std::env::temp_dir().join(format!("shai-replays-{}", Uuid::new_v4()));
+        (ReplayStore::open(&root, retention).unwrap(), root)
+    }
+
+    #[tokio::test]
+    async fn test_replay_id_is_deterministic() {
+        let trace = vec![user("why is the build red?")];
+        let overrides = SamplingParams { temperature: Some(0.2), ..Default::default() };
+
+        let a = ReplayDescriptor::capture("responses", "req-1", "sess", "default", &trace, overrides.clone()).await;
+        let b = ReplayDescriptor::capture("responses", "req-1", "sess", "default", &trace, overrides.clone()).await;
+        assert_eq!(a.id, b.id);
+        assert_eq!(a.prompt_hash, b.prompt_hash);
+        assert!(a.id.starts_with("rpl_"));
+        assert_eq!(a.sampling.temperature, Some(0.2));
+
+        let other_prompt = ReplayDescriptor::capture("responses", "req-1", "sess", "default", &[user("other")], overrides.clone()).await;
+        assert_ne!(a.id, other_prompt.id);
+        let other_request = ReplayDescriptor::capture("responses", "req-2", "sess", "default", &trace, overrides).await;
+        assert_ne!(a.id, other_request.id);
+    }
+
+    #[tokio::test]
+    async fn test_save_get_and_prune() {
+        let (store, root) = temp_store(Some(Duration::from_secs(60)));
+        let descriptor = ReplayDescriptor::capture("chat.completions", "req-1", "sess", "default", &[user("hi")], SamplingParams::default()).await;
+        store.save(&descriptor).unwrap();
+
+        assert_eq!(store.get(&descriptor.id).unwrap(), Some(descriptor.clone()));
+        assert_eq!(store.get("rpl_unknown").unwrap(), None);
+        assert!(store.get("../index").is_err());
+
+        assert_eq!(store.prune(SystemTime::now()).unwrap(), 0);
+        assert_eq!(store.prune(SystemTime::now() + Duration::from_secs(120)).unwrap(), 1);
+        assert_eq!(store.get(&descriptor.id).unwrap(), None);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
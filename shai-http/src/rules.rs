@@ -0,0 +1,563 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use axum::http::{header::AUTHORIZATION, HeaderMap, HeaderName};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::ErrorResponse;
+pub(crate) use shai_llm::wildcard_match;
+
+/// Error code of the requests refused by a `reject` rule
+pub const RULE_REJECTED_CODE: &str = "rejected_by_rule";
+
+/// Parameters rules may not set or clamp, they have a dedicated action or drive the request handling
+const RESERVED_PARAMETERS: &[&str] = &["model", "messages", "input", "instructions", "stream"];
+
+/// OpenAI route a rule applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RuleRoute {
+    #[serde(rename = "chat.completions")]
+    ChatCompletions,
+    #[serde(rename = "responses")]
+    Responses,
+}
+
+impl fmt::Display for RuleRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ChatCompletions => write!(f, "chat.completions"),
+            Self::Responses => write!(f, "responses"),
+        }
+    }
+}
+
+/// Header condition: the header is present, or equal to `value` when set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeaderCondition {
+    pub name: String,
+    #[serde(default)]
+    pub value: Option<String>,
+}
+
+/// Conditions of a rule, every condition set must hold (an empty match fires on all requests)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleMatch {
+    #[serde(default)]
+    pub route: Option<RuleRoute>,
+    /// Bearer token of the request
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// Model pattern, `*` matches any run of characters (e.g. "gpt-3.5-*")
+    /// Matched against the model as rewritten by the rules fired before
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub header: Option<HeaderCondition>,
+}
+
+/// What a rule does to a matching request, actions run in order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum RuleAction {
+    /// Replace the requested model
+    RewriteModel { model: String },
+    /// Set a top-level request parameter (e.g. "temperature")
+    SetParameter { name: String, value: Value },
+    /// Bound a numeric parameter, only when the request sets it
+    ClampParameter {
+        name: String,
+        #[serde(default)]
+        min: Option<f64>,
+        #[serde(default)]
+        max: Option<f64>,
+    },
+    /// Add a system message ahead of the conversation (instructions on the Responses API)
+    AddSystemMessage { content: String },
+    /// Refuse the request with this message
+    Reject { message: String },
+}
+
+/// A named rule: conditions and the actions applied when they hold
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformRule {
+    pub name: String,
+    #[serde(rename = "match", default)]
+    pub conditions: RuleMatch,
+    pub actions: Vec<RuleAction>,
+}
+
+/// Ordered transformation rules applied to incoming OpenAI requests
+///
+/// Rules are evaluated in order before the request parameters are resolved, each matching rule
+/// fires (a `reject` stops the evaluation). They are the `rules` section of the server config file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformRules {
+    #[serde(default)]
+    pub rules: Vec<TransformRule>,
+}
+
+#[derive(Debug, Error)]
+pub enum RulesError {
+    #[error("failed to read config file {path}: {source}")]
+    Io { path: PathBuf, #[source] source: std::io::Error },
+    #[error("invalid rules section in config file {path}: {source}")]
+    Parse { path: PathBuf, #[source] source: serde_json::Error },
+    #[error("invalid rule '{rule}': {message}")]
+    Invalid { rule: String, message: String },
+    #[error("no config file configured")]
+    NoFile,
+}
+
+/// Server config file (`shai serve --config`): a JSON object with a section per concern, the
+/// sections other than `rules` are not read here
+#[derive(Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    rules: Vec<TransformRule>,
+}
+
+impl From<RulesError> for ErrorResponse {
+    fn from(error: RulesError) -> Self {
+        ErrorResponse::invalid_request(error.to_string())
+    }
+}
+
+/// Bearer token of a request
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+impl RuleMatch {
+    fn matches(&self, route: RuleRoute, headers: &HeaderMap, model: &str) -> bool {
+        if self.route.is_some_and(|r| r != route) {
+            return false;
+        }
+        if let Some(api_key) = &self.api_key {
+            if bearer_token(headers) != Some(api_key.as_str()) {
+                return false;
+            }
+        }
+        if let Some(pattern) = &self.model {
+            if !wildcard_match(pattern, model) {
+                return false;
+            }
+        }
+        if let Some(header) = &self.header {
+            let value = headers.get(header.name.to_ascii_lowercase().as_str()).and_then(|v| v.to_str().ok());
+            match (&header.value, value) {
+                (_, None) => return false,
+                (Some(expected), Some(value)) if expected != value => return false,
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+impl RuleAction {
+    /// Apply a non-rejecting action to the request body, returns the change made if any
+    fn apply(&self, route: RuleRoute, body: &mut Value) -> Option<String> {
+        match self {
+            Self::RewriteModel { model } => {
+                let previous = body.get("model").and_then(Value::as_str).unwrap_or_default().to_string();
+                body["model"] = json!(model);
+                Some(format!("model {} -> {}", previous, model))
+            }
+            Self::SetParameter { name, value } => {
+                body[name.as_str()] = value.clone();
+                Some(format!("{} = {}", name, value))
+            }
+            Self::ClampParameter { name, min, max } => {
+                let current = body.get(name.as_str())?;
+                let clamped = clamp_number(current, *min, *max)?;
+                let change = format!("{} {} -> {}", name, current, clamped);
+                body[name.as_str()] = clamped;
+                Some(change)
+            }
+            Self::AddSystemMessage { content } => {
+                add_system_message(route, body, content);
+                Some("system message added".to_string())
+            }
+            Self::Reject { .. } => None,
+        }
+    }
+}
+
+/// Bounded value of a number, None when it is not a number or already within bounds
+/// Integers stay integers (token limits must deserialize as such)
+fn clamp_number(value: &Value, min: Option<f64>, max: Option<f64>) -> Option<Value> {
+    if let Some(current) = value.as_i64() {
+        let mut clamped = current;
+        if let Some(max) = max {
+            clamped = clamped.min(max.floor() as i64);
+        }
+        if let Some(min) = min {
+            clamped = clamped.max(min.ceil() as i64);
+        }
+        return (clamped != current).then(|| json!(clamped));
+    }
+
+    let current = value.as_f64()?;
+    let mut clamped = current;
+    if let Some(max) = max {
+        clamped = clamped.min(max);
+    }
+    if let Some(min) = min {
+        clamped = clamped.max(min);
+    }
+    (clamped != current).then(|| json!(clamped))
+}
+
+/// Insert a system message after the leading system messages of a chat completion,
+/// appended to the instructions of a response
+fn add_system_message(route: RuleRoute, body: &mut Value, content: &str) {
+    match route {
+        RuleRoute::ChatCompletions => {
+            let Some(messages) = body.get_mut("messages").and_then(Value::as_array_mut) else {
+                body["messages"] = json!([{ "role": "system", "content": content }]);
+                return;
+            };
+            let index = messages
+                .iter()
+                .take_while(|message| matches!(message["role"].as_str(), Some("system") | Some("developer")))
+                .count();
+            messages.insert(index, json!({ "role": "system", "content": content }));
+        }
+        RuleRoute::Responses => {
+            let instructions = match body.get("instructions").and_then(Value::as_str) {
+                Some(existing) if !existing.is_empty() => format!("{}\n\n{}", existing, content),
+                _ => content.to_string(),
+            };
+            body["instructions"] = json!(instructions);
+        }
+    }
+}
+
+impl TransformRules {
+    /// Load and validate the `rules` section of a server config file (no section = no rules)
+    pub fn from_config_file(path: &Path) -> Result<Self, RulesError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|source| RulesError::Io { path: path.to_path_buf(), source })?;
+        let file: ConfigFile = serde_json::from_str(&content)
+            .map_err(|source| RulesError::Parse { path: path.to_path_buf(), source })?;
+        let rules = Self { rules: file.rules };
+        rules.validate()?;
+        Ok(rules)
+    }
+
+    /// Check the rules can all fire as written
+    pub fn validate(&self) -> Result<(), RulesError> {
+        let mut names = HashSet::new();
+        for rule in &self.rules {
+            let invalid = |message: &str| RulesError::Invalid { rule: rule.name.clone(), message: message.to_string() };
+
+            if rule.name.is_empty() {
+                return Err(invalid("rules must have a name"));
+            }
+            if !names.insert(rule.name.as_str()) {
+                return Err(invalid("duplicate rule name"));
+            }
+            if rule.conditions.model.as_deref().is_some_and(str::is_empty) {
+                return Err(invalid("empty model pattern"));
+            }
+            if let Some(header) = &rule.conditions.header {
+                if HeaderName::from_bytes(header.name.as_bytes()).is_err() {
+                    return Err(invalid(&format!("invalid header name '{}'", header.name)));
+                }
+            }
+            if rule.actions.is_empty() {
+                return Err(invalid("no action"));
+            }
+
+            for (index, action) in rule.actions.iter().enumerate() {
+                match action {
+                    RuleAction::RewriteModel { model } if model.is_empty() => {
+                        return Err(invalid("rewrite_model needs a model"));
+                    }
+                    RuleAction::SetParameter { name, .. } | RuleAction::ClampParameter { name, .. }
+                        if name.is_empty() || RESERVED_PARAMETERS.contains(&name.as_str()) =>
+                    {
+                        return Err(invalid(&format!("parameter '{}' cannot be set by a rule", name)));
+                    }
+                    RuleAction::ClampParameter { min: None, max: None, .. } => {
+                        return Err(invalid("clamp_parameter needs a min or a max"));
+                    }
+                    RuleAction::ClampParameter { min: Some(min), max: Some(max), .. } if min > max => {
+                        return Err(invalid("clamp_parameter min is greater than its max"));
+                    }
+                    RuleAction::Reject { .. } if index + 1 < rule.actions.len() => {
+                        return Err(invalid("reject must be the last action"));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the rules to a JSON request body, returns the names of the rules fired
+    /// Fired rules and rejections are recorded in the audit log
+    pub fn apply_json(&self, route: RuleRoute, request_id: &str, headers: &HeaderMap, body: &mut Value) -> Result<Vec<String>, ErrorResponse> {
+        if !body.is_object() {
+            return Err(ErrorResponse::invalid_request("Request body must be a JSON object".to_string()));
+        }
+
+        let mut fired = Vec::new();
+        for rule in &self.rules {
+            let model = body.get("model").and_then(Value::as_str).unwrap_or_default();
+            if !rule.conditions.matches(route, headers, model) {
+                continue;
+            }
+
+            let mut changes = Vec::new();
+            for action in &rule.actions {
+                if let RuleAction::Reject { message } = action {
+                    warn!("[{}] audit: rule '{}' rejected {} request: {}", request_id, rule.name, route, message);
+                    return Err(ErrorResponse::new(message.clone(), "invalid_request".to_string(), Some(RULE_REJECTED_CODE.to_string())));
+                }
+                changes.extend(action.apply(route, body));
+            }
+            info!("[{}] audit: rule '{}' fired on {} request: {}", request_id, rule.name, route,
+                if changes.is_empty() { "no change".to_string() } else { changes.join(", ") });
+            fired.push(rule.name.clone());
+        }
+        Ok(fired)
+    }
+
+    /// Apply the rules to typed request parameters
+    pub fn apply<T: Serialize + DeserializeOwned>(&self, route: RuleRoute, request_id: &str, headers: &HeaderMap, params: &mut T) -> Result<(), ErrorResponse> {
+        if self.rules.is_empty() {
+            return Ok(());
+        }
+
+        let mut body = serde_json::to_value(&*params)
+            .map_err(|e| ErrorResponse::internal_error(format!("Failed to apply request rules: {}", e)))?;
+        let fired = self.apply_json(route, request_id, headers, &mut body)?;
+        if !fired.is_empty() {
+            *params = serde_json::from_value(body).map_err(|e| {
+                ErrorResponse::invalid_request(format!("Request rewritten by rules {} is invalid: {}", fired.join(", "), e))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Rules in effect on a server, reloadable from the server config file
+pub struct RuleSet {
+    path: Option<PathBuf>,
+    rules: RwLock<Arc<TransformRules>>,
+}
+
+impl RuleSet {
+    pub fn new(path: Option<PathBuf>, rules: TransformRules) -> Self {
+        Self { path, rules: RwLock::new(Arc::new(rules)) }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Rules in effect (requests keep the version they started with)
+    pub fn current(&self) -> Arc<TransformRules> {
+        self.rules.read().unwrap().clone()
+    }
+
+    /// Re-read the rules section of the config file, the rules in effect are kept when it is invalid
+    /// Returns the number of rules now in effect
+    pub fn reload(&self) -> Result<usize, RulesError> {
+        let path = self.path.as_deref().ok_or(RulesError::NoFile)?;
+        let rules = TransformRules::from_config_file(path)?;
+        let count = rules.rules.len();
+        *self.rules.write().unwrap() = Arc::new(rules);
+        info!("Reloaded {} request rules from {}", count, path.display());
+        Ok(count)
+    }
+
+    /// Apply the rules in effect to typed request parameters
+    pub fn apply<T: Serialize + DeserializeOwned>(&self, route: RuleRoute, request_id: &str, headers: &HeaderMap, params: &mut T) -> Result<(), ErrorResponse> {
+        self.current().apply(route, request_id, headers, params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use openai_dive::v1::resources::chat::ChatCompletionParameters;
+
+    fn rules(value: Value) -> TransformRules {
+        let rules: TransformRules = serde_json::from_value(value).unwrap();
+        rules.validate().unwrap();
+        rules
+    }
+
+    fn headers(api_key: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(key) = api_key {
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", key)).unwrap());
+        }
+        headers
+    }
+
+    fn chat(model: &str) -> Value {
+        json!({ "model": model, "messages": [
+            { "role": "system", "content": "be brief" },
+            { "role": "user", "content": "hi" }
+        ]})
+    }
+
+    #[test]
+    fn test_rules_fire_in_order() {
+        let rules = rules(json!({ "rules": [
+            { "name": "deprecated", "match": { "model": "gpt-3.5-*" },
+              "actions": [{ "action": "rewrite_model", "model": "gpt-4o-mini" }] },
+            // sees the rewritten model
+            { "name": "mini-cap", "match": { "model": "gpt-4o-mini" },
+              "actions": [{ "action": "set_parameter", "name": "max_tokens", "value": 4000 },
+                          { "action": "clamp_parameter", "name": "max_tokens", "max": 1000 }] },
+            { "name": "responses-only", "match": { "route": "responses" },
+              "actions": [{ "action": "reject", "message": "no" }] }
+        ]}));
+
+        let mut body = chat("gpt-3.5-turbo");
+        let fired = rules.apply_json(RuleRoute::ChatCompletions, "req", &headers(None), &mut body).unwrap();
+        assert_eq!(fired, vec!["deprecated", "mini-cap"]);
+        assert_eq!(body["model"], "gpt-4o-mini");
+        assert_eq!(body["max_tokens"], 1000);
+
+        // a later rule does not undo an earlier reject
+        let mut body = chat("gpt-4o");
+        let error = rules.apply_json(RuleRoute::Responses, "req", &headers(None), &mut body).unwrap_err();
+        assert_eq!(error.error.code.as_deref(), Some(RULE_REJECTED_CODE));
+    }
+
+    #[test]
+    fn test_api_key_and_header_conditions() {
+        let rules = rules(json!({ "rules": [
+            { "name": "acme", "match": { "api_key": "sk-acme", "header": { "name": "X-Client", "value": "legacy" } },
+              "actions": [{ "action": "add_system_message", "content": "Answer in French." }] },
+            { "name": "blocked", "match": { "api_key": "sk-blocked" },
+              "actions": [{ "action": "reject", "message": "Key disabled" }] }
+        ]}));
+
+        let mut legacy = headers(Some("sk-acme"));
+        legacy.insert("x-client", HeaderValue::from_static("legacy"));
+        let mut body = chat("gpt-4o");
+        rules.apply_json(RuleRoute::ChatCompletions, "req", &legacy, &mut body).unwrap();
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1]["content"], "Answer in French.");
+        assert_eq!(messages[2]["role"], "user");
+
+        // same key without the header: untouched
+        let mut body = chat("gpt-4o");
+        let fired = rules.apply_json(RuleRoute::ChatCompletions, "req", &headers(Some("sk-acme")), &mut body).unwrap();
+        assert!(fired.is_empty());
+        assert_eq!(body, chat("gpt-4o"));
+
+        let error = rules.apply_json(RuleRoute::ChatCompletions, "req", &headers(Some("sk-blocked")), &mut chat("gpt-4o")).unwrap_err();
+        assert_eq!(error.error.message, "Key disabled");
+
+        // responses get the message in their instructions
+        let mut body = json!({ "model": "gpt-4o", "input": "hi", "instructions": "be brief" });
+        rules.apply_json(RuleRoute::Responses, "req", &legacy, &mut body).unwrap();
+        assert_eq!(body["instructions"], "be brief\n\nAnswer in French.");
+    }
+
+    #[test]
+    fn test_clamp_only_bounds_present_numbers() {
+        assert_eq!(clamp_number(&json!(0.2), Some(0.5), None), Some(json!(0.5)));
+        assert_eq!(clamp_number(&json!(4096), None, Some(1000.0)), Some(json!(1000)));
+        assert_eq!(clamp_number(&json!(500), None, Some(1000.0)), None);
+        assert_eq!(clamp_number(&json!("high"), None, Some(1.0)), None);
+
+        let rules = rules(json!({ "rules": [
+            { "name": "cap", "actions": [{ "action": "clamp_parameter", "name": "max_tokens", "max": 10 }] }
+        ]}));
+        let mut body = chat("gpt-4o");
+        rules.apply_json(RuleRoute::ChatCompletions, "req", &headers(None), &mut body).unwrap();
+        assert!(body.get("max_tokens").is_none());
+    }
+
+    #[test]
+    fn test_typed_parameters_round_trip() {
+        let rules = rules(json!({ "rules": [
+            { "name": "deprecated", "match": { "model": "gpt-3.5-turbo" },
+              "actions": [{ "action": "rewrite_model", "model": "gpt-4o-mini" },
+                          { "action": "set_parameter", "name": "temperature", "value": 0.2 }] },
+            { "name": "broken", "match": { "model": "broken" },
+              "actions": [{ "action": "set_parameter", "name": "temperature", "value": "hot" }] }
+        ]}));
+
+        let mut params: ChatCompletionParameters = serde_json::from_value(chat("gpt-3.5-turbo")).unwrap();
+        rules.apply(RuleRoute::ChatCompletions, "req", &headers(None), &mut params).unwrap();
+        assert_eq!(params.model, "gpt-4o-mini");
+        assert_eq!(params.temperature, Some(0.2));
+
+        // a rewrite producing invalid parameters is reported as an invalid request
+        let mut params: ChatCompletionParameters = serde_json::from_value(chat("broken")).unwrap();
+        let error = rules.apply(RuleRoute::ChatCompletions, "req", &headers(None), &mut params).unwrap_err();
+        assert_eq!(error.error.r#type, "invalid_request");
+    }
+
+    #[test]
+    fn test_validation() {
+        let invalid = |value: Value| {
+            let rules: TransformRules = serde_json::from_value(value).unwrap();
+            rules.validate().is_err()
+        };
+        let action = json!([{ "action": "rewrite_model", "model": "m" }]);
+
+        assert!(invalid(json!({ "rules": [{ "name": "", "actions": action }] })));
+        assert!(invalid(json!({ "rules": [{ "name": "a", "actions": action }, { "name": "a", "actions": action }] })));
+        assert!(invalid(json!({ "rules": [{ "name": "a", "actions": [] }] })));
+        assert!(invalid(json!({ "rules": [{ "name": "a", "match": { "header": { "name": "bad header" } }, "actions": action }] })));
+        assert!(invalid(json!({ "rules": [{ "name": "a", "actions": [{ "action": "set_parameter", "name": "model", "value": "m" }] }] })));
+        assert!(invalid(json!({ "rules": [{ "name": "a", "actions": [{ "action": "clamp_parameter", "name": "top_p" }] }] })));
+        assert!(invalid(json!({ "rules": [{ "name": "a", "actions": [{ "action": "clamp_parameter", "name": "top_p", "min": 1.0, "max": 0.5 }] }] })));
+        assert!(invalid(json!({ "rules": [{ "name": "a", "actions": [{ "action": "reject", "message": "no" }, { "action": "rewrite_model", "model": "m" }] }] })));
+
+        // unknown fields are typos, not ignored
+        assert!(serde_json::from_value::<TransformRules>(json!({ "rules": [{ "name": "a", "when": {}, "actions": action }] })).is_err());
+    }
+
+    #[test]
+    fn test_reload_keeps_rules_on_invalid_file() {
+        let path = std::env::temp_dir().join(format!("shai-server-{}.json", uuid::Uuid::new_v4()));
+        let write = |value: Value| std::fs::write(&path, value.to_string()).unwrap();
+
+        // the rules are one section of the server config file
+        write(json!({
+            "rules": [{ "name": "a", "actions": [{ "action": "rewrite_model", "model": "m" }] }],
+            "other": { "kept": true }
+        }));
+        let set = RuleSet::new(Some(path.clone()), TransformRules::from_config_file(&path).unwrap());
+        assert_eq!(set.current().rules.len(), 1);
+
+        write(json!({ "rules": [{ "name": "a", "actions": [] }] }));
+        assert!(matches!(set.reload(), Err(RulesError::Invalid { .. })));
+        assert_eq!(set.current().rules[0].name, "a");
+
+        write(json!({ "rules": [{ "name": "a", "when": {}, "actions": [] }] }));
+        assert!(matches!(set.reload(), Err(RulesError::Parse { .. })));
+
+        write(json!({}));
+        assert_eq!(set.reload().unwrap(), 0);
+        assert!(matches!(RuleSet::new(None, TransformRules::default()).reload(), Err(RulesError::NoFile)));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
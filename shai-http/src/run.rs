@@ -0,0 +1,1028 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use futures::stream::{Stream, StreamExt};
+use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent};
+use openai_dive::v1::resources::shared::Usage;
+use serde::{Deserialize, Serialize};
+use shai_core::agent::{AgentController, AgentError, AgentEvent, PublicAgentState};
+use shai_core::tools::{ToolCall, ToolResult};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::sync::broadcast::Receiver;
+use tokio::time::Instant;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tracing::{info, warn};
+
+use crate::features::Features;
+use crate::session::{colored_session_id, RequestLifecycle, RequestSession, RunSummaryLog};
+use crate::workspace::{WorkspaceChanges, WorkspaceTracker};
+#[cfg(feature = "git")]
+use crate::git::Checkpoint;
+use crate::ErrorResponse;
+
+/// Why an agent run stopped
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum RunStopReason {
+    /// The agent finished its turn and is waiting for input
+    Paused,
+    /// The agent terminated
+    Completed { success: bool },
+    /// No terminal event was received before the deadline
+    Timeout,
+    /// The event channel closed before a terminal event (agent died)
+    #[default]
+    Closed,
+}
+
+impl RunStopReason {
+    /// API error for runs that ended without reaching a terminal state
+    pub fn to_error(&self) -> Option<ErrorResponse> {
+        match self {
+            RunStopReason::Timeout => Some(ErrorResponse::timeout("Agent run timed out".to_string())),
+            RunStopReason::Closed => Some(ErrorResponse::internal_error("Agent stopped before completing the response".to_string())),
+            RunStopReason::Paused | RunStopReason::Completed { .. } => None,
+        }
+    }
+}
+
+/// Options controlling how a run is driven
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// Treat the agent pausing (end of turn) as the end of the run
+    pub stop_on_pause: bool,
+    /// Maximum duration of the run (None = unlimited)
+    pub timeout: Option<Duration>,
+    /// Stop the agent's current task if the consumer goes away before the run ends
+    pub cancel_on_disconnect: bool,
+    /// Tool calls lasting longer are listed in the run summary (None = not reported)
+    pub slow_tool_threshold: Option<Duration>,
+    /// A ping is sent on SSE streams when no event came for this long (None = never)
+    pub heartbeat: Option<Duration>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            stop_on_pause: true,
+            timeout: None,
+            cancel_on_disconnect: false,
+            slow_tool_threshold: None,
+            heartbeat: None,
+        }
+    }
+}
+
+impl RunOptions {
+    pub fn with_stop_on_pause(mut self, stop_on_pause: bool) -> Self {
+        self.stop_on_pause = stop_on_pause;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_cancel_on_disconnect(mut self, cancel_on_disconnect: bool) -> Self {
+        self.cancel_on_disconnect = cancel_on_disconnect;
+        self
+    }
+
+    pub fn with_slow_tool_threshold(mut self, slow_tool_threshold: Option<Duration>) -> Self {
+        self.slow_tool_threshold = slow_tool_threshold;
+        self
+    }
+
+    pub fn with_heartbeat(mut self, heartbeat: Option<Duration>) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+}
+
+/// A tool call executed during a run
+#[derive(Debug, Clone)]
+pub struct ToolCallRecord {
+    pub call: ToolCall,
+    pub result: ToolResult,
+    pub duration: TimeDelta,
+}
+
+/// Token usage accumulated over a run
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RunUsage {
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+}
+
+impl RunUsage {
+    pub fn total_tokens(&self) -> u32 {
+        self.input_tokens + self.output_tokens
+    }
+
+    /// Add the usage of one brain iteration
+    pub fn add(&mut self, input_tokens: u32, output_tokens: u32) {
+        self.input_tokens += input_tokens;
+        self.output_tokens += output_tokens;
+    }
+}
+
+impl From<RunUsage> for Usage {
+    fn from(usage: RunUsage) -> Self {
+        Usage {
+            input_tokens: None,
+            input_tokens_details: None,
+            output_tokens: None,
+            output_tokens_details: None,
+            prompt_tokens: Some(usage.input_tokens),
+            completion_tokens: Some(usage.output_tokens),
+            total_tokens: usage.total_tokens(),
+            completion_tokens_details: None,
+            prompt_tokens_details: None,
+        }
+    }
+}
+
+/// Why a run ended, as reported in its summary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunTerminalReason {
+    /// The agent finished its turn (or terminated) without errors
+    Completed,
+    /// The run ended on errors
+    Failed,
+    /// The consumer went away or the agent was cancelled before the end
+    Cancelled,
+    Timeout,
+    /// A budget (retries, iterations) ran out
+    BudgetExhausted,
+    /// The agent died before a terminal event
+    Closed,
+}
+
+/// Calls of one tool during a run
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ToolCallStats {
+    pub calls: u32,
+    pub failures: u32,
+    pub total_duration_ms: u64,
+    pub max_duration_ms: u64,
+}
+
+/// Number of tool calls listed in the slowest tools of a run summary
+pub const SLOWEST_TOOLS: usize = 5;
+
+/// A tool call of a run that went over the slow threshold
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SlowToolCall {
+    pub tool: String,
+    pub tool_call_id: String,
+    pub duration_ms: u64,
+}
+
+/// End-of-run report, produced once on every terminal path
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub session_id: String,
+    pub started_at: DateTime<Utc>,
+    pub elapsed_ms: u64,
+    pub brain_iterations: u32,
+    /// Tool calls by tool name
+    pub tool_calls: BTreeMap<String, ToolCallStats>,
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    /// Budgets the run hit (empty_completion_retries, max_iterations, timeout, token_budget)
+    pub budgets: Vec<String>,
+    pub warnings: Vec<String>,
+    pub terminal_reason: RunTerminalReason,
+    /// Feature flags the request ran with
+    #[serde(default)]
+    pub features: Features,
+    /// Slowest tool calls over the slow threshold, longest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub slow_tools: Vec<SlowToolCall>,
+}
+
+/// Accumulates the summary of a run while its events go by
+#[derive(Debug)]
+struct RunSummaryRecorder {
+    started_at: DateTime<Utc>,
+    started: Instant,
+    brain_iterations: u32,
+    tool_calls: BTreeMap<String, ToolCallStats>,
+    usage: RunUsage,
+    budgets: Vec<String>,
+    warnings: Vec<String>,
+    errors: usize,
+    cancelled: bool,
+    features: Features,
+    slow_tool_threshold: Option<Duration>,
+    slow_tools: Vec<SlowToolCall>,
+}
+
+impl RunSummaryRecorder {
+    fn new() -> Self {
+        Self {
+            started_at: Utc::now(),
+            started: Instant::now(),
+            brain_iterations: 0,
+            tool_calls: BTreeMap::new(),
+            usage: RunUsage::default(),
+            budgets: Vec::new(),
+            warnings: Vec::new(),
+            errors: 0,
+            cancelled: false,
+            features: Features::default(),
+            slow_tool_threshold: None,
+            slow_tools: Vec::new(),
+        }
+    }
+
+    fn touch_budget(&mut self, budget: &str) {
+        if !self.budgets.iter().any(|b| b == budget) {
+            self.budgets.push(budget.to_string());
+        }
+    }
+
+    fn record(&mut self, event: &AgentEvent) {
+        match event {
+            AgentEvent::BrainResult { thought: Err(AgentError::BudgetExceeded { .. }), .. } => {
+                // refused before any LLM call, its error event is counted
+                self.touch_budget("token_budget");
+            }
+            AgentEvent::BrainResult { thought, .. } => {
+                self.brain_iterations += 1;
+                match thought {
+                    Err(AgentError::EmptyCompletion(_)) => self.touch_budget("empty_completion_retries"),
+                    Err(AgentError::MaxIterationsReached) => self.touch_budget("max_iterations"),
+                    _ => {}
+                }
+                if let Err(err) = thought {
+                    self.errors += 1;
+                    self.warnings.push(err.to_string());
+                }
+            }
+            AgentEvent::ToolCallCompleted { call, result, duration } => {
+                let stats = self.tool_calls.entry(call.tool_name.clone()).or_default();
+                let duration_ms = duration.num_milliseconds().max(0) as u64;
+                stats.calls += 1;
+                stats.total_duration_ms += duration_ms;
+                stats.max_duration_ms = stats.max_duration_ms.max(duration_ms);
+                if !matches!(result, ToolResult::Success { .. }) {
+                    stats.failures += 1;
+                }
+                if self.slow_tool_threshold.is_some_and(|threshold| duration_ms > threshold.as_millis() as u64) {
+                    self.slow_tools.push(SlowToolCall {
+                        tool: call.tool_name.clone(),
+                        tool_call_id: call.tool_call_id.clone(),
+                        duration_ms,
+                    });
+                }
+            }
+            AgentEvent::TokenUsage { input_tokens, output_tokens } => {
+                self.usage.add(*input_tokens, *output_tokens);
+            }
+            AgentEvent::Error { error } => {
+                self.errors += 1;
+                self.warnings.push(error.clone());
+            }
+            AgentEvent::StatusChanged { new_status: PublicAgentState::Cancelled, .. } => self.cancelled = true,
+            _ => {}
+        }
+    }
+
+    /// Summary of a run that stopped for `reason` (None = abandoned before its end)
+    fn finish(&mut self, session_id: &str, reason: Option<&RunStopReason>) -> RunSummary {
+        let terminal_reason = match reason {
+            None => RunTerminalReason::Cancelled,
+            Some(RunStopReason::Timeout) => {
+                self.touch_budget("timeout");
+                RunTerminalReason::Timeout
+            }
+            Some(RunStopReason::Closed) => RunTerminalReason::Closed,
+            Some(_) if self.budgets.iter().any(|b| b != "timeout") => RunTerminalReason::BudgetExhausted,
+            Some(_) if self.cancelled => RunTerminalReason::Cancelled,
+            Some(RunStopReason::Completed { success: false }) => RunTerminalReason::Failed,
+            Some(_) if self.errors > 0 => RunTerminalReason::Failed,
+            Some(_) => RunTerminalReason::Completed,
+        };
+
+        let mut slow_tools = self.slow_tools.clone();
+        slow_tools.sort_by(|a, b| b.duration_ms.cmp(&a.duration_ms));
+        slow_tools.truncate(SLOWEST_TOOLS);
+
+        RunSummary {
+            session_id: session_id.to_string(),
+            started_at: self.started_at,
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+            brain_iterations: self.brain_iterations,
+            tool_calls: self.tool_calls.clone(),
+            input_tokens: self.usage.input_tokens,
+            output_tokens: self.usage.output_tokens,
+            budgets: self.budgets.clone(),
+            warnings: self.warnings.clone(),
+            terminal_reason,
+            features: self.features,
+            slow_tools,
+        }
+    }
+}
+
+/// Result of a run driven to completion
+#[derive(Debug, Clone, Default)]
+pub struct RunOutcome {
+    /// Last assistant text (or the Completed message when not empty)
+    pub final_message: String,
+    pub tool_calls: Vec<ToolCallRecord>,
+    pub usage: RunUsage,
+    /// Brain and agent errors seen during the run
+    pub errors: Vec<String>,
+    /// Set when the model kept returning empty replies (number of attempts)
+    pub empty_completion: Option<u32>,
+    /// Files changed by the run (when workspace tracking is enabled)
+    pub workspace_changes: Option<WorkspaceChanges>,
+    pub reason: RunStopReason,
+    pub summary: Option<RunSummary>,
+}
+
+impl RunOutcome {
+    /// Fold an event into the outcome
+    pub fn record(&mut self, event: &AgentEvent) {
+        match event {
+            AgentEvent::BrainResult { thought: Ok(msg), .. } => {
+                if let ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. } = msg {
+                    self.final_message = text.clone();
+                }
+            }
+            AgentEvent::BrainResult { thought: Err(err), .. } => {
+                if let AgentError::EmptyCompletion(attempts) = err {
+                    self.empty_completion = Some(*attempts);
+                }
+                self.errors.push(err.to_string());
+            }
+            AgentEvent::ToolCallCompleted { duration, call, result } => {
+                self.tool_calls.push(ToolCallRecord {
+                    call: call.clone(),
+                    result: result.clone(),
+                    duration: *duration,
+                });
+            }
+            AgentEvent::TokenUsage { input_tokens, output_tokens } => {
+                self.usage.add(*input_tokens, *output_tokens);
+            }
+            AgentEvent::Error { error } => {
+                self.errors.push(error.clone());
+            }
+            AgentEvent::Completed { message, .. } => {
+                if !message.is_empty() {
+                    self.final_message = message.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// API error for a run whose answer cannot be returned as a success
+    pub fn to_error(&self) -> Option<ErrorResponse> {
+        if let Some(error) = self.reason.to_error() {
+            return Some(error);
+        }
+        self.empty_completion.map(|attempts| {
+            ErrorResponse::empty_completion(format!("The model returned an empty completion after {} attempts", attempts))
+        })
+    }
+
+    /// True if the run reached a terminal state without errors
+    pub fn is_success(&self) -> bool {
+        self.errors.is_empty()
+            && matches!(self.reason, RunStopReason::Paused | RunStopReason::Completed { success: true })
+    }
+}
+
+/// Terminal state carried by an event, if any
+fn terminal_reason(event: &AgentEvent, stop_on_pause: bool) -> Option<RunStopReason> {
+    match event {
+        AgentEvent::Completed { success, .. } => Some(RunStopReason::Completed { success: *success }),
+        AgentEvent::StatusChanged { new_status: PublicAgentState::Paused, .. } if stop_on_pause => {
+            Some(RunStopReason::Paused)
+        }
+        _ => None,
+    }
+}
+
+/// Drives one request on an agent session: reads events until a terminal state,
+/// skips over lagged events, enforces the timeout and keeps the request lifecycle
+/// alive for the whole run. Dropping a timed out run stops the agent's current task,
+/// so does dropping an unfinished run when `cancel_on_disconnect` is set.
+pub struct AgentRun {
+    events: BroadcastStream<AgentEvent>,
+    session_id: String,
+    options: RunOptions,
+    deadline: Option<Instant>,
+    stop_reason: Option<RunStopReason>,
+    controller: Option<AgentController>,
+    workspace: Option<WorkspaceTracker>,
+    recorder: RunSummaryRecorder,
+    summary: Option<RunSummary>,
+    /// Session log the summary is appended to (persisted with the session)
+    summary_log: Option<RunSummaryLog>,
+    /// Last agent error not followed by a successful brain step
+    last_error: Option<ErrorResponse>,
+    /// Workspace commit of the request, made when the run is dropped
+    #[cfg(feature = "git")]
+    checkpoint: Option<Checkpoint>,
+    _lifecycle: Option<RequestLifecycle>,
+}
+
+impl AgentRun {
+    /// Run a request obtained from AgentSession::handle_request
+    pub fn new(request_session: RequestSession, session_id: String, options: RunOptions) -> Self {
+        let mut run = Self::from_parts(
+            request_session.event_rx,
+            Some(request_session.controller),
+            Some(request_session.lifecycle),
+            session_id,
+            options,
+        );
+        run.workspace = request_session.workspace;
+        run.recorder.features = request_session.features;
+        run.summary_log = Some(request_session.run_summaries);
+        #[cfg(feature = "git")]
+        {
+            run.checkpoint = request_session.checkpoint;
+        }
+        run
+    }
+
+    /// Observe a session without owning it (read-only, never cancels the agent)
+    pub fn watch(event_rx: Receiver<AgentEvent>, session_id: String, options: RunOptions) -> Self {
+        Self::from_parts(event_rx, None, None, session_id, options.with_cancel_on_disconnect(false))
+    }
+
+    fn from_parts(
+        event_rx: Receiver<AgentEvent>,
+        controller: Option<AgentController>,
+        lifecycle: Option<RequestLifecycle>,
+        session_id: String,
+        options: RunOptions,
+    ) -> Self {
+        let recorder = RunSummaryRecorder {
+            slow_tool_threshold: options.slow_tool_threshold,
+            ..RunSummaryRecorder::new()
+        };
+        Self {
+            events: BroadcastStream::new(event_rx),
+            session_id,
+            deadline: options.timeout.map(|timeout| Instant::now() + timeout),
+            options,
+            stop_reason: None,
+            controller,
+            workspace: None,
+            recorder,
+            summary: None,
+            summary_log: None,
+            last_error: None,
+            #[cfg(feature = "git")]
+            checkpoint: None,
+            _lifecycle: lifecycle,
+        }
+    }
+
+    /// Why the run stopped (None while it is still running)
+    pub fn stop_reason(&self) -> Option<&RunStopReason> {
+        self.stop_reason.as_ref()
+    }
+
+    /// Next event of the run, None once a terminal state was reached
+    /// The terminal event itself is returned before the run ends
+    pub async fn next_event(&mut self) -> Option<AgentEvent> {
+        if self.stop_reason.is_some() {
+            return None;
+        }
+
+        loop {
+            let next = match self.deadline {
+                Some(deadline) => match tokio::time::timeout_at(deadline, self.events.next()).await {
+                    Ok(next) => next,
+                    Err(_) => {
+                        warn!("{} - run timed out", colored_session_id(&self.session_id));
+                        self.stop_reason = Some(RunStopReason::Timeout);
+                        return None;
+                    }
+                },
+                None => self.events.next().await,
+            };
+
+            match next {
+                Some(Ok(event)) => {
+                    self.recorder.record(&event);
+                    match &event {
+                        AgentEvent::BrainResult { thought: Ok(_), .. } => self.last_error = None,
+                        AgentEvent::BrainResult { thought: Err(err), .. } => self.last_error = Some(ErrorResponse::agent_error(err)),
+                        AgentEvent::Error { error } => self.last_error = Some(ErrorResponse::internal_error(error.clone())),
+                        _ => {}
+                    }
+                    #[cfg(feature = "git")]
+                    if let Some(checkpoint) = &mut self.checkpoint {
+                        checkpoint.record(&event);
+                    }
+                    self.stop_reason = terminal_reason(&event, self.options.stop_on_pause);
+                    return Some(event);
+                }
+                Some(Err(BroadcastStreamRecvError::Lagged(skipped))) => {
+                    warn!("{} - run lagged behind, {} events skipped", colored_session_id(&self.session_id), skipped);
+                    self.recorder.warnings.push(format!("{} events skipped (consumer lagged behind)", skipped));
+                }
+                None => {
+                    self.stop_reason = Some(RunStopReason::Closed);
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Interval of the pings of an SSE stream waiting for the next event (None = no ping)
+    pub fn heartbeat(&self) -> Option<Duration> {
+        self.options.heartbeat
+    }
+
+    /// Error the run failed on, available once it stopped
+    /// The reason of a run that stopped early, else the agent error it ended on
+    pub fn error(&self) -> Option<ErrorResponse> {
+        let reason = self.stop_reason.as_ref()?;
+        reason.to_error().or_else(|| self.last_error.clone())
+    }
+
+    /// Summary of the run, available once it stopped
+    /// Built on first call and appended to the session log
+    pub fn summary(&mut self) -> Option<RunSummary> {
+        if self.summary.is_none() {
+            let reason = self.stop_reason.clone()?;
+            self.finish_summary(Some(&reason));
+        }
+        self.summary.clone()
+    }
+
+    fn finish_summary(&mut self, reason: Option<&RunStopReason>) {
+        let summary = self.recorder.finish(&self.session_id, reason);
+        info!(
+            "{} - run summary: {:?}, {} iterations, {} tool calls, {} tokens in, {} tokens out, {}ms",
+            colored_session_id(&self.session_id),
+            summary.terminal_reason,
+            summary.brain_iterations,
+            summary.tool_calls.values().map(|t| t.calls).sum::<u32>(),
+            summary.input_tokens,
+            summary.output_tokens,
+            summary.elapsed_ms,
+        );
+        for slow in &summary.slow_tools {
+            warn!("{} - slow tool call {} ({}): {}ms", colored_session_id(&self.session_id), slow.tool, slow.tool_call_id, slow.duration_ms);
+        }
+        if let Some(log) = &self.summary_log {
+            log.lock().unwrap().push(summary.clone());
+        }
+        self.summary = Some(summary);
+    }
+
+    /// Workspace changes of the run, available once it stopped (only returned once)
+    pub async fn workspace_changes(&mut self) -> Option<WorkspaceChanges> {
+        if self.stop_reason.is_none() {
+            return None;
+        }
+        let changes = self.workspace.take()?.finish().await;
+        info!(
+            "{} - workspace changes: {} created, {} modified, {} deleted",
+            colored_session_id(&self.session_id), changes.created.len(), changes.modified.len(), changes.deleted.len()
+        );
+        Some(changes)
+    }
+
+    /// Drive the run to its end and aggregate the events
+    pub async fn collect(mut self) -> RunOutcome {
+        let mut outcome = RunOutcome::default();
+        while let Some(event) = self.next_event().await {
+            outcome.record(&event);
+        }
+        outcome.reason = self.stop_reason.clone().unwrap_or_default();
+        outcome.summary = self.summary();
+        outcome.workspace_changes = self.workspace_changes().await;
+        outcome
+    }
+
+    /// Turn the run into a stream of events ending after the terminal event
+    pub fn into_stream(self) -> impl Stream<Item = AgentEvent> {
+        futures::stream::unfold(self, |mut run| async move {
+            run.next_event().await.map(|event| (event, run))
+        })
+    }
+}
+
+impl Drop for AgentRun {
+    fn drop(&mut self) {
+        // requests abandoned before their end get a summary too (before the lifecycle persists the session)
+        if self.summary.is_none() && self.summary_log.is_some() {
+            let reason = self.stop_reason.clone();
+            self.finish_summary(reason.as_ref());
+        }
+
+        // the commit holds the repository, the next request on the workspace waits for it
+        #[cfg(feature = "git")]
+        if let Some(checkpoint) = self.checkpoint.take() {
+            checkpoint.commit_in_background();
+        }
+
+        // a timed out run always stops the agent, an abandoned one only when asked to
+        let stop = match self.stop_reason {
+            Some(RunStopReason::Timeout) => true,
+            None => self.options.cancel_on_disconnect,
+            Some(_) => false,
+        };
+        if !stop {
+            return;
+        }
+
+        if let Some(controller) = self.controller.take() {
+            info!("{} - run ended early, stopping current task", colored_session_id(&self.session_id));
+            tokio::spawn(async move {
+                let _ = controller.stop_current_task().await;
+            });
+        }
+    }
+}
+
+/// Run a request to completion and return its aggregated outcome
+pub async fn run_agent_collect(request_session: RequestSession, session_id: String, options: RunOptions) -> RunOutcome {
+    AgentRun::new(request_session, session_id, options).collect().await
+}
+
+/// Run a request and expose its events as a stream ending after the terminal event
+pub fn run_agent_stream(
+    request_session: RequestSession,
+    session_id: String,
+    options: RunOptions,
+) -> impl Stream<Item = AgentEvent> {
+    AgentRun::new(request_session, session_id, options).into_stream()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shai_core::agent::AgentRequest;
+    use tokio::sync::{broadcast, mpsc};
+
+    fn assistant(text: &str) -> AgentEvent {
+        AgentEvent::BrainResult {
+            timestamp: chrono::Utc::now(),
+            thought: Ok(ChatMessage::Assistant {
+                content: Some(ChatMessageContent::Text(text.to_string())),
+                tool_calls: None,
+                name: None,
+                audio: None,
+                reasoning_content: None,
+                refusal: None,
+            }),
+        }
+    }
+
+    fn paused() -> AgentEvent {
+        AgentEvent::StatusChanged { old_status: PublicAgentState::Running, new_status: PublicAgentState::Paused }
+    }
+
+    fn tool_completed(name: &str) -> AgentEvent {
+        AgentEvent::ToolCallCompleted {
+            duration: TimeDelta::milliseconds(5),
+            call: ToolCall {
+                tool_call_id: format!("call_{}", name),
+                tool_name: name.to_string(),
+                parameters: serde_json::json!({}),
+            },
+            result: ToolResult::success("ok".to_string()),
+        }
+    }
+
+    fn watch(rx: Receiver<AgentEvent>, options: RunOptions) -> AgentRun {
+        AgentRun::watch(rx, "test".to_string(), options)
+    }
+
+    #[tokio::test]
+    async fn test_paused_ends_run() {
+        let (tx, rx) = broadcast::channel(16);
+        tx.send(assistant("hello")).unwrap();
+        tx.send(tool_completed("read")).unwrap();
+        tx.send(AgentEvent::TokenUsage { input_tokens: 10, output_tokens: 3 }).unwrap();
+        tx.send(AgentEvent::TokenUsage { input_tokens: 12, output_tokens: 4 }).unwrap();
+        tx.send(paused()).unwrap();
+        tx.send(assistant("after pause")).unwrap();
+
+        let outcome = watch(rx, RunOptions::default()).collect().await;
+        assert_eq!(outcome.reason, RunStopReason::Paused);
+        assert_eq!(outcome.final_message, "hello");
+        assert_eq!(outcome.tool_calls.len(), 1);
+        assert_eq!(outcome.usage, RunUsage { input_tokens: 22, output_tokens: 7 });
+        assert!(outcome.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_pause_ignored_without_stop_on_pause() {
+        let (tx, rx) = broadcast::channel(16);
+        tx.send(paused()).unwrap();
+        tx.send(assistant("still running")).unwrap();
+        tx.send(AgentEvent::Completed { success: true, message: String::new() }).unwrap();
+
+        let outcome = watch(rx, RunOptions::default().with_stop_on_pause(false)).collect().await;
+        assert_eq!(outcome.reason, RunStopReason::Completed { success: true });
+        assert_eq!(outcome.final_message, "still running");
+    }
+
+    #[tokio::test]
+    async fn test_completed_failure() {
+        let (tx, rx) = broadcast::channel(16);
+        tx.send(AgentEvent::BrainResult {
+            timestamp: chrono::Utc::now(),
+            thought: Err(AgentError::LlmError("provider down".to_string())),
+        }).unwrap();
+        tx.send(AgentEvent::Completed { success: false, message: "gave up".to_string() }).unwrap();
+
+        let outcome = watch(rx, RunOptions::default()).collect().await;
+        assert_eq!(outcome.reason, RunStopReason::Completed { success: false });
+        assert_eq!(outcome.final_message, "gave up");
+        assert_eq!(outcome.errors.len(), 1);
+        assert!(!outcome.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_empty_completion_is_an_error() {
+        let (tx, rx) = broadcast::channel(16);
+        tx.send(AgentEvent::BrainResult {
+            timestamp: chrono::Utc::now(),
+            thought: Err(AgentError::EmptyCompletion(3)),
+        }).unwrap();
+        tx.send(paused()).unwrap();
+
+        let outcome = watch(rx, RunOptions::default()).collect().await;
+        assert_eq!(outcome.empty_completion, Some(3));
+        let error = outcome.to_error().expect("empty completion must not be a success");
+        assert_eq!(error.error.r#type, "empty_completion");
+    }
+
+    #[tokio::test]
+    async fn test_run_error() {
+        let (tx, rx) = broadcast::channel(16);
+        tx.send(AgentEvent::BrainResult {
+            timestamp: chrono::Utc::now(),
+            thought: Err(AgentError::LlmError("provider down".to_string())),
+        }).unwrap();
+        tx.send(paused()).unwrap();
+
+        let mut run = watch(rx, RunOptions::default());
+        assert!(run.next_event().await.is_some());
+        // not stopped yet
+        assert!(run.error().is_none());
+        assert!(run.next_event().await.is_some());
+        let error = run.error().expect("the run ended on a provider error");
+        assert_eq!(error.error.r#type, "upstream_error");
+
+        // a later successful step clears the error
+        let (tx, rx) = broadcast::channel(16);
+        tx.send(AgentEvent::BrainResult { timestamp: chrono::Utc::now(), thought: Err(AgentError::EmptyCompletion(1)) }).unwrap();
+        tx.send(assistant("recovered")).unwrap();
+        tx.send(paused()).unwrap();
+        let mut run = watch(rx, RunOptions::default());
+        while run.next_event().await.is_some() {}
+        assert!(run.error().is_none());
+
+        let (tx, rx) = broadcast::channel(16);
+        let mut run = watch(rx, RunOptions::default().with_timeout(Some(Duration::from_millis(20))));
+        assert!(run.next_event().await.is_none());
+        assert_eq!(run.error().unwrap().error.r#type, "timeout");
+        drop(tx);
+    }
+
+    #[tokio::test]
+    async fn test_channel_closed() {
+        let (tx, rx) = broadcast::channel(16);
+        tx.send(assistant("partial")).unwrap();
+        drop(tx);
+
+        let outcome = watch(rx, RunOptions::default()).collect().await;
+        assert_eq!(outcome.reason, RunStopReason::Closed);
+        assert_eq!(outcome.final_message, "partial");
+        assert!(!outcome.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let (tx, rx) = broadcast::channel(16);
+        tx.send(assistant("slow")).unwrap();
+
+        let options = RunOptions::default().with_timeout(Some(Duration::from_millis(20)));
+        let outcome = watch(rx, options).collect().await;
+        assert_eq!(outcome.reason, RunStopReason::Timeout);
+        drop(tx);
+    }
+
+    #[tokio::test]
+    async fn test_lagged_events_are_skipped() {
+        let (tx, rx) = broadcast::channel(2);
+        for i in 0..5 {
+            tx.send(assistant(&format!("step {}", i))).unwrap();
+        }
+        tx.send(paused()).unwrap();
+
+        let outcome = watch(rx, RunOptions::default()).collect().await;
+        assert_eq!(outcome.reason, RunStopReason::Paused);
+        assert_eq!(outcome.final_message, "step 4");
+    }
+
+    #[tokio::test]
+    async fn test_stream_ends_after_terminal_event() {
+        let (tx, rx) = broadcast::channel(16);
+        tx.send(assistant("hello")).unwrap();
+        tx.send(paused()).unwrap();
+        tx.send(assistant("ignored")).unwrap();
+
+        let events: Vec<AgentEvent> = watch(rx, RunOptions::default()).into_stream().collect().await;
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[1], AgentEvent::StatusChanged { new_status: PublicAgentState::Paused, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_stops_current_task() {
+        let (tx, rx) = broadcast::channel(16);
+        let (txcmd, mut rxcmd) = mpsc::unbounded_channel();
+        let controller = AgentController { txcmd };
+
+        let options = RunOptions::default().with_cancel_on_disconnect(true);
+        let mut run = AgentRun::from_parts(rx, Some(controller), None, "test".to_string(), options);
+        tx.send(assistant("working")).unwrap();
+        assert!(run.next_event().await.is_some());
+        drop(run);
+
+        let command = tokio::time::timeout(Duration::from_secs(1), rxcmd.recv()).await.unwrap().unwrap();
+        assert!(matches!(command.command, AgentRequest::StopCurrentTask));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_stops_current_task() {
+        let (tx, rx) = broadcast::channel(16);
+        let (txcmd, mut rxcmd) = mpsc::unbounded_channel();
+        let controller = AgentController { txcmd };
+
+        let options = RunOptions::default().with_timeout(Some(Duration::from_millis(20)));
+        let run = AgentRun::from_parts(rx, Some(controller), None, "test".to_string(), options);
+        tx.send(assistant("slow")).unwrap();
+        let outcome = run.collect().await;
+        assert_eq!(outcome.reason, RunStopReason::Timeout);
+
+        let command = tokio::time::timeout(Duration::from_secs(1), rxcmd.recv()).await.unwrap().unwrap();
+        assert!(matches!(command.command, AgentRequest::StopCurrentTask));
+        drop(tx);
+    }
+
+    #[tokio::test]
+    async fn test_finished_run_does_not_cancel() {
+        let (tx, rx) = broadcast::channel(16);
+        let (txcmd, mut rxcmd) = mpsc::unbounded_channel();
+        let controller = AgentController { txcmd };
+
+        let options = RunOptions::default().with_cancel_on_disconnect(true);
+        let run = AgentRun::from_parts(rx, Some(controller), None, "test".to_string(), options);
+        tx.send(paused()).unwrap();
+        let outcome = run.collect().await;
+        assert_eq!(outcome.reason, RunStopReason::Paused);
+
+        tokio::task::yield_now().await;
+        assert!(rxcmd.try_recv().is_err());
+    }
+
+    async fn summary_of(events: Vec<AgentEvent>, options: RunOptions) -> RunSummary {
+        let (tx, rx) = broadcast::channel(16);
+        for event in events {
+            tx.send(event).unwrap();
+        }
+        let outcome = watch(rx, options).collect().await;
+        drop(tx);
+        outcome.summary.expect("every terminal path has a summary")
+    }
+
+    #[tokio::test]
+    async fn test_summary_success() {
+        let summary = summary_of(vec![
+            assistant("reading"),
+            tool_completed("read"),
+            tool_completed("read"),
+            tool_completed("ls"),
+            AgentEvent::TokenUsage { input_tokens: 10, output_tokens: 3 },
+            assistant("done"),
+            paused(),
+        ], RunOptions::default()).await;
+
+        assert_eq!(summary.terminal_reason, RunTerminalReason::Completed);
+        assert_eq!(summary.brain_iterations, 2);
+        assert_eq!(summary.tool_calls["read"].calls, 2);
+        assert_eq!(summary.tool_calls["read"].total_duration_ms, 10);
+        assert_eq!(summary.tool_calls["ls"].calls, 1);
+        assert_eq!((summary.input_tokens, summary.output_tokens), (10, 3));
+        assert!(summary.budgets.is_empty());
+        assert!(summary.warnings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_summary_slow_tools() {
+        let slow = |name: &str, ms: i64| match tool_completed(name) {
+            AgentEvent::ToolCallCompleted { call, result, .. } => {
+                AgentEvent::ToolCallCompleted { call, result, duration: TimeDelta::milliseconds(ms) }
+            }
+            _ => unreachable!(),
+        };
+        let events = || vec![slow("bash", 1500), tool_completed("read"), slow("grep", 3000), paused()];
+
+        let summary = summary_of(events(), RunOptions::default().with_slow_tool_threshold(Some(Duration::from_secs(1)))).await;
+        let slowest: Vec<_> = summary.slow_tools.iter().map(|slow| (slow.tool.as_str(), slow.duration_ms)).collect();
+        assert_eq!(slowest, vec![("grep", 3000), ("bash", 1500)]);
+
+        // not reported without a threshold
+        let summary = summary_of(events(), RunOptions::default()).await;
+        assert!(summary.slow_tools.is_empty());
+        assert!(!serde_json::to_string(&summary).unwrap().contains("slow_tools"));
+    }
+
+    #[tokio::test]
+    async fn test_summary_error() {
+        let summary = summary_of(vec![
+            AgentEvent::BrainResult { timestamp: chrono::Utc::now(), thought: Err(AgentError::LlmError("provider down".to_string())) },
+            AgentEvent::Completed { success: false, message: "gave up".to_string() },
+        ], RunOptions::default()).await;
+        assert_eq!(summary.terminal_reason, RunTerminalReason::Failed);
+        assert_eq!(summary.warnings.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_summary_cancel() {
+        let summary = summary_of(vec![
+            assistant("working"),
+            AgentEvent::StatusChanged { old_status: PublicAgentState::Running, new_status: PublicAgentState::Cancelled },
+            AgentEvent::Completed { success: false, message: String::new() },
+        ], RunOptions::default()).await;
+        assert_eq!(summary.terminal_reason, RunTerminalReason::Cancelled);
+
+        // a request abandoned by its consumer still appends its summary to the session log
+        let (tx, rx) = broadcast::channel(16);
+        let log: RunSummaryLog = Default::default();
+        let mut run = watch(rx, RunOptions::default());
+        run.summary_log = Some(log.clone());
+        tx.send(assistant("working")).unwrap();
+        assert!(run.next_event().await.is_some());
+        drop(run);
+
+        let logged = log.lock().unwrap().clone();
+        assert_eq!(logged.len(), 1);
+        assert_eq!(logged[0].terminal_reason, RunTerminalReason::Cancelled);
+        assert_eq!(logged[0].brain_iterations, 1);
+    }
+
+    #[tokio::test]
+    async fn test_summary_timeout() {
+        let (tx, rx) = broadcast::channel(16);
+        tx.send(assistant("slow")).unwrap();
+
+        let options = RunOptions::default().with_timeout(Some(Duration::from_millis(20)));
+        let summary = watch(rx, options).collect().await.summary.unwrap();
+        assert_eq!(summary.terminal_reason, RunTerminalReason::Timeout);
+        assert_eq!(summary.budgets, vec!["timeout"]);
+        drop(tx);
+    }
+
+    #[tokio::test]
+    async fn test_summary_budget_exhausted() {
+        let summary = summary_of(vec![
+            AgentEvent::BrainResult { timestamp: chrono::Utc::now(), thought: Err(AgentError::EmptyCompletion(3)) },
+            paused(),
+        ], RunOptions::default()).await;
+        assert_eq!(summary.terminal_reason, RunTerminalReason::BudgetExhausted);
+        assert_eq!(summary.budgets, vec!["empty_completion_retries"]);
+    }
+
+    #[tokio::test]
+    async fn test_summary_closed() {
+        let (tx, rx) = broadcast::channel(16);
+        tx.send(assistant("partial")).unwrap();
+        drop(tx);
+
+        let summary = watch(rx, RunOptions::default()).collect().await.summary.unwrap();
+        assert_eq!(summary.terminal_reason, RunTerminalReason::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_summary_is_built_once() {
+        let (tx, rx) = broadcast::channel(16);
+        let log: RunSummaryLog = Default::default();
+        let mut run = watch(rx, RunOptions::default());
+        run.summary_log = Some(log.clone());
+        tx.send(paused()).unwrap();
+
+        assert!(run.next_event().await.is_some());
+        let first = run.summary().unwrap();
+        assert_eq!(run.summary().unwrap(), first);
+        drop(run);
+        assert_eq!(log.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+type ArtifactError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Reference to an artifact held by a session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtifactRef {
+    /// blake3 hash of the content (hex), also the blob name
+    pub hash: String,
+    pub size: u64,
+}
+
+/// Index entry: one blob and the sessions referencing it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ArtifactEntry {
+    size: u64,
+    sessions: BTreeSet<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ArtifactIndex {
+    artifacts: HashMap<String, ArtifactEntry>,
+}
+
+/// Result of a garbage collection pass
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GcReport {
+    pub removed: Vec<String>,
+    pub freed_bytes: u64,
+}
+
+/// Result of an integrity check
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IntegrityReport {
+    pub checked: usize,
+    /// Blobs whose content no longer matches their hash
+    pub corrupted: Vec<String>,
+    /// Indexed artifacts without a blob on disk
+    pub missing: Vec<String>,
+    /// Blobs on disk that are not in the index
+    pub orphaned: Vec<String>,
+}
+
+impl IntegrityReport {
+    pub fn is_healthy(&self) -> bool {
+        self.corrupted.is_empty() && self.missing.is_empty() && self.orphaned.is_empty()
+    }
+}
+
+/// Content-addressable store for session artifacts (tool media, uploads, spilled inputs)
+///
+/// Each content is written once under its blake3 hash, sessions hold references to it.
+/// Releasing a session drops its references, unreferenced blobs are removed by `gc`.
+/// The reference index is a JSON file rewritten atomically after each change; it is
+/// guarded by an in-process lock, so a folder must not be shared between servers.
+pub struct ArtifactStore {
+    root: PathBuf,
+    index: Mutex<ArtifactIndex>,
+}
+
+impl ArtifactStore {
+    /// Get the folder path for artifact storage
+    pub fn folder() -> PathBuf {
+        std::env::var("SHAI_ARTIFACT_FOLDER")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(".shai/artifacts"))
+    }
+
+    /// Open the store at the configured folder
+    pub fn open_default() -> Result<Self, ArtifactError> {
+        Self::open(Self::folder())
+    }
+
+    /// Open (or create) a store rooted at `root`
+    pub fn open(root: impl Into<PathBuf>) -> Result<Self, ArtifactError> {
+        let root = root.into();
+        fs::create_dir_all(root.join("blobs"))?;
+
+        let index_path = root.join("index.json");
+        let index = if index_path.exists() {
+            serde_json::from_str(&fs::read_to_string(&index_path)?)?
+        } else {
+            ArtifactIndex::default()
+        };
+
+        Ok(Self { root, index: Mutex::new(index) })
+    }
+
+    fn blob_path(&self, hash: &str) -> PathBuf {
+        self.root.join("blobs").join(&hash[..2]).join(hash)
+    }
+
+    /// Atomic write of the index: write to temp file, then rename
+    fn save_index(&self, index: &ArtifactIndex) -> Result<(), ArtifactError> {
+        let json = serde_json::to_string(index)?;
+        let temp_path = self.root.join(format!("{}.tmp", Uuid::new_v4()));
+        fs::write(&temp_path, json)?;
+        fs::rename(&temp_path, self.root.join("index.json"))?;
+        Ok(())
+    }
+
+    /// Store content for a session, the blob is only written if the content is new
+    pub fn put(&self, session_id: &str, content: &[u8]) -> Result<ArtifactRef, ArtifactError> {
+        let hash = blake3::hash(content).to_hex().to_string();
+        let size = content.len() as u64;
+
+        let mut index = self.index.lock().unwrap();
+        let blob_path = self.blob_path(&hash);
+        if !index.artifacts.contains_key(&hash) || !blob_path.exists() {
+            write_blob(&blob_path, content)?;
+            debug!("Artifact stored: {} ({} bytes)", hash, size);
+        }
+
+        let entry = index.artifacts.entry(hash.clone()).or_insert_with(|| ArtifactEntry { size, ..Default::default() });
+        if entry.sessions.insert(session_id.to_string()) {
+            self.save_index(&index)?;
+        }
+
+        Ok(ArtifactRef { hash, size })
+    }
+
+    /// Read an artifact by hash
+    pub fn get(&self, hash: &str) -> Result<Vec<u8>, ArtifactError> {
+        if !self.index.lock().unwrap().artifacts.contains_key(hash) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("Artifact not found: {}", hash)).into());
+        }
+        Ok(fs::read(self.blob_path(hash))?)
+    }
+
+    /// Number of sessions referencing an artifact
+    pub fn ref_count(&self, hash: &str) -> usize {
+        self.index.lock().unwrap().artifacts.get(hash).map_or(0, |entry| entry.sessions.len())
+    }
+
+    /// Drop every reference held by a session (called when the session is deleted)
+    /// Returns the number of references released, blobs are removed by the next `gc`
+    pub fn release_session(&self, session_id: &str) -> Result<usize, ArtifactError> {
+        let mut index = self.index.lock().unwrap();
+        let released = index.artifacts.values_mut().filter(|entry| entry.sessions.remove(session_id)).count();
+        if released > 0 {
+            self.save_index(&index)?;
+        }
+        Ok(released)
+    }
+
+    /// Remove the blobs no session references anymore
+    pub fn gc(&self) -> Result<GcReport, ArtifactError> {
+        let mut index = self.index.lock().unwrap();
+        let unreferenced: Vec<String> = index.artifacts.iter()
+            .filter(|(_, entry)| entry.sessions.is_empty())
+            .map(|(hash, _)| hash.clone())
+            .collect();
+
+        let mut report = GcReport::default();
+        for hash in unreferenced {
+            match fs::remove_file(self.blob_path(&hash)) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    error!("Failed to remove artifact {}: {}", hash, e);
+                    continue;
+                }
+            }
+            if let Some(entry) = index.artifacts.remove(&hash) {
+                report.freed_bytes += entry.size;
+            }
+            report.removed.push(hash);
+        }
+
+        if !report.removed.is_empty() {
+            self.save_index(&index)?;
+            debug!("Artifact GC removed {} blobs ({} bytes)", report.removed.len(), report.freed_bytes);
+        }
+        Ok(report)
+    }
+
+    /// Re-hash every blob and compare the index with the blobs on disk
+    pub fn verify(&self) -> Result<IntegrityReport, ArtifactError> {
+        let index = self.index.lock().unwrap();
+        let mut report = IntegrityReport::default();
+
+        for hash in index.artifacts.keys() {
+            report.checked += 1;
+            match fs::read(self.blob_path(hash)) {
+                Ok(content) if blake3::hash(&content).to_hex().as_str() == hash => {}
+                Ok(_) => {
+                    warn!("Artifact {} is corrupted", hash);
+                    report.corrupted.push(hash.clone());
+                }
+                Err(_) => report.missing.push(hash.clone()),
+            }
+        }
+
+        for shard in fs::read_dir(self.root.join("blobs"))? {
+            let shard = shard?;
+            if !shard.file_type()?.is_dir() {
+                continue;
+            }
+            for blob in fs::read_dir(shard.path())? {
+                let name = blob?.file_name().to_string_lossy().to_string();
+                // blobs being written by write_blob
+                if name.ends_with(".tmp") {
+                    continue;
+                }
+                if !index.artifacts.contains_key(&name) {
+                    report.orphaned.push(name);
+                }
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// Write a blob atomically so a concurrent reader never sees partial content
+fn write_blob(path: &Path, content: &[u8]) -> Result<(), ArtifactError> {
+    let folder = path.parent().expect("blob path has a shard folder");
+    fs::create_dir_all(folder)?;
+    let temp_path = folder.join(format!("{}.tmp", Uuid::new_v4()));
+    fs::write(&temp_path, content)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn temp_store() -> (Arc<ArtifactStore>, PathBuf) {
+        let root = std::env::temp_dir().join(format!("shai-artifacts-{}", Uuid::new_v4()));
+        (Arc::new(ArtifactStore::open(&root).unwrap()), root)
+    }
+
+    fn blob_count(root: &Path) -> usize {
+        fs::read_dir(root.join("blobs")).unwrap()
+            .map(|shard| fs::read_dir(shard.unwrap().path()).unwrap().count())
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_identical_uploads_are_deduplicated() {
+        let (store, root) = temp_store();
+        let content = b"same screenshot uploaded twice".to_vec();
+
+        let uploads: Vec<_> = ["session-a", "session-b"].into_iter().map(|sid| {
+            let store = store.clone();
+            let content = content.clone();
+            tokio::task::spawn_blocking(move || store.put(sid, &content).unwrap())
+        }).collect();
+
+        let mut refs = Vec::new();
+        for upload in uploads {
+            refs.push(upload.await.unwrap());
+        }
+
+        assert_eq!(refs[0], refs[1]);
+        assert_eq!(store.ref_count(&refs[0].hash), 2);
+        assert_eq!(blob_count(&root), 1);
+        assert_eq!(store.get(&refs[0].hash).unwrap(), content);
+        assert!(store.verify().unwrap().is_healthy());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_gc_after_sessions_are_released() {
+        let (store, root) = temp_store();
+        let shared = store.put("session-a", b"shared").unwrap();
+        store.put("session-b", b"shared").unwrap();
+
+        store.release_session("session-a").unwrap();
+        assert!(store.gc().unwrap().removed.is_empty(), "blob still referenced by session-b");
+
+        store.release_session("session-b").unwrap();
+        let report = store.gc().unwrap();
+        assert_eq!(report.removed, vec![shared.hash.clone()]);
+        assert_eq!(report.freed_bytes, 6);
+        assert_eq!(blob_count(&root), 0);
+        assert!(store.get(&shared.hash).is_err());
+
+        // the index survives a restart
+        drop(store);
+        let reopened = ArtifactStore::open(&root).unwrap();
+        assert_eq!(reopened.ref_count(&shared.hash), 0);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_verify_detects_corruption() {
+        let (store, root) = temp_store();
+        let artifact = store.put("session-a", b"original").unwrap();
+        fs::write(store.blob_path(&artifact.hash), b"tampered").unwrap();
+
+        let report = store.verify().unwrap();
+        assert_eq!(report.corrupted, vec![artifact.hash.clone()]);
+        assert!(!report.is_healthy());
+
+        // stray files and blobs being written are not orphaned artifacts
+        fs::write(store.blob_path(&artifact.hash), b"original").unwrap();
+        fs::write(root.join("blobs").join(".DS_Store"), b"").unwrap();
+        let shard = store.blob_path(&artifact.hash).parent().unwrap().to_path_buf();
+        fs::write(shard.join(format!("{}.tmp", Uuid::new_v4())), b"partial").unwrap();
+        assert!(store.verify().unwrap().is_healthy());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
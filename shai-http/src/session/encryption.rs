@@ -0,0 +1,227 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+
+/// First bytes of an encrypted session file, followed by the format version
+const MAGIC: &[u8] = b"SHAIENC";
+
+/// Version of the encrypted format: AES-256-GCM, the header authenticated with the content
+const VERSION: u8 = 1;
+
+/// Bytes of the fingerprint naming the key a file is encrypted with
+const KEY_ID_LEN: usize = 4;
+
+const NONCE_LEN: usize = 12;
+
+const TAG_LEN: usize = 16;
+
+/// Magic, version and key fingerprint, authenticated as associated data
+const HEADER_LEN: usize = MAGIC.len() + 1 + KEY_ID_LEN;
+
+/// Bytes of a 256-bit key
+const KEY_LEN: usize = 32;
+
+/// A session file that cannot be encrypted or decrypted
+#[derive(Debug, thiserror::Error)]
+pub enum EncryptionError {
+    #[error("invalid session encryption key: {0}")]
+    InvalidKey(String),
+    #[error("no session encryption key configured")]
+    NoKey,
+    #[error("session file is encrypted but no encryption key is configured")]
+    KeyMissing,
+    #[error("session file is not encrypted")]
+    NotEncrypted,
+    #[error("session file is encrypted with format version {0}, not supported")]
+    UnsupportedVersion(u8),
+    #[error("encrypted session file is truncated")]
+    Truncated,
+    #[error("session file is encrypted with an unknown key (fingerprint {0})")]
+    UnknownKey(String),
+    #[error("encrypted session file failed authentication: its content was altered")]
+    Tampered,
+    #[error("session content could not be encrypted")]
+    EncryptFailed,
+}
+
+/// Keys of the encrypted session files: the first encrypts, all decrypt so that files written
+/// before a key rotation stay readable
+#[derive(Clone, PartialEq)]
+pub struct SessionKeys(Vec<[u8; KEY_LEN]>);
+
+impl fmt::Debug for SessionKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SessionKeys({} keys)", self.0.len())
+    }
+}
+
+impl SessionKeys {
+    pub fn new(keys: Vec<[u8; KEY_LEN]>) -> Result<Self, EncryptionError> {
+        match keys.is_empty() {
+            true => Err(EncryptionError::NoKey),
+            false => Ok(Self(keys)),
+        }
+    }
+
+    /// Keys written as 64 hex characters, separated by commas or lines, `#` starts a comment
+    pub fn parse(text: &str) -> Result<Self, EncryptionError> {
+        let keys = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .flat_map(|line| line.split(','))
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(parse_key)
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(keys)
+    }
+
+    /// Keys of SHAI_SESSION_ENCRYPTION_KEY, or of the file at SHAI_SESSION_ENCRYPTION_KEY_FILE,
+    /// None when neither is set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        if let Ok(keys) = std::env::var("SHAI_SESSION_ENCRYPTION_KEY") {
+            return Self::parse(&keys).map(Some).map_err(|e| format!("SHAI_SESSION_ENCRYPTION_KEY: {}", e));
+        }
+        match std::env::var("SHAI_SESSION_ENCRYPTION_KEY_FILE") {
+            Ok(path) => Self::from_file(PathBuf::from(path)).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn from_file(path: PathBuf) -> Result<Self, String> {
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Whether the content starts with the header of an encrypted session file
+    pub fn is_encrypted(content: &[u8]) -> bool {
+        content.starts_with(MAGIC)
+    }
+
+    /// Whether the content is encrypted with the key new files are encrypted with
+    pub fn is_current(&self, content: &[u8]) -> bool {
+        Self::is_encrypted(content) && content.get(MAGIC.len() + 1..HEADER_LEN) == Some(&key_id(&self.0[0])[..])
+    }
+
+    /// Encrypt with the first key: header, random nonce, then the ciphertext and its tag
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        let key = &self.0[0];
+        let mut content = Vec::with_capacity(HEADER_LEN + NONCE_LEN + plaintext.len() + TAG_LEN);
+        content.extend_from_slice(MAGIC);
+        content.push(VERSION);
+        content.extend_from_slice(&key_id(key));
+
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher(key)
+            .encrypt(&nonce, Payload { msg: plaintext, aad: &content })
+            .map_err(|_| EncryptionError::EncryptFailed)?;
+        content.extend_from_slice(&nonce);
+        content.extend_from_slice(&ciphertext);
+        Ok(content)
+    }
+
+    /// Decrypt a file written by `encrypt` with any of the keys
+    pub fn decrypt(&self, content: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        if !Self::is_encrypted(content) {
+            return Err(EncryptionError::NotEncrypted);
+        }
+        if content.len() < HEADER_LEN + NONCE_LEN + TAG_LEN {
+            return Err(EncryptionError::Truncated);
+        }
+        let (header, rest) = content.split_at(HEADER_LEN);
+        if header[MAGIC.len()] != VERSION {
+            return Err(EncryptionError::UnsupportedVersion(header[MAGIC.len()]));
+        }
+        let id = &header[MAGIC.len() + 1..];
+        let key = self.0.iter()
+            .find(|key| key_id(key) == id)
+            .ok_or_else(|| EncryptionError::UnknownKey(hex(id)))?;
+
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        cipher(key)
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: header })
+            .map_err(|_| EncryptionError::Tampered)
+    }
+}
+
+fn cipher(key: &[u8; KEY_LEN]) -> Aes256Gcm {
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+}
+
+/// Fingerprint of a key stored in the header, tells a wrong key from altered content
+fn key_id(key: &[u8; KEY_LEN]) -> [u8; KEY_ID_LEN] {
+    let hash = blake3::hash(key);
+    let mut id = [0; KEY_ID_LEN];
+    id.copy_from_slice(&hash.as_bytes()[..KEY_ID_LEN]);
+    id
+}
+
+fn parse_key(text: &str) -> Result<[u8; KEY_LEN], EncryptionError> {
+    if text.len() != KEY_LEN * 2 || !text.is_ascii() {
+        return Err(EncryptionError::InvalidKey(format!("expected {} hex characters, got {}", KEY_LEN * 2, text.len())));
+    }
+    let mut key = [0; KEY_LEN];
+    for (byte, pair) in key.iter_mut().zip(text.as_bytes().chunks(2)) {
+        let pair = std::str::from_utf8(pair).unwrap_or_default();
+        *byte = u8::from_str_radix(pair, 16)
+            .map_err(|_| EncryptionError::InvalidKey(format!("'{}' is not hexadecimal", pair)))?;
+    }
+    Ok(key)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_A: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const KEY_B: &str = "ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100";
+
+    #[test]
+    fn test_round_trip_and_rotation() {
+        let old = SessionKeys::parse(KEY_B).unwrap();
+        let encrypted = old.encrypt(b"{\"session_id\": \"alpha\"}").unwrap();
+        assert!(SessionKeys::is_encrypted(&encrypted));
+        assert_eq!(old.decrypt(&encrypted).unwrap(), b"{\"session_id\": \"alpha\"}");
+
+        // after a rotation the old files stay readable, the new ones use the first key
+        let rotated = SessionKeys::parse(&format!("# current\n{}\n{}  # previous", KEY_A, KEY_B)).unwrap();
+        assert_eq!(rotated.decrypt(&encrypted).unwrap(), b"{\"session_id\": \"alpha\"}");
+        assert!(!rotated.is_current(&encrypted));
+        assert!(rotated.is_current(&rotated.encrypt(b"{}").unwrap()));
+
+        let error = SessionKeys::parse(KEY_A).unwrap().decrypt(&encrypted).unwrap_err();
+        assert!(matches!(error, EncryptionError::UnknownKey(_)), "{}", error);
+    }
+
+    #[test]
+    fn test_tampered_and_truncated_files() {
+        let keys = SessionKeys::parse(KEY_A).unwrap();
+        let encrypted = keys.encrypt(b"{\"trace\": []}").unwrap();
+
+        let mut tampered = encrypted.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(keys.decrypt(&tampered), Err(EncryptionError::Tampered)));
+
+        let mut tampered = encrypted.clone();
+        tampered[HEADER_LEN + NONCE_LEN] ^= 1;
+        assert!(matches!(keys.decrypt(&tampered), Err(EncryptionError::Tampered)));
+
+        assert!(matches!(keys.decrypt(&encrypted[..encrypted.len() - 1]), Err(EncryptionError::Tampered)));
+        assert!(matches!(keys.decrypt(&encrypted[..HEADER_LEN + 3]), Err(EncryptionError::Truncated)));
+        assert!(matches!(keys.decrypt(b"{\"trace\": []}"), Err(EncryptionError::NotEncrypted)));
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        assert!(matches!(SessionKeys::parse(""), Err(EncryptionError::NoKey)));
+        assert!(matches!(SessionKeys::parse("abcd"), Err(EncryptionError::InvalidKey(_))));
+        assert!(matches!(SessionKeys::parse(&KEY_A.replace('0', "g")), Err(EncryptionError::InvalidKey(_))));
+        assert_eq!(SessionKeys::parse(&format!("{},{}", KEY_A, KEY_B)).unwrap().0.len(), 2);
+    }
+}
@@ -1,9 +1,14 @@
+use openai_dive::v1::resources::chat::ChatMessage;
 use shai_core::agent::AgentController;
+use std::sync::{Arc, Mutex as StdMutex};
 use tokio::sync::OwnedMutexGuard;
 use tracing::{info, warn};
 
+use crate::quota::{QuotaKind, SessionQuota};
+use crate::run::RunSummary;
 use crate::session::logger::colored_session_id;
-use crate::session::persist::SessionPersist;
+use crate::session::persist::{PersistBackend, PersistedSizeExceeded, SessionAttributes, SessionPersist};
+use crate::session::RunSummaryLog;
 
 
 pub enum RequestLifecycle {
@@ -11,50 +16,103 @@ pub enum RequestLifecycle {
         controller_guard: OwnedMutexGuard<AgentController>,
         request_id: String,
         session_id: String,
+        attributes: SessionAttributes,
+        input_items: Arc<StdMutex<Vec<serde_json::Value>>>,
+        run_summaries: RunSummaryLog,
+        quota: Arc<SessionQuota>,
+        store: Arc<dyn PersistBackend>,
     },
     Ephemeral {
         controller_guard: OwnedMutexGuard<AgentController>,
         request_id: String,
         session_id: String,
+        attributes: SessionAttributes,
+        input_items: Arc<StdMutex<Vec<serde_json::Value>>>,
+        run_summaries: RunSummaryLog,
+        quota: Arc<SessionQuota>,
+        store: Arc<dyn PersistBackend>,
     },
 }
 
 impl RequestLifecycle {
-    pub fn new(ephemeral: bool, controller_guard: OwnedMutexGuard<AgentController>, request_id: String, session_id: String) -> Self {
+    pub fn new(
+        ephemeral: bool,
+        controller_guard: OwnedMutexGuard<AgentController>,
+        request_id: String,
+        session_id: String,
+        attributes: SessionAttributes,
+        input_items: Arc<StdMutex<Vec<serde_json::Value>>>,
+        run_summaries: RunSummaryLog,
+        quota: Arc<SessionQuota>,
+        store: Arc<dyn PersistBackend>,
+    ) -> Self {
         match ephemeral {
-            true => Self::Ephemeral { controller_guard, request_id, session_id },
-            false => Self::Background { controller_guard, request_id, session_id },
+            true => Self::Ephemeral { controller_guard, request_id, session_id, attributes, input_items, run_summaries, quota, store },
+            false => Self::Background { controller_guard, request_id, session_id, attributes, input_items, run_summaries, quota, store },
+        }
+    }
+}
+
+/// Save the session into its store at the end of a request, recording its trace and persisted sizes
+pub(super) async fn save_session(
+    store: Arc<dyn PersistBackend>,
+    ctrl: AgentController,
+    sid: String,
+    attributes: SessionAttributes,
+    input_items: Vec<serde_json::Value>,
+    run_summaries: Vec<RunSummary>,
+    quota: Arc<SessionQuota>,
+) {
+    let trace = match ctrl.get_trace().await {
+        Ok(trace) => trace,
+        Err(e) => {
+            warn!("Failed to get trace for session {}: {}", sid, e);
+            return;
         }
+    };
+    save_trace(store, sid, trace, attributes, input_items, run_summaries, quota).await;
+}
+
+/// Save the session with `trace` into its store, recording its trace and persisted sizes
+pub(super) async fn save_trace(
+    store: Arc<dyn PersistBackend>,
+    sid: String,
+    trace: Vec<ChatMessage>,
+    attributes: SessionAttributes,
+    input_items: Vec<serde_json::Value>,
+    run_summaries: Vec<RunSummary>,
+    quota: Arc<SessionQuota>,
+) {
+    quota.record_trace(&trace);
+
+    match SessionPersist::save_session_to(&*store, &sid, trace, &attributes, input_items, run_summaries, quota.persist_limits(), quota.dropped_messages()).await {
+        Ok(size) => quota.record_persisted(size),
+        Err(e) => match e.downcast_ref::<PersistedSizeExceeded>() {
+            Some(exceeded) => quota.exceeded(QuotaKind::Persisted, &exceeded.to_string()),
+            None => warn!("Failed to save session {}: {}", sid, e),
+        },
     }
 }
 
 impl Drop for RequestLifecycle {
     fn drop(&mut self) {
         match self {
-            Self::Background { controller_guard, request_id, session_id } => {
+            Self::Background { controller_guard, request_id, session_id, attributes, input_items, run_summaries, quota, store } => {
                 info!(
                     "[{}] - {} Stream completed, releasing controller lock (background session)",
                     request_id,
                     colored_session_id(session_id)
                 );
 
-                // Save session to disk (async)
+                // Save session to its store (async)
                 let ctrl = controller_guard.clone();
                 let sid = session_id.clone();
-                tokio::spawn(async move {
-                    match ctrl.get_trace().await {
-                        Ok(trace) => {
-                            if let Err(e) = SessionPersist::save_session(&sid, trace) {
-                                warn!("Failed to save session {}: {}", sid, e);
-                            }
-                        }
-                        Err(e) => {
-                            warn!("Failed to get trace for session {}: {}", sid, e);
-                        }
-                    }
-                });
+                let attributes = attributes.clone();
+                let input_items = input_items.lock().unwrap().clone();
+                let run_summaries = run_summaries.lock().unwrap().clone();
+                tokio::spawn(save_session(store.clone(), ctrl, sid, attributes, input_items, run_summaries, quota.clone()));
             }
-            Self::Ephemeral { controller_guard, request_id, session_id } => {
+            Self::Ephemeral { controller_guard, request_id, session_id, attributes, input_items, run_summaries, quota, store } => {
                 info!(
                     "[{}] - {} Stream completed, destroying agent (ephemeral session)",
                     request_id,
@@ -64,18 +122,14 @@ impl Drop for RequestLifecycle {
                 // Clone before moving into async task
                 let ctrl = controller_guard.clone();
                 let sid = session_id.clone();
+                let attributes = attributes.clone();
+                let input_items = input_items.lock().unwrap().clone();
+                let run_summaries = run_summaries.lock().unwrap().clone();
+                let quota = quota.clone();
+                let store = store.clone();
                 tokio::spawn(async move {
-                    // Save session to disk
-                    match ctrl.get_trace().await {
-                        Ok(trace) => {
-                            if let Err(e) = SessionPersist::save_session(&sid, trace) {
-                                warn!("Failed to save session {}: {}", sid, e);
-                            }
-                        }
-                        Err(e) => {
-                            warn!("Failed to get trace for session {}: {}", sid, e);
-                        }
-                    }
+                    // Save session to its store
+                    save_session(store, ctrl.clone(), sid, attributes, input_items, run_summaries, quota).await;
 
                     // Terminate the agent
                     let _ = ctrl.terminate().await;
@@ -16,6 +16,26 @@ pub fn colored_session_id(session_id: &str) -> String {
     format!("\x1b[38;5;{}msid={}\x1b[0m", color, session_id)
 }
 
+/// Variant name of an event, the `type` label of the agent event metrics
+pub fn event_kind(event: &AgentEvent) -> &'static str {
+    match event {
+        AgentEvent::StatusChanged { .. } => "StatusChanged",
+        AgentEvent::ThinkingStart => "ThinkingStart",
+        AgentEvent::BrainResult { .. } => "BrainResult",
+        AgentEvent::ToolCallStarted { .. } => "ToolCallStarted",
+        AgentEvent::ToolCallCompleted { .. } => "ToolCallCompleted",
+        AgentEvent::ToolCallArgumentsDelta { .. } => "ToolCallArgumentsDelta",
+        AgentEvent::ToolProgress { .. } => "ToolProgress",
+        AgentEvent::UserInput { .. } => "UserInput",
+        AgentEvent::UserInputRequired { .. } => "UserInputRequired",
+        AgentEvent::PermissionRequired { .. } => "PermissionRequired",
+        AgentEvent::Error { .. } => "Error",
+        AgentEvent::Completed { .. } => "Completed",
+        AgentEvent::TokenUsage { .. } => "TokenUsage",
+        AgentEvent::EmptyCompletionRetried { .. } => "EmptyCompletionRetried",
+    }
+}
+
 pub fn log_event(event: &AgentEvent, session_id: &str) {
     let session_id = colored_session_id(session_id);
     match event {
@@ -40,6 +60,10 @@ pub fn log_event(event: &AgentEvent, session_id: &str) {
                 }
             }
         }
+        AgentEvent::ToolProgress { tool_name, message, percent, .. } => {
+            debug!("{} - ToolProgress: {} {:?}% {}",
+                session_id, tool_name, percent, message);
+        }
         AgentEvent::BrainResult { .. } => {
             debug!("{} - BrainResult", session_id);
         }
@@ -1,51 +1,453 @@
-use shai_core::agent::{Agent, AgentError};
-use std::collections::HashMap;
-use std::sync::Arc;
+use shai_core::agent::{Agent, AgentError, AgentEvent, PublicAgentState};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex as StdMutex, RwLock as StdRwLock, Weak};
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
 use openai_dive::v1::resources::chat::ChatMessage;
 
-use shai_core::agent::AgentBuilder;
-use crate::session::{log_event, logger::colored_session_id};
-use crate::session::persist::SessionPersist;
+use shai_core::agent::{AgentBuilder, ContextManager, ContextStrategy};
+use shai_core::config::agent::{default_workspace_ignore, AgentConfig};
+use shai_core::tools::{ToolResult, QUOTA_EXCEEDED_METADATA, RAW_OUTPUT_METADATA};
+use serde_json::Value;
+use crate::features::Features;
+use crate::quota::{QuotaKind, SessionQuota, SessionQuotas};
+use crate::session::{event_kind, log_event, logger::colored_session_id};
+use crate::session::artifacts::ArtifactStore;
+use crate::session::persist::{PersistBackend, PersistError, PruneReport, RequestOverrides, SessionAttributes, SessionData, SessionPersist, SessionRetention};
+use crate::session::sink::{LoggingEventSink, SessionEventSink};
+use crate::session::tool_stats::{output_bytes, ToolCallOutcome, ToolStatsWindow};
+use crate::session::transcript::TranscriptLog;
+use crate::replay::{ReplayDescriptor, ReplayStore, DEFAULT_REPLAY_RETENTION};
+use crate::workspace::WorkspaceConfig;
+#[cfg(feature = "git")]
+use crate::git::GitWorkspace;
 
-use super::AgentSession;
+use super::{AgentSession, RequestQueue};
+use super::session::prepend_system_prompt;
 
 /// Configuration for the session manager
 #[derive(Clone, Debug)]
 pub struct SessionManagerConfig {
     /// Maximum number of concurrent sessions (None = unlimited)
     pub max_sessions: Option<usize>,
+    /// Maximum number of concurrent sessions of one client, its API key or X-Shai-User (None = unlimited)
+    /// Sessions of requests naming no client are only bound by `max_sessions`
+    pub max_sessions_per_owner: Option<usize>,
     /// Whether sessions are ephemeral or background (ephemeral session is destroyed after a single query)
     pub ephemeral: bool,
+    /// Idle time after which a session is evicted (None = sessions never expire)
+    /// Can be overridden per session with the X-Shai-Session-TTL header
+    pub session_ttl_secs: Option<u64>,
+    /// Environment variable names clients may set on a session (None = any name except the dangerous ones)
+    /// A dangerous name (PATH, LD_PRELOAD, ...) is only accepted when listed here explicitly
+    pub env_allowlist: Option<Vec<String>>,
+    /// Snapshot the working directory around each request and report the files it changed
+    /// (default of the workspace-tracking feature flag)
+    pub track_workspace: bool,
+    /// Time replay descriptors are kept (None = never pruned)
+    pub replay_retention_secs: Option<u64>,
+    /// Emit tool call arguments while the model generates them (in addition to agent configs enabling it)
+    /// (default of the tool-argument-streaming feature flag)
+    pub stream_tool_arguments: bool,
+    /// Time a deleted session stays restorable before it is purged (None = kept until deleted permanently)
+    pub trash_retention_secs: Option<u64>,
+    /// Age and count beyond which saved sessions are deleted by the periodic sweep (default: kept forever)
+    pub persisted_retention: SessionRetention,
+    /// Hard caps on the memory and disk used by each session
+    pub quotas: SessionQuotas,
+    /// Commit the working directory to the checkpoint store after each request, one history per session
+    /// (requires the `git` feature, default of the git-checkpoints feature flag)
+    pub git_checkpoints: bool,
+    /// Requests that may wait for a session processing another one (None = unlimited)
+    pub max_queue_depth: Option<usize>,
+    /// Longest wait of a request for its session before it is refused with 429 (None = no limit)
+    pub queue_timeout_secs: Option<u64>,
+    /// System message opening every conversation that does not bring its own (None = none added)
+    pub system_prompt: Option<String>,
+    /// LLM tokens a session may consume over all its requests (None = unlimited)
+    pub token_budget: Option<u32>,
+    /// Keep the raw output of the tools in the artifact store (SHAI_ARTIFACT_FOLDER), off by default:
+    /// the store is only created when enabled
+    pub artifacts: bool,
+    /// Drop the oldest messages of an agent trace over `context_max_tokens` (default of the compaction feature flag)
+    pub compaction: bool,
+    /// Tokens of the trace above which compaction drops messages
+    pub context_max_tokens: u32,
+    /// Run the tool calls of a model reply concurrently (default of the parallel-tools feature flag)
+    pub parallel_tool_calls: bool,
 }
 
+/// Trace size at which compaction starts when the server sets none
+pub const DEFAULT_CONTEXT_MAX_TOKENS: u32 = 100_000;
+
 impl Default for SessionManagerConfig {
     fn default() -> Self {
         Self {
             max_sessions: Some(100),
+            max_sessions_per_owner: None,
             ephemeral: false,
+            session_ttl_secs: None,
+            env_allowlist: None,
+            track_workspace: false,
+            replay_retention_secs: Some(DEFAULT_REPLAY_RETENTION.as_secs()),
+            stream_tool_arguments: false,
+            trash_retention_secs: Some(DEFAULT_TRASH_RETENTION.as_secs()),
+            persisted_retention: SessionRetention::default(),
+            quotas: SessionQuotas::default(),
+            git_checkpoints: false,
+            max_queue_depth: None,
+            queue_timeout_secs: None,
+            system_prompt: None,
+            token_budget: None,
+            artifacts: false,
+            compaction: false,
+            context_max_tokens: DEFAULT_CONTEXT_MAX_TOKENS,
+            parallel_tool_calls: true,
         }
     }
 }
 
+/// Variables that change how processes load or resolve binaries, or run code in the shells of the tools
+const DANGEROUS_ENV_VARS: &[&str] = &[
+    "PATH", "BASH_ENV", "ENV", "IFS", "SHELLOPTS", "BASHOPTS", "PS4", "PROMPT_COMMAND", "GLIBC_TUNABLES",
+];
+
+/// Prefixes of the dangerous variables: dynamic loader settings and exported bash functions
+const DANGEROUS_ENV_PREFIXES: &[&str] = &["LD_", "DYLD_", "BASH_FUNC_"];
+
+/// Whether a client may not set this variable without an allowlist naming it
+/// Names a process could not receive as is (empty, with `=` or NUL) are refused too
+fn is_dangerous_env_var(name: &str) -> bool {
+    name.is_empty()
+        || name.contains(['=', '\0'])
+        || DANGEROUS_ENV_VARS.contains(&name)
+        || DANGEROUS_ENV_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+/// How often the eviction scan looks for expired sessions
+const EVICTION_SCAN_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Default time a deleted session stays in the trash
+pub const DEFAULT_TRASH_RETENTION: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Time the agent of a deleted session gets to stop before it is aborted
+pub const SESSION_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interval at which `shutdown` checks whether the running requests ended
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Minimum delay between two purges of the trash
+const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Minimum delay between two prunes of the saved sessions past their retention
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Sessions loaded in memory by owner
+/// Only changed with the sessions map locked, as sessions are inserted in it and removed from it
+#[derive(Debug, Default)]
+struct OwnerCounts(StdMutex<HashMap<String, usize>>);
+
+impl OwnerCounts {
+    fn count(&self, owner: &str) -> usize {
+        self.0.lock().unwrap().get(owner).copied().unwrap_or(0)
+    }
+
+    /// Check that the owner may load one more session
+    fn check(&self, owner: Option<&str>, limit: Option<usize>) -> Result<(), AgentError> {
+        match (owner, limit) {
+            (Some(owner), Some(limit)) if self.count(owner) >= limit => Err(AgentError::SessionLimitReached { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    fn add(&self, owner: Option<&str>) {
+        if let Some(owner) = owner {
+            *self.0.lock().unwrap().entry(owner.to_string()).or_default() += 1;
+        }
+    }
+
+    fn release(&self, owner: Option<&str>) {
+        let Some(owner) = owner else {
+            return;
+        };
+        let mut counts = self.0.lock().unwrap();
+        if let Some(count) = counts.get_mut(owner) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                counts.remove(owner);
+            }
+        }
+    }
+}
+
+/// Store of a manager, set by `with_store` after the eviction loop started
+type SharedStore = Arc<StdRwLock<Arc<dyn PersistBackend>>>;
+
 /// Session manager - manages multiple agent sessions by ID
 /// Handles creation, deletion, and access control for sessions
 pub struct SessionManager {
     sessions: Arc<Mutex<HashMap<String, Arc<AgentSession>>>>,
     max_sessions: Option<usize>,
-    ephemeral: bool
+    max_sessions_per_owner: Option<usize>,
+    /// Sessions of `sessions` by owner
+    owners: Arc<OwnerCounts>,
+    ephemeral: bool,
+    session_ttl: Option<Duration>,
+    env_allowlist: Option<Vec<String>>,
+    /// Feature flags of the sessions created without explicit ones
+    features: Features,
+    trash_retention: Option<Duration>,
+    persisted_retention: SessionRetention,
+    quotas: SessionQuotas,
+    token_budget: Option<u32>,
+    context_max_tokens: u32,
+    queue: RequestQueue,
+    system_prompt: Option<String>,
+    event_sink: Arc<dyn SessionEventSink>,
+    /// Store the sessions are saved to after their requests, shared with the eviction loop
+    store: SharedStore,
+    artifacts: Option<Arc<ArtifactStore>>,
+    replays: Option<Arc<ReplayStore>>,
+    /// Recent tool calls of all sessions
+    tool_stats: Arc<ToolStatsWindow>,
 }
 
 impl SessionManager {
     pub fn new(config: SessionManagerConfig) -> Self {
+        let sessions = Arc::new(Mutex::new(HashMap::new()));
+        let session_ttl = config.session_ttl_secs.map(Duration::from_secs);
+        let trash_retention = config.trash_retention_secs.map(Duration::from_secs);
+        let artifacts = match config.artifacts.then(ArtifactStore::open_default) {
+            Some(Ok(store)) => Some(Arc::new(store)),
+            Some(Err(e)) => {
+                error!("Failed to open artifact store, artifacts are disabled: {}", e);
+                None
+            }
+            None => None,
+        };
+
+        let replays = match ReplayStore::open_default(config.replay_retention_secs.map(Duration::from_secs)) {
+            Ok(store) => Some(Arc::new(store)),
+            Err(e) => {
+                error!("Failed to open replay store, replay descriptors are disabled: {}", e);
+                None
+            }
+        };
+
+        if config.git_checkpoints && cfg!(not(feature = "git")) {
+            warn!("Git checkpoints requested but shai-http was built without the git feature, they are disabled");
+        }
+
+        let store: SharedStore = Arc::new(StdRwLock::new(SessionPersist::backend()));
+
+        // The scan holds a weak reference so it stops once the manager is dropped
+        tokio::spawn(Self::eviction_loop(
+            Arc::downgrade(&sessions),
+            store.clone(),
+            session_ttl,
+            trash_retention,
+            config.persisted_retention,
+            artifacts.clone(),
+            replays.clone(),
+        ));
+
         Self {
-            sessions: Arc::new(Mutex::new(HashMap::new())),
+            sessions,
             max_sessions: config.max_sessions,
-            ephemeral: config.ephemeral
+            max_sessions_per_owner: config.max_sessions_per_owner,
+            owners: Arc::new(OwnerCounts::default()),
+            ephemeral: config.ephemeral,
+            session_ttl,
+            features: Features::from_config(&config),
+            env_allowlist: config.env_allowlist,
+            trash_retention,
+            persisted_retention: config.persisted_retention,
+            quotas: config.quotas,
+            token_budget: config.token_budget,
+            context_max_tokens: config.context_max_tokens,
+            queue: RequestQueue {
+                max_depth: config.max_queue_depth,
+                timeout: config.queue_timeout_secs.map(Duration::from_secs),
+            },
+            system_prompt: config.system_prompt,
+            event_sink: Arc::new(LoggingEventSink),
+            store,
+            artifacts,
+            replays,
+            tool_stats: Arc::new(ToolStatsWindow::default()),
         }
     }
 
+    /// Store the sessions are persisted to
+    pub fn store(&self) -> Arc<dyn PersistBackend> {
+        self.store.read().unwrap().clone()
+    }
+
+    /// Content-addressable store shared by all sessions (None if not enabled or it could not be opened)
+    pub fn artifacts(&self) -> Option<&Arc<ArtifactStore>> {
+        self.artifacts.as_ref()
+    }
+
+    /// Store of per-request replay descriptors (None if it could not be opened)
+    pub fn replays(&self) -> Option<&Arc<ReplayStore>> {
+        self.replays.as_ref()
+    }
+
+    /// Rolling window of the tool calls of all sessions
+    pub fn tool_stats(&self) -> &Arc<ToolStatsWindow> {
+        &self.tool_stats
+    }
+
+    /// Save the replay descriptor of a request in the background
+    pub fn record_replay(&self, descriptor: &ReplayDescriptor) {
+        if let Some(replays) = &self.replays {
+            replays.record(descriptor.clone());
+        }
+    }
+
+    /// Maximum number of concurrent sessions (None = unlimited)
+    pub fn max_sessions(&self) -> Option<usize> {
+        self.max_sessions
+    }
+
+    /// Maximum number of concurrent sessions of one client (None = unlimited)
+    pub fn max_sessions_per_owner(&self) -> Option<usize> {
+        self.max_sessions_per_owner
+    }
+
+    /// Check that a client may set these session environment variables
+    pub fn validate_env(&self, env: &HashMap<String, String>) -> Result<(), String> {
+        for name in env.keys() {
+            let allowed = match &self.env_allowlist {
+                Some(allowlist) => allowlist.iter().any(|n| n == name),
+                None => !is_dangerous_env_var(name),
+            };
+            if !allowed {
+                return Err(format!("Environment variable not allowed: {}", name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Global session TTL (None = sessions never expire)
+    pub fn session_ttl(&self) -> Option<Duration> {
+        self.session_ttl
+    }
+
+    /// TTL applied to a session: its own override if set, else the global TTL
+    pub fn effective_ttl(&self, session: &AgentSession) -> Option<Duration> {
+        session.ttl().or(self.session_ttl)
+    }
+
+    async fn eviction_loop(
+        sessions: Weak<Mutex<HashMap<String, Arc<AgentSession>>>>,
+        store: SharedStore,
+        session_ttl: Option<Duration>,
+        trash_retention: Option<Duration>,
+        persisted_retention: SessionRetention,
+        artifacts: Option<Arc<ArtifactStore>>,
+        replays: Option<Arc<ReplayStore>>,
+    ) {
+        let mut ticker = tokio::time::interval(EVICTION_SCAN_INTERVAL);
+        let mut last_purge: Option<Instant> = None;
+        let mut last_prune: Option<Instant> = None;
+        loop {
+            ticker.tick().await;
+            let Some(sessions) = sessions.upgrade() else {
+                break;
+            };
+
+            let mut expired = Vec::new();
+            for session in sessions.lock().await.values() {
+                // A session processing a request is active, idle time restarts once it ends
+                if session.is_busy() {
+                    session.touch();
+                    continue;
+                }
+                if session.ttl().or(session_ttl).is_some_and(|ttl| session.idle_for() > ttl) {
+                    expired.push(session.clone());
+                }
+            }
+            drop(sessions);
+
+            for session in expired {
+                // Saved first, terminating the agent then lets its task remove the session and notify the sink
+                // A request that took the session since the scan keeps it
+                match session.evict().await {
+                    Ok(true) => info!("{} - Session expired after {}s idle", colored_session_id(&session.session_id), session.idle_for().as_secs()),
+                    Ok(false) => debug!("{} - Expired session picked up by a request, kept", colored_session_id(&session.session_id)),
+                    Err(e) => debug!("{} - Failed to terminate expired session: {}", colored_session_id(&session.session_id), e),
+                }
+            }
+
+            // Sessions trashed for longer than the retention are deleted for good
+            if let Some(retention) = trash_retention.filter(|_| last_purge.is_none_or(|at| at.elapsed() >= TRASH_PURGE_INTERVAL)) {
+                last_purge = Some(Instant::now());
+                let backend = store.read().unwrap().clone();
+                match SessionPersist::purge_trash_from(&*backend, retention).await {
+                    Ok(purged) => {
+                        for session_id in &purged {
+                            Self::release_artifacts(artifacts.as_deref(), session_id);
+                        }
+                        if !purged.is_empty() {
+                            info!("Purged {} trashed sessions", purged.len());
+                        }
+                    }
+                    Err(e) => error!("Trash purge failed: {}", e),
+                }
+            }
+
+            // Saved sessions past their retention, the ones loaded in memory are kept
+            if !persisted_retention.is_unlimited() && last_prune.is_none_or(|at| at.elapsed() >= PRUNE_INTERVAL) {
+                last_prune = Some(Instant::now());
+                if let Some(sessions) = sessions.upgrade() {
+                    let backend = store.read().unwrap().clone();
+                    match Self::prune_sessions(&sessions, &*backend, artifacts.as_deref(), &persisted_retention).await {
+                        Ok(report) if !report.deleted.is_empty() => info!(
+                            "Pruned {} of {} saved sessions past their retention ({} active kept)",
+                            report.deleted.len(), report.scanned, report.skipped_active
+                        ),
+                        Ok(_) => {}
+                        Err(e) => error!("Session prune failed: {}", e),
+                    }
+                }
+            }
+
+            // Blobs released by deleted sessions are collected on the same sweep
+            if let Some(artifacts) = artifacts.clone() {
+                match tokio::task::spawn_blocking(move || artifacts.gc()).await {
+                    Ok(Ok(report)) if !report.removed.is_empty() => {
+                        info!("Artifact GC removed {} blobs ({} bytes)", report.removed.len(), report.freed_bytes);
+                    }
+                    Ok(Err(e)) => error!("Artifact GC failed: {}", e),
+                    _ => {}
+                }
+            }
+
+            // Replay descriptors past their retention (at most once per hour)
+            if let Some(replays) = replays.clone() {
+                match tokio::task::spawn_blocking(move || replays.prune_if_due()).await {
+                    Ok(Ok(removed)) if removed > 0 => info!("Pruned {} replay descriptors", removed),
+                    Ok(Err(e)) => error!("Replay pruning failed: {}", e),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Replace the sink receiving session lifecycle events (default: LoggingEventSink)
+    pub fn with_event_sink(mut self, event_sink: Arc<dyn SessionEventSink>) -> Self {
+        self.event_sink = event_sink;
+        self
+    }
+
+    /// Save and load the sessions with this store (default: the store of `SessionPersist`)
+    pub fn with_store(self, store: Arc<dyn PersistBackend>) -> Self {
+        *self.store.write().unwrap() = store;
+        self
+    }
+
     async fn create_session(
         &self,
         http_request_id: &String,
@@ -53,36 +455,149 @@ impl SessionManager {
         agent_name: Option<String>,
         ephemeral: bool,
         trace: Option<Vec<ChatMessage>>,
+        attributes: SessionAttributes,
     ) -> Result<Arc<AgentSession>, AgentError> {
         info!("[{}] - {} Creating new session", http_request_id, colored_session_id(session_id));
+        let attributes = SessionAttributes { agent_name: agent_name.clone(), ..attributes };
 
         // Build the agent with optional trace
-        let mut builder = AgentBuilder::create(agent_name.clone().filter(|name| name != "default"))
-            .await
+        let overrides = &attributes.overrides;
+        let config_name = agent_name.clone().filter(|name| name != "default");
+        let builder = match &overrides.route {
+            Some(route) => {
+                let model = overrides.model.clone().unwrap_or_else(|| route.model.clone());
+                AgentBuilder::create_with_provider(config_name, &route.provider, model).await
+            }
+            None => AgentBuilder::create_with_model(config_name, overrides.model.clone()).await,
+        };
+        let mut builder = builder
             .map_err(|e| AgentError::ExecutionError(format!("Failed to create agent: {}", e)))?
-            .sudo();
+            .sudo()
+            .tool_env(attributes.env.clone())
+            .sampling(overrides.sampling.clone());
+        if attributes.tools_disabled {
+            builder = builder.tools(Vec::new());
+        }
+
+        // The file tools charge their writes to the session disk quota, the brain steps its token budget
+        let quota = Arc::new(
+            SessionQuota::new(session_id, self.quotas.clone())
+                .with_token_budget(self.token_budget)
+                .with_event_sink(self.event_sink.clone()),
+        );
+        builder = builder.disk_quota(quota.disk().clone()).token_budget(quota.tokens().clone());
 
-        if let Some(trace) = trace {
+        let features = attributes.features.unwrap_or(self.features);
+
+        #[cfg(feature = "git")]
+        let git = match features.git_checkpoints {
+            true => Self::open_git(session_id, agent_name.as_deref(), &quota).await,
+            false => None,
+        };
+
+        // The system prompt of the server opens the conversation unless it has a system message,
+        // a new session leaves it to its first request, which may bring one
+        let pending_system_prompt = match trace {
+            Some(_) => None,
+            None => self.system_prompt.clone(),
+        };
+        if let Some(mut trace) = trace {
+            if let Some(prompt) = &self.system_prompt {
+                prepend_system_prompt(&mut trace, prompt);
+            }
+            quota.record_trace(&trace);
             builder = builder.with_traces(trace);
         }
+        if features.tool_argument_streaming {
+            builder = builder.stream_tool_arguments(true);
+        }
+        if features.compaction {
+            builder = builder.context_manager(ContextManager::new(self.context_max_tokens, ContextStrategy::SlidingWindow));
+        }
+        builder = builder.parallel_tool_calls(features.parallel_tools);
 
         let mut agent = builder.build();
 
         let controller = agent.controller();
         let event_rx = agent.watch();
 
-        // Spawn logging task alongside agent
+        // Spawn logging task alongside agent, it also reports request outcomes to the event sink
         let mut event_for_logger = event_rx.resubscribe();
         let sid_for_logger = session_id.to_string();
+        let sink_for_logger = self.event_sink.clone();
+        let artifacts_for_logger = self.artifacts.clone();
+        let quota_for_logger = quota.clone();
+        let transcript = Arc::new(TranscriptLog::default());
+        let transcript_for_logger = transcript.clone();
+        let tool_stats_for_logger = self.tool_stats.clone();
         let logging_task = tokio::spawn(async move {
+            let mut request_failed = false;
             while let Ok(event) = event_for_logger.recv().await {
                 log_event(&event, &sid_for_logger);
+                sink_for_logger.on_agent_event(&sid_for_logger, event_kind(&event));
+                transcript_for_logger.record(&event);
+                if let AgentEvent::ToolCallCompleted { call, result, duration } = &event {
+                    let outcome = ToolCallOutcome::of(result);
+                    let duration_ms = duration.num_milliseconds().max(0) as u64;
+                    let size = output_bytes(result);
+                    tool_stats_for_logger.record(&call.tool_name, outcome, duration_ms, size);
+                    sink_for_logger.on_tool_call(&sid_for_logger, &call.tool_name, outcome, duration_ms as f64 / 1000.0, size);
+                }
+                match &event {
+                    AgentEvent::ToolCallCompleted { call, result: ToolResult::Success { metadata: Some(metadata), .. }, .. } => {
+                        // raw output of a post-processed tool call, kept for reference
+                        if let (Some(artifacts), Some(Value::String(raw))) = (&artifacts_for_logger, metadata.get(RAW_OUTPUT_METADATA)) {
+                            // over the disk quota the raw output is dropped, the filtered one is in the trace
+                            if quota_for_logger.charge_disk(raw.len() as u64).is_err() {
+                                continue;
+                            }
+                            match artifacts.put(&sid_for_logger, raw.as_bytes()) {
+                                Ok(artifact) => debug!("{} - raw output of {} stored as artifact {}", colored_session_id(&sid_for_logger), call.tool_call_id, artifact.hash),
+                                Err(e) => {
+                                    quota_for_logger.disk().release(raw.len() as u64);
+                                    error!("{} - failed to store raw output of {}: {}", colored_session_id(&sid_for_logger), call.tool_call_id, e);
+                                }
+                            }
+                        }
+                    }
+                    AgentEvent::ToolCallCompleted { result: ToolResult::Error { error, metadata: Some(metadata) }, .. } if metadata.contains_key(QUOTA_EXCEEDED_METADATA) => {
+                        quota_for_logger.exceeded(QuotaKind::Disk, error);
+                    }
+                    AgentEvent::EmptyCompletionRetried { retries } => {
+                        sink_for_logger.on_empty_completion_retries(&sid_for_logger, *retries, true);
+                    }
+                    AgentEvent::BrainResult { thought: Err(AgentError::EmptyCompletion(attempts)), .. } => {
+                        sink_for_logger.on_empty_completion_retries(&sid_for_logger, attempts.saturating_sub(1), false);
+                        request_failed = true;
+                    }
+                    AgentEvent::BrainResult { thought: Err(error @ AgentError::BudgetExceeded { .. }), .. } => {
+                        quota_for_logger.exceeded(QuotaKind::Tokens, &error.to_string());
+                        request_failed = true;
+                    }
+                    AgentEvent::BrainResult { thought: Err(_), .. } | AgentEvent::Error { .. } => {
+                        request_failed = true;
+                    }
+                    AgentEvent::StatusChanged { new_status: PublicAgentState::Paused, .. } => {
+                        sink_for_logger.on_request_completed(&sid_for_logger, !request_failed);
+                        request_failed = false;
+                    }
+                    AgentEvent::Completed { success, .. } => {
+                        sink_for_logger.on_request_completed(&sid_for_logger, *success && !request_failed);
+                        request_failed = false;
+                    }
+                    _ => {}
+                }
             }
+            transcript_for_logger.close();
         });
 
         // Spawn agent task with cleanup logic
         let sessions_for_cleanup = self.sessions.clone();
+        let owners_for_cleanup = self.owners.clone();
         let sid_for_cleanup = session_id.to_string();
+        let sink_for_cleanup = self.event_sink.clone();
+        let created_at = Instant::now();
+        self.event_sink.on_session_created(session_id);
         let agent_task = tokio::spawn(async move {
             match agent.run().await {
                 Ok(_) => {
@@ -92,21 +607,78 @@ impl SessionManager {
                     error!("{} - Agent execution error: {}", colored_session_id(&sid_for_cleanup), e);
                 }
             }
-            sessions_for_cleanup.lock().await.remove(&sid_for_cleanup);
+            Self::unload(&sessions_for_cleanup, &owners_for_cleanup, &sid_for_cleanup).await;
+            sink_for_cleanup.on_session_destroyed(&sid_for_cleanup, created_at.elapsed().as_secs_f64());
             info!("{} - Session removed from manager", colored_session_id(&sid_for_cleanup));
         });
 
-        let session = Arc::new(AgentSession::new(
+        // Requests decide whether they track their changes, the settings are always available
+        let workspace = Some(Self::workspace_config(agent_name.as_deref()));
+        let session = AgentSession::new(
             session_id.to_string(),
             controller,
             event_rx,
-            logging_task,
             agent_task,
+            logging_task,
             agent_name,
             ephemeral,
-        ));
+            attributes,
+        ).with_workspace(workspace).with_quota(quota).with_transcript(transcript).with_queue(self.queue).with_system_prompt(pending_system_prompt).with_store(self.store());
+        #[cfg(feature = "git")]
+        let session = session.with_git(git);
 
-        Ok(session)
+        Ok(Arc::new(session))
+    }
+
+    /// Git checkpoints of the session workspace, disabled if the checkpoint store cannot be opened
+    /// A resumed session carries on from its last checkpoint
+    #[cfg(feature = "git")]
+    async fn open_git(session_id: &str, agent_name: Option<&str>, quota: &Arc<SessionQuota>) -> Option<Arc<GitWorkspace>> {
+        match GitWorkspace::open_default(session_id, Self::workspace_config(agent_name), quota.clone()).await {
+            Ok(git) => Some(Arc::new(git)),
+            Err(e) => {
+                error!("Failed to open the checkpoint store, git checkpoints are disabled: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Workspace snapshot settings, ignore patterns come from the agent config when it exists
+    fn workspace_config(agent_name: Option<&str>) -> WorkspaceConfig {
+        let ignore = agent_name
+            .filter(|name| *name != "default")
+            .and_then(|name| AgentConfig::load(name).ok())
+            .map(|config| config.workspace_ignore)
+            .unwrap_or_else(default_workspace_ignore);
+        let root = std::env::current_dir().unwrap_or_else(|_| ".".into());
+        WorkspaceConfig::new(root, ignore)
+    }
+
+    /// Get a session only if it is loaded in memory
+    pub async fn find_session(&self, session_id: &str) -> Option<Arc<AgentSession>> {
+        self.sessions.lock().await.get(session_id).cloned()
+    }
+
+    /// Get a session from memory, else resume it with the agent it was saved with
+    /// None when it is neither loaded nor persisted
+    pub async fn find_or_resume_session(&self, http_request_id: &str, session_id: &str) -> Result<Option<Arc<AgentSession>>, AgentError> {
+        if let Some(session) = self.find_session(session_id).await {
+            return Ok(Some(session));
+        }
+        // Resumed sessions are background sessions
+        if self.ephemeral {
+            return Ok(None);
+        }
+
+        match SessionPersist::find_session_from(&*self.store(), session_id).await {
+            Ok(Some(session_data)) => {
+                let agent_name = session_data.attributes.agent_name.clone().unwrap_or_else(|| "default".to_string());
+                let overrides = session_data.attributes.overrides.clone();
+                self.resume_session(http_request_id, session_id, agent_name, session_data, overrides).await.map(Some)
+            }
+            Ok(None) => Ok(None),
+            Err(e) => Err(AgentError::ExecutionError(format!("Session {} cannot be read: {}", session_id, e))),
+        }
     }
 
     /// Get an existing session by ID
@@ -128,24 +700,10 @@ impl SessionManager {
         }
 
         // Try to load from disk
-        match SessionPersist::load_session(session_id) {
+        match SessionPersist::load_session_from(&*self.store(), session_id).await {
             Ok(session_data) => {
-                info!("[{}] - {} Loading session from disk", http_request_id, colored_session_id(session_id));
-
-                // Restore the session with the saved trace
-                let session = self.create_session(
-                    &http_request_id.to_string(),
-                    session_id,
-                    Some(agent_name),
-                    false, // Loaded sessions are not ephemeral
-                    Some(session_data.trace), // Initialize with saved trace
-                ).await?;
-
-                // Store in manager
-                let mut sessions = self.sessions.lock().await;
-                sessions.insert(session_id.to_string(), session.clone());
-
-                Ok(session)
+                let overrides = RequestOverrides::default();
+                self.resume_session(http_request_id, session_id, agent_name, session_data, overrides).await
             }
             Err(e) => {
                 error!("Failed to load session {} from disk: {}", session_id, e);
@@ -157,6 +715,106 @@ impl SessionManager {
         }
     }
 
+    /// Get a session from memory, else resume it from its persisted data, else create it
+    /// A persisted session that cannot be read (corrupted file, older format) is logged and
+    /// replaced by a new session rather than failing the request
+    /// `attributes` are the settings of a created session, a resumed one keeps its own and only
+    /// takes the request overrides
+    pub async fn get_or_create_session(
+        &self,
+        http_request_id: &str,
+        session_id: &str,
+        agent_name: String,
+        ephemeral: bool,
+        attributes: SessionAttributes,
+    ) -> Result<Arc<AgentSession>, AgentError> {
+        if !ephemeral {
+            if let Some(session) = self.find_session(session_id).await {
+                info!("[{}] - {} Using existing in-memory session", http_request_id, colored_session_id(session_id));
+                return Ok(session);
+            }
+
+            match SessionPersist::find_session_from(&*self.store(), session_id).await {
+                Ok(Some(session_data)) => {
+                    let overrides = attributes.overrides.clone();
+                    return self.resume_session(http_request_id, session_id, agent_name, session_data, overrides).await;
+                }
+                Ok(None) => {}
+                Err(e) => warn!(
+                    "[{}] - {} Persisted session unreadable, starting a new one: {}",
+                    http_request_id,
+                    colored_session_id(session_id),
+                    e
+                ),
+            }
+        }
+        self.create_new_session_with(http_request_id, session_id, Some(agent_name), ephemeral, attributes).await
+    }
+
+    /// Load a persisted session in memory, its agent starts from the saved trace
+    async fn resume_session(
+        &self,
+        http_request_id: &str,
+        session_id: &str,
+        agent_name: String,
+        session_data: SessionData,
+        overrides: RequestOverrides,
+    ) -> Result<Arc<AgentSession>, AgentError> {
+        info!("[{}] - {} Loading session from disk", http_request_id, colored_session_id(session_id));
+
+        // Restore the session with the saved trace
+        let session = self.create_session(
+            &http_request_id.to_string(),
+            session_id,
+            Some(agent_name),
+            false, // Loaded sessions are not ephemeral
+            Some(session_data.trace), // Initialize with saved trace
+            SessionAttributes { overrides, ..session_data.attributes },
+        ).await?;
+        session.record_created_at(session_data.created_at);
+        session.record_input_items(session_data.input_items);
+        session.record_run_summaries(session_data.run_summaries);
+        session.quota().record_dropped_messages(session_data.dropped_messages);
+
+        // Store in manager, unless a concurrent request resumed it first
+        let mut sessions = self.sessions.lock().await;
+        if let Some(existing) = sessions.get(session_id) {
+            return Ok(existing.clone());
+        }
+        // the agent of a refused session is stopped as it is dropped
+        self.owners.check(session.owner(), self.max_sessions_per_owner)?;
+        sessions.insert(session_id.to_string(), session.clone());
+        self.owners.add(session.owner());
+
+        Ok(session)
+    }
+
+    /// Persist a fork of a session and load it in memory, ready for the next request
+    /// The fork is saved first so that it survives independently of its source
+    pub async fn fork_session(&self, http_request_id: &str, fork: SessionData) -> Result<Arc<AgentSession>, AgentError> {
+        if self.ephemeral {
+            return Err(AgentError::ExecutionError("Only Ephemeral session are authorized on this server".to_string()));
+        }
+
+        let session_id = fork.session_id.clone();
+        SessionPersist::save_session_to(
+            &*self.store(),
+            &session_id,
+            fork.trace.clone(),
+            &fork.attributes,
+            Vec::new(),
+            Vec::new(),
+            self.quotas.persist_limits(),
+            fork.dropped_messages,
+        )
+        .await
+        .map_err(|e| AgentError::ExecutionError(format!("Failed to save session {}: {}", session_id, e)))?;
+
+        let agent_name = fork.attributes.agent_name.clone().unwrap_or_else(|| "default".to_string());
+        let overrides = fork.attributes.overrides.clone();
+        self.resume_session(http_request_id, &session_id, agent_name, fork, overrides).await
+    }
+
     /// Create a new session with the given ID
     /// Returns error if session already exists
     pub async fn create_new_session(
@@ -165,6 +823,70 @@ impl SessionManager {
         session_id: &str,
         agent_name: Option<String>,
         ephemeral: bool,
+    ) -> Result<Arc<AgentSession>, AgentError> {
+        self.create_new_session_with(http_request_id, session_id, agent_name, ephemeral, SessionAttributes::default()).await
+    }
+
+    /// Create a new session with the given ID and creation settings
+    /// Returns error if session already exists
+    pub async fn create_new_session_with(
+        &self,
+        http_request_id: &str,
+        session_id: &str,
+        agent_name: Option<String>,
+        ephemeral: bool,
+        attributes: SessionAttributes,
+    ) -> Result<Arc<AgentSession>, AgentError> {
+        self.insert_new_session(http_request_id, session_id, agent_name, ephemeral, None, attributes).await
+    }
+
+    /// Create a session continuing a previous one under a new id: it starts from a copy of
+    /// the previous trace and settings, the previous session is left as it is
+    /// The previous session is read from memory when loaded, else from disk
+    /// `overrides` apply to the new session only, the ones of the previous request are not kept
+    pub async fn continue_session(
+        &self,
+        http_request_id: &str,
+        previous_id: &str,
+        session_id: &str,
+        agent_name: Option<String>,
+        overrides: RequestOverrides,
+        ephemeral: bool,
+    ) -> Result<Arc<AgentSession>, AgentError> {
+        let (trace, attributes, user) = match self.find_session(previous_id).await {
+            Some(previous) => {
+                if previous.is_busy() {
+                    return Err(AgentError::ExecutionError(format!(
+                        "Session {} is still processing a request",
+                        previous_id
+                    )));
+                }
+                (previous.trace().await?, previous.current_attributes(), previous.user())
+            }
+            None => {
+                let data = SessionPersist::load_session_from(&*self.store(), previous_id).await.map_err(|e| {
+                    debug!("Failed to load session {} from disk: {}", previous_id, e);
+                    AgentError::ExecutionError(format!("Session not found: {}", previous_id))
+                })?;
+                (data.trace, data.attributes, None)
+            }
+        };
+
+        let attributes = SessionAttributes { overrides, ..attributes };
+        info!("[{}] - {} Continuing session {}", http_request_id, colored_session_id(session_id), previous_id);
+        let session = self.insert_new_session(http_request_id, session_id, agent_name, ephemeral, Some(trace), attributes).await?;
+        session.set_user(user);
+        Ok(session)
+    }
+
+    async fn insert_new_session(
+        &self,
+        http_request_id: &str,
+        session_id: &str,
+        agent_name: Option<String>,
+        ephemeral: bool,
+        trace: Option<Vec<ChatMessage>>,
+        attributes: SessionAttributes,
     ) -> Result<Arc<AgentSession>, AgentError> {
         // Check if ephemeral-only mode is enforced
         if self.ephemeral && !ephemeral {
@@ -173,6 +895,14 @@ impl SessionManager {
             )));
         }
 
+        // A trashed session keeps its id until it is restored or purged
+        if SessionPersist::deleted_at_from(&*self.store(), session_id).await.ok().flatten().is_some() {
+            return Err(AgentError::ExecutionError(format!(
+                "Session {} is in the trash, restore it or delete it permanently",
+                session_id
+            )));
+        }
+
         let mut sessions = self.sessions.lock().await;
 
         // Check if session already exists
@@ -193,10 +923,14 @@ impl SessionManager {
             }
         }
 
-        let session = self.create_session(&http_request_id.to_string(), session_id, agent_name, ephemeral, None).await?;
+        // Checked before the agent is built, the lock is held until the session is counted
+        self.owners.check(attributes.overrides.owner.as_deref(), self.max_sessions_per_owner)?;
+
+        let session = self.create_session(&http_request_id.to_string(), session_id, agent_name, ephemeral, trace, attributes).await?;
 
         // Store all sessions in hashmap (ephemeral sessions will be automatically cleaned up when agent terminates)
         sessions.insert(session_id.to_string(), session.clone());
+        self.owners.add(session.owner());
 
         Ok(session)
     }
@@ -209,8 +943,619 @@ impl SessionManager {
         Ok(())
     }
 
+    /// Delete a session: its agent is terminated and its persisted data moves to the trash,
+    /// where it stays restorable for the trash retention
+    /// Returns false if the session does not exist
+    pub async fn trash_session(&self, http_request_id: &String, session_id: &str) -> Result<bool, AgentError> {
+        self.delete_session(http_request_id, session_id, false, false).await
+    }
+
+    /// Bring back a session deleted less than the trash retention ago
+    /// Returns false if the session is not in the trash (or its retention expired)
+    pub async fn restore_session(&self, http_request_id: &String, session_id: &str) -> Result<bool, AgentError> {
+        let store = self.store();
+        let deleted_at = SessionPersist::deleted_at_from(&*store, session_id)
+            .await
+            .map_err(|e| AgentError::ExecutionError(format!("Failed to read session {}: {}", session_id, e)))?;
+        let Some(deleted_at) = deleted_at else {
+            return Ok(false);
+        };
+
+        let expired = self.trash_retention.is_some_and(|retention| {
+            (chrono::Utc::now() - deleted_at).to_std().is_ok_and(|age| age > retention)
+        });
+        if expired {
+            return Ok(false);
+        }
+
+        store.restore_session(session_id)
+            .await
+            .map_err(|e| AgentError::ExecutionError(format!("Failed to restore session {}: {}", session_id, e)))?;
+        info!("[{}] - {} Session restored from trash", http_request_id, colored_session_id(session_id));
+        Ok(true)
+    }
+
+    /// Delete a session for good, whether it is active or in the trash
+    /// Returns false if the session does not exist
+    pub async fn purge_session(&self, http_request_id: &String, session_id: &str) -> Result<bool, AgentError> {
+        self.delete_session(http_request_id, session_id, true, false).await
+    }
+
+    /// Terminate a session, wait for its agent to stop and unload it, then trash its persisted
+    /// data, or delete it for good with `purge`
+    /// Without `force` the termination waits for the running request, which saves the session when
+    /// it ends. With `force` the agent is terminated under the running request, which ends with it
+    /// Returns false if the session does not exist
+    pub async fn delete_session(
+        &self,
+        http_request_id: &String,
+        session_id: &str,
+        purge: bool,
+        force: bool,
+    ) -> Result<bool, AgentError> {
+        let in_memory = self.find_session(session_id).await;
+        if let Some(session) = &in_memory {
+            let terminated = match force {
+                true => session.terminate_now(http_request_id).await,
+                false => session.cancel(http_request_id).await,
+            };
+            // an agent that already stopped on its own no longer answers
+            if !session.is_stopped() {
+                terminated?;
+            }
+            session.wait_stopped(SESSION_SHUTDOWN_TIMEOUT).await;
+            // an aborted agent did not unload its session
+            Self::unload(&self.sessions, &self.owners, session_id).await;
+        }
+
+        let store = self.store();
+        if purge {
+            let persisted = SessionPersist::load_session_from(&*store, session_id).await.is_ok()
+                || SessionPersist::deleted_at_from(&*store, session_id).await.ok().flatten().is_some();
+            if in_memory.is_none() && !persisted {
+                return Ok(false);
+            }
+            SessionPersist::delete_session_from(&*store, session_id).await;
+            Self::release_artifacts(self.artifacts.as_deref(), session_id);
+            info!("[{}] - {} Session permanently deleted", http_request_id, colored_session_id(session_id));
+            return Ok(true);
+        }
+
+        let persisted = SessionPersist::load_session_from(&*store, session_id).await.is_ok();
+        if persisted {
+            store.soft_delete_session(session_id)
+                .await
+                .map_err(|e| AgentError::ExecutionError(format!("Failed to delete session {}: {}", session_id, e)))?;
+            info!("[{}] - {} Session moved to trash", http_request_id, colored_session_id(session_id));
+        }
+        Ok(in_memory.is_some() || persisted)
+    }
+
+    /// Remove a session from memory, its owner gets its slot back
+    /// Called by the agent task as it ends and by deletions, only the first one finds the session
+    async fn unload(sessions: &Mutex<HashMap<String, Arc<AgentSession>>>, owners: &OwnerCounts, session_id: &str) {
+        let mut sessions = sessions.lock().await;
+        if let Some(session) = sessions.remove(session_id) {
+            owners.release(session.owner());
+        }
+    }
+
+    /// Sessions loaded in memory for `owner`
+    pub fn owner_session_count(&self, owner: &str) -> usize {
+        self.owners.count(owner)
+    }
+
+    /// Drop the artifact references of a deleted session, blobs are collected by the next sweep
+    fn release_artifacts(artifacts: Option<&ArtifactStore>, session_id: &str) {
+        if let Some(artifacts) = artifacts {
+            if let Err(e) = artifacts.release_session(session_id) {
+                error!("{} - Failed to release artifacts: {}", colored_session_id(session_id), e);
+            }
+        }
+    }
+
+    async fn prune_sessions(
+        sessions: &Mutex<HashMap<String, Arc<AgentSession>>>,
+        store: &dyn PersistBackend,
+        artifacts: Option<&ArtifactStore>,
+        retention: &SessionRetention,
+    ) -> Result<PruneReport, PersistError> {
+        if !SessionPersist::is_enabled() {
+            return Ok(PruneReport::default());
+        }
+        let active: HashSet<String> = sessions.lock().await.keys().cloned().collect();
+        let report = SessionPersist::prune_from(store, retention, &active).await?;
+        for session_id in &report.deleted {
+            Self::release_artifacts(artifacts, session_id);
+        }
+        Ok(report)
+    }
+
+    /// Retention of the saved sessions applied by the periodic sweep
+    pub fn persisted_retention(&self) -> &SessionRetention {
+        &self.persisted_retention
+    }
+
+    /// Delete the saved sessions past `retention` now, the sessions loaded in memory are kept
+    pub async fn prune_persisted(&self, retention: &SessionRetention) -> Result<PruneReport, PersistError> {
+        Self::prune_sessions(&self.sessions, &*self.store(), self.artifacts.as_deref(), retention).await
+    }
+
+    /// Sessions loaded in memory, oldest first
+    pub async fn list_sessions(&self) -> Vec<Arc<AgentSession>> {
+        let mut sessions: Vec<_> = self.sessions.lock().await.values().cloned().collect();
+        sessions.sort_by(|a, b| a.created_at().cmp(&b.created_at()).then_with(|| a.session_id.cmp(&b.session_id)));
+        sessions
+    }
+
     /// Get the number of active sessions
     pub async fn session_count(&self) -> usize {
         self.sessions.lock().await.len()
     }
+
+    /// Wait up to `timeout` for the running requests to end, then save and terminate every session
+    /// Sessions still processing a request are saved as they are (see AgentSession::save_running)
+    /// and terminated, their ids are returned
+    pub async fn shutdown(&self, timeout: Duration) -> Vec<String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let busy = self.list_sessions().await.iter().filter(|session| session.is_busy()).count();
+            if busy == 0 || Instant::now() >= deadline {
+                break;
+            }
+            debug!("Waiting for {} running requests", busy);
+            tokio::time::sleep(SHUTDOWN_POLL_INTERVAL).await;
+        }
+
+        let mut still_running = Vec::new();
+        for session in self.list_sessions().await {
+            match session.evict().await {
+                Ok(true) => debug!("{} - Session saved on shutdown", colored_session_id(&session.session_id)),
+                Ok(false) => {
+                    warn!("{} - Session still running after {}s, saved as it is and terminated", colored_session_id(&session.session_id), timeout.as_secs());
+                    if let Err(e) = session.save_running().await {
+                        warn!("{} - Failed to save running session on shutdown: {}", colored_session_id(&session.session_id), e);
+                    }
+                    // Ends its stream, the client is not left waiting on a closed server
+                    let _ = session.terminate_now(&"shutdown".to_string()).await;
+                    still_running.push(session.session_id.clone());
+                }
+                Err(e) => debug!("{} - Failed to terminate session on shutdown: {}", colored_session_id(&session.session_id), e),
+            }
+        }
+        still_running
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use openai_dive::v1::resources::chat::ChatMessageContent;
+    use shai_core::agent::{Brain, ThinkerContext, ThinkerDecision};
+    use crate::model::ModelRoute;
+    use crate::session::{FilePersistBackend, PersistLimits, SessionMetadata};
+    use crate::ErrorResponse;
+    use axum::response::IntoResponse;
+
+    /// Brain that never answers, its requests run until the agent is terminated
+    struct StuckBrain;
+
+    #[async_trait]
+    impl Brain for StuckBrain {
+        async fn next_step(&mut self, _: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+            std::future::pending().await
+        }
+    }
+
+    /// Brain answering one step for each permit added to its gate
+    struct GatedBrain(Arc<tokio::sync::Semaphore>);
+
+    #[async_trait]
+    impl Brain for GatedBrain {
+        async fn next_step(&mut self, _: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+            self.0.acquire().await.expect("the gate is never closed").forget();
+            Ok(ThinkerDecision::agent_pause(ChatMessage::Assistant {
+                content: Some(ChatMessageContent::Text("done".to_string())),
+                reasoning_content: None,
+                refusal: None,
+                name: None,
+                audio: None,
+                tool_calls: None,
+            }))
+        }
+    }
+
+    /// New session whose agent never answers, with the system prompt of the manager pending
+    async fn insert_stuck_session(manager: &SessionManager, session_id: &str) -> Arc<AgentSession> {
+        insert_session(manager, session_id, Box::new(StuckBrain)).await
+    }
+
+    /// New session of an agent running `brain`, with the system prompt of the manager pending
+    async fn insert_session(manager: &SessionManager, session_id: &str, brain: Box<dyn Brain>) -> Arc<AgentSession> {
+        let mut agent = AgentBuilder::with_brain(brain).id(session_id).sudo().build();
+        let controller = agent.controller();
+        let event_rx = agent.watch();
+        let agent_task = tokio::spawn(async move {
+            let _ = agent.run().await;
+        });
+        let logging_task = tokio::spawn(async {});
+        let session = Arc::new(AgentSession::new(
+            session_id.to_string(),
+            controller,
+            event_rx,
+            agent_task,
+            logging_task,
+            None,
+            false,
+            SessionAttributes::default(),
+        ).with_queue(manager.queue).with_system_prompt(manager.system_prompt.clone()).with_store(manager.store()));
+        manager.sessions.lock().await.insert(session_id.to_string(), session.clone());
+        session
+    }
+
+    #[tokio::test]
+    async fn test_validate_env() {
+        let env = |names: &[&str]| names.iter().map(|name| (name.to_string(), "x".to_string())).collect::<HashMap<_, _>>();
+        let manager = SessionManager::new(SessionManagerConfig::default());
+
+        assert!(manager.validate_env(&env(&["RUST_LOG", "HOME", "MY_LD_FLAG"])).is_ok());
+        for name in ["PATH", "LD_PRELOAD", "LD_DEBUG", "DYLD_FRAMEWORK_PATH", "BASH_FUNC_ls%%", "IFS",
+                     "SHELLOPTS", "BASHOPTS", "PS4", "PROMPT_COMMAND", "GLIBC_TUNABLES", "", "A=B"] {
+            let error = manager.validate_env(&env(&[name])).unwrap_err();
+            assert!(error.contains("not allowed"), "{:?} was allowed", name);
+        }
+
+        // an allowlist replaces the deny list
+        let config = SessionManagerConfig { env_allowlist: Some(vec!["LD_DEBUG".to_string()]), ..Default::default() };
+        let manager = SessionManager::new(config);
+        assert!(manager.validate_env(&env(&["LD_DEBUG"])).is_ok());
+        assert!(manager.validate_env(&env(&["RUST_LOG"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_force_delete_while_streaming() {
+        let manager = SessionManager::new(SessionManagerConfig::default());
+        let session_id = format!("sess-{}", uuid::Uuid::new_v4());
+        let session = insert_stuck_session(&manager, &session_id).await;
+
+        let request_id = "req-1".to_string();
+        let message = ChatMessage::User { content: ChatMessageContent::Text("hello".to_string()), name: None };
+        let request = session.handle_request(&request_id, vec![message], Features::default()).await.unwrap();
+        assert!(session.is_busy());
+
+        let deleted = tokio::time::timeout(
+            SESSION_SHUTDOWN_TIMEOUT * 2,
+            manager.delete_session(&request_id, &session_id, true, true),
+        ).await.expect("force deletion must not wait for the request");
+        assert!(deleted.unwrap());
+        assert!(session.is_stopped());
+        assert!(manager.find_session(&session_id).await.is_none());
+
+        // the agent is gone, the request has nothing left to save
+        drop(request);
+        assert!(!manager.delete_session(&request_id, &session_id, true, true).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_saves_busy_sessions() {
+        let folder = tempfile::tempdir().unwrap();
+        let store: Arc<dyn PersistBackend> = Arc::new(FilePersistBackend::new(folder.path().to_path_buf()));
+        let manager = SessionManager::new(SessionManagerConfig::default()).with_store(store.clone());
+        let session_id = format!("sess-{}", uuid::Uuid::new_v4());
+        let session = insert_stuck_session(&manager, &session_id).await;
+
+        let message = ChatMessage::User { content: ChatMessageContent::Text("hello".to_string()), name: None };
+        let _request = session.handle_request(&"req-1".to_string(), vec![message], Features::default()).await.unwrap();
+        for _ in 0..50 {
+            if !session.live_trace().await.unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        // the request never ends: the session is saved as it is, then terminated
+        let still_running = manager.shutdown(Duration::from_millis(100)).await;
+        assert_eq!(still_running, vec![session_id.clone()]);
+        let saved = SessionPersist::load_session_from(&*store, &session_id).await.unwrap();
+        assert!(matches!(&saved.trace[..], [ChatMessage::User { content: ChatMessageContent::Text(text), .. }] if text == "hello"));
+    }
+
+    #[tokio::test]
+    async fn test_request_queue_of_busy_session() {
+        let config = SessionManagerConfig { max_queue_depth: Some(1), queue_timeout_secs: Some(1), ..Default::default() };
+        let manager = SessionManager::new(config);
+        let session_id = format!("sess-{}", uuid::Uuid::new_v4());
+        let gate = Arc::new(tokio::sync::Semaphore::new(0));
+        let session = insert_session(&manager, &session_id, Box::new(GatedBrain(gate.clone()))).await;
+        let message = || ChatMessage::User { content: ChatMessageContent::Text("hello".to_string()), name: None };
+
+        // the first request does not end until the gate opens, like a slow stream
+        let first = session.handle_request(&"req-1".to_string(), vec![message()], Features::default()).await.unwrap();
+
+        let second = tokio::spawn({
+            let session = session.clone();
+            async move { session.handle_request(&"req-2".to_string(), vec![message()], Features::default()).await.map(|_| ()) }
+        });
+        while session.queued_requests() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // the queue is full, the third one is refused right away
+        let third = session.handle_request(&"req-3".to_string(), vec![message()], Features::default()).await;
+        assert!(matches!(third, Err(AgentError::SessionBusy { retry_after_secs: 1, .. })));
+        assert_eq!(session.queued_requests(), 1);
+
+        // the second one gives up after the queue timeout
+        let second = second.await.unwrap();
+        assert!(matches!(second, Err(AgentError::SessionBusy { .. })));
+        assert_eq!(session.queued_requests(), 0);
+
+        let busy = ErrorResponse::request_failed(second.unwrap_err()).into_response();
+        assert_eq!(busy.status(), axum::http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(busy.headers()[axum::http::header::RETRY_AFTER], "1");
+
+        // once the running request ends, the queued one gets the session
+        let fourth = tokio::spawn({
+            let session = session.clone();
+            async move { session.handle_request(&"req-4".to_string(), vec![message()], Features::default()).await.map(|_| ()) }
+        });
+        while session.queued_requests() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        gate.add_permits(1);
+        drop(first);
+        assert!(fourth.await.unwrap().is_ok());
+        assert_eq!(session.queued_requests(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_skips_running_request() {
+        let manager = SessionManager::new(SessionManagerConfig::default());
+        let session_id = format!("sess-{}", uuid::Uuid::new_v4());
+        let session = insert_stuck_session(&manager, &session_id).await;
+
+        let snapshot = session.snapshot().await.unwrap();
+        assert_eq!(snapshot.session_id, session_id);
+
+        // a fork does not wait for the request, it falls back to the persisted session
+        let message = ChatMessage::User { content: ChatMessageContent::Text("hello".to_string()), name: None };
+        let request = session.handle_request(&"req-1".to_string(), vec![message], Features::default()).await.unwrap();
+        assert!(tokio::time::timeout(Duration::from_secs(1), session.snapshot()).await.unwrap().is_none());
+        drop(request);
+    }
+
+    #[tokio::test]
+    async fn test_eviction_spares_busy_sessions() {
+        let manager = SessionManager::new(SessionManagerConfig::default());
+        let session_id = format!("sess-{}", uuid::Uuid::new_v4());
+        let session = insert_stuck_session(&manager, &session_id).await;
+
+        let message = ChatMessage::User { content: ChatMessageContent::Text("hello".to_string()), name: None };
+        let request = session.handle_request(&"req-1".to_string(), vec![message], Features::default()).await.unwrap();
+        assert!(!session.evict().await.unwrap());
+        assert!(!session.is_stopped());
+
+        // the stuck request is stopped, the session is idle again
+        request.controller.stop_current_task().await.unwrap();
+        drop(request);
+        assert!(session.evict().await.unwrap());
+        assert!(session.wait_stopped(SESSION_SHUTDOWN_TIMEOUT).await);
+
+        SessionPersist::delete_session(&session_id).await;
+    }
+
+    #[tokio::test]
+    async fn test_resume_persisted_session_after_restart() {
+        let session_id = format!("sess-{}", uuid::Uuid::new_v4());
+        let trace = vec![
+            ChatMessage::User { content: ChatMessageContent::Text("remember 42".to_string()), name: None },
+            ChatMessage::Assistant {
+                content: Some(ChatMessageContent::Text("noted".to_string())),
+                reasoning_content: None,
+                refusal: None,
+                name: None,
+                audio: None,
+                tool_calls: None,
+            },
+        ];
+        let metadata = SessionMetadata { user: Some("alice".to_string()), tags: vec!["support".to_string()], ..Default::default() };
+        let persisted = SessionAttributes { metadata: metadata.clone(), ..Default::default() };
+        let folder = tempfile::tempdir().unwrap();
+        let store: Arc<dyn PersistBackend> = Arc::new(FilePersistBackend::new(folder.path().to_path_buf()));
+        let size = SessionPersist::save_session_to(&*store, &session_id, trace.clone(), &persisted, vec![], vec![], PersistLimits::default(), 0)
+            .await
+            .unwrap();
+        assert!(size > 0);
+
+        // a new manager on the same store is a restarted server: nothing in memory
+        let manager = SessionManager::new(SessionManagerConfig::default()).with_store(store);
+        assert!(manager.find_session(&session_id).await.is_none());
+
+        // the agent is built on a local provider, it is not called
+        let route = ModelRoute { provider: "ollama".to_string(), model: "test".to_string() };
+        let attributes = SessionAttributes {
+            overrides: RequestOverrides { route: Some(route), ..Default::default() },
+            ..Default::default()
+        };
+        let session = manager
+            .get_or_create_session("req-1", &session_id, "default".to_string(), false, attributes)
+            .await
+            .unwrap();
+        let resumed = session.trace().await.unwrap();
+        let old_messages = &resumed[resumed.len().saturating_sub(trace.len())..];
+        assert_eq!(serde_json::to_value(old_messages).unwrap(), serde_json::to_value(&trace).unwrap());
+
+        // the metadata comes back with the session and the next save keeps its updates
+        assert_eq!(session.metadata(), metadata);
+        session.merge_metadata(SessionMetadata { tags: vec!["billing".to_string()], ..Default::default() });
+        let snapshot = session.snapshot().await.unwrap();
+        assert_eq!(snapshot.attributes.metadata.tags, vec!["support", "billing"]);
+        assert_eq!(snapshot.attributes.metadata.user.as_deref(), Some("alice"));
+    }
+
+    #[tokio::test]
+    async fn test_sessions_per_owner() {
+        let config = SessionManagerConfig { max_sessions_per_owner: Some(1), ..Default::default() };
+        let manager = SessionManager::new(config);
+        let route = ModelRoute { provider: "ollama".to_string(), model: "test".to_string() };
+        let attributes = |owner: &str| SessionAttributes {
+            overrides: RequestOverrides { route: Some(route.clone()), owner: Some(owner.to_string()), ..Default::default() },
+            ..Default::default()
+        };
+        let (first, second, other) = (
+            format!("sess-{}", uuid::Uuid::new_v4()),
+            format!("sess-{}", uuid::Uuid::new_v4()),
+            format!("sess-{}", uuid::Uuid::new_v4()),
+        );
+
+        manager.get_or_create_session("req-1", &first, "default".to_string(), false, attributes("user:alice")).await.unwrap();
+        let Err(refused) = manager.get_or_create_session("req-2", &second, "default".to_string(), false, attributes("user:alice")).await else {
+            panic!("a second session of the same client must be refused");
+        };
+        assert!(matches!(refused, AgentError::SessionLimitReached { limit: 1 }));
+        assert!(manager.find_session(&second).await.is_none());
+        let limited = ErrorResponse::session_not_created(refused).into_response();
+        assert_eq!(limited.status(), axum::http::StatusCode::TOO_MANY_REQUESTS);
+
+        // other clients have their own count
+        manager.get_or_create_session("req-3", &other, "default".to_string(), false, attributes("user:bob")).await.unwrap();
+        assert_eq!(manager.owner_session_count("user:bob"), 1);
+
+        // a deleted session frees its slot
+        assert!(manager.delete_session(&"req-4".to_string(), &first, true, true).await.unwrap());
+        assert_eq!(manager.owner_session_count("user:alice"), 0);
+        manager.get_or_create_session("req-5", &second, "default".to_string(), false, attributes("user:alice")).await.unwrap();
+
+        // so does a session its agent task unloads, once evicted
+        let session = manager.find_session(&other).await.unwrap();
+        assert!(session.evict().await.unwrap());
+        tokio::time::timeout(SESSION_SHUTDOWN_TIMEOUT, async {
+            while manager.find_session(&other).await.is_some() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.expect("the agent task unloads the evicted session");
+        assert_eq!(manager.owner_session_count("user:bob"), 0);
+
+        assert!(manager.delete_session(&"req-6".to_string(), &second, true, true).await.unwrap());
+        SessionPersist::delete_session(&other).await;
+    }
+
+    #[tokio::test]
+    async fn test_fork_persisted_session() {
+        let session_id = format!("sess-{}", uuid::Uuid::new_v4());
+        let trace = vec![
+            ChatMessage::User { content: ChatMessageContent::Text("pick a number".to_string()), name: None },
+            ChatMessage::Assistant {
+                content: Some(ChatMessageContent::Text("7".to_string())),
+                reasoning_content: None,
+                refusal: None,
+                name: None,
+                audio: None,
+                tool_calls: None,
+            },
+        ];
+        // the agent is built on a local provider, it is not called
+        let route = ModelRoute { provider: "ollama".to_string(), model: "test".to_string() };
+        let attributes = SessionAttributes {
+            overrides: RequestOverrides { route: Some(route), ..Default::default() },
+            ..Default::default()
+        };
+        let folder = tempfile::tempdir().unwrap();
+        let store: Arc<dyn PersistBackend> = Arc::new(FilePersistBackend::new(folder.path().to_path_buf()));
+        SessionPersist::save_session_to(&*store, &session_id, trace.clone(), &attributes, vec![], vec![], PersistLimits::default(), 0)
+            .await
+            .unwrap();
+        let source = SessionPersist::find_session_from(&*store, &session_id).await.unwrap().unwrap();
+
+        assert_eq!(source.fork("cut".to_string(), 1).trace.len(), 1);
+        assert_eq!(source.fork("past-end".to_string(), 5).trace.len(), 2);
+
+        let fork_id = format!("fork-{}", uuid::Uuid::new_v4());
+        let manager = SessionManager::new(SessionManagerConfig::default()).with_store(store.clone());
+        let fork = manager.fork_session("req-1", source.fork(fork_id.clone(), 2)).await.unwrap();
+        assert_eq!(fork.session_id, fork_id);
+        assert!(manager.find_session(&session_id).await.is_none());
+
+        let saved = SessionPersist::find_session_from(&*store, &fork_id).await.unwrap().unwrap();
+        assert_eq!(serde_json::to_value(&saved.trace).unwrap(), serde_json::to_value(&trace).unwrap());
+
+        // the source is untouched when the fork goes away
+        SessionPersist::delete_session_from(&*store, &fork_id).await;
+        assert!(SessionPersist::find_session_from(&*store, &session_id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_system_prompt_opens_resumed_conversation() {
+        let session_id = format!("sess-{}", uuid::Uuid::new_v4());
+        let trace = vec![
+            ChatMessage::User { content: ChatMessageContent::Text("hi".to_string()), name: None },
+            ChatMessage::Assistant {
+                content: Some(ChatMessageContent::Text("hello".to_string())),
+                reasoning_content: None,
+                refusal: None,
+                name: None,
+                audio: None,
+                tool_calls: None,
+            },
+        ];
+        let folder = tempfile::tempdir().unwrap();
+        let store: Arc<dyn PersistBackend> = Arc::new(FilePersistBackend::new(folder.path().to_path_buf()));
+        SessionPersist::save_session_to(&*store, &session_id, trace, &SessionAttributes::default(), vec![], vec![], PersistLimits::default(), 0)
+            .await
+            .unwrap();
+
+        let config = SessionManagerConfig { system_prompt: Some("You support Acme customers.".to_string()), ..Default::default() };
+        let manager = SessionManager::new(config).with_store(store);
+        // the agent is built on a local provider, it is not called
+        let route = ModelRoute { provider: "ollama".to_string(), model: "test".to_string() };
+        let attributes = SessionAttributes {
+            overrides: RequestOverrides { route: Some(route), ..Default::default() },
+            ..Default::default()
+        };
+        let session = manager
+            .get_or_create_session("req-1", &session_id, "default".to_string(), false, attributes)
+            .await
+            .unwrap();
+        let resumed = session.trace().await.unwrap();
+        let prompts: Vec<_> = resumed.iter().filter(|message| matches!(message, ChatMessage::System { .. })).collect();
+        assert_eq!(prompts.len(), 1);
+        assert!(serde_json::to_string(prompts[0]).unwrap().contains("Acme"));
+
+        // a conversation with its own system message keeps it alone
+        let mut own = vec![ChatMessage::System { content: ChatMessageContent::Text("Be brief.".to_string()), name: None }];
+        assert!(!prepend_system_prompt(&mut own, "You support Acme customers."));
+        assert_eq!(own.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_system_prompt_opens_new_conversation() {
+        let folder = tempfile::tempdir().unwrap();
+        let store: Arc<dyn PersistBackend> = Arc::new(FilePersistBackend::new(folder.path().to_path_buf()));
+        let config = SessionManagerConfig { system_prompt: Some("You support Acme customers.".to_string()), ..Default::default() };
+        let manager = SessionManager::new(config).with_store(store);
+        let user = ChatMessage::User { content: ChatMessageContent::Text("hi".to_string()), name: None };
+        let own = ChatMessage::System { content: ChatMessageContent::Text("Be brief.".to_string()), name: None };
+
+        // the first request of a new session gets the prompt, unless it brings its own system message
+        for (messages, expected) in [(vec![user.clone()], "You support Acme customers."), (vec![own, user], "Be brief.")] {
+            let session_id = format!("sess-{}", uuid::Uuid::new_v4());
+            let session = insert_stuck_session(&manager, &session_id).await;
+            let _request = session.handle_request(&"req-1".to_string(), messages, Features::default()).await.unwrap();
+            let mut trace = Vec::new();
+            for _ in 0..50 {
+                trace = session.live_trace().await.unwrap();
+                if !trace.is_empty() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+
+            let prompts: Vec<_> = trace.iter().filter(|message| matches!(message, ChatMessage::System { .. })).collect();
+            assert_eq!(prompts.len(), 1, "{:?}", trace);
+            assert!(matches!(&trace[0], ChatMessage::System { content: ChatMessageContent::Text(text), .. } if text == expected));
+            assert_eq!(trace.len(), 2);
+            manager.delete_session(&"req-2".to_string(), &session_id, true, true).await.unwrap();
+        }
+    }
 }
@@ -1,12 +1,36 @@
+mod artifacts;
+mod encryption;
 mod lifecycle;
 mod session;
 mod manager;
 mod logger;
 mod persist;
+#[cfg(feature = "azure")]
+mod persist_azure;
+#[cfg(feature = "redis")]
+mod persist_redis;
+#[cfg(feature = "sqlite")]
+mod persist_sqlite;
+mod sink;
+mod tool_stats;
+mod transcript;
 
-pub use logger::log_event;
+pub use logger::{log_event, event_kind, colored_session_id};
 pub use lifecycle::{RequestLifecycle};
-pub use session::{AgentSession, RequestSession};
-pub use manager::{SessionManager, SessionManagerConfig};
-pub use persist::{SessionPersist, SessionData};
+pub use session::{AgentSession, RequestQueue, RequestSession, RunSummaryLog};
+pub use manager::{SessionManager, SessionManagerConfig, DEFAULT_CONTEXT_MAX_TOKENS};
+pub use persist::{SessionPersist, SessionData, SessionAttributes, SessionMetadata, RequestOverrides, PersistBackend, PersistError, PersistedSizeExceeded, PersistLimits, FilePersistBackend, DEFAULT_COMPRESSION_LEVEL, SessionStoreConfig, SessionRetention, PruneReport};
+#[cfg(feature = "azure")]
+pub use persist_azure::AzureBlobPersistBackend;
+#[cfg(feature = "redis")]
+pub use persist_redis::RedisPersistBackend;
+#[cfg(feature = "sqlite")]
+pub use persist_sqlite::SqlitePersistBackend;
+pub use encryption::{SessionKeys, EncryptionError};
+pub use artifacts::{ArtifactStore, ArtifactRef, GcReport, IntegrityReport};
+pub use sink::{SessionEventSink, LoggingEventSink, CompositeEventSink};
+pub use tool_stats::{ToolStatsWindow, ToolAggregate, ToolCallOutcome, TOOL_STATS_CAPACITY, TOOL_STATS_RETENTION};
+pub use transcript::{TranscriptLog, TranscriptEntry, TranscriptRenderer, TRANSCRIPT_CAPACITY};
+#[cfg(feature = "prometheus")]
+pub use sink::PrometheusEventSink;
 
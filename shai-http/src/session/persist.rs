@@ -1,12 +1,102 @@
-use std::fs;
+use std::collections::{HashMap, HashSet};
 use std::io::{self, ErrorKind};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use openai_dive::v1::resources::chat::ChatMessage;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, error};
+use shai_core::agent::SamplingOverrides;
+use tracing::{debug, error, warn};
 use uuid::Uuid;
 
+use super::encryption::{EncryptionError, SessionKeys};
+use crate::features::Features;
+use crate::run::RunSummary;
+use crate::model::ModelRoute;
+
+/// Session settings fixed at creation, persisted so a resumed session behaves identically
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionAttributes {
+    /// Environment variables injected into tool execution
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Feature flags of the request that created the session (None = server defaults)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub features: Option<Features>,
+    /// The agent runs without its tools (`tool_choice: "none"`)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub tools_disabled: bool,
+    /// Agent the session was created with (None = default agent)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_name: Option<String>,
+    /// Owner, tags and custom fields given by the clients, updated by later requests
+    #[serde(default, skip_serializing_if = "SessionMetadata::is_empty")]
+    pub metadata: SessionMetadata,
+    /// Settings of the request creating the session, neither persisted nor inherited by continuations
+    #[serde(skip)]
+    pub overrides: RequestOverrides,
+}
+
+/// Metadata the clients attach to a session to find it again (`GET /v1/sessions?user=&tag=`)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionMetadata {
+    /// User the session belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Free-form fields of the client
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub fields: HashMap<String, String>,
+}
+
+impl SessionMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.user.is_none() && self.tags.is_empty() && self.fields.is_empty()
+    }
+
+    /// Apply the metadata of a later request: its user replaces the current one, its tags are
+    /// added, its fields are set and the ones given an empty value are removed
+    pub fn merge(&mut self, update: SessionMetadata) {
+        if update.user.is_some() {
+            self.user = update.user;
+        }
+        for tag in update.tags {
+            if !self.tags.contains(&tag) {
+                self.tags.push(tag);
+            }
+        }
+        for (key, value) in update.fields {
+            match value.is_empty() {
+                true => self.fields.remove(&key),
+                false => self.fields.insert(key, value),
+            };
+        }
+    }
+
+    /// Whether the session belongs to `user` and carries `tag` (None matches anything)
+    pub fn matches(&self, user: Option<&str>, tag: Option<&str>) -> bool {
+        user.is_none_or(|user| self.user.as_deref() == Some(user))
+            && tag.is_none_or(|tag| self.tags.iter().any(|t| t == tag))
+    }
+}
+
+/// Agent settings a request changes for the session it creates
+#[derive(Debug, Clone, Default)]
+pub struct RequestOverrides {
+    /// Provider and model of the registry entry the request named (None = the agent's LLM)
+    pub route: Option<ModelRoute>,
+    /// Model replacing the one of the agent or route (X-Shai-Model)
+    pub model: Option<String>,
+    /// Sampling parameters of the request (temperature, top_p, max tokens, stop)
+    pub sampling: SamplingOverrides,
+    /// Client the session is counted against for `max_sessions_per_owner` (None = not counted)
+    pub owner: Option<String>,
+}
+
 /// Session data stored on disk
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionData {
@@ -14,12 +104,656 @@ pub struct SessionData {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub trace: Vec<ChatMessage>,
+    #[serde(flatten)]
+    pub attributes: SessionAttributes,
+    /// Response API input items of every request, in order (empty for older sessions)
+    #[serde(default)]
+    pub input_items: Vec<serde_json::Value>,
+    /// Summary of every request run on the session, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub run_summaries: Vec<RunSummary>,
+    /// Oldest messages dropped from the trace to keep it within the persisted limits, in total
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub dropped_messages: usize,
 }
 
-/// Handle session persistence to disk
-pub struct SessionPersist;
+fn is_zero(count: &usize) -> bool {
+    *count == 0
+}
 
-type PersistError = Box<dyn std::error::Error + Send + Sync>;
+impl SessionData {
+    /// Copy of the session under a new id, with the first `messages` messages of its trace
+    /// A cut between a tool call and its results is moved back before the call, see `tool_call_boundary`
+    /// The copy starts its own history: the input items and run summaries of the source are not kept
+    pub fn fork(&self, session_id: String, messages: usize) -> Self {
+        let now = Utc::now();
+        Self {
+            session_id,
+            created_at: now,
+            updated_at: now,
+            trace: self.trace[..tool_call_boundary(&self.trace, messages)].to_vec(),
+            attributes: self.attributes.clone(),
+            input_items: Vec::new(),
+            run_summaries: Vec::new(),
+            dropped_messages: self.dropped_messages,
+        }
+    }
+}
+
+/// Bounds of a saved session (None = unlimited)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PersistLimits {
+    /// Serialized size of the session, a larger session is refused with PersistedSizeExceeded
+    pub max_bytes: Option<u64>,
+    /// Messages of the saved trace, the oldest ones are dropped beyond it
+    pub max_trace_messages: Option<usize>,
+    /// Serialized size of the saved trace, the oldest messages are dropped beyond it
+    pub max_trace_bytes: Option<u64>,
+}
+
+impl PersistLimits {
+    /// Drop the oldest messages of the trace until it fits in the trace limits, returns how many were dropped
+    ///
+    /// System and developer messages are always kept. An assistant message calling tools goes
+    /// with the tool results following it, so that no call is left without its result.
+    pub fn truncate_trace(&self, trace: &mut Vec<ChatMessage>) -> usize {
+        if self.max_trace_messages.is_none() && self.max_trace_bytes.is_none() {
+            return 0;
+        }
+        let sizes: Vec<u64> = trace.iter()
+            .map(|message| serde_json::to_vec(message).map_or(0, |json| json.len() as u64))
+            .collect();
+        let fits = |count: usize, bytes: u64| {
+            self.max_trace_messages.is_none_or(|max| count <= max) && self.max_trace_bytes.is_none_or(|max| bytes <= max)
+        };
+
+        let mut count = trace.len();
+        let mut bytes: u64 = sizes.iter().sum();
+        let mut dropped = vec![false; trace.len()];
+        let mut next = 0;
+        while !fits(count, bytes) {
+            let Some(start) = (next..trace.len()).find(|&i| !is_instruction(&trace[i])) else {
+                break;
+            };
+            next = start + 1;
+            // tool results go with the call before them (or with nothing when it is already gone)
+            if calls_tools(&trace[start]) || matches!(&trace[start], ChatMessage::Tool { .. }) {
+                while next < trace.len() && matches!(&trace[next], ChatMessage::Tool { .. }) {
+                    next += 1;
+                }
+            }
+            for i in (start..next).filter(|&i| !is_instruction(&trace[i])) {
+                dropped[i] = true;
+                count -= 1;
+                bytes -= sizes[i];
+            }
+        }
+
+        let mut index = 0;
+        trace.retain(|_| {
+            index += 1;
+            !dropped[index - 1]
+        });
+        dropped.iter().filter(|&&dropped| dropped).count()
+    }
+}
+
+/// Assistant message calling tools, its results follow it in the trace
+fn calls_tools(message: &ChatMessage) -> bool {
+    matches!(message, ChatMessage::Assistant { tool_calls: Some(calls), .. } if !calls.is_empty())
+}
+
+/// Largest cut of the trace at or before `at` messages that leaves no tool call without its results:
+/// a cut inside a call and its results is moved back before the call
+fn tool_call_boundary(trace: &[ChatMessage], at: usize) -> usize {
+    let mut at = at.min(trace.len());
+    if matches!(trace.get(at), Some(ChatMessage::Tool { .. })) {
+        while at > 0 && matches!(trace[at - 1], ChatMessage::Tool { .. }) {
+            at -= 1;
+        }
+    }
+    if at > 0 && calls_tools(&trace[at - 1]) {
+        at -= 1;
+    }
+    at
+}
+
+/// Messages setting the behavior of the agent, never dropped from a saved trace
+fn is_instruction(message: &ChatMessage) -> bool {
+    matches!(message, ChatMessage::System { .. } | ChatMessage::Developer { .. })
+}
+
+/// Error returned by persistence backends
+pub type PersistError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A session over the persisted size quota, it is not saved
+#[derive(Debug, thiserror::Error)]
+#[error("session is {size} bytes serialized, over its {limit} bytes persisted size quota")]
+pub struct PersistedSizeExceeded {
+    pub size: u64,
+    pub limit: u64,
+}
+
+/// Storage for persisted sessions
+/// The server opens the store of its `SessionStoreConfig` and hands it to its SessionManager, the
+/// static `SessionPersist` helpers use the one of SHAI_SESSION_PERSIST_BACKEND (file, azure, redis, sqlite)
+#[async_trait]
+pub trait PersistBackend: Send + Sync {
+    /// Write the session, replacing any previous version
+    async fn save(&self, data: &SessionData) -> Result<(), PersistError>;
+
+    /// Read a session, None if it does not exist (or was soft-deleted)
+    async fn load(&self, session_id: &str) -> Result<Option<SessionData>, PersistError>;
+
+    /// Ids of the stored sessions starting with `prefix` (soft-deleted sessions excluded)
+    async fn list_sessions(&self, prefix: &str) -> Result<Vec<String>, PersistError>;
+
+    /// Permanently remove a session
+    async fn delete_session(&self, session_id: &str) -> Result<(), PersistError>;
+
+    /// Hide a session without removing its data
+    async fn soft_delete_session(&self, _session_id: &str) -> Result<(), PersistError> {
+        Err(io::Error::new(ErrorKind::Unsupported, "Soft-delete is not supported by this backend").into())
+    }
+
+    /// Undo a soft-delete
+    async fn restore_session(&self, _session_id: &str) -> Result<(), PersistError> {
+        Err(io::Error::new(ErrorKind::Unsupported, "Restore is not supported by this backend").into())
+    }
+
+    /// Soft-delete date of a session, None if it is not soft-deleted
+    async fn deleted_at(&self, _session_id: &str) -> Result<Option<DateTime<Utc>>, PersistError> {
+        Ok(None)
+    }
+
+    /// Soft-deleted sessions with their deletion date
+    async fn list_soft_deleted(&self) -> Result<Vec<(String, DateTime<Utc>)>, PersistError> {
+        Ok(Vec::new())
+    }
+
+    /// Saved sessions with the date of their last save (the soft-deleted ones are not listed)
+    async fn list_updated(&self) -> Result<Vec<(String, DateTime<Utc>)>, PersistError> {
+        let mut sessions = Vec::new();
+        for id in self.list_sessions("").await? {
+            if let Some(data) = self.load(&id).await? {
+                sessions.push((id, data.updated_at));
+            }
+        }
+        Ok(sessions)
+    }
+}
+
+/// Dates of a session file, parsed without its trace
+#[derive(Deserialize)]
+struct SessionDates {
+    updated_at: DateTime<Utc>,
+}
+
+/// How long saved sessions are kept (None = no limit), see SessionPersist::prune
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionRetention {
+    /// Time since its last save after which a session is deleted
+    pub max_age: Option<Duration>,
+    /// Saved sessions kept, the least recently saved ones are deleted beyond it
+    pub max_sessions: Option<usize>,
+}
+
+impl SessionRetention {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_age.is_none() && self.max_sessions.is_none()
+    }
+}
+
+/// Outcome of a prune of the saved sessions
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PruneReport {
+    /// Saved sessions looked at
+    pub scanned: usize,
+    /// Ids of the deleted sessions
+    pub deleted: Vec<String>,
+    /// Sessions past the retention kept because they are loaded in memory
+    pub skipped_active: usize,
+}
+
+/// Soft-deleted session file: the session data and its deletion date
+#[derive(Serialize, Deserialize)]
+pub(super) struct TrashedSession {
+    pub(super) deleted_at: DateTime<Utc>,
+    pub(super) session: SessionData,
+}
+
+/// Sessions stored as `{session_id}.json` files in a local folder, or `{session_id}.json.zst`
+/// when compression is on (files of either format are read), encrypted when keys are set
+/// (encrypted and plain files are told apart by their header and both read)
+/// Soft-deleted sessions are moved to its `.trash` subfolder
+pub struct FilePersistBackend {
+    folder: PathBuf,
+    /// zstd level of the files written (None = plain JSON)
+    compression: Option<i32>,
+    /// Keys the files are encrypted with (None = written unencrypted)
+    encryption: Option<SessionKeys>,
+}
+
+/// Extension of the plain JSON session files
+const JSON_EXTENSION: &str = ".json";
+
+/// Extension of the zstd-compressed session files
+const ZSTD_EXTENSION: &str = ".json.zst";
+
+/// zstd level used when compression is asked without one
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+
+impl FilePersistBackend {
+    pub fn new(folder: PathBuf) -> Self {
+        Self { folder, compression: None, encryption: None }
+    }
+
+    /// Write the sessions compressed with zstd at this level (None = plain JSON)
+    pub fn with_compression(mut self, level: Option<i32>) -> Self {
+        self.compression = level;
+        self
+    }
+
+    /// Encrypt the files written with the first key, decrypt the files read with any of them
+    pub fn with_encryption(mut self, keys: Option<SessionKeys>) -> Self {
+        self.encryption = keys;
+        self
+    }
+
+    fn trash_folder(&self) -> PathBuf {
+        self.folder.join(".trash")
+    }
+
+    /// Path of the plain and compressed files of a session in a folder
+    fn file_paths(folder: &Path, session_id: &str) -> (PathBuf, PathBuf) {
+        (
+            folder.join(format!("{}{}", session_id, JSON_EXTENSION)),
+            folder.join(format!("{}{}", session_id, ZSTD_EXTENSION)),
+        )
+    }
+
+    /// Atomic write: write to temp file, then rename
+    async fn write_atomic(&self, path: &Path, json: Vec<u8>) -> Result<(), PersistError> {
+        let temp_path = self.folder.join(format!("{}.tmp", Uuid::new_v4()));
+        tokio::fs::write(&temp_path, json).await?;
+        tokio::fs::rename(&temp_path, path).await?;
+        Ok(())
+    }
+
+    /// Write a session file in the format of the store, the file of the other format is removed
+    async fn write_session_file<T: Serialize + Send + 'static>(&self, folder: &Path, session_id: &str, value: T) -> Result<PathBuf, PersistError> {
+        let (plain, compressed) = Self::file_paths(folder, session_id);
+        let json = to_json_blocking(value).await?;
+        let (path, other) = match self.compression {
+            Some(_) => (compressed, plain),
+            None => (plain, compressed),
+        };
+        let content = match self.compression {
+            Some(level) => tokio::task::spawn_blocking(move || zstd::encode_all(json.as_slice(), level)).await??,
+            None => json,
+        };
+        let content = match self.encryption.clone() {
+            Some(keys) => tokio::task::spawn_blocking(move || keys.encrypt(&content)).await??,
+            None => content,
+        };
+        self.write_atomic(&path, content).await?;
+        Self::remove_file(&other).await?;
+        Ok(path)
+    }
+
+    /// Parse the file of a session in either format, None if there is none
+    async fn read_session_file<T: DeserializeOwned + Send + 'static>(&self, folder: &Path, session_id: &str) -> Result<Option<T>, PersistError> {
+        let (plain, compressed) = Self::file_paths(folder, session_id);
+        if let Some(content) = Self::read_file(&compressed).await? {
+            let content = self.decrypt(content).await?;
+            let json = tokio::task::spawn_blocking(move || zstd::decode_all(content.as_slice())).await??;
+            return Ok(Some(from_json_blocking(json).await?));
+        }
+        match Self::read_file(&plain).await? {
+            Some(content) => Ok(Some(from_json_blocking(self.decrypt(content).await?).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Content of an encrypted file decrypted, other files as they are
+    /// An encrypted file without keys, with an unknown key or altered is an EncryptionError
+    async fn decrypt(&self, content: Vec<u8>) -> Result<Vec<u8>, PersistError> {
+        if !SessionKeys::is_encrypted(&content) {
+            return Ok(content);
+        }
+        let Some(keys) = self.encryption.clone() else {
+            return Err(EncryptionError::KeyMissing.into());
+        };
+        Ok(tokio::task::spawn_blocking(move || keys.decrypt(&content)).await??)
+    }
+
+    /// Remove the files of a session in both formats, returns whether there was one
+    async fn remove_session_files(folder: &Path, session_id: &str) -> Result<bool, PersistError> {
+        let (plain, compressed) = Self::file_paths(folder, session_id);
+        let removed_plain = Self::remove_file(&plain).await?;
+        let removed_compressed = Self::remove_file(&compressed).await?;
+        Ok(removed_plain || removed_compressed)
+    }
+
+    /// Content of a file, None if it does not exist
+    async fn read_file(path: &Path) -> Result<Option<Vec<u8>>, PersistError> {
+        match tokio::fs::read(path).await {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn read_trashed(&self, session_id: &str) -> Result<Option<TrashedSession>, PersistError> {
+        self.read_session_file(&self.trash_folder(), session_id).await
+    }
+
+    /// Ids of the session files of a folder (either format), none when the folder does not exist
+    async fn list_ids(folder: &Path) -> Result<Vec<String>, PersistError> {
+        let mut entries = match tokio::fs::read_dir(folder).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut ids = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some(id) = name.strip_suffix(ZSTD_EXTENSION).or_else(|| name.strip_suffix(JSON_EXTENSION)) {
+                ids.push(id.to_string());
+            }
+        }
+        // a session caught between the write of one format and the removal of the other is listed once
+        ids.sort();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    async fn remove_file(path: &Path) -> Result<bool, PersistError> {
+        match tokio::fs::remove_file(path).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Compress the plain JSON files of the store (sessions and trash) in place, at the level of
+    /// the store or the default one, returns the number of files converted
+    pub async fn compress_existing(&self) -> Result<usize, PersistError> {
+        let compressing = Self {
+            folder: self.folder.clone(),
+            compression: Some(self.compression.unwrap_or(DEFAULT_COMPRESSION_LEVEL)),
+            encryption: self.encryption.clone(),
+        };
+        let mut converted = 0;
+        for folder in [self.folder.clone(), self.trash_folder()] {
+            for id in Self::list_ids(&folder).await? {
+                let (plain, _) = Self::file_paths(&folder, &id);
+                let Some(content) = Self::read_file(&plain).await? else {
+                    continue;
+                };
+                // parsed first so that a broken file is left as it is
+                let value: serde_json::Value = from_json_blocking(self.decrypt(content).await?).await?;
+                compressing.write_session_file(&folder, &id, value).await?;
+                converted += 1;
+            }
+        }
+        Ok(converted)
+    }
+
+    /// Encrypt the session files of the store (sessions and trash) with its first key: plain
+    /// files and files of the previous keys are rewritten, returns the number of files rewritten
+    pub async fn encrypt_existing(&self) -> Result<usize, PersistError> {
+        let Some(keys) = &self.encryption else {
+            return Err(EncryptionError::NoKey.into());
+        };
+        let mut rewritten = 0;
+        for folder in [self.folder.clone(), self.trash_folder()] {
+            for id in Self::list_ids(&folder).await? {
+                let (plain, compressed) = Self::file_paths(&folder, &id);
+                let content = match Self::read_file(&compressed).await? {
+                    Some(content) => content,
+                    None => Self::read_file(&plain).await?.unwrap_or_default(),
+                };
+                if keys.is_current(&content) {
+                    continue;
+                }
+                let Some(value) = self.read_session_file::<serde_json::Value>(&folder, &id).await? else {
+                    continue;
+                };
+                self.write_session_file(&folder, &id, value).await?;
+                rewritten += 1;
+            }
+        }
+        Ok(rewritten)
+    }
+}
+
+/// Pretty JSON of a value, serialized on the blocking pool: a trace of several megabytes
+/// would otherwise hold a runtime worker and stall the requests of other sessions
+async fn to_json_blocking<T: Serialize + Send + 'static>(value: T) -> Result<Vec<u8>, PersistError> {
+    Ok(tokio::task::spawn_blocking(move || serde_json::to_vec_pretty(&value)).await??)
+}
+
+/// Parse JSON on the blocking pool, see to_json_blocking
+async fn from_json_blocking<T: DeserializeOwned + Send + 'static>(json: Vec<u8>) -> Result<T, PersistError> {
+    Ok(tokio::task::spawn_blocking(move || serde_json::from_slice(&json)).await??)
+}
+
+#[async_trait]
+impl PersistBackend for FilePersistBackend {
+    async fn save(&self, data: &SessionData) -> Result<(), PersistError> {
+        // Create directory if it doesn't exist
+        if let Err(e) = tokio::fs::create_dir_all(&self.folder).await {
+            error!("Failed to create session directory: {}", e);
+            return Err(e.into());
+        }
+
+        let file_path = self.write_session_file(&self.folder, &data.session_id, data.clone()).await?;
+        debug!("Session saved to disk: {}", file_path.display());
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &str) -> Result<Option<SessionData>, PersistError> {
+        let session = self.read_session_file(&self.folder, session_id).await?;
+        if session.is_none() {
+            debug!("Session file does not exist: {}", session_id);
+        }
+        Ok(session)
+    }
+
+    async fn list_sessions(&self, prefix: &str) -> Result<Vec<String>, PersistError> {
+        let mut ids = Self::list_ids(&self.folder).await?;
+        ids.retain(|id| id.starts_with(prefix));
+        Ok(ids)
+    }
+
+    async fn delete_session(&self, session_id: &str) -> Result<(), PersistError> {
+        if Self::remove_session_files(&self.folder, session_id).await? {
+            debug!("Deleted session file: {}", session_id);
+        }
+        Self::remove_session_files(&self.trash_folder(), session_id).await?;
+        Ok(())
+    }
+
+    async fn soft_delete_session(&self, session_id: &str) -> Result<(), PersistError> {
+        let Some(session) = self.load(session_id).await? else {
+            return Err(io::Error::new(ErrorKind::NotFound, format!("Session not found: {}", session_id)).into());
+        };
+
+        tokio::fs::create_dir_all(self.trash_folder()).await?;
+        let trashed = TrashedSession { deleted_at: Utc::now(), session };
+        self.write_session_file(&self.trash_folder(), session_id, trashed).await?;
+        Self::remove_session_files(&self.folder, session_id).await?;
+
+        debug!("Session moved to trash: {}", session_id);
+        Ok(())
+    }
+
+    async fn restore_session(&self, session_id: &str) -> Result<(), PersistError> {
+        let Some(trashed) = self.read_trashed(session_id).await? else {
+            return Err(io::Error::new(ErrorKind::NotFound, format!("Session not in trash: {}", session_id)).into());
+        };
+
+        self.write_session_file(&self.folder, session_id, trashed.session).await?;
+        Self::remove_session_files(&self.trash_folder(), session_id).await?;
+
+        debug!("Session restored from trash: {}", session_id);
+        Ok(())
+    }
+
+    async fn deleted_at(&self, session_id: &str) -> Result<Option<DateTime<Utc>>, PersistError> {
+        Ok(self.read_trashed(session_id).await?.map(|trashed| trashed.deleted_at))
+    }
+
+    async fn list_soft_deleted(&self) -> Result<Vec<(String, DateTime<Utc>)>, PersistError> {
+        let mut sessions = Vec::new();
+        for id in Self::list_ids(&self.trash_folder()).await? {
+            if let Some(trashed) = self.read_trashed(&id).await? {
+                sessions.push((id, trashed.deleted_at));
+            }
+        }
+        Ok(sessions)
+    }
+
+    async fn list_updated(&self) -> Result<Vec<(String, DateTime<Utc>)>, PersistError> {
+        let mut sessions = Vec::new();
+        for id in Self::list_ids(&self.folder).await? {
+            // a file that does not parse is left alone rather than guessed stale
+            match self.read_session_file::<SessionDates>(&self.folder, &id).await {
+                Ok(Some(dates)) => sessions.push((id, dates.updated_at)),
+                Ok(None) => {}
+                Err(e) => warn!("Skipping unreadable session file {}: {}", id, e),
+            }
+        }
+        Ok(sessions)
+    }
+}
+
+/// Store sessions are persisted to, chosen with `ServerConfig::with_session_store`
+/// Stores other than files need their cargo feature, the file store is used in their place otherwise
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionStoreConfig {
+    /// `{session_id}.json` files in a local folder, compressed with zstd at `compression` level when set,
+    /// encrypted with the `encryption` keys when set
+    File { folder: PathBuf, compression: Option<i32>, encryption: Option<SessionKeys> },
+    /// SQLite database (`sqlite` feature)
+    Sqlite { path: PathBuf },
+    /// Redis server shared by the replicas of a deployment (`redis` feature), keys expire after `ttl` when set
+    Redis { url: String, ttl: Option<Duration> },
+    /// Azure Blob Storage container (`azure` feature), configured from AZURE_STORAGE_ACCOUNT,
+    /// AZURE_STORAGE_KEY and SHAI_AZURE_CONTAINER so that the credentials stay out of the config
+    Azure,
+}
+
+impl SessionStoreConfig {
+    /// Store selected by SHAI_SESSION_PERSIST_BACKEND (file, azure, redis, sqlite)
+    /// Without it, sessions go to the SQLite database of SHAI_SESSION_DB_PATH when set, to files otherwise
+    /// Panics when encryption keys are set but invalid rather than storing the sessions unencrypted
+    pub fn from_env() -> Self {
+        let encryption = SessionKeys::from_env()
+            .unwrap_or_else(|e| panic!("Invalid session encryption keys, refusing to store sessions unencrypted: {}", e));
+        let kind = std::env::var("SHAI_SESSION_PERSIST_BACKEND").unwrap_or_else(|_| {
+            match std::env::var("SHAI_SESSION_DB_PATH") {
+                Ok(_) => "sqlite".to_string(),
+                Err(_) => "file".to_string(),
+            }
+        });
+        let store = match kind.to_lowercase().as_str() {
+            "file" => Self::compression_from_env().map(|compression| Self::File {
+                folder: SessionPersist::folder(),
+                compression,
+                encryption: encryption.clone(),
+            }),
+            "sqlite" => std::env::var("SHAI_SESSION_DB_PATH")
+                .map(|path| Self::Sqlite { path: PathBuf::from(path) })
+                .map_err(|_| "SHAI_SESSION_DB_PATH is not set".to_string()),
+            "redis" => Self::redis_from_env(),
+            "azure" => Ok(Self::Azure),
+            other => Err(format!("Unknown session persistence backend '{}'", other)),
+        };
+        let store = store.unwrap_or_else(|e| {
+            error!("Failed to configure session persistence, using files: {}", e);
+            Self::File { folder: SessionPersist::folder(), compression: None, encryption: encryption.clone() }
+        });
+        if encryption.is_some() && !matches!(store, Self::File { .. }) {
+            warn!("Session encryption keys only apply to the file store, the {} store is not encrypted", store.kind());
+        }
+        store
+    }
+
+    /// zstd level of the file store: SHAI_SESSION_PERSIST_COMPRESSION=zstd turns compression on,
+    /// at the level of SHAI_SESSION_PERSIST_COMPRESSION_LEVEL (default 3)
+    fn compression_from_env() -> Result<Option<i32>, String> {
+        match std::env::var("SHAI_SESSION_PERSIST_COMPRESSION").map(|value| value.to_lowercase()).as_deref() {
+            Err(_) | Ok("") | Ok("none") => return Ok(None),
+            Ok("zstd") => {}
+            Ok(other) => return Err(format!("Unknown session compression '{}'", other)),
+        }
+        match std::env::var("SHAI_SESSION_PERSIST_COMPRESSION_LEVEL") {
+            Ok(level) => level.trim().parse().map(Some).map_err(|_| format!("Invalid SHAI_SESSION_PERSIST_COMPRESSION_LEVEL: {}", level)),
+            Err(_) => Ok(Some(DEFAULT_COMPRESSION_LEVEL)),
+        }
+    }
+
+    /// Redis store of SHAI_REDIS_URL and SHAI_REDIS_TTL_SECS (optional, 0 = no expiry)
+    fn redis_from_env() -> Result<Self, String> {
+        let url = std::env::var("SHAI_REDIS_URL").map_err(|_| "SHAI_REDIS_URL is not set")?;
+        let ttl = match std::env::var("SHAI_REDIS_TTL_SECS") {
+            Ok(secs) => secs.parse::<u64>().map_err(|_| format!("Invalid SHAI_REDIS_TTL_SECS: {}", secs))?,
+            Err(_) => 0,
+        };
+        Ok(Self::Redis { url, ttl: Some(Duration::from_secs(ttl)).filter(|ttl| !ttl.is_zero()) })
+    }
+
+    /// Name of the store, as printed at startup
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::File { .. } => "file",
+            Self::Sqlite { .. } => "sqlite",
+            Self::Redis { .. } => "redis",
+            Self::Azure => "azure",
+        }
+    }
+
+    /// Open the store, files of SHAI_SESSION_PERSIST_FOLDER are used when it cannot be
+    pub fn open(&self) -> Arc<dyn PersistBackend> {
+        match self {
+            Self::File { folder, compression, encryption } => {
+                return Arc::new(
+                    FilePersistBackend::new(folder.clone())
+                        .with_compression(*compression)
+                        .with_encryption(encryption.clone()),
+                );
+            }
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite { path } => return Arc::new(super::persist_sqlite::SqlitePersistBackend::new(path.clone())),
+            #[cfg(not(feature = "sqlite"))]
+            Self::Sqlite { .. } => error!("SQLite persistence requires the `sqlite` feature, using files"),
+            #[cfg(feature = "redis")]
+            Self::Redis { url, ttl } => match super::persist_redis::RedisPersistBackend::new(url, *ttl) {
+                Ok(backend) => return Arc::new(backend),
+                Err(e) => error!("Failed to configure Redis persistence, using files: {}", e),
+            },
+            #[cfg(not(feature = "redis"))]
+            Self::Redis { .. } => error!("Redis persistence requires the `redis` feature, using files"),
+            #[cfg(feature = "azure")]
+            Self::Azure => match super::persist_azure::AzureBlobPersistBackend::from_env() {
+                Ok(backend) => return Arc::new(backend),
+                Err(e) => error!("Failed to configure Azure persistence, using files: {}", e),
+            },
+            #[cfg(not(feature = "azure"))]
+            Self::Azure => error!("Azure persistence requires the `azure` feature, using files"),
+        }
+        Arc::new(FilePersistBackend::new(SessionPersist::folder()))
+    }
+}
+
+/// Store of the static `SessionPersist` helpers, created on first use
+static BACKEND: OnceLock<Arc<dyn PersistBackend>> = OnceLock::new();
+
+/// Handle session persistence through the configured backend
+pub struct SessionPersist;
 
 impl SessionPersist {
     /// Check if session persistence is enabled via environment variable
@@ -29,74 +763,103 @@ impl SessionPersist {
             .unwrap_or(true)
     }
 
-    /// Get the folder path for session storage
+    /// Get the folder path for session storage (file backend)
     pub fn folder() -> PathBuf {
         std::env::var("SHAI_SESSION_PERSIST_FOLDER")
             .map(PathBuf::from)
             .unwrap_or_else(|_| PathBuf::from(".shai/sessions"))
     }
 
-    /// Get the file path for a specific session
-    fn session_file_path(session_id: &str) -> PathBuf {
-        Self::folder().join(format!("{}.json", session_id))
+    /// Store of `SessionStoreConfig::from_env`, created on first use
+    /// Sessions managers have their own store (`SessionManager::with_store`), this one is for the
+    /// callers without one
+    pub fn backend() -> Arc<dyn PersistBackend> {
+        BACKEND.get_or_init(|| SessionStoreConfig::from_env().open()).clone()
+    }
+
+    /// Save a session, keeping the creation date of a previous version
+    /// Returns the serialized size of the session (0 when nothing was saved), a session larger
+    /// than `max_bytes` is refused with PersistedSizeExceeded and its previous version is kept
+    pub async fn save_session(
+        session_id: &str,
+        trace: Vec<ChatMessage>,
+        attributes: &SessionAttributes,
+        input_items: Vec<serde_json::Value>,
+        run_summaries: Vec<RunSummary>,
+        max_bytes: Option<u64>,
+    ) -> Result<u64, PersistError> {
+        let limits = PersistLimits { max_bytes, ..Default::default() };
+        Self::save_session_to(&*Self::backend(), session_id, trace, attributes, input_items, run_summaries, limits, 0).await
     }
 
-    /// Save a session to disk (atomic write using temp file)
-    pub fn save_session(
+    /// `save_session` into a given store, the one a session was created with
+    /// The trace is truncated to the trace limits first, `dropped_messages` is the count of messages
+    /// already dropped before it (those of the saved version a session was resumed from)
+    #[allow(clippy::too_many_arguments)]
+    pub async fn save_session_to(
+        backend: &dyn PersistBackend,
         session_id: &str,
         trace: Vec<ChatMessage>,
-    ) -> Result<(), PersistError> {
+        attributes: &SessionAttributes,
+        input_items: Vec<serde_json::Value>,
+        run_summaries: Vec<RunSummary>,
+        limits: PersistLimits,
+        dropped_messages: usize,
+    ) -> Result<u64, PersistError> {
         if !Self::is_enabled() {
-            return Ok(());
+            return Ok(0);
         }
 
-        let folder = Self::folder();
-
-        // Create directory if it doesn't exist
-        if let Err(e) = fs::create_dir_all(&folder) {
-            error!("Failed to create session directory: {}", e);
-            return Err(e.into());
+        // A request ending after its session was trashed must not bring it back
+        if backend.deleted_at(session_id).await?.is_some() {
+            debug!("Session {} is soft-deleted, not saving", session_id);
+            return Ok(0);
         }
 
-        let file_path = Self::session_file_path(session_id);
-
-        // Load existing data to preserve created_at, or create new
-        let (created_at, updated_at) = if file_path.exists() {
-            match fs::read_to_string(&file_path) {
-                Ok(content) => {
-                    match serde_json::from_str::<SessionData>(&content) {
-                        Ok(existing) => (existing.created_at, Utc::now()),
-                        Err(_) => (Utc::now(), Utc::now()),
-                    }
-                }
-                Err(_) => (Utc::now(), Utc::now()),
-            }
-        } else {
-            (Utc::now(), Utc::now())
+        let created_at = match backend.load(session_id).await {
+            Ok(Some(existing)) => existing.created_at,
+            _ => Utc::now(),
         };
 
         let session_data = SessionData {
             session_id: session_id.to_string(),
             created_at,
-            updated_at,
+            updated_at: Utc::now(),
             trace,
+            attributes: attributes.clone(),
+            input_items,
+            run_summaries,
+            dropped_messages,
         };
 
-        // Serialize to JSON
-        let json = serde_json::to_string_pretty(&session_data)?;
-
-        // Atomic write: write to temp file, then rename
-        let temp_path = folder.join(format!("{}.tmp", Uuid::new_v4()));
-        fs::write(&temp_path, json)?;
-        fs::rename(&temp_path, &file_path)?;
+        // Truncated and measured on the blocking pool like the writes of the file store, see to_json_blocking
+        let (session_data, size) = tokio::task::spawn_blocking(move || {
+            let mut session_data = session_data;
+            let dropped = limits.truncate_trace(&mut session_data.trace);
+            if dropped > 0 {
+                debug!("Dropped the {} oldest messages of session {} over the trace limits", dropped, session_data.session_id);
+            }
+            session_data.dropped_messages += dropped;
+            let size = serde_json::to_vec(&session_data).map(|json| json.len() as u64);
+            (session_data, size)
+        }).await?;
+        let size = size?;
+        if let Some(limit) = limits.max_bytes.filter(|limit| size > *limit) {
+            return Err(PersistedSizeExceeded { size, limit }.into());
+        }
 
-        debug!("Session saved to disk: {}", file_path.display());
-        Ok(())
+        backend.save(&session_data).await?;
+        Ok(size)
     }
 
-    /// Load a single session from disk by session_id
+    /// Load a single session by session_id
     /// Returns the session data if found, or an error if not found or failed to load
-    pub fn load_session(session_id: &str) -> Result<SessionData, PersistError> {
+    pub async fn load_session(session_id: &str) -> Result<SessionData, PersistError> {
+        Self::load_session_from(&*Self::backend(), session_id).await
+    }
+
+    /// `load_session` of a given store
+    pub async fn load_session_from(backend: &dyn PersistBackend, session_id: &str) -> Result<SessionData, PersistError> {
         if !Self::is_enabled() {
             return Err(io::Error::new(
                 ErrorKind::Other,
@@ -105,39 +868,618 @@ impl SessionPersist {
             .into());
         }
 
-        let file_path = Self::session_file_path(session_id);
-
-        // If file doesn't exist, return error
-        if !file_path.exists() {
-            debug!("Session file does not exist: {}", file_path.display());
-            return Err(io::Error::new(
+        match backend.load(session_id).await? {
+            Some(session_data) => {
+                debug!("Loaded session: {}", session_id);
+                Ok(session_data)
+            }
+            None => Err(io::Error::new(
                 ErrorKind::NotFound,
-                format!("Session file not found: {}", session_id),
+                format!("Session not found: {}", session_id),
             )
-            .into());
+            .into()),
         }
+    }
 
-        // Read and parse the session file
-        let content = fs::read_to_string(&file_path)?;
-        let session_data: SessionData = serde_json::from_str(&content)?;
+    /// Load a session, None when it was never saved or persistence is disabled
+    /// Errors are left for the sessions that exist but cannot be read (corrupted, unknown format)
+    pub async fn find_session(session_id: &str) -> Result<Option<SessionData>, PersistError> {
+        Self::find_session_from(&*Self::backend(), session_id).await
+    }
+
+    /// `find_session` of a given store
+    pub async fn find_session_from(backend: &dyn PersistBackend, session_id: &str) -> Result<Option<SessionData>, PersistError> {
+        if !Self::is_enabled() {
+            return Ok(None);
+        }
+        backend.load(session_id).await
+    }
 
-        debug!("Loaded session from disk: {}", session_id);
-        Ok(session_data)
+    /// Delete a persisted session
+    pub async fn delete_session(session_id: &str) {
+        Self::delete_session_from(&*Self::backend(), session_id).await
     }
 
-    /// Delete a session file from disk
-    pub fn delete_session(session_id: &str) {
+    /// `delete_session` of a given store
+    pub async fn delete_session_from(backend: &dyn PersistBackend, session_id: &str) {
         if !Self::is_enabled() {
             return;
         }
 
-        let file_path = Self::session_file_path(session_id);
+        if let Err(e) = backend.delete_session(session_id).await {
+            error!("Failed to delete session {}: {}", session_id, e);
+        }
+    }
+
+    /// Move a persisted session to the trash
+    pub async fn soft_delete_session(session_id: &str) -> Result<(), PersistError> {
+        Self::backend().soft_delete_session(session_id).await
+    }
+
+    /// Bring a trashed session back
+    pub async fn restore_session(session_id: &str) -> Result<(), PersistError> {
+        Self::backend().restore_session(session_id).await
+    }
+
+    /// Date the session was trashed, None if it is not in the trash
+    pub async fn deleted_at(session_id: &str) -> Result<Option<DateTime<Utc>>, PersistError> {
+        Self::deleted_at_from(&*Self::backend(), session_id).await
+    }
+
+    /// `deleted_at` of a given store
+    pub async fn deleted_at_from(backend: &dyn PersistBackend, session_id: &str) -> Result<Option<DateTime<Utc>>, PersistError> {
+        if !Self::is_enabled() {
+            return Ok(None);
+        }
+        backend.deleted_at(session_id).await
+    }
+
+    /// Permanently remove sessions trashed for longer than `retention`, returns their ids
+    pub async fn purge_trash(retention: Duration) -> Result<Vec<String>, PersistError> {
+        Self::purge_trash_from(&*Self::backend(), retention).await
+    }
+
+    /// `purge_trash` of a given store
+    pub async fn purge_trash_from(backend: &dyn PersistBackend, retention: Duration) -> Result<Vec<String>, PersistError> {
+        if !Self::is_enabled() {
+            return Ok(Vec::new());
+        }
+
+        let cutoff = Utc::now() - chrono::Duration::from_std(retention)?;
+        let mut purged = Vec::new();
+        for (session_id, deleted_at) in backend.list_soft_deleted().await? {
+            if deleted_at > cutoff {
+                continue;
+            }
+            match backend.delete_session(&session_id).await {
+                Ok(_) => purged.push(session_id),
+                Err(e) => error!("Failed to purge session {}: {}", session_id, e),
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Permanently remove the saved sessions past `retention`: not saved for longer than its
+    /// max age, or beyond its max count (the least recently saved first)
+    /// Sessions of `active` (loaded in memory) are kept whatever their age
+    pub async fn prune(retention: &SessionRetention, active: &HashSet<String>) -> Result<PruneReport, PersistError> {
+        if !Self::is_enabled() {
+            return Ok(PruneReport::default());
+        }
+        Self::prune_from(&*Self::backend(), retention, active).await
+    }
+
+    /// `prune` of a given store
+    pub async fn prune_from(
+        backend: &dyn PersistBackend,
+        retention: &SessionRetention,
+        active: &HashSet<String>,
+    ) -> Result<PruneReport, PersistError> {
+        if retention.is_unlimited() {
+            return Ok(PruneReport::default());
+        }
+
+        let cutoff = retention.max_age.map(chrono::Duration::from_std).transpose()?.map(|age| Utc::now() - age);
+        let mut sessions = backend.list_updated().await?;
+        sessions.sort_by(|a, b| b.1.cmp(&a.1));
+
+        let mut report = PruneReport { scanned: sessions.len(), ..Default::default() };
+        for (rank, (session_id, updated_at)) in sessions.into_iter().enumerate() {
+            let expired = cutoff.is_some_and(|cutoff| updated_at < cutoff)
+                || retention.max_sessions.is_some_and(|max| rank >= max);
+            if !expired {
+                continue;
+            }
+            if active.contains(&session_id) {
+                report.skipped_active += 1;
+                continue;
+            }
+            match backend.delete_session(&session_id).await {
+                Ok(_) => report.deleted.push(session_id),
+                Err(e) => error!("Failed to prune session {}: {}", session_id, e),
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Instant;
+    use openai_dive::v1::resources::chat::{ChatMessageContent, Function, ToolCall};
+
+    fn text(text: &str) -> ChatMessageContent {
+        ChatMessageContent::Text(text.to_string())
+    }
+
+    fn assistant(text_content: &str, calls: &[&str]) -> ChatMessage {
+        let tool_calls = calls.iter().map(|id| ToolCall {
+            id: id.to_string(),
+            r#type: "function".to_string(),
+            function: Function { name: "read".to_string(), arguments: "{}".to_string() },
+        }).collect::<Vec<_>>();
+        ChatMessage::Assistant {
+            content: Some(text(text_content)),
+            reasoning_content: None,
+            refusal: None,
+            name: None,
+            audio: None,
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+        }
+    }
+
+    fn tool(id: &str) -> ChatMessage {
+        ChatMessage::Tool { content: text("content"), tool_call_id: id.to_string() }
+    }
+
+    fn json(messages: &[ChatMessage]) -> serde_json::Value {
+        serde_json::to_value(messages).unwrap()
+    }
+
+    /// Every tool call of the trace has its result, and every result its call
+    fn assert_calls_paired(trace: &[ChatMessage]) {
+        let calls: Vec<&str> = trace.iter().flat_map(|message| match message {
+            ChatMessage::Assistant { tool_calls: Some(calls), .. } => calls.iter().map(|call| call.id.as_str()).collect(),
+            _ => vec![],
+        }).collect();
+        let results: Vec<&str> = trace.iter().filter_map(|message| match message {
+            ChatMessage::Tool { tool_call_id, .. } => Some(tool_call_id.as_str()),
+            _ => None,
+        }).collect();
+        assert_eq!(calls, results, "tool calls and results are not paired in {:?}", trace);
+    }
+
+    fn conversation() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::System { content: text("You are helpful."), name: None },
+            ChatMessage::User { content: text("read a and b"), name: None },
+            assistant("Reading them.", &["call_1", "call_2"]),
+            tool("call_1"),
+            tool("call_2"),
+            assistant("Done.", &[]),
+            ChatMessage::User { content: text("read c"), name: None },
+            assistant("Reading it.", &["call_3"]),
+            tool("call_3"),
+            assistant("Done.", &[]),
+        ]
+    }
+
+    #[test]
+    fn test_truncate_trace_keeps_system_and_tool_pairs() {
+        for max in 0..=10 {
+            let mut trace = conversation();
+            let limits = PersistLimits { max_trace_messages: Some(max), ..Default::default() };
+            let dropped = limits.truncate_trace(&mut trace);
+
+            assert_eq!(trace.len() + dropped, 10);
+            assert!(trace.len() <= max.max(1), "{} messages kept for a limit of {}", trace.len(), max);
+            assert!(matches!(trace[0], ChatMessage::System { .. }));
+            assert_calls_paired(&trace);
+        }
+
+        // the newest messages are the ones kept
+        let mut trace = conversation();
+        let limits = PersistLimits { max_trace_messages: Some(5), ..Default::default() };
+        assert_eq!(limits.truncate_trace(&mut trace), 5);
+        assert_eq!(json(&trace[1..]), json(&conversation()[6..]));
+
+        // within the limits, or without any, nothing is dropped
+        let mut trace = conversation();
+        assert_eq!(PersistLimits::default().truncate_trace(&mut trace), 0);
+        let limits = PersistLimits { max_trace_messages: Some(10), max_trace_bytes: Some(1 << 20), ..Default::default() };
+        assert_eq!(limits.truncate_trace(&mut trace), 0);
+        assert_eq!(json(&trace), json(&conversation()));
+    }
+
+    #[test]
+    fn test_fork_cut_moved_to_tool_call_boundary() {
+        let source = SessionData {
+            session_id: "source".to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            trace: conversation(),
+            attributes: SessionAttributes::default(),
+            input_items: Vec::new(),
+            run_summaries: Vec::new(),
+            dropped_messages: 0,
+        };
+
+        // after a call, or between its results: the fork ends before the call
+        for at in [3, 4] {
+            let fork = source.fork("fork".to_string(), at);
+            assert_eq!(fork.trace.len(), 2, "cut at {}", at);
+            assert_calls_paired(&fork.trace);
+        }
+        // after the last result the calls are complete
+        assert_eq!(source.fork("fork".to_string(), 5).trace.len(), 5);
+        assert_calls_paired(&source.fork("fork".to_string(), 5).trace);
+        assert_eq!(source.fork("fork".to_string(), 7).trace.len(), 7);
+        assert_eq!(source.fork("fork".to_string(), 8).trace.len(), 7);
+        assert_eq!(tool_call_boundary(&source.trace, 100), 10);
+        assert_eq!(tool_call_boundary(&source.trace, 0), 0);
+    }
+
+    #[test]
+    fn test_truncate_trace_to_bytes() {
+        let full = conversation();
+        let tail_bytes: u64 = full[6..].iter().chain(&full[..1]).map(|message| serde_json::to_vec(message).unwrap().len() as u64).sum();
+
+        let mut trace = conversation();
+        let limits = PersistLimits { max_trace_bytes: Some(tail_bytes), ..Default::default() };
+        assert_eq!(limits.truncate_trace(&mut trace), 5);
+        assert_eq!(json(&trace[1..]), json(&full[6..]));
+        assert_calls_paired(&trace);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_messages_are_counted_across_saves() {
+        let folder = std::env::temp_dir().join(format!("shai-sessions-{}", Uuid::new_v4()));
+        let store = FilePersistBackend::new(folder.clone());
+        let limits = PersistLimits { max_trace_messages: Some(5), ..Default::default() };
+
+        SessionPersist::save_session_to(&store, "alpha-1", conversation(), &SessionAttributes::default(), vec![], vec![], limits, 0)
+            .await
+            .unwrap();
+        let saved = store.load("alpha-1").await.unwrap().unwrap();
+        assert_eq!(saved.trace.len(), 5);
+        assert_eq!(saved.dropped_messages, 5);
+
+        // resumed from the saved trace: the count goes on from the dropped messages of that version
+        let mut trace = saved.trace.clone();
+        trace.extend([ChatMessage::User { content: text("thanks"), name: None }, assistant("You're welcome.", &[])]);
+        SessionPersist::save_session_to(&store, "alpha-1", trace, &SessionAttributes::default(), vec![], vec![], limits, saved.dropped_messages)
+            .await
+            .unwrap();
+        let saved = store.load("alpha-1").await.unwrap().unwrap();
+        assert_eq!(saved.trace.len(), 4);
+        assert_eq!(saved.dropped_messages, 8);
+        assert_calls_paired(&saved.trace);
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn test_metadata_merge_and_filter() {
+        let mut metadata = SessionMetadata {
+            user: Some("alice".to_string()),
+            tags: vec!["support".to_string()],
+            fields: HashMap::from([("plan".to_string(), "pro".to_string()), ("ticket".to_string(), "42".to_string())]),
+        };
+        metadata.merge(SessionMetadata {
+            user: None,
+            tags: vec!["support".to_string(), "billing".to_string()],
+            fields: HashMap::from([("ticket".to_string(), String::new()), ("region".to_string(), "eu".to_string())]),
+        });
+        assert_eq!(metadata.user.as_deref(), Some("alice"));
+        assert_eq!(metadata.tags, vec!["support", "billing"]);
+        assert_eq!(metadata.fields, HashMap::from([("plan".to_string(), "pro".to_string()), ("region".to_string(), "eu".to_string())]));
+
+        assert!(metadata.matches(None, None));
+        assert!(metadata.matches(Some("alice"), Some("billing")));
+        assert!(!metadata.matches(Some("bob"), None));
+        assert!(!metadata.matches(Some("alice"), Some("sales")));
+        assert!(!SessionMetadata::default().matches(Some("alice"), None));
+
+        // stored with the other attributes, absent from the files of sessions without any
+        let attributes = SessionAttributes { metadata: metadata.clone(), ..Default::default() };
+        let json = serde_json::to_value(&attributes).unwrap();
+        assert_eq!(json["metadata"]["user"], "alice");
+        assert_eq!(serde_json::from_value::<SessionAttributes>(json).unwrap().metadata, metadata);
+        assert!(serde_json::to_value(SessionAttributes::default()).unwrap().get("metadata").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_file_backend_roundtrip() {
+        let folder = std::env::temp_dir().join(format!("shai-sessions-{}", Uuid::new_v4()));
+        let backend = FilePersistBackend::new(folder.clone());
+
+        for id in ["alpha-1", "alpha-2", "beta-1"] {
+            backend.save(&SessionData {
+                session_id: id.to_string(),
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+                trace: vec![],
+                attributes: SessionAttributes::default(),
+                input_items: vec![],
+                run_summaries: vec![],
+                dropped_messages: 0,
+            }).await.unwrap();
+        }
+
+        let mut ids = backend.list_sessions("alpha").await.unwrap();
+        ids.sort();
+        assert_eq!(ids, vec!["alpha-1", "alpha-2"]);
+        assert_eq!(backend.load("beta-1").await.unwrap().unwrap().session_id, "beta-1");
+
+        backend.delete_session("beta-1").await.unwrap();
+        assert!(backend.load("beta-1").await.unwrap().is_none());
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_backend_soft_delete_and_restore() {
+        let folder = std::env::temp_dir().join(format!("shai-sessions-{}", Uuid::new_v4()));
+        let backend = FilePersistBackend::new(folder.clone());
+
+        for id in ["alpha-1", "alpha-2"] {
+            backend.save(&SessionData {
+                session_id: id.to_string(),
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+                trace: vec![],
+                attributes: SessionAttributes::default(),
+                input_items: vec![serde_json::json!({"role": "user", "content": id})],
+                run_summaries: vec![],
+                dropped_messages: 0,
+            }).await.unwrap();
+        }
+
+        backend.soft_delete_session("alpha-1").await.unwrap();
+        assert!(backend.load("alpha-1").await.unwrap().is_none());
+        assert_eq!(backend.list_sessions("alpha").await.unwrap(), vec!["alpha-2"]);
+        assert!(backend.deleted_at("alpha-1").await.unwrap().is_some());
+        assert!(backend.deleted_at("alpha-2").await.unwrap().is_none());
+
+        let trashed = backend.list_soft_deleted().await.unwrap();
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].0, "alpha-1");
+
+        // the data comes back untouched
+        backend.restore_session("alpha-1").await.unwrap();
+        let restored = backend.load("alpha-1").await.unwrap().unwrap();
+        assert_eq!(restored.input_items, vec![serde_json::json!({"role": "user", "content": "alpha-1"})]);
+        assert!(backend.list_soft_deleted().await.unwrap().is_empty());
+        assert!(backend.restore_session("alpha-1").await.is_err());
+
+        // a hard delete also removes the trashed copy
+        backend.soft_delete_session("alpha-2").await.unwrap();
+        backend.delete_session("alpha-2").await.unwrap();
+        assert!(backend.list_soft_deleted().await.unwrap().is_empty());
+        assert!(backend.restore_session("alpha-2").await.is_err());
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_prune_by_age_and_count() {
+        let folder = std::env::temp_dir().join(format!("shai-sessions-{}", Uuid::new_v4()));
+        let backend = FilePersistBackend::new(folder.clone());
+        let days_ago = |days: i64| Utc::now() - chrono::Duration::days(days);
+
+        for (id, updated_at) in [("fresh", days_ago(0)), ("week", days_ago(7)), ("month", days_ago(30)), ("active", days_ago(60))] {
+            backend.save(&SessionData {
+                session_id: id.to_string(),
+                created_at: updated_at,
+                updated_at,
+                trace: vec![],
+                attributes: SessionAttributes::default(),
+                input_items: vec![],
+                run_summaries: vec![],
+                dropped_messages: 0,
+            }).await.unwrap();
+        }
+        fs::write(folder.join("garbage.json"), "not json").unwrap();
+        let active = HashSet::from(["active".to_string()]);
+
+        let unlimited = SessionPersist::prune_from(&backend, &SessionRetention::default(), &active).await.unwrap();
+        assert_eq!(unlimited.scanned, 0);
+
+        let retention = SessionRetention { max_age: Some(Duration::from_secs(14 * 24 * 3600)), max_sessions: None };
+        let report = SessionPersist::prune_from(&backend, &retention, &active).await.unwrap();
+        assert_eq!(report.scanned, 4);
+        assert_eq!(report.deleted, vec!["month"]);
+        assert_eq!(report.skipped_active, 1);
+
+        let retention = SessionRetention { max_age: None, max_sessions: Some(1) };
+        let report = SessionPersist::prune_from(&backend, &retention, &active).await.unwrap();
+        assert_eq!(report.deleted, vec!["week"]);
+
+        let mut left = backend.list_sessions("").await.unwrap();
+        left.sort();
+        assert_eq!(left, vec!["active", "fresh", "garbage"]);
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    fn session(id: &str) -> SessionData {
+        SessionData {
+            session_id: id.to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            trace: conversation(),
+            attributes: SessionAttributes::default(),
+            input_items: vec![serde_json::json!({"role": "user", "content": id})],
+            run_summaries: vec![],
+            dropped_messages: 0,
+        }
+    }
 
-        if file_path.exists() {
-            match fs::remove_file(&file_path) {
-                Ok(_) => debug!("Deleted session file: {}", file_path.display()),
-                Err(e) => error!("Failed to delete session file {:?}: {}", file_path, e),
+    #[tokio::test]
+    async fn test_file_backend_compressed_roundtrip() {
+        let folder = std::env::temp_dir().join(format!("shai-sessions-{}", Uuid::new_v4()));
+        let plain = FilePersistBackend::new(folder.clone());
+        let compressed = FilePersistBackend::new(folder.clone()).with_compression(Some(DEFAULT_COMPRESSION_LEVEL));
+
+        plain.save(&session("alpha-1")).await.unwrap();
+        compressed.save(&session("alpha-2")).await.unwrap();
+        assert!(folder.join("alpha-1.json").exists());
+        assert!(folder.join("alpha-2.json.zst").exists());
+        let raw = fs::read(folder.join("alpha-2.json.zst")).unwrap();
+        assert!(serde_json::from_slice::<serde_json::Value>(&raw).is_err());
+
+        // both stores read both formats
+        for store in [&plain, &compressed] {
+            let mut ids = store.list_sessions("alpha").await.unwrap();
+            ids.sort();
+            assert_eq!(ids, vec!["alpha-1", "alpha-2"]);
+            for id in ["alpha-1", "alpha-2"] {
+                let loaded = store.load(id).await.unwrap().unwrap();
+                assert_eq!(loaded.input_items, vec![serde_json::json!({"role": "user", "content": id})]);
+                assert_eq!(json(&loaded.trace), json(&conversation()));
             }
         }
+
+        // saving in the other format replaces the file
+        compressed.save(&session("alpha-1")).await.unwrap();
+        assert!(!folder.join("alpha-1.json").exists());
+        assert!(folder.join("alpha-1.json.zst").exists());
+
+        // the trash keeps the format of the store
+        compressed.soft_delete_session("alpha-2").await.unwrap();
+        assert!(folder.join(".trash").join("alpha-2.json.zst").exists());
+        assert!(plain.deleted_at("alpha-2").await.unwrap().is_some());
+        plain.restore_session("alpha-2").await.unwrap();
+        assert!(folder.join("alpha-2.json").exists());
+
+        plain.delete_session("alpha-1").await.unwrap();
+        compressed.delete_session("alpha-2").await.unwrap();
+        assert!(plain.list_sessions("").await.unwrap().is_empty());
+        assert!(fs::read_dir(&folder).unwrap().filter_map(Result::ok).all(|entry| entry.path().is_dir()));
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_compress_existing_files() {
+        let folder = std::env::temp_dir().join(format!("shai-sessions-{}", Uuid::new_v4()));
+        let plain = FilePersistBackend::new(folder.clone());
+        for id in ["alpha-1", "alpha-2", "alpha-3"] {
+            plain.save(&session(id)).await.unwrap();
+        }
+        plain.soft_delete_session("alpha-3").await.unwrap();
+        let plain_size = fs::metadata(folder.join("alpha-1.json")).unwrap().len();
+
+        let store = FilePersistBackend::new(folder.clone()).with_compression(Some(DEFAULT_COMPRESSION_LEVEL));
+        assert_eq!(store.compress_existing().await.unwrap(), 3);
+        assert_eq!(store.compress_existing().await.unwrap(), 0);
+
+        assert!(!folder.join("alpha-1.json").exists());
+        assert!(fs::metadata(folder.join("alpha-1.json.zst")).unwrap().len() < plain_size);
+        assert!(folder.join(".trash").join("alpha-3.json.zst").exists());
+        assert_eq!(json(&plain.load("alpha-2").await.unwrap().unwrap().trace), json(&conversation()));
+        assert!(plain.deleted_at("alpha-3").await.unwrap().is_some());
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_backend_encrypted_roundtrip() {
+        let folder = std::env::temp_dir().join(format!("shai-sessions-{}", Uuid::new_v4()));
+        let key = |byte: &str| byte.repeat(32);
+        let plain = FilePersistBackend::new(folder.clone());
+        let old = FilePersistBackend::new(folder.clone()).with_encryption(Some(SessionKeys::parse(&key("0b")).unwrap()));
+        let rotated = FilePersistBackend::new(folder.clone())
+            .with_compression(Some(DEFAULT_COMPRESSION_LEVEL))
+            .with_encryption(Some(SessionKeys::parse(&format!("{},{}", key("0a"), key("0b"))).unwrap()));
+
+        plain.save(&session("alpha-1")).await.unwrap();
+        old.save(&session("alpha-2")).await.unwrap();
+        let raw = fs::read(folder.join("alpha-2.json")).unwrap();
+        assert!(SessionKeys::is_encrypted(&raw));
+        assert!(!String::from_utf8_lossy(&raw).contains("alpha-2"));
+
+        // plain files and files of the previous key stay readable
+        for id in ["alpha-1", "alpha-2"] {
+            assert_eq!(json(&rotated.load(id).await.unwrap().unwrap().trace), json(&conversation()));
+        }
+        let error = plain.load("alpha-2").await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<EncryptionError>(), Some(EncryptionError::KeyMissing)), "{}", error);
+
+        assert_eq!(rotated.encrypt_existing().await.unwrap(), 2);
+        assert_eq!(rotated.encrypt_existing().await.unwrap(), 0);
+        let error = old.load("alpha-1").await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<EncryptionError>(), Some(EncryptionError::UnknownKey(_))), "{}", error);
+
+        // an altered or truncated file is refused with an error rather than parsed
+        let path = folder.join("alpha-2.json.zst");
+        let mut raw = fs::read(&path).unwrap();
+        let middle = raw.len() / 2;
+        raw[middle] ^= 1;
+        fs::write(&path, &raw).unwrap();
+        let error = rotated.load("alpha-2").await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<EncryptionError>(), Some(EncryptionError::Tampered)), "{}", error);
+        fs::write(&path, &raw[..10]).unwrap();
+        let error = rotated.load("alpha-2").await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<EncryptionError>(), Some(EncryptionError::Truncated)), "{}", error);
+
+        fs::remove_dir_all(folder).unwrap();
     }
+
+    #[tokio::test]
+    async fn test_store_config_opens_file_store() {
+        let folder = std::env::temp_dir().join(format!("shai-sessions-{}", Uuid::new_v4()));
+        let store = SessionStoreConfig::File { folder: folder.clone(), compression: None, encryption: None }.open();
+
+        let size = SessionPersist::save_session_to(&*store, "alpha-1", vec![], &SessionAttributes::default(), vec![], vec![], PersistLimits::default(), 0)
+            .await
+            .unwrap();
+        assert!(size > 0);
+        // the JSON files stay readable by the file backend
+        assert!(folder.join("alpha-1.json").exists());
+        assert_eq!(FilePersistBackend::new(folder.clone()).list_sessions("alpha").await.unwrap(), vec!["alpha-1"]);
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+
+    #[tokio::test]
+    async fn test_large_save_does_not_stall_runtime() {
+        // the test runtime has a single worker: anything blocking it delays the ticker
+        const MAX_STALL: Duration = Duration::from_millis(10);
+        let folder = std::env::temp_dir().join(format!("shai-sessions-{}", Uuid::new_v4()));
+        let store = FilePersistBackend::new(folder.clone());
+        let message = ChatMessage::User { content: ChatMessageContent::Text("x".repeat(1024)), name: None };
+        let trace = vec![message; 5 * 1024];
+
+        let (stop_tx, mut stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let ticker = tokio::spawn(async move {
+            let mut worst = Duration::ZERO;
+            let mut last = Instant::now();
+            loop {
+                tokio::select! {
+                    _ = &mut stop_rx => return worst,
+                    _ = tokio::time::sleep(Duration::from_millis(1)) => {}
+                }
+                worst = worst.max(last.elapsed());
+                last = Instant::now();
+            }
+        });
+
+        let size = SessionPersist::save_session_to(&store, "large", trace, &SessionAttributes::default(), vec![], vec![], PersistLimits::default(), 0)
+            .await
+            .unwrap();
+        let loaded = store.load("large").await.unwrap().unwrap();
+        stop_tx.send(()).unwrap();
+        let worst = ticker.await.unwrap();
+
+        assert!(size > 5 * 1024 * 1024);
+        assert_eq!(loaded.trace.len(), 5 * 1024);
+        assert!(worst < MAX_STALL, "timer stalled for {:?} while the session was saved", worst);
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+
 }
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use async_trait::async_trait;
+use azure_core::{error::ErrorKind as AzureErrorKind, request_options::Metadata, StatusCode};
+use azure_storage::StorageCredentials;
+use azure_storage_blobs::prelude::{ClientBuilder, ContainerClient};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use tracing::debug;
+
+use super::persist::{PersistBackend, PersistError, SessionData};
+
+/// Metadata key marking a soft-deleted session blob (value: RFC 3339 deletion date)
+const DELETED_AT_METADATA: &str = "shai_deleted_at";
+
+/// Sessions stored as `{session_id}.json` blobs in an Azure Blob Storage container
+pub struct AzureBlobPersistBackend {
+    container: ContainerClient,
+}
+
+impl AzureBlobPersistBackend {
+    pub fn new(account: String, access_key: String, container: String) -> Self {
+        let credentials = StorageCredentials::access_key(account.clone(), access_key);
+        Self {
+            container: ClientBuilder::new(account, credentials).container_client(container),
+        }
+    }
+
+    /// Configure from AZURE_STORAGE_ACCOUNT, AZURE_STORAGE_KEY and SHAI_AZURE_CONTAINER
+    pub fn from_env() -> Result<Self, PersistError> {
+        let var = |name: &str| std::env::var(name).map_err(|_| format!("{} is not set", name));
+        Ok(Self::new(
+            var("AZURE_STORAGE_ACCOUNT")?,
+            var("AZURE_STORAGE_KEY")?,
+            var("SHAI_AZURE_CONTAINER")?,
+        ))
+    }
+
+    fn blob_name(session_id: &str) -> String {
+        format!("{}.json", session_id)
+    }
+
+    /// Blob metadata, None if the blob does not exist
+    async fn metadata(&self, session_id: &str) -> Result<Option<HashMap<String, String>>, PersistError> {
+        let blob = self.container.blob_client(Self::blob_name(session_id));
+        match blob.get_metadata().await {
+            Ok(response) => Ok(Some(response.metadata)),
+            Err(e) if is_not_found(&e) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn set_deleted_at(&self, session_id: &str, deleted_at: Option<String>) -> Result<(), PersistError> {
+        let Some(current) = self.metadata(session_id).await? else {
+            return Err(format!("Session not found: {}", session_id).into());
+        };
+
+        // set_metadata replaces the whole set, keep the other entries
+        let mut metadata = Metadata::new();
+        for (key, value) in current.into_iter().filter(|(key, _)| key != DELETED_AT_METADATA) {
+            metadata.insert(key, value);
+        }
+        if let Some(deleted_at) = deleted_at {
+            metadata.insert(DELETED_AT_METADATA, deleted_at);
+        }
+
+        self.container
+            .blob_client(Self::blob_name(session_id))
+            .set_metadata(metadata)
+            .await?;
+        Ok(())
+    }
+}
+
+fn is_not_found(error: &azure_core::Error) -> bool {
+    matches!(error.kind(), AzureErrorKind::HttpResponse { status: StatusCode::NotFound, .. })
+}
+
+fn is_soft_deleted(metadata: &HashMap<String, String>) -> bool {
+    metadata.contains_key(DELETED_AT_METADATA)
+}
+
+/// Deletion date stored in the blob metadata (epoch for an unreadable value, so it gets purged)
+fn metadata_deleted_at(metadata: &HashMap<String, String>) -> Option<DateTime<Utc>> {
+    metadata.get(DELETED_AT_METADATA).map(|value| {
+        DateTime::parse_from_rfc3339(value)
+            .map(|date| date.with_timezone(&Utc))
+            .unwrap_or(DateTime::UNIX_EPOCH)
+    })
+}
+
+#[async_trait]
+impl PersistBackend for AzureBlobPersistBackend {
+    async fn save(&self, data: &SessionData) -> Result<(), PersistError> {
+        let json = serde_json::to_vec(data)?;
+        self.container
+            .blob_client(Self::blob_name(&data.session_id))
+            .put_block_blob(json)
+            .content_type("application/json")
+            .await?;
+
+        debug!("Session saved to Azure blob: {}", data.session_id);
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &str) -> Result<Option<SessionData>, PersistError> {
+        match self.metadata(session_id).await? {
+            Some(metadata) if !is_soft_deleted(&metadata) => {}
+            _ => return Ok(None),
+        }
+
+        match self.container.blob_client(Self::blob_name(session_id)).get_content().await {
+            Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+            Err(e) if is_not_found(&e) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list_sessions(&self, prefix: &str) -> Result<Vec<String>, PersistError> {
+        let mut pages = self.container
+            .list_blobs()
+            .prefix(prefix.to_string())
+            .include_metadata(true)
+            .into_stream();
+
+        let mut ids = Vec::new();
+        while let Some(page) = pages.next().await {
+            for blob in page?.blobs.blobs() {
+                let deleted = blob.metadata.as_ref().is_some_and(is_soft_deleted);
+                if let Some(id) = blob.name.strip_suffix(".json") {
+                    if !deleted {
+                        ids.push(id.to_string());
+                    }
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    async fn delete_session(&self, session_id: &str) -> Result<(), PersistError> {
+        match self.container.blob_client(Self::blob_name(session_id)).delete().await {
+            Ok(_) => {
+                debug!("Deleted session blob: {}", session_id);
+                Ok(())
+            }
+            Err(e) if is_not_found(&e) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn soft_delete_session(&self, session_id: &str) -> Result<(), PersistError> {
+        self.set_deleted_at(session_id, Some(Utc::now().to_rfc3339())).await
+    }
+
+    async fn restore_session(&self, session_id: &str) -> Result<(), PersistError> {
+        match self.metadata(session_id).await? {
+            Some(metadata) if is_soft_deleted(&metadata) => self.set_deleted_at(session_id, None).await,
+            _ => Err(format!("Session not in trash: {}", session_id).into()),
+        }
+    }
+
+    async fn deleted_at(&self, session_id: &str) -> Result<Option<DateTime<Utc>>, PersistError> {
+        Ok(self.metadata(session_id).await?.as_ref().and_then(metadata_deleted_at))
+    }
+
+    async fn list_soft_deleted(&self) -> Result<Vec<(String, DateTime<Utc>)>, PersistError> {
+        let mut pages = self.container
+            .list_blobs()
+            .include_metadata(true)
+            .into_stream();
+
+        let mut sessions = Vec::new();
+        while let Some(page) = pages.next().await {
+            for blob in page?.blobs.blobs() {
+                let deleted_at = blob.metadata.as_ref().and_then(metadata_deleted_at);
+                if let (Some(id), Some(deleted_at)) = (blob.name.strip_suffix(".json"), deleted_at) {
+                    sessions.push((id.to_string(), deleted_at));
+                }
+            }
+        }
+        Ok(sessions)
+    }
+}
@@ -0,0 +1,219 @@
+use std::time::Duration;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use redis::{aio::ConnectionManager, AsyncCommands, Client};
+use tokio::sync::OnceCell;
+use tracing::debug;
+
+use super::persist::{PersistBackend, PersistError, SessionData, TrashedSession};
+
+/// Key prefix of the stored sessions
+const SESSION_KEY_PREFIX: &str = "shai:session:";
+
+/// Key prefix of the soft-deleted sessions
+const TRASH_KEY_PREFIX: &str = "shai:trash:";
+
+/// Sessions stored as JSON strings under `shai:session:{session_id}` keys of a Redis server
+/// Soft-deleted sessions are moved to `shai:trash:{session_id}`, the optional TTL only applies to live sessions
+/// The server opens it for `SessionStoreConfig::Redis`, applications embedding a SessionManager hand
+/// it over with `SessionManager::with_store`
+pub struct RedisPersistBackend {
+    client: Client,
+    connection: OnceCell<ConnectionManager>,
+    ttl: Option<Duration>,
+}
+
+impl RedisPersistBackend {
+    pub fn new(url: &str, ttl: Option<Duration>) -> Result<Self, PersistError> {
+        Ok(Self {
+            client: Client::open(url)?,
+            connection: OnceCell::new(),
+            ttl,
+        })
+    }
+
+    /// Configure from SHAI_REDIS_URL and SHAI_REDIS_TTL_SECS (optional, 0 = no expiry)
+    pub fn from_env() -> Result<Self, PersistError> {
+        let url = std::env::var("SHAI_REDIS_URL").map_err(|_| "SHAI_REDIS_URL is not set")?;
+        let ttl = match std::env::var("SHAI_REDIS_TTL_SECS") {
+            Ok(secs) => secs
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid SHAI_REDIS_TTL_SECS: {}", secs))?,
+            Err(_) => 0,
+        };
+        Self::new(&url, Some(Duration::from_secs(ttl)).filter(|ttl| !ttl.is_zero()))
+    }
+
+    /// Connection shared by all calls, opened on first use and re-established by the manager
+    async fn connection(&self) -> Result<ConnectionManager, PersistError> {
+        let connection = self.connection
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await?;
+        Ok(connection.clone())
+    }
+
+    fn session_key(session_id: &str) -> String {
+        format!("{}{}", SESSION_KEY_PREFIX, session_id)
+    }
+
+    fn trash_key(session_id: &str) -> String {
+        format!("{}{}", TRASH_KEY_PREFIX, session_id)
+    }
+
+    async fn read_trashed(&self, session_id: &str) -> Result<Option<TrashedSession>, PersistError> {
+        let json: Option<String> = self.connection().await?.get(Self::trash_key(session_id)).await?;
+        Ok(json.map(|json| serde_json::from_str(&json)).transpose()?)
+    }
+
+    /// Ids of the keys starting with `key_prefix` followed by `prefix`
+    async fn scan_ids(&self, key_prefix: &str, prefix: &str) -> Result<Vec<String>, PersistError> {
+        let mut connection = self.connection().await?;
+        let pattern = format!("{}{}*", key_prefix, escape_pattern(prefix));
+        let mut keys = connection.scan_match::<_, String>(pattern).await?;
+
+        let mut ids = Vec::new();
+        while let Some(key) = keys.next_item().await {
+            if let Some(id) = key.strip_prefix(key_prefix) {
+                ids.push(id.to_string());
+            }
+        }
+        Ok(ids)
+    }
+}
+
+/// Escape the glob characters of a SCAN MATCH pattern
+fn escape_pattern(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[async_trait]
+impl PersistBackend for RedisPersistBackend {
+    async fn save(&self, data: &SessionData) -> Result<(), PersistError> {
+        let json = serde_json::to_string(data)?;
+        let key = Self::session_key(&data.session_id);
+        let mut connection = self.connection().await?;
+        match self.ttl {
+            Some(ttl) => connection.set_ex::<_, _, ()>(key, json, ttl.as_secs()).await?,
+            None => connection.set::<_, _, ()>(key, json).await?,
+        }
+
+        debug!("Session saved to Redis: {}", data.session_id);
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &str) -> Result<Option<SessionData>, PersistError> {
+        let json: Option<String> = self.connection().await?.get(Self::session_key(session_id)).await?;
+        Ok(json.map(|json| serde_json::from_str(&json)).transpose()?)
+    }
+
+    async fn list_sessions(&self, prefix: &str) -> Result<Vec<String>, PersistError> {
+        self.scan_ids(SESSION_KEY_PREFIX, prefix).await
+    }
+
+    async fn delete_session(&self, session_id: &str) -> Result<(), PersistError> {
+        self.connection().await?
+            .del::<_, ()>(vec![Self::session_key(session_id), Self::trash_key(session_id)])
+            .await?;
+        debug!("Deleted session from Redis: {}", session_id);
+        Ok(())
+    }
+
+    async fn soft_delete_session(&self, session_id: &str) -> Result<(), PersistError> {
+        let Some(session) = self.load(session_id).await? else {
+            return Err(format!("Session not found: {}", session_id).into());
+        };
+
+        let trashed = serde_json::to_string(&TrashedSession { deleted_at: Utc::now(), session })?;
+        redis::pipe()
+            .atomic()
+            .set(Self::trash_key(session_id), trashed).ignore()
+            .del(Self::session_key(session_id)).ignore()
+            .query_async::<()>(&mut self.connection().await?)
+            .await?;
+
+        debug!("Session moved to Redis trash: {}", session_id);
+        Ok(())
+    }
+
+    async fn restore_session(&self, session_id: &str) -> Result<(), PersistError> {
+        let Some(trashed) = self.read_trashed(session_id).await? else {
+            return Err(format!("Session not in trash: {}", session_id).into());
+        };
+
+        let json = serde_json::to_string(&trashed.session)?;
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        match self.ttl {
+            Some(ttl) => pipe.set_ex(Self::session_key(session_id), json, ttl.as_secs()).ignore(),
+            None => pipe.set(Self::session_key(session_id), json).ignore(),
+        };
+        pipe.del(Self::trash_key(session_id)).ignore()
+            .query_async::<()>(&mut self.connection().await?)
+            .await?;
+
+        debug!("Session restored from Redis trash: {}", session_id);
+        Ok(())
+    }
+
+    async fn deleted_at(&self, session_id: &str) -> Result<Option<DateTime<Utc>>, PersistError> {
+        Ok(self.read_trashed(session_id).await?.map(|trashed| trashed.deleted_at))
+    }
+
+    async fn list_soft_deleted(&self) -> Result<Vec<(String, DateTime<Utc>)>, PersistError> {
+        let mut sessions = Vec::new();
+        for id in self.scan_ids(TRASH_KEY_PREFIX, "").await? {
+            if let Some(trashed) = self.read_trashed(&id).await? {
+                sessions.push((id, trashed.deleted_at));
+            }
+        }
+        Ok(sessions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::session::{SessionAttributes, SessionManager, SessionManagerConfig, SessionPersist};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_scan_prefix_is_escaped() {
+        assert_eq!(escape_pattern("resp_"), "resp_");
+        assert_eq!(escape_pattern("a*b?[c]\\"), "a\\*b\\?\\[c\\]\\\\");
+        assert_eq!(RedisPersistBackend::session_key("resp_1"), "shai:session:resp_1");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Redis server, set SHAI_TEST_REDIS_URL"]
+    async fn test_redis_backend_roundtrip() {
+        let url = std::env::var("SHAI_TEST_REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
+        let backend = Arc::new(RedisPersistBackend::new(&url, None).unwrap());
+        let manager = SessionManager::new(SessionManagerConfig::default()).with_store(backend.clone());
+        let session_id = format!("test-{}", Uuid::new_v4());
+
+        let attributes = SessionAttributes { agent_name: Some("coder".to_string()), ..Default::default() };
+        SessionPersist::save_session_to(&*manager.store(), &session_id, vec![], &attributes, vec![], vec![], Default::default(), 0)
+            .await
+            .unwrap();
+        let loaded = backend.load(&session_id).await.unwrap().unwrap();
+        assert_eq!(loaded.attributes.agent_name.as_deref(), Some("coder"));
+        assert!(backend.list_sessions("test-").await.unwrap().contains(&session_id));
+
+        backend.soft_delete_session(&session_id).await.unwrap();
+        assert!(backend.load(&session_id).await.unwrap().is_none());
+        assert!(backend.deleted_at(&session_id).await.unwrap().is_some());
+        backend.restore_session(&session_id).await.unwrap();
+        assert!(backend.load(&session_id).await.unwrap().is_some());
+
+        backend.delete_session(&session_id).await.unwrap();
+        assert!(backend.load(&session_id).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,262 @@
+use std::path::{Path, PathBuf};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use openai_dive::v1::resources::chat::ChatMessage;
+use serde::{Deserialize, Serialize};
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions};
+use tokio::sync::OnceCell;
+use tracing::debug;
+
+use crate::run::RunSummary;
+use super::persist::{PersistBackend, PersistError, SessionAttributes, SessionData};
+
+/// Schema of the session database, applied when the pool is opened
+static MIGRATOR: Migrator = sqlx::migrate!("migrations/sqlite");
+
+/// Connections kept open to the database
+const MAX_CONNECTIONS: u32 = 4;
+
+/// Session fields stored next to the trace, as JSON
+#[derive(Serialize, Deserialize)]
+struct SessionFields {
+    #[serde(flatten)]
+    attributes: SessionAttributes,
+    #[serde(default)]
+    input_items: Vec<serde_json::Value>,
+    #[serde(default)]
+    run_summaries: Vec<RunSummary>,
+    #[serde(default)]
+    dropped_messages: usize,
+}
+
+type SessionRow = (String, DateTime<Utc>, DateTime<Utc>, Vec<u8>, String);
+
+/// Sessions stored as rows of a `sessions` table in a SQLite database
+/// Soft-deleted sessions keep their row with a `deleted_at` date
+pub struct SqlitePersistBackend {
+    path: PathBuf,
+    pool: OnceCell<SqlitePool>,
+}
+
+impl SqlitePersistBackend {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), pool: OnceCell::new() }
+    }
+
+    /// Configure from SHAI_SESSION_DB_PATH
+    pub fn from_env() -> Result<Self, PersistError> {
+        let path = std::env::var("SHAI_SESSION_DB_PATH").map_err(|_| "SHAI_SESSION_DB_PATH is not set")?;
+        Ok(Self::new(path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Pool shared by all calls, the database is created and migrated on first use
+    async fn pool(&self) -> Result<&SqlitePool, PersistError> {
+        self.pool
+            .get_or_try_init(|| async {
+                if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let options = SqliteConnectOptions::new()
+                    .filename(&self.path)
+                    .create_if_missing(true)
+                    .journal_mode(SqliteJournalMode::Wal);
+                let pool = SqlitePoolOptions::new().max_connections(MAX_CONNECTIONS).connect_with(options).await?;
+                MIGRATOR.run(&pool).await?;
+                debug!("Session database ready: {}", self.path.display());
+                Ok::<_, PersistError>(pool)
+            })
+            .await
+    }
+}
+
+/// LIKE pattern matching the ids starting with `prefix`
+fn prefix_pattern(prefix: &str) -> String {
+    let mut pattern = String::with_capacity(prefix.len() + 1);
+    for c in prefix.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+#[async_trait]
+impl PersistBackend for SqlitePersistBackend {
+    async fn save(&self, data: &SessionData) -> Result<(), PersistError> {
+        let trace = serde_json::to_vec(&data.trace)?;
+        let metadata = serde_json::to_string(&SessionFields {
+            attributes: data.attributes.clone(),
+            input_items: data.input_items.clone(),
+            run_summaries: data.run_summaries.clone(),
+            dropped_messages: data.dropped_messages,
+        })?;
+
+        let mut transaction = self.pool().await?.begin().await?;
+        sqlx::query(
+            "INSERT INTO sessions (session_id, created_at, updated_at, trace, metadata) VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT (session_id) DO UPDATE SET
+                updated_at = excluded.updated_at, trace = excluded.trace, metadata = excluded.metadata",
+        )
+        .bind(&data.session_id)
+        .bind(data.created_at)
+        .bind(data.updated_at)
+        .bind(trace)
+        .bind(metadata)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+
+        debug!("Session saved to SQLite: {}", data.session_id);
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &str) -> Result<Option<SessionData>, PersistError> {
+        let row: Option<SessionRow> = sqlx::query_as(
+            "SELECT session_id, created_at, updated_at, trace, metadata FROM sessions WHERE session_id = ? AND deleted_at IS NULL",
+        )
+        .bind(session_id)
+        .fetch_optional(self.pool().await?)
+        .await?;
+
+        let Some((session_id, created_at, updated_at, trace, metadata)) = row else {
+            return Ok(None);
+        };
+        let trace: Vec<ChatMessage> = serde_json::from_slice(&trace)?;
+        let metadata: SessionFields = serde_json::from_str(&metadata)?;
+        Ok(Some(SessionData {
+            session_id,
+            created_at,
+            updated_at,
+            trace,
+            attributes: metadata.attributes,
+            input_items: metadata.input_items,
+            run_summaries: metadata.run_summaries,
+            dropped_messages: metadata.dropped_messages,
+        }))
+    }
+
+    async fn list_sessions(&self, prefix: &str) -> Result<Vec<String>, PersistError> {
+        let ids: Vec<(String,)> = sqlx::query_as(
+            "SELECT session_id FROM sessions WHERE session_id LIKE ? ESCAPE '\\' AND deleted_at IS NULL ORDER BY session_id",
+        )
+        .bind(prefix_pattern(prefix))
+        .fetch_all(self.pool().await?)
+        .await?;
+        Ok(ids.into_iter().map(|(id,)| id).collect())
+    }
+
+    async fn delete_session(&self, session_id: &str) -> Result<(), PersistError> {
+        sqlx::query("DELETE FROM sessions WHERE session_id = ?")
+            .bind(session_id)
+            .execute(self.pool().await?)
+            .await?;
+        debug!("Deleted session from SQLite: {}", session_id);
+        Ok(())
+    }
+
+    async fn soft_delete_session(&self, session_id: &str) -> Result<(), PersistError> {
+        let deleted = sqlx::query("UPDATE sessions SET deleted_at = ? WHERE session_id = ? AND deleted_at IS NULL")
+            .bind(Utc::now())
+            .bind(session_id)
+            .execute(self.pool().await?)
+            .await?;
+        if deleted.rows_affected() == 0 {
+            return Err(format!("Session not found: {}", session_id).into());
+        }
+
+        debug!("Session moved to SQLite trash: {}", session_id);
+        Ok(())
+    }
+
+    async fn restore_session(&self, session_id: &str) -> Result<(), PersistError> {
+        let restored = sqlx::query("UPDATE sessions SET deleted_at = NULL WHERE session_id = ? AND deleted_at IS NOT NULL")
+            .bind(session_id)
+            .execute(self.pool().await?)
+            .await?;
+        if restored.rows_affected() == 0 {
+            return Err(format!("Session not in trash: {}", session_id).into());
+        }
+
+        debug!("Session restored from SQLite trash: {}", session_id);
+        Ok(())
+    }
+
+    async fn deleted_at(&self, session_id: &str) -> Result<Option<DateTime<Utc>>, PersistError> {
+        let row: Option<(Option<DateTime<Utc>>,)> = sqlx::query_as("SELECT deleted_at FROM sessions WHERE session_id = ?")
+            .bind(session_id)
+            .fetch_optional(self.pool().await?)
+            .await?;
+        Ok(row.and_then(|(deleted_at,)| deleted_at))
+    }
+
+    async fn list_soft_deleted(&self) -> Result<Vec<(String, DateTime<Utc>)>, PersistError> {
+        let sessions = sqlx::query_as("SELECT session_id, deleted_at FROM sessions WHERE deleted_at IS NOT NULL")
+            .fetch_all(self.pool().await?)
+            .await?;
+        Ok(sessions)
+    }
+
+    async fn list_updated(&self) -> Result<Vec<(String, DateTime<Utc>)>, PersistError> {
+        let sessions = sqlx::query_as("SELECT session_id, updated_at FROM sessions WHERE deleted_at IS NULL")
+            .fetch_all(self.pool().await?)
+            .await?;
+        Ok(sessions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn session(id: &str) -> SessionData {
+        SessionData {
+            session_id: id.to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            trace: vec![],
+            attributes: SessionAttributes { agent_name: Some("coder".to_string()), ..Default::default() },
+            input_items: vec![serde_json::json!({"role": "user", "content": id})],
+            run_summaries: vec![],
+            dropped_messages: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_backend_roundtrip() {
+        let folder = std::env::temp_dir().join(format!("shai-sessions-{}", Uuid::new_v4()));
+        let backend = SqlitePersistBackend::new(folder.join("sessions.db"));
+
+        for id in ["alpha-1", "alpha-2", "alpha_x", "beta-1"] {
+            backend.save(&session(id)).await.unwrap();
+        }
+        // saving again replaces the row
+        backend.save(&session("beta-1")).await.unwrap();
+
+        assert_eq!(backend.list_sessions("alpha-").await.unwrap(), vec!["alpha-1", "alpha-2"]);
+        let loaded = backend.load("beta-1").await.unwrap().unwrap();
+        assert_eq!(loaded.attributes.agent_name.as_deref(), Some("coder"));
+        assert_eq!(loaded.input_items, vec![serde_json::json!({"role": "user", "content": "beta-1"})]);
+
+        backend.soft_delete_session("alpha-1").await.unwrap();
+        assert!(backend.load("alpha-1").await.unwrap().is_none());
+        assert!(backend.deleted_at("alpha-1").await.unwrap().is_some());
+        assert_eq!(backend.list_soft_deleted().await.unwrap().len(), 1);
+        assert_eq!(backend.list_updated().await.unwrap().len(), 3);
+        backend.restore_session("alpha-1").await.unwrap();
+        assert!(backend.load("alpha-1").await.unwrap().is_some());
+        assert!(backend.restore_session("alpha-1").await.is_err());
+
+        backend.delete_session("beta-1").await.unwrap();
+        assert!(backend.load("beta-1").await.unwrap().is_none());
+
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+}
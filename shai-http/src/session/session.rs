@@ -1,19 +1,76 @@
-use shai_core::agent::{AgentController, AgentError, AgentEvent};
-use openai_dive::v1::resources::chat::ChatMessage;
-use std::sync::Arc;
-use tokio::sync::{broadcast::Receiver, Mutex};
+use chrono::{DateTime, Utc};
+use shai_core::agent::{AgentController, AgentError, AgentEvent, PublicAgentState};
+use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast::Receiver, Mutex, OwnedMutexGuard};
 use tokio::task::JoinHandle;
-use tracing::info;
+use tracing::{info, info_span, warn, Instrument};
+use crate::features::Features;
+use crate::quota::{QuotaUsage, SessionQuota, SessionQuotas};
+use crate::run::RunSummary;
 use crate::session::logger::colored_session_id;
+use crate::workspace::{WorkspaceChangeLog, WorkspaceChanges, WorkspaceConfig, WorkspaceTracker};
+#[cfg(feature = "git")]
+use crate::git::{revert_note, Checkpoint, CheckpointCommit, CheckpointError, GitWorkspace};
 
-use super::RequestLifecycle;
+use super::lifecycle::{save_session, save_trace};
+use super::{PersistBackend, RequestLifecycle, SessionAttributes, SessionData, SessionMetadata, SessionPersist, TranscriptLog};
 
+/// Summaries of the requests run on a session, shared with the running request
+pub type RunSummaryLog = Arc<StdMutex<Vec<RunSummary>>>;
 
 /// Represents a single HTTP request session with automatic lifecycle management
 pub struct RequestSession {
     pub controller: AgentController,
     pub event_rx: Receiver<AgentEvent>,
-    pub lifecycle: RequestLifecycle
+    pub lifecycle: RequestLifecycle,
+    /// Workspace snapshot taken before the request (when tracking is enabled)
+    pub workspace: Option<WorkspaceTracker>,
+    /// Session log the summary of the request is appended to
+    pub run_summaries: RunSummaryLog,
+    /// Feature flags resolved for the request
+    pub features: Features,
+    /// Workspace repository held until the request's changes are committed (when checkpoints are enabled)
+    #[cfg(feature = "git")]
+    pub checkpoint: Option<Checkpoint>,
+}
+
+/// How requests wait for a session processing another one
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestQueue {
+    /// Requests that may wait at the same time, the next ones are refused (None = unlimited)
+    pub max_depth: Option<usize>,
+    /// Longest wait before a request is refused (None = waits until the session is free)
+    pub timeout: Option<Duration>,
+}
+
+impl RequestQueue {
+    /// Seconds a refused client is told to wait before retrying
+    fn retry_after_secs(&self) -> u64 {
+        self.timeout.map_or(1, |timeout| timeout.as_secs().max(1))
+    }
+}
+
+/// Put the system prompt of the server at the head of a conversation without a system message
+/// Returns false when the conversation already has one
+pub(super) fn prepend_system_prompt(trace: &mut Vec<ChatMessage>, prompt: &str) -> bool {
+    if trace.iter().any(|message| matches!(message, ChatMessage::System { .. })) {
+        return false;
+    }
+    trace.insert(0, ChatMessage::System { content: ChatMessageContent::Text(prompt.to_string()), name: None });
+    true
+}
+
+/// Counts a request in the queue of a session until it gets the session or gives up
+struct QueuedRequest(Arc<AtomicUsize>);
+
+impl Drop for QueuedRequest {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 /// A single agent session - represents one running agent instance
@@ -23,9 +80,34 @@ pub struct RequestSession {
 /// - In ephemeral mode (ephemeral=true), the entire session stops and is deleted once the query ends or the client disconnect
 pub struct AgentSession {
     controller: Arc<Mutex<AgentController>>,
+    /// Controller used without the lock, for read-only queries while a request runs
+    observer: AgentController,
+    requests_served: AtomicU64,
+    queue: RequestQueue,
+    /// Requests waiting for the running one to end
+    queued: Arc<AtomicUsize>,
+    /// System prompt of the server not yet given to the agent, added to the first request unless it brings its own
+    system_prompt: StdMutex<Option<String>>,
     event_rx: Receiver<AgentEvent>,
     logging_task: JoinHandle<()>,
     agent_task: JoinHandle<()>,
+    created_at: StdMutex<DateTime<Utc>>,
+    last_activity: StdMutex<Instant>,
+    ttl: StdMutex<Option<Duration>>,
+    user: StdMutex<Option<String>>,
+    attributes: SessionAttributes,
+    /// Metadata of the clients, starts as the one of the attributes and follows their updates
+    metadata: StdMutex<SessionMetadata>,
+    input_items: Arc<StdMutex<Vec<serde_json::Value>>>,
+    run_summaries: RunSummaryLog,
+    workspace: Option<WorkspaceConfig>,
+    workspace_changes: WorkspaceChangeLog,
+    quota: Arc<SessionQuota>,
+    transcript: Arc<TranscriptLog>,
+    /// Store the session is saved to after each request
+    store: Arc<dyn PersistBackend>,
+    #[cfg(feature = "git")]
+    git: Option<Arc<GitWorkspace>>,
 
     pub session_id: String,
     pub agent_name: String,
@@ -41,20 +123,160 @@ impl AgentSession {
         logging_task: JoinHandle<()>,
         agent_name: Option<String>,
         ephemeral: bool,
+        attributes: SessionAttributes,
     ) -> Self {
         let agent_name_display = agent_name.unwrap_or_else(|| "default".to_string());
 
         Self {
+            observer: controller.clone(),
+            requests_served: AtomicU64::new(0),
+            queue: RequestQueue::default(),
+            queued: Arc::new(AtomicUsize::new(0)),
+            system_prompt: StdMutex::new(None),
             controller: Arc::new(Mutex::new(controller)),
             event_rx,
             logging_task,
             agent_task,
+            created_at: StdMutex::new(Utc::now()),
+            last_activity: StdMutex::new(Instant::now()),
+            ttl: StdMutex::new(None),
+            user: StdMutex::new(None),
+            metadata: StdMutex::new(attributes.metadata.clone()),
+            attributes,
+            input_items: Arc::new(StdMutex::new(Vec::new())),
+            run_summaries: Arc::new(StdMutex::new(Vec::new())),
+            workspace: None,
+            workspace_changes: WorkspaceChangeLog::default(),
+            quota: Arc::new(SessionQuota::new(&session_id, SessionQuotas::default())),
+            transcript: Arc::new(TranscriptLog::default()),
+            store: SessionPersist::backend(),
+            #[cfg(feature = "git")]
+            git: None,
             session_id,
             agent_name: agent_name_display,
             ephemeral: ephemeral,
         }
     }
 
+    /// Workspace snapshot settings of the requests tracking their changes (None = never tracked)
+    pub fn with_workspace(mut self, workspace: Option<WorkspaceConfig>) -> Self {
+        self.workspace = workspace;
+        self
+    }
+
+    /// Bound the requests waiting for the session and their wait (default: unbounded)
+    pub fn with_queue(mut self, queue: RequestQueue) -> Self {
+        self.queue = queue;
+        self
+    }
+
+    /// Save the session to this store (default: the store of `SessionPersist`)
+    pub fn with_store(mut self, store: Arc<dyn PersistBackend>) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// Open the conversation with this system prompt, unless the first request sends a system message
+    pub fn with_system_prompt(self, system_prompt: Option<String>) -> Self {
+        *self.system_prompt.lock().unwrap() = system_prompt;
+        self
+    }
+
+    /// Requests waiting for the running one to end
+    pub fn queued_requests(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Take the session for a request, after the requests queued before it
+    /// Refused with SessionBusy when the queue is full or the wait goes over the queue timeout
+    async fn acquire(&self, http_request_id: &str) -> Result<OwnedMutexGuard<AgentController>, AgentError> {
+        if let Ok(guard) = self.controller.clone().try_lock_owned() {
+            return Ok(guard);
+        }
+
+        let position = self.queued.fetch_add(1, Ordering::SeqCst) + 1;
+        let _queued = QueuedRequest(self.queued.clone());
+        if self.queue.max_depth.is_some_and(|max| position > max) {
+            warn!("[{}] - {} refused, {} requests already waiting", http_request_id, colored_session_id(&self.session_id), position - 1);
+            return Err(AgentError::SessionBusy {
+                message: format!("Session {} is processing a request and its queue is full", self.session_id),
+                retry_after_secs: self.queue.retry_after_secs(),
+            });
+        }
+
+        info!("[{}] - {} waiting for the running request (position {})", http_request_id, colored_session_id(&self.session_id), position);
+        let lock = self.controller.clone().lock_owned();
+        match self.queue.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, lock).await.map_err(|_| AgentError::SessionBusy {
+                message: format!("Session {} is still processing a request after {}s", self.session_id, timeout.as_secs()),
+                retry_after_secs: self.queue.retry_after_secs(),
+            }),
+            None => Ok(lock.await),
+        }
+    }
+
+    /// Track the session resource consumption with this quota (default: no caps)
+    pub fn with_quota(mut self, quota: Arc<SessionQuota>) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Recent events of the session, fed by the logging task
+    pub fn with_transcript(mut self, transcript: Arc<TranscriptLog>) -> Self {
+        self.transcript = transcript;
+        self
+    }
+
+    /// Recent events of the session and their live feed, rendered by the tail endpoint
+    pub fn transcript(&self) -> &Arc<TranscriptLog> {
+        &self.transcript
+    }
+
+    /// Commit the workspace at the end of each request (None = disabled)
+    #[cfg(feature = "git")]
+    pub fn with_git(mut self, git: Option<Arc<GitWorkspace>>) -> Self {
+        self.git = git;
+        self
+    }
+
+    #[cfg(feature = "git")]
+    pub fn git(&self) -> Option<&Arc<GitWorkspace>> {
+        self.git.as_ref()
+    }
+
+    /// Reset the workspace to a commit and tell the agent with a system note in its trace
+    /// Waits for the running request (and its commit) to end
+    #[cfg(feature = "git")]
+    pub async fn revert_workspace(&self, http_request_id: &str, commit: &str) -> Result<CheckpointCommit, CheckpointError> {
+        let git = self.git.as_ref().ok_or(CheckpointError::Disabled)?;
+        let controller_guard = self.controller.clone().lock_owned().await;
+        self.touch();
+
+        let target = git.revert(commit).await?;
+        info!("[{}] - {} workspace reverted to {}", http_request_id, colored_session_id(&self.session_id), target.id);
+        controller_guard.append_trace(vec![revert_note(&target)]).await?;
+        Ok(target)
+    }
+
+    pub fn quota(&self) -> &Arc<SessionQuota> {
+        &self.quota
+    }
+
+    /// Quota consumption of the session
+    pub fn quota_usage(&self) -> QuotaUsage {
+        self.quota.usage()
+    }
+
+    /// LLM tokens the session may still consume (None = no token budget)
+    pub fn remaining_budget(&self) -> Option<u64> {
+        self.quota.tokens().remaining()
+    }
+
+    /// Workspace changes made by a recent request
+    pub fn workspace_changes(&self, http_request_id: &str) -> Option<WorkspaceChanges> {
+        self.workspace_changes.get(http_request_id)
+    }
+
     /// Terminate a session
     pub async fn cancel(&self, http_request_id: &String)  -> Result<(), AgentError> {
         let ctrl = self.controller.clone().lock_owned().await;
@@ -62,6 +284,123 @@ impl AgentSession {
         ctrl.terminate().await
     }
 
+    /// Terminate the agent without waiting for the running request, which ends on the termination
+    pub async fn terminate_now(&self, http_request_id: &String) -> Result<(), AgentError> {
+        info!("[{}] - {} terminating session", http_request_id, colored_session_id(&self.session_id));
+        self.observer.terminate().await
+    }
+
+    /// True once the agent task ended
+    pub fn is_stopped(&self) -> bool {
+        self.agent_task.is_finished()
+    }
+
+    /// Wait for the agent task to end, aborted when it is still running after `timeout`
+    /// Returns false if it had to be aborted
+    pub async fn wait_stopped(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !self.is_stopped() {
+            if Instant::now() >= deadline {
+                warn!("{} agent still running after {}s, aborting it", colored_session_id(&self.session_id), timeout.as_secs());
+                self.agent_task.abort();
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        true
+    }
+
+    /// Save and terminate an idle session, returns false (nothing done) while a request holds it
+    /// The lock is kept until the agent is terminated, a request arriving meanwhile finds it gone
+    pub async fn evict(&self) -> Result<bool, AgentError> {
+        let Ok(ctrl) = self.controller.clone().try_lock_owned() else {
+            return Ok(false);
+        };
+        save_session(
+            self.store.clone(),
+            ctrl.clone(),
+            self.session_id.clone(),
+            self.current_attributes(),
+            self.input_items(),
+            self.run_summaries(),
+            self.quota.clone(),
+        ).await;
+        ctrl.terminate().await?;
+        Ok(true)
+    }
+
+    /// Save a session a request still holds, as it is now: its live trace without the tool calls
+    /// still waiting for their results, so that it can be resumed. Used before terminating it
+    pub async fn save_running(&self) -> Result<(), AgentError> {
+        let trace = without_pending_tool_calls(self.live_trace().await?);
+        save_trace(
+            self.store.clone(),
+            self.session_id.clone(),
+            trace,
+            self.current_attributes(),
+            self.input_items(),
+            self.run_summaries(),
+            self.quota.clone(),
+        ).await;
+        Ok(())
+    }
+
+    /// Run tools without asking (sudo) or wait for the permission of the client, waits for the running request
+    pub async fn set_sudo(&self, enabled: bool) -> Result<bool, AgentError> {
+        let ctrl = self.controller.clone().lock_owned().await;
+        match enabled {
+            true => ctrl.sudo().await,
+            false => ctrl.no_sudo().await,
+        }
+    }
+
+    /// Number of messages in the trace, None while a request is running (the trace is not settled)
+    pub async fn message_count(&self) -> Option<usize> {
+        self.settled_trace().await.map(|trace| trace.len())
+    }
+
+    /// Trace of the agent, None while a request is running (the trace is not settled)
+    pub async fn settled_trace(&self) -> Option<Vec<ChatMessage>> {
+        let ctrl = self.controller.clone().try_lock_owned().ok()?;
+        ctrl.get_trace().await.ok()
+    }
+
+    /// Trace of the agent as it is now, also while a request is running (without the lock)
+    pub async fn live_trace(&self) -> Result<Vec<ChatMessage>, AgentError> {
+        self.observer.get_trace().await
+    }
+
+    /// The session as it would be persisted now, None while a request is running
+    pub async fn snapshot(&self) -> Option<SessionData> {
+        let trace = self.settled_trace().await?;
+        Some(SessionData {
+            session_id: self.session_id.clone(),
+            created_at: self.created_at(),
+            updated_at: Utc::now(),
+            trace,
+            attributes: self.current_attributes(),
+            input_items: self.input_items(),
+            run_summaries: self.run_summaries(),
+            dropped_messages: self.quota.dropped_messages(),
+        })
+    }
+
+    /// Current state of the agent, answered while a request runs (None = the agent is gone)
+    pub async fn state(&self) -> Option<PublicAgentState> {
+        self.observer.get_state().await.ok()
+    }
+
+    /// Requests run on the session, the ones of a restored session included
+    pub fn requests_served(&self) -> u64 {
+        self.requests_served.load(Ordering::Relaxed)
+    }
+
+    /// Trace of the agent, waits for the request in progress to end
+    pub async fn trace(&self) -> Result<Vec<ChatMessage>, AgentError> {
+        let ctrl = self.controller.clone().lock_owned().await;
+        ctrl.get_trace().await
+    }
+
     /// Subscribe to events from this session (read-only, non-blocking)
     /// Used for GET /v1/responses/{response_id} to observe an ongoing session
     pub fn watch(&self) -> Receiver<AgentEvent> {
@@ -70,23 +409,187 @@ impl AgentSession {
 
     /// Handle a request for this agent session
     /// Returns a RequestSession that manages the lifecycle
-    pub async fn handle_request(&self, http_request_id: &String, trace: Vec<ChatMessage>) -> Result<RequestSession, AgentError> {
-        let controller_guard = self.controller.clone().lock_owned().await;
+    /// The request's feature flags decide whether its workspace changes are tracked and committed
+    pub async fn handle_request(&self, http_request_id: &String, mut trace: Vec<ChatMessage>, features: Features) -> Result<RequestSession, AgentError> {
+        let controller_guard = self.acquire(http_request_id).await?;
+        self.touch();
         controller_guard.wait_turn(None).await?;
+        let system_prompt = self.system_prompt.lock().unwrap().clone();
+        if let Some(prompt) = &system_prompt {
+            prepend_system_prompt(&mut trace, prompt);
+        }
+        // Refused before the agent sees the messages, the trace stays as it was
+        self.quota.check_trace(&trace)?;
+        self.quota.check_tokens()?;
         info!("[{}] - {} handling request", http_request_id, colored_session_id(&self.session_id));
 
-        controller_guard.send_trace(trace).await?;
+        let workspace = match &self.workspace {
+            Some(config) if features.workspace_tracking => Some(WorkspaceTracker::start(config.clone(), http_request_id.clone(), self.workspace_changes.clone()).await),
+            _ => None,
+        };
+
+        // Taken before the agent runs so that the previous request's commit is done
+        #[cfg(feature = "git")]
+        let checkpoint = match &self.git {
+            Some(git) if features.git_checkpoints => Some(git.begin(http_request_id.clone()).await),
+            _ => None,
+        };
+
+        self.transcript.record_user_input(&trace);
+        // Parent of the brain step and tool call spans of the agent, under the HTTP request span
+        let span = info_span!("agent.request", request_id = %http_request_id, session_id = %self.session_id, agent_name = %self.agent_name);
+        controller_guard.send_trace(trace).instrument(span).await?;
+        if system_prompt.is_some() {
+            self.system_prompt.lock().unwrap().take();
+        }
+        self.requests_served.fetch_add(1, Ordering::Relaxed);
 
         let event_rx = self.event_rx.resubscribe();
         let controller = controller_guard.clone();
-        let lifecycle = RequestLifecycle::new(self.ephemeral, controller_guard, http_request_id.clone(), self.session_id.clone());
+        let lifecycle = RequestLifecycle::new(
+            self.ephemeral,
+            controller_guard,
+            http_request_id.clone(),
+            self.session_id.clone(),
+            self.current_attributes(),
+            self.input_items.clone(),
+            self.run_summaries.clone(),
+            self.quota.clone(),
+            self.store.clone(),
+        );
 
-        Ok(RequestSession{controller, event_rx, lifecycle})
+        Ok(RequestSession {
+            controller,
+            event_rx,
+            lifecycle,
+            workspace,
+            run_summaries: self.run_summaries.clone(),
+            features,
+            #[cfg(feature = "git")]
+            checkpoint,
+        })
     }
 
     pub fn is_ephemeral(&self) -> bool {
         self.ephemeral
     }
+
+    /// When the session was first created (kept across restores from storage)
+    pub fn created_at(&self) -> DateTime<Utc> {
+        *self.created_at.lock().unwrap()
+    }
+
+    /// Restore the creation date of a persisted session
+    pub fn record_created_at(&self, created_at: DateTime<Utc>) {
+        *self.created_at.lock().unwrap() = created_at;
+    }
+
+    /// Mark the session as active now
+    pub fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    /// Time elapsed since the last request on this session
+    pub fn idle_for(&self) -> Duration {
+        self.last_activity.lock().unwrap().elapsed()
+    }
+
+    /// Per-session TTL overriding the manager-wide TTL (None = use the global TTL)
+    pub fn ttl(&self) -> Option<Duration> {
+        *self.ttl.lock().unwrap()
+    }
+
+    pub fn set_ttl(&self, ttl: Option<Duration>) {
+        *self.ttl.lock().unwrap() = ttl;
+    }
+
+    /// End-user the session acts for (OpenAI `user` field), None if not provided
+    pub fn user(&self) -> Option<String> {
+        self.user.lock().unwrap().clone()
+    }
+
+    pub fn set_user(&self, user: Option<String>) {
+        *self.user.lock().unwrap() = user;
+    }
+
+    /// Settings the session was created with
+    pub fn attributes(&self) -> &SessionAttributes {
+        &self.attributes
+    }
+
+    /// Client the session is counted against (None = not counted)
+    pub fn owner(&self) -> Option<&str> {
+        self.attributes.overrides.owner.as_deref()
+    }
+
+    /// Settings of the session with its metadata as it is now, as they are persisted
+    pub(crate) fn current_attributes(&self) -> SessionAttributes {
+        SessionAttributes { metadata: self.metadata(), ..self.attributes.clone() }
+    }
+
+    /// Owner, tags and custom fields of the session
+    pub fn metadata(&self) -> SessionMetadata {
+        self.metadata.lock().unwrap().clone()
+    }
+
+    /// Apply the metadata of a request, saved with the session at the end of its next request
+    pub fn merge_metadata(&self, update: SessionMetadata) {
+        self.metadata.lock().unwrap().merge(update);
+    }
+
+    /// Session environment with values hidden, for listings and logs
+    pub fn masked_env(&self) -> HashMap<String, String> {
+        self.attributes.env.keys().map(|k| (k.clone(), "***".to_string())).collect()
+    }
+
+    /// Response API input items received by this session, in order
+    pub fn input_items(&self) -> Vec<serde_json::Value> {
+        self.input_items.lock().unwrap().clone()
+    }
+
+    /// Append the input items of a request (persisted with the session)
+    pub fn record_input_items(&self, items: Vec<serde_json::Value>) {
+        self.input_items.lock().unwrap().extend(items);
+    }
+
+    /// Summaries of the requests run on this session, in order
+    pub fn run_summaries(&self) -> Vec<RunSummary> {
+        self.run_summaries.lock().unwrap().clone()
+    }
+
+    /// Restore the summaries of a persisted session
+    pub fn record_run_summaries(&self, summaries: Vec<RunSummary>) {
+        // every run leaves a summary, they count the requests served before the restore
+        self.requests_served.fetch_add(summaries.len() as u64, Ordering::Relaxed);
+        // and the tokens they consumed stay charged to the budget
+        for summary in &summaries {
+            self.quota.tokens().record(summary.input_tokens, summary.output_tokens);
+        }
+        self.run_summaries.lock().unwrap().extend(summaries);
+    }
+
+    /// True while a request holds the controller lock
+    pub fn is_busy(&self) -> bool {
+        self.controller.try_lock().is_err()
+    }
+}
+
+/// Trace cut before the last tool calls of the agent when they did not all get their result
+fn without_pending_tool_calls(mut trace: Vec<ChatMessage>) -> Vec<ChatMessage> {
+    let last_calls = trace.iter().enumerate().rev().find_map(|(position, message)| match message {
+        ChatMessage::Assistant { tool_calls: Some(calls), .. } if !calls.is_empty() => Some((position, calls)),
+        _ => None,
+    });
+    let Some((position, calls)) = last_calls else {
+        return trace;
+    };
+    let answered = calls.iter().all(|call| {
+        trace[position + 1..].iter().any(|message| matches!(message, ChatMessage::Tool { tool_call_id, .. } if *tool_call_id == call.id))
+    });
+    if !answered {
+        trace.truncate(position);
+    }
+    trace
 }
 
 impl Drop for AgentSession {
@@ -0,0 +1,148 @@
+use std::sync::Arc;
+use tracing::info;
+
+use crate::quota::QuotaKind;
+use crate::session::tool_stats::ToolCallOutcome;
+use crate::session::logger::colored_session_id;
+
+/// Receives session lifecycle notifications from the SessionManager
+/// Implementations must be cheap and non-blocking, they are called inline
+pub trait SessionEventSink: Send + Sync {
+    /// A new session was created (fresh or restored from disk)
+    fn on_session_created(&self, id: &str);
+
+    /// A session was removed from the manager
+    fn on_session_destroyed(&self, id: &str, duration_secs: f64);
+
+    /// A request on the session reached a terminal point (paused or completed)
+    fn on_request_completed(&self, id: &str, success: bool);
+
+    /// The model replied with empty messages that were retried
+    /// `recovered` is false when the retries were exhausted (empty_completion error)
+    fn on_empty_completion_retries(&self, _id: &str, _retries: u32, _recovered: bool) {}
+
+    /// An operation of the session was refused because it went over a quota
+    fn on_quota_exceeded(&self, _id: &str, _quota: QuotaKind) {}
+
+    /// A tool call of the session completed
+    fn on_tool_call(&self, _id: &str, _tool: &str, _outcome: ToolCallOutcome, _duration_secs: f64, _output_bytes: u64) {}
+
+    /// The agent of the session emitted an event, `kind` is its variant name (e.g. "ToolCallStarted")
+    fn on_agent_event(&self, _id: &str, _kind: &'static str) {}
+}
+
+/// Sink that writes lifecycle events to the tracing log
+#[derive(Default)]
+pub struct LoggingEventSink;
+
+impl SessionEventSink for LoggingEventSink {
+    fn on_session_created(&self, id: &str) {
+        info!("{} - session created", colored_session_id(id));
+    }
+
+    fn on_session_destroyed(&self, id: &str, duration_secs: f64) {
+        info!("{} - session destroyed after {:.1}s", colored_session_id(id), duration_secs);
+    }
+
+    fn on_request_completed(&self, id: &str, success: bool) {
+        info!("{} - request completed success={}", colored_session_id(id), success);
+    }
+
+    fn on_empty_completion_retries(&self, id: &str, retries: u32, recovered: bool) {
+        info!("{} - empty completion retried {} times recovered={}", colored_session_id(id), retries, recovered);
+    }
+}
+
+/// Sink that fans out every event to a list of sinks
+pub struct CompositeEventSink(pub Vec<Arc<dyn SessionEventSink>>);
+
+impl SessionEventSink for CompositeEventSink {
+    fn on_session_created(&self, id: &str) {
+        for sink in &self.0 {
+            sink.on_session_created(id);
+        }
+    }
+
+    fn on_session_destroyed(&self, id: &str, duration_secs: f64) {
+        for sink in &self.0 {
+            sink.on_session_destroyed(id, duration_secs);
+        }
+    }
+
+    fn on_request_completed(&self, id: &str, success: bool) {
+        for sink in &self.0 {
+            sink.on_request_completed(id, success);
+        }
+    }
+
+    fn on_empty_completion_retries(&self, id: &str, retries: u32, recovered: bool) {
+        for sink in &self.0 {
+            sink.on_empty_completion_retries(id, retries, recovered);
+        }
+    }
+
+    fn on_quota_exceeded(&self, id: &str, quota: QuotaKind) {
+        for sink in &self.0 {
+            sink.on_quota_exceeded(id, quota);
+        }
+    }
+
+    fn on_tool_call(&self, id: &str, tool: &str, outcome: ToolCallOutcome, duration_secs: f64, output_bytes: u64) {
+        for sink in &self.0 {
+            sink.on_tool_call(id, tool, outcome, duration_secs, output_bytes);
+        }
+    }
+
+    fn on_agent_event(&self, id: &str, kind: &'static str) {
+        for sink in &self.0 {
+            sink.on_agent_event(id, kind);
+        }
+    }
+}
+
+/// Sink that records lifecycle events through the `metrics` facade
+/// The exporter (Prometheus recorder) must be installed by the application
+#[cfg(feature = "prometheus")]
+#[derive(Default)]
+pub struct PrometheusEventSink;
+
+#[cfg(feature = "prometheus")]
+impl SessionEventSink for PrometheusEventSink {
+    fn on_session_created(&self, _id: &str) {
+        metrics::counter!("shai_sessions_created_total").increment(1);
+        metrics::gauge!("shai_sessions_active").increment(1.0);
+    }
+
+    fn on_session_destroyed(&self, _id: &str, duration_secs: f64) {
+        metrics::counter!("shai_sessions_destroyed_total").increment(1);
+        metrics::gauge!("shai_sessions_active").decrement(1.0);
+        metrics::histogram!("shai_session_duration_seconds").record(duration_secs);
+    }
+
+    fn on_request_completed(&self, _id: &str, success: bool) {
+        let outcome = if success { "success" } else { "failure" };
+        metrics::counter!("shai_session_requests_total", "outcome" => outcome).increment(1);
+    }
+
+    fn on_empty_completion_retries(&self, _id: &str, retries: u32, recovered: bool) {
+        metrics::counter!("shai_empty_completion_retries_total").increment(retries as u64);
+        if !recovered {
+            metrics::counter!("shai_empty_completions_total").increment(1);
+        }
+    }
+
+    fn on_quota_exceeded(&self, _id: &str, quota: QuotaKind) {
+        metrics::counter!("shai_session_quota_exceeded_total", "quota" => quota.to_string()).increment(1);
+    }
+
+    fn on_tool_call(&self, _id: &str, tool: &str, outcome: ToolCallOutcome, duration_secs: f64, output_bytes: u64) {
+        let tool = tool.to_string();
+        metrics::counter!("shai_tool_calls_total", "tool" => tool.clone(), "outcome" => outcome.as_str()).increment(1);
+        metrics::histogram!("shai_tool_duration_seconds", "tool" => tool.clone()).record(duration_secs);
+        metrics::histogram!("shai_tool_output_bytes", "tool" => tool).record(output_bytes as f64);
+    }
+
+    fn on_agent_event(&self, _id: &str, kind: &'static str) {
+        metrics::counter!("shai_agent_events_total", "type" => kind).increment(1);
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use shai_core::tools::ToolResult;
+
+/// Time the tool calls are kept in the rolling window
+pub const TOOL_STATS_RETENTION: Duration = Duration::from_secs(3600);
+
+/// Maximum number of tool calls kept in the rolling window
+pub const TOOL_STATS_CAPACITY: usize = 10_000;
+
+/// How a tool call ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolCallOutcome {
+    Success,
+    Error,
+    Denied,
+}
+
+impl ToolCallOutcome {
+    pub fn of(result: &ToolResult) -> Self {
+        match result {
+            ToolResult::Success { .. } => ToolCallOutcome::Success,
+            ToolResult::Error { .. } => ToolCallOutcome::Error,
+            ToolResult::Denied => ToolCallOutcome::Denied,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ToolCallOutcome::Success => "success",
+            ToolCallOutcome::Error => "error",
+            ToolCallOutcome::Denied => "denied",
+        }
+    }
+}
+
+/// Size in bytes of what a tool call returned to the model
+pub fn output_bytes(result: &ToolResult) -> u64 {
+    match result {
+        ToolResult::Success { output, .. } => output.len() as u64,
+        ToolResult::Error { error, .. } => error.len() as u64,
+        ToolResult::Denied => 0,
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ToolSample {
+    at: DateTime<Utc>,
+    tool: String,
+    outcome: ToolCallOutcome,
+    duration_ms: u64,
+    output_bytes: u64,
+}
+
+/// Calls of one tool over a period of the rolling window
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ToolAggregate {
+    pub tool: String,
+    pub calls: u64,
+    pub successes: u64,
+    pub errors: u64,
+    pub denied: u64,
+    pub total_duration_ms: u64,
+    pub avg_duration_ms: u64,
+    pub p95_duration_ms: u64,
+    pub max_duration_ms: u64,
+    pub total_output_bytes: u64,
+    pub max_output_bytes: u64,
+}
+
+/// Tool calls of all sessions over the last hour (bounded), queried by GET /v1/admin/tools/stats
+/// so that operators get per-tool figures without a metrics stack
+pub struct ToolStatsWindow {
+    samples: StdMutex<VecDeque<ToolSample>>,
+    retention: Duration,
+    capacity: usize,
+}
+
+impl Default for ToolStatsWindow {
+    fn default() -> Self {
+        Self::new(TOOL_STATS_RETENTION, TOOL_STATS_CAPACITY)
+    }
+}
+
+impl ToolStatsWindow {
+    pub fn new(retention: Duration, capacity: usize) -> Self {
+        Self { samples: StdMutex::new(VecDeque::new()), retention, capacity }
+    }
+
+    /// Add a completed tool call, dropping the calls that left the window
+    pub fn record(&self, tool: &str, outcome: ToolCallOutcome, duration_ms: u64, output_bytes: u64) {
+        self.record_at(Utc::now(), tool, outcome, duration_ms, output_bytes);
+    }
+
+    fn record_at(&self, at: DateTime<Utc>, tool: &str, outcome: ToolCallOutcome, duration_ms: u64, output_bytes: u64) {
+        let mut samples = self.samples.lock().unwrap();
+        samples.push_back(ToolSample { at, tool: tool.to_string(), outcome, duration_ms, output_bytes });
+        let oldest = at - self.retention;
+        while samples.len() > self.capacity || samples.front().is_some_and(|sample| sample.at < oldest) {
+            samples.pop_front();
+        }
+    }
+
+    /// Aggregates by tool name of the calls completed after `since` (None = the whole window)
+    pub fn aggregate(&self, since: Option<DateTime<Utc>>) -> Vec<ToolAggregate> {
+        let mut durations: BTreeMap<String, Vec<u64>> = BTreeMap::new();
+        let mut aggregates: BTreeMap<String, ToolAggregate> = BTreeMap::new();
+        {
+            let samples = self.samples.lock().unwrap();
+            for sample in samples.iter().filter(|sample| since.is_none_or(|since| sample.at >= since)) {
+                let aggregate = aggregates.entry(sample.tool.clone()).or_insert_with(|| ToolAggregate {
+                    tool: sample.tool.clone(),
+                    ..Default::default()
+                });
+                aggregate.calls += 1;
+                match sample.outcome {
+                    ToolCallOutcome::Success => aggregate.successes += 1,
+                    ToolCallOutcome::Error => aggregate.errors += 1,
+                    ToolCallOutcome::Denied => aggregate.denied += 1,
+                }
+                aggregate.total_duration_ms += sample.duration_ms;
+                aggregate.max_duration_ms = aggregate.max_duration_ms.max(sample.duration_ms);
+                aggregate.total_output_bytes += sample.output_bytes;
+                aggregate.max_output_bytes = aggregate.max_output_bytes.max(sample.output_bytes);
+                durations.entry(sample.tool.clone()).or_default().push(sample.duration_ms);
+            }
+        }
+
+        aggregates
+            .into_values()
+            .map(|mut aggregate| {
+                let mut tool_durations = durations.remove(&aggregate.tool).unwrap_or_default();
+                tool_durations.sort_unstable();
+                aggregate.avg_duration_ms = aggregate.total_duration_ms / aggregate.calls;
+                // nearest rank
+                let rank = (tool_durations.len() * 95).div_ceil(100).max(1);
+                aggregate.p95_duration_ms = tool_durations[rank - 1];
+                aggregate
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn test_aggregates_by_tool() {
+        let window = ToolStatsWindow::default();
+        for duration_ms in 1..=20 {
+            window.record("bash", ToolCallOutcome::Success, duration_ms * 10, 100);
+        }
+        window.record("bash", ToolCallOutcome::Error, 5, 30);
+        window.record("write", ToolCallOutcome::Denied, 0, 0);
+
+        let aggregates = window.aggregate(None);
+        assert_eq!(aggregates.len(), 2);
+        let bash = &aggregates[0];
+        assert_eq!((bash.tool.as_str(), bash.calls, bash.successes, bash.errors, bash.denied), ("bash", 21, 20, 1, 0));
+        assert_eq!(bash.max_duration_ms, 200);
+        assert_eq!(bash.p95_duration_ms, 190);
+        assert_eq!(bash.avg_duration_ms, (2100 + 5) / 21);
+        assert_eq!((bash.total_output_bytes, bash.max_output_bytes), (2030, 100));
+        assert_eq!((aggregates[1].tool.as_str(), aggregates[1].denied), ("write", 1));
+    }
+
+    #[test]
+    fn test_window_is_bounded() {
+        let window = ToolStatsWindow::new(Duration::from_secs(60), 3);
+        let now = Utc::now();
+        window.record_at(now - TimeDelta::seconds(120), "old", ToolCallOutcome::Success, 1, 1);
+        window.record_at(now - TimeDelta::seconds(30), "bash", ToolCallOutcome::Success, 1, 1);
+        window.record_at(now, "read", ToolCallOutcome::Success, 1, 1);
+        // out of the retention
+        assert!(window.aggregate(None).iter().all(|aggregate| aggregate.tool != "old"));
+
+        for _ in 0..3 {
+            window.record_at(now, "ls", ToolCallOutcome::Success, 1, 1);
+        }
+        // over the capacity
+        let aggregates = window.aggregate(None);
+        assert_eq!(aggregates.len(), 1);
+        assert_eq!(aggregates[0].calls, 3);
+
+        window.record_at(now - TimeDelta::seconds(10), "grep", ToolCallOutcome::Success, 1, 1);
+        let recent = window.aggregate(Some(now - TimeDelta::seconds(5)));
+        assert!(recent.iter().all(|aggregate| aggregate.tool != "grep"));
+    }
+}
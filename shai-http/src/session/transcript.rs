@@ -0,0 +1,313 @@
+use std::collections::VecDeque;
+use std::sync::Mutex as StdMutex;
+
+use chrono::{DateTime, Utc};
+use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent, ChatMessageContentPart};
+use shai_core::agent::{AgentEvent, PublicAgentState};
+use shai_core::tools::ToolResult;
+use tokio::sync::broadcast;
+
+/// Events kept per session for the transcript tail
+pub const TRANSCRIPT_CAPACITY: usize = 1000;
+
+/// Lines of tool output shown in the transcript
+const OUTPUT_LINES: usize = 8;
+
+/// Characters of a tool output line or of tool arguments shown in the transcript
+const LINE_CHARS: usize = 200;
+
+/// An agent event and the time the session received it
+#[derive(Clone)]
+pub struct TranscriptEntry {
+    pub at: DateTime<Utc>,
+    pub event: AgentEvent,
+}
+
+struct TranscriptInner {
+    entries: VecDeque<TranscriptEntry>,
+    /// None once the agent stopped, live subscribers then see the end of the stream
+    live: Option<broadcast::Sender<TranscriptEntry>>,
+}
+
+/// Recent events of a session, with a live feed of the new ones
+/// History and feed are taken under the same lock so that subscribers see every event once
+pub struct TranscriptLog {
+    inner: StdMutex<TranscriptInner>,
+    capacity: usize,
+}
+
+impl TranscriptLog {
+    pub fn new(capacity: usize) -> Self {
+        let (live, _) = broadcast::channel(256);
+        Self {
+            inner: StdMutex::new(TranscriptInner { entries: VecDeque::new(), live: Some(live) }),
+            capacity,
+        }
+    }
+
+    /// Record an event, argument deltas are left out (the started call carries the arguments)
+    pub fn record(&self, event: &AgentEvent) {
+        if matches!(event, AgentEvent::ToolCallArgumentsDelta { .. } | AgentEvent::ThinkingStart) {
+            return;
+        }
+        let entry = TranscriptEntry { at: Utc::now(), event: event.clone() };
+        let mut inner = self.inner.lock().unwrap();
+        if inner.entries.len() >= self.capacity {
+            inner.entries.pop_front();
+        }
+        inner.entries.push_back(entry.clone());
+        if let Some(live) = &inner.live {
+            let _ = live.send(entry);
+        }
+    }
+
+    /// Record the last user message of the trace sent to the agent
+    pub fn record_user_input(&self, trace: &[ChatMessage]) {
+        let input = trace.iter().rev().find_map(|message| match message {
+            ChatMessage::User { content, .. } => Some(content_text(content)),
+            _ => None,
+        });
+        if let Some(input) = input {
+            self.record(&AgentEvent::UserInput { input });
+        }
+    }
+
+    /// The agent stopped, end the live feed
+    pub fn close(&self) {
+        self.inner.lock().unwrap().live = None;
+    }
+
+    /// Recorded events and the feed of the next ones (None if the agent stopped)
+    pub fn subscribe(&self) -> (Vec<TranscriptEntry>, Option<broadcast::Receiver<TranscriptEntry>>) {
+        let inner = self.inner.lock().unwrap();
+        let history = inner.entries.iter().cloned().collect();
+        (history, inner.live.as_ref().map(|live| live.subscribe()))
+    }
+}
+
+impl Default for TranscriptLog {
+    fn default() -> Self {
+        Self::new(TRANSCRIPT_CAPACITY)
+    }
+}
+
+fn content_text(content: &ChatMessageContent) -> String {
+    match content {
+        ChatMessageContent::Text(text) => text.clone(),
+        ChatMessageContent::ContentPart(parts) => parts
+            .iter()
+            .map(|part| match part {
+                ChatMessageContentPart::Text(part) => part.text.clone(),
+                _ => "[attachment]".to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        ChatMessageContent::None => String::new(),
+    }
+}
+
+/// Make a text safe to print on a terminal
+/// Control characters are dropped, ANSI escape sequences too unless `keep_ansi`
+pub fn sanitize(text: &str, keep_ansi: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' if keep_ansi => out.push(c),
+            '\x1b' => match chars.next() {
+                // CSI: parameters up to a final byte in @..~
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC: up to BEL or ESC \
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' {
+                            break;
+                        }
+                        if c == '\x1b' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            '\n' | '\t' => out.push(c),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn truncate_chars(text: &str, max: usize) -> String {
+    match text.char_indices().nth(max) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// Renders transcript entries as plain text lines: `12:03:04.123 assistant: ...`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TranscriptRenderer {
+    /// Keep the ANSI colors of tool outputs
+    pub color: bool,
+}
+
+impl TranscriptRenderer {
+    pub fn new(color: bool) -> Self {
+        Self { color }
+    }
+
+    /// Lines of one entry, continuation lines are indented under the first one
+    pub fn render(&self, entry: &TranscriptEntry) -> Vec<String> {
+        let Some((label, body)) = self.describe(&entry.event) else {
+            return Vec::new();
+        };
+        let timestamp = entry.at.format("%H:%M:%S%.3f").to_string();
+        let indent = " ".repeat(timestamp.len() + 1);
+
+        let mut lines = body.lines();
+        let first = lines.next().unwrap_or_default();
+        let mut rendered = vec![format!("{} {}: {}", timestamp, label, first).trim_end().to_string()];
+        rendered.extend(lines.map(|line| format!("{}  {}", indent, line).trim_end().to_string()));
+        rendered
+    }
+
+    fn text(&self, text: &str) -> String {
+        sanitize(text, false)
+    }
+
+    /// Tool output, cut to its first lines
+    fn output(&self, output: &str) -> String {
+        let output = sanitize(output, self.color);
+        let lines: Vec<&str> = output.lines().collect();
+        let mut shown: Vec<String> = lines.iter().take(OUTPUT_LINES).map(|line| truncate_chars(line, LINE_CHARS)).collect();
+        if lines.len() > OUTPUT_LINES {
+            shown.push(format!("... ({} more lines)", lines.len() - OUTPUT_LINES));
+        }
+        shown.join("\n")
+    }
+
+    fn describe(&self, event: &AgentEvent) -> Option<(&'static str, String)> {
+        let described = match event {
+            AgentEvent::UserInput { input } => ("user", self.text(input)),
+            AgentEvent::BrainResult { thought: Ok(ChatMessage::Assistant { content, reasoning_content, .. }), .. } => {
+                let text = content.as_ref().map(content_text).unwrap_or_default();
+                match (text.trim().is_empty(), reasoning_content) {
+                    (false, _) => ("assistant", self.text(&text)),
+                    (true, Some(reasoning)) if !reasoning.trim().is_empty() => ("thinking", self.output(reasoning)),
+                    _ => return None,
+                }
+            }
+            AgentEvent::BrainResult { thought: Err(error), .. } => ("error", self.text(&error.to_string())),
+            AgentEvent::ToolCallStarted { call, .. } => {
+                let arguments = truncate_chars(&call.parameters.to_string(), LINE_CHARS);
+                ("tool", format!("{} {}", call.tool_name, self.text(&arguments)))
+            }
+            AgentEvent::ToolCallCompleted { duration, call, result } => {
+                let ms = duration.num_milliseconds();
+                match result {
+                    ToolResult::Success { output, .. } => ("tool", format!("{} ok ({}ms)\n{}", call.tool_name, ms, self.output(output))),
+                    ToolResult::Error { error, .. } => ("tool", format!("{} failed ({}ms)\n{}", call.tool_name, ms, self.output(error))),
+                    ToolResult::Denied => ("tool", format!("{} denied", call.tool_name)),
+                }
+            }
+            AgentEvent::ToolProgress { tool_name, message, percent, .. } => {
+                let percent = percent.map(|p| format!(" {}%", p)).unwrap_or_default();
+                ("tool", format!("{}{} {}", tool_name, percent, self.text(message)))
+            }
+            AgentEvent::UserInputRequired { request_id, .. } => ("status", format!("waiting for user input ({})", request_id)),
+            AgentEvent::PermissionRequired { request, .. } => ("status", format!("waiting for permission: {} {}", request.tool_name, self.text(&request.operation))),
+            AgentEvent::StatusChanged { new_status, .. } => match new_status {
+                PublicAgentState::Running => ("status", "running".to_string()),
+                PublicAgentState::Paused => ("status", "paused".to_string()),
+                PublicAgentState::Cancelled => ("status", "cancelled".to_string()),
+                PublicAgentState::Failed { error } => ("status", format!("failed: {}", self.text(error))),
+                _ => return None,
+            },
+            AgentEvent::TokenUsage { input_tokens, output_tokens } => {
+                ("usage", format!("{} input / {} output tokens", input_tokens, output_tokens))
+            }
+            AgentEvent::EmptyCompletionRetried { retries } => ("warning", format!("empty completion retried {} times", retries)),
+            AgentEvent::Error { error } => ("error", self.text(error)),
+            AgentEvent::Completed { success, message } => {
+                let outcome = if *success { "success" } else { "failure" };
+                ("completed", format!("{} {}", outcome, self.text(message)))
+            }
+            _ => return None,
+        };
+        Some(described)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shai_core::tools::ToolCall;
+
+    fn entry(event: AgentEvent) -> TranscriptEntry {
+        let at = DateTime::parse_from_rfc3339("2025-01-01T12:03:04.123Z").unwrap().with_timezone(&Utc);
+        TranscriptEntry { at, event }
+    }
+
+    fn completed(output: String) -> AgentEvent {
+        AgentEvent::ToolCallCompleted {
+            duration: chrono::TimeDelta::milliseconds(42),
+            call: ToolCall { tool_call_id: "call_1".to_string(), tool_name: "bash".to_string(), parameters: serde_json::json!({}) },
+            result: ToolResult::success(output),
+        }
+    }
+
+    #[test]
+    fn test_render_lines() {
+        let renderer = TranscriptRenderer::default();
+        let lines = renderer.render(&entry(AgentEvent::UserInput { input: "fix the build\nplease".to_string() }));
+        assert_eq!(lines, vec!["12:03:04.123 user: fix the build", "               please"]);
+
+        let output = (1..=10).map(|i| format!("line {}", i)).collect::<Vec<_>>().join("\n");
+        let lines = renderer.render(&entry(completed(output)));
+        assert_eq!(lines[0], "12:03:04.123 tool: bash ok (42ms)");
+        assert_eq!(lines[1].trim(), "line 1");
+        assert_eq!(lines.len(), 1 + OUTPUT_LINES + 1);
+        assert_eq!(lines.last().unwrap().trim(), "... (2 more lines)");
+
+        assert!(renderer.render(&entry(AgentEvent::ThinkingStart)).is_empty());
+    }
+
+    #[test]
+    fn test_ansi_is_stripped_unless_color() {
+        let colored = "\x1b[31merror\x1b[0m done\x1b]0;title\x07\x08";
+        assert_eq!(sanitize(colored, false), "error done");
+        assert_eq!(sanitize(colored, true), "\x1b[31merror\x1b[0m done\x1b]0;title");
+
+        let plain = TranscriptRenderer::new(false).render(&entry(completed(colored.to_string())));
+        assert!(!plain.concat().contains('\x1b'));
+        let color = TranscriptRenderer::new(true).render(&entry(completed(colored.to_string())));
+        assert!(color.concat().contains("\x1b[31m"));
+    }
+
+    #[test]
+    fn test_log_history_and_feed() {
+        let log = TranscriptLog::new(2);
+        for input in ["a", "b", "c"] {
+            log.record(&AgentEvent::UserInput { input: input.to_string() });
+        }
+        let (history, live) = log.subscribe();
+        assert_eq!(history.len(), 2);
+        assert!(matches!(&history[0].event, AgentEvent::UserInput { input } if input == "b"));
+
+        let mut live = live.unwrap();
+        log.record(&AgentEvent::UserInput { input: "d".to_string() });
+        assert!(matches!(live.try_recv().unwrap().event, AgentEvent::UserInput { input } if input == "d"));
+
+        log.close();
+        assert!(log.subscribe().1.is_none());
+        assert!(matches!(live.try_recv(), Err(broadcast::error::TryRecvError::Closed)));
+    }
+}
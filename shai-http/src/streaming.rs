@@ -1,14 +1,20 @@
 use async_trait::async_trait;
 use axum::response::sse::Event;
-use futures::stream::{Stream, StreamExt};
-use serde::Serialize;
-use shai_core::agent::{AgentEvent, PublicAgentState};
+use futures::stream::Stream;
+use serde::{Deserialize, Serialize};
+use shai_core::agent::AgentEvent;
+use shai_core::tools::{ToolResult, QUOTA_EXCEEDED_METADATA};
+use std::collections::VecDeque;
 use std::convert::Infallible;
+use std::time::Duration;
 use tokio::sync::broadcast::Receiver;
-use tokio_stream::wrappers::BroadcastStream;
-use tracing::error;
+use tokio::time::{interval_at, Instant, Interval, MissedTickBehavior};
+use tracing::{error, warn};
 
+use crate::quota::QuotaKind;
+use crate::run::{AgentRun, RunOptions, RunSummary};
 use crate::session::RequestSession;
+use crate::ErrorResponse;
 
 /// Trait for formatting AgentEvents into API-specific response formats
 #[async_trait]
@@ -23,6 +29,36 @@ pub trait EventFormatter: Send {
         session_id: &str,
     ) -> Option<Self::Output>;
 
+    /// Convert an AgentEvent to zero or more outputs, for events that map to several
+    /// API events. Defaults to format_event
+    async fn format_events(
+        &mut self,
+        event: AgentEvent,
+        session_id: &str,
+    ) -> Vec<Self::Output> {
+        self.format_event(event, session_id).await.into_iter().collect()
+    }
+
+    /// Called with the run summary right before the terminal event is formatted
+    fn set_run_summary(&mut self, _summary: &RunSummary) {}
+
+    /// Final frame of a failed run, sent in place of its terminal event
+    /// Default is OpenAI's streaming error convention: an unnamed `data: {"error": {...}}` frame
+    fn format_error(&mut self, error: &ErrorResponse, _session_id: &str) -> Result<Event, serde_json::Error> {
+        serde_json::to_string(error).map(|json| Event::default().data(json))
+    }
+
+    /// Sentinel closing a successful stream, sent after every other event (None = the stream just ends)
+    fn format_done(&mut self) -> Option<Event> {
+        None
+    }
+
+    /// Frame sent when no event came for the heartbeat interval, keeping proxies from closing an idle stream
+    /// Default is a `: ping` comment, which SSE parsers drop before any event reaches the client (None = no heartbeat)
+    fn format_heartbeat(&mut self) -> Option<Event> {
+        Some(Event::default().comment(PING_EVENT))
+    }
+
     /// Get the SSE event name for this output
     /// Default is "message"
     fn event_name(&self, _output: &Self::Output) -> &str {
@@ -30,73 +66,282 @@ pub trait EventFormatter: Send {
     }
 }
 
-/// Internal helper to create SSE stream with optional lifecycle
-fn sse_stream_internal<F, L>(
-    event_rx: Receiver<AgentEvent>,
+/// SSE event name used to forward tool progress, whatever the API format
+pub const TOOL_PROGRESS_EVENT: &str = "tool_progress";
+
+/// Payload of a `tool_progress` SSE event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolProgressPayload {
+    pub call_id: String,
+    pub tool_name: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percent: Option<u8>,
+}
+
+/// SSE event name used to forward tool call arguments while the model generates them,
+/// for API formats that have no event of their own for it
+pub const TOOL_CALL_ARGUMENTS_DELTA_EVENT: &str = "tool_call.arguments.delta";
+
+/// Payload of a `tool_call.arguments.delta` SSE event
+/// Deltas of a call share its `call_id`, their concatenation is the final arguments
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCallArgumentsDeltaPayload {
+    pub call_id: String,
+    pub tool_name: String,
+    pub index: u32,
+    pub delta: String,
+}
+
+/// Text of the heartbeat comment, it is not an event and clients never see it
+pub const PING_EVENT: &str = "ping";
+
+/// Default time without event after which a stream gets a ping
+pub const DEFAULT_SSE_HEARTBEAT: Duration = Duration::from_secs(30);
+
+/// Heartbeat interval from SHAI_SSE_HEARTBEAT_SECS (0 = disabled), the default when unset or invalid
+pub fn heartbeat_from_env() -> Option<Duration> {
+    match std::env::var("SHAI_SSE_HEARTBEAT_SECS").ok().and_then(|value| value.trim().parse::<u64>().ok()) {
+        Some(0) => None,
+        Some(secs) => Some(Duration::from_secs(secs)),
+        None => Some(DEFAULT_SSE_HEARTBEAT),
+    }
+}
+
+/// Ticker of the heartbeat, its first tick comes one interval from now
+fn heartbeat_ticker(period: Duration) -> Interval {
+    let mut ticker = interval_at(Instant::now() + period, period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticker
+}
+
+/// Data of the frame ending a successful chat completions stream, as OpenAI sends it
+pub const DONE_SENTINEL: &str = "[DONE]";
+
+/// SSE event name of the run summary, sent right before the terminal event
+pub const RUN_SUMMARY_EVENT: &str = "run.summary";
+
+/// SSE event name of the workspace change summary sent after the run's terminal event
+pub const WORKSPACE_CHANGES_EVENT: &str = "workspace.changes";
+
+/// SSE event name of the error frame ending a failed run, for APIs without an error convention of their own
+pub const ERROR_EVENT: &str = "error";
+
+/// SSE event name sent when a tool write was refused by the session disk quota
+pub const QUOTA_EXCEEDED_EVENT: &str = "quota.exceeded";
+
+/// Payload of a `quota.exceeded` SSE event
+/// The tool call itself still completes with an error result, this event tells why
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaExceededPayload {
+    pub quota: QuotaKind,
+    pub call_id: String,
+    pub tool_name: String,
+    pub limit: u64,
+    pub used: u64,
+    pub requested: u64,
+    pub message: String,
+}
+
+/// Tool progress is forwarded as a named intermediate event, bypassing the API formatter
+fn tool_progress_event(event: &AgentEvent) -> Option<Result<Event, serde_json::Error>> {
+    let AgentEvent::ToolProgress { call_id, tool_name, message, percent } = event else {
+        return None;
+    };
+    let payload = ToolProgressPayload {
+        call_id: call_id.clone(),
+        tool_name: tool_name.clone(),
+        message: message.clone(),
+        percent: *percent,
+    };
+    Some(serde_json::to_string(&payload).map(|json| Event::default().event(TOOL_PROGRESS_EVENT).data(json)))
+}
+
+fn tool_call_arguments_delta_event(event: &AgentEvent) -> Option<Result<Event, serde_json::Error>> {
+    let AgentEvent::ToolCallArgumentsDelta { call_id, tool_name, index, delta } = event else {
+        return None;
+    };
+    let payload = ToolCallArgumentsDeltaPayload {
+        call_id: call_id.clone(),
+        tool_name: tool_name.clone(),
+        index: *index,
+        delta: delta.clone(),
+    };
+    Some(serde_json::to_string(&payload).map(|json| Event::default().event(TOOL_CALL_ARGUMENTS_DELTA_EVENT).data(json)))
+}
+
+fn quota_exceeded_event(event: &AgentEvent) -> Option<Result<Event, serde_json::Error>> {
+    let AgentEvent::ToolCallCompleted { call, result: ToolResult::Error { error, metadata: Some(metadata) }, .. } = event else {
+        return None;
+    };
+    let details = metadata.get(QUOTA_EXCEEDED_METADATA)?;
+    let bytes = |key: &str| details.get(key).and_then(serde_json::Value::as_u64).unwrap_or(0);
+    let payload = QuotaExceededPayload {
+        quota: QuotaKind::Disk,
+        call_id: call.tool_call_id.clone(),
+        tool_name: call.tool_name.clone(),
+        limit: bytes("limit"),
+        used: bytes("used"),
+        requested: bytes("requested"),
+        message: error.clone(),
+    };
+    Some(serde_json::to_string(&payload).map(|json| Event::default().event(QUOTA_EXCEEDED_EVENT).data(json)))
+}
+
+/// Format the events of a run into SSE events
+/// The stream ends after the run's terminal event, dropping it ends the run (client disconnect)
+/// While the run is idle for its heartbeat interval, the formatter's heartbeat frame is sent
+pub fn run_to_sse_stream<F>(
+    run: AgentRun,
     formatter: F,
     session_id: String,
-    lifecycle: Option<L>,
-    stop_on_pause: bool,
 ) -> impl Stream<Item = Result<Event, Infallible>>
 where
     F: EventFormatter + 'static,
-    L: Send + 'static,
 {
-    futures::stream::unfold(
-        (BroadcastStream::new(event_rx), formatter, false, lifecycle),
-        move |state| {
-            let session_id = session_id.clone();
-            async move {
-                let (mut rx, mut fmt, done, lifecycle) = state;
-
-                if done {
-                    return None;
+    let heartbeat = run.heartbeat().map(heartbeat_ticker);
+    futures::stream::unfold((run, formatter, VecDeque::new(), false, false, heartbeat), move |(mut run, mut fmt, mut pending, mut summary_sent, mut done, mut heartbeat)| {
+        let session_id = session_id.clone();
+        async move {
+            loop {
+                if let Some(sse_event) = pending.pop_front() {
+                    return Some((Ok(sse_event), (run, fmt, pending, summary_sent, done, heartbeat)));
                 }
 
-                loop {
-                    match rx.next().await {
-                        Some(Ok(event)) => {
-                            let is_terminal = is_terminal_event(&event, stop_on_pause);
-                            let formatted = fmt.format_event(event, &session_id).await;
-                            let new_done = if is_terminal { true } else { done };
-
-                            if let Some(output) = formatted {
-                                match serde_json::to_string(&output) {
-                                    Ok(json) => {
-                                        let sse_event = Event::default().data(json);
-                                        return Some((Ok(sse_event), (rx, fmt, new_done, lifecycle)));
-                                    }
-                                    Err(e) => {
-                                        error!("[{}] Failed to serialize event: {}", session_id, e);
-                                        continue;
-                                    }
-                                }
-                            } else {
-                                if new_done {
-                                    return None;
-                                }
-                                continue;
-                            }
-                        }
-                        Some(Err(e)) => {
-                            error!("[{}] Error receiving event: {}", session_id, e);
-                            return None;
-                        }
-                        None => {
-                            return None;
+                // Waiting for the next event is cancel-safe, an event is never lost to a heartbeat
+                let next = match heartbeat.as_mut() {
+                    Some(ticker) => tokio::select! {
+                        event = run.next_event() => Some(event),
+                        _ = ticker.tick() => None,
+                    },
+                    None => Some(run.next_event().await),
+                };
+                let Some(next) = next else {
+                    match fmt.format_heartbeat() {
+                        Some(ping) => return Some((Ok(ping), (run, fmt, pending, summary_sent, done, heartbeat))),
+                        None => heartbeat = None,
+                    }
+                    continue;
+                };
+                let Some(event) = next else {
+                    // The run is over, no ping follows its last events
+                    heartbeat = None;
+                    break;
+                };
+                if let Some(ticker) = heartbeat.as_mut() {
+                    ticker.reset();
+                }
+
+                match tool_progress_event(&event) {
+                    Some(Ok(sse_event)) => return Some((Ok(sse_event), (run, fmt, pending, summary_sent, done, heartbeat))),
+                    Some(Err(e)) => {
+                        error!("[{}] Failed to serialize tool progress: {}", session_id, e);
+                        continue;
+                    }
+                    None => {}
+                }
+
+                // The summary goes right before the terminal event
+                if let Some(summary) = run.summary() {
+                    fmt.set_run_summary(&summary);
+                    pending.extend(run_summary_event(&session_id, &summary));
+                    summary_sent = true;
+
+                    // A failed run ends on an error frame rather than on a successful looking terminal event
+                    if let Some(error) = run.error() {
+                        pending.extend(run_error_event(&mut fmt, &error, &session_id));
+                        continue;
+                    }
+                }
+
+                // A refused write is announced before the formatter reports the failed call
+                match quota_exceeded_event(&event) {
+                    Some(Ok(sse_event)) => pending.push_back(sse_event),
+                    Some(Err(e)) => error!("[{}] Failed to serialize quota event: {}", session_id, e),
+                    None => {}
+                }
+
+                // Argument deltas go through the formatter, the generic event is the fallback
+                let generic_delta = tool_call_arguments_delta_event(&event);
+                let outputs = fmt.format_events(event, &session_id).await;
+                if outputs.is_empty() {
+                    match generic_delta {
+                        Some(Ok(sse_event)) => pending.push_back(sse_event),
+                        Some(Err(e)) => error!("[{}] Failed to serialize tool call arguments: {}", session_id, e),
+                        None => {}
+                    }
+                }
+
+                for output in outputs {
+                    match serde_json::to_string(&output) {
+                        Ok(json) => pending.push_back(Event::default().data(json)),
+                        Err(e) => {
+                            error!("[{}] Failed to serialize event: {}", session_id, e);
                         }
                     }
                 }
             }
-        },
-    )
+
+            // The run ended without terminal event (timeout, agent died)
+            if !summary_sent {
+                summary_sent = true;
+                pending.extend(run.summary().and_then(|summary| run_summary_event(&session_id, &summary)));
+                if let Some(error) = run.error() {
+                    pending.extend(run_error_event(&mut fmt, &error, &session_id));
+                }
+                if let Some(sse_event) = pending.pop_front() {
+                    return Some((Ok(sse_event), (run, fmt, pending, summary_sent, done, heartbeat)));
+                }
+            }
+
+            // The run ended, report the files it changed (diffs via the changes endpoint)
+            if let Some(changes) = run.workspace_changes().await {
+                match serde_json::to_string(&changes.summary()) {
+                    Ok(json) => pending.push_back(Event::default().event(WORKSPACE_CHANGES_EVENT).data(json)),
+                    Err(e) => error!("[{}] Failed to serialize workspace changes: {}", session_id, e),
+                }
+            }
+
+            // A successful stream is closed by the formatter's sentinel, a failed one by its error frame
+            if !done {
+                done = true;
+                if run.error().is_none() {
+                    pending.extend(fmt.format_done());
+                }
+            }
+
+            let sse_event = pending.pop_front()?;
+            Some((Ok(sse_event), (run, fmt, pending, summary_sent, done, heartbeat)))
+        }
+    })
+}
+
+fn run_summary_event(session_id: &str, summary: &RunSummary) -> Option<Event> {
+    match serde_json::to_string(summary) {
+        Ok(json) => Some(Event::default().event(RUN_SUMMARY_EVENT).data(json)),
+        Err(e) => {
+            error!("[{}] Failed to serialize run summary: {}", session_id, e);
+            None
+        }
+    }
+}
+
+fn run_error_event<F: EventFormatter>(fmt: &mut F, error: &ErrorResponse, session_id: &str) -> Option<Event> {
+    warn!("[{}] Run failed: {}", session_id, error.error.message);
+    match fmt.format_error(error, session_id) {
+        Ok(sse_event) => Some(sse_event),
+        Err(e) => {
+            error!("[{}] Failed to serialize run error: {}", session_id, e);
+            None
+        }
+    }
 }
 
 /// Core SSE stream creation from event receiver
 /// Watches events, formats them, and stops on completion or client disconnect
 ///
 /// # Parameters
-/// * `stop_on_pause` - If true, only stops on Completed. If false, stops on Completed or StatusChanged to Paused.
+/// * `stop_on_pause` - If true, stops on Completed or StatusChanged to Paused. If false, only stops on Completed.
 pub fn event_to_sse_stream<F>(
     event_rx: Receiver<AgentEvent>,
     formatter: F,
@@ -106,14 +351,16 @@ pub fn event_to_sse_stream<F>(
 where
     F: EventFormatter + 'static,
 {
-    sse_stream_internal(event_rx, formatter, session_id, None::<()>, stop_on_pause)
+    let options = RunOptions::default().with_stop_on_pause(stop_on_pause);
+    let run = AgentRun::watch(event_rx, session_id.clone(), options);
+    run_to_sse_stream(run, formatter, session_id)
 }
 
 /// Create an SSE stream from a RequestSession
-/// Same as sse_stream but keeps lifecycle in scope for session cleanup
+/// Same as event_to_sse_stream but keeps lifecycle in scope for session cleanup
 ///
 /// # Parameters
-/// * `stop_on_pause` - If true, only stops on Completed. If false, stops on Completed or StatusChanged to Paused.
+/// * `stop_on_pause` - If true, stops on Completed or StatusChanged to Paused. If false, only stops on Completed.
 pub fn session_to_sse_stream<F>(
     request_session: RequestSession,
     formatter: F,
@@ -123,24 +370,198 @@ pub fn session_to_sse_stream<F>(
 where
     F: EventFormatter + 'static,
 {
-    let event_rx = request_session.event_rx;
-    let _controller = request_session.controller;
-    let lifecycle = request_session.lifecycle;
-
-    sse_stream_internal(event_rx, formatter, session_id, Some(lifecycle), stop_on_pause)
+    let options = RunOptions::default().with_stop_on_pause(stop_on_pause);
+    run_to_sse_stream(AgentRun::new(request_session, session_id.clone(), options), formatter, session_id)
 }
 
-/// Check if an event signals the end of the stream
-///
-/// # Parameters
-/// * `stop_on_pause` - If true, only Completed is terminal. If false, both Completed and Paused are terminal.
-fn is_terminal_event(event: &AgentEvent, stop_on_pause: bool) -> bool {
-    match event {
-        AgentEvent::Completed { .. } => true,
-        AgentEvent::StatusChanged {
-            new_status: PublicAgentState::Paused,
-            ..
-        } => stop_on_pause,
-        _ => false,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::{IntoResponse, Sse};
+    use openai_dive::v1::resources::response::request::ResponseParameters;
+    use openai_dive::v1::resources::response::response::ReasoningStatus;
+    use shai_core::agent::{AgentError, PublicAgentState};
+    use shai_core::tools::ToolCall;
+    use tokio::sync::broadcast;
+
+    use crate::apis::openai::completion::formatter::ChatCompletionFormatter;
+    use crate::apis::openai::response::formatter::ResponseFormatter;
+    use crate::apis::openai::response::types::{ResponseEventData, ResponseEventType};
+    use crate::apis::simple::formatter::SimpleFormatter;
+    use crate::client::{ClientEvent, SseDecoder, StreamKind};
+
+    /// Two chunks worth of tool activity, then the provider fails
+    fn failing_run() -> Receiver<AgentEvent> {
+        let call = ToolCall {
+            tool_call_id: "call_1".to_string(),
+            tool_name: "read".to_string(),
+            parameters: serde_json::json!({ "path": "a.txt" }),
+        };
+        let (tx, rx) = broadcast::channel(16);
+        tx.send(AgentEvent::StatusChanged { old_status: PublicAgentState::Paused, new_status: PublicAgentState::Running }).unwrap();
+        tx.send(AgentEvent::ToolCallStarted { timestamp: chrono::Utc::now(), call: call.clone() }).unwrap();
+        tx.send(AgentEvent::ToolCallCompleted {
+            duration: chrono::TimeDelta::milliseconds(5),
+            call,
+            result: ToolResult::success("content".to_string()),
+        }).unwrap();
+        tx.send(AgentEvent::BrainResult {
+            timestamp: chrono::Utc::now(),
+            thought: Err(AgentError::LlmError("provider down".to_string())),
+        }).unwrap();
+        tx.send(AgentEvent::StatusChanged { old_status: PublicAgentState::Running, new_status: PublicAgentState::Paused }).unwrap();
+        rx
     }
+
+    /// Events of the stream as decoded by the client
+    async fn client_events<F: EventFormatter + 'static>(formatter: F, kind: StreamKind) -> Vec<ClientEvent> {
+        let stream = event_to_sse_stream(failing_run(), formatter, "resp_1".to_string(), true);
+        let body = axum::body::to_bytes(Sse::new(stream).into_response().into_body(), usize::MAX).await.unwrap();
+        SseDecoder::new()
+            .push(&body)
+            .into_iter()
+            .map(|message| ClientEvent::decode(kind, message).unwrap())
+            .collect()
+    }
+
+    fn assert_provider_error(error: &ErrorResponse) {
+        assert_eq!(error.error.r#type, "upstream_error");
+        assert_eq!(error.error.code.as_deref(), Some("provider_error"));
+        assert_eq!(error.error.message, "LLM error: provider down");
+    }
+
+    #[tokio::test]
+    async fn test_chat_completion_stream_ends_on_error_chunk() {
+        let events = client_events(ChatCompletionFormatter::new("test".to_string()), StreamKind::ChatCompletions).await;
+
+        let chunks: Vec<_> = events.iter().filter(|e| matches!(e, ClientEvent::ChatCompletionChunk(_))).collect();
+        // role chunk, then the tool call start and completion
+        assert_eq!(chunks.len(), 3);
+        assert!(events.iter().all(|e| !matches!(e, ClientEvent::Done)));
+        // no chunk claims the completion finished
+        assert!(events.iter().all(|e| !matches!(e, ClientEvent::ChatCompletionChunk(c) if c.choices[0].finish_reason.is_some())));
+
+        let ClientEvent::Error(error) = events.last().unwrap() else {
+            panic!("expected the stream to end on an error, got {:?}", events.last());
+        };
+        assert_provider_error(error);
+    }
+
+    #[tokio::test]
+    async fn test_chat_completion_stream_deltas() {
+        let call = ToolCall {
+            tool_call_id: "call_1".to_string(),
+            tool_name: "read".to_string(),
+            parameters: serde_json::json!({ "path": "a.txt" }),
+        };
+        let (tx, rx) = broadcast::channel(16);
+        tx.send(AgentEvent::StatusChanged { old_status: PublicAgentState::Paused, new_status: PublicAgentState::Running }).unwrap();
+        tx.send(AgentEvent::ToolCallStarted { timestamp: chrono::Utc::now(), call: call.clone() }).unwrap();
+        tx.send(AgentEvent::ToolCallCompleted {
+            duration: chrono::TimeDelta::milliseconds(5),
+            call,
+            result: ToolResult::success("content".to_string()),
+        }).unwrap();
+        // one usage event per brain iteration
+        tx.send(AgentEvent::TokenUsage { input_tokens: 100, output_tokens: 20 }).unwrap();
+        tx.send(AgentEvent::TokenUsage { input_tokens: 150, output_tokens: 5 }).unwrap();
+        tx.send(AgentEvent::Completed { success: true, message: "done".to_string() }).unwrap();
+
+        let stream = event_to_sse_stream(rx, ChatCompletionFormatter::new("test".to_string()), "chat_1".to_string(), true);
+        let body = axum::body::to_bytes(Sse::new(stream).into_response().into_body(), usize::MAX).await.unwrap();
+        let events: Vec<_> = SseDecoder::new()
+            .push(&body)
+            .into_iter()
+            .map(|message| ClientEvent::decode(StreamKind::ChatCompletions, message).unwrap())
+            .collect();
+        let chunks: Vec<_> = events.iter().filter_map(|e| match e {
+            ClientEvent::ChatCompletionChunk(chunk) => Some(chunk),
+            _ => None,
+        }).collect();
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks.iter().all(|chunk| chunk.id == chunks[0].id));
+
+        // the role comes alone in the first chunk
+        let json = serde_json::to_value(chunks[0]).unwrap();
+        assert_eq!(json["choices"][0]["delta"]["role"], "assistant");
+        assert_eq!(json["choices"][0]["delta"]["content"], "");
+
+        // the started call is streamed as a tool_calls delta
+        let json = serde_json::to_value(chunks[1]).unwrap();
+        let tool_call = &json["choices"][0]["delta"]["tool_calls"][0];
+        assert_eq!(tool_call["index"], 0);
+        assert_eq!(tool_call["id"], "call_1");
+        assert_eq!(tool_call["function"]["name"], "read");
+        assert_eq!(tool_call["function"]["arguments"], r#"{"path":"a.txt"}"#);
+
+        // the final chunk carries the usage of the whole run
+        let json = serde_json::to_value(chunks[3]).unwrap();
+        assert_eq!(json["choices"][0]["delta"]["content"], "done");
+        assert_eq!(json["usage"]["prompt_tokens"], 250);
+        assert_eq!(json["usage"]["completion_tokens"], 25);
+        assert_eq!(json["usage"]["total_tokens"], 275);
+        assert!(chunks[..3].iter().all(|chunk| chunk.usage.is_none()));
+        assert!(matches!(events.last(), Some(ClientEvent::Done)));
+    }
+
+    #[tokio::test]
+    async fn test_response_stream_ends_on_response_failed() {
+        let payload: ResponseParameters = serde_json::from_value(serde_json::json!({ "model": "test", "input": "hi" })).unwrap();
+        let events = client_events(ResponseFormatter::new("test".to_string(), payload), StreamKind::Responses).await;
+
+        let ClientEvent::Response(failed) = events.last().unwrap() else {
+            panic!("expected the stream to end on response.failed, got {:?}", events.last());
+        };
+        assert_eq!(failed.event_type, ResponseEventType::ResponseFailed);
+        let ResponseEventData::Response { response, .. } = &failed.data else {
+            panic!("response.failed carries the response");
+        };
+        assert!(matches!(response.status, ReasoningStatus::Failed));
+        // the tool call streamed before the failure is kept
+        assert_eq!(response.output.len(), 1);
+        assert!(events.iter().all(|e| !matches!(e, ClientEvent::Response(r) if r.event_type == ResponseEventType::ResponseCompleted)));
+
+        let json = serde_json::to_value(failed).unwrap();
+        assert_eq!(json["response"]["error"]["code"], "provider_error");
+        assert_eq!(json["response"]["error"]["message"], "LLM error: provider down");
+    }
+
+    #[tokio::test]
+    async fn test_query_stream_ends_on_error_event() {
+        let events = client_events(SimpleFormatter::new("test".to_string()), StreamKind::Query).await;
+
+        assert_eq!(events.iter().filter(|e| matches!(e, ClientEvent::Query(_))).count(), 2);
+        assert!(matches!(&events[events.len() - 2], ClientEvent::RunSummary(_)));
+        let ClientEvent::Error(error) = events.last().unwrap() else {
+            panic!("expected the stream to end on an error event, got {:?}", events.last());
+        };
+        assert_provider_error(error);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_while_run_is_idle() {
+        let (tx, rx) = broadcast::channel(16);
+        let options = RunOptions::default().with_heartbeat(Some(Duration::from_millis(20)));
+        let run = AgentRun::watch(rx, "query_1".to_string(), options);
+        let stream = run_to_sse_stream(run, SimpleFormatter::new("test".to_string()), "query_1".to_string());
+
+        // the agent stays silent for a few heartbeats, then completes
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(90)).await;
+            tx.send(AgentEvent::Completed { success: true, message: "done".to_string() }).unwrap();
+        });
+
+        let body = axum::body::to_bytes(Sse::new(stream).into_response().into_body(), usize::MAX).await.unwrap();
+        let text = String::from_utf8_lossy(&body);
+        let ping = format!(": {}\n", PING_EVENT);
+        assert!(text.matches(&ping).count() >= 2, "expected pings while the run was idle, got {:?}", text);
+
+        // the pings only fill the wait, the stream ends with the run
+        assert!(text.rfind(&ping).unwrap() < text.find("data:").unwrap());
+        // they are comments, the events the clients parse are the run's own
+        let messages = SseDecoder::new().push(&body);
+        assert!(messages.iter().all(|message| message.event != PING_EVENT));
+        assert!(messages.iter().any(|message| message.event == RUN_SUMMARY_EVENT));
+    }
+
 }
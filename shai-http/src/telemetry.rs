@@ -0,0 +1,109 @@
+use std::sync::OnceLock;
+
+use axum::http::{HeaderMap, Request};
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::{info_span, warn, Level, Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Service name when OTEL_SERVICE_NAME is not set
+pub const DEFAULT_SERVICE_NAME: &str = "shai";
+
+static PROVIDER: OnceLock<TracerProvider> = OnceLock::new();
+
+/// OTLP endpoint the spans are exported to (OTEL_EXPORTER_OTLP_ENDPOINT), None = tracing disabled
+pub fn endpoint() -> Option<String> {
+    std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok().filter(|endpoint| !endpoint.trim().is_empty())
+}
+
+/// Tracing layer exporting the spans over OTLP, None when no endpoint is configured
+/// Only the request spans and the agent spans (brain steps, tool calls) are exported, not the log events
+pub fn layer<S>() -> Option<impl Layer<S>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let endpoint = endpoint()?;
+    let exporter = match SpanExporter::builder().with_tonic().with_endpoint(endpoint.clone()).build() {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            warn!("OpenTelemetry exporter to {} not installed: {}", endpoint, e);
+            return None;
+        }
+    };
+    let service_name = std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| DEFAULT_SERVICE_NAME.to_string());
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new(vec![KeyValue::new("service.name", service_name)]))
+        .build();
+    let tracer = provider.tracer("shai-http");
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    global::set_tracer_provider(provider.clone());
+    let _ = PROVIDER.set(provider);
+
+    let spans = Targets::new().with_target("shai_http", Level::INFO).with_target("agent", Level::INFO);
+    Some(tracing_opentelemetry::layer().with_tracer(tracer).with_filter(spans))
+}
+
+/// Export the spans still buffered, before the process exits
+pub fn shutdown() {
+    if let Some(provider) = PROVIDER.get() {
+        if let Err(e) = provider.shutdown() {
+            warn!("OpenTelemetry shutdown failed: {}", e);
+        }
+    }
+}
+
+/// Root span of an HTTP request, continuing the trace of the W3C `traceparent` header when sent
+pub fn request_span<B>(request: &Request<B>) -> Span {
+    let span = info_span!(
+        "http.request",
+        otel.kind = "server",
+        http.request.method = %request.method(),
+        url.path = %request.uri().path(),
+    );
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(request.headers())));
+    span.set_parent(parent);
+    span
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TraceContextExt;
+
+    #[test]
+    fn test_traceparent_extracted() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let request = Request::get("/v1/models")
+            .header("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+            .body(())
+            .unwrap();
+
+        let context = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(request.headers())));
+        let span_context = context.span().span_context().clone();
+        assert!(span_context.is_remote());
+        assert_eq!(span_context.trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(span_context.span_id().to_string(), "00f067aa0ba902b7");
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::SystemTime;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use tracing::{debug, warn};
+
+/// Text files up to this size keep their content in the snapshot to produce diffs
+pub const DEFAULT_MAX_DIFF_BYTES: u64 = 256 * 1024;
+
+/// Number of request change sets kept per session
+const CHANGES_HISTORY: usize = 20;
+
+/// What to snapshot around each request
+#[derive(Debug, Clone)]
+pub struct WorkspaceConfig {
+    pub root: PathBuf,
+    /// Path components to skip (e.g. ".git", "node_modules")
+    pub ignore: Vec<String>,
+    pub max_diff_bytes: u64,
+}
+
+impl WorkspaceConfig {
+    pub fn new(root: PathBuf, ignore: Vec<String>) -> Self {
+        Self { root, ignore, max_diff_bytes: DEFAULT_MAX_DIFF_BYTES }
+    }
+
+    pub(crate) fn is_ignored(&self, relative: &Path) -> bool {
+        relative.components().any(|component| {
+            let name = component.as_os_str().to_string_lossy();
+            self.ignore.iter().any(|pattern| pattern == name.as_ref())
+        })
+    }
+}
+
+/// One file of a snapshot
+#[derive(Debug, Clone)]
+struct FileEntry {
+    size: u64,
+    mtime: Option<SystemTime>,
+    hash: String,
+    binary: bool,
+    /// Content of small text files, used to produce diffs
+    text: Option<String>,
+}
+
+/// Files of the workspace at one point in time, keyed by path relative to the root
+#[derive(Debug, Clone, Default)]
+pub struct WorkspaceManifest {
+    files: BTreeMap<String, FileEntry>,
+}
+
+impl WorkspaceManifest {
+    /// Walk the workspace and hash every file that is not ignored
+    pub fn capture(config: &WorkspaceConfig) -> Self {
+        let mut manifest = Self::default();
+        let mut pending = vec![config.root.clone()];
+
+        while let Some(dir) = pending.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warn!("Failed to read workspace directory {}: {}", dir.display(), e);
+                    continue;
+                }
+            };
+
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let Ok(relative) = path.strip_prefix(&config.root) else {
+                    continue;
+                };
+                if config.is_ignored(relative) {
+                    continue;
+                }
+                let Ok(file_type) = entry.file_type() else {
+                    continue;
+                };
+                if file_type.is_dir() {
+                    pending.push(path);
+                } else if file_type.is_file() {
+                    if let Some(file) = read_entry(&path, config.max_diff_bytes) {
+                        manifest.files.insert(relative.to_string_lossy().to_string(), file);
+                    }
+                }
+            }
+        }
+
+        manifest
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Files created, modified and deleted between `self` (before) and `after`
+    pub fn changes(&self, after: &WorkspaceManifest, request_id: &str) -> WorkspaceChanges {
+        let mut changes = WorkspaceChanges { request_id: request_id.to_string(), ..Default::default() };
+
+        for (path, new) in &after.files {
+            match self.files.get(path) {
+                None => changes.created.push(FileChange::new(path, None, Some(new))),
+                Some(old) if old.hash != new.hash => changes.modified.push(FileChange::new(path, Some(old), Some(new))),
+                Some(_) => {}
+            }
+        }
+        for (path, old) in &self.files {
+            if !after.files.contains_key(path) {
+                changes.deleted.push(FileChange::new(path, Some(old), None));
+            }
+        }
+
+        changes
+    }
+}
+
+fn read_entry(path: &Path, max_diff_bytes: u64) -> Option<FileEntry> {
+    let metadata = fs::metadata(path).ok()?;
+    let content = fs::read(path).ok()?;
+    let binary = content.iter().take(8192).any(|b| *b == 0) || std::str::from_utf8(&content).is_err();
+    let text = if !binary && metadata.len() <= max_diff_bytes {
+        String::from_utf8(content.clone()).ok()
+    } else {
+        None
+    };
+
+    Some(FileEntry {
+        size: metadata.len(),
+        mtime: metadata.modified().ok(),
+        hash: blake3::hash(&content).to_hex().to_string(),
+        binary,
+        text,
+    })
+}
+
+/// A created, modified or deleted file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileChange {
+    pub path: String,
+    pub size_before: Option<u64>,
+    pub size_after: Option<u64>,
+    /// Last modification time of the new version
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified_at: Option<DateTime<Utc>>,
+    /// Binary files only report their size delta
+    pub binary: bool,
+    /// Unified diff for text files under the size cap
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+}
+
+impl FileChange {
+    fn new(path: &str, before: Option<&FileEntry>, after: Option<&FileEntry>) -> Self {
+        let binary = before.is_some_and(|f| f.binary) || after.is_some_and(|f| f.binary);
+        let old_text = match before {
+            Some(file) => file.text.as_deref(),
+            None => Some(""),
+        };
+        let new_text = match after {
+            Some(file) => file.text.as_deref(),
+            None => Some(""),
+        };
+
+        let diff = match (binary, old_text, new_text) {
+            (false, Some(old), Some(new)) => Some(
+                TextDiff::from_lines(old, new)
+                    .unified_diff()
+                    .header(&format!("a/{}", path), &format!("b/{}", path))
+                    .to_string(),
+            ),
+            _ => None,
+        };
+
+        Self {
+            path: path.to_string(),
+            size_before: before.map(|f| f.size),
+            size_after: after.map(|f| f.size),
+            modified_at: after.and_then(|f| f.mtime).map(DateTime::<Utc>::from),
+            binary,
+            diff,
+        }
+    }
+}
+
+/// Changes made to the workspace by one request
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorkspaceChanges {
+    pub request_id: String,
+    pub created: Vec<FileChange>,
+    pub modified: Vec<FileChange>,
+    pub deleted: Vec<FileChange>,
+}
+
+impl WorkspaceChanges {
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.modified.is_empty() && self.deleted.is_empty()
+    }
+
+    /// Same changes without the diffs (for events and final payloads)
+    pub fn summary(&self) -> Self {
+        let strip = |changes: &[FileChange]| -> Vec<FileChange> {
+            changes.iter().map(|c| FileChange { diff: None, ..c.clone() }).collect()
+        };
+        Self {
+            request_id: self.request_id.clone(),
+            created: strip(&self.created),
+            modified: strip(&self.modified),
+            deleted: strip(&self.deleted),
+        }
+    }
+}
+
+/// Recent change sets of a session, newest last
+#[derive(Debug, Clone, Default)]
+pub struct WorkspaceChangeLog(Arc<StdMutex<VecDeque<WorkspaceChanges>>>);
+
+impl WorkspaceChangeLog {
+    pub fn push(&self, changes: WorkspaceChanges) {
+        let mut log = self.0.lock().unwrap();
+        if log.len() >= CHANGES_HISTORY {
+            log.pop_front();
+        }
+        log.push_back(changes);
+    }
+
+    pub fn get(&self, request_id: &str) -> Option<WorkspaceChanges> {
+        self.0.lock().unwrap().iter().find(|c| c.request_id == request_id).cloned()
+    }
+}
+
+/// Snapshot taken when a request starts, turned into a change set when it ends
+pub struct WorkspaceTracker {
+    config: WorkspaceConfig,
+    before: WorkspaceManifest,
+    request_id: String,
+    log: WorkspaceChangeLog,
+}
+
+impl WorkspaceTracker {
+    /// Capture the workspace before the request runs
+    pub async fn start(config: WorkspaceConfig, request_id: String, log: WorkspaceChangeLog) -> Self {
+        let snapshot_config = config.clone();
+        let before = tokio::task::spawn_blocking(move || WorkspaceManifest::capture(&snapshot_config))
+            .await
+            .unwrap_or_default();
+        debug!("[{}] workspace snapshot: {} files", request_id, before.len());
+        Self { config, before, request_id, log }
+    }
+
+    /// Capture the workspace again, record and return the changes
+    pub async fn finish(self) -> WorkspaceChanges {
+        let Self { config, before, request_id, log } = self;
+        let changes = tokio::task::spawn_blocking(move || {
+            let after = WorkspaceManifest::capture(&config);
+            before.changes(&after, &request_id)
+        })
+        .await
+        .unwrap_or_default();
+
+        log.push(changes.clone());
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_workspace() -> WorkspaceConfig {
+        let root = std::env::temp_dir().join(format!("shai-workspace-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        WorkspaceConfig::new(root, vec![".git".to_string(), "node_modules".to_string()])
+    }
+
+    #[test]
+    fn test_created_modified_deleted() {
+        let config = temp_workspace();
+        let root = config.root.clone();
+        fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(root.join("old.txt"), "bye\n").unwrap();
+        fs::write(root.join("image.bin"), [0u8, 1, 2]).unwrap();
+        fs::write(root.join(".git/HEAD"), "ref: main\n").unwrap();
+
+        let before = WorkspaceManifest::capture(&config);
+        assert_eq!(before.len(), 3, ".git must be ignored");
+
+        fs::write(root.join("src/main.rs"), "fn main() {\n    println!(\"hi\");\n}\n").unwrap();
+        fs::write(root.join("new.txt"), "hello\n").unwrap();
+        fs::remove_file(root.join("old.txt")).unwrap();
+        fs::write(root.join("image.bin"), [0u8, 1, 2, 3, 4]).unwrap();
+        fs::write(root.join(".git/HEAD"), "ref: other\n").unwrap();
+
+        let after = WorkspaceManifest::capture(&config);
+        let changes = before.changes(&after, "req-1");
+
+        assert_eq!(changes.created.len(), 1);
+        assert_eq!(changes.created[0].path, "new.txt");
+        assert_eq!(changes.deleted[0].path, "old.txt");
+        assert_eq!(changes.modified.len(), 2);
+
+        let main = changes.modified.iter().find(|c| c.path.ends_with("main.rs")).unwrap();
+        assert!(main.diff.as_ref().unwrap().contains("+    println!(\"hi\");"));
+
+        let image = changes.modified.iter().find(|c| c.path == "image.bin").unwrap();
+        assert!(image.binary);
+        assert!(image.diff.is_none());
+        assert_eq!((image.size_before, image.size_after), (Some(3), Some(5)));
+
+        assert!(changes.summary().modified.iter().all(|c| c.diff.is_none()));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_change_log_keeps_recent_requests() {
+        let log = WorkspaceChangeLog::default();
+        for i in 0..CHANGES_HISTORY + 1 {
+            log.push(WorkspaceChanges { request_id: format!("req-{}", i), ..Default::default() });
+        }
+        assert!(log.get("req-0").is_none());
+        assert!(log.get(&format!("req-{}", CHANGES_HISTORY)).is_some());
+    }
+}
@@ -0,0 +1,357 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use axum::{http::StatusCode, routing::post, Json, Router};
+use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent};
+use openai_dive::v1::resources::response::request::ResponseParameters;
+use openai_dive::v1::resources::response::response::ResponseOutput;
+use shai_http::apis::sessions::SessionStatus;
+use shai_http::access::NOT_ALLOWED_CODE;
+use shai_http::rules::RULE_REJECTED_CODE;
+use shai_http::session::{RequestOverrides, SessionAttributes, SessionPersist};
+use shai_http::{
+    build_router, AgentSession, ApiKeys, ClientConfig, ClientError, Feature, FeatureConfig, Features, ModelRegistry, ModelRoute,
+    ServerConfig, ServerState, SessionStoreConfig, ShaiClient, TransformRules,
+};
+use uuid::Uuid;
+
+/// Serve a router on a random local port, returns its URL
+async fn serve(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", address)
+}
+
+/// The shai router nested under /ai, the client is configured with that base path
+async fn shai_client(config: ServerConfig, client_config: impl FnOnce(ClientConfig) -> ClientConfig) -> ShaiClient {
+    let url = serve(Router::new().nest("/ai", build_router(ServerState::new(config)))).await;
+    ShaiClient::new(client_config(ClientConfig::new(format!("{}/ai/", url)))).unwrap()
+}
+
+fn unknown_id() -> String {
+    format!("sess-{}", Uuid::new_v4())
+}
+
+#[tokio::test]
+async fn test_cancel_under_base_path() {
+    let client = shai_client(ServerConfig::new("127.0.0.1:0".to_string()), |c| c).await;
+
+    let status = client.cancel_response("resp_unknown").await.unwrap();
+    assert_eq!(status.id, "resp_unknown");
+    assert_eq!(status.object, "response");
+    assert_eq!(status.status, "cancelled");
+}
+
+#[tokio::test]
+async fn test_errors_are_typed() {
+    let client = shai_client(ServerConfig::new("127.0.0.1:0".to_string()), |c| c).await;
+    let id = unknown_id();
+
+    for result in [client.delete_session(&id).await, client.restore_session(&id).await] {
+        match result {
+            Err(ClientError::Api { status: 404, error }) => assert_eq!(error.r#type, "not_found"),
+            other => panic!("expected a not found error, got {:?}", other),
+        }
+    }
+    assert_eq!(client.get_session(&id).await.unwrap_err().status(), Some(404));
+    assert_eq!(client.request_changes(&id, "req-1").await.unwrap_err().status(), Some(404));
+    assert_eq!(client.list_input_items(&id, Some(10), None).await.unwrap_err().status(), Some(404));
+
+    // no admin token on the server: permanent deletion is disabled
+    assert_eq!(client.purge_session(&id).await.unwrap_err().status(), Some(403));
+}
+
+#[tokio::test]
+async fn test_stored_response_roundtrip() {
+    let client = shai_client(ServerConfig::new("127.0.0.1:0".to_string()), |c| c).await;
+    let id = format!("resp_{}", Uuid::new_v4());
+
+    // what a stored response leaves behind once its request ended
+    let trace = vec![
+        ChatMessage::User { content: ChatMessageContent::Text("hi".to_string()), name: None },
+        ChatMessage::Assistant {
+            content: Some(ChatMessageContent::Text("Hello!".to_string())),
+            reasoning_content: None,
+            refusal: None,
+            name: None,
+            audio: None,
+            tool_calls: None,
+        },
+    ];
+    let attributes = SessionAttributes { agent_name: Some("coder".to_string()), ..Default::default() };
+    SessionPersist::save_session(&id, trace, &attributes, Vec::new(), Vec::new(), None).await.unwrap();
+
+    let response = client.get_response(&id).await.unwrap();
+    assert_eq!(response.id, id);
+    assert_eq!(response.model, "coder");
+    assert_eq!(response.output.len(), 1);
+    assert!(matches!(&response.output[0], ResponseOutput::Message(_)));
+
+    let deleted = client.delete_response(&id).await.unwrap();
+    assert_eq!((deleted.id.as_str(), deleted.deleted), (id.as_str(), true));
+
+    for status in [client.get_response(&id).await.unwrap_err().status(), client.delete_response(&id).await.unwrap_err().status()] {
+        assert_eq!(status, Some(404));
+    }
+}
+
+#[tokio::test]
+async fn test_purge_requires_the_admin_token() {
+    let config = ServerConfig::new("127.0.0.1:0".to_string()).with_admin_token(Some("secret".to_string()));
+    let state = ServerState::new(config);
+    let url = serve(build_router(state)).await;
+    let id = unknown_id();
+
+    let wrong = ShaiClient::new(ClientConfig::new(&url).with_admin_token("guess")).unwrap();
+    assert_eq!(wrong.purge_session(&id).await.unwrap_err().status(), Some(403));
+
+    // authorized, the session just does not exist
+    let admin = ShaiClient::new(ClientConfig::new(&url).with_admin_token("secret")).unwrap();
+    assert_eq!(admin.purge_session(&id).await.unwrap_err().status(), Some(404));
+}
+
+/// Server storing its sessions in `folder`, with the session `id` loaded
+/// Its agent runs on a local provider, the tests do not need it to answer
+async fn server_with_session(folder: &std::path::Path, id: &str) -> (String, Arc<AgentSession>) {
+    let store = SessionStoreConfig::File { folder: folder.to_path_buf(), compression: None, encryption: None };
+    let config = ServerConfig::new("127.0.0.1:0".to_string())
+        .with_session_store(store)
+        .with_admin_token(Some("secret".to_string()));
+    let state = ServerState::new(config);
+    let attributes = SessionAttributes {
+        overrides: RequestOverrides {
+            route: Some(ModelRoute { provider: "ollama".to_string(), model: "mock".to_string() }),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let session = state.session_manager.create_new_session_with("test", id, None, false, attributes).await.unwrap();
+    (serve(build_router(state)).await, session)
+}
+
+#[tokio::test]
+async fn test_delete_refused_while_a_request_runs() {
+    let folder = tempfile::tempdir().unwrap();
+    let id = unknown_id();
+    let (url, session) = server_with_session(folder.path(), &id).await;
+    let client = ShaiClient::new(ClientConfig::new(&url)).unwrap();
+
+    // the request holds the session lock until it is dropped
+    let message = ChatMessage::User { content: ChatMessageContent::Text("hi".to_string()), name: None };
+    let _request = session.handle_request(&"test".to_string(), vec![message], Features::default()).await.unwrap();
+    assert!(session.is_busy());
+    assert_eq!(client.delete_session(&id).await.unwrap_err().status(), Some(409));
+    assert_eq!(client.get_session(&id).await.unwrap().id, id);
+
+    let response = reqwest::Client::new()
+        .delete(format!("{}/v1/sessions/{}?force=true", url, id))
+        .send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.json::<SessionStatus>().await.unwrap().status, "trashed");
+    assert_eq!(client.get_session(&id).await.unwrap_err().status(), Some(404));
+}
+
+#[tokio::test]
+async fn test_get_response_streams_while_in_progress() {
+    let folder = tempfile::tempdir().unwrap();
+    let id = unknown_id();
+    let (url, session) = server_with_session(folder.path(), &id).await;
+    let http = reqwest::Client::new();
+    let content_type = |response: &reqwest::Response| {
+        response.headers().get("content-type").and_then(|value| value.to_str().ok()).unwrap_or_default().to_string()
+    };
+
+    // an idle response is returned
+    let response = http.get(format!("{}/v1/responses/{}", url, id)).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(content_type(&response).starts_with("application/json"));
+
+    // one in progress streams its events unless the caller asks for the object
+    let message = ChatMessage::User { content: ChatMessageContent::Text("hi".to_string()), name: None };
+    let _request = session.handle_request(&"test".to_string(), vec![message], Features::default()).await.unwrap();
+    let response = http.get(format!("{}/v1/responses/{}", url, id)).send().await.unwrap();
+    assert!(content_type(&response).starts_with("text/event-stream"));
+    let response = http.get(format!("{}/v1/responses/{}?stream=false", url, id)).send().await.unwrap();
+    assert!(content_type(&response).starts_with("application/json"));
+}
+
+#[tokio::test]
+async fn test_purge_is_admin_only() {
+    let folder = tempfile::tempdir().unwrap();
+    let id = unknown_id();
+    let (url, _session) = server_with_session(folder.path(), &id).await;
+    let http = reqwest::Client::new();
+    let session_url = format!("{}/v1/sessions/{}", url, id);
+
+    for query in ["purge=true", "permanent=true"] {
+        let status = http.delete(format!("{}?{}", session_url, query)).send().await.unwrap().status();
+        assert_eq!(status, 403, "?{} answered without the admin token", query);
+        let status = http.delete(format!("{}?{}", session_url, query))
+            .header("X-Shai-Admin-Token", "guess")
+            .send().await.unwrap().status();
+        assert_eq!(status, 403, "?{} answered with a wrong admin token", query);
+    }
+    // the refused purges left the session alone
+    assert_eq!(http.get(&session_url).send().await.unwrap().status(), 200);
+
+    let response = http.delete(format!("{}?purge=true", session_url))
+        .header("X-Shai-Admin-Token", "secret")
+        .send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.json::<SessionStatus>().await.unwrap().status, "deleted");
+    assert_eq!(http.get(&session_url).send().await.unwrap().status(), 404);
+}
+
+#[tokio::test]
+async fn test_admin_routes_require_the_admin_token() {
+    let config = ServerConfig::new("127.0.0.1:0".to_string()).with_admin_token(Some("secret".to_string()));
+    let url = serve(build_router(ServerState::new(config))).await;
+    let http = reqwest::Client::new();
+
+    for path in ["artifacts/verify", "replays/replay_unknown", "providers", "tools/stats"] {
+        let status = http.get(format!("{}/v1/admin/{}", url, path)).send().await.unwrap().status();
+        assert_eq!(status, 403, "{} answered without the admin token", path);
+    }
+    let status = http.get(format!("{}/v1/admin/providers", url))
+        .header("X-Shai-Admin-Token", "secret")
+        .send().await.unwrap().status();
+    assert_eq!(status, 200);
+}
+
+#[tokio::test]
+async fn test_only_idempotent_calls_are_retried() {
+    let hits = Arc::new(AtomicUsize::new(0));
+
+    // restore fails once then succeeds, responses always fail
+    let restore_hits = hits.clone();
+    let response_hits = hits.clone();
+    let app = Router::new()
+        .route("/v1/sessions/{session_id}/restore", post(move || {
+            let hits = restore_hits.clone();
+            async move {
+                match hits.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(StatusCode::SERVICE_UNAVAILABLE),
+                    _ => Ok(Json(SessionStatus { id: "s1".to_string(), object: "session".to_string(), status: "restored".to_string() })),
+                }
+            }
+        }))
+        .route("/v1/responses", post(move || {
+            let hits = response_hits.clone();
+            async move {
+                hits.fetch_add(1, Ordering::SeqCst);
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+        }));
+    let client = ShaiClient::new(ClientConfig::new(serve(app).await).with_max_retries(2)).unwrap();
+
+    let status = client.restore_session("s1").await.unwrap();
+    assert_eq!(status.status, "restored");
+    assert_eq!(hits.swap(0, Ordering::SeqCst), 2);
+
+    let error = client.create_response(ResponseParameters::default()).await.unwrap_err();
+    assert_eq!(error.status(), Some(503));
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_rules_reject_before_any_session() {
+    let rules: TransformRules = serde_json::from_value(serde_json::json!({ "rules": [
+        { "name": "legacy-key", "match": { "api_key": "sk-legacy" },
+          "actions": [{ "action": "reject", "message": "This key was retired" }] }
+    ]})).unwrap();
+    let config = ServerConfig::new("127.0.0.1:0".to_string()).with_rules(rules).unwrap();
+    let client = shai_client(config, |c| c.with_api_key("sk-legacy")).await;
+
+    let params = serde_json::from_value(serde_json::json!({
+        "model": "gpt-4o", "messages": [{ "role": "user", "content": "hi" }]
+    })).unwrap();
+    match client.chat_completion(params).await {
+        Err(ClientError::Api { status: 400, error }) => {
+            assert_eq!(error.code.as_deref(), Some(RULE_REJECTED_CODE));
+            assert_eq!(error.message, "This key was retired");
+        }
+        other => panic!("expected the rule to reject the request, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_rules_and_strict_feature_flags() {
+    let rules: TransformRules = serde_json::from_value(serde_json::json!({ "rules": [
+        { "name": "retired", "match": { "model": "legacy-*" },
+          "actions": [{ "action": "reject", "message": "This model was retired" }] }
+    ]})).unwrap();
+    let body = serde_json::json!({ "model": "legacy-1", "messages": [{ "role": "user", "content": "hi" }] });
+
+    for strict in [true, false] {
+        let features = FeatureConfig { overridable: vec![Feature::WorkspaceTracking], strict, ..Default::default() };
+        let config = ServerConfig::new("127.0.0.1:0".to_string()).with_rules(rules.clone()).unwrap().with_features(features);
+        let url = serve(build_router(ServerState::new(config))).await;
+        let send = |flags: &'static str| {
+            reqwest::Client::new()
+                .post(format!("{}/v1/chat/completions", url))
+                .header("X-Shai-Features", flags)
+                .json(&body)
+                .send()
+        };
+
+        // strict mode refuses the invalid flags before any rule fires, otherwise they are ignored
+        let response = send("compaction=on").await.unwrap();
+        assert_eq!(response.status(), 400);
+        let error: serde_json::Value = response.json().await.unwrap();
+        match strict {
+            true => assert!(error["error"]["message"].as_str().unwrap().contains("x-shai-features")),
+            false => assert_eq!(error["error"]["code"], RULE_REJECTED_CODE),
+        }
+
+        // valid flags leave the request to the rules
+        let error: serde_json::Value = send("workspace-tracking=on").await.unwrap().json().await.unwrap();
+        assert_eq!(error["error"]["code"], RULE_REJECTED_CODE);
+    }
+}
+
+#[tokio::test]
+async fn test_rewritten_model_is_checked_against_the_key() {
+    let rules: TransformRules = serde_json::from_value(serde_json::json!({ "rules": [
+        { "name": "upgrade", "match": { "model": "coder" },
+          "actions": [{ "action": "rewrite_model", "model": "ops" }] }
+    ]})).unwrap();
+    let api_keys: ApiKeys = serde_json::from_value(serde_json::json!({ "keys": {
+        "sk-ci": { "allowed_agents": ["coder*"] }
+    }})).unwrap();
+    let config = ServerConfig::new("127.0.0.1:0".to_string()).with_rules(rules).unwrap().with_api_keys(api_keys);
+    let client = shai_client(config, |c| c.with_api_key("sk-ci")).await;
+
+    let params = serde_json::from_value(serde_json::json!({
+        "model": "coder", "messages": [{ "role": "user", "content": "hi" }]
+    })).unwrap();
+    match client.chat_completion(params).await {
+        Err(ClientError::Api { status: 403, error }) => {
+            assert_eq!(error.code.as_deref(), Some(NOT_ALLOWED_CODE));
+            assert!(error.message.contains("'ops'") && error.message.contains("coder*"));
+        }
+        other => panic!("expected the rewritten model to be refused, got {:?}", other),
+    }
+
+    // the default agent is not in the allowance either
+    let models = client.list_models().await.unwrap();
+    assert!(models.data.iter().all(|model| model.id.starts_with("coder")));
+}
+
+#[tokio::test]
+async fn test_model_registry() {
+    let models = ModelRegistry::default().with_model("fast", "groq", "llama-3.1-8b-instant").with_provider("ollama");
+    let client = shai_client(ServerConfig::new("127.0.0.1:0".to_string()).with_models(models), |c| c).await;
+
+    let listed = client.list_models().await.unwrap();
+    let fast = listed.data.iter().find(|model| model.id == "fast").unwrap();
+    assert_eq!(fast.owned_by, "groq");
+
+    let params = serde_json::from_value(serde_json::json!({
+        "model": "no-such-model", "messages": [{ "role": "user", "content": "hi" }]
+    })).unwrap();
+    match client.chat_completion(params).await {
+        Err(ClientError::Api { status: 404, error }) => assert_eq!(error.code.as_deref(), Some("model_not_found")),
+        other => panic!("expected an unknown model error, got {:?}", other),
+    }
+}
@@ -0,0 +1,174 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::get,
+    Router,
+};
+use shai_http::{build_router, AuthConfig, ModelRegistry, ServerConfig, ServerState};
+use tower::ServiceExt;
+
+fn host_app() -> Router {
+    let config = ServerConfig::new("127.0.0.1:0".to_string());
+    Router::new()
+        .route("/health", get(|| async { "ok" }))
+        .nest("/ai", build_router(ServerState::new(config)))
+}
+
+#[tokio::test]
+async fn test_router_nested_under_prefix() {
+    let response = host_app()
+        .oneshot(
+            Request::post("/ai/v1/responses/resp_unknown/cancel")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["id"], "resp_unknown");
+    assert_eq!(json["status"], "cancelled");
+}
+
+#[tokio::test]
+async fn test_host_routes_untouched() {
+    let app = host_app();
+
+    let response = app.clone()
+        .oneshot(Request::get("/health").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // shai routes only exist under the prefix
+    let response = app
+        .oneshot(Request::post("/v1/responses/resp_unknown/cancel").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_bearer_token_required() {
+    let auth = AuthConfig::new(vec!["sk-test".to_string()]).with_exempt_paths(vec!["/v1/ready".to_string()]);
+    let config = ServerConfig::new("127.0.0.1:0".to_string()).with_auth(auth);
+    let app = Router::new().nest("/ai", build_router(ServerState::new(config)));
+    let cancel = |token: Option<&str>| {
+        let mut request = Request::post("/ai/v1/responses/resp_unknown/cancel");
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        request.body(Body::empty()).unwrap()
+    };
+
+    for token in [None, Some("sk-wrong")] {
+        let response = app.clone().oneshot(cancel(token)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"]["code"], "invalid_api_key");
+    }
+
+    let response = app.clone().oneshot(cancel(Some("sk-test"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // exempt paths are relative to the router
+    let response = app
+        .oneshot(Request::get("/ai/v1/ready").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_ne!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_list_sessions_empty() {
+    let response = host_app()
+        .oneshot(Request::get("/ai/v1/sessions?limit=10&offset=0").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json, serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn test_history_of_unknown_session() {
+    let response = host_app()
+        .oneshot(Request::get("/ai/v1/sessions/sess_unknown/history?limit=10").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["error"]["type"], "invalid_request_error");
+    assert_eq!(json["error"]["code"], "session_not_found");
+}
+
+#[tokio::test]
+async fn test_openai_error_bodies() {
+    let app = host_app();
+    let post = |body: &str| {
+        Request::post("/ai/v1/chat/completions")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    let response = app.clone().oneshot(post("{\"model\": ")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["error"]["type"], "invalid_request_error");
+    assert_eq!(json["error"]["code"], "invalid_request");
+    assert!(json["error"]["param"].is_null());
+    assert!(!json["error"]["message"].as_str().unwrap().is_empty());
+
+    let response = app
+        .clone()
+        .oneshot(post(r#"{"model": "default", "n": 99, "messages": [{"role": "user", "content": "hi"}]}"#))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["error"]["param"], "n");
+
+    let response = app
+        .oneshot(post(r#"{"model": "default", "n": 2, "stream": true, "messages": [{"role": "user", "content": "hi"}]}"#))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["error"]["type"], "not_implemented");
+    assert_eq!(json["error"]["param"], "n");
+}
+
+#[tokio::test]
+async fn test_embeddings_unsupported_by_provider() {
+    // the handler refuses before reaching anthropic, the key is never sent
+    std::env::set_var("ANTHROPIC_API_KEY", "sk-unused");
+    let models = ModelRegistry::default().with_model("claude", "anthropic", "claude-3-5-haiku-latest");
+    let config = ServerConfig::new("127.0.0.1:0".to_string()).with_models(models);
+    let app = Router::new().nest("/ai", build_router(ServerState::new(config)));
+
+    let response = app
+        .oneshot(
+            Request::post("/ai/v1/embeddings")
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"model": "claude", "input": "hello"}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["error"]["type"], "not_implemented");
+    assert!(json["error"]["message"].as_str().unwrap().contains("anthropic"));
+}
@@ -0,0 +1,159 @@
+use axum::{routing::post, Json, Router};
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use shai_http::session::{RequestOverrides, SessionAttributes};
+use shai_http::{build_router, AuthConfig, ModelRegistry, ModelRoute, ServerConfig, ServerState, SessionStoreConfig};
+use std::sync::OnceLock;
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Error, Message};
+
+/// Serve a router on a random local port, returns its address
+async fn serve(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    address.to_string()
+}
+
+/// HTTP status of a refused handshake
+async fn handshake_status(url: &str, token: Option<&str>) -> u16 {
+    let mut request = url.into_client_request().unwrap();
+    if let Some(token) = token {
+        request.headers_mut().insert("authorization", format!("Bearer {}", token).parse().unwrap());
+    }
+    match tokio_tungstenite::connect_async(request).await {
+        Err(Error::Http(response)) => response.status().as_u16(),
+        Err(e) => panic!("unexpected websocket error: {}", e),
+        Ok(_) => panic!("the handshake should have been refused"),
+    }
+}
+
+#[tokio::test]
+async fn test_socket_requires_a_loaded_session() {
+    let address = serve(build_router(ServerState::new(ServerConfig::new("127.0.0.1:0".to_string())))).await;
+    let status = handshake_status(&format!("ws://{}/v1/sessions/sess-unknown/ws", address), None).await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn test_socket_behind_auth() {
+    let config = ServerConfig::new("127.0.0.1:0".to_string()).with_auth(AuthConfig::new(vec!["sk-test".to_string()]));
+    let address = serve(build_router(ServerState::new(config))).await;
+    let url = format!("ws://{}/v1/sessions/sess-unknown/ws?approvals=true", address);
+
+    assert_eq!(handshake_status(&url, None).await, 401);
+    // past the auth layer, the session lookup answers
+    assert_eq!(handshake_status(&url, Some("sk-test")).await, 404);
+}
+
+/// OpenAI-compatible LLM answering every completion with `content`
+fn mock_llm(content: &'static str) -> Router {
+    Router::new().route("/v1/chat/completions", post(move |Json(request): Json<Value>| async move {
+        Json(json!({
+            "id": "cmpl-1",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": request["model"],
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": content },
+                "finish_reason": "stop"
+            }],
+            "usage": { "prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4 }
+        }))
+    }))
+}
+
+/// Point the ollama provider at a mock LLM answering "hello from the agent"
+/// The mock runs on its own runtime, shared by the tests as the environment variable is
+fn use_mock_llm() {
+    static LLM: OnceLock<String> = OnceLock::new();
+    let address = LLM.get_or_init(|| {
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            runtime.block_on(async move {
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                tx.send(listener.local_addr().unwrap().to_string()).unwrap();
+                axum::serve(listener, mock_llm("hello from the agent")).await.unwrap()
+            })
+        });
+        rx.recv().unwrap()
+    });
+    std::env::set_var("OLLAMA_BASE_URL", format!("http://{}/v1", address));
+}
+
+#[tokio::test]
+async fn test_socket_roundtrip() {
+    use_mock_llm();
+
+    let folder = tempfile::tempdir().unwrap();
+    let store = SessionStoreConfig::File { folder: folder.path().to_path_buf(), compression: None, encryption: None };
+    let state = ServerState::new(ServerConfig::new("127.0.0.1:0".to_string()).with_session_store(store));
+    let attributes = SessionAttributes {
+        overrides: RequestOverrides {
+            route: Some(ModelRoute { provider: "ollama".to_string(), model: "mock".to_string() }),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    state.session_manager
+        .create_new_session_with("test", "sess-ws", None, true, attributes)
+        .await
+        .unwrap();
+    let address = serve(build_router(state)).await;
+
+    let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/v1/sessions/sess-ws/ws", address)).await.unwrap();
+    let message = json!({ "type": "message", "content": "say hello" });
+    socket.send(Message::Text(message.to_string().into())).await.unwrap();
+
+    // the session is ephemeral: the server closes the socket once the request ended
+    let mut frames = Vec::new();
+    let receive = async {
+        while let Some(Ok(Message::Text(text))) = socket.next().await {
+            frames.push(serde_json::from_str::<Value>(text.as_str()).unwrap());
+        }
+    };
+    tokio::time::timeout(std::time::Duration::from_secs(10), receive).await.expect("the request did not end");
+
+    let answer = frames.iter()
+        .find(|frame| frame["event"] == "message" && frame["data"]["assistant"].is_string())
+        .unwrap_or_else(|| panic!("no answer in {:?}", frames));
+    assert_eq!(answer["data"]["id"], "sess-ws");
+    assert_eq!(answer["data"]["assistant"], "hello from the agent");
+    assert!(frames.iter().any(|frame| frame["event"] == "run.summary"));
+    assert!(frames.iter().all(|frame| frame["event"] != "error"), "{:?}", frames);
+}
+
+#[tokio::test]
+async fn test_chat_completion_with_several_choices() {
+    use_mock_llm();
+
+    let folder = tempfile::tempdir().unwrap();
+    let store = SessionStoreConfig::File { folder: folder.path().to_path_buf(), compression: None, encryption: None };
+    let config = ServerConfig::new("127.0.0.1:0".to_string())
+        .with_session_store(store)
+        .with_models(ModelRegistry::default().with_model("mock", "ollama", "mock"))
+        .with_max_choices(4, 2);
+    let address = serve(build_router(ServerState::new(config))).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("http://{}/v1/chat/completions", address))
+        .json(&json!({ "model": "mock", "n": 3, "messages": [{ "role": "user", "content": "say hello" }] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let completion: Value = response.json().await.unwrap();
+
+    let choices = completion["choices"].as_array().unwrap();
+    assert_eq!(choices.len(), 3);
+    for (index, choice) in choices.iter().enumerate() {
+        assert_eq!(choice["index"], index);
+        assert_eq!(choice["message"]["content"], "hello from the agent");
+        assert_eq!(choice["finish_reason"], "stop");
+    }
+    // each choice is an agent run of one LLM call, the usage of the response sums them
+    assert_eq!(completion["usage"]["prompt_tokens"], 9);
+    assert_eq!(completion["usage"]["completion_tokens"], 3);
+    assert_eq!(completion["usage"]["total_tokens"], 12);
+}
@@ -0,0 +1,406 @@
+use std::sync::{OnceLock, RwLock};
+
+use openai_dive::v1::resources::chat::{ChatCompletionParameters, ChatMessage, ChatMessageContent, ChatMessageContentPart};
+use serde::{Deserialize, Serialize};
+
+/// Roles a transform can rename between (they share the same content shape)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptRole {
+    System,
+    Developer,
+    User,
+}
+
+/// A rewrite of the outgoing message list
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PromptTransform {
+    /// Move system (and developer) instructions to the top of the first user message
+    MergeSystemIntoFirstUser,
+    /// Write tool calls into the assistant text and tool results as user messages,
+    /// for models without native tool turns
+    ToolTranscriptAsText,
+    /// Join consecutive text messages of the same role
+    CollapseConsecutive,
+    /// Send messages of one role as another
+    RenameRole { from: PromptRole, to: PromptRole },
+}
+
+/// Transforms applied to the messages sent to the models matching `pattern`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptAdapter {
+    /// Model name pattern, case-insensitive, `*` matches any run of characters
+    pub pattern: String,
+    /// Applied in order, an empty list disables adaptation
+    #[serde(default)]
+    pub transforms: Vec<PromptTransform>,
+}
+
+impl PromptAdapter {
+    pub fn new(pattern: &str, transforms: Vec<PromptTransform>) -> Self {
+        Self { pattern: pattern.to_string(), transforms }
+    }
+
+    pub fn matches(&self, model: &str) -> bool {
+        wildcard_match(&self.pattern.to_lowercase(), &model.to_lowercase())
+    }
+
+    /// Apply the transforms, the input is left untouched when there are none
+    pub fn apply(&self, messages: Vec<ChatMessage>) -> Vec<ChatMessage> {
+        self.transforms.iter().fold(messages, |messages, transform| transform.apply(messages))
+    }
+}
+
+/// Adapters of the local model families with known chat template quirks
+pub fn builtin_prompt_adapters() -> &'static [PromptAdapter] {
+    static BUILTIN: OnceLock<Vec<PromptAdapter>> = OnceLock::new();
+    BUILTIN.get_or_init(|| vec![
+        // Gemma templates have no system turn and expect strict user/model alternation
+        PromptAdapter::new("*gemma*", vec![PromptTransform::MergeSystemIntoFirstUser, PromptTransform::CollapseConsecutive]),
+        // DeepSeek-R1 is tuned to receive all instructions in the user prompt
+        PromptAdapter::new("*deepseek-r1*", vec![PromptTransform::MergeSystemIntoFirstUser]),
+        // Phi-3 templates know neither tool turns nor consecutive same-role turns
+        PromptAdapter::new("*phi3*", vec![PromptTransform::ToolTranscriptAsText, PromptTransform::CollapseConsecutive]),
+        PromptAdapter::new("*phi-3*", vec![PromptTransform::ToolTranscriptAsText, PromptTransform::CollapseConsecutive]),
+    ])
+}
+
+static PROMPT_ADAPTER_OVERRIDES: RwLock<Vec<PromptAdapter>> = RwLock::new(Vec::new());
+
+/// Replace the configured adapters, they are looked up before the built-in table
+pub fn set_prompt_adapters(adapters: Vec<PromptAdapter>) {
+    *PROMPT_ADAPTER_OVERRIDES.write().unwrap() = adapters;
+}
+
+/// Adapter of a model: the first matching configured adapter, else the first built-in one
+pub fn prompt_adapter_for(model: &str) -> Option<PromptAdapter> {
+    let overrides = PROMPT_ADAPTER_OVERRIDES.read().unwrap();
+    overrides.iter()
+        .chain(builtin_prompt_adapters())
+        .find(|adapter| adapter.matches(model))
+        .cloned()
+}
+
+pub trait AdaptPrompt {
+    /// Rewrite the messages for the chat template quirks of the requested model
+    fn adapt_prompt(self) -> ChatCompletionParameters;
+}
+
+impl AdaptPrompt for ChatCompletionParameters {
+    fn adapt_prompt(mut self) -> ChatCompletionParameters {
+        if let Some(adapter) = prompt_adapter_for(&self.model) {
+            self.messages = adapter.apply(self.messages);
+        }
+        self
+    }
+}
+
+impl PromptTransform {
+    pub fn apply(&self, messages: Vec<ChatMessage>) -> Vec<ChatMessage> {
+        match self {
+            Self::MergeSystemIntoFirstUser => merge_system_into_first_user(messages),
+            Self::ToolTranscriptAsText => tool_transcript_as_text(messages),
+            Self::CollapseConsecutive => collapse_consecutive(messages),
+            Self::RenameRole { from, to } => messages.into_iter().map(|message| rename_role(message, *from, *to)).collect(),
+        }
+    }
+}
+
+/// `*` glob match, the only wildcard of model patterns
+pub fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // no wildcard: exact match
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// Text of a content, None if it holds non-text parts
+fn content_text(content: &ChatMessageContent) -> Option<String> {
+    match content {
+        ChatMessageContent::Text(text) => Some(text.clone()),
+        ChatMessageContent::ContentPart(parts) => parts.iter()
+            .map(|part| match part {
+                ChatMessageContentPart::Text(part) => Some(part.text.clone()),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|texts| texts.join("\n")),
+        ChatMessageContent::None => Some(String::new()),
+    }
+}
+
+fn text(text: String) -> ChatMessageContent {
+    ChatMessageContent::Text(text)
+}
+
+fn merge_system_into_first_user(messages: Vec<ChatMessage>) -> Vec<ChatMessage> {
+    let mut instructions = Vec::new();
+    let mut rest = Vec::with_capacity(messages.len());
+    for message in messages {
+        let instruction = match &message {
+            ChatMessage::System { content, .. } | ChatMessage::Developer { content, .. } => content_text(content),
+            _ => None,
+        };
+        match instruction {
+            Some(text) if !text.is_empty() => instructions.push(text),
+            Some(_) => {}
+            None => rest.push(message),
+        }
+    }
+    if instructions.is_empty() {
+        return rest;
+    }
+
+    let instructions = instructions.join("\n\n");
+    let first_user = rest.iter().position(|message| matches!(message, ChatMessage::User { content, .. } if content_text(content).is_some()));
+    match first_user {
+        Some(index) => {
+            if let ChatMessage::User { content, .. } = &mut rest[index] {
+                let prompt = content_text(content).unwrap_or_default();
+                *content = text(if prompt.is_empty() { instructions } else { format!("{}\n\n{}", instructions, prompt) });
+            }
+        }
+        None => rest.insert(0, ChatMessage::User { content: text(instructions), name: None }),
+    }
+    rest
+}
+
+fn tool_transcript_as_text(messages: Vec<ChatMessage>) -> Vec<ChatMessage> {
+    messages.into_iter().map(|message| match message {
+        ChatMessage::Assistant { content, reasoning_content, refusal, name, audio, tool_calls: Some(tool_calls) } if !tool_calls.is_empty() => {
+            let mut lines: Vec<String> = content.as_ref().and_then(content_text).filter(|t| !t.is_empty()).into_iter().collect();
+            for call in &tool_calls {
+                lines.push(format!("[tool call {}: {}({})]", call.id, call.function.name, call.function.arguments));
+            }
+            ChatMessage::Assistant { content: Some(text(lines.join("\n"))), reasoning_content, refusal, name, audio, tool_calls: None }
+        }
+        ChatMessage::Tool { content, tool_call_id } => ChatMessage::User {
+            content: text(format!("[tool result {}]\n{}", tool_call_id, content_text(&content).unwrap_or_default())),
+            name: None,
+        },
+        message => message,
+    }).collect()
+}
+
+/// Role and text of a message that can be merged with its neighbours
+fn mergeable(message: &ChatMessage) -> Option<(&'static str, String)> {
+    match message {
+        ChatMessage::System { content, .. } => content_text(content).map(|t| ("system", t)),
+        ChatMessage::Developer { content, .. } => content_text(content).map(|t| ("developer", t)),
+        ChatMessage::User { content, .. } => content_text(content).map(|t| ("user", t)),
+        ChatMessage::Assistant { content, tool_calls, .. } if tool_calls.as_ref().is_none_or(|calls| calls.is_empty()) => {
+            Some(("assistant", content.as_ref().and_then(content_text).unwrap_or_default()))
+        }
+        _ => None,
+    }
+}
+
+fn collapse_consecutive(messages: Vec<ChatMessage>) -> Vec<ChatMessage> {
+    let mut collapsed: Vec<ChatMessage> = Vec::with_capacity(messages.len());
+    for message in messages {
+        let merged = match (collapsed.last().and_then(mergeable), mergeable(&message)) {
+            (Some((previous_role, previous)), Some((role, current))) if previous_role == role => {
+                let joined = match (previous.is_empty(), current.is_empty()) {
+                    (true, _) => current,
+                    (_, true) => previous,
+                    _ => format!("{}\n\n{}", previous, current),
+                };
+                Some(joined)
+            }
+            _ => None,
+        };
+
+        let Some(joined) = merged else {
+            collapsed.push(message);
+            continue;
+        };
+        match collapsed.last_mut() {
+            Some(ChatMessage::System { content, .. } | ChatMessage::Developer { content, .. } | ChatMessage::User { content, .. }) => *content = text(joined),
+            Some(ChatMessage::Assistant { content, .. }) => *content = Some(text(joined)),
+            _ => {}
+        }
+    }
+    collapsed
+}
+
+fn rename_role(message: ChatMessage, from: PromptRole, to: PromptRole) -> ChatMessage {
+    let (role, content, name) = match message {
+        ChatMessage::System { content, name } => (PromptRole::System, content, name),
+        ChatMessage::Developer { content, name } => (PromptRole::Developer, content, name),
+        ChatMessage::User { content, name } => (PromptRole::User, content, name),
+        message => return message,
+    };
+
+    let role = if role == from { to } else { role };
+    match role {
+        PromptRole::System => ChatMessage::System { content, name },
+        PromptRole::Developer => ChatMessage::Developer { content, name },
+        PromptRole::User => ChatMessage::User { content, name },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai_dive::v1::resources::chat::{ChatCompletionParametersBuilder, Function, ToolCall};
+
+    fn system(content: &str) -> ChatMessage {
+        ChatMessage::System { content: text(content.to_string()), name: None }
+    }
+
+    fn user(content: &str) -> ChatMessage {
+        ChatMessage::User { content: text(content.to_string()), name: None }
+    }
+
+    fn assistant(content: Option<&str>, calls: &[(&str, &str, &str)]) -> ChatMessage {
+        let tool_calls: Vec<ToolCall> = calls.iter().map(|(id, name, arguments)| ToolCall {
+            id: id.to_string(),
+            r#type: "function".to_string(),
+            function: Function { name: name.to_string(), arguments: arguments.to_string() },
+        }).collect();
+        ChatMessage::Assistant {
+            content: content.map(|c| text(c.to_string())),
+            reasoning_content: None,
+            refusal: None,
+            name: None,
+            audio: None,
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+        }
+    }
+
+    fn tool(id: &str, content: &str) -> ChatMessage {
+        ChatMessage::Tool { content: text(content.to_string()), tool_call_id: id.to_string() }
+    }
+
+    /// A coding session: instructions, a tool round trip, then a follow-up
+    fn trace() -> Vec<ChatMessage> {
+        vec![
+            system("You are a coding agent."),
+            system("Answer briefly."),
+            user("List the files."),
+            assistant(Some("Let me look."), &[("call_1", "ls", "{\"path\":\".\"}")]),
+            tool("call_1", "Cargo.toml\nsrc"),
+            assistant(Some("There are two entries."), &[]),
+            user("Thanks."),
+            user("Now read Cargo.toml."),
+        ]
+    }
+
+    /// One line per message, pins the transformed traces
+    fn render(messages: &[ChatMessage]) -> Vec<String> {
+        messages.iter().map(|message| {
+            let body = |content: &ChatMessageContent| content_text(content).unwrap_or_default().replace('\n', "\\n");
+            match message {
+                ChatMessage::System { content, .. } => format!("system: {}", body(content)),
+                ChatMessage::Developer { content, .. } => format!("developer: {}", body(content)),
+                ChatMessage::User { content, .. } => format!("user: {}", body(content)),
+                ChatMessage::Assistant { content, tool_calls, .. } => {
+                    let calls: Vec<_> = tool_calls.iter().flatten().map(|call| call.function.name.clone()).collect();
+                    format!("assistant: {} {:?}", content.as_ref().map(body).unwrap_or_default(), calls)
+                }
+                ChatMessage::Tool { content, tool_call_id } => format!("tool({}): {}", tool_call_id, body(content)),
+                other => format!("{:?}", other),
+            }
+        }).collect()
+    }
+
+    fn adapted(model: &str) -> Vec<String> {
+        let adapter = builtin_prompt_adapters().iter().find(|adapter| adapter.matches(model)).cloned();
+        render(&adapter.map(|adapter| adapter.apply(trace())).unwrap_or_else(trace))
+    }
+
+    #[test]
+    fn test_gemma_golden() {
+        assert_eq!(adapted("gemma2:9b"), vec![
+            "user: You are a coding agent.\\n\\nAnswer briefly.\\n\\nList the files.",
+            "assistant: Let me look. [\"ls\"]",
+            "tool(call_1): Cargo.toml\\nsrc",
+            "assistant: There are two entries. []",
+            "user: Thanks.\\n\\nNow read Cargo.toml.",
+        ]);
+    }
+
+    #[test]
+    fn test_deepseek_r1_golden() {
+        assert_eq!(adapted("deepseek-r1:14b"), vec![
+            "user: You are a coding agent.\\n\\nAnswer briefly.\\n\\nList the files.",
+            "assistant: Let me look. [\"ls\"]",
+            "tool(call_1): Cargo.toml\\nsrc",
+            "assistant: There are two entries. []",
+            "user: Thanks.",
+            "user: Now read Cargo.toml.",
+        ]);
+    }
+
+    #[test]
+    fn test_phi3_golden() {
+        assert_eq!(adapted("Phi3:mini"), vec![
+            "system: You are a coding agent.\\n\\nAnswer briefly.",
+            "user: List the files.",
+            "assistant: Let me look.\\n[tool call call_1: ls({\"path\":\".\"})] []",
+            "user: [tool result call_1]\\nCargo.toml\\nsrc",
+            "assistant: There are two entries. []",
+            "user: Thanks.\\n\\nNow read Cargo.toml.",
+        ]);
+    }
+
+    #[test]
+    fn test_unadapted_models_round_trip() {
+        for model in ["gpt-4o", "claude-sonnet-4", "qwen2.5-coder:7b"] {
+            let params = ChatCompletionParametersBuilder::default().model(model).messages(trace()).build().unwrap();
+            let adapted = params.clone().adapt_prompt();
+            assert_eq!(serde_json::to_value(&adapted).unwrap(), serde_json::to_value(&params).unwrap(), "{}", model);
+        }
+
+        // an adapter without transforms is the identity too
+        let noop = PromptAdapter::new("*", vec![]);
+        assert_eq!(render(&noop.apply(trace())), render(&trace()));
+    }
+
+    #[test]
+    fn test_overrides_take_precedence() {
+        let rename = PromptTransform::RenameRole { from: PromptRole::System, to: PromptRole::User };
+        let overrides: Vec<PromptAdapter> = serde_json::from_value(serde_json::json!([
+            { "pattern": "gemma*", "transforms": [{ "type": "rename_role", "from": "system", "to": "user" }] },
+            { "pattern": "phi3*" }
+        ])).unwrap();
+        assert_eq!(overrides[0].transforms, vec![rename]);
+
+        let builtin = prompt_adapter_for("deepseek-r1:7b");
+        set_prompt_adapters(overrides);
+        assert_eq!(prompt_adapter_for("gemma2:2b").unwrap().transforms.len(), 1);
+        assert!(prompt_adapter_for("phi3:mini").unwrap().transforms.is_empty());
+        // models the configuration does not cover keep the built-in adapter
+        assert_eq!(prompt_adapter_for("deepseek-r1:7b"), builtin);
+        set_prompt_adapters(Vec::new());
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*gemma*", "google/gemma-2-9b-it"));
+        assert!(wildcard_match("gemma*", "gemma2:9b"));
+        assert!(!wildcard_match("gemma*", "codegemma:7b"));
+        assert!(wildcard_match("llama*instruct", "llama-3.1-8b-instruct"));
+        assert!(!wildcard_match("llama*instruct", "llama-3.1-8b"));
+        assert!(wildcard_match("gpt-4o", "gpt-4o"));
+        assert!(wildcard_match("gpt-3.5-*", "gpt-3.5-turbo"));
+        assert!(wildcard_match("gpt-*-mini", "gpt-4o-mini"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("exact", "exactly"));
+        assert!(!wildcard_match("a*a", "a"));
+    }
+}
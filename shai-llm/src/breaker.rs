@@ -0,0 +1,560 @@
+// llm/breaker.rs
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use openai_dive::v1::error::APIError;
+use openai_dive::v1::resources::{
+    chat::{ChatCompletionParameters, ChatCompletionResponse},
+    embedding::{EmbeddingParameters, EmbeddingResponse},
+    model::ListModelResponse,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::provider::{LlmError, LlmProvider, LlmStream, ProviderInfo};
+use crate::retry::status_of;
+
+/// Circuit breaker settings of a provider entry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BreakerConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Consecutive failed requests that open the circuit
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// Time requests fail fast before a probe request is let through
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_cooldown_secs() -> u64 {
+    30
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            failure_threshold: default_failure_threshold(),
+            cooldown_secs: default_cooldown_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// Requests go through
+    Closed,
+    /// The provider is considered down, requests fail fast
+    Open,
+    /// Cooldown elapsed, a single probe request decides whether the circuit closes
+    HalfOpen,
+}
+
+impl fmt::Display for BreakerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Closed => write!(f, "closed"),
+            Self::Open => write!(f, "open"),
+            Self::HalfOpen => write!(f, "half_open"),
+        }
+    }
+}
+
+/// State of a breaker, for logs and admin endpoints
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BreakerStatus {
+    pub provider: String,
+    /// Provider instance the breaker guards (provider name and settings fingerprint)
+    pub instance: String,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    pub failure_threshold: u32,
+    pub cooldown_secs: u64,
+    /// Seconds before a probe is allowed (open circuits only)
+    pub retry_in_secs: Option<u64>,
+    /// Times the circuit opened
+    pub opened: u64,
+    /// Requests failed fast while the circuit was open
+    pub rejected: u64,
+}
+
+/// Error of the requests refused by an open circuit
+#[derive(Debug)]
+pub struct CircuitOpenError {
+    pub provider: String,
+    pub retry_in: Duration,
+}
+
+impl fmt::Display for CircuitOpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is unavailable (circuit open), retrying in {}s", self.provider, self.retry_in.as_secs())
+    }
+}
+
+impl std::error::Error for CircuitOpenError {}
+
+/// Whether a failed request counts against the provider
+/// Requests the provider answered (bad request, rate limit, auth...) show it is up: the
+/// decision is made on the HTTP status or the type of the error, never on its message
+fn is_outage(error: &LlmError) -> bool {
+    if error.is::<CircuitOpenError>() {
+        return false;
+    }
+    if let Some(status) = status_of(error) {
+        return status >= 500 || status == 408;
+    }
+    // an answer that could not be read still came from the provider
+    !matches!(error.downcast_ref::<APIError>(), Some(APIError::ParseError(_)))
+}
+
+type Clock = Arc<dyn Fn() -> Instant + Send + Sync>;
+
+struct BreakerInner {
+    config: BreakerConfig,
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    /// A half-open probe is running
+    probing: bool,
+    opened: u64,
+    rejected: u64,
+}
+
+/// Closed → open → half-open → closed breaker of one provider instance
+///
+/// `failure_threshold` consecutive outages open the circuit, requests then fail fast with a
+/// CircuitOpenError for `cooldown_secs`. The next request is a probe: success closes the
+/// circuit, failure opens it for another cooldown. Other requests fail fast during the probe.
+pub struct CircuitBreaker {
+    provider: String,
+    instance: String,
+    clock: Clock,
+    inner: Mutex<BreakerInner>,
+}
+
+/// Breakers shared by every client of the same provider instance
+static BREAKERS: OnceLock<Mutex<HashMap<String, Arc<CircuitBreaker>>>> = OnceLock::new();
+
+/// Status of every breaker of the process
+pub fn breaker_statuses() -> Vec<BreakerStatus> {
+    let Some(breakers) = BREAKERS.get() else {
+        return Vec::new();
+    };
+    let mut statuses: Vec<BreakerStatus> = breakers.lock().unwrap().values().map(|b| b.status()).collect();
+    statuses.sort_by(|a, b| a.instance.cmp(&b.instance));
+    statuses
+}
+
+/// Permission to send one request, its outcome must be recorded
+pub struct BreakerPermit {
+    breaker: Arc<CircuitBreaker>,
+    probe: bool,
+    recorded: bool,
+}
+
+impl Drop for BreakerPermit {
+    fn drop(&mut self) {
+        // a cancelled probe lets the next request probe instead
+        if self.probe && !self.recorded {
+            self.breaker.inner.lock().unwrap().probing = false;
+        }
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(provider: &str, instance: &str, config: BreakerConfig) -> Self {
+        Self {
+            provider: provider.to_string(),
+            instance: instance.to_string(),
+            clock: Arc::new(Instant::now),
+            inner: Mutex::new(BreakerInner {
+                config,
+                state: BreakerState::Closed,
+                consecutive_failures: 0,
+                opened_at: None,
+                probing: false,
+                opened: 0,
+                rejected: 0,
+            }),
+        }
+    }
+
+    /// Breaker of a provider instance, created on first use
+    /// The settings of an existing breaker are updated, its state is kept
+    pub fn shared(provider: &str, instance: &str, config: BreakerConfig) -> Arc<Self> {
+        let mut breakers = BREAKERS.get_or_init(Default::default).lock().unwrap();
+        let breaker = breakers
+            .entry(instance.to_string())
+            .or_insert_with(|| Arc::new(Self::new(provider, instance, config.clone())));
+        breaker.inner.lock().unwrap().config = config;
+        breaker.clone()
+    }
+
+    fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.inner.lock().unwrap().state
+    }
+
+    pub fn status(&self) -> BreakerStatus {
+        let now = (self.clock)();
+        let inner = self.inner.lock().unwrap();
+        BreakerStatus {
+            provider: self.provider.clone(),
+            instance: self.instance.clone(),
+            state: inner.state,
+            consecutive_failures: inner.consecutive_failures,
+            failure_threshold: inner.config.failure_threshold,
+            cooldown_secs: inner.config.cooldown_secs,
+            retry_in_secs: (inner.state == BreakerState::Open).then(|| Self::retry_in(&inner, now).as_secs()),
+            opened: inner.opened,
+            rejected: inner.rejected,
+        }
+    }
+
+    fn retry_in(inner: &BreakerInner, now: Instant) -> Duration {
+        let cooldown = Duration::from_secs(inner.config.cooldown_secs);
+        inner.opened_at.map_or(Duration::ZERO, |opened_at| (opened_at + cooldown).saturating_duration_since(now))
+    }
+
+    fn transition(&self, inner: &mut BreakerInner, to: BreakerState, now: Instant) {
+        let from = inner.state;
+        inner.state = to;
+        match to {
+            BreakerState::Open => {
+                inner.opened += 1;
+                inner.opened_at = Some(now);
+                warn!("{} circuit breaker {} -> open after {} consecutive failures, failing fast for {}s",
+                    self.instance, from, inner.consecutive_failures, inner.config.cooldown_secs);
+            }
+            BreakerState::HalfOpen => info!("{} circuit breaker open -> half_open, probing the provider", self.instance),
+            BreakerState::Closed => {
+                inner.opened_at = None;
+                info!("{} circuit breaker {} -> closed", self.instance, from);
+            }
+        }
+        #[cfg(feature = "prometheus")]
+        {
+            metrics::counter!("shai_llm_breaker_transitions_total", "provider" => self.instance.clone(), "state" => to.to_string()).increment(1);
+            let level = match to {
+                BreakerState::Closed => 0.0,
+                BreakerState::HalfOpen => 1.0,
+                BreakerState::Open => 2.0,
+            };
+            metrics::gauge!("shai_llm_breaker_state", "provider" => self.instance.clone()).set(level);
+        }
+    }
+
+    /// Let a request through, or refuse it while the circuit is open
+    pub fn acquire(self: &Arc<Self>) -> Result<BreakerPermit, CircuitOpenError> {
+        let now = (self.clock)();
+        let mut inner = self.inner.lock().unwrap();
+        let state = inner.state;
+        let probe = match state {
+            BreakerState::Closed => false,
+            BreakerState::Open if Self::retry_in(&inner, now).is_zero() => {
+                self.transition(&mut inner, BreakerState::HalfOpen, now);
+                true
+            }
+            BreakerState::HalfOpen if !inner.probing => true,
+            BreakerState::Open | BreakerState::HalfOpen => {
+                inner.rejected += 1;
+                #[cfg(feature = "prometheus")]
+                metrics::counter!("shai_llm_breaker_rejected_total", "provider" => self.instance.clone()).increment(1);
+                debug!("{} circuit breaker {}: request refused", self.instance, inner.state);
+                return Err(CircuitOpenError { provider: self.provider.clone(), retry_in: Self::retry_in(&inner, now) });
+            }
+        };
+        inner.probing |= probe;
+        Ok(BreakerPermit { breaker: self.clone(), probe, recorded: false })
+    }
+
+    /// Record the outcome of a request let through
+    pub fn record(&self, mut permit: BreakerPermit, outage: bool) {
+        permit.recorded = true;
+        let now = (self.clock)();
+        let mut inner = self.inner.lock().unwrap();
+        if permit.probe {
+            inner.probing = false;
+        }
+
+        if !outage {
+            inner.consecutive_failures = 0;
+            if inner.state != BreakerState::Closed {
+                self.transition(&mut inner, BreakerState::Closed, now);
+            }
+            return;
+        }
+
+        inner.consecutive_failures += 1;
+        let reopen = permit.probe && inner.state == BreakerState::HalfOpen;
+        let trip = inner.state == BreakerState::Closed && inner.consecutive_failures >= inner.config.failure_threshold;
+        if reopen || trip {
+            self.transition(&mut inner, BreakerState::Open, now);
+        }
+    }
+
+    /// Run a request through the breaker, `call` is not invoked while the circuit is open
+    pub async fn call<T, F, Fut>(self: &Arc<Self>, call: F) -> Result<T, LlmError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<T, LlmError>>,
+    {
+        let permit = self.acquire().map_err(|e| Box::new(e) as LlmError)?;
+        let result = call().await;
+        self.record(permit, result.as_ref().is_err_and(is_outage));
+        result
+    }
+}
+
+/// Provider guarded by a circuit breaker
+pub struct CircuitBreakerProvider {
+    inner: Box<dyn LlmProvider>,
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl CircuitBreakerProvider {
+    pub fn new(inner: Box<dyn LlmProvider>, breaker: Arc<CircuitBreaker>) -> Self {
+        Self { inner, breaker }
+    }
+
+    pub fn breaker(&self) -> &Arc<CircuitBreaker> {
+        &self.breaker
+    }
+}
+
+#[async_trait]
+impl LlmProvider for CircuitBreakerProvider {
+    async fn models(&self) -> Result<ListModelResponse, LlmError> {
+        self.breaker.call(|| self.inner.models()).await
+    }
+
+    async fn default_model(&self) -> Result<String, LlmError> {
+        self.breaker.call(|| self.inner.default_model()).await
+    }
+
+    async fn chat(&self, request: ChatCompletionParameters) -> Result<ChatCompletionResponse, LlmError> {
+        self.breaker.call(|| self.inner.chat(request)).await
+    }
+
+    /// The outcome is recorded when the stream ends, or on its first error
+    async fn chat_stream(&self, request: ChatCompletionParameters) -> Result<LlmStream, LlmError> {
+        let permit = self.breaker.acquire().map_err(|e| Box::new(e) as LlmError)?;
+        let stream = match self.inner.chat_stream(request).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                self.breaker.record(permit, is_outage(&e));
+                return Err(e);
+            }
+        };
+
+        let breaker = self.breaker.clone();
+        let guarded = futures::stream::unfold((stream, Some(permit)), move |(mut stream, mut permit)| {
+            let breaker = breaker.clone();
+            async move {
+                let item = stream.next().await;
+                let outage = match &item {
+                    Some(Ok(_)) => None,
+                    Some(Err(e)) => Some(is_outage(e)),
+                    None => Some(false),
+                };
+                if let (Some(outage), Some(permit)) = (outage, permit.take()) {
+                    breaker.record(permit, outage);
+                }
+                item.map(|item| (item, (stream, permit)))
+            }
+        });
+        Ok(Box::new(Box::pin(guarded)))
+    }
+
+    async fn embeddings(&self, request: EmbeddingParameters) -> Result<EmbeddingResponse, LlmError> {
+        self.breaker.call(|| self.inner.embeddings(request)).await
+    }
+
+    fn supports_functions(&self, model: String) -> bool {
+        self.inner.supports_functions(model)
+    }
+
+    fn supports_structured_output(&self, model: String) -> bool {
+        self.inner.supports_structured_output(model)
+    }
+
+    fn supports_embeddings(&self) -> bool {
+        self.inner.supports_embeddings()
+    }
+
+    fn supports_images(&self, model: String) -> bool {
+        self.inner.supports_images(model)
+    }
+
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn info() -> ProviderInfo {
+        ProviderInfo {
+            name: "circuit_breaker",
+            display_name: "Circuit breaker (guards another provider)",
+            env_vars: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockProvider, MockState};
+    use crate::HttpStatusError;
+    use openai_dive::v1::resources::chat::ChatCompletionParametersBuilder;
+
+    /// Clock moved by hand
+    fn test_clock() -> (Arc<Mutex<Instant>>, Clock) {
+        let now = Arc::new(Mutex::new(Instant::now()));
+        let clock = now.clone();
+        (now, Arc::new(move || *clock.lock().unwrap()))
+    }
+
+    fn guarded(state: &Arc<MockState>, clock: Clock) -> CircuitBreakerProvider {
+        let config = BreakerConfig { enabled: true, failure_threshold: 2, cooldown_secs: 30 };
+        let breaker = CircuitBreaker::new("mock", "mock:test", config).with_clock(clock);
+        CircuitBreakerProvider::new(Box::new(MockProvider(state.clone())), Arc::new(breaker))
+    }
+
+    fn request() -> ChatCompletionParameters {
+        ChatCompletionParametersBuilder::default()
+            .model("test")
+            .messages(vec![])
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_closed_open_half_open_closed() {
+        let state = Arc::new(MockState::default());
+        let (now, clock) = test_clock();
+        let provider = guarded(&state, clock);
+        let breaker = provider.breaker().clone();
+
+        state.fail_with(Some(503));
+        assert!(provider.chat(request()).await.is_err());
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert!(provider.chat(request()).await.is_err());
+        assert_eq!(breaker.state(), BreakerState::Open);
+
+        // open: fail fast without reaching the provider
+        let error = provider.chat(request()).await.unwrap_err();
+        assert!(error.is::<CircuitOpenError>());
+        assert_eq!(state.calls(), 2);
+        assert_eq!(breaker.status().retry_in_secs, Some(30));
+
+        // cooldown elapsed: the probe fails, the circuit opens again
+        *now.lock().unwrap() += Duration::from_secs(31);
+        assert!(!provider.chat(request()).await.unwrap_err().is::<CircuitOpenError>());
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(provider.chat(request()).await.unwrap_err().is::<CircuitOpenError>());
+        assert_eq!(state.calls(), 3);
+
+        // the next probe succeeds and closes it
+        *now.lock().unwrap() += Duration::from_secs(31);
+        state.fail_with(None);
+        assert!(provider.chat(request()).await.is_ok());
+        let status = breaker.status();
+        assert_eq!(status.state, BreakerState::Closed);
+        assert_eq!(status.consecutive_failures, 0);
+        assert_eq!(status.opened, 2);
+        assert_eq!(status.rejected, 2);
+        assert_eq!(status.retry_in_secs, None);
+    }
+
+    #[tokio::test]
+    async fn test_single_probe_while_half_open() {
+        let state = Arc::new(MockState::default());
+        let (now, clock) = test_clock();
+        let provider = guarded(&state, clock);
+        let breaker = provider.breaker().clone();
+
+        state.fail_with(Some(500));
+        for _ in 0..2 {
+            let _ = provider.chat(request()).await;
+        }
+        *now.lock().unwrap() += Duration::from_secs(30);
+
+        let probe = breaker.acquire().unwrap();
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        assert!(breaker.acquire().is_err());
+
+        // an abandoned probe hands over to the next request
+        drop(probe);
+        let probe = breaker.acquire().unwrap();
+        breaker.record(probe, false);
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_answered_errors_do_not_trip() {
+        let state = Arc::new(MockState::default());
+        let (_now, clock) = test_clock();
+        let provider = guarded(&state, clock);
+
+        state.fail_with(Some(400));
+        for _ in 0..5 {
+            assert!(provider.chat(request()).await.is_err());
+        }
+        assert_eq!(provider.breaker().state(), BreakerState::Closed);
+        assert_eq!(provider.breaker().status().consecutive_failures, 0);
+
+        // transport errors are outages, whatever their message says
+        let refused: LlmError = "connection refused".into();
+        assert!(is_outage(&refused));
+        let reset: LlmError = "connection reset after 400 bytes".into();
+        assert!(is_outage(&reset));
+        let limited: LlmError = Box::new(APIError::RateLimitError("slow down".to_string()));
+        assert!(!is_outage(&limited));
+        let answered: LlmError = Box::new(HttpStatusError { status: 422, retry_after: None, message: "503 tokens".to_string() });
+        assert!(!is_outage(&answered));
+    }
+
+    #[tokio::test]
+    async fn test_mid_stream_errors_trip() {
+        let state = Arc::new(MockState::default());
+        let (_now, clock) = test_clock();
+        let provider = guarded(&state, clock);
+
+        for _ in 0..2 {
+            let mut stream = provider.chat_stream(request()).await.unwrap();
+            assert!(stream.next().await.unwrap().is_err());
+            assert!(stream.next().await.is_none());
+        }
+        assert_eq!(provider.breaker().state(), BreakerState::Open);
+        assert!(provider.chat_stream(request()).await.err().unwrap().is::<CircuitOpenError>());
+        assert_eq!(state.calls(), 2);
+    }
+
+    #[test]
+    fn test_shared_breakers() {
+        let config = BreakerConfig { cooldown_secs: 5, ..Default::default() };
+        let a = CircuitBreaker::shared("mock", "mock:shared-test", BreakerConfig::default());
+        let b = CircuitBreaker::shared("mock", "mock:shared-test", config);
+        assert!(Arc::ptr_eq(&a, &b));
+        // the latest settings apply
+        assert_eq!(a.status().cooldown_secs, 5);
+        assert!(breaker_statuses().iter().any(|s| s.instance == "mock:shared-test"));
+    }
+}
@@ -49,6 +49,10 @@ pub struct ChatClient {
     pub headers: Option<HashMap<String, String>>,
     pub organization: Option<String>,
     pub project: Option<String>,
+    /// Header carrying the API key instead of `Authorization: Bearer` (e.g. `api-key` for Azure)
+    pub api_key_header: Option<String>,
+    /// Query parameters sent with every request (e.g. `api-version` for Azure)
+    pub query: Vec<(String, String)>,
 }
 
 impl ChatClient {
@@ -61,6 +65,8 @@ impl ChatClient {
             headers: None,
             organization: None,
             project: None,
+            api_key_header: None,
+            query: Vec::new(),
         }
     }
 
@@ -71,7 +77,11 @@ impl ChatClient {
             .http_client
             .request(method, &url)
             .header(reqwest::header::CONTENT_TYPE, content_type)
-            .bearer_auth(&self.api_key);
+            .query(&self.query);
+        request = match &self.api_key_header {
+            Some(header) => request.header(header.as_str(), &self.api_key),
+            None => request.bearer_auth(&self.api_key),
+        };
 
         if let Some(headers) = &self.headers {
             for (key, value) in headers {
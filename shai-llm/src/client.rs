@@ -4,13 +4,18 @@ use crate::ToolCallMethod;
 // llm/client.rs
 use super::provider::{LlmError, LlmProvider, LlmStream, ProviderInfo};
 use super::providers::{
-    anthropic::AnthropicProvider, mistral::MistralProvider, ollama::OllamaProvider,
+    anthropic::AnthropicProvider, azure_openai::AzureOpenAiProvider, gemini::GeminiProvider, groq::GroqProvider, mistral::MistralProvider, ollama::OllamaProvider,
     openai::OpenAIProvider, openai_compatible::OpenAICompatibleProvider,
     openrouter::OpenRouterProvider, ovhcloud::OvhCloudProvider,
 };
+use super::adapter::AdaptPrompt;
+use super::rotation::{key_fingerprint, KeyPoolConfig, KeyedProviderFactory, RotatingProvider};
+use super::breaker::{BreakerConfig, CircuitBreaker, CircuitBreakerProvider};
+use super::retry::{RetryConfig, RetryProvider};
 use openai_dive::v1::resources::chat::ChatCompletionParametersBuilder;
 use openai_dive::v1::resources::{
     chat::{ChatCompletionParameters, ChatCompletionResponse, ChatMessage, ChatMessageContent},
+    embedding::{EmbeddingParameters, EmbeddingResponse},
     model::ListModelResponse,
 };
 use regex::Regex;
@@ -78,6 +83,30 @@ impl LlmClient {
         })
     }
 
+    /// Create a Gemini provider from environment variables
+    /// Returns None if required environment variables are not set
+    pub fn from_env_gemini() -> Option<Self> {
+        GeminiProvider::from_env().map(|provider| Self {
+            provider: Box::new(provider),
+        })
+    }
+
+    /// Create a Groq provider from environment variables
+    /// Returns None if required environment variables are not set
+    pub fn from_env_groq() -> Option<Self> {
+        GroqProvider::from_env().map(|provider| Self {
+            provider: Box::new(provider),
+        })
+    }
+
+    /// Create an Azure OpenAI provider from environment variables
+    /// Returns None if required environment variables are not set
+    pub fn from_env_azure_openai() -> Option<Self> {
+        AzureOpenAiProvider::from_env().map(|provider| Self {
+            provider: Box::new(provider),
+        })
+    }
+
     pub fn openai(api_key: String) -> Self {
         Self {
             provider: Box::new(OpenAIProvider::new(api_key)),
@@ -114,9 +143,27 @@ impl LlmClient {
         }
     }
 
-    pub fn mistral(api_key: String) -> Self {
+    pub fn mistral(api_key: String, base_url: Option<String>) -> Self {
         Self {
-            provider: Box::new(MistralProvider::new(api_key)),
+            provider: Box::new(MistralProvider::new(api_key, base_url)),
+        }
+    }
+
+    pub fn gemini(api_key: String, base_url: Option<String>) -> Self {
+        Self {
+            provider: Box::new(GeminiProvider::new(api_key, base_url)),
+        }
+    }
+
+    pub fn groq(api_key: String, base_url: Option<String>) -> Self {
+        Self {
+            provider: Box::new(GroqProvider::new(api_key, base_url)),
+        }
+    }
+
+    pub fn azure_openai(endpoint: String, api_key: String, deployment: String, api_version: Option<String>) -> Self {
+        Self {
+            provider: Box::new(AzureOpenAiProvider::new(endpoint, api_key, deployment, api_version)),
         }
     }
 
@@ -127,8 +174,11 @@ impl LlmClient {
             match provider.as_str() {
                 "ovhcloud" => return Self::from_env_ovhcloud(),
                 "openai" => return Self::from_env_openai(),
+                "azure_openai" => return Self::from_env_azure_openai(),
                 "mistral" => return Self::from_env_mistral(),
                 "anthropic" => return Self::from_env_anthropic(),
+                "gemini" => return Self::from_env_gemini(),
+                "groq" => return Self::from_env_groq(),
                 "openrouter" => return Self::from_env_openrouter(),
                 "openai_compatible" => return Self::from_env_openai_compatible(),
                 "ollama" => return Self::from_env_ollama(),
@@ -142,12 +192,21 @@ impl LlmClient {
         if let Some(client) = Self::from_env_openai() {
             return Some(client);
         }
+        if let Some(client) = Self::from_env_azure_openai() {
+            return Some(client);
+        }
         if let Some(client) = Self::from_env_mistral() {
             return Some(client);
         }
         if let Some(client) = Self::from_env_anthropic() {
             return Some(client);
         }
+        if let Some(client) = Self::from_env_gemini() {
+            return Some(client);
+        }
+        if let Some(client) = Self::from_env_groq() {
+            return Some(client);
+        }
         if let Some(client) = Self::from_env_openrouter() {
             return Some(client);
         }
@@ -169,7 +228,10 @@ impl LlmClient {
             OpenAICompatibleProvider::info(),
             OpenRouterProvider::info(),
             AnthropicProvider::info(),
+            GeminiProvider::info(),
+            GroqProvider::info(),
             OpenAIProvider::info(),
+            AzureOpenAiProvider::info(),
         ]
     }
 
@@ -198,11 +260,33 @@ impl LlmClient {
                     .ok_or("OPENAI_API_KEY not found in config or environment")?;
                 Ok(Self::openai(api_key))
             }
+            "azure_openai" => {
+                let endpoint = Self::get_or_env(env_values, "AZURE_OPENAI_ENDPOINT")
+                    .ok_or("AZURE_OPENAI_ENDPOINT not found in config or environment")?;
+                let api_key = Self::get_or_env(env_values, "AZURE_OPENAI_API_KEY")
+                    .ok_or("AZURE_OPENAI_API_KEY not found in config or environment")?;
+                let deployment = Self::get_or_env(env_values, "AZURE_OPENAI_DEPLOYMENT")
+                    .ok_or("AZURE_OPENAI_DEPLOYMENT not found in config or environment")?;
+                let api_version = Self::get_or_env(env_values, "AZURE_OPENAI_API_VERSION");
+                Ok(Self::azure_openai(endpoint, api_key, deployment, api_version))
+            }
             "anthropic" => {
                 let api_key = Self::get_or_env(env_values, "ANTHROPIC_API_KEY")
                     .ok_or("ANTHROPIC_API_KEY not found in config or environment")?;
                 Ok(Self::anthropic(api_key))
             }
+            "gemini" => {
+                let api_key = Self::get_or_env(env_values, "GEMINI_API_KEY")
+                    .ok_or("GEMINI_API_KEY not found in config or environment")?;
+                let base_url = Self::get_or_env(env_values, "GEMINI_BASE_URL");
+                Ok(Self::gemini(api_key, base_url))
+            }
+            "groq" => {
+                let api_key = Self::get_or_env(env_values, "GROQ_API_KEY")
+                    .ok_or("GROQ_API_KEY not found in config or environment")?;
+                let base_url = Self::get_or_env(env_values, "GROQ_BASE_URL");
+                Ok(Self::groq(api_key, base_url))
+            }
             "ollama" => {
                 let base_url = Self::get_or_env(env_values, "OLLAMA_BASE_URL")
                     .unwrap_or_else(|| "http://localhost:11434/v1".to_string());
@@ -212,7 +296,8 @@ impl LlmClient {
             "mistral" => {
                 let api_key = Self::get_or_env(env_values, "MISTRAL_API_KEY")
                     .ok_or("MISTRAL_API_KEY not found in config or environment")?;
-                Ok(Self::mistral(api_key))
+                let base_url = Self::get_or_env(env_values, "MISTRAL_BASE_URL");
+                Ok(Self::mistral(api_key, base_url))
             }
             "ovhcloud" => {
                 let api_key =
@@ -235,10 +320,108 @@ impl LlmClient {
             _ => Err(format!("Unknown provider: {}", provider_name).into()),
         }
     }
+
+    /// Environment variable holding the API key of a provider
+    pub fn api_key_var(provider_name: &str) -> Option<&'static str> {
+        match provider_name {
+            "openai" => Some("OPENAI_API_KEY"),
+            "azure_openai" => Some("AZURE_OPENAI_API_KEY"),
+            "anthropic" => Some("ANTHROPIC_API_KEY"),
+            "gemini" => Some("GEMINI_API_KEY"),
+            "groq" => Some("GROQ_API_KEY"),
+            "ollama" => Some("OLLAMA_API_KEY"),
+            "mistral" => Some("MISTRAL_API_KEY"),
+            "ovhcloud" => Some("OVH_API_KEY"),
+            "openrouter" => Some("OPENROUTER_API_KEY"),
+            "openai_compatible" => Some("OPENAI_COMPATIBLE_API_KEY"),
+            _ => None,
+        }
+    }
+
+    /// Create a provider spreading its requests over the keys of `key_pool`
+    /// Other settings (base url...) come from the environment values as in create_provider
+    pub fn create_provider_with_keys(
+        provider_name: &str,
+        env_values: &std::collections::HashMap<String, String>,
+        key_pool: &KeyPoolConfig,
+    ) -> Result<Self, LlmError> {
+        let key_var = Self::api_key_var(provider_name)
+            .ok_or_else(|| format!("Provider {} does not use API keys", provider_name))?;
+
+        let name = provider_name.to_string();
+        let env_values = env_values.clone();
+        let factory: KeyedProviderFactory = std::sync::Arc::new(move |key| {
+            let mut env_values = env_values.clone();
+            env_values.insert(key_var.to_string(), key);
+            Self::create_provider(&name, &env_values).map(|client| client.provider)
+        });
+
+        Ok(Self {
+            provider: Box::new(RotatingProvider::new(key_pool.clone(), factory)?),
+        })
+    }
+
+    /// Create a provider from a config entry, with key rotation when the entry lists several keys
+    /// The provider is guarded by the circuit breaker of its instance unless the entry disables it
+    pub fn create_provider_from_config(
+        provider_name: &str,
+        env_values: &std::collections::HashMap<String, String>,
+        key_pool: Option<&KeyPoolConfig>,
+        breaker: Option<&BreakerConfig>,
+    ) -> Result<Self, LlmError> {
+        let client = match key_pool {
+            Some(key_pool) => Self::create_provider_with_keys(provider_name, env_values, key_pool)?,
+            None => Self::create_provider(provider_name, env_values)?,
+        };
+
+        let breaker = breaker.cloned().unwrap_or_default();
+        if !breaker.enabled {
+            return Ok(client);
+        }
+        let instance = Self::instance_id(provider_name, env_values, key_pool);
+        Ok(client.with_circuit_breaker(&instance, breaker))
+    }
+
+    /// Name of a provider instance: entries with the same settings share one circuit breaker
+    fn instance_id(
+        provider_name: &str,
+        env_values: &std::collections::HashMap<String, String>,
+        key_pool: Option<&KeyPoolConfig>,
+    ) -> String {
+        let mut settings: Vec<String> = env_values.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        settings.sort();
+        if let Some(key_pool) = key_pool {
+            settings.extend(key_pool.keys.iter().cloned());
+            settings.extend(key_pool.keys_file.iter().map(|path| path.display().to_string()));
+        }
+        // key_fingerprint keeps the keys out of the name
+        let fingerprint = key_fingerprint(&settings.join("\n"));
+        format!("{}:{}", provider_name, fingerprint.trim_start_matches("key_"))
+    }
+
+    /// Guard the provider with the circuit breaker shared by the clients of `instance`
+    pub fn with_circuit_breaker(self, instance: &str, config: BreakerConfig) -> Self {
+        let breaker = CircuitBreaker::shared(self.provider.name(), instance, config);
+        Self {
+            provider: Box::new(CircuitBreakerProvider::new(self.provider, breaker)),
+        }
+    }
+
+    /// Retry the transient errors of the provider (rate limits, server errors) with exponential backoff
+    pub fn with_retry(self, config: RetryConfig) -> Self {
+        Self {
+            provider: Box::new(RetryProvider::new(self.provider, config)),
+        }
+    }
 }
 
 /// Provider Delegate
 impl LlmClient {
+    /// Client of a provider built by the caller (custom or test providers)
+    pub fn from_provider(provider: Box<dyn LlmProvider>) -> Self {
+        Self { provider }
+    }
+
     pub async fn models(&self) -> Result<ListModelResponse, LlmError> {
         self.provider.models().await
     }
@@ -251,10 +434,27 @@ impl LlmClient {
         }
     }
 
+    pub async fn embeddings(&self, request: EmbeddingParameters) -> Result<EmbeddingResponse, LlmError> {
+        self.provider.embeddings(request).await
+    }
+
+    pub fn supports_embeddings(&self) -> bool {
+        self.provider.supports_embeddings()
+    }
+
     pub fn provider_name(&self) -> &'static str {
         self.provider.name()
     }
 
+    pub fn supports_images(&self, model: &str) -> bool {
+        self.provider.supports_images(model.to_string())
+    }
+
+    /// Tokens the request consumes before the reply, for its model (see token_count::count_tokens)
+    pub fn count_tokens(&self, request: &ChatCompletionParameters) -> Result<u32, LlmError> {
+        crate::token_count::count_tokens(request, &request.model)
+    }
+
     /// Get a reference to the underlying provider (for testing)
     pub fn provider(&self) -> &dyn LlmProvider {
         &*self.provider
@@ -267,12 +467,18 @@ impl LlmClient {
         &self,
         request: ChatCompletionParameters,
     ) -> Result<ChatCompletionResponse, LlmError> {
-        let request = request.fix_mistral_alternating();
+        let request = request.fix_mistral_alternating().adapt_prompt();
 
+        #[cfg(feature = "prometheus")]
+        let started = std::time::Instant::now();
         let response = self
             .provider
             .chat(request.clone())
-            .await
+            .await;
+        #[cfg(feature = "prometheus")]
+        self.record_duration(started);
+
+        let response = response
             .inspect_err(|error| {
                 crate::logging::log_llm_error(&request, error, self.provider_name());
             })?
@@ -285,9 +491,21 @@ impl LlmClient {
         &self,
         request: ChatCompletionParameters,
     ) -> Result<LlmStream, LlmError> {
-        let request = request.fix_mistral_alternating();
+        let request = request.fix_mistral_alternating().adapt_prompt();
+
+        // Streams are timed until the response starts
+        #[cfg(feature = "prometheus")]
+        let started = std::time::Instant::now();
+        let stream = self.provider.chat_stream(request).await;
+        #[cfg(feature = "prometheus")]
+        self.record_duration(started);
+        stream
+    }
 
-        self.provider.chat_stream(request).await
+    #[cfg(feature = "prometheus")]
+    fn record_duration(&self, started: std::time::Instant) {
+        metrics::histogram!("shai_llm_request_duration_seconds", "provider" => self.provider_name())
+            .record(started.elapsed().as_secs_f64());
     }
 }
 
@@ -1,18 +1,37 @@
+pub mod adapter;
 pub mod client;
 pub mod providers;
 pub mod provider;
 pub mod chat;
+pub mod stream;
+pub mod rotation;
+pub mod breaker;
+pub mod retry;
 pub mod tool;
 pub mod logging;
+pub mod usage;
+pub mod token_count;
+pub mod structured;
+#[cfg(test)]
+pub(crate) mod testing;
 
 // Re-export our client
 pub use client::LlmClient;
+pub use stream::{read_stream_until, CancelSignal, StreamAccumulator, StreamOutcome, ToolCallDelta, ToolCallStreaming};
+pub use adapter::{wildcard_match, PromptAdapter, PromptRole, PromptTransform};
+pub use rotation::{KeyPoolConfig, KeyStrategy, KeyUsage, RotatingProvider};
+pub use usage::{estimate_message_tokens, estimate_tokens, estimate_usage};
+pub use token_count::{count_message_tokens, count_tokens};
+pub use breaker::{BreakerConfig, BreakerState, BreakerStatus, CircuitBreaker, CircuitBreakerProvider, CircuitOpenError};
+pub use retry::{HttpStatusError, RetryConfig, RetryProvider};
+pub use structured::{JsonOutputBuilder, StructuredOutputError};
 
 pub use tool::{
     ToolDescription, 
     ToolCallMethod,
     ToolBox,
     ContainsTool,
+    DisplayToolBox,
     StructuredOutputBuilder, 
     AssistantResponse, 
     IntoChatMessage, 
@@ -5,6 +5,7 @@ use std::error::Error;
 use openai_dive::v1::endpoints::chat::Chat;
 use openai_dive::v1::resources::{
     chat::{ChatCompletionParameters, ChatCompletionResponse, ChatCompletionChunkResponse},
+    embedding::{EmbeddingParameters, EmbeddingResponse},
     model::ListModelResponse,
 };
 
@@ -58,10 +59,25 @@ pub trait LlmProvider: Send + Sync {
     async fn chat(&self, request: ChatCompletionParameters) -> Result<ChatCompletionResponse, LlmError>;
     
     async fn chat_stream(&self, request: ChatCompletionParameters) -> Result<LlmStream, LlmError>;
+
+    /// Embeddings of the input, providers without an embeddings API keep this default
+    async fn embeddings(&self, _request: EmbeddingParameters) -> Result<EmbeddingResponse, LlmError> {
+        Err(format!("{} does not support embeddings", self.name()).into())
+    }
+
+    /// Whether `embeddings` reaches the provider (providers implementing it override this)
+    fn supports_embeddings(&self) -> bool {
+        false
+    }
     
     fn supports_functions(&self, model: String) -> bool;
     
     fn supports_structured_output(&self, model: String) -> bool;
+
+    /// Whether image content parts reach the model (providers that drop them override this)
+    fn supports_images(&self, _model: String) -> bool {
+        true
+    }
     
     fn name(&self) -> &'static str;
     
@@ -1,11 +1,13 @@
 use crate::provider::{LlmProvider, LlmError, LlmStream, ProviderInfo, EnvVar};
+use crate::retry::HttpStatusError;
 use super::api::*;
+use super::stream::{finish_reason, AnthropicStream};
 use async_trait::async_trait;
 use reqwest::Client;
 use serde_json::json;
 use futures::{StreamExt, stream};
 use openai_dive::v1::resources::{
-    chat::{ChatCompletionParameters, ChatCompletionResponse, ChatCompletionChunkResponse, ChatMessage, DeltaChatMessage, ChatMessageContent, ChatCompletionChoice, ChatCompletionChunkChoice, ToolCall, Function},
+    chat::{ChatCompletionParameters, ChatCompletionResponse, ChatMessage, ChatMessageContent, ChatCompletionChoice, ToolCall, Function},
     model::ListModelResponse,
     shared::{FinishReason, Usage},
 };
@@ -31,169 +33,39 @@ impl AnthropicProvider {
         })
     }
 
+    /// Chunks of a streamed response, tool_use blocks are streamed as `tool_calls` deltas
     async fn parse_anthropic_stream(
         response: reqwest::Response,
+        model: String,
     ) -> Result<LlmStream, LlmError> {
-        let stream = response.bytes_stream();
-        
-        let parsed_stream = stream
-            .map(|chunk_result| {
-                match chunk_result {
-                    Ok(chunk) => {
-                        let chunk_str = String::from_utf8_lossy(&chunk);
-                        Self::parse_sse_chunk(&chunk_str)
-                    }
-                    Err(e) => vec![Err(Box::new(e) as LlmError)],
-                }
+        let mut parser = AnthropicStream::new(model);
+        let parsed_stream = response
+            .bytes_stream()
+            .map(move |chunk_result| match chunk_result {
+                Ok(chunk) => parser.push(&chunk),
+                Err(e) => vec![Err(Box::new(e) as LlmError)],
             })
-            .flat_map(|results| stream::iter(results));
+            .flat_map(stream::iter);
 
         Ok(Box::new(Box::pin(parsed_stream)))
     }
 
-    fn parse_sse_chunk(chunk: &str) -> Vec<Result<ChatCompletionChunkResponse, LlmError>> {
-        let mut results = Vec::new();
-        let mut current_event_type: Option<String> = None;
-        let mut current_data = String::new();
-        
-        for line in chunk.lines() {
-            let line = line.trim();
-            
-            if line.starts_with("event: ") {
-                current_event_type = Some(line[7..].to_string());
-            } else if line.starts_with("data: ") {
-                current_data = line[6..].to_string();
-            } else if line.is_empty() && current_event_type.is_some() {
-                // End of SSE event
-                if let Some(event_type) = current_event_type.take() {
-                    match Self::process_anthropic_event(&event_type, &current_data) {
-                        Ok(Some(response)) => results.push(Ok(response)),
-                        Ok(None) => {}, // Non-content events like ping
-                        Err(e) => results.push(Err(e)),
-                    }
-                    current_data.clear();
-                }
-            }
-        }
-        
-        results
-    }
-
-    fn process_anthropic_event(event_type: &str, data: &str) -> Result<Option<ChatCompletionChunkResponse>, LlmError> {
-        match serde_json::from_str::<AnthropicStreamEvent>(data) {
-            Ok(event) => Self::convert_anthropic_event_to_stream_response(event),
-            Err(e) => {
-                // Don't fail on unknown events, just skip them
-                if event_type == "ping" {
-                    Ok(None)
-                } else {
-                    Err(Box::new(std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        format!("Failed to parse Anthropic event {}: {}. Error: {}", event_type, data, e)
-                    )) as LlmError)
-                }
-            }
-        }
-    }
-
-    fn convert_anthropic_event_to_stream_response(event: AnthropicStreamEvent) -> Result<Option<ChatCompletionChunkResponse>, LlmError> {
-        match event {
-            AnthropicStreamEvent::ContentBlockDelta { delta, .. } => {
-                let content = match delta {
-                    AnthropicDelta::TextDelta { text } => Some(ChatMessageContent::Text(text)),
-                    AnthropicDelta::ThinkingDelta { thinking: _ } => return Ok(None), // Skip thinking content
-                    AnthropicDelta::InputJsonDelta { partial_json } => Some(ChatMessageContent::Text(partial_json)),
-                };
-
-                Ok(Some(ChatCompletionChunkResponse {
-                    id: Some(format!("anthropic-{}", uuid::Uuid::new_v4())),
-                    object: "chat.completion.chunk".to_string(),
-                    created: std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs() as u32,
-                    model: "claude".to_string(),
-                    choices: vec![ChatCompletionChunkChoice {
-                        index: Some(0),
-                        delta: DeltaChatMessage::Assistant {
-                            content,
-                            reasoning_content: None,
-                            refusal: None,
-                            name: None,
-                            tool_calls: None,
-                        },
-                        finish_reason: None,
-                        logprobs: None,
-                    }],
-                    usage: None,
-                    system_fingerprint: None,
-                }))
-            }
-            AnthropicStreamEvent::MessageDelta { delta, .. } => {
-                let finish_reason = delta.stop_reason.map(|_| FinishReason::StopSequenceReached);
-
-                Ok(Some(ChatCompletionChunkResponse {
-                    id: Some(format!("anthropic-{}", uuid::Uuid::new_v4())),
-                    object: "chat.completion.chunk".to_string(),
-                    created: std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs() as u32,
-                    model: "claude".to_string(),
-                    choices: vec![ChatCompletionChunkChoice {
-                        index: Some(0),
-                        delta: DeltaChatMessage::Assistant {
-                            content: None,
-                            reasoning_content: None,
-                            refusal: None,
-                            name: None,
-                            tool_calls: None,
-                        },
-                        finish_reason,
-                        logprobs: None,
-                    }],
-                    usage: None,
-                    system_fingerprint: None,
-                }))
-            }
-            AnthropicStreamEvent::MessageStop => {
-                Ok(Some(ChatCompletionChunkResponse {
-                    id: Some(format!("anthropic-{}", uuid::Uuid::new_v4())),
-                    object: "chat.completion.chunk".to_string(),
-                    created: std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs() as u32,
-                    model: "claude".to_string(),
-                    choices: vec![ChatCompletionChunkChoice {
-                        index: Some(0),
-                        delta: DeltaChatMessage::Assistant {
-                            content: None,
-                            reasoning_content: None,
-                            refusal: None,
-                            name: None,
-                            tool_calls: None,
-                        },
-                        finish_reason: Some(FinishReason::StopSequenceReached),
-                        logprobs: None,
-                    }],
-                    usage: None,
-                    system_fingerprint: None,
-                }))
-            }
-            _ => Ok(None), // Skip other events like message_start, content_block_start, ping, etc.
-        }
-    }
-
     pub(crate) fn convert_to_anthropic_format(&self, request: &ChatCompletionParameters) -> serde_json::Value {
         let (system_messages, messages) = self.convert_messages(&request.messages);
 
         let mut anthropic_request = json!({
             "model": request.model,
-            "max_tokens": request.max_tokens.unwrap_or(1000),
+            "max_tokens": request.max_completion_tokens.or(request.max_tokens).unwrap_or(1000),
             "messages": messages
         });
 
+        if let Some(temperature) = request.temperature {
+            anthropic_request["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = request.top_p {
+            anthropic_request["top_p"] = json!(top_p);
+        }
+
         if !system_messages.is_empty() {
             anthropic_request["system"] = json!(system_messages.join("\n\n"));
         }
@@ -377,7 +249,7 @@ impl AnthropicProvider {
                     audio: None,
                     tool_calls: tool_calls_option,
                 },
-                finish_reason: Some(FinishReason::StopSequenceReached),
+                finish_reason: Some(response["stop_reason"].as_str().map_or(FinishReason::StopSequenceReached, finish_reason)),
                 logprobs: None,
             }],
             usage: Some(Usage {
@@ -410,6 +282,18 @@ impl LlmProvider for AnthropicProvider {
                 created: Some(1640995200), // Dummy timestamp
                 owned_by: "anthropic".to_string(),
             },
+            Model {
+                id: "claude-sonnet-4-20250514".to_string(),
+                object: "model".to_string(),
+                created: Some(1640995200),
+                owned_by: "anthropic".to_string(),
+            },
+            Model {
+                id: "claude-opus-4-20250514".to_string(),
+                object: "model".to_string(),
+                created: Some(1640995200),
+                owned_by: "anthropic".to_string(),
+            },
             Model {
                 id: "claude-3-5-haiku-20241022".to_string(),
                 object: "model".to_string(),
@@ -455,8 +339,7 @@ impl LlmProvider for AnthropicProvider {
             .await?;
 
         if !response.status().is_success() {
-            let error_text = response.text().await?;
-            return Err(format!("Anthropic API error: {}", error_text).into());
+            return Err(Box::new(HttpStatusError::from_response("Anthropic API error", response).await));
         }
 
         let anthropic_response: serde_json::Value = response.json().await?;
@@ -478,11 +361,10 @@ impl LlmProvider for AnthropicProvider {
             .await?;
 
         if !response.status().is_success() {
-            let error_text = response.text().await?;
-            return Err(format!("Anthropic API streaming error: {}", error_text).into());
+            return Err(Box::new(HttpStatusError::from_response("Anthropic API streaming error", response).await));
         }
 
-        Self::parse_anthropic_stream(response).await
+        Self::parse_anthropic_stream(response, request.model.clone()).await
     }
 
     fn supports_functions(&self, model: String) -> bool {
@@ -493,6 +375,10 @@ impl LlmProvider for AnthropicProvider {
         false
     }
 
+    fn supports_images(&self, _model: String) -> bool {
+        false // image parts are not converted to Anthropic content blocks
+    }
+
     fn name(&self) -> &'static str {
         "anthropic"
     }
@@ -500,7 +386,7 @@ impl LlmProvider for AnthropicProvider {
     fn info() -> ProviderInfo {
         ProviderInfo {
             name: "anthropic",
-            display_name: "Anthropic (Claude 3.5 Sonnet, Claude 3 Opus)",
+            display_name: "Anthropic (Claude Opus 4, Claude Sonnet 4, Claude 3.5)",
             env_vars: vec![
                 EnvVar::required("ANTHROPIC_API_KEY", "Anthropic API key"),
             ],
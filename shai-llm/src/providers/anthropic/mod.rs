@@ -1,5 +1,6 @@
 pub mod api;
 pub mod anthropic;
+mod stream;
 pub mod tests;
 
 pub use anthropic::AnthropicProvider;
\ No newline at end of file
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+
+use openai_dive::v1::resources::{
+    chat::{ChatCompletionChunkChoice, ChatCompletionChunkResponse, ChatMessageContent, DeltaChatMessage, DeltaFunction, DeltaToolCall},
+    shared::{FinishReason, Usage},
+};
+
+use super::api::*;
+use crate::provider::LlmError;
+
+/// OpenAI finish reason of an Anthropic stop_reason
+pub(crate) fn finish_reason(stop_reason: &str) -> FinishReason {
+    match stop_reason {
+        "tool_use" => FinishReason::ToolCalls,
+        "max_tokens" => FinishReason::TokenLimitReached,
+        _ => FinishReason::StopSequenceReached,
+    }
+}
+
+/// Translates the server-sent events of the Messages API into chat completion chunks
+///
+/// Events may be split across network chunks, bytes are buffered until a full line is
+/// received. Text blocks become content deltas, tool_use blocks become `tool_calls` deltas
+/// (id and name on content_block_start, arguments from the input_json_delta fragments).
+pub(crate) struct AnthropicStream {
+    buffer: Vec<u8>,
+    event_type: Option<String>,
+    data: String,
+    id: String,
+    model: String,
+    created: u32,
+    input_tokens: u32,
+    /// Position of each tool_use block among the tool calls, by content block index
+    tool_indexes: HashMap<u32, u32>,
+}
+
+impl AnthropicStream {
+    pub(crate) fn new(model: String) -> Self {
+        Self {
+            buffer: Vec::new(),
+            event_type: None,
+            data: String::new(),
+            id: format!("anthropic-{}", uuid::Uuid::new_v4()),
+            model,
+            created: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as u32,
+            input_tokens: 0,
+            tool_indexes: HashMap::new(),
+        }
+    }
+
+    /// Feed bytes received from the connection, returns the chunks of the events they complete
+    pub(crate) fn push(&mut self, bytes: &[u8]) -> Vec<Result<ChatCompletionChunkResponse, LlmError>> {
+        self.buffer.extend_from_slice(bytes);
+        let mut results = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+
+            if let Some(event_type) = line.strip_prefix("event:") {
+                self.event_type = Some(event_type.trim().to_string());
+            } else if let Some(data) = line.strip_prefix("data:") {
+                self.data.push_str(data.strip_prefix(' ').unwrap_or(data));
+            } else if line.is_empty() && (self.event_type.is_some() || !self.data.is_empty()) {
+                // End of an event
+                let event_type = self.event_type.take().unwrap_or_default();
+                let data = std::mem::take(&mut self.data);
+                match self.process_event(&event_type, &data) {
+                    Ok(Some(chunk)) => results.push(Ok(chunk)),
+                    Ok(None) => {}
+                    Err(e) => results.push(Err(e)),
+                }
+            }
+        }
+        results
+    }
+
+    fn process_event(&mut self, event_type: &str, data: &str) -> Result<Option<ChatCompletionChunkResponse>, LlmError> {
+        match serde_json::from_str::<AnthropicStreamEvent>(data) {
+            Ok(event) => self.convert_event(event),
+            // Don't fail on pings
+            Err(_) if event_type == "ping" => Ok(None),
+            Err(e) => Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Failed to parse Anthropic event {}: {}. Error: {}", event_type, data, e),
+            )) as LlmError),
+        }
+    }
+
+    fn convert_event(&mut self, event: AnthropicStreamEvent) -> Result<Option<ChatCompletionChunkResponse>, LlmError> {
+        match event {
+            AnthropicStreamEvent::MessageStart { message } => {
+                self.id = message.id;
+                self.model = message.model;
+                self.input_tokens = message.usage.input_tokens.unwrap_or(0);
+                Ok(None)
+            }
+            AnthropicStreamEvent::ContentBlockStart { index, content_block } if content_block.block_type == "tool_use" => {
+                let position = self.tool_indexes.len() as u32;
+                self.tool_indexes.insert(index, position);
+                Ok(Some(self.tool_call_chunk(DeltaToolCall {
+                    index: Some(position),
+                    id: content_block.id,
+                    r#type: Some("function".to_string()),
+                    function: DeltaFunction { name: content_block.name, arguments: Some(String::new()) },
+                })))
+            }
+            AnthropicStreamEvent::ContentBlockDelta { index, delta } => match delta {
+                AnthropicDelta::TextDelta { text } => Ok(Some(self.chunk(
+                    DeltaChatMessage::Assistant {
+                        content: Some(ChatMessageContent::Text(text)),
+                        reasoning_content: None,
+                        refusal: None,
+                        name: None,
+                        tool_calls: None,
+                    },
+                    None,
+                    None,
+                ))),
+                AnthropicDelta::InputJsonDelta { partial_json } => {
+                    let Some(position) = self.tool_indexes.get(&index).copied() else {
+                        return Ok(None);
+                    };
+                    Ok(Some(self.tool_call_chunk(DeltaToolCall {
+                        index: Some(position),
+                        id: None,
+                        r#type: None,
+                        function: DeltaFunction { name: None, arguments: Some(partial_json) },
+                    })))
+                }
+                // Skip thinking content
+                AnthropicDelta::ThinkingDelta { .. } => Ok(None),
+            },
+            AnthropicStreamEvent::MessageDelta { delta, usage } => {
+                let usage = usage.map(|usage| Usage {
+                    input_tokens: None,
+                    input_tokens_details: None,
+                    output_tokens: None,
+                    output_tokens_details: None,
+                    prompt_tokens: Some(self.input_tokens),
+                    completion_tokens: Some(usage.output_tokens),
+                    total_tokens: self.input_tokens + usage.output_tokens,
+                    prompt_tokens_details: None,
+                    completion_tokens_details: None,
+                });
+                let finish_reason = delta.stop_reason.as_deref().map(finish_reason);
+                Ok(Some(self.chunk(
+                    DeltaChatMessage::Assistant {
+                        content: None,
+                        reasoning_content: None,
+                        refusal: None,
+                        name: None,
+                        tool_calls: None,
+                    },
+                    finish_reason,
+                    usage,
+                )))
+            }
+            AnthropicStreamEvent::Error { error } => Err(format!("Anthropic API streaming error: {}", error).into()),
+            // message_stop, content_block_stop, ping, text block starts: the stop reason came with message_delta
+            _ => Ok(None),
+        }
+    }
+
+    fn tool_call_chunk(&self, tool_call: DeltaToolCall) -> ChatCompletionChunkResponse {
+        let delta = DeltaChatMessage::Assistant {
+            content: None,
+            reasoning_content: None,
+            refusal: None,
+            name: None,
+            tool_calls: Some(vec![tool_call]),
+        };
+        self.chunk(delta, None, None)
+    }
+
+    fn chunk(&self, delta: DeltaChatMessage, finish_reason: Option<FinishReason>, usage: Option<Usage>) -> ChatCompletionChunkResponse {
+        ChatCompletionChunkResponse {
+            id: Some(self.id.clone()),
+            object: "chat.completion.chunk".to_string(),
+            created: self.created,
+            model: self.model.clone(),
+            choices: vec![ChatCompletionChunkChoice {
+                index: Some(0),
+                delta,
+                finish_reason,
+                logprobs: None,
+            }],
+            usage,
+            system_fingerprint: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StreamAccumulator;
+    use openai_dive::v1::resources::chat::ChatMessage;
+
+    const EVENTS: &str = concat!(
+        "event: message_start\n",
+        "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"type\":\"message\",\"role\":\"assistant\",\"content\":[],\"model\":\"claude-sonnet\",\"stop_reason\":null,\"stop_sequence\":null,\"usage\":{\"input_tokens\":25,\"output_tokens\":1}}}\n\n",
+        "event: content_block_start\n",
+        "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Let me read it. \"}}\n\n",
+        "event: content_block_start\n",
+        "data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"read\",\"input\":{}}}\n\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"path\\\":\"}}\n\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"\\\"é.txt\\\"}\"}}\n\n",
+        "event: ping\n",
+        "data: {\"type\": \"ping\"}\n\n",
+        "event: message_delta\n",
+        "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\",\"stop_sequence\":null},\"usage\":{\"output_tokens\":30}}\n\n",
+        "event: message_stop\n",
+        "data: {\"type\":\"message_stop\"}\n\n",
+    );
+
+    #[test]
+    fn test_tool_use_stream_split_anywhere() {
+        // events cut at arbitrary points, including inside the multi-byte character
+        for split in [1, 7, 100, EVENTS.find('é').unwrap() + 1, EVENTS.len() - 3] {
+            let mut stream = AnthropicStream::new("claude".to_string());
+            let (head, tail) = EVENTS.as_bytes().split_at(split);
+            let chunks: Vec<_> = [head, tail]
+                .into_iter()
+                .flat_map(|bytes| stream.push(bytes))
+                .collect::<Result<_, _>>()
+                .unwrap();
+
+            let mut accumulator = StreamAccumulator::new();
+            for chunk in chunks {
+                accumulator.push(chunk);
+            }
+            let response = accumulator.finish();
+            assert_eq!(response.model, "claude-sonnet");
+            assert!(matches!(response.choices[0].finish_reason, Some(FinishReason::ToolCalls)));
+            let usage = response.usage.unwrap();
+            assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.total_tokens), (Some(25), Some(30), 55));
+
+            let ChatMessage::Assistant { content, tool_calls, .. } = &response.choices[0].message else {
+                panic!("expected an assistant message");
+            };
+            assert!(matches!(content, Some(ChatMessageContent::Text(text)) if text == "Let me read it. "));
+            let calls = tool_calls.as_ref().unwrap();
+            assert_eq!(calls.len(), 1);
+            assert_eq!(calls[0].id, "toolu_1");
+            assert_eq!(calls[0].function.name, "read");
+            assert_eq!(calls[0].function.arguments, r#"{"path":"é.txt"}"#);
+        }
+    }
+
+    #[test]
+    fn test_stop_reasons() {
+        assert!(matches!(finish_reason("end_turn"), FinishReason::StopSequenceReached));
+        assert!(matches!(finish_reason("tool_use"), FinishReason::ToolCalls));
+        assert!(matches!(finish_reason("max_tokens"), FinishReason::TokenLimitReached));
+    }
+}
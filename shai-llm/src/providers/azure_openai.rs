@@ -0,0 +1,126 @@
+// llm/providers/azure_openai.rs
+use crate::provider::{LlmProvider, LlmError, LlmStream, ProviderInfo, EnvVar};
+use crate::chat::{ChatClient, NoHooks};
+use async_trait::async_trait;
+use futures::StreamExt;
+use openai_dive::v1::resources::{
+    chat::{ChatCompletionParameters, ChatCompletionResponse},
+    model::{ListModelResponse, Model},
+};
+
+/// API version used when AZURE_OPENAI_API_VERSION is not set
+pub const DEFAULT_API_VERSION: &str = "2024-10-21";
+
+/// Azure OpenAI Service: the requests go to one deployment
+/// (`{endpoint}/openai/deployments/{deployment}/chat/completions?api-version=...`)
+/// and are authenticated with the `api-key` header
+pub struct AzureOpenAiProvider {
+    client: ChatClient,
+    deployment: String,
+}
+
+impl AzureOpenAiProvider {
+    pub fn new(endpoint: String, api_key: String, deployment: String, api_version: Option<String>) -> Self {
+        let base_url = format!("{}/openai/deployments/{}", endpoint.trim_end_matches('/'), deployment);
+        let mut client = ChatClient::new(api_key, base_url);
+        client.api_key_header = Some("api-key".to_string());
+        client.query = vec![(
+            "api-version".to_string(),
+            api_version.unwrap_or_else(|| DEFAULT_API_VERSION.to_string()),
+        )];
+        Self { client, deployment }
+    }
+
+    /// Create Azure OpenAI provider from environment variables
+    /// Returns None if required environment variables are not set
+    pub fn from_env() -> Option<Self> {
+        let endpoint = std::env::var("AZURE_OPENAI_ENDPOINT").ok()?;
+        let api_key = std::env::var("AZURE_OPENAI_API_KEY").ok()?;
+        let deployment = std::env::var("AZURE_OPENAI_DEPLOYMENT").ok()?;
+        let api_version = std::env::var("AZURE_OPENAI_API_VERSION").ok();
+        Some(Self::new(endpoint, api_key, deployment, api_version))
+    }
+}
+
+#[async_trait]
+impl LlmProvider for AzureOpenAiProvider {
+    /// The deployment is the only model: the data plane API does not list the deployments of a resource
+    async fn models(&self) -> Result<ListModelResponse, LlmError> {
+        Ok(ListModelResponse {
+            object: "list".to_string(),
+            data: vec![Model {
+                id: self.deployment.clone(),
+                object: "model".to_string(),
+                created: None,
+                owned_by: "azure_openai".to_string(),
+            }],
+        })
+    }
+
+    async fn default_model(&self) -> Result<String, LlmError> {
+        Ok(self.deployment.clone())
+    }
+
+    async fn chat(&self, request: ChatCompletionParameters) -> Result<ChatCompletionResponse, LlmError> {
+        let response = self.client.chat_completion(&request, &NoHooks).await
+            .map_err(|e| Box::new(e) as LlmError)?;
+        Ok(response)
+    }
+
+    async fn chat_stream(&self, mut request: ChatCompletionParameters) -> Result<LlmStream, LlmError> {
+        // Ensure streaming is enabled
+        request.stream = Some(true);
+
+        let stream = self.client.chat_completion_stream(&request, NoHooks).await
+            .map_err(|e| Box::new(e) as LlmError)?;
+
+        let converted_stream = stream.map(|result| {
+            result.map_err(|e| Box::new(e) as LlmError)
+        });
+
+        Ok(Box::new(Box::pin(converted_stream)))
+    }
+
+    fn supports_functions(&self, _model: String) -> bool {
+        true
+    }
+
+    fn supports_structured_output(&self, _model: String) -> bool {
+        true
+    }
+
+    fn name(&self) -> &'static str {
+        "azure_openai"
+    }
+
+    fn info() -> ProviderInfo {
+        ProviderInfo {
+            name: "azure_openai",
+            display_name: "Azure OpenAI Service",
+            env_vars: vec![
+                EnvVar::required("AZURE_OPENAI_ENDPOINT", "Resource endpoint (https://{resource}.openai.azure.com)"),
+                EnvVar::required("AZURE_OPENAI_API_KEY", "Azure OpenAI API key"),
+                EnvVar::required("AZURE_OPENAI_DEPLOYMENT", "Name of the model deployment"),
+                EnvVar::optional("AZURE_OPENAI_API_VERSION", "API version (default 2024-10-21)"),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deployment_url() {
+        let provider = AzureOpenAiProvider::new(
+            "https://contoso.openai.azure.com/".to_string(),
+            "key".to_string(),
+            "gpt-4o".to_string(),
+            None,
+        );
+        assert_eq!(provider.client.base_url, "https://contoso.openai.azure.com/openai/deployments/gpt-4o");
+        assert_eq!(provider.client.api_key_header.as_deref(), Some("api-key"));
+        assert_eq!(provider.client.query, vec![("api-version".to_string(), DEFAULT_API_VERSION.to_string())]);
+    }
+}
@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+
+// Gemini generateContent response types (also the payload of each streamed event)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiResponse {
+    #[serde(default)]
+    pub candidates: Vec<GeminiCandidate>,
+    pub usage_metadata: Option<GeminiUsage>,
+    pub model_version: Option<String>,
+    pub response_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiCandidate {
+    pub content: Option<GeminiContent>,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeminiContent {
+    pub role: Option<String>,
+    #[serde(default)]
+    pub parts: Vec<GeminiPart>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiPart {
+    pub text: Option<String>,
+    pub function_call: Option<GeminiFunctionCall>,
+    /// Thinking summary of thinking models, not part of the answer
+    #[serde(default)]
+    pub thought: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeminiFunctionCall {
+    /// Only set by recent models
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub args: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiUsage {
+    #[serde(default)]
+    pub prompt_token_count: u32,
+    #[serde(default)]
+    pub candidates_token_count: u32,
+    #[serde(default)]
+    pub thoughts_token_count: u32,
+}
+
+// GET /models response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeminiModelList {
+    #[serde(default)]
+    pub models: Vec<GeminiModel>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiModel {
+    /// `models/{id}`
+    pub name: String,
+    #[serde(default)]
+    pub supported_generation_methods: Vec<String>,
+}
+
+pub const GEMINI_API_BASE: &str = "https://generativelanguage.googleapis.com/v1beta";
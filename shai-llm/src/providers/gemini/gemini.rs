@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+
+use crate::provider::{LlmProvider, LlmError, LlmStream, ProviderInfo, EnvVar};
+use crate::retry::HttpStatusError;
+use super::api::*;
+use super::stream::GeminiStream;
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::{json, Value};
+use futures::{StreamExt, stream};
+use openai_dive::v1::resources::{
+    chat::{ChatCompletionParameters, ChatCompletionResponse, ChatCompletionTool, ChatMessage, ChatMessageContent, ChatMessageContentPart, ChatCompletionChoice, ToolCall, Function},
+    model::{ListModelResponse, Model},
+    shared::{FinishReason, Usage},
+};
+
+/// JSON schema keywords the Gemini function declarations reject
+const UNSUPPORTED_SCHEMA_KEYS: [&str; 2] = ["$schema", "additionalProperties"];
+
+/// OpenAI finish reason of a Gemini finishReason
+pub(crate) fn finish_reason(reason: &str, has_tool_calls: bool) -> FinishReason {
+    match reason {
+        // Gemini ends function calls with STOP
+        _ if has_tool_calls => FinishReason::ToolCalls,
+        "MAX_TOKENS" => FinishReason::TokenLimitReached,
+        "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" => FinishReason::ContentFilterFlagged,
+        _ => FinishReason::StopSequenceReached,
+    }
+}
+
+pub(crate) fn usage(usage: &GeminiUsage) -> Usage {
+    let completion_tokens = usage.candidates_token_count + usage.thoughts_token_count;
+    Usage {
+        input_tokens: None,
+        input_tokens_details: None,
+        output_tokens: None,
+        output_tokens_details: None,
+        prompt_tokens: Some(usage.prompt_token_count),
+        completion_tokens: Some(completion_tokens),
+        total_tokens: usage.prompt_token_count + completion_tokens,
+        prompt_tokens_details: None,
+        completion_tokens_details: None,
+    }
+}
+
+/// Tool call of a Gemini functionCall, calls without id get a generated one
+pub(crate) fn tool_call(call: GeminiFunctionCall) -> ToolCall {
+    ToolCall {
+        id: call.id.unwrap_or_else(|| format!("call_{}", uuid::Uuid::new_v4().simple())),
+        r#type: "function".to_string(),
+        function: Function {
+            name: call.name,
+            arguments: call.args.to_string(),
+        },
+    }
+}
+
+/// Answer text and function calls of a candidate, thinking parts are skipped
+pub(crate) fn candidate_output(candidate: GeminiCandidate) -> (String, Vec<GeminiFunctionCall>) {
+    let mut text = String::new();
+    let mut calls = Vec::new();
+    for part in candidate.content.map(|content| content.parts).unwrap_or_default() {
+        if let Some(call) = part.function_call {
+            calls.push(call);
+        } else if let Some(part_text) = part.text.filter(|_| !part.thought) {
+            text.push_str(&part_text);
+        }
+    }
+    (text, calls)
+}
+
+pub struct GeminiProvider {
+    api_key: String,
+    base_url: String,
+    client: Client,
+}
+
+impl GeminiProvider {
+    pub fn new(api_key: String, base_url: Option<String>) -> Self {
+        Self {
+            api_key,
+            base_url: base_url.unwrap_or_else(|| GEMINI_API_BASE.to_string()),
+            client: Client::new(),
+        }
+    }
+
+    /// Create Gemini provider from environment variables
+    /// Returns None if required environment variables are not set
+    pub fn from_env() -> Option<Self> {
+        std::env::var("GEMINI_API_KEY").ok().map(|api_key| {
+            Self::new(api_key, std::env::var("GEMINI_BASE_URL").ok())
+        })
+    }
+
+    fn model_url(&self, model: &str, method: &str) -> String {
+        format!("{}/models/{}:{}", self.base_url, model.trim_start_matches("models/"), method)
+    }
+
+    pub(crate) fn convert_to_gemini_format(&self, request: &ChatCompletionParameters) -> Value {
+        let (system, contents) = Self::convert_messages(&request.messages);
+
+        let mut gemini_request = json!({ "contents": contents });
+        if !system.is_empty() {
+            gemini_request["systemInstruction"] = json!({ "parts": [{ "text": system.join("\n\n") }] });
+        }
+
+        if let Some(tools) = request.tools.as_ref().filter(|tools| !tools.is_empty()) {
+            gemini_request["tools"] = json!([{ "functionDeclarations": Self::convert_tools(tools) }]);
+        }
+
+        let mut generation_config = serde_json::Map::new();
+        if let Some(temperature) = request.temperature {
+            generation_config.insert("temperature".to_string(), json!(temperature));
+        }
+        if let Some(top_p) = request.top_p {
+            generation_config.insert("topP".to_string(), json!(top_p));
+        }
+        if let Some(max_tokens) = request.max_completion_tokens.or(request.max_tokens) {
+            generation_config.insert("maxOutputTokens".to_string(), json!(max_tokens));
+        }
+        if !generation_config.is_empty() {
+            gemini_request["generationConfig"] = Value::Object(generation_config);
+        }
+
+        gemini_request
+    }
+
+    /// System instructions and contents of a conversation
+    /// Gemini has no tool call ids, function responses are named after the call they answer,
+    /// and consecutive messages of one role are merged since turns must alternate
+    fn convert_messages(messages: &[ChatMessage]) -> (Vec<String>, Vec<Value>) {
+        let mut system = Vec::new();
+        let mut contents: Vec<Value> = Vec::new();
+        let mut tool_names: HashMap<&str, &str> = HashMap::new();
+
+        for message in messages {
+            let (role, parts) = match message {
+                ChatMessage::System { content, .. } | ChatMessage::Developer { content, .. } => {
+                    system.push(Self::extract_content_text(content));
+                    continue;
+                }
+                ChatMessage::User { content, .. } => ("user", vec![json!({ "text": Self::extract_content_text(content) })]),
+                ChatMessage::Assistant { content, tool_calls, .. } => {
+                    let mut parts = Vec::new();
+                    let text = content.as_ref().map(Self::extract_content_text).unwrap_or_default();
+                    if !text.is_empty() {
+                        parts.push(json!({ "text": text }));
+                    }
+                    for call in tool_calls.iter().flatten() {
+                        tool_names.insert(&call.id, &call.function.name);
+                        let args: Value = serde_json::from_str(&call.function.arguments).unwrap_or_else(|_| json!({}));
+                        parts.push(json!({ "functionCall": { "name": call.function.name, "args": args } }));
+                    }
+                    if parts.is_empty() {
+                        continue;
+                    }
+                    ("model", parts)
+                }
+                ChatMessage::Tool { content, tool_call_id, .. } => {
+                    let name = tool_names.get(tool_call_id.as_str()).copied().unwrap_or_default();
+                    ("user", vec![json!({ "functionResponse": { "name": name, "response": { "content": content } } })])
+                }
+            };
+
+            match contents.last_mut() {
+                Some(last) if last["role"] == role => {
+                    if let Some(last_parts) = last["parts"].as_array_mut() {
+                        last_parts.extend(parts);
+                    }
+                }
+                _ => contents.push(json!({ "role": role, "parts": parts })),
+            }
+        }
+
+        (system, contents)
+    }
+
+    fn convert_tools(tools: &[ChatCompletionTool]) -> Vec<Value> {
+        tools.iter().map(|tool| {
+            let mut declaration = json!({
+                "name": tool.function.name,
+                "description": tool.function.description.as_ref().unwrap_or(&tool.function.name),
+            });
+            let mut parameters = tool.function.parameters.clone();
+            strip_unsupported_schema_keys(&mut parameters);
+            // a function without parameters is declared without a schema
+            if parameters.get("properties").and_then(Value::as_object).is_some_and(|properties| !properties.is_empty()) {
+                declaration["parameters"] = parameters;
+            }
+            declaration
+        }).collect()
+    }
+
+    fn extract_content_text(content: &ChatMessageContent) -> String {
+        match content {
+            ChatMessageContent::Text(text) => text.clone(),
+            ChatMessageContent::ContentPart(parts) => {
+                parts.iter().filter_map(|part| match part {
+                    ChatMessageContentPart::Text(text_part) => Some(text_part.text.clone()),
+                    _ => None, // Skip images, audio, etc.
+                }).collect::<Vec<_>>().join(" ")
+            }
+            ChatMessageContent::None => String::new(),
+        }
+    }
+
+    pub(crate) fn convert_from_gemini_format(&self, response: GeminiResponse, model: &str) -> Result<ChatCompletionResponse, LlmError> {
+        let candidate = response.candidates.into_iter().next()
+            .ok_or("Gemini API returned no candidate")?;
+        let reason = candidate.finish_reason.clone();
+        let (text, calls) = candidate_output(candidate);
+        let tool_calls: Vec<ToolCall> = calls.into_iter().map(tool_call).collect();
+
+        Ok(ChatCompletionResponse {
+            id: Some(response.response_id.unwrap_or_else(|| format!("gemini-{}", uuid::Uuid::new_v4()))),
+            object: "chat.completion".to_string(),
+            created: 0,
+            model: response.model_version.unwrap_or_else(|| model.to_string()),
+            choices: vec![ChatCompletionChoice {
+                index: 0,
+                finish_reason: Some(finish_reason(reason.as_deref().unwrap_or("STOP"), !tool_calls.is_empty())),
+                message: ChatMessage::Assistant {
+                    content: (!text.is_empty()).then_some(ChatMessageContent::Text(text)),
+                    reasoning_content: None,
+                    refusal: None,
+                    name: None,
+                    audio: None,
+                    tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+                },
+                logprobs: None,
+            }],
+            usage: Some(usage(&response.usage_metadata.unwrap_or_default())),
+            service_tier: None,
+            system_fingerprint: None,
+        })
+    }
+
+    async fn post(&self, url: &str, body: &Value) -> Result<reqwest::Response, LlmError> {
+        let response = self.client
+            .post(url)
+            .header("x-goog-api-key", &self.api_key)
+            .json(body)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(Box::new(HttpStatusError::from_response("Gemini API error", response).await));
+        }
+        Ok(response)
+    }
+}
+
+/// Remove the schema keywords Gemini does not accept, at any depth
+fn strip_unsupported_schema_keys(schema: &mut Value) {
+    match schema {
+        Value::Object(object) => {
+            for key in UNSUPPORTED_SCHEMA_KEYS {
+                object.remove(key);
+            }
+            object.values_mut().for_each(strip_unsupported_schema_keys);
+        }
+        Value::Array(items) => items.iter_mut().for_each(strip_unsupported_schema_keys),
+        _ => {}
+    }
+}
+
+#[async_trait]
+impl LlmProvider for GeminiProvider {
+    async fn models(&self) -> Result<ListModelResponse, LlmError> {
+        let response = self.client
+            .get(format!("{}/models", self.base_url))
+            .header("x-goog-api-key", &self.api_key)
+            .query(&[("pageSize", "1000")])
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(Box::new(HttpStatusError::from_response("Gemini API error", response).await));
+        }
+
+        let list: GeminiModelList = response.json().await?;
+        let data = list.models.into_iter()
+            .filter(|model| model.supported_generation_methods.iter().any(|method| method == "generateContent"))
+            .map(|model| Model {
+                id: model.name.trim_start_matches("models/").to_string(),
+                object: "model".to_string(),
+                created: None,
+                owned_by: "google".to_string(),
+            })
+            .collect();
+
+        Ok(ListModelResponse {
+            object: "list".to_string(),
+            data,
+        })
+    }
+
+    async fn chat(&self, request: ChatCompletionParameters) -> Result<ChatCompletionResponse, LlmError> {
+        let gemini_request = self.convert_to_gemini_format(&request);
+        let response = self.post(&self.model_url(&request.model, "generateContent"), &gemini_request).await?;
+
+        let gemini_response: GeminiResponse = response.json().await?;
+        self.convert_from_gemini_format(gemini_response, &request.model)
+    }
+
+    async fn chat_stream(&self, request: ChatCompletionParameters) -> Result<LlmStream, LlmError> {
+        let gemini_request = self.convert_to_gemini_format(&request);
+        let url = format!("{}?alt=sse", self.model_url(&request.model, "streamGenerateContent"));
+        let response = self.post(&url, &gemini_request).await?;
+
+        let mut parser = GeminiStream::new(request.model.clone());
+        let parsed_stream = response
+            .bytes_stream()
+            .map(move |chunk_result| match chunk_result {
+                Ok(chunk) => parser.push(&chunk),
+                Err(e) => vec![Err(Box::new(e) as LlmError)],
+            })
+            .flat_map(stream::iter);
+
+        Ok(Box::new(Box::pin(parsed_stream)))
+    }
+
+    fn supports_functions(&self, _model: String) -> bool {
+        true
+    }
+
+    fn supports_structured_output(&self, _model: String) -> bool {
+        false
+    }
+
+    fn supports_images(&self, _model: String) -> bool {
+        false // image parts are not converted to Gemini inline data
+    }
+
+    fn name(&self) -> &'static str {
+        "gemini"
+    }
+
+    fn info() -> ProviderInfo {
+        ProviderInfo {
+            name: "gemini",
+            display_name: "Google Gemini",
+            env_vars: vec![
+                EnvVar::required("GEMINI_API_KEY", "Gemini API key"),
+                EnvVar::optional("GEMINI_BASE_URL", "Gemini API Base URL (default: https://generativelanguage.googleapis.com/v1beta)"),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider() -> GeminiProvider {
+        GeminiProvider::new("test".to_string(), None)
+    }
+
+    fn request(messages: Vec<ChatMessage>, tools: Option<Vec<ChatCompletionTool>>) -> ChatCompletionParameters {
+        let mut request: ChatCompletionParameters = serde_json::from_value(json!({
+            "model": "gemini-2.0-flash",
+            "messages": [],
+            "temperature": 0.5,
+        })).unwrap();
+        request.messages = messages;
+        request.tools = tools;
+        request
+    }
+
+    #[test]
+    fn test_conversation_to_gemini_format() {
+        let tool: ChatCompletionTool = serde_json::from_value(json!({
+            "type": "function",
+            "function": {
+                "name": "read",
+                "description": "Read a file",
+                "parameters": { "$schema": "x", "type": "object", "additionalProperties": false,
+                                "properties": { "path": { "type": "string" } } },
+            },
+        })).unwrap();
+        let messages = vec![
+            ChatMessage::System { content: ChatMessageContent::Text("Be brief.".to_string()), name: None },
+            ChatMessage::User { content: ChatMessageContent::Text("read a.txt".to_string()), name: None },
+            ChatMessage::Assistant {
+                content: None,
+                reasoning_content: None,
+                refusal: None,
+                name: None,
+                audio: None,
+                tool_calls: Some(vec![ToolCall {
+                    id: "call_1".to_string(),
+                    r#type: "function".to_string(),
+                    function: Function { name: "read".to_string(), arguments: "{\"path\":\"a.txt\"}".to_string() },
+                }]),
+            },
+            ChatMessage::Tool { content: ChatMessageContent::Text("hello".to_string()), tool_call_id: "call_1".to_string() },
+            ChatMessage::User { content: ChatMessageContent::Text("and then?".to_string()), name: None },
+        ];
+
+        let gemini = provider().convert_to_gemini_format(&request(messages, Some(vec![tool])));
+        assert_eq!(gemini["systemInstruction"]["parts"][0]["text"], "Be brief.");
+        assert_eq!(gemini["generationConfig"]["temperature"], 0.5);
+
+        let declaration = &gemini["tools"][0]["functionDeclarations"][0];
+        assert_eq!(declaration["name"], "read");
+        assert!(declaration["parameters"].get("$schema").is_none());
+        assert!(declaration["parameters"].get("additionalProperties").is_none());
+
+        let contents = gemini["contents"].as_array().unwrap();
+        assert_eq!(contents.len(), 3);
+        assert_eq!(contents[1]["role"], "model");
+        assert_eq!(contents[1]["parts"][0]["functionCall"]["args"]["path"], "a.txt");
+        // the function response and the next user message form one turn
+        assert_eq!(contents[2]["role"], "user");
+        assert_eq!(contents[2]["parts"][0]["functionResponse"]["name"], "read");
+        assert_eq!(contents[2]["parts"][0]["functionResponse"]["response"]["content"], "hello");
+        assert_eq!(contents[2]["parts"][1]["text"], "and then?");
+    }
+
+    #[test]
+    fn test_function_call_response() {
+        let response: GeminiResponse = serde_json::from_value(json!({
+            "candidates": [{
+                "content": { "role": "model", "parts": [
+                    { "text": "thinking...", "thought": true },
+                    { "text": "Let me read it." },
+                    { "functionCall": { "name": "read", "args": { "path": "a.txt" } } },
+                ]},
+                "finishReason": "STOP",
+            }],
+            "usageMetadata": { "promptTokenCount": 20, "candidatesTokenCount": 10, "thoughtsTokenCount": 5 },
+        })).unwrap();
+
+        let response = provider().convert_from_gemini_format(response, "gemini-2.0-flash").unwrap();
+        assert_eq!(response.model, "gemini-2.0-flash");
+        assert!(matches!(response.choices[0].finish_reason, Some(FinishReason::ToolCalls)));
+        let usage = response.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.total_tokens), (Some(20), Some(15), 35));
+
+        let ChatMessage::Assistant { content, tool_calls, .. } = &response.choices[0].message else {
+            panic!("expected an assistant message");
+        };
+        assert!(matches!(content, Some(ChatMessageContent::Text(text)) if text == "Let me read it."));
+        let calls = tool_calls.as_ref().unwrap();
+        assert_eq!(calls[0].function.name, "read");
+        assert_eq!(calls[0].function.arguments, r#"{"path":"a.txt"}"#);
+        assert!(calls[0].id.starts_with("call_"));
+    }
+}
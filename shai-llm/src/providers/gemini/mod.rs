@@ -0,0 +1,5 @@
+pub mod api;
+pub mod gemini;
+mod stream;
+
+pub use gemini::GeminiProvider;
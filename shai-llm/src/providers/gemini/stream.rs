@@ -0,0 +1,164 @@
+use openai_dive::v1::resources::chat::{
+    ChatCompletionChunkChoice, ChatCompletionChunkResponse, ChatMessageContent, DeltaChatMessage, DeltaFunction, DeltaToolCall,
+};
+
+use super::api::GeminiResponse;
+use super::gemini::{candidate_output, finish_reason, tool_call, usage};
+use crate::provider::LlmError;
+
+/// Translates the server-sent events of streamGenerateContent (`alt=sse`) into chat completion chunks
+///
+/// Each event carries a full GenerateContentResponse: text parts become content deltas and
+/// function calls, which Gemini never splits, become complete `tool_calls` deltas.
+pub(crate) struct GeminiStream {
+    buffer: Vec<u8>,
+    data: String,
+    id: String,
+    model: String,
+    created: u32,
+    /// Tool calls streamed so far, the index of the next one
+    tool_calls: u32,
+}
+
+impl GeminiStream {
+    pub(crate) fn new(model: String) -> Self {
+        Self {
+            buffer: Vec::new(),
+            data: String::new(),
+            id: format!("gemini-{}", uuid::Uuid::new_v4()),
+            model,
+            created: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as u32,
+            tool_calls: 0,
+        }
+    }
+
+    /// Feed bytes received from the connection, returns the chunks of the events they complete
+    pub(crate) fn push(&mut self, bytes: &[u8]) -> Vec<Result<ChatCompletionChunkResponse, LlmError>> {
+        self.buffer.extend_from_slice(bytes);
+        let mut results = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+
+            if let Some(data) = line.strip_prefix("data:") {
+                self.data.push_str(data.strip_prefix(' ').unwrap_or(data));
+            } else if line.is_empty() && !self.data.is_empty() {
+                // End of an event
+                let data = std::mem::take(&mut self.data);
+                match serde_json::from_str::<GeminiResponse>(&data) {
+                    Ok(response) => results.extend(self.convert_response(response).map(Ok)),
+                    Err(e) => results.push(Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Failed to parse Gemini event: {}. Error: {}", data, e),
+                    )) as LlmError)),
+                }
+            }
+        }
+        results
+    }
+
+    fn convert_response(&mut self, response: GeminiResponse) -> Option<ChatCompletionChunkResponse> {
+        if let Some(id) = response.response_id {
+            self.id = id;
+        }
+        if let Some(model) = response.model_version {
+            self.model = model;
+        }
+
+        let candidate = response.candidates.into_iter().next()?;
+        let reason = candidate.finish_reason.clone();
+        let (text, calls) = candidate_output(candidate);
+
+        let tool_calls: Vec<DeltaToolCall> = calls
+            .into_iter()
+            .map(|call| {
+                let call = tool_call(call);
+                let index = self.tool_calls;
+                self.tool_calls += 1;
+                DeltaToolCall {
+                    index: Some(index),
+                    id: Some(call.id),
+                    r#type: Some(call.r#type),
+                    function: DeltaFunction { name: Some(call.function.name), arguments: Some(call.function.arguments) },
+                }
+            })
+            .collect();
+
+        // usage is cumulative, only the one of the last event is reported
+        let finish_reason = reason.as_deref().map(|reason| finish_reason(reason, self.tool_calls > 0));
+        let usage = finish_reason.as_ref().and(response.usage_metadata.as_ref()).map(usage);
+
+        Some(ChatCompletionChunkResponse {
+            id: Some(self.id.clone()),
+            object: "chat.completion.chunk".to_string(),
+            created: self.created,
+            model: self.model.clone(),
+            choices: vec![ChatCompletionChunkChoice {
+                index: Some(0),
+                delta: DeltaChatMessage::Assistant {
+                    content: (!text.is_empty()).then_some(ChatMessageContent::Text(text)),
+                    reasoning_content: None,
+                    refusal: None,
+                    name: None,
+                    tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+                },
+                finish_reason,
+                logprobs: None,
+            }],
+            usage,
+            system_fingerprint: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StreamAccumulator;
+    use openai_dive::v1::resources::chat::ChatMessage;
+    use openai_dive::v1::resources::shared::FinishReason;
+
+    const EVENTS: &str = concat!(
+        "data: {\"candidates\":[{\"content\":{\"role\":\"model\",\"parts\":[{\"text\":\"Let me \"}]}}],\"modelVersion\":\"gemini-2.0-flash\",\"responseId\":\"r1\"}\r\n\r\n",
+        "data: {\"candidates\":[{\"content\":{\"role\":\"model\",\"parts\":[{\"text\":\"read é.txt. \"}]}}],\"usageMetadata\":{\"promptTokenCount\":25}}\r\n\r\n",
+        "data: {\"candidates\":[{\"content\":{\"role\":\"model\",\"parts\":[{\"functionCall\":{\"name\":\"read\",\"args\":{\"path\":\"é.txt\"}}}]},\"finishReason\":\"STOP\"}],",
+        "\"usageMetadata\":{\"promptTokenCount\":25,\"candidatesTokenCount\":30,\"totalTokenCount\":55}}\r\n\r\n",
+    );
+
+    #[test]
+    fn test_function_call_stream_split_anywhere() {
+        for split in [1, 7, 100, EVENTS.find('é').unwrap() + 1, EVENTS.len() - 3] {
+            let mut stream = GeminiStream::new("gemini".to_string());
+            let (head, tail) = EVENTS.as_bytes().split_at(split);
+            let chunks: Vec<_> = [head, tail]
+                .into_iter()
+                .flat_map(|bytes| stream.push(bytes))
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert_eq!(chunks.len(), 3);
+
+            let mut accumulator = StreamAccumulator::new();
+            for chunk in chunks {
+                accumulator.push(chunk);
+            }
+            let response = accumulator.finish();
+            assert_eq!(response.model, "gemini-2.0-flash");
+            assert!(matches!(response.choices[0].finish_reason, Some(FinishReason::ToolCalls)));
+            let usage = response.usage.unwrap();
+            assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.total_tokens), (Some(25), Some(30), 55));
+
+            let ChatMessage::Assistant { content, tool_calls, .. } = &response.choices[0].message else {
+                panic!("expected an assistant message");
+            };
+            assert!(matches!(content, Some(ChatMessageContent::Text(text)) if text == "Let me read é.txt. "));
+            let calls = tool_calls.as_ref().unwrap();
+            assert_eq!(calls.len(), 1);
+            assert_eq!(calls[0].function.name, "read");
+            assert_eq!(calls[0].function.arguments, r#"{"path":"é.txt"}"#);
+        }
+    }
+}
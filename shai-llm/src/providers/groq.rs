@@ -0,0 +1,144 @@
+// llm/providers/groq.rs
+use crate::provider::{LlmProvider, LlmError, LlmStream, ProviderInfo, EnvVar};
+use crate::providers::openai_compatible::OpenAICompatibleProvider;
+use async_trait::async_trait;
+use openai_dive::v1::resources::{
+    chat::{ChatCompletionParameters, ChatCompletionResponse},
+    model::ListModelResponse,
+};
+
+const GROQ_API_BASE: &str = "https://api.groq.com/openai/v1";
+
+/// Model used when the listing does not offer it first
+const GROQ_DEFAULT_MODEL: &str = "llama-3.3-70b-versatile";
+
+/// Groq models accepting tool calls, matched on the model name prefix
+const GROQ_TOOL_MODELS: &[&str] = &[
+    "llama-3.3-70b",
+    "llama-3.1-8b",
+    "llama3-groq",
+    "meta-llama/llama-4",
+    "qwen/qwen3",
+    "qwen-qwq",
+    "deepseek-r1-distill",
+    "moonshotai/kimi-k2",
+    "openai/gpt-oss",
+    "mistral-saba",
+    "gemma2-9b",
+];
+
+/// Groq models enforcing a JSON schema response format, matched on the model name prefix
+const GROQ_STRUCTURED_OUTPUT_MODELS: &[&str] = &[
+    "openai/gpt-oss",
+    "moonshotai/kimi-k2",
+    "meta-llama/llama-4",
+];
+
+/// Groq's OpenAI-compatible API
+/// Requests go through the OpenAI-compatible client, Groq only differs in its token limit field
+/// and in the models supporting tools and structured output
+pub struct GroqProvider {
+    inner: OpenAICompatibleProvider,
+}
+
+impl GroqProvider {
+    pub fn new(api_key: String, base_url: Option<String>) -> Self {
+        let url = base_url.unwrap_or_else(|| GROQ_API_BASE.to_string());
+        Self { inner: OpenAICompatibleProvider::new(api_key, url) }
+    }
+
+    /// Create Groq provider from environment variables
+    /// Returns None if required environment variables are not set
+    pub fn from_env() -> Option<Self> {
+        std::env::var("GROQ_API_KEY").ok().map(|api_key| {
+            let base_url = std::env::var("GROQ_BASE_URL").ok();
+            Self::new(api_key, base_url)
+        })
+    }
+
+    fn sanitize_request(&self, mut request: ChatCompletionParameters) -> ChatCompletionParameters {
+        // Groq uses max_tokens instead of max_completion_tokens
+        if request.max_completion_tokens.is_some() {
+            request.max_tokens = request.max_completion_tokens;
+            request.max_completion_tokens = None;
+        }
+
+        request
+    }
+}
+
+#[async_trait]
+impl LlmProvider for GroqProvider {
+    async fn models(&self) -> Result<ListModelResponse, LlmError> {
+        self.inner.models().await
+    }
+
+    async fn default_model(&self) -> Result<String, LlmError> {
+        let models = self.models().await?;
+
+        models.data.iter()
+            .find(|m| m.id == GROQ_DEFAULT_MODEL)
+            .or_else(|| models.data.iter().find(|m| self.supports_functions(m.id.clone())))
+            .or_else(|| models.data.first())
+            .map(|m| m.id.clone())
+            .ok_or_else(|| "no model available".into())
+    }
+
+    async fn chat(&self, request: ChatCompletionParameters) -> Result<ChatCompletionResponse, LlmError> {
+        self.inner.chat(self.sanitize_request(request)).await
+    }
+
+    async fn chat_stream(&self, request: ChatCompletionParameters) -> Result<LlmStream, LlmError> {
+        self.inner.chat_stream(self.sanitize_request(request)).await
+    }
+
+    fn supports_functions(&self, model: String) -> bool {
+        let model = model.to_lowercase();
+        GROQ_TOOL_MODELS.iter().any(|prefix| model.starts_with(prefix))
+    }
+
+    fn supports_structured_output(&self, model: String) -> bool {
+        let model = model.to_lowercase();
+        GROQ_STRUCTURED_OUTPUT_MODELS.iter().any(|prefix| model.starts_with(prefix))
+    }
+
+    fn name(&self) -> &'static str {
+        "groq"
+    }
+
+    fn info() -> ProviderInfo {
+        ProviderInfo {
+            name: "groq",
+            display_name: "Groq",
+            env_vars: vec![
+                EnvVar::required("GROQ_API_KEY", "Groq API key"),
+                EnvVar::optional("GROQ_BASE_URL", "Groq base URL (defaults to https://api.groq.com/openai/v1)"),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tool_supporting_models() {
+        let provider = GroqProvider::new("key".to_string(), None);
+        assert!(provider.supports_functions("llama-3.3-70b-versatile".to_string()));
+        assert!(provider.supports_functions("meta-llama/llama-4-scout-17b-16e-instruct".to_string()));
+        assert!(provider.supports_functions("openai/gpt-oss-120b".to_string()));
+        assert!(!provider.supports_functions("whisper-large-v3".to_string()));
+        assert!(!provider.supports_functions("llama-guard-3-8b".to_string()));
+    }
+
+    #[test]
+    fn test_structured_output_models() {
+        let provider = GroqProvider::new("key".to_string(), None);
+        assert!(provider.supports_structured_output("openai/gpt-oss-20b".to_string()));
+        assert!(provider.supports_structured_output("moonshotai/kimi-k2-instruct".to_string()));
+        assert!(!provider.supports_structured_output("llama-3.1-8b-instant".to_string()));
+        // no embeddings API
+        assert!(!provider.supports_embeddings());
+    }
+}
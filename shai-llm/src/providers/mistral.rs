@@ -11,6 +11,11 @@ use openai_dive::v1::resources::{
 };
 use serde::{Deserialize, Serialize};
 
+const MISTRAL_API_BASE: &str = "https://api.mistral.ai/v1";
+
+/// Model families accepting tool calls reliably, matched on the model name prefix
+const MISTRAL_TOOL_MODELS: &[&str] = &["mistral-large", "mistral-small", "codestral"];
+
 #[derive(Debug, Deserialize, Serialize)]
 struct MistralModelCapabilities {
     completion_chat: bool,
@@ -62,8 +67,8 @@ pub struct MistralProvider {
 }
 
 impl MistralProvider {
-    pub fn new(api_key: String) -> Self {
-        let client = ChatClient::new(api_key, "https://api.mistral.ai/v1".to_string());
+    pub fn new(api_key: String, base_url: Option<String>) -> Self {
+        let client = ChatClient::new(api_key, base_url.unwrap_or_else(|| MISTRAL_API_BASE.to_string()));
         Self { 
             client,
             hooks: MistralHooks,
@@ -73,9 +78,10 @@ impl MistralProvider {
     /// Create Mistral provider from environment variables
     /// Returns None if required environment variables are not set
     pub fn from_env() -> Option<Self> {
-        std::env::var("MISTRAL_API_KEY")
-            .ok()
-            .map(|api_key| Self::new(api_key))
+        std::env::var("MISTRAL_API_KEY").ok().map(|api_key| {
+            let base_url = std::env::var("MISTRAL_BASE_URL").ok();
+            Self::new(api_key, base_url)
+        })
     }
 }
 
@@ -208,7 +214,8 @@ impl LlmProvider for MistralProvider {
     }
 
     fn supports_functions(&self, model: String) -> bool {
-        true
+        let model = model.to_lowercase();
+        MISTRAL_TOOL_MODELS.iter().any(|prefix| model.starts_with(prefix))
     }
 
     fn supports_structured_output(&self, model: String) -> bool {
@@ -225,8 +232,107 @@ impl LlmProvider for MistralProvider {
             display_name: "Mistral AI (Mixtral, Pixtral)",
             env_vars: vec![
                 EnvVar::required("MISTRAL_API_KEY", "Mistral AI API key"),
+                EnvVar::optional("MISTRAL_BASE_URL", "Mistral AI base URL (defaults to https://api.mistral.ai/v1)"),
             ],
         }
     }
-    
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::LlmClient;
+    use openai_dive::v1::resources::chat::{ChatCompletionParametersBuilder, ChatMessage, ChatMessageContent};
+    use std::collections::HashMap;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answer one request with `body`, returns the base URL and the raw request received
+    async fn serve_once(body: &'static str) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            let mut buffer = [0u8; 4096];
+            loop {
+                let read = socket.read(&mut buffer).await.unwrap();
+                received.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&received).to_string();
+                if let Some((head, content)) = text.split_once("\r\n\r\n") {
+                    let length = head.lines()
+                        .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                        .unwrap_or(0);
+                    if content.len() >= length || read == 0 {
+                        break;
+                    }
+                }
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&received).to_string()
+        });
+        (base_url, handle)
+    }
+
+    #[test]
+    fn test_tool_supporting_models() {
+        let provider = MistralProvider::new("key".to_string(), None);
+        assert!(provider.supports_functions("mistral-large-latest".to_string()));
+        assert!(provider.supports_functions("mistral-small-2503".to_string()));
+        assert!(provider.supports_functions("codestral-latest".to_string()));
+        assert!(!provider.supports_functions("pixtral-12b-2409".to_string()));
+        assert!(!provider.supports_functions("mistral-embed".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_client_round_trip() {
+        // Mistral leaves out the type of the tool calls it returns
+        let (base_url, server) = serve_once(r#"{
+            "id": "cmpl-1",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": "mistral-large-latest",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": "",
+                    "tool_calls": [{"id": "call_1", "function": {"name": "read", "arguments": "{}"}}]
+                },
+                "finish_reason": "tool_calls"
+            }],
+            "usage": {"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15}
+        }"#).await;
+
+        let env_values = HashMap::from([
+            ("MISTRAL_API_KEY".to_string(), "secret".to_string()),
+            ("MISTRAL_BASE_URL".to_string(), base_url),
+        ]);
+        let client = LlmClient::create_provider("mistral", &env_values).unwrap();
+        assert_eq!(client.provider_name(), "mistral");
+
+        let request = ChatCompletionParametersBuilder::default()
+            .model("mistral-large-latest")
+            .messages(vec![ChatMessage::User { content: ChatMessageContent::Text("read a.txt".to_string()), name: None }])
+            .build()
+            .unwrap();
+        let response = client.chat(request).await.unwrap();
+
+        let received = server.await.unwrap();
+        assert!(received.starts_with("POST /v1/chat/completions"));
+        assert!(received.to_lowercase().contains("authorization: bearer secret"));
+        assert!(received.contains("mistral-large-latest"));
+        match &response.choices[0].message {
+            ChatMessage::Assistant { tool_calls: Some(calls), .. } => {
+                assert_eq!(calls[0].id, "call_1");
+                assert_eq!(calls[0].r#type, "function");
+            }
+            other => panic!("expected tool calls, got {:?}", other),
+        }
+    }
+}
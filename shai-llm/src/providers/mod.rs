@@ -5,6 +5,9 @@ pub mod ovhcloud;
 pub mod anthropic;
 pub mod ollama;
 pub mod mistral;
+pub mod gemini;
+pub mod groq;
+pub mod azure_openai;
 // pub mod mistral_native; // TODO: Complete implementation
 
 #[cfg(test)]
@@ -6,6 +6,7 @@ use openai_dive::v1::{
     api::Client,
     resources::{
         chat::{ChatCompletionChunkResponse, ChatCompletionParameters, ChatCompletionResponse},
+        embedding::{EmbeddingParameters, EmbeddingResponse},
         model::ListModelResponse,
     },
 };
@@ -83,6 +84,12 @@ impl LlmProvider for OllamaProvider {
         Ok(Box::new(Box::pin(converted_stream)))
     }
 
+    async fn embeddings(&self, request: EmbeddingParameters) -> Result<EmbeddingResponse, LlmError> {
+        let response = self.client.embeddings().create(request).await
+            .map_err(|e| Box::new(e) as LlmError)?;
+        Ok(response)
+    }
+
     fn supports_functions(&self, _model: String) -> bool {
         true
     }
@@ -91,6 +98,10 @@ impl LlmProvider for OllamaProvider {
         true
     }
 
+    fn supports_embeddings(&self) -> bool {
+        true
+    }
+
     fn name(&self) -> &'static str {
         "ollama"
     }
@@ -6,6 +6,7 @@ use openai_dive::v1::{
     api::Client,
     resources::{
         chat::{ChatCompletionParameters, ChatCompletionResponse, ChatCompletionChunkResponse},
+        embedding::{EmbeddingParameters, EmbeddingResponse},
         model::ListModelResponse,
     },
 };
@@ -68,6 +69,12 @@ impl LlmProvider for OpenAIProvider {
         Ok(Box::new(Box::pin(converted_stream)))
     }
 
+    async fn embeddings(&self, request: EmbeddingParameters) -> Result<EmbeddingResponse, LlmError> {
+        let response = self.client.embeddings().create(request).await
+            .map_err(|e| Box::new(e) as LlmError)?;
+        Ok(response)
+    }
+
     fn supports_functions(&self, model: String) -> bool {
         true
     }
@@ -76,6 +83,10 @@ impl LlmProvider for OpenAIProvider {
         true
     }
 
+    fn supports_embeddings(&self) -> bool {
+        true
+    }
+
     fn name(&self) -> &'static str {
         "openai"
     }
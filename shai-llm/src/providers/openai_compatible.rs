@@ -6,6 +6,7 @@ use openai_dive::v1::{
     api::Client,
     resources::{
         chat::{ChatCompletionParameters, ChatCompletionResponse, ChatCompletionChunkResponse},
+        embedding::{EmbeddingParameters, EmbeddingResponse},
         model::ListModelResponse,
         shared::Usage,
     },
@@ -64,6 +65,12 @@ impl LlmProvider for OpenAICompatibleProvider {
         Ok(Box::new(Box::pin(converted_stream)))
     }
 
+    async fn embeddings(&self, request: EmbeddingParameters) -> Result<EmbeddingResponse, LlmError> {
+        let response = self.client.embeddings().create(request).await
+            .map_err(|e| Box::new(e) as LlmError)?;
+        Ok(response)
+    }
+
     fn supports_functions(&self, model: String) -> bool {
         true
     }
@@ -72,6 +79,10 @@ impl LlmProvider for OpenAICompatibleProvider {
         true
     }
 
+    fn supports_embeddings(&self) -> bool {
+        true
+    }
+
     fn name(&self) -> &'static str {
         "openai_compatible"
     }
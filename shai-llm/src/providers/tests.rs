@@ -270,6 +270,8 @@ pub fn create_provider_from_env(provider_name: &str) -> Option<Box<dyn LlmProvid
         "openai_compatible" => crate::providers::openai_compatible::OpenAICompatibleProvider::from_env().map(|p| Box::new(p) as Box<dyn LlmProvider>),
         "ovhcloud" => crate::providers::ovhcloud::OvhCloudProvider::from_env().map(|p| Box::new(p) as Box<dyn LlmProvider>),
         "mistral" => crate::providers::mistral::MistralProvider::from_env().map(|p| Box::new(p) as Box<dyn LlmProvider>),
+        "gemini" => crate::providers::gemini::GeminiProvider::from_env().map(|p| Box::new(p) as Box<dyn LlmProvider>),
+        "groq" => crate::providers::groq::GroqProvider::from_env().map(|p| Box::new(p) as Box<dyn LlmProvider>),
         _ => None,
     }
 }
@@ -336,7 +338,9 @@ register_providers_for_testing!(
     openrouter,
     openai_compatible,
     ovhcloud,
-    mistral
+    mistral,
+    gemini,
+    groq
 );
 
 /// Additional integration tests
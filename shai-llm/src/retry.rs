@@ -0,0 +1,291 @@
+// llm/retry.rs
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use openai_dive::v1::error::APIError;
+use openai_dive::v1::resources::{
+    chat::{ChatCompletionParameters, ChatCompletionResponse},
+    embedding::{EmbeddingParameters, EmbeddingResponse},
+    model::ListModelResponse,
+};
+use tracing::warn;
+
+use crate::logging::log_llm_error;
+use crate::provider::{LlmError, LlmProvider, LlmStream, ProviderInfo};
+
+/// Retry settings of a client, transient errors are retried with exponential backoff
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryConfig {
+    /// Attempts of a request, the first one included (1 = no retry)
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled on each retry
+    pub initial_delay: Duration,
+    /// Upper bound of a delay, `Retry-After` included
+    pub max_delay: Duration,
+    /// Wait a random time between half and all of the delay, so that clients do not retry in lockstep
+    pub jitter: bool,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Backoff before retry number `retry` (1 = first retry)
+    fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        let delay = self.initial_delay.saturating_mul(factor).min(self.max_delay);
+        if !self.jitter {
+            return delay;
+        }
+        let half = delay / 2;
+        half + Duration::from_millis(fastrand::u64(0..=half.as_millis() as u64))
+    }
+
+    /// Wait before retry number `retry`, the server's `Retry-After` when it asks for longer
+    fn delay(&self, retry: u32, error: &LlmError) -> Duration {
+        let retry_after = error.downcast_ref::<HttpStatusError>().and_then(|e| e.retry_after);
+        self.backoff(retry).max(retry_after.unwrap_or_default()).min(self.max_delay)
+    }
+}
+
+/// Non-success HTTP answer of a provider API, with the `Retry-After` it sent
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub status: u16,
+    pub retry_after: Option<Duration>,
+    pub message: String,
+}
+
+impl HttpStatusError {
+    /// Error of a non-success `response`, `context` names the API ("Anthropic API error")
+    pub async fn from_response(context: &str, response: reqwest::Response) -> Self {
+        let status = response.status().as_u16();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        let body = response.text().await.unwrap_or_default();
+        Self { status, retry_after, message: format!("{} ({}): {}", context, status, body) }
+    }
+}
+
+impl fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+/// `Retry-After` value: delay in seconds or HTTP date
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or_default())
+}
+
+/// HTTP status of a failed request, when the error carries one
+pub(crate) fn status_of(error: &LlmError) -> Option<u16> {
+    if let Some(error) = error.downcast_ref::<HttpStatusError>() {
+        return Some(error.status);
+    }
+    if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+        return error.status().map(|status| status.as_u16());
+    }
+    match error.downcast_ref::<APIError>()? {
+        APIError::UnknownError(status, _) => Some(*status),
+        APIError::InvalidRequestError(_) => Some(400),
+        APIError::AuthenticationError(_) => Some(401),
+        APIError::PermissionError(_) => Some(403),
+        APIError::NotFoundError(_) => Some(404),
+        APIError::RateLimitError(_) => Some(429),
+        _ => None,
+    }
+}
+
+/// Whether a failed request may succeed when sent again: rate limits, timeouts, server errors
+/// and connection failures. Other 4xx answers would fail the same way.
+pub fn is_transient(error: &LlmError) -> bool {
+    if let Some(status) = status_of(error) {
+        return status == 429 || status == 408 || status >= 500;
+    }
+    if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+        return error.is_timeout() || error.is_connect();
+    }
+    // providers with their own error types
+    let message = error.to_string().to_lowercase();
+    let transient = ["429", "rate limit", "500", "502", "503", "504", "overloaded", "timed out", "timeout"];
+    transient.iter().any(|hint| message.contains(hint))
+}
+
+/// Provider retrying the transient errors of another provider
+pub struct RetryProvider {
+    inner: Box<dyn LlmProvider>,
+    config: RetryConfig,
+}
+
+impl RetryProvider {
+    pub fn new(inner: Box<dyn LlmProvider>, config: RetryConfig) -> Self {
+        Self { inner, config }
+    }
+
+    /// Send `request` until it succeeds, fails for good or runs out of attempts
+    /// Retried failures are logged here, the last one is left to the caller
+    async fn retry<T, F, Fut>(&self, request: &ChatCompletionParameters, call: F) -> Result<T, LlmError>
+    where
+        F: Fn(ChatCompletionParameters) -> Fut,
+        Fut: Future<Output = Result<T, LlmError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let error = match call(request.clone()).await {
+                Ok(response) => return Ok(response),
+                Err(error) if attempt >= self.config.max_attempts || !is_transient(&error) => return Err(error),
+                Err(error) => error,
+            };
+            log_llm_error(request, &error, self.inner.name());
+            let delay = self.config.delay(attempt, &error);
+            warn!("{} request failed (attempt {}/{}), retrying in {}ms: {}",
+                self.inner.name(), attempt, self.config.max_attempts, delay.as_millis(), error);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for RetryProvider {
+    async fn models(&self) -> Result<ListModelResponse, LlmError> {
+        self.inner.models().await
+    }
+
+    async fn default_model(&self) -> Result<String, LlmError> {
+        self.inner.default_model().await
+    }
+
+    async fn chat(&self, request: ChatCompletionParameters) -> Result<ChatCompletionResponse, LlmError> {
+        self.retry(&request, |request| self.inner.chat(request)).await
+    }
+
+    /// Only opening the stream is retried, errors in the middle of it are returned as is
+    async fn chat_stream(&self, request: ChatCompletionParameters) -> Result<LlmStream, LlmError> {
+        self.retry(&request, |request| self.inner.chat_stream(request)).await
+    }
+
+    /// Forwarded as is, the retries cover the chat requests
+    async fn embeddings(&self, request: EmbeddingParameters) -> Result<EmbeddingResponse, LlmError> {
+        self.inner.embeddings(request).await
+    }
+
+    fn supports_functions(&self, model: String) -> bool {
+        self.inner.supports_functions(model)
+    }
+
+    fn supports_structured_output(&self, model: String) -> bool {
+        self.inner.supports_structured_output(model)
+    }
+
+    fn supports_embeddings(&self) -> bool {
+        self.inner.supports_embeddings()
+    }
+
+    fn supports_images(&self, model: String) -> bool {
+        self.inner.supports_images(model)
+    }
+
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn info() -> ProviderInfo {
+        ProviderInfo {
+            name: "retry",
+            display_name: "Retry (retries another provider)",
+            env_vars: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockProvider, MockState};
+    use openai_dive::v1::resources::chat::ChatCompletionParametersBuilder;
+    use std::sync::{Arc, Mutex};
+
+    fn provider(statuses: Vec<u16>) -> (Arc<MockState>, RetryProvider) {
+        let (state, mock) = MockProvider::new(MockState { failures: Mutex::new(statuses.into()), ..Default::default() });
+        let config = RetryConfig { initial_delay: Duration::from_millis(1), jitter: false, ..Default::default() };
+        (state, RetryProvider::new(Box::new(mock), config))
+    }
+
+    fn request() -> ChatCompletionParameters {
+        ChatCompletionParametersBuilder::default().model("mock").messages(vec![]).build().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_transient_errors_retried() {
+        let (state, provider) = provider(vec![503, 429]);
+        assert!(provider.chat(request()).await.is_ok());
+        assert_eq!(state.calls(), 3);
+
+        // gives up after max_attempts
+        let (state, provider) = provider(vec![500, 502, 504, 500]);
+        assert!(provider.chat(request()).await.is_err());
+        assert_eq!(state.calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_permanent_errors_not_retried() {
+        let (state, provider) = provider(vec![400]);
+        assert!(provider.chat(request()).await.is_err());
+        assert_eq!(state.calls(), 1);
+    }
+
+    #[test]
+    fn test_backoff_and_retry_after() {
+        let config = RetryConfig { jitter: false, ..Default::default() };
+        assert_eq!(config.backoff(1), Duration::from_millis(500));
+        assert_eq!(config.backoff(3), Duration::from_secs(2));
+        assert_eq!(config.backoff(20), Duration::from_secs(30));
+
+        let jittered = RetryConfig::default().backoff(2);
+        assert!(jittered >= Duration::from_millis(500) && jittered <= Duration::from_secs(1));
+
+        assert_eq!(parse_retry_after(" 7 "), Some(Duration::from_secs(7)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon"), None);
+
+        let error: LlmError = Box::new(HttpStatusError {
+            status: 429,
+            retry_after: Some(Duration::from_secs(10)),
+            message: "slow down".to_string(),
+        });
+        assert!(is_transient(&error));
+        assert_eq!(config.delay(1, &error), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_supports_embeddings_forwarded() {
+        let (_, provider) = provider(vec![]);
+        assert!(!provider.supports_embeddings());
+
+        let ollama = crate::providers::ollama::OllamaProvider::new(None, None);
+        let provider = RetryProvider::new(Box::new(ollama), RetryConfig::default());
+        assert!(provider.supports_embeddings());
+    }
+}
@@ -0,0 +1,560 @@
+// llm/rotation.rs
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use async_trait::async_trait;
+use openai_dive::v1::error::APIError;
+use openai_dive::v1::resources::{
+    chat::{ChatCompletionParameters, ChatCompletionResponse},
+    embedding::{EmbeddingParameters, EmbeddingResponse},
+    model::ListModelResponse,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn};
+
+use crate::provider::{LlmError, LlmProvider, LlmStream, ProviderInfo};
+
+/// How the next key is picked among the healthy ones
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyStrategy {
+    /// Each key in turn
+    #[default]
+    RoundRobin,
+    /// The key that was rate limited the longest time ago (never limited first)
+    LeastRecentlyLimited,
+}
+
+/// Several API keys for a single provider entry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyPoolConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<String>,
+    /// File with one key per line, reloaded when it changes (rotation without restart)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keys_file: Option<PathBuf>,
+    #[serde(default)]
+    pub strategy: KeyStrategy,
+    /// Time a rate limited key is left aside
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: u64,
+    /// Called with a JSON payload when a key gets quarantined
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alert_webhook: Option<String>,
+}
+
+fn default_cooldown_secs() -> u64 {
+    60
+}
+
+impl Default for KeyPoolConfig {
+    fn default() -> Self {
+        Self {
+            keys: Vec::new(),
+            keys_file: None,
+            strategy: KeyStrategy::default(),
+            cooldown_secs: default_cooldown_secs(),
+            alert_webhook: None,
+        }
+    }
+}
+
+/// Builds the provider for a given key
+pub type KeyedProviderFactory = Arc<dyn Fn(String) -> Result<Box<dyn LlmProvider>, LlmError> + Send + Sync>;
+
+/// Usage of a key, keys are only ever shown by their fingerprint
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KeyUsage {
+    pub key: String,
+    pub requests: u64,
+    pub rate_limited: u64,
+    pub unauthorized: u64,
+    pub quarantined: bool,
+    pub cooling_down: bool,
+}
+
+/// Outcome of a request, as far as the key is concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyOutcome {
+    Ok,
+    RateLimited,
+    Unauthorized,
+    /// Failed for a reason unrelated to the key
+    Other,
+}
+
+impl KeyOutcome {
+    fn of(error: &LlmError) -> Self {
+        match error.downcast_ref::<APIError>() {
+            Some(APIError::RateLimitError(_)) | Some(APIError::UnknownError(429, _)) => return Self::RateLimited,
+            Some(APIError::AuthenticationError(_)) | Some(APIError::UnknownError(401, _)) => return Self::Unauthorized,
+            Some(_) => return Self::Other,
+            None => {}
+        }
+        // providers with their own error types
+        let message = error.to_string().to_lowercase();
+        if message.contains("429") || message.contains("rate limit") {
+            Self::RateLimited
+        } else if message.contains("401") || message.contains("unauthorized") || message.contains("invalid api key") {
+            Self::Unauthorized
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// Stable, non reversible name of a key for logs, metrics and rate limit buckets
+/// 64 bits of a blake3 hash: distinct keys do not share a name, nor a bucket
+pub fn key_fingerprint(key: &str) -> String {
+    format!("key_{}", &blake3::hash(key.as_bytes()).to_hex()[..16])
+}
+
+struct KeyState {
+    key: String,
+    fingerprint: String,
+    provider: Arc<dyn LlmProvider>,
+    requests: u64,
+    rate_limited: u64,
+    unauthorized: u64,
+    quarantined: bool,
+    cooldown_until: Option<Instant>,
+    last_limited: Option<Instant>,
+}
+
+impl KeyState {
+    fn available(&self, now: Instant) -> bool {
+        !self.quarantined && self.cooldown_until.map_or(true, |until| until <= now)
+    }
+}
+
+struct PoolState {
+    keys: Vec<KeyState>,
+    cursor: usize,
+    file_modified: Option<SystemTime>,
+}
+
+/// Provider spreading requests over several keys, with per-key health tracking
+///
+/// A rate limited key cools down for `cooldown_secs`, a rejected key (401) is quarantined
+/// until it disappears from the key file. Requests fail over to the next key on both.
+pub struct RotatingProvider {
+    name: &'static str,
+    config: KeyPoolConfig,
+    factory: KeyedProviderFactory,
+    state: Mutex<PoolState>,
+}
+
+impl RotatingProvider {
+    pub fn new(config: KeyPoolConfig, factory: KeyedProviderFactory) -> Result<Self, LlmError> {
+        let (keys, file_modified) = Self::read_keys(&config)?;
+        if keys.is_empty() {
+            return Err("key pool has no key".into());
+        }
+
+        let mut states = Vec::with_capacity(keys.len());
+        for key in keys {
+            states.push(Self::key_state(&factory, key)?);
+        }
+        let name = states[0].provider.name();
+
+        info!("{} key pool: {} keys ({:?})", name, states.len(), config.strategy);
+        Ok(Self {
+            name,
+            config,
+            factory,
+            state: Mutex::new(PoolState { keys: states, cursor: 0, file_modified }),
+        })
+    }
+
+    fn key_state(factory: &KeyedProviderFactory, key: String) -> Result<KeyState, LlmError> {
+        let provider: Arc<dyn LlmProvider> = Arc::from(factory(key.clone())?);
+        Ok(KeyState {
+            fingerprint: key_fingerprint(&key),
+            key,
+            provider,
+            requests: 0,
+            rate_limited: 0,
+            unauthorized: 0,
+            quarantined: false,
+            cooldown_until: None,
+            last_limited: None,
+        })
+    }
+
+    /// Inline keys followed by the keys of the key file
+    fn read_keys(config: &KeyPoolConfig) -> Result<(Vec<String>, Option<SystemTime>), LlmError> {
+        let mut keys: Vec<String> = config.keys.iter().filter(|k| !k.is_empty()).cloned().collect();
+        let mut modified = None;
+        if let Some(path) = &config.keys_file {
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read key file {}: {}", path.display(), e))?;
+            modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+            keys.extend(
+                content.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_string),
+            );
+        }
+        let mut seen = std::collections::HashSet::new();
+        keys.retain(|key| seen.insert(key.clone()));
+        Ok((keys, modified))
+    }
+
+    /// Reload the keys, health of the keys still present is kept. Returns the number of keys
+    pub fn reload(&self) -> Result<usize, LlmError> {
+        let (keys, modified) = Self::read_keys(&self.config)?;
+        if keys.is_empty() {
+            return Err("key pool reload found no key, keeping the current keys".into());
+        }
+
+        // providers of new keys are built first, a failure leaves the pool untouched
+        let known: Vec<String> = self.state.lock().unwrap().keys.iter().map(|s| s.key.clone()).collect();
+        let mut added = Vec::new();
+        for key in keys.iter().filter(|key| !known.contains(key)) {
+            added.push(Self::key_state(&self.factory, key.clone())?);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let mut previous: Vec<KeyState> = std::mem::take(&mut state.keys);
+        let mut states = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(idx) = previous.iter().position(|s| s.key == key) {
+                states.push(previous.swap_remove(idx));
+            } else if let Some(idx) = added.iter().position(|s| s.key == key) {
+                states.push(added.swap_remove(idx));
+            }
+        }
+
+        info!("{} key pool reloaded: {} keys ({} removed)", self.name, states.len(), previous.len());
+        state.keys = states;
+        state.cursor = 0;
+        state.file_modified = modified;
+        Ok(state.keys.len())
+    }
+
+    /// Reload when the key file changed since it was last read
+    fn reload_if_changed(&self) {
+        let Some(path) = &self.config.keys_file else {
+            return;
+        };
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        if modified.is_none() || modified == self.state.lock().unwrap().file_modified {
+            return;
+        }
+        if let Err(e) = self.reload() {
+            warn!("{} key pool: {}", self.name, e);
+        }
+    }
+
+    /// Per key usage, for logs and admin endpoints
+    pub fn usage(&self) -> Vec<KeyUsage> {
+        let now = Instant::now();
+        self.state.lock().unwrap().keys.iter()
+            .map(|s| KeyUsage {
+                key: s.fingerprint.clone(),
+                requests: s.requests,
+                rate_limited: s.rate_limited,
+                unauthorized: s.unauthorized,
+                quarantined: s.quarantined,
+                cooling_down: !s.quarantined && !s.available(now),
+            })
+            .collect()
+    }
+
+    /// Pick the key for the next attempt, keys in `tried` are skipped
+    fn pick(&self, tried: &[String]) -> Option<(String, Arc<dyn LlmProvider>)> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let count = state.keys.len();
+        let candidates: Vec<usize> = (0..count)
+            .map(|offset| (state.cursor + offset) % count)
+            .filter(|&idx| !state.keys[idx].quarantined && !tried.contains(&state.keys[idx].fingerprint))
+            .collect();
+
+        let available = candidates.iter().copied().filter(|&idx| state.keys[idx].available(now));
+        let chosen = match self.config.strategy {
+            KeyStrategy::RoundRobin => available.clone().next(),
+            // min_by_key keeps the first of equal keys, so ties are broken in round robin order
+            KeyStrategy::LeastRecentlyLimited => available.clone().min_by_key(|&idx| state.keys[idx].last_limited),
+        }
+        // every key cools down: the one available soonest beats failing the request
+        .or_else(|| candidates.iter().copied().min_by_key(|&idx| state.keys[idx].cooldown_until))?;
+
+        state.cursor = (chosen + 1) % count;
+        let key = &mut state.keys[chosen];
+        key.requests += 1;
+        debug!("{} key pool: using {} ({} requests)", self.name, key.fingerprint, key.requests);
+        #[cfg(feature = "prometheus")]
+        metrics::counter!("shai_llm_key_requests_total", "provider" => self.name, "key" => key.fingerprint.clone()).increment(1);
+        Some((key.fingerprint.clone(), key.provider.clone()))
+    }
+
+    fn report(&self, fingerprint: &str, outcome: KeyOutcome) {
+        if matches!(outcome, KeyOutcome::Ok | KeyOutcome::Other) {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let Some(key) = state.keys.iter_mut().find(|s| s.fingerprint == fingerprint) else {
+            return;
+        };
+        match outcome {
+            KeyOutcome::RateLimited => {
+                let now = Instant::now();
+                key.rate_limited += 1;
+                key.last_limited = Some(now);
+                key.cooldown_until = Some(now + Duration::from_secs(self.config.cooldown_secs));
+                warn!("{} key pool: {} rate limited, cooling down for {}s", self.name, key.fingerprint, self.config.cooldown_secs);
+                #[cfg(feature = "prometheus")]
+                metrics::counter!("shai_llm_key_rate_limited_total", "provider" => self.name, "key" => key.fingerprint.clone()).increment(1);
+            }
+            KeyOutcome::Unauthorized => {
+                key.unauthorized += 1;
+                if key.quarantined {
+                    return;
+                }
+                key.quarantined = true;
+                error!("{} key pool: {} rejected by the provider, quarantined", self.name, key.fingerprint);
+                #[cfg(feature = "prometheus")]
+                metrics::counter!("shai_llm_key_quarantined_total", "provider" => self.name, "key" => key.fingerprint.clone()).increment(1);
+                self.alert(&key.fingerprint);
+            }
+            KeyOutcome::Ok | KeyOutcome::Other => {}
+        }
+    }
+
+    /// Notify the alert webhook that a key got quarantined
+    fn alert(&self, fingerprint: &str) {
+        let Some(url) = self.config.alert_webhook.clone() else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let payload = serde_json::json!({
+            "event": "key_quarantined",
+            "provider": self.name,
+            "key": fingerprint,
+            "at": chrono::Utc::now().to_rfc3339(),
+        });
+        runtime.spawn(async move {
+            if let Err(e) = reqwest::Client::new().post(&url).json(&payload).send().await {
+                warn!("key pool alert webhook failed: {}", e);
+            }
+        });
+    }
+
+    /// Run a request, failing over to the next key when a key is rate limited or rejected
+    async fn with_failover<T, F, Fut>(&self, call: F) -> Result<T, LlmError>
+    where
+        F: Fn(Arc<dyn LlmProvider>) -> Fut,
+        Fut: std::future::Future<Output = Result<T, LlmError>>,
+    {
+        self.reload_if_changed();
+
+        let mut tried = Vec::new();
+        let mut last_error: Option<LlmError> = None;
+        while let Some((fingerprint, provider)) = self.pick(&tried) {
+            match call(provider).await {
+                Ok(result) => return Ok(result),
+                Err(e) => {
+                    let outcome = KeyOutcome::of(&e);
+                    self.report(&fingerprint, outcome);
+                    if outcome == KeyOutcome::Other {
+                        return Err(e);
+                    }
+                    tried.push(fingerprint);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| format!("all {} API keys are quarantined", self.name).into()))
+    }
+
+    /// Provider of any usable key, for calls that do not hit the API
+    fn any_provider(&self) -> Arc<dyn LlmProvider> {
+        let state = self.state.lock().unwrap();
+        state.keys.iter()
+            .find(|s| !s.quarantined)
+            .unwrap_or(&state.keys[0])
+            .provider
+            .clone()
+    }
+}
+
+#[async_trait]
+impl LlmProvider for RotatingProvider {
+    async fn models(&self) -> Result<ListModelResponse, LlmError> {
+        self.with_failover(|provider| async move { provider.models().await }).await
+    }
+
+    async fn default_model(&self) -> Result<String, LlmError> {
+        self.with_failover(|provider| async move { provider.default_model().await }).await
+    }
+
+    async fn chat(&self, request: ChatCompletionParameters) -> Result<ChatCompletionResponse, LlmError> {
+        self.with_failover(|provider| {
+            let request = request.clone();
+            async move { provider.chat(request).await }
+        }).await
+    }
+
+    /// Failover only covers opening the stream, errors in the middle of it are returned as is
+    async fn chat_stream(&self, request: ChatCompletionParameters) -> Result<LlmStream, LlmError> {
+        self.with_failover(|provider| {
+            let request = request.clone();
+            async move { provider.chat_stream(request).await }
+        }).await
+    }
+
+    async fn embeddings(&self, request: EmbeddingParameters) -> Result<EmbeddingResponse, LlmError> {
+        self.with_failover(|provider| {
+            let request = request.clone();
+            async move { provider.embeddings(request).await }
+        }).await
+    }
+
+    fn supports_functions(&self, model: String) -> bool {
+        self.any_provider().supports_functions(model)
+    }
+
+    fn supports_structured_output(&self, model: String) -> bool {
+        self.any_provider().supports_structured_output(model)
+    }
+
+    fn supports_embeddings(&self) -> bool {
+        self.any_provider().supports_embeddings()
+    }
+
+    fn supports_images(&self, model: String) -> bool {
+        self.any_provider().supports_images(model)
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn info() -> ProviderInfo {
+        ProviderInfo {
+            name: "key_pool",
+            display_name: "Key pool (several keys of one provider)",
+            env_vars: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockProvider, MockState};
+    use openai_dive::v1::resources::chat::ChatCompletionParametersBuilder;
+
+    /// Answers according to the key: "limited" keys get 429, "revoked" keys get 401
+    /// The answers carry the key as model, to tell which key served a request
+    fn keyed_provider(key: String) -> MockProvider {
+        let status = if key.starts_with("limited") {
+            Some(429)
+        } else if key.starts_with("revoked") {
+            Some(401)
+        } else {
+            None
+        };
+        MockProvider(Arc::new(MockState { failing: Mutex::new(status), model: Some(key), ..Default::default() }))
+    }
+
+    fn pool(keys: &[&str], strategy: KeyStrategy) -> RotatingProvider {
+        let config = KeyPoolConfig {
+            keys: keys.iter().map(|k| k.to_string()).collect(),
+            strategy,
+            ..Default::default()
+        };
+        let factory: KeyedProviderFactory = Arc::new(|key| Ok(Box::new(keyed_provider(key)) as Box<dyn LlmProvider>));
+        RotatingProvider::new(config, factory).unwrap()
+    }
+
+    fn request() -> ChatCompletionParameters {
+        ChatCompletionParametersBuilder::default()
+            .model("test")
+            .messages(vec![])
+            .build()
+            .unwrap()
+    }
+
+    async fn served_by(provider: &RotatingProvider) -> String {
+        provider.chat(request()).await.unwrap().model
+    }
+
+    #[test]
+    fn test_key_fingerprint() {
+        let fingerprint = key_fingerprint("sk-one");
+        assert_eq!(fingerprint, key_fingerprint("sk-one"));
+        assert_ne!(fingerprint, key_fingerprint("sk-two"));
+        assert_eq!(fingerprint.len(), "key_".len() + 16);
+        assert!(!fingerprint.contains("sk-one"));
+    }
+
+    #[tokio::test]
+    async fn test_round_robin() {
+        let provider = pool(&["a", "b", "c"], KeyStrategy::RoundRobin);
+        let mut served = Vec::new();
+        for _ in 0..4 {
+            served.push(served_by(&provider).await);
+        }
+        assert_eq!(served, vec!["a", "b", "c", "a"]);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_key_fails_over_and_cools_down() {
+        let provider = pool(&["limited", "b"], KeyStrategy::LeastRecentlyLimited);
+        assert_eq!(served_by(&provider).await, "b");
+        // the limited key cools down, every request goes to the other one
+        assert_eq!(served_by(&provider).await, "b");
+
+        let usage = provider.usage();
+        assert_eq!(usage[0].rate_limited, 1);
+        assert!(usage[0].cooling_down);
+        assert!(!usage[1].cooling_down);
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized_key_is_quarantined() {
+        let provider = pool(&["revoked", "b"], KeyStrategy::RoundRobin);
+        for _ in 0..3 {
+            assert_eq!(served_by(&provider).await, "b");
+        }
+        let usage = provider.usage();
+        assert!(usage[0].quarantined);
+        assert_eq!(usage[0].requests, 1);
+
+        // keys never show up in usage or errors
+        assert!(usage.iter().all(|u| u.key.starts_with("key_") && u.key != "revoked"));
+        let all_revoked = pool(&["revoked-1", "revoked-2"], KeyStrategy::RoundRobin);
+        let error = all_revoked.chat(request()).await.unwrap_err().to_string();
+        assert!(!error.contains("revoked-"));
+        assert!(all_revoked.chat(request()).await.unwrap_err().to_string().contains("quarantined"));
+    }
+
+    #[tokio::test]
+    async fn test_keys_reload_from_file() {
+        let path = std::env::temp_dir().join(format!("shai-keys-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "# rotated weekly\nrevoked\nb\n").unwrap();
+        let config = KeyPoolConfig { keys_file: Some(path.clone()), ..Default::default() };
+        let factory: KeyedProviderFactory = Arc::new(|key| Ok(Box::new(keyed_provider(key)) as Box<dyn LlmProvider>));
+        let provider = RotatingProvider::new(config, factory).unwrap();
+
+        assert_eq!(served_by(&provider).await, "b");
+        assert!(provider.usage()[0].quarantined);
+
+        std::fs::write(&path, "b\nc\n").unwrap();
+        assert_eq!(provider.reload().unwrap(), 2);
+        let usage = provider.usage();
+        assert!(usage.iter().all(|u| !u.quarantined));
+        // health of the remaining key is kept
+        assert_eq!(usage[0].requests, 1);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,390 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use async_trait::async_trait;
+use futures::StreamExt;
+use openai_dive::v1::resources::chat::{
+    ChatCompletionChoice, ChatCompletionChunkResponse, ChatCompletionParameters, ChatCompletionParametersBuilder,
+    ChatCompletionResponse, ChatMessage, ChatMessageContent, DeltaChatMessage, Function, ToolCall,
+};
+use openai_dive::v1::resources::shared::{FinishReason, Usage};
+use uuid::Uuid;
+
+use crate::{client::ExtractThinkContent, provider::{LlmError, LlmStream}, tool::{keep_sampling, ToolBox}, FunctionCallingAutoBuilder, LlmClient};
+
+/// A fragment of tool call arguments, as generated by the model
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCallDelta {
+    /// Position of the call in the assistant message
+    pub index: u32,
+    /// Normalized call id, the same id ends up in the assembled tool call
+    pub call_id: String,
+    /// Function name (empty until the provider sent it)
+    pub name: String,
+    pub arguments: String,
+}
+
+#[derive(Debug, Default)]
+struct PartialToolCall {
+    id: Option<String>,
+    name: String,
+    arguments: String,
+}
+
+/// Folds the chunks of a streamed completion into a complete response
+///
+/// Providers differ in how they stream tool calls: some repeat the id on every chunk,
+/// some only send it on the first one, some never send it. Calls are keyed by index and
+/// keep the first id seen, a call without id gets a generated one.
+#[derive(Debug, Default)]
+pub struct StreamAccumulator {
+    id: Option<String>,
+    model: String,
+    created: u32,
+    content: String,
+    reasoning: String,
+    tool_calls: BTreeMap<u32, PartialToolCall>,
+    finish_reason: Option<FinishReason>,
+    usage: Option<Usage>,
+}
+
+impl StreamAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a chunk, returns the tool call argument fragments it carried
+    pub fn push(&mut self, chunk: ChatCompletionChunkResponse) -> Vec<ToolCallDelta> {
+        if self.id.is_none() {
+            self.id = chunk.id;
+        }
+        if self.model.is_empty() {
+            self.model = chunk.model;
+        }
+        if self.created == 0 {
+            self.created = chunk.created;
+        }
+        if chunk.usage.is_some() {
+            self.usage = chunk.usage;
+        }
+
+        let mut deltas = Vec::new();
+        // agents only request a single choice
+        for choice in chunk.choices.into_iter().filter(|c| c.index.unwrap_or(0) == 0) {
+            if choice.finish_reason.is_some() {
+                self.finish_reason = choice.finish_reason;
+            }
+
+            let (content, reasoning_content, tool_calls) = match choice.delta {
+                DeltaChatMessage::Assistant { content, reasoning_content, tool_calls, .. }
+                | DeltaChatMessage::Untagged { content, reasoning_content, tool_calls, .. } => (content, reasoning_content, tool_calls),
+                _ => continue,
+            };
+
+            if let Some(ChatMessageContent::Text(text)) = content {
+                self.content.push_str(&text);
+            }
+            if let Some(reasoning) = reasoning_content {
+                self.reasoning.push_str(&reasoning);
+            }
+
+            for (position, call) in tool_calls.unwrap_or_default().into_iter().enumerate() {
+                let index = call.index.unwrap_or(position as u32);
+                let partial = self.tool_calls.entry(index).or_default();
+                if partial.id.is_none() {
+                    partial.id = call.id.filter(|id| !id.is_empty());
+                }
+                if let Some(name) = call.function.name.filter(|name| !name.is_empty()) {
+                    if partial.name.is_empty() {
+                        partial.name = name;
+                    }
+                }
+
+                let Some(arguments) = call.function.arguments.filter(|a| !a.is_empty()) else {
+                    continue;
+                };
+                partial.arguments.push_str(&arguments);
+                let call_id = partial.id.get_or_insert_with(|| format!("call_{}", Uuid::new_v4().simple())).clone();
+                deltas.push(ToolCallDelta { index, call_id, name: partial.name.clone(), arguments });
+            }
+        }
+        deltas
+    }
+
+    /// The complete response, tool call arguments are the concatenation of their fragments
+    pub fn finish(self) -> ChatCompletionResponse {
+        let tool_calls: Vec<ToolCall> = self.tool_calls.into_values()
+            .map(|partial| ToolCall {
+                id: partial.id.unwrap_or_else(|| format!("call_{}", Uuid::new_v4().simple())),
+                r#type: "function".to_string(),
+                function: Function {
+                    name: partial.name,
+                    arguments: partial.arguments,
+                },
+            })
+            .collect();
+
+        let message = ChatMessage::Assistant {
+            content: (!self.content.is_empty()).then(|| ChatMessageContent::Text(self.content)),
+            reasoning_content: (!self.reasoning.is_empty()).then_some(self.reasoning),
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            name: None,
+            audio: None,
+            refusal: None,
+        };
+
+        ChatCompletionResponse {
+            id: self.id,
+            object: "chat.completion".to_string(),
+            created: self.created,
+            model: self.model,
+            choices: vec![ChatCompletionChoice {
+                index: 0,
+                message,
+                finish_reason: self.finish_reason,
+                logprobs: None,
+            }],
+            usage: self.usage,
+            system_fingerprint: None,
+            service_tier: None,
+        }
+    }
+}
+
+/// Resolves when a streamed completion must stop (e.g. a cancellation token being cancelled)
+pub type CancelSignal<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+
+/// How a cancellable streamed completion ended
+#[derive(Debug, Clone)]
+pub enum StreamOutcome {
+    Completed(ChatCompletionResponse),
+    /// Stopped before the end, holds what the model generated until then
+    Cancelled(ChatCompletionResponse),
+}
+
+impl StreamOutcome {
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, StreamOutcome::Cancelled(_))
+    }
+
+    pub fn into_response(self) -> ChatCompletionResponse {
+        match self {
+            StreamOutcome::Completed(response) | StreamOutcome::Cancelled(response) => response,
+        }
+    }
+}
+
+/// Read a completion stream to its end, or until `cancelled` resolves
+/// On cancellation the stream is dropped right away, which closes the provider connection
+pub async fn read_stream_until(
+    mut stream: LlmStream,
+    on_delta: &(dyn Fn(ToolCallDelta) + Send + Sync),
+    cancelled: &mut CancelSignal<'_>,
+) -> Result<StreamOutcome, LlmError> {
+    let mut accumulator = StreamAccumulator::new();
+    loop {
+        tokio::select! {
+            biased;
+            _ = cancelled.as_mut() => {
+                drop(stream);
+                return Ok(StreamOutcome::Cancelled(accumulator.finish().extract_think_content()));
+            }
+            chunk = stream.next() => match chunk {
+                Some(chunk) => {
+                    for delta in accumulator.push(chunk?) {
+                        on_delta(delta);
+                    }
+                }
+                None => break,
+            },
+        }
+    }
+    Ok(StreamOutcome::Completed(accumulator.finish().extract_think_content()))
+}
+
+#[async_trait]
+pub trait ToolCallStreaming {
+    /// Function calling (auto) over a streamed completion
+    /// `on_delta` receives the tool call arguments while the model generates them
+    async fn chat_with_tools_fc_stream(
+        &self,
+        request: ChatCompletionParameters,
+        tools: &ToolBox,
+        on_delta: &(dyn Fn(ToolCallDelta) + Send + Sync),
+    ) -> Result<ChatCompletionResponse, LlmError>;
+
+    /// Same as chat_with_tools_fc_stream, stops as soon as `cancelled` resolves
+    /// and returns the partial output instead of an error
+    async fn chat_with_tools_fc_stream_until(
+        &self,
+        request: ChatCompletionParameters,
+        tools: &ToolBox,
+        on_delta: &(dyn Fn(ToolCallDelta) + Send + Sync),
+        cancelled: CancelSignal<'_>,
+    ) -> Result<StreamOutcome, LlmError>;
+}
+
+#[async_trait]
+impl ToolCallStreaming for LlmClient {
+    async fn chat_with_tools_fc_stream(
+        &self,
+        request: ChatCompletionParameters,
+        tools: &ToolBox,
+        on_delta: &(dyn Fn(ToolCallDelta) + Send + Sync),
+    ) -> Result<ChatCompletionResponse, LlmError> {
+        let outcome = self.chat_with_tools_fc_stream_until(request, tools, on_delta, Box::pin(std::future::pending())).await?;
+        Ok(outcome.into_response())
+    }
+
+    async fn chat_with_tools_fc_stream_until(
+        &self,
+        request: ChatCompletionParameters,
+        tools: &ToolBox,
+        on_delta: &(dyn Fn(ToolCallDelta) + Send + Sync),
+        mut cancelled: CancelSignal<'_>,
+    ) -> Result<StreamOutcome, LlmError> {
+        let built = ChatCompletionParametersBuilder::default()
+            .model(&request.model)
+            .messages(request.messages.clone())
+            .with_function_calling_auto(tools)
+            .build()
+            .map_err(|e| LlmError::from(e.to_string()))?;
+        let request = keep_sampling(built, &request);
+
+        // the connection may still be opening when the request is cancelled
+        let stream = tokio::select! {
+            biased;
+            _ = cancelled.as_mut() => return Ok(StreamOutcome::Cancelled(StreamAccumulator::new().finish())),
+            stream = self.chat_stream(request) => stream?,
+        };
+        read_stream_until(stream, on_delta, &mut cancelled).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai_dive::v1::resources::chat::{ChatCompletionChunkChoice, DeltaFunction, DeltaToolCall};
+
+    fn chunk(tool_calls: Vec<DeltaToolCall>, finish_reason: Option<FinishReason>) -> ChatCompletionChunkResponse {
+        ChatCompletionChunkResponse {
+            id: Some("chatcmpl-1".to_string()),
+            object: "chat.completion.chunk".to_string(),
+            created: 1,
+            model: "test".to_string(),
+            choices: vec![ChatCompletionChunkChoice {
+                index: Some(0),
+                delta: DeltaChatMessage::Assistant {
+                    content: None,
+                    reasoning_content: None,
+                    refusal: None,
+                    name: None,
+                    tool_calls: Some(tool_calls),
+                },
+                finish_reason,
+                logprobs: None,
+            }],
+            usage: None,
+            system_fingerprint: None,
+        }
+    }
+
+    fn fragment(index: u32, id: Option<&str>, name: Option<&str>, arguments: &str) -> DeltaToolCall {
+        DeltaToolCall {
+            index: Some(index),
+            id: id.map(str::to_string),
+            r#type: Some("function".to_string()),
+            function: DeltaFunction {
+                name: name.map(str::to_string),
+                arguments: Some(arguments.to_string()),
+            },
+        }
+    }
+
+    fn assembled(response: ChatCompletionResponse) -> Vec<ToolCall> {
+        match response.choices.into_iter().next().unwrap().message {
+            ChatMessage::Assistant { tool_calls, .. } => tool_calls.unwrap_or_default(),
+            other => panic!("expected an assistant message, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_stream_returns_partial_output() {
+        let text = ChatCompletionChunkResponse {
+            choices: vec![ChatCompletionChunkChoice {
+                index: Some(0),
+                delta: DeltaChatMessage::Assistant {
+                    content: Some(ChatMessageContent::Text("Hello, I am".to_string())),
+                    reasoning_content: None,
+                    refusal: None,
+                    name: None,
+                    tool_calls: None,
+                },
+                finish_reason: None,
+                logprobs: None,
+            }],
+            ..chunk(vec![], None)
+        };
+        // a generation that would take minutes after its first chunk
+        let slow = futures::stream::iter(vec![Ok::<_, LlmError>(text)]).chain(futures::stream::pending());
+        let stream: LlmStream = Box::new(slow);
+
+        let started = std::time::Instant::now();
+        let mut cancelled: CancelSignal = Box::pin(tokio::time::sleep(std::time::Duration::from_millis(20)));
+        let outcome = read_stream_until(stream, &|_: ToolCallDelta| {}, &mut cancelled).await.unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_millis(200), "cancelled after {:?}", started.elapsed());
+
+        assert!(outcome.is_cancelled());
+        let ChatMessage::Assistant { content, .. } = outcome.into_response().choices.into_iter().next().unwrap().message else {
+            panic!("expected an assistant message");
+        };
+        assert!(matches!(content, Some(ChatMessageContent::Text(text)) if text == "Hello, I am"));
+    }
+
+    #[test]
+    fn test_deltas_concatenate_to_final_arguments() {
+        let content = "fn main() {\n    println!(\"héllo\");\n}\n";
+        let arguments = serde_json::json!({"path": "src/main.rs", "content": content}).to_string();
+        let pieces: Vec<String> = arguments.chars().collect::<Vec<_>>().chunks(7).map(|c| c.iter().collect()).collect();
+
+        let mut accumulator = StreamAccumulator::new();
+        let mut deltas = Vec::new();
+        for (i, piece) in pieces.iter().enumerate() {
+            // the id and name only come with the first chunk
+            let call = if i == 0 { fragment(0, Some("call_abc"), Some("write"), piece) } else { fragment(0, None, None, piece) };
+            deltas.extend(accumulator.push(chunk(vec![call], None)));
+        }
+        accumulator.push(chunk(vec![], Some(FinishReason::ToolCalls)));
+
+        assert!(deltas.iter().all(|d| d.call_id == "call_abc" && d.name == "write"));
+        let concatenated: String = deltas.iter().map(|d| d.arguments.as_str()).collect();
+
+        let calls = assembled(accumulator.finish());
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].id, "call_abc");
+        assert_eq!(calls[0].function.arguments.as_bytes(), concatenated.as_bytes());
+        assert_eq!(concatenated, arguments);
+    }
+
+    #[test]
+    fn test_calls_without_id_are_normalized() {
+        let mut accumulator = StreamAccumulator::new();
+        let mut deltas = accumulator.push(chunk(vec![
+            fragment(0, Some(""), Some("read"), "{\"path\":"),
+            fragment(1, None, Some("ls"), "{}"),
+        ], None));
+        deltas.extend(accumulator.push(chunk(vec![fragment(0, None, None, "\"a.txt\"}")], None)));
+
+        let calls = assembled(accumulator.finish());
+        assert_eq!(calls.len(), 2);
+        assert!(calls[0].id.starts_with("call_"));
+        assert_ne!(calls[0].id, calls[1].id);
+
+        // every delta of a call carries the id of the assembled call
+        for call in &calls {
+            let arguments: String = deltas.iter().filter(|d| d.call_id == call.id).map(|d| d.arguments.as_str()).collect();
+            assert_eq!(arguments, call.function.arguments);
+        }
+        assert_eq!(calls[0].function.arguments, "{\"path\":\"a.txt\"}");
+    }
+}
@@ -0,0 +1,184 @@
+// llm/structured.rs
+use std::fmt;
+
+use openai_dive::v1::resources::chat::{
+    ChatCompletionParameters, ChatCompletionParametersBuilder, ChatCompletionResponseFormat, ChatMessage,
+    ChatMessageContent, ChatMessageContentPart, JsonSchemaBuilder,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::provider::LlmError;
+use crate::LlmClient;
+
+/// Name of the schema sent by `with_json_schema`
+const RESPONSE_SCHEMA_NAME: &str = "response";
+
+/// Response formats forcing the model to answer in JSON
+pub trait JsonOutputBuilder {
+    /// Any JSON object (`response_format: { type: "json_object" }`)
+    fn with_json_mode(&mut self) -> &mut Self;
+
+    /// JSON matching `schema` (`response_format: { type: "json_schema" }`), enforced strictly
+    fn with_json_schema(&mut self, schema: Value) -> &mut Self;
+}
+
+impl JsonOutputBuilder for ChatCompletionParametersBuilder {
+    fn with_json_mode(&mut self) -> &mut Self {
+        self.response_format(ChatCompletionResponseFormat::JsonObject)
+    }
+
+    fn with_json_schema(&mut self, schema: Value) -> &mut Self {
+        let json_schema = JsonSchemaBuilder::default()
+            .name(RESPONSE_SCHEMA_NAME)
+            .schema(schema)
+            .strict(true)
+            .build()
+            .unwrap();
+        self.response_format(ChatCompletionResponseFormat::JsonSchema { json_schema })
+    }
+}
+
+/// A structured output request that could not be served
+#[derive(Debug)]
+pub enum StructuredOutputError {
+    /// The provider does not enforce a response format for the model, the request was not sent
+    Unsupported { provider: String, model: String },
+    /// The model answered something that is not the requested JSON
+    InvalidJson { error: String, content: String },
+}
+
+impl fmt::Display for StructuredOutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported { provider, model } => {
+                write!(f, "{} does not support structured output for model {}", provider, model)
+            }
+            Self::InvalidJson { error, .. } => write!(f, "structured output is not valid JSON: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for StructuredOutputError {}
+
+/// Whether the request asks for a JSON response
+pub fn requests_json(request: &ChatCompletionParameters) -> bool {
+    matches!(
+        request.response_format,
+        Some(ChatCompletionResponseFormat::JsonObject | ChatCompletionResponseFormat::JsonSchema { .. })
+    )
+}
+
+/// Text of an assistant message, the text parts joined for multi-part content
+pub(crate) fn message_text(message: &ChatMessage) -> Option<String> {
+    match message {
+        ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. } => Some(text.clone()),
+        ChatMessage::Assistant { content: Some(ChatMessageContent::ContentPart(parts)), .. } => Some(
+            parts
+                .iter()
+                .filter_map(|part| match part {
+                    ChatMessageContentPart::Text(part) => Some(part.text.as_str()),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Parse the text of a structured answer, refused with StructuredOutputError::InvalidJson
+pub fn parse_structured<T: DeserializeOwned>(content: &str) -> Result<T, StructuredOutputError> {
+    serde_json::from_str(content).map_err(|e| StructuredOutputError::InvalidJson {
+        error: e.to_string(),
+        content: content.to_string(),
+    })
+}
+
+/// Structured output requests
+impl LlmClient {
+    /// Whether the provider enforces a JSON response format for the model
+    pub fn supports_structured_output(&self, model: &str) -> bool {
+        self.provider().supports_structured_output(model.to_string())
+    }
+
+    /// Send a request built `with_json_mode` or `with_json_schema` and parse its answer
+    /// Providers that do not enforce the format for the model are refused before the request
+    /// is sent, an answer that does not parse is a StructuredOutputError::InvalidJson
+    pub async fn chat_structured<T: DeserializeOwned>(&self, request: ChatCompletionParameters) -> Result<T, LlmError> {
+        if requests_json(&request) && !self.supports_structured_output(&request.model) {
+            return Err(Box::new(StructuredOutputError::Unsupported {
+                provider: self.provider_name().to_string(),
+                model: request.model.clone(),
+            }));
+        }
+
+        let response = self.chat(request).await?;
+        let content = response
+            .choices
+            .first()
+            .and_then(|choice| message_text(&choice.message))
+            .unwrap_or_default();
+        Ok(parse_structured(&content)?)
+    }
+
+    /// `chat_structured` answering the JSON value
+    pub async fn chat_json(&self, request: ChatCompletionParameters) -> Result<Value, LlmError> {
+        self.chat_structured(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockProvider, MockState};
+    use serde::Deserialize;
+    use serde_json::json;
+
+    fn client(content: &'static str, structured: bool) -> LlmClient {
+        let (_, mock) = MockProvider::new(MockState { content: content.to_string(), structured, ..Default::default() });
+        LlmClient::from_provider(Box::new(mock))
+    }
+
+    fn request(schema: Option<Value>) -> ChatCompletionParameters {
+        let mut builder = ChatCompletionParametersBuilder::default();
+        builder.model("test").messages(vec![]);
+        match schema {
+            Some(schema) => builder.with_json_schema(schema),
+            None => builder.with_json_mode(),
+        };
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn test_response_formats() {
+        let format = serde_json::to_value(request(None).response_format).unwrap();
+        assert_eq!(format, json!({ "type": "json_object" }));
+
+        let schema = json!({ "type": "object", "properties": { "city": { "type": "string" } } });
+        let format = serde_json::to_value(request(Some(schema.clone())).response_format).unwrap();
+        assert_eq!(format["type"], "json_schema");
+        assert_eq!(format["json_schema"]["name"], RESPONSE_SCHEMA_NAME);
+        assert_eq!(format["json_schema"]["schema"], schema);
+        assert_eq!(format["json_schema"]["strict"], true);
+    }
+
+    #[tokio::test]
+    async fn test_structured_answers_are_parsed() {
+        #[derive(Deserialize)]
+        struct Weather {
+            city: String,
+        }
+
+        let weather: Weather = client(r#"{"city": "Paris"}"#, true).chat_structured(request(None)).await.unwrap();
+        assert_eq!(weather.city, "Paris");
+
+        let error = client("Sure! The city is Paris.", true).chat_json(request(None)).await.unwrap_err();
+        match error.downcast_ref::<StructuredOutputError>() {
+            Some(StructuredOutputError::InvalidJson { content, .. }) => assert_eq!(content, "Sure! The city is Paris."),
+            other => panic!("expected an invalid JSON error, got {:?}", other),
+        }
+
+        let error = client(r#"{"city": "Paris"}"#, false).chat_json(request(None)).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<StructuredOutputError>(), Some(StructuredOutputError::Unsupported { .. })));
+    }
+}
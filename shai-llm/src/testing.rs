@@ -0,0 +1,128 @@
+// llm/testing.rs
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use openai_dive::v1::error::APIError;
+use openai_dive::v1::resources::{
+    chat::{ChatCompletionParameters, ChatCompletionResponse},
+    model::ListModelResponse,
+};
+use serde_json::json;
+
+use crate::provider::{LlmError, LlmProvider, LlmStream, ProviderInfo};
+
+/// Script of a MockProvider, kept by the test to steer it and count the requests it receives
+#[derive(Default)]
+pub(crate) struct MockState {
+    /// HTTP statuses the next requests fail with, one per request
+    pub failures: Mutex<VecDeque<u16>>,
+    /// HTTP status every request fails with once `failures` is empty
+    pub failing: Mutex<Option<u16>>,
+    /// Content of the answers
+    pub content: String,
+    /// Model of the answers, the requested one when not set
+    pub model: Option<String>,
+    /// Whether the provider enforces response formats
+    pub structured: bool,
+    /// Requests received, chat and streams
+    pub calls: AtomicUsize,
+    /// Parameters of the requests received, in order
+    pub requests: Mutex<Vec<ChatCompletionParameters>>,
+}
+
+impl MockState {
+    pub fn fail_with(&self, status: Option<u16>) {
+        *self.failing.lock().unwrap() = status;
+    }
+
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    /// Parameters of the last request received
+    pub fn last_request(&self) -> Option<ChatCompletionParameters> {
+        self.requests.lock().unwrap().last().cloned()
+    }
+
+    /// Error of the next request, if it fails
+    fn next_failure(&self, request: &ChatCompletionParameters) -> Option<LlmError> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        self.requests.lock().unwrap().push(request.clone());
+        let status = self.failures.lock().unwrap().pop_front().or(*self.failing.lock().unwrap());
+        status.map(api_error)
+    }
+}
+
+/// Error the OpenAI client returns for an HTTP `status`
+pub(crate) fn api_error(status: u16) -> LlmError {
+    let message = "mock error".to_string();
+    Box::new(match status {
+        400 => APIError::InvalidRequestError(message),
+        401 => APIError::AuthenticationError(message),
+        403 => APIError::PermissionError(message),
+        404 => APIError::NotFoundError(message),
+        429 => APIError::RateLimitError(message),
+        _ => APIError::UnknownError(status, message),
+    })
+}
+
+/// Provider of the tests of the provider wrappers (breaker, retry, key rotation, structured output)
+/// Streams open and break before their first chunk, like a dropped connection
+pub(crate) struct MockProvider(pub Arc<MockState>);
+
+impl MockProvider {
+    pub fn new(state: MockState) -> (Arc<MockState>, Self) {
+        let state = Arc::new(state);
+        (state.clone(), Self(state))
+    }
+}
+
+#[async_trait]
+impl LlmProvider for MockProvider {
+    async fn models(&self) -> Result<ListModelResponse, LlmError> {
+        Err("not supported".into())
+    }
+
+    async fn chat(&self, request: ChatCompletionParameters) -> Result<ChatCompletionResponse, LlmError> {
+        if let Some(error) = self.0.next_failure(&request) {
+            return Err(error);
+        }
+        Ok(serde_json::from_value(json!({
+            "id": "cmpl-1",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": self.0.model.clone().unwrap_or(request.model),
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": self.0.content },
+                "finish_reason": "stop"
+            }]
+        }))?)
+    }
+
+    async fn chat_stream(&self, request: ChatCompletionParameters) -> Result<LlmStream, LlmError> {
+        if let Some(error) = self.0.next_failure(&request) {
+            return Err(error);
+        }
+        let broken: LlmError = Box::new(APIError::StreamError("connection reset".to_string()));
+        Ok(Box::new(futures::stream::iter(vec![Err(broken)])))
+    }
+
+    fn supports_functions(&self, _model: String) -> bool {
+        true
+    }
+
+    fn supports_structured_output(&self, _model: String) -> bool {
+        self.0.structured
+    }
+
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn info() -> ProviderInfo {
+        ProviderInfo { name: "mock", display_name: "Mock", env_vars: vec![] }
+    }
+}
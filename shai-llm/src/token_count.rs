@@ -0,0 +1,144 @@
+// llm/token_count.rs
+use openai_dive::v1::resources::chat::{ChatCompletionParameters, ChatMessage, ChatMessageContent, ChatMessageContentPart};
+use tiktoken_rs::{cl100k_base_singleton, o200k_base_singleton, CoreBPE};
+
+use crate::provider::LlmError;
+use crate::usage::estimate_message_tokens;
+
+/// Tokens added by the chat format around each message (role, separators)
+const MESSAGE_OVERHEAD: u32 = 3;
+
+/// Tokens priming the reply of the assistant
+const REPLY_OVERHEAD: u32 = 3;
+
+/// Tokens counted for a non-text content part (a low detail image)
+const PART_TOKENS: u32 = 85;
+
+/// Model families of other providers, counted with the character-based estimate
+const ESTIMATED_FAMILIES: &[&str] = &[
+    "claude", "gemini", "gemma", "mistral", "mixtral", "codestral", "devstral", "magistral", "ministral", "pixtral",
+    "llama", "codellama", "qwen", "qwq", "deepseek", "phi", "command", "grok", "kimi", "glm", "yi", "granite",
+];
+
+/// How the tokens of a model family are counted
+enum Tokenizer {
+    Bpe(&'static CoreBPE),
+    Estimate,
+}
+
+/// Model name without its provider or organization prefix ("openai/gpt-4o" -> "gpt-4o")
+fn base_name(model: &str) -> String {
+    model.rsplit('/').next().unwrap_or(model).to_ascii_lowercase()
+}
+
+fn tokenizer(model: &str) -> Result<Tokenizer, LlmError> {
+    let name = base_name(model);
+    let starts = |prefixes: &[&str]| prefixes.iter().any(|prefix| name.starts_with(prefix));
+
+    if starts(&["gpt-4o", "chatgpt-4o", "gpt-4.1", "gpt-4.5", "gpt-5", "gpt-oss", "o1", "o3", "o4"]) {
+        return Ok(Tokenizer::Bpe(o200k_base_singleton()));
+    }
+    if starts(&["gpt-4", "gpt-3.5", "gpt-35", "text-embedding-3", "text-embedding-ada"]) {
+        return Ok(Tokenizer::Bpe(cl100k_base_singleton()));
+    }
+    if starts(ESTIMATED_FAMILIES) {
+        return Ok(Tokenizer::Estimate);
+    }
+    Err(format!("cannot count the tokens of model '{}': unknown model family", model).into())
+}
+
+fn text_tokens(bpe: &CoreBPE, text: &str) -> u32 {
+    bpe.encode_ordinary(text).len() as u32
+}
+
+fn content_tokens(bpe: &CoreBPE, content: &ChatMessageContent) -> u32 {
+    match content {
+        ChatMessageContent::Text(text) => text_tokens(bpe, text),
+        ChatMessageContent::ContentPart(parts) => parts
+            .iter()
+            .map(|part| match part {
+                ChatMessageContentPart::Text(part) => text_tokens(bpe, &part.text),
+                _ => PART_TOKENS,
+            })
+            .sum(),
+        ChatMessageContent::None => 0,
+    }
+}
+
+fn message_tokens(bpe: &CoreBPE, message: &ChatMessage) -> u32 {
+    let tokens = match message {
+        ChatMessage::System { content, .. }
+        | ChatMessage::Developer { content, .. }
+        | ChatMessage::User { content, .. } => content_tokens(bpe, content),
+        ChatMessage::Assistant { content, reasoning_content, tool_calls, .. } => {
+            content.as_ref().map_or(0, |content| content_tokens(bpe, content))
+                + reasoning_content.as_deref().map_or(0, |text| text_tokens(bpe, text))
+                + tool_calls.iter().flatten()
+                    .map(|call| text_tokens(bpe, &call.function.name) + text_tokens(bpe, &call.function.arguments))
+                    .sum::<u32>()
+        }
+        ChatMessage::Tool { content, .. } => content_tokens(bpe, content),
+    };
+    tokens + MESSAGE_OVERHEAD
+}
+
+/// Tokens of `messages` for `model`: exact for the OpenAI families (tiktoken), estimated from the
+/// characters for the other known families. Unknown families are refused rather than miscounted
+pub fn count_message_tokens(messages: &[ChatMessage], model: &str) -> Result<u32, LlmError> {
+    Ok(match tokenizer(model)? {
+        Tokenizer::Bpe(bpe) => messages.iter().map(|message| message_tokens(bpe, message)).sum(),
+        Tokenizer::Estimate => messages.iter().map(estimate_message_tokens).sum(),
+    })
+}
+
+/// Tokens a chat completion request consumes before the reply: its messages, its tool
+/// definitions and the reply priming, see count_message_tokens for the model families
+pub fn count_tokens(request: &ChatCompletionParameters, model: &str) -> Result<u32, LlmError> {
+    let messages = count_message_tokens(&request.messages, model)?;
+    let tools = match &request.tools {
+        Some(tools) if !tools.is_empty() => {
+            let definitions = serde_json::to_string(tools)?;
+            match tokenizer(model)? {
+                Tokenizer::Bpe(bpe) => text_tokens(bpe, &definitions),
+                Tokenizer::Estimate => crate::usage::estimate_tokens(&definitions),
+            }
+        }
+        _ => 0,
+    };
+    Ok(messages + tools + REPLY_OVERHEAD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai_dive::v1::resources::chat::ChatCompletionParametersBuilder;
+
+    fn request(model: &str, text: &str) -> ChatCompletionParameters {
+        ChatCompletionParametersBuilder::default()
+            .model(model)
+            .messages(vec![
+                ChatMessage::System { content: ChatMessageContent::Text("You are helpful.".to_string()), name: None },
+                ChatMessage::User { content: ChatMessageContent::Text(text.to_string()), name: None },
+            ])
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_count_tokens_of_openai_models() {
+        // "You are helpful." and "Hello world" are 4 and 2 tokens in both vocabularies
+        let expected = (4 + MESSAGE_OVERHEAD) + (2 + MESSAGE_OVERHEAD) + REPLY_OVERHEAD;
+        assert_eq!(count_tokens(&request("gpt-4o-mini", "Hello world"), "gpt-4o-mini").unwrap(), expected);
+        assert_eq!(count_tokens(&request("gpt-4", "Hello world"), "openai/gpt-4").unwrap(), expected);
+    }
+
+    #[test]
+    fn test_count_tokens_of_other_families() {
+        let request = request("claude-sonnet-4", &"a".repeat(40));
+        let estimated = request.messages.iter().map(estimate_message_tokens).sum::<u32>() + REPLY_OVERHEAD;
+        assert_eq!(count_tokens(&request, "anthropic/claude-sonnet-4").unwrap(), estimated);
+        assert_eq!(count_tokens(&request, "Mistral-Large").unwrap(), estimated);
+
+        assert!(count_tokens(&request, "my-custom-model").is_err());
+    }
+}
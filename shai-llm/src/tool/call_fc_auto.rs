@@ -3,7 +3,7 @@ use async_trait::async_trait;
 
 use openai_dive::v1::resources::chat::{ChatCompletionFunction, ChatCompletionParameters, ChatCompletionParametersBuilder, ChatCompletionResponse, ChatCompletionTool, ChatCompletionToolChoice, ChatCompletionToolType, ChatMessage};
 
-use crate::{provider::LlmError, tool::ToolBox, LlmClient, ToolDescription};
+use crate::{provider::LlmError, tool::{keep_sampling, ToolBox}, LlmClient, ToolDescription};
 
 pub trait FunctionCallingAutoBuilder {
     fn with_function_calling_auto(&mut self, tools: &ToolBox) -> &mut Self;
@@ -42,13 +42,13 @@ impl ToolCallFunctionCallingAuto for LlmClient {
         request: ChatCompletionParameters,
         tools: &ToolBox
     ) -> Result<ChatCompletionResponse, LlmError> {
-        let request = ChatCompletionParametersBuilder::default()
+        let built = ChatCompletionParametersBuilder::default()
             .model(&request.model)
             .messages(request.messages.clone())
             .with_function_calling_auto(&tools)
-            .temperature(0.3)
             .build()
             .map_err(|e| LlmError::from(e.to_string()))?;
+        let request = keep_sampling(built, &request);
 
         let response = self
             .chat(request.clone())
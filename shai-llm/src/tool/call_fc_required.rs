@@ -4,7 +4,7 @@ use schemars::json_schema;
 use serde_json::json;
 
 use openai_dive::v1::resources::chat::{ChatCompletionFunction, ChatCompletionParameters, ChatCompletionParametersBuilder, ChatCompletionResponse, ChatCompletionTool, ChatCompletionToolChoice, ChatCompletionToolType, ChatMessage, Function, ToolCall};
-use crate::{provider::LlmError, tool::ToolBox, LlmClient, ToolDescription};
+use crate::{provider::LlmError, tool::{keep_sampling, ToolBox}, LlmClient, ToolDescription};
 
 
 pub struct NoOp {}
@@ -63,13 +63,13 @@ impl ToolCallFunctionCallingRequired for LlmClient {
         request: ChatCompletionParameters,
         tools: &ToolBox
     ) -> Result<ChatCompletionResponse, LlmError> {
-        let request = ChatCompletionParametersBuilder::default()
+        let built = ChatCompletionParametersBuilder::default()
             .model(&request.model)
             .messages(request.messages.clone())
             .with_function_calling_required(&tools)
-            .temperature(0.3)
             .build()
             .map_err(|e| LlmError::from(e.to_string()))?;
+        let request = keep_sampling(built, &request);
 
         let mut response = self
             .chat(request.clone())
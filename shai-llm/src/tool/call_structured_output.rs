@@ -8,7 +8,8 @@ use openai_dive::v1::resources::chat::{
     ChatMessage, ChatMessageContent, Function, ToolCall as LlmToolCall
 };
 use crate::provider::LlmError;
-use crate::tool::ToolBox;
+use crate::structured::{message_text, parse_structured};
+use crate::tool::{keep_sampling, ToolBox};
 use crate::LlmClient;
 
 /// Tool call structure for structured output JSON schema
@@ -143,26 +144,23 @@ impl ToolCallStructuredOutput for LlmClient {
             *system_text = format!("{}{}", system_text, tools_doc);
         }
 
-        let request = ChatCompletionParametersBuilder::default()
+        let built = ChatCompletionParametersBuilder::default()
             .model(&request.model)
-            .messages(request.messages)
-            .temperature(0.3)
+            .messages(request.messages.clone())
             .with_structured_output(&tools)
             .build()
             .map_err(|e| LlmError::from(e.to_string()))?;
+        let request = keep_sampling(built, &request);
 
         let mut response = self
             .chat(request.clone())
             .await
             .map_err(|e| LlmError::from(e.to_string()))?;
         
-        // Parse the structured output
-        let structured_response: AssistantResponse = match &response.choices[0].message {
-            ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. } => {
-                serde_json::from_str(text)
-                    .map_err(|e| LlmError::from(format!("Failed to parse structured response: {}", e)))?
-            }
-            _ => return Err("Expected Assistant message with text content".into()),
+        // Parse the structured output, an answer that does not parse is a StructuredOutputError
+        let structured_response: AssistantResponse = match message_text(&response.choices[0].message) {
+            Some(text) => parse_structured(&text)?,
+            None => return Err("Expected Assistant message with text content".into()),
         };
 
         response.choices[0].message = structured_response.into_chatmessage();
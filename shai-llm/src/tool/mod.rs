@@ -7,8 +7,57 @@ pub mod call_structured_output;
 #[cfg(test)]
 mod test_so;
 
-pub use tool::{ToolDescription, ToolCallMethod, ToolBox, ContainsTool};
+pub use tool::{ToolDescription, ToolCallMethod, ToolBox, ContainsTool, DisplayToolBox, ToolBoxDisplay};
 pub use call::{LlmToolCall,ToolCallAuto};
 pub use call_structured_output::{AssistantResponse, StructuredOutputBuilder, IntoChatMessage};
 pub use call_fc_auto::FunctionCallingAutoBuilder;
-pub use call_fc_required::FunctionCallingRequiredBuilder;
\ No newline at end of file
+pub use call_fc_required::FunctionCallingRequiredBuilder;
+
+use openai_dive::v1::resources::chat::ChatCompletionParameters;
+
+/// Temperature of the tool calling requests that do not set one
+pub const DEFAULT_TOOL_CALL_TEMPERATURE: f32 = 0.3;
+
+/// Carry the sampling parameters and response format of the caller's request over to the request
+/// built for a tool calling method, a method that sets its own format (structured output) keeps it
+pub(crate) fn keep_sampling(mut request: ChatCompletionParameters, from: &ChatCompletionParameters) -> ChatCompletionParameters {
+    request.temperature = from.temperature.or(Some(DEFAULT_TOOL_CALL_TEMPERATURE));
+    request.top_p = from.top_p;
+    request.max_tokens = from.max_tokens;
+    request.max_completion_tokens = from.max_completion_tokens;
+    request.stop = from.stop.clone();
+    if request.response_format.is_none() {
+        request.response_format = from.response_format.clone();
+    }
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockProvider, MockState};
+    use crate::{LlmClient, ToolCallStreaming};
+    use openai_dive::v1::resources::chat::{ChatCompletionParametersBuilder, ChatCompletionResponseFormat, ChatMessage, ChatMessageContent};
+
+    #[tokio::test]
+    async fn test_response_format_reaches_the_provider() {
+        let (state, mock) = MockProvider::new(MockState { content: r#"{"ok": true}"#.to_string(), structured: true, ..Default::default() });
+        let client = LlmClient::from_provider(Box::new(mock));
+        let mut request = ChatCompletionParametersBuilder::default()
+            .model("mock")
+            .messages(vec![ChatMessage::User { content: ChatMessageContent::Text("hi".to_string()), name: None }])
+            .build()
+            .unwrap();
+        request.response_format = Some(ChatCompletionResponseFormat::JsonObject);
+        let sent_format = || serde_json::to_value(state.last_request().unwrap().response_format).unwrap();
+
+        for method in [ToolCallMethod::FunctionCall, ToolCallMethod::FunctionCallRequired, ToolCallMethod::Auto] {
+            client.chat_with_tools(request.clone(), &ToolBox::new(), method).await.unwrap();
+            assert_eq!(sent_format()["type"], "json_object", "{:?} dropped the response format", method);
+        }
+
+        // the mock stream breaks, the request was sent all the same
+        let _ = client.chat_with_tools_fc_stream(request, &ToolBox::new(), &|_| {}).await;
+        assert_eq!(sent_format()["type"], "json_object");
+    }
+}
\ No newline at end of file
@@ -1,3 +1,4 @@
+use std::fmt;
 use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 
@@ -42,3 +43,91 @@ impl ContainsTool for ToolBox {
         self.iter().any(|tool| tool.name() == name)
     }
 }
+
+/// ToolBox is a plain Vec so it cannot implement Display itself,
+/// `toolbox.display()` returns a wrapper rendering it as a markdown table
+pub trait DisplayToolBox {
+    fn display(&self) -> ToolBoxDisplay<'_>;
+}
+
+impl DisplayToolBox for ToolBox {
+    fn display(&self) -> ToolBoxDisplay<'_> {
+        ToolBoxDisplay(self)
+    }
+}
+
+/// Markdown table of a toolbox: Name, Description, Parameters (top-level schema properties)
+pub struct ToolBoxDisplay<'a>(&'a [Arc<dyn ToolDescription>]);
+
+impl fmt::Display for ToolBoxDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "| Name | Description | Parameters |")?;
+        writeln!(f, "|------|-------------|------------|")?;
+        for tool in self.0 {
+            let schema = tool.parameters_schema();
+            let parameters = schema
+                .get("properties")
+                .and_then(|p| p.as_object())
+                .map(|props| props.keys().cloned().collect::<Vec<_>>().join(", "))
+                .unwrap_or_default();
+
+            writeln!(
+                f,
+                "| {} | {} | {} |",
+                escape_cell(&tool.name()),
+                escape_cell(&tool.description()),
+                escape_cell(&parameters)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Keep a value on a single table cell
+fn escape_cell(value: &str) -> String {
+    value.replace('|', "\\|").split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    struct FakeTool;
+
+    impl ToolDescription for FakeTool {
+        fn name(&self) -> String {
+            "read".to_string()
+        }
+
+        fn description(&self) -> String {
+            "Read a file\nfrom disk | fast".to_string()
+        }
+
+        fn parameters_schema(&self) -> serde_json::Value {
+            json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string" },
+                    "line_count": { "type": "integer" }
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn test_toolbox_markdown_table() {
+        let toolbox: ToolBox = vec![Arc::new(FakeTool)];
+        let rendered = toolbox.display().to_string();
+        let lines: Vec<&str> = rendered.lines().collect();
+
+        assert_eq!(lines[0], "| Name | Description | Parameters |");
+        assert!(lines[2].starts_with("| read | Read a file from disk \\| fast | "));
+
+        // property order depends on serde_json's preserve_order feature
+        let cells: Vec<&str> = lines[2].trim_matches('|').split(" | ").collect();
+        let mut parameters: Vec<&str> = cells.last().unwrap().trim().split(", ").collect();
+        parameters.sort();
+        assert_eq!(parameters, vec!["line_count", "path"]);
+    }
+}
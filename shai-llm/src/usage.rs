@@ -0,0 +1,87 @@
+// llm/usage.rs
+use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent, ChatMessageContentPart};
+
+/// Average characters per token of BPE tokenizers on English text and code
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Tokens added by the chat format around each message (role, separators)
+const MESSAGE_OVERHEAD: u32 = 4;
+
+/// Tokens counted for a non-text content part (a low detail image)
+const PART_TOKENS: u32 = 85;
+
+/// Estimated token count of a text, for providers that do not report usage
+pub fn estimate_tokens(text: &str) -> u32 {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN) as u32
+}
+
+fn content_tokens(content: &ChatMessageContent) -> u32 {
+    match content {
+        ChatMessageContent::Text(text) => estimate_tokens(text),
+        ChatMessageContent::ContentPart(parts) => parts
+            .iter()
+            .map(|part| match part {
+                ChatMessageContentPart::Text(part) => estimate_tokens(&part.text),
+                _ => PART_TOKENS,
+            })
+            .sum(),
+        ChatMessageContent::None => 0,
+    }
+}
+
+/// Estimated token count of a message: its text, tool calls and the chat format overhead
+pub fn estimate_message_tokens(message: &ChatMessage) -> u32 {
+    let tokens = match message {
+        ChatMessage::System { content, .. }
+        | ChatMessage::Developer { content, .. }
+        | ChatMessage::User { content, .. } => content_tokens(content),
+        ChatMessage::Assistant { content, reasoning_content, tool_calls, .. } => {
+            content.as_ref().map_or(0, content_tokens)
+                + reasoning_content.as_deref().map_or(0, estimate_tokens)
+                + tool_calls.iter().flatten()
+                    .map(|call| estimate_tokens(&call.function.name) + estimate_tokens(&call.function.arguments))
+                    .sum::<u32>()
+        }
+        ChatMessage::Tool { content, .. } => content_tokens(content),
+    };
+    tokens + MESSAGE_OVERHEAD
+}
+
+/// Estimated (input, output) tokens of a completion: the request messages and the reply
+pub fn estimate_usage(messages: &[ChatMessage], reply: &ChatMessage) -> (u32, u32) {
+    let input = messages.iter().map(estimate_message_tokens).sum();
+    (input, estimate_message_tokens(reply))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai_dive::v1::resources::chat::{Function, ToolCall};
+
+    #[test]
+    fn test_estimate_usage() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+        // characters, not bytes
+        assert_eq!(estimate_tokens("éééé"), 1);
+
+        let messages = vec![
+            ChatMessage::System { content: ChatMessageContent::Text("a".repeat(40)), name: None },
+            ChatMessage::User { content: ChatMessageContent::Text("a".repeat(8)), name: None },
+        ];
+        let reply = ChatMessage::Assistant {
+            content: None,
+            reasoning_content: None,
+            refusal: None,
+            name: None,
+            audio: None,
+            tool_calls: Some(vec![ToolCall {
+                id: "call_1".to_string(),
+                r#type: "function".to_string(),
+                function: Function { name: "read".to_string(), arguments: "{\"path\":\"a\"}".to_string() },
+            }]),
+        };
+        assert_eq!(estimate_usage(&messages, &reply), (10 + 4 + 2 + 4, 1 + 3 + 4));
+    }
+}